@@ -1,4 +1,4 @@
-use std::{iter::repeat, sync::Arc};
+use std::{iter::repeat_n, sync::Arc};
 
 use tokio::{net::UdpSocket, spawn};
 
@@ -9,7 +9,7 @@ fn main() {
         .unwrap()
         .block_on(async move {
             let socket = Arc::new(UdpSocket::bind("10.0.0.1:0").await.unwrap());
-            let send_tasks = Vec::from_iter(repeat(socket).take(1000).map(|socket| {
+            let send_tasks = Vec::from_iter(repeat_n(socket, 1000).map(|socket| {
                 spawn(async move {
                     socket
                         .send_to(&vec![0; 1400], "10.0.0.10:10000")
@@ -0,0 +1,167 @@
+// an interactive client of the key-value service of `kv-server`, one command per line
+//   get <key>
+//   put <key> <value>
+//   del <key>
+//   scan <key> <count>
+// cargo run --example kv-client -- pbft 0 127.0.0.1:10000,127.0.0.1:10001,127.0.0.1:10002,127.0.0.1:10003 127.0.0.1:20000 [proofs]
+// with pbft, every completed op is appended to the `proofs` file together with the replies it is
+// accepted on, which `kv-verify` checks against the replicas' public keys
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufWriter, Write},
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use bincode::Options;
+use permissioned_blockchain::{
+    app::ycsb::{Op, Result},
+    client::{OnOutcome, Outcome},
+    context::{
+        crypto::{Signed, Verify},
+        ordered_multicast::Variant,
+        tokio::Dispatch,
+        ClientIndex, Config, Host, Receivers, TimerId,
+    },
+    hotstuff, minbft, pbft, unreplicated, zyzzyva, Client, Context,
+};
+use serde::de::DeserializeOwned;
+
+fn addrs(arg: &str) -> Vec<SocketAddr> {
+    Vec::from_iter(arg.split(',').map(|addr| addr.parse().unwrap()))
+}
+
+fn parse(line: &str) -> Option<Op> {
+    let words = Vec::from_iter(line.split_whitespace());
+    Some(match words[..] {
+        ["get", key] => Op::Read(key.into()),
+        ["put", key, value] => Op::Insert(key.into(), value.into()),
+        ["del", key] => Op::Delete(key.into()),
+        ["scan", key, count] => Op::Scan(key.into(), count.parse().ok()?),
+        _ => return None,
+    })
+}
+
+fn run<C>(
+    config: Config,
+    index: ClientIndex,
+    mut proofs: Option<BufWriter<File>>,
+    new_client: impl FnOnce(Context<C::Message>) -> C,
+) where
+    C: Client + Send + Sync + 'static,
+    C::Message: DeserializeOwned + Verify + Send + 'static,
+{
+    struct R<C>(Arc<C>);
+    impl<C: Client> Receivers for R<C> {
+        type Message = C::Message;
+
+        fn handle(&mut self, _: Host, _: Host, message: Self::Message) {
+            self.0.handle(message)
+        }
+
+        fn on_timer(&mut self, receiver: Host, _: TimerId) {
+            panic!("{receiver:?} timeout")
+        }
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let dispatch = Dispatch::new(
+        config,
+        runtime.handle().clone(),
+        false,
+        Variant::Unreachable,
+    );
+    std::thread::spawn(move || runtime.block_on(std::future::pending::<()>()));
+    let client = Arc::new(new_client(dispatch.register(Host::Client(index))));
+    std::thread::spawn({
+        let mut receivers = R(client.clone());
+        move || dispatch.run(&mut receivers)
+    });
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line.unwrap();
+        let Some(op) = parse(&line) else {
+            println!("! unknown command");
+            continue;
+        };
+        let (sender, receiver) = flume::bounded(1);
+        let op = bincode::options().serialize(&op).unwrap();
+        client.invoke(
+            op.clone(),
+            OnOutcome(move |outcome: Outcome| sender.send(outcome).unwrap()),
+        );
+        let outcome = receiver.recv().unwrap();
+        // the (op, result, replies) records of `kv-verify`
+        if let Some(file) = &mut proofs {
+            let replies = outcome
+                .proof
+                .as_ref()
+                .unwrap()
+                .downcast_ref::<Vec<Signed<pbft::Reply>>>();
+            let record = (op, &outcome.result, replies.unwrap());
+            bincode::options()
+                .serialize_into(&mut *file, &record)
+                .unwrap();
+            file.flush().unwrap()
+        }
+        match bincode::options()
+            .deserialize::<Result>(&outcome.result)
+            .unwrap()
+        {
+            Result::ReadOk(value) => println!("{value}"),
+            Result::ScanOk(values) => println!("{}", values.join(" ")),
+            Result::NotFound => println!("! not found"),
+            _ => println!("ok"),
+        }
+        println!("* latency {:?}", outcome.latency)
+    }
+}
+
+fn main() {
+    let args = Vec::from_iter(std::env::args().skip(1));
+    let ([mode, index, replica_addrs, client_addrs]
+    | [mode, index, replica_addrs, client_addrs, _]) = &args[..]
+    else {
+        panic!("usage: kv-client <mode> <client index> <replica addrs> <client addrs> [proofs]")
+    };
+    let proofs = args
+        .get(4)
+        .map(|path| BufWriter::new(File::create(path).unwrap()));
+    if proofs.is_some() && mode != "pbft" {
+        panic!("proofs are only exported for pbft")
+    }
+    let index = index.parse::<ClientIndex>().unwrap();
+    let replica_addrs = addrs(replica_addrs);
+    let mut hosts = HashMap::new();
+    for (index, addr) in addrs(client_addrs).into_iter().enumerate() {
+        hosts.insert(Host::Client(index as _), addr);
+    }
+    for (index, &addr) in replica_addrs.iter().enumerate() {
+        hosts.insert(Host::Replica(index as _), addr);
+    }
+    let config = Config::new(hosts, (replica_addrs.len() - 1) / 3);
+
+    match &**mode {
+        "unreplicated" => run(config, index, None, |context| {
+            unreplicated::Client::new(context, index)
+        }),
+        "pbft" => run(config, index, proofs, |context| {
+            pbft::Client::new(context, index)
+        }),
+        "zyzzyva" => run(config, index, None, |context| {
+            zyzzyva::Client::new(context, index, false)
+        }),
+        "hotstuff" => run(config, index, None, |context| {
+            hotstuff::Client::new(context, index)
+        }),
+        "minbft" => run(config, index, None, |context| {
+            minbft::Client::new(context, index)
+        }),
+        _ => panic!("unsupported mode {mode}"),
+    }
+}
@@ -0,0 +1,77 @@
+// a replica of a key-value service, replicated with the chosen protocol
+// cargo run --example kv-server -- pbft 0 127.0.0.1:10000,127.0.0.1:10001,127.0.0.1:10002,127.0.0.1:10003 127.0.0.1:20000
+// run one for every replica address, then talk to them with `kv-client` at one of the client
+// addresses
+// replies are signed with the replica's key instead of a MAC, so the proofs exported by `kv-client`
+// are checkable by anyone with the public keys, see `kv-verify`
+
+use std::{collections::HashMap, net::SocketAddr};
+
+use control_messages::SignatureScheme;
+use permissioned_blockchain::{
+    app::ycsb,
+    context::{ordered_multicast::Variant, tokio::Dispatch, Config, Host, ReplicaIndex},
+    hotstuff, minbft, pbft, unreplicated, zyzzyva, App,
+};
+
+fn addrs(arg: &str) -> Vec<SocketAddr> {
+    Vec::from_iter(arg.split(',').map(|addr| addr.parse().unwrap()))
+}
+
+fn main() {
+    let args = Vec::from_iter(std::env::args().skip(1));
+    let [mode, index, replica_addrs, client_addrs] = &args[..] else {
+        panic!("usage: kv-server <mode> <replica index> <replica addrs> <client addrs>")
+    };
+    let index = index.parse::<ReplicaIndex>().unwrap();
+    let replica_addrs = addrs(replica_addrs);
+    let mut hosts = HashMap::new();
+    for (index, addr) in addrs(client_addrs).into_iter().enumerate() {
+        hosts.insert(Host::Client(index as _), addr);
+    }
+    for (index, &addr) in replica_addrs.iter().enumerate() {
+        hosts.insert(Host::Replica(index as _), addr);
+    }
+    let mut config = Config::new(hosts, (replica_addrs.len() - 1) / 3);
+    config
+        .signature_policy
+        .insert("Reply", SignatureScheme::K256);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let dispatch = Dispatch::new(config, runtime.handle().clone(), true, Variant::Unreachable);
+    std::thread::spawn(move || runtime.block_on(std::future::pending::<()>()));
+
+    let host = Host::Replica(index);
+    let app = App::Ycsb(ycsb::App::default());
+    println!(
+        "* {mode} replica {index} on {}",
+        replica_addrs[index as usize]
+    );
+    match &**mode {
+        "unreplicated" => dispatch.run(&mut unreplicated::Replica::new(
+            dispatch.register(host),
+            app,
+        )),
+        "pbft" => dispatch.run(&mut pbft::Replica::new(dispatch.register(host), index, app)),
+        "zyzzyva" => dispatch.run(&mut zyzzyva::Replica::new(
+            dispatch.register(host),
+            index,
+            app,
+        )),
+        "hotstuff" => dispatch.run(&mut hotstuff::Replica::new(
+            dispatch.register(host),
+            index,
+            app,
+        )),
+        "minbft" => dispatch.run(&mut minbft::Replica::new(
+            dispatch.register(host),
+            index,
+            app,
+        )),
+        // neo also needs a sequencer, see `neo-sequencer`
+        _ => panic!("unsupported mode {mode}"),
+    }
+}
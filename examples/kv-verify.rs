@@ -0,0 +1,125 @@
+// check the proofs exported by `kv-client` with pbft, i.e. every op is accepted on replies from
+// f + 1 distinct replicas that are signed by their keys and agree on the result
+// the replicas' keys are derived from their indices, so only the replica addresses are needed, the
+// same as given to `kv-server`
+// every reply carries the digest of the op it executes, which must be the one recorded along, so
+// the replies of one op cannot pass for the proof of another
+// cargo run --example kv-verify -- 127.0.0.1:10000,127.0.0.1:10001,127.0.0.1:10002,127.0.0.1:10003 proofs
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufRead, BufReader},
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use bincode::Options;
+use control_messages::SignatureScheme;
+use permissioned_blockchain::{
+    app::ycsb::Op,
+    context::{
+        crypto::{Signed, Verifier, Verify},
+        ordered_multicast::Variant,
+        Config, Host,
+    },
+    pbft,
+};
+
+fn check(
+    op: &[u8],
+    result: &[u8],
+    replies: &[Signed<pbft::Reply>],
+    config: &Config,
+    verifier: &Verifier,
+) -> Result<(), String> {
+    let Some(first) = replies.first() else {
+        return Err("no reply".into());
+    };
+    let op_digest = pbft::op_digest(op);
+    let mut replica_indices = HashSet::new();
+    for reply in replies {
+        pbft::Message::Reply(reply.clone())
+            .verify(verifier)
+            .map_err(|err| {
+                format!(
+                    "invalid signature of replica {}: {err}",
+                    reply.replica_index
+                )
+            })?;
+        if reply.op_digest != op_digest {
+            return Err(format!(
+                "replica {} replied another op",
+                reply.replica_index
+            ));
+        }
+        if (reply.client_index, reply.request_num) != (first.client_index, first.request_num) {
+            return Err(format!(
+                "replica {} replied another request",
+                reply.replica_index
+            ));
+        }
+        if reply.result != result {
+            return Err(format!("replica {} disagrees", reply.replica_index));
+        }
+        if reply.block_digest != first.block_digest {
+            return Err(format!(
+                "replica {} committed another block",
+                reply.replica_index
+            ));
+        }
+        if !replica_indices.insert(reply.replica_index) {
+            return Err(format!("replica {} counted twice", reply.replica_index));
+        }
+    }
+    if replica_indices.len() <= config.num_faulty {
+        return Err(format!("only {} replicas", replica_indices.len()));
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = Vec::from_iter(std::env::args().skip(1));
+    let [replica_addrs, path] = &args[..] else {
+        panic!("usage: kv-verify <replica addrs> <proofs>")
+    };
+    let replica_addrs = Vec::from_iter(
+        replica_addrs
+            .split(',')
+            .map(|addr| addr.parse::<SocketAddr>().unwrap()),
+    );
+    let mut hosts = HashMap::new();
+    for (index, &addr) in replica_addrs.iter().enumerate() {
+        hosts.insert(Host::Replica(index as _), addr);
+    }
+    let mut config = Config::new(hosts, (replica_addrs.len() - 1) / 3);
+    // reject replies authenticated by MACs, which anyone holding the shared key can forge
+    config
+        .signature_policy
+        .insert("Reply", SignatureScheme::K256);
+    let verifier = Verifier::new_standard(&config, Arc::new(Variant::Unreachable));
+
+    let mut file = BufReader::new(File::open(path).unwrap());
+    let (mut num_valid, mut num_invalid) = (0, 0);
+    while !file.fill_buf().unwrap().is_empty() {
+        let (op, result, replies) = bincode::options()
+            .deserialize_from::<_, (Vec<u8>, Vec<u8>, Vec<Signed<pbft::Reply>>)>(&mut file)
+            .unwrap();
+        let checked = check(&op, &result, &replies, &config, &verifier);
+        let op = bincode::options().deserialize::<Op>(&op).unwrap();
+        match checked {
+            Ok(()) => {
+                num_valid += 1;
+                println!("ok {op:?}")
+            }
+            Err(err) => {
+                num_invalid += 1;
+                println!("! {op:?} {err}")
+            }
+        }
+    }
+    println!("* {num_valid} valid {num_invalid} invalid");
+    if num_invalid != 0 {
+        std::process::exit(1)
+    }
+}
@@ -0,0 +1,97 @@
+// step through pbft with 4 replicas and 1 client on the simulated network, one event at a time
+//   invoke          invoke a null op on the client
+//   step [n]        deliver the next n events, 1 by default
+//   run             deliver until quiescent
+//   show            print the progress of every replica
+// cargo run --example lockstep
+
+use std::{
+    io::BufRead,
+    sync::{
+        atomic::{AtomicU32, Ordering::SeqCst},
+        Arc,
+    },
+};
+
+use permissioned_blockchain::{
+    common::Chain,
+    context::{
+        ordered_multicast::Variant,
+        simulated::{ClientNode, Network, Nodes},
+        Config, Host,
+    },
+    pbft, App, Client,
+};
+
+fn main() {
+    let addrs = (0..4)
+        .map(Host::Replica)
+        .chain([Host::Client(0)])
+        .enumerate()
+        .map(|(i, host)| (host, ([10, 0, 0, 1], 10000 + i as u16).into()))
+        .collect();
+    let mut config = Config::new(addrs, 1);
+    config.fake_crypto = true;
+    let network = Network::new(config, true, |_| Variant::Unreachable);
+    let mut replicas =
+        Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+    let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+    let num_completed = Arc::new(AtomicU32::new(0));
+    let mut lockstep = network.lockstep();
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line.unwrap();
+        let words = Vec::from_iter(line.split_whitespace());
+        let num_step = match words[..] {
+            ["invoke"] => {
+                let num_completed = num_completed.clone();
+                client.0.invoke(Default::default(), move |_| {
+                    let num_completed = num_completed.fetch_add(1, SeqCst) + 1;
+                    println!("* completed {num_completed}")
+                });
+                continue;
+            }
+            ["step"] => 1,
+            ["step", n] => n.parse().unwrap(),
+            ["run"] => usize::MAX,
+            ["show"] => {
+                for (index, replica) in replicas.iter().enumerate() {
+                    let executed = replica.chain.digest_execute;
+                    println!(
+                        "* replica {index} view {} executed {}",
+                        replica.view_num,
+                        if executed == Chain::genesis().digest() {
+                            String::from("genesis")
+                        } else {
+                            executed[..4].iter().map(|b| format!("{b:02x}")).collect()
+                        }
+                    )
+                }
+                continue;
+            }
+            _ => {
+                println!("! unknown command");
+                continue;
+            }
+        };
+
+        let mut nodes = Nodes::new();
+        for (index, replica) in replicas.iter_mut().enumerate() {
+            nodes.insert(Host::Replica(index as _), replica);
+        }
+        nodes.insert(Host::Client(0), &mut client);
+        for _ in 0..num_step {
+            let Some(event) = lockstep.step(&mut nodes) else {
+                println!("* quiescent");
+                break;
+            };
+            let message = String::from_iter(event.message.chars().take(120));
+            println!(
+                "@{:?} {:?} -> {:?} {message}",
+                event.time, event.sender, event.receiver
+            )
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use std::{net::UdpSocket, time::Instant};
 
+use control_messages::SequencerCurve;
 use permissioned_blockchain::context::{
     crypto::DigestHash,
     ordered_multicast::{serialize, Variant},
@@ -26,7 +27,8 @@ fn main() {
     let socket = UdpSocket::bind("10.0.0.255:60004").unwrap();
     let mut buf = vec![0; 1024];
     let (len, _) = socket.recv_from(&mut buf).unwrap();
-    let variant = Variant::new_k256();
+    // signed by the switch
+    let variant = Variant::new_ecdsa(SequencerCurve::K256);
     let message = variant.deserialize::<Message>(&buf[..len]);
     println!("{message:?}");
     let start = Instant::now();
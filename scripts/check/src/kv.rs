@@ -0,0 +1,301 @@
+// the model of a single key of the key-value apps, which the history is partitioned into, as every
+// point op touches one key. range ops touch many and are not checked
+// the values of the preloaded keys are not in the history, so the model starts from an unknown
+// record and learns it from the results along a linearization, i.e. it checks whether some initial
+// values make the history linearizable
+
+use std::collections::BTreeMap;
+
+use bincode::Options;
+use permissioned_blockchain::app::{kvstore, ycsb};
+
+use crate::wgl::Model;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Record {
+    Unknown,
+    Absent,
+    // the fields known so far, and the number of fields if known
+    Present(BTreeMap<usize, Vec<u8>>, Option<usize>),
+}
+
+impl Record {
+    fn fields(fields: &[Vec<u8>]) -> Self {
+        Self::Present(
+            fields.iter().cloned().enumerate().collect(),
+            Some(fields.len()),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    Read,
+    ReadField(usize),
+    // inserts or overwrites
+    Write(Vec<Vec<u8>>),
+    // overwrites only if present
+    Update(Vec<Vec<u8>>),
+    UpdateField(usize, Vec<u8>),
+    Delete,
+}
+
+#[derive(Debug, Clone)]
+pub enum Output {
+    // the fields, none if not found
+    Read(Option<Vec<Vec<u8>>>),
+    ReadField(Option<Vec<u8>>),
+    Write,
+    // whether the key was there
+    Found(bool),
+}
+
+// the output is none if the op is outstanding, which returns whatever the state leads to
+pub type Call = (Op, Option<Output>);
+
+pub struct Kv;
+
+impl Model for Kv {
+    type State = Record;
+    type Call = Call;
+
+    fn step(state: &Self::State, call: &Self::Call) -> Option<Self::State> {
+        use Output::*;
+        let next = match (call, state) {
+            ((Op::Read, None), _) => state.clone(),
+            ((Op::Read, Some(Read(None))), Record::Unknown | Record::Absent) => Record::Absent,
+            ((Op::Read, Some(Read(Some(fields)))), Record::Unknown) => Record::fields(fields),
+            ((Op::Read, Some(Read(Some(fields)))), Record::Present(known, num_field)) => {
+                if num_field.is_some_and(|num_field| num_field != fields.len())
+                    || known
+                        .iter()
+                        .any(|(&index, value)| fields.get(index) != Some(value))
+                {
+                    return None;
+                }
+                Record::fields(fields)
+            }
+            ((Op::ReadField(_), None), _) => state.clone(),
+            ((Op::ReadField(index), Some(ReadField(Some(value)))), Record::Unknown) => {
+                Record::Present([(*index, value.clone())].into(), None)
+            }
+            (
+                (Op::ReadField(index), Some(ReadField(Some(value)))),
+                Record::Present(known, num_field),
+            ) => {
+                if num_field.is_some_and(|num_field| *index >= num_field)
+                    || known.get(index).is_some_and(|known| known != value)
+                {
+                    return None;
+                }
+                let mut known = known.clone();
+                known.insert(*index, value.clone());
+                Record::Present(known, *num_field)
+            }
+            // the record is absent, or has fewer fields, which an unknown record may be either
+            ((Op::ReadField(_), Some(ReadField(None))), Record::Unknown | Record::Absent) => {
+                state.clone()
+            }
+            ((Op::ReadField(index), Some(ReadField(None))), Record::Present(known, num_field)) => {
+                if known.contains_key(index)
+                    || num_field.is_some_and(|num_field| *index < num_field)
+                {
+                    return None;
+                }
+                state.clone()
+            }
+            ((Op::Write(fields), None | Some(Write)), _) => Record::fields(fields),
+            ((Op::Update(_), Some(Found(false))), Record::Unknown | Record::Absent) => {
+                Record::Absent
+            }
+            ((Op::Update(fields), Some(Found(true))), Record::Unknown | Record::Present(..)) => {
+                Record::fields(fields)
+            }
+            // an outstanding update of an unknown record, which is taken as present, as the
+            // workloads only update preloaded keys
+            ((Op::Update(fields), None), Record::Unknown | Record::Present(..)) => {
+                Record::fields(fields)
+            }
+            ((Op::Update(_), None), Record::Absent) => Record::Absent,
+            ((Op::UpdateField(..), Some(Found(false))), Record::Unknown | Record::Absent) => {
+                state.clone()
+            }
+            (
+                (Op::UpdateField(index, _), Some(Found(false))),
+                Record::Present(known, num_field),
+            ) => {
+                if known.contains_key(index)
+                    || num_field.is_some_and(|num_field| *index < num_field)
+                {
+                    return None;
+                }
+                state.clone()
+            }
+            ((Op::UpdateField(index, value), None | Some(Found(true))), Record::Unknown) => {
+                Record::Present([(*index, value.clone())].into(), None)
+            }
+            (
+                (Op::UpdateField(index, value), None | Some(Found(true))),
+                Record::Present(known, num_field),
+            ) => {
+                if num_field.is_some_and(|num_field| *index >= num_field) {
+                    // an outstanding update that does not take effect
+                    if call.1.is_none() {
+                        return Some(state.clone());
+                    }
+                    return None;
+                }
+                let mut known = known.clone();
+                known.insert(*index, value.clone());
+                Record::Present(known, *num_field)
+            }
+            ((Op::UpdateField(..), None), Record::Absent) => Record::Absent,
+            ((Op::Delete, None), _) => Record::Absent,
+            ((Op::Delete, Some(Found(found))), _) => {
+                if matches!(
+                    (found, state),
+                    (true, Record::Absent) | (false, Record::Present(..))
+                ) {
+                    return None;
+                }
+                Record::Absent
+            }
+            _ => return None,
+        };
+        Some(next)
+    }
+}
+
+// the key of a point op and the op, none if the op touches a range of keys, or the op or the result
+// does not decode
+pub fn decode_ycsb(op: &[u8], result: Option<&[u8]>) -> Option<(Vec<u8>, Call)> {
+    let fields = |value: &str| {
+        Vec::from_iter(
+            value
+                .split(ycsb::FIELD_SEPARATOR)
+                .map(|field| field.as_bytes().to_vec()),
+        )
+    };
+    let result = match result {
+        Some(result) => Some(
+            bincode::options()
+                .deserialize::<ycsb::Result>(result)
+                .ok()?,
+        ),
+        None => None,
+    };
+    let found = |result: Option<ycsb::Result>| {
+        result.map(|result| Output::Found(result != ycsb::Result::NotFound))
+    };
+    let (key, op, output) = match bincode::options().deserialize(op).ok()? {
+        ycsb::Op::Read(key) => (
+            key,
+            Op::Read,
+            result.map(|result| match result {
+                ycsb::Result::ReadOk(value) => Output::Read(Some(fields(&value))),
+                _ => Output::Read(None),
+            }),
+        ),
+        ycsb::Op::ReadField(key, index) => (
+            key,
+            Op::ReadField(index),
+            result.map(|result| match result {
+                ycsb::Result::ReadOk(value) => Output::ReadField(Some(value.into_bytes())),
+                _ => Output::ReadField(None),
+            }),
+        ),
+        ycsb::Op::Insert(key, value) => (
+            key,
+            Op::Write(fields(&value)),
+            result.map(|_| Output::Write),
+        ),
+        ycsb::Op::Update(key, value) => (key, Op::Update(fields(&value)), found(result)),
+        ycsb::Op::UpdateField(key, index, value) => (
+            key,
+            Op::UpdateField(index, value.into_bytes()),
+            found(result),
+        ),
+        ycsb::Op::Delete(key) => (key, Op::Delete, found(result)),
+        ycsb::Op::Scan(..) => return None,
+    };
+    Some((key.into_bytes(), (op, output)))
+}
+
+// see `decode_ycsb`, the values are single fields
+pub fn decode_kvstore(op: &[u8], result: Option<&[u8]>) -> Option<(Vec<u8>, Call)> {
+    let result = match result {
+        Some(result) => Some(kvstore::Result::decode(result)?),
+        None => None,
+    };
+    let (key, op, output) = match bincode::options().deserialize(op).ok()? {
+        kvstore::Op::Get(key) => (
+            key,
+            Op::Read,
+            result.map(|result| match result {
+                kvstore::Result::Get(value) => Output::Read(value.map(|value| vec![value])),
+                _ => Output::Read(None),
+            }),
+        ),
+        kvstore::Op::Put(key, value) => {
+            (key, Op::Write(vec![value]), result.map(|_| Output::Write))
+        }
+        kvstore::Op::Delete(key) => (
+            key,
+            Op::Delete,
+            result.map(|result| Output::Found(result == kvstore::Result::Delete(true))),
+        ),
+        kvstore::Op::Range { .. } => return None,
+    };
+    Some((key, (op, output)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wgl::{check, Timed};
+
+    use super::*;
+
+    #[test]
+    fn unknown_initial_value() {
+        let timed = |op, output, invoke, complete| Timed {
+            call: (op, Some(output)),
+            invoke,
+            complete: Some(complete),
+        };
+        let read = |value: &[u8]| Output::Read(Some(vec![value.to_vec()]));
+        // the first read reveals the preloaded value, which a later read must return as well
+        let history = [
+            timed(Op::Read, read(b"a"), 0, 1),
+            timed(Op::Read, read(b"a"), 2, 3),
+        ];
+        assert!(check::<Kv>(Record::Unknown, &history));
+        let history = [
+            timed(Op::Read, read(b"a"), 0, 1),
+            timed(Op::Read, read(b"b"), 2, 3),
+        ];
+        assert!(!check::<Kv>(Record::Unknown, &history));
+        // a lost update
+        let history = [
+            timed(Op::Update(vec![b"b".to_vec()]), Output::Found(true), 0, 1),
+            timed(Op::Read, read(b"a"), 2, 3),
+        ];
+        assert!(!check::<Kv>(Record::Unknown, &history));
+        // the update of a field is read along with the preloaded others
+        let history = [
+            timed(Op::UpdateField(1, b"x".to_vec()), Output::Found(true), 0, 1),
+            timed(
+                Op::Read,
+                Output::Read(Some(vec![b"a".to_vec(), b"x".to_vec()])),
+                2,
+                3,
+            ),
+            timed(
+                Op::ReadField(0),
+                Output::ReadField(Some(b"a".to_vec())),
+                4,
+                5,
+            ),
+        ];
+        assert!(check::<Kv>(Record::Unknown, &history))
+    }
+}
@@ -0,0 +1,90 @@
+// checks whether the histories recorded by benchmark clients with `--history` are linearizable
+// usage: check <ycsb | kvstore> <history file or directory of them>...
+// e.g. `check ycsb history/<id>` with the histories that `control` saves of a run, merged by the
+// wall clock timestamps of the hosts
+
+use std::{collections::HashMap, env::args, path::Path, process::exit};
+
+use permissioned_blockchain::client::history::{self, Entry};
+
+use crate::{
+    kv::{Kv, Record},
+    wgl::{check, Timed},
+};
+
+mod kv;
+mod wgl;
+
+fn load(path: &Path, entries: &mut Vec<Entry>) {
+    if path.is_dir() {
+        let mut paths = Vec::from_iter(
+            std::fs::read_dir(path)
+                .unwrap()
+                .map(|entry| entry.unwrap().path()),
+        );
+        paths.sort_unstable();
+        for path in paths {
+            load(&path, entries)
+        }
+    } else {
+        entries
+            .extend(history::read(path).unwrap_or_else(|err| panic!("{}: {err}", path.display())))
+    }
+}
+
+fn main() {
+    let args = Vec::from_iter(args().skip(1));
+    let [app, paths @ ..] = &*args else {
+        eprintln!("usage: check <ycsb | kvstore> <history file or directory of them>...");
+        exit(2)
+    };
+    let decode = match &**app {
+        "ycsb" => kv::decode_ycsb,
+        "kvstore" => kv::decode_kvstore,
+        _ => panic!("unsupported app {app}"),
+    };
+    let mut entries = Vec::new();
+    for path in paths {
+        load(Path::new(path), &mut entries)
+    }
+
+    let mut keys = HashMap::<_, Vec<_>>::new();
+    let mut num_skipped = 0;
+    let mut num_outstanding = 0;
+    for entry in &entries {
+        let Some((key, call)) = decode(&entry.op, entry.result.as_deref()) else {
+            num_skipped += 1;
+            continue;
+        };
+        if entry.complete.is_none() {
+            num_outstanding += 1
+        }
+        keys.entry(key).or_default().push(Timed {
+            call,
+            invoke: entry.invoke,
+            complete: entry.complete,
+        })
+    }
+    println!(
+        "* {} ops on {} keys, {num_outstanding} outstanding, {num_skipped} range ops not checked",
+        entries.len() - num_skipped,
+        keys.len()
+    );
+    let mut violated = Vec::from_iter(
+        keys.iter()
+            .filter(|(_, calls)| !check::<Kv>(Record::Unknown, calls)),
+    );
+    if violated.is_empty() {
+        println!("* linearizable");
+        return;
+    }
+    violated.sort_unstable_by_key(|(key, _)| *key);
+    for (key, calls) in &violated {
+        println!(
+            "! not linearizable on key {} over {} ops",
+            String::from_utf8_lossy(key),
+            calls.len()
+        )
+    }
+    exit(1)
+}
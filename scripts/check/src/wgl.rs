@@ -0,0 +1,219 @@
+// the linearizability checker of Wing and Gong with the memoization of Lowe, as porcupine does
+// it searches for an order of the calls that the model accepts and that respects real time, i.e. a
+// call that completes before another invokes is ordered before it, by linearizing a call that has
+// invoked at each step and backtracking on a return whose call is not linearized yet. the states
+// that are reached with the same set of linearized calls are visited once
+
+use std::{collections::HashSet, hash::Hash};
+
+pub trait Model {
+    type State: Clone + Eq + Hash;
+    type Call;
+
+    // the state after `call`, or none if the model does not accept it
+    fn step(state: &Self::State, call: &Self::Call) -> Option<Self::State>;
+}
+
+#[derive(Debug, Clone)]
+pub struct Timed<C> {
+    pub call: C,
+    pub invoke: u64,
+    // none if the call is outstanding, which may be linearized anywhere after it invokes, or never
+    pub complete: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Event {
+    call: usize,
+    is_return: bool,
+}
+
+// a doubly linked list of events in time order, with node 0 as the head
+struct Events {
+    events: Vec<Event>,
+    prev: Vec<usize>,
+    next: Vec<usize>,
+    // the node of every call's return
+    returns: Vec<usize>,
+}
+
+const NIL: usize = usize::MAX;
+
+impl Events {
+    fn new<C>(calls: &[Timed<C>]) -> Self {
+        let mut events = Vec::from_iter(calls.iter().enumerate().flat_map(|(index, call)| {
+            [
+                (call.invoke, false, index),
+                (call.complete.unwrap_or(u64::MAX), true, index),
+            ]
+        }));
+        // a call that invokes when another one completes is taken as concurrent with it
+        events.sort_unstable();
+        let mut returns = vec![0; calls.len()];
+        let mut list = Self {
+            events: vec![Event {
+                call: NIL,
+                is_return: false,
+            }],
+            prev: vec![NIL],
+            next: Vec::new(),
+            returns: Vec::new(),
+        };
+        for (node, (_, is_return, call)) in events.into_iter().enumerate() {
+            let node = node + 1;
+            if is_return {
+                returns[call] = node
+            }
+            list.events.push(Event { call, is_return });
+            list.prev.push(node - 1);
+        }
+        list.next = Vec::from_iter((1..list.events.len()).chain([NIL]));
+        list.returns = returns;
+        list
+    }
+
+    fn first(&self) -> usize {
+        self.next[0]
+    }
+
+    fn unlink(&mut self, node: usize) {
+        let (prev, next) = (self.prev[node], self.next[node]);
+        self.next[prev] = next;
+        if next != NIL {
+            self.prev[next] = prev
+        }
+    }
+
+    // reverts `unlink` of `node`, in the reversed order of unlinking
+    fn relink(&mut self, node: usize) {
+        let (prev, next) = (self.prev[node], self.next[node]);
+        self.next[prev] = node;
+        if next != NIL {
+            self.prev[next] = node
+        }
+    }
+
+    // takes out the call at `node` along with its return
+    fn lift(&mut self, node: usize) {
+        self.unlink(node);
+        self.unlink(self.returns[self.events[node].call])
+    }
+
+    fn unlift(&mut self, node: usize) {
+        self.relink(self.returns[self.events[node].call]);
+        self.relink(node)
+    }
+}
+
+pub fn check<M: Model>(init: M::State, calls: &[Timed<M::Call>]) -> bool {
+    let mut events = Events::new(calls);
+    let mut linearized = vec![0u64; calls.len().div_ceil(64)];
+    let mut state = init;
+    let mut visited = HashSet::new();
+    // the linearized calls' nodes and the states before them
+    let mut stack = Vec::<(usize, M::State)>::new();
+    let mut node = events.first();
+    loop {
+        let Some(&Event { call, is_return }) = events.events.get(node) else {
+            // every call is linearized
+            return true;
+        };
+        // the rest are the returns of outstanding calls, which need not be linearized
+        if is_return && calls[call].complete.is_none() {
+            return true;
+        }
+        if !is_return {
+            if let Some(next_state) = M::step(&state, &calls[call].call) {
+                linearized[call / 64] |= 1 << (call % 64);
+                if visited.insert((linearized.clone(), next_state.clone())) {
+                    stack.push((node, std::mem::replace(&mut state, next_state)));
+                    events.lift(node);
+                    node = events.first();
+                    continue;
+                }
+                linearized[call / 64] &= !(1 << (call % 64));
+            }
+            node = events.next[node];
+            continue;
+        }
+        // the call of this return must have been linearized before it
+        let Some((call_node, prev_state)) = stack.pop() else {
+            return false;
+        };
+        let call = events.events[call_node].call;
+        linearized[call / 64] &= !(1 << (call % 64));
+        state = prev_state;
+        events.unlift(call_node);
+        node = events.next[call_node];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a register of a number, written by `Some` and read by `None` which returns the number
+    struct Register;
+
+    impl Model for Register {
+        type State = u64;
+        type Call = (Option<u64>, u64);
+
+        fn step(state: &Self::State, call: &Self::Call) -> Option<Self::State> {
+            match *call {
+                (Some(value), _) => Some(value),
+                (None, value) if value == *state => Some(*state),
+                _ => None,
+            }
+        }
+    }
+
+    fn timed(
+        call: (Option<u64>, u64),
+        invoke: u64,
+        complete: Option<u64>,
+    ) -> Timed<(Option<u64>, u64)> {
+        Timed {
+            call,
+            invoke,
+            complete,
+        }
+    }
+
+    #[test]
+    fn register() {
+        // the read overlaps the write, so it may return either value
+        let write = timed((Some(1), 0), 0, Some(10));
+        for value in [0, 1] {
+            assert!(check::<Register>(
+                0,
+                &[write.clone(), timed((None, value), 5, Some(15))]
+            ))
+        }
+        // a read after the write completes must return it
+        assert!(!check::<Register>(
+            0,
+            &[write.clone(), timed((None, 0), 11, Some(15))]
+        ));
+        // a stale read after a fresh one, though both overlap the write
+        assert!(!check::<Register>(
+            0,
+            &[
+                write.clone(),
+                timed((None, 1), 1, Some(4)),
+                timed((None, 0), 5, Some(8))
+            ]
+        ));
+        // an outstanding write may take effect any time after it invokes
+        let write = timed((Some(1), 0), 0, None);
+        assert!(check::<Register>(
+            0,
+            &[
+                write.clone(),
+                timed((None, 0), 1, Some(4)),
+                timed((None, 1), 5, Some(8))
+            ]
+        ));
+        assert!(check::<Register>(0, &[write, timed((None, 0), 5, Some(8))]))
+    }
+}
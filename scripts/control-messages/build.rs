@@ -0,0 +1,39 @@
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().into())
+}
+
+fn main() {
+    let hash = git(&["rev-parse", "HEAD"]).unwrap_or(String::from("unknown"));
+    // only tracked files count, and edits are noticed once they are staged or committed, since the
+    // script is rerun on changes of HEAD and index
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+        .map(|status| !status.is_empty())
+        .unwrap_or_default();
+    println!(
+        "cargo:rustc-env=NEO_GIT_HASH={hash}{}",
+        if dirty { "-dirty" } else { "" }
+    );
+    println!(
+        "cargo:rustc-env=NEO_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={git_dir}/HEAD");
+        println!("cargo:rerun-if-changed={git_dir}/index");
+        if let Some(head_ref) = git(&["symbolic-ref", "HEAD"]) {
+            println!("cargo:rerun-if-changed={git_dir}/{head_ref}")
+        }
+        // where the branch is moved to once its loose ref is packed by gc, a missing path would
+        // rerun the script on every build
+        let packed_refs = format!("{git_dir}/packed-refs");
+        if std::path::Path::new(&packed_refs).exists() {
+            println!("cargo:rerun-if-changed={packed_refs}")
+        }
+    }
+}
@@ -0,0 +1,101 @@
+// helpers of `reload` and `neo-aws` for pushing binaries to hosts over ssh
+
+use std::{
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Mutex,
+    },
+    thread::scope,
+};
+
+// maximum number of hosts that are deployed to at the same time
+pub const CONCURRENCY: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    Skipped,
+    Transferred,
+}
+
+fn sha256sum(command: &mut Command) -> Option<String> {
+    let output = command.stderr(Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8(output.stdout)
+            .ok()?
+            .split_whitespace()
+            .next()?
+            .into(),
+    )
+}
+
+// copy `local` to `remote` path on `host`, unless the remote file has the same content already
+pub fn sync_file(host: &str, local: &str, remote: &str) -> Result<Transfer, String> {
+    let local_hash =
+        sha256sum(Command::new("sha256sum").arg(local)).ok_or(format!("fail to hash {local}"))?;
+    if sha256sum(Command::new("ssh").args([host, "sha256sum", remote])) == Some(local_hash) {
+        return Ok(Transfer::Skipped);
+    }
+    let status = Command::new("rsync")
+        .arg(local)
+        .arg(format!("{host}:{remote}"))
+        .status()
+        .map_err(|err| err.to_string())?;
+    if !status.success() {
+        return Err(format!("rsync {local} exits with {status}"));
+    }
+    Ok(Transfer::Transferred)
+}
+
+// run `session` for every host with at most `CONCURRENCY` in parallel, printing progress, and
+// return the failed hosts along with errors after printing a summary
+pub fn for_each_host(
+    hosts: &[String],
+    session: impl Fn(&str) -> Result<Transfer, String> + Sync,
+) -> Vec<(String, String)> {
+    let next = AtomicUsize::new(0);
+    let finished = AtomicUsize::new(0);
+    let outcomes = Mutex::new(Vec::new());
+    scope(|s| {
+        for _ in 0..CONCURRENCY.min(hosts.len()) {
+            s.spawn(|| {
+                while let Some(host) = hosts.get(next.fetch_add(1, SeqCst)) {
+                    let outcome = session(host);
+                    let finished = finished.fetch_add(1, SeqCst) + 1;
+                    eprint!(
+                        "* [{finished}/{}] {host} {outcome:?}        \r",
+                        hosts.len()
+                    );
+                    outcomes.lock().unwrap().push((host.clone(), outcome))
+                }
+            });
+        }
+    });
+    eprintln!();
+
+    let outcomes = outcomes.into_inner().unwrap();
+    let count = |transfer| {
+        outcomes
+            .iter()
+            .filter(|(_, outcome)| outcome.as_ref() == Ok(&transfer))
+            .count()
+    };
+    let failures = Vec::from_iter(
+        outcomes
+            .iter()
+            .filter_map(|(host, outcome)| Some((host.clone(), outcome.clone().err()?))),
+    );
+    println!(
+        "* {} transferred, {} unchanged, {} failed",
+        count(Transfer::Transferred),
+        count(Transfer::Skipped),
+        failures.len()
+    );
+    for (host, err) in &failures {
+        println!("! {host}: {err}")
+    }
+    failures
+}
@@ -1,24 +1,695 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, SystemTime},
+};
 
 use serde::{Deserialize, Serialize};
 
+pub mod deploy;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Task {
     pub mode: String,
     pub app: App,
     pub client_addrs: Vec<SocketAddr>,
     pub replica_addrs: Vec<SocketAddr>,
-    pub multicast_addr: SocketAddr,
+    // one per multicast group, which clients are spread across by index
+    pub multicast_addrs: Vec<SocketAddr>,
+    // source IPs that replicas admit ordered multicast from, any if not set
+    #[serde(default)]
+    pub multicast_sources: Option<Vec<IpAddr>>,
     pub num_faulty: usize,
     pub drop_rate: f64,
+    pub socket: SocketConfig,
+    pub min_mac_coverage: f64,
+    // which replicas the software sequencer carries MACs for, neo-hm only
+    #[serde(default)]
+    pub mac_pattern: MacPattern,
+    // the software sequencer signs every this many messages, neo-pk only
+    pub sign_interval: u32,
+    // the curve that the sequencer signs on, neo-pk and neo-bn only
+    #[serde(default)]
+    pub sequencer_curve: SequencerCurve,
+    // number of log entries preallocated by replicas before reporting ready, 0 to disable
+    #[serde(default)]
+    pub warm_up_entry_num: usize,
+    pub client_table_capacity: usize,
+    // where clients send requests to, protocol's default if not set
+    #[serde(default)]
+    pub client_destination: Option<ClientDestination>,
+    // replica index => address of its standby, where messages are accepted from as from the replica
+    // neo only, and the address must also receive the ordered multicast
+    #[serde(default)]
+    pub standby_addrs: Vec<(u8, SocketAddr)>,
+    // message class (type name, e.g. "Prepare") => scheme overriding the protocol's default
+    #[serde(default)]
+    pub signature_policy: HashMap<String, SignatureScheme>,
+    // scheme of replicas' public key signatures, K256 if not set
+    #[serde(default)]
+    pub public_scheme: Option<SignatureScheme>,
+    // fraction of ops that replicas compare against the results of a shadow app, 0 to not run one
+    #[serde(default)]
+    pub self_check_rate: f64,
+    // replicas drop messages of past views or executed blocks before verifying them
+    #[serde(default)]
+    pub reject_stale: bool,
+    // replicas deserialize and verify client requests on this many threads, sharded by client, or
+    // on the dispatch thread if 0
+    #[serde(default)]
+    pub request_workers: usize,
+    // clients MAC their requests with keys of their own derived from this secret, instead of the
+    // key every host shares
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    // directory of the keys generated by `keygen`, which replicas sign with in place of the ones
+    // derived from replica indexes, see `Config::load_keys`
+    #[serde(default)]
+    pub key_dir: Option<String>,
+    // the epoch of the keys in `key_dir`, i.e. the number of rotations before the task, and the
+    // directory of the keys of the epoch before, whose public ones are still accepted for
+    // `key_grace` after starting, for the hosts that start after the others rotate, see `RotateKeys`
+    #[serde(default)]
+    pub key_epoch: u8,
+    #[serde(default)]
+    pub previous_key_dir: Option<String>,
+    #[serde(default)]
+    pub key_grace: Duration,
+    // clients timestamp ordered multicast, which the software sequencer and replicas follow up, to
+    // break down the latency of the sequencing hop, see `ReplicaStats::sequencing`
+    #[serde(default)]
+    pub sequencing_timing: bool,
+    // ops between checkpoints of the app, 0 to not take any, pbft and neo only, and required by
+    // neo without confirm, which rolls back to them
+    #[serde(default)]
+    pub checkpoint_interval: u64,
+    // directory of the write-ahead logs that replicas recover from on start and append executed
+    // blocks to, pbft only
+    #[serde(default)]
+    pub persist: Option<String>,
+    // directory on the hosts of replicas to record the events of their dispatches into, one
+    // `trace-<index>.bin` per replica, for replaying the run offline
+    #[serde(default)]
+    pub trace: Option<String>,
+    // replica index => how it misbehaves, pbft only
+    #[serde(default)]
+    pub byzantine: Vec<(u8, ByzantineStrategy)>,
+    // emulated by replicas on receiving, on top of `drop_rate`
+    #[serde(default)]
+    pub network_model: NetworkModel,
+    // ops that replicas order ahead of executing before asking clients to back off and holding back
+    // the ordered multicast, 0 to do neither, neo only
+    #[serde(default)]
+    pub max_inflight: u64,
     pub seed: u64,
     pub role: Role,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SignatureScheme {
+    Plain,
+    K256,
+    Ed25519,
+    Bls,
+    Hmac,
+}
+
+// the links that replicas receive from, the ones not listed are not shaped, e.g. in TOML
+//
+//     # 40ms between the two regions of replicas 0, 1 and 2, 3, on a 100Mbps link
+//     replica_links = [[0, 2, { delay_us = 40000, bandwidth = 12500000 }], ...]
+//     client_link = { loss_rate = 0.001, delay_us = 1000, jitter_us = 200 }
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkModel {
+    // (from, to, link) between replicas
+    pub replica_links: Vec<(u8, u8, LinkModel)>,
+    // between every client and every replica, in both directions
+    pub client_link: Option<LinkModel>,
+    pub multicast_link: Option<LinkModel>,
+}
+
+impl NetworkModel {
+    pub fn load(path: &str) -> Self {
+        let content =
+            std::fs::read_to_string(path).unwrap_or_else(|err| panic!("reading {path}: {err}"));
+        let model =
+            toml::from_str::<Self>(&content).unwrap_or_else(|err| panic!("parsing {path}: {err}"));
+        model
+            .check()
+            .unwrap_or_else(|err| panic!("parsing {path}: {err}"));
+        model
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        for (from, to, link) in &self.replica_links {
+            link.check()
+                .map_err(|err| format!("link from {from} to {to}: {err}"))?
+        }
+        if let Some(link) = &self.client_link {
+            link.check().map_err(|err| format!("client link: {err}"))?
+        }
+        if let Some(link) = &self.multicast_link {
+            link.check()
+                .map_err(|err| format!("multicast link: {err}"))?
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LinkModel {
+    pub loss_rate: f64,
+    pub delay_us: u64,
+    // further delay up to this long, uniformly
+    pub jitter_us: u64,
+    // bytes per second
+    pub bandwidth: Option<u64>,
+}
+
+impl LinkModel {
+    pub fn check(&self) -> Result<(), String> {
+        if !(0. ..=1.).contains(&self.loss_rate) {
+            return Err(format!("loss rate {} not in [0, 1]", self.loss_rate));
+        }
+        if self.bandwidth == Some(0) {
+            return Err(String::from("zero bandwidth"));
+        }
+        Ok(())
+    }
+}
+
+// how a faulty replica deviates on what it sends, see `context::byzantine` of
+// `permissioned-blockchain`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ByzantineStrategy {
+    // send nothing
+    Silent,
+    // propose conflicting messages to the replicas of odd index
+    Equivocate,
+    // send every vote in place of the next one, so the others see votes of the past
+    StaleVote,
+    // garble the digests carried by messages and sign them again
+    CorruptDigest,
+}
+
+// what replicas verify of the received messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Strictness {
+    // accept everything
+    Nop,
+    // verify MACs, i.e. HMAC and HalfSipHash, and accept public key signatures unchecked
+    MacOnly,
+    #[default]
+    Full,
+}
+
+// switch the verification strictness of a running replica after it commits `op_num`, or
+// immediately if it is `None`
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SwitchStrictness {
+    pub strictness: Strictness,
+    pub op_num: Option<u64>,
+}
+
+// rotate the keys of a running replica to the ones in `key_dir` generated by `keygen`, and the key
+// that the sequencer signs with to `sequencer_key` SEC1 encoded, neo-pk and neo-bn only, accepting
+// the replaced ones for `grace` more
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotateKeys {
+    pub key_dir: Option<String>,
+    pub sequencer_key: Option<Vec<u8>>,
+    pub grace: Duration,
+}
+
+// a fault injected on the receiving side of replicas, the same in simulation and on the testbed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ChaosAction {
+    // drop messages with this probability
+    Drop(f64),
+    // delay every message by this long
+    Delay(Duration),
+    // further delay messages with the probability by the duration, so later ones overtake them
+    Reorder(f64, Duration),
+    // drop every message to and from the replica
+    Isolate(u8),
+    Rejoin(u8),
+    // drop the messages between the replicas of set bits and the other ones
+    Partition(u128),
+    Heal,
+}
+
+impl ChaosAction {
+    pub fn check(&self) -> Result<(), String> {
+        match *self {
+            Self::Drop(rate) | Self::Reorder(rate, _) if !(0. ..=1.).contains(&rate) => {
+                Err(format!("rate {rate} not in [0, 1]"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// timed chaos actions, written one per line as `<time> <action> <args>...` e.g.
+//
+//     # lossy network, then replica 1 is cut off for a second
+//     0s drop 0.01
+//     2s isolate 1
+//     3s rejoin 1
+//     3s reorder 0.1 500us
+//     10s partition 0 1 # replicas 0 and 1 on one side, the rest on the other
+//     20s heal
+//
+// times are from the start of the simulation, or of the benchmark on the testbed, and the actions
+// take effect in the order they are written
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scenario(pub Vec<(Duration, ChaosAction)>);
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s
+        .find(|c: char| c.is_alphabetic())
+        .ok_or(format!("missing unit in {s}"))?;
+    let value = s[..split]
+        .parse::<f64>()
+        .map_err(|err| format!("{s}: {err}"))?;
+    let unit = match &s[split..] {
+        "s" => 1.,
+        "ms" => 1e-3,
+        "us" => 1e-6,
+        unit => return Err(format!("unknown unit {unit}")),
+    };
+    Ok(Duration::from_secs_f64(value * unit))
+}
+
+impl std::str::FromStr for Scenario {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut steps = Vec::new();
+        for (line_num, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap();
+            let words = Vec::from_iter(line.split_whitespace());
+            let parse = || {
+                let number = |index: usize| {
+                    words
+                        .get(index)
+                        .ok_or(String::from("missing argument"))
+                        .and_then(|word| word.parse::<f64>().map_err(|err| err.to_string()))
+                };
+                // a bit of `ChaosAction::Partition`, see `check` for the replicas of a run
+                let replica = |index: usize| {
+                    let word = words.get(index).ok_or(String::from("missing argument"))?;
+                    word.parse::<u8>()
+                        .ok()
+                        .filter(|&replica| (replica as u32) < u128::BITS)
+                        .ok_or(format!("invalid replica index {word}"))
+                };
+                let at = parse_duration(words[0])?;
+                let action = match (words.get(1).copied(), words.len()) {
+                    (Some("drop"), 3) => ChaosAction::Drop(number(2)?),
+                    (Some("delay"), 3) => ChaosAction::Delay(parse_duration(words[2])?),
+                    (Some("reorder"), 4) => {
+                        ChaosAction::Reorder(number(2)?, parse_duration(words[3])?)
+                    }
+                    (Some("isolate"), 3) => ChaosAction::Isolate(replica(2)?),
+                    (Some("rejoin"), 3) => ChaosAction::Rejoin(replica(2)?),
+                    (Some("partition"), 3..) => {
+                        let mut side = 0u128;
+                        for index in 2..words.len() {
+                            side |= 1 << replica(index)?
+                        }
+                        ChaosAction::Partition(side)
+                    }
+                    (Some("heal"), 2) => ChaosAction::Heal,
+                    _ => return Err(format!("unknown action {line:?}")),
+                };
+                action.check()?;
+                Ok((at, action))
+            };
+            if !words.is_empty() {
+                steps.push(parse().map_err(|err: String| format!("line {}: {err}", line_num + 1))?)
+            }
+        }
+        // stable, so actions of the same time keep the written order
+        steps.sort_by_key(|(at, _)| *at);
+        Ok(Self(steps))
+    }
+}
+
+impl Scenario {
+    // every replica that the actions name is one of the `num_replica` ones of the run
+    pub fn check(&self, num_replica: usize) -> Result<(), String> {
+        for (at, action) in &self.0 {
+            let in_range = match *action {
+                ChaosAction::Isolate(index) | ChaosAction::Rejoin(index) => {
+                    (index as usize) < num_replica
+                }
+                ChaosAction::Partition(side) => {
+                    side.checked_shr(num_replica as _).unwrap_or(0) == 0
+                }
+                _ => true,
+            };
+            if !in_range {
+                return Err(format!("{at:?} {action:?} beyond {num_replica} replicas"));
+            }
+        }
+        Ok(())
+    }
+
+    // from the first partition to the heal after it, or from the first isolation to the rejoin of
+    // the same replica after it
+    pub fn fault_window(&self) -> Option<(Duration, Duration)> {
+        let position = self.0.iter().position(|(_, action)| {
+            matches!(action, ChaosAction::Partition(_) | ChaosAction::Isolate(_))
+        })?;
+        let (start, fault) = self.0[position];
+        let (end, _) = self.0[position + 1..]
+            .iter()
+            .find(|(_, action)| match (fault, action) {
+                (ChaosAction::Partition(_), ChaosAction::Heal) => true,
+                (ChaosAction::Isolate(index), &ChaosAction::Rejoin(rejoined)) => index == rejoined,
+                _ => false,
+            })?;
+        Some((start, *end))
+    }
+}
+
+// how long after `healed` the throughput gets back to 90% of the one before `faulted`, with the ops
+// completed in each second of a run as in `BenchmarkStats::throughput_timeline`, none if it never
+// does in the run
+// the first second is skipped as the warm up
+pub fn recovery_time(timeline: &[u64], faulted: Duration, healed: Duration) -> Option<Duration> {
+    let before = timeline.get(1..faulted.as_secs() as usize)?;
+    if before.is_empty() {
+        return None;
+    }
+    let baseline = before.iter().sum::<u64>() as f64 / before.len() as f64;
+    let healed_second = healed.as_secs() as usize;
+    let recovered = timeline
+        .iter()
+        .enumerate()
+        .skip(healed_second)
+        .find(|(_, &ops)| ops as f64 >= baseline * 0.9)?
+        .0;
+    Some(Duration::from_secs((recovered + 1) as u64).saturating_sub(healed))
+}
+
+// options applied to the sockets of replicas and clients, system default if not set
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SocketConfig {
+    pub rcvbuf: Option<usize>,
+    pub sndbuf: Option<usize>,
+    // SO_BUSY_POLL in microseconds
+    pub busy_poll: Option<u32>,
+    pub tos: Option<u8>,
+    // SO_TIMESTAMPING on receiving
+    #[serde(default)]
+    pub timestamping: Option<Timestamping>,
+    #[serde(default)]
+    pub transport: Transport,
+    // up to this many datagrams per recvmmsg/sendmmsg syscall, one syscall per datagram if not set,
+    // and replicas batch the messages sent while handling a burst of events together
+    // batched receiving does not timestamp, so it falls back to one datagram per syscall with
+    // `timestamping`
+    #[serde(default)]
+    pub batch: Option<usize>,
+}
+
+impl SocketConfig {
+    // of `batch`, which is preallocated for
+    pub const MAX_BATCH: usize = 64;
+}
+
+// where received packets are timestamped, see `ReplicaStats::socket_delay`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Timestamping {
+    // by kernel on entering the network stack
+    Software,
+    // by NIC, which must have RX timestamping enabled e.g. with `hwstamp_ctl -r 1`, and its clock
+    // synchronized to the system clock e.g. with `phc2sys`
+    Hardware,
+}
+
+// how messages between hosts are sent, ordered multicast always goes through UDP
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Transport {
+    // a datagram per message, or per fragment of a larger one
+    #[default]
+    Udp,
+    // length-prefixed frames on a connection to each peer, reconnected on errors, for the networks
+    // where losing a fragment of a large message, e.g. a batch, too often loses the message
+    Tcp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClientDestination {
+    Primary,
+    Random,
+    All,
+    Multicast,
+}
+
+impl ClientDestination {
+    // the replicas of `mode` accept requests sent this way
+    pub fn check(&self, mode: &str) -> Result<(), String> {
+        let multicast = matches!(self, Self::Multicast);
+        let accepted = match mode {
+            // only what the sequencer numbers is ordered
+            "neo-hm" | "neo-pk" | "neo-bn" => multicast,
+            // the sequenced modes take unicast requests as well
+            "unreplicated-om" | "pbft-sq" => true,
+            // no ordered multicast to receive from
+            _ => !multicast,
+        };
+        if accepted {
+            Ok(())
+        } else {
+            Err(format!("{mode} does not accept requests sent to {self:?}"))
+        }
+    }
+}
+
+// which replicas get a code in the ordered multicast of a message, where a packet carries codes
+// for at most 4 replicas, the one for replica `i` in slot `i % 4`
+// only the software sequencer follows this, the switch always sends every window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MacPattern {
+    // a packet for each window of 4 consecutive replicas, so every replica is covered
+    #[default]
+    Windows,
+    // a single packet for one window, which rotates with sequence number
+    Rotating,
+    // a single packet carrying codes for this many slots, each for a random replica
+    Random(usize),
+}
+
+// command line of `neo-sequencer`, the software stand-in of the switch, e.g.
+//
+//     neo-sequencer --scheme p256 --sign-interval 10 --num-replica 4 --multicast-ip 10.0.0.1
+//
+// which signs with P-256 on a thread pool, every 10th message or the last of a burst, or with
+// `--scheme k256` on secp256k1 like the switch, or with `--scheme half-sip-hash --mac-pattern
+// windows` computes a vector of SipHash MACs instead
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequencerArgs {
+    pub scheme: SequencerScheme,
+    pub num_replica: usize,
+    pub multicast_ip: IpAddr,
+    // received on and multicast to, one per multicast group
+    pub port: u16,
+    pub seed: Option<u64>,
+    // merge neo's requests, up to the size or until the first one is held for the timeout
+    pub batch_size: usize,
+    pub batch_timeout: Duration,
+    // one per core except the receiving one if not set
+    pub signing_threads: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequencerScheme {
+    HalfSipHash(MacPattern),
+    // the interval of signatures
+    Ecdsa(SequencerCurve, u32),
+}
+
+// the switch signs on secp256k1, and the software sequencer on either
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SequencerCurve {
+    K256,
+    #[default]
+    P256,
+}
+
+impl SequencerArgs {
+    pub fn new(scheme: SequencerScheme, num_replica: usize, multicast_ip: IpAddr) -> Self {
+        Self {
+            scheme,
+            num_replica,
+            multicast_ip,
+            port: 60004,
+            seed: None,
+            batch_size: 1,
+            batch_timeout: Duration::from_micros(100),
+            signing_threads: None,
+        }
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let (mut scheme, mut mac_pattern, mut sign_interval) = (None, MacPattern::Windows, 1);
+        let (mut num_replica, mut multicast_ip) = (None, None);
+        let mut parsed = Self::new(
+            SequencerScheme::Ecdsa(Default::default(), 1),
+            0,
+            Ipv4Addr::UNSPECIFIED.into(),
+        );
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or(format!("missing value of {flag}"))?;
+            fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+                value.parse().map_err(|_| format!("invalid {flag} {value}"))
+            }
+            match &*flag {
+                "--scheme" => scheme = Some(value),
+                "--mac-pattern" => mac_pattern = value.parse()?,
+                "--sign-interval" => sign_interval = parse(&flag, &value)?,
+                "--num-replica" => num_replica = Some(parse(&flag, &value)?),
+                "--multicast-ip" => multicast_ip = Some(parse(&flag, &value)?),
+                "--port" => parsed.port = parse(&flag, &value)?,
+                "--seed" => parsed.seed = Some(parse(&flag, &value)?),
+                "--batch-size" => parsed.batch_size = parse(&flag, &value)?,
+                "--batch-timeout-us" => {
+                    parsed.batch_timeout = Duration::from_micros(parse(&flag, &value)?)
+                }
+                // none of the messages would be sent
+                "--signing-threads" => match parse(&flag, &value)? {
+                    0 => return Err(format!("invalid {flag} {value}, at least 1")),
+                    signing_threads => parsed.signing_threads = Some(signing_threads),
+                },
+                _ => return Err(format!("unknown flag {flag}")),
+            }
+        }
+        parsed.scheme = match scheme.as_deref() {
+            Some("half-sip-hash") => SequencerScheme::HalfSipHash(mac_pattern),
+            Some(scheme) => SequencerScheme::Ecdsa(scheme.parse()?, sign_interval),
+            None => return Err(String::from("missing --scheme")),
+        };
+        parsed.num_replica = num_replica.ok_or("missing --num-replica")?;
+        parsed.multicast_ip = multicast_ip.ok_or("missing --multicast-ip")?;
+        Ok(parsed)
+    }
+}
+
+impl std::fmt::Display for SequencerArgs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.scheme {
+            SequencerScheme::HalfSipHash(pattern) => {
+                write!(f, "--scheme half-sip-hash --mac-pattern {pattern}")?
+            }
+            SequencerScheme::Ecdsa(curve, interval) => {
+                write!(f, "--scheme {curve} --sign-interval {interval}")?
+            }
+        }
+        write!(
+            f,
+            " --num-replica {} --multicast-ip {} --port {} --batch-size {} --batch-timeout-us {}",
+            self.num_replica,
+            self.multicast_ip,
+            self.port,
+            self.batch_size,
+            self.batch_timeout.as_micros()
+        )?;
+        if let Some(seed) = self.seed {
+            write!(f, " --seed {seed}")?
+        }
+        if let Some(signing_threads) = self.signing_threads {
+            write!(f, " --signing-threads {signing_threads}")?
+        }
+        Ok(())
+    }
+}
+
+// the form passed to `neo-sequencer` on command line
+impl std::fmt::Display for MacPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Windows => write!(f, "windows"),
+            Self::Rotating => write!(f, "rotating"),
+            Self::Random(num_slot) => write!(f, "random-{num_slot}"),
+        }
+    }
+}
+
+impl std::fmt::Display for SequencerCurve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::K256 => write!(f, "k256"),
+            Self::P256 => write!(f, "p256"),
+        }
+    }
+}
+
+impl std::str::FromStr for SequencerCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "k256" => Ok(Self::K256),
+            "p256" => Ok(Self::P256),
+            _ => Err(format!("unknown scheme {s}")),
+        }
+    }
+}
+
+impl std::str::FromStr for MacPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "windows" => Ok(Self::Windows),
+            "rotating" => Ok(Self::Rotating),
+            _ => match s.strip_prefix("random-").map(str::parse) {
+                Some(Ok(num_slot)) if (1..=4).contains(&num_slot) => Ok(Self::Random(num_slot)),
+                _ => Err(format!("unknown MAC pattern {s}")),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum App {
     Null,
     Ycsb(YcsbConfig),
+    KvStore(KvStoreConfig),
+    Wasm(WasmConfig),
+}
+
+// a WebAssembly module that executes every op, see `app::wasm` of `permissioned-blockchain` for
+// what it exports and imports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WasmConfig {
+    // on every replica host, e.g. `{work_dir}/<file name>` of one in `Environment::wasm_modules`
+    pub module: String,
+    // the export that clients call, with random arguments of `arg_len` bytes
+    pub function: String,
+    pub arg_len: usize,
+    // per op, after which the op fails, `DEFAULT_FUEL` of `app::wasm` if not set
+    pub fuel: Option<u64>,
+}
+
+// the ordered key-value store, populated with `num_key` random keys, which the ops choose uniformly
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct KvStoreConfig {
+    pub num_key: usize,
+    pub key_len: usize,
+    pub value_len: usize,
+    // sum up to 100
+    pub get_portion: u32,
+    pub put_portion: u32,
+    pub delete_portion: u32,
+    pub range_portion: u32,
+    // ranges are uniformly random in length up to this
+    pub max_range_len: usize,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -30,6 +701,58 @@ pub struct YcsbConfig {
     pub read_portion: u32,
     pub update_portion: u32,
     pub rmw_portion: u32,
+    // the portions of all the op types sum up to 100
+    #[serde(default)]
+    pub scan_portion: u32,
+    #[serde(default)]
+    pub insert_portion: u32,
+    // scans read a uniformly random number of records up to this
+    #[serde(default)]
+    pub max_scan_len: usize,
+    #[serde(default)]
+    pub key_distribution: KeyDistribution,
+    // records consist of this many fields of `value_len` each, which updates write one at a time,
+    // or a single value if 0
+    #[serde(default)]
+    pub num_field: usize,
+}
+
+impl YcsbConfig {
+    pub fn check(&self) -> Result<(), String> {
+        let total = self.read_portion
+            + self.update_portion
+            + self.rmw_portion
+            + self.scan_portion
+            + self.insert_portion;
+        if total != 100 {
+            return Err(format!("portions sum up to {total} rather than 100"));
+        }
+        if self.scan_portion != 0 && self.max_scan_len == 0 {
+            return Err(String::from("scans with zero max scan length"));
+        }
+        if self.num_key == 0 || self.num_value == 0 {
+            return Err(String::from("no key or value to choose from"));
+        }
+        match self.key_distribution {
+            KeyDistribution::Zipfian(theta) | KeyDistribution::Latest(theta)
+                if !(theta > 0. && theta < 1.) =>
+            {
+                Err(format!("Zipfian skew {theta} not in (0, 1)"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// how ops choose their keys, where the Zipfian skew is in (0, 1), e.g. 0.99 of standard YCSB
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum KeyDistribution {
+    #[default]
+    Uniform,
+    // over the preloaded keys, of which the first ones are the most popular
+    Zipfian(f64),
+    // over all keys, of which the most recently inserted ones are the most popular, for workload D
+    Latest(f64),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,16 +767,1261 @@ pub struct BenchmarkClient {
     pub num_client: usize, // per group
     pub offset: usize,
     pub duration: Duration,
+    // every client issues requests back to back if not set
+    #[serde(default)]
+    pub skew: Option<ClientSkew>,
+    #[serde(default)]
+    pub load: ClientLoad,
+    // record every invocation with its result into a file, served on `/history` after the benchmark
+    #[serde(default)]
+    pub history: bool,
+}
+
+impl BenchmarkClient {
+    pub fn check(&self) -> Result<(), String> {
+        self.load.check()?;
+        if let Some(skew) = &self.skew {
+            skew.check()?
+        }
+        Ok(())
+    }
+}
+
+// how clients issue requests, where a session is a protocol client with its own client index, as
+// the protocols order the requests of a client index one at a time
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ClientLoad {
+    // every client issues the next request once the previous one completes
+    #[default]
+    Closed,
+    // every client keeps requests outstanding within a window of this many from its earliest
+    // outstanding one, each on a session of its own
+    Pipelined(usize),
+    // requests arrive at this rate per second per group regardless of completions, as a Poisson
+    // process, and are served by the group's clients, i.e. at most `num_client` outstanding, in
+    // arrival order. the latency includes the wait for an idle client
+    Open(f64),
+}
+
+impl ClientLoad {
+    pub fn num_session(&self) -> usize {
+        match self {
+            Self::Pipelined(window) => *window,
+            _ => 1,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        match *self {
+            Self::Pipelined(0) => Err(String::from("empty window")),
+            Self::Open(rate) if !(rate.is_finite() && rate > 0.) => {
+                Err(format!("arrival rate {rate} not positive"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Display for ClientLoad {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Closed => write!(f, "closed"),
+            Self::Pipelined(window) => write!(f, "pipelined-{window}"),
+            Self::Open(rate) => write!(f, "open-{rate}"),
+        }
+    }
+}
+
+// the first `heavy_fraction` of clients in every group are heavy hitters, and each of the rest issues
+// `1 / weight` as many requests as one, by thinking between requests in the closed loop, or by
+// receiving `1 / weight` as many arrivals in the open loop
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ClientSkew {
+    pub heavy_fraction: f64,
+    pub weight: f64,
+}
+
+impl ClientSkew {
+    // rate weight of every client in a group of `num_client`, relative to a heavy hitter
+    pub fn weights(&self, num_client: usize) -> Vec<f64> {
+        let num_heavy = (self.heavy_fraction * num_client as f64).ceil() as usize;
+        Vec::from_iter((0..num_client).map(|i| if i < num_heavy { 1. } else { 1. / self.weight }))
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if !(0. ..=1.).contains(&self.heavy_fraction) {
+            return Err(format!(
+                "heavy fraction {} not in [0, 1]",
+                self.heavy_fraction
+            ));
+        }
+        if !(self.weight.is_finite() && self.weight >= 1.) {
+            return Err(format!("weight {} less than 1", self.weight));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Replica {
     //
     pub index: u8,
+    // run at the address in `Task::standby_addrs`, logging and executing silently until promoted
+    #[serde(default)]
+    pub standby: bool,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+// modes supported by `permissioned-blockchain`
+pub const MODES: &[&str] = &[
+    "unreplicated",
+    "unreplicated-om",
+    "neo-hm",
+    "neo-pk",
+    "neo-bn",
+    "pbft",
+    "pbft-sq",
+    "zyzzyva",
+    "zyzzyva-f",
+    "hotstuff",
+    "hotstuff-bls",
+    "hotstuff-mempool",
+    "minbft",
+    "sbft",
+    "tendermint",
+];
+
+// apps supported by `permissioned-blockchain`
+pub const APPS: &[&str] = &["null", "ycsb", "kvstore", "wasm"];
+
+// identity of a binary's build, printed by `--version` of every binary and served on
+// `/build-info` by `permissioned-blockchain`, so that control can refuse to run against stale
+// binaries
+// `permissioned-blockchain` reports only the modes and apps compiled in, which are fewer than what
+// the source supports when built with less features
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub package: String,
+    pub version: String,
+    pub git_hash: String,
+    pub target: String,
+    pub profile: String,
+    pub modes: Vec<String>,
+    pub apps: Vec<String>,
+}
+
+// expands in the calling crate, so package and profile are the caller's
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::BuildInfo::new(
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            if cfg!(debug_assertions) {
+                "debug"
+            } else {
+                "release"
+            },
+        )
+    };
+}
+
+impl BuildInfo {
+    pub fn new(package: &str, version: &str, profile: &str) -> Self {
+        Self {
+            package: package.into(),
+            version: version.into(),
+            git_hash: env!("NEO_GIT_HASH").into(),
+            target: env!("NEO_TARGET").into(),
+            profile: profile.into(),
+            modes: MODES.iter().map(|&mode| mode.into()).collect(),
+            apps: APPS.iter().map(|&app| app.into()).collect(),
+        }
+    }
+
+    // print and exit if the first argument is `--version`
+    pub fn exit_on_version_flag(&self) {
+        if std::env::args().nth(1).as_deref() == Some("--version") {
+            println!("{self}");
+            std::process::exit(0)
+        }
+    }
+
+    // whether two builds come from the same source, regardless of which package they are and which
+    // features they are built with
+    pub fn same_source(&self, other: &Self) -> bool {
+        self.git_hash == other.git_hash
+    }
+
+    pub fn with_registry<'a>(
+        self,
+        modes: impl IntoIterator<Item = &'a str>,
+        apps: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        Self {
+            modes: modes.into_iter().map(Into::into).collect(),
+            apps: apps.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+}
+
+impl std::fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} ({} {} {}) modes: {} apps: {}",
+            self.package,
+            self.version,
+            self.git_hash,
+            self.target,
+            self.profile,
+            self.modes.join(" "),
+            self.apps.join(" ")
+        )
+    }
+}
+
+// replica count requirements of a protocol mode, consulted by control when deciding which replicas
+// to start and by replicas when checking their assigned index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Liveness {
+    // replicas that participate in the protocol, i.e. valid indices are `0..num_replica`
+    pub num_replica: usize,
+    // replicas that must be started for the protocol to make progress, the rest (with highest
+    // indices) are omitted in benchmarks, standing in for faulty ones
+    pub num_live: usize,
+}
+
+impl Liveness {
+    pub fn new(mode: &str, num_faulty: usize) -> Self {
+        let (num_replica, num_live) = match mode {
+            "unreplicated" | "unreplicated-om" => (1, 1),
+            "minbft" => (2 * num_faulty + 1, num_faulty + 1),
+            // fast path requires all replicas
+            "zyzzyva" => (3 * num_faulty + 1, 3 * num_faulty + 1),
+            // n = 3f + 2c + 1 with c = 0, i.e. the primary is the only collector, and the fallback
+            // commits with 2f + c + 1 shares while f replicas are down
+            "sbft" => (3 * num_faulty + 1, 2 * num_faulty + 1),
+            "neo-hm" | "neo-pk" | "neo-bn" | "pbft" | "pbft-sq" | "zyzzyva-f" | "hotstuff"
+            | "hotstuff-bls" | "hotstuff-mempool" | "tendermint" => {
+                (3 * num_faulty + 1, 2 * num_faulty + 1)
+            }
+            _ => panic!("unknown mode {mode}"),
+        };
+        Self {
+            num_replica,
+            num_live,
+        }
+    }
+
+    pub fn may_omit(&self, index: usize) -> bool {
+        index >= self.num_live
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BenchmarkStats {
     pub throughput: f32,
     pub average_latency: Option<Duration>,
+    pub socket_drops: u64,
+    // one per client group, which are colocated on the benchmark host
+    pub groups: Vec<GroupLatency>,
+    // Jain's index of the groups' average latencies, 1 if they are all equal, down to 1/n if one
+    // group takes all the latency
+    pub latency_fairness: Option<f64>,
+    // from invoking to the first reply over all groups, for protocols that reply speculatively, so
+    // it can be compared against the commit latency above
+    pub first_reply: Option<GroupLatency>,
+    // latency of the heavy hitters over all groups, if the clients are skewed
+    pub heavy_hitters: Option<GroupLatency>,
+    // latency over all groups, mergeable across benchmark hosts
+    #[serde(default)]
+    pub latency_histogram: LatencyHistogram,
+    // ops completed in each second of the run over all groups, to tell warm-up and instability
+    // apart from the aggregate `throughput`
+    #[serde(default)]
+    pub throughput_timeline: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct GroupLatency {
+    pub num_op: usize,
+    pub average: Option<Duration>,
+    pub p50: Option<Duration>,
+    pub p99: Option<Duration>,
+}
+
+impl GroupLatency {
+    // sorts `latencies` in place
+    pub fn new(latencies: &mut [Duration]) -> Self {
+        latencies.sort_unstable();
+        // nearest rank
+        let percentile = |q: f64| {
+            let rank = (q * latencies.len() as f64).ceil().max(1.) as usize;
+            latencies.get(rank - 1).copied()
+        };
+        Self {
+            num_op: latencies.len(),
+            average: latencies
+                .iter()
+                .sum::<Duration>()
+                .checked_div(latencies.len() as u32),
+            p50: percentile(0.5),
+            p99: percentile(0.99),
+        }
+    }
+}
+
+pub fn jain_index(values: impl IntoIterator<Item = f64>) -> Option<f64> {
+    let (mut num, mut sum, mut square_sum) = (0, 0., 0.);
+    for value in values {
+        num += 1;
+        sum += value;
+        square_sum += value * value
+    }
+    if square_sum == 0. {
+        return None;
+    }
+    Some(sum * sum / (num as f64 * square_sum))
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplicaStats {
+    // packets dropped by kernel e.g. on receive buffer overflow, summed over all sockets
+    pub socket_drops: u64,
+    // neo-hm only
+    pub mac_coverage: Option<MacCoverageStats>,
+    // neo-pk and neo-bn only
+    pub signatures: Option<SignatureStats>,
+    // ordered multicast rejected for coming from a source other than `Task::multicast_sources`
+    pub spoofed_multicast: u64,
+    // ordered multicast dropped for failing verification
+    #[serde(default)]
+    pub invalid_multicast: u64,
+    // dropped for `Task::reject_stale`
+    pub stale: u64,
+    // unreplicated and neo only
+    pub client_table: Option<ClientTableStats>,
+    // neo only
+    pub query_trigger: Option<QueryTriggerStats>,
+    // messages waiting for blocks or op numbers that are not known yet, neo, pbft and hotstuff only
+    pub waiting: Option<WaitingStats>,
+    // committed ops under each verification strictness in order, neo only as other protocols do
+    // not report commits
+    pub strictness_phases: Vec<StrictnessPhase>,
+    // from receiving a message on socket to start handling it
+    pub queueing_delay: Log2Histogram,
+    // handling of the message, including verification
+    pub processing_delay: Log2Histogram,
+    // from the packet timestamped by kernel or NIC to being received from socket, if timestamping
+    // is enabled
+    pub socket_delay: Option<Log2Histogram>,
+    pub utilization: Utilization,
+    // ordered multicast modes only
+    pub multicast_sources: Vec<MulticastSourceStats>,
+    // with `Task::sequencing_timing` only
+    pub sequencing: Option<SequencingStats>,
+    // the wall clock of executing every `EXECUTION_INDEX_INTERVAL`th op, by the op number of the
+    // protocol
+    pub execution_index: Vec<(u64, SystemTime)>,
+}
+
+impl ReplicaStats {
+    pub const EXECUTION_INDEX_INTERVAL: u64 = 1 << 10;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MulticastSourceStats {
+    pub source: String,
+    // the op number is ordered already with the same message
+    pub duplicated: u64,
+    // the op number is lower than one received from the source before
+    pub regressed: u64,
+    // the op number is ordered already with another message, i.e. the sequencer equivocates
+    pub equivocated: u64,
+    pub max_op_num: u64,
+}
+
+// the latency of the sequencing hop, measured with the wall clocks of clients, the software sequencer
+// and replicas, so the breakdown is as accurate as the clocks are synchronized
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SequencingStats {
+    // from client sending to sequencer receiving
+    pub to_sequencer: Log2Histogram,
+    // from sequencer receiving to sending, i.e. sequencing and authenticating
+    pub in_sequencer: Log2Histogram,
+    // from sequencer sending to replica receiving, through the relays if any
+    pub to_replica: Log2Histogram,
+    // not sequenced by the software sequencer, e.g. by the switch, so only the whole hop is recorded
+    // into `to_replica`
+    pub unstamped: u64,
+    // deltas that are negative because of clock skew, recorded as zero
+    pub skewed: u64,
+}
+
+impl SequencingStats {
+    // times in nanoseconds since unix epoch
+    pub fn record(
+        &mut self,
+        client_send: u64,
+        sequencer_receive: u64,
+        sequencer_send: u64,
+        replica_receive: u64,
+    ) {
+        let mut delta = |start: u64, end: u64| {
+            if end < start {
+                self.skewed += 1
+            }
+            Duration::from_nanos(end.saturating_sub(start))
+        };
+        if sequencer_receive == 0 {
+            let hop = delta(client_send, replica_receive);
+            self.unstamped += 1;
+            self.to_replica.record(hop);
+            return;
+        }
+        let (to_sequencer, in_sequencer, to_replica) = (
+            delta(client_send, sequencer_receive),
+            delta(sequencer_receive, sequencer_send),
+            delta(sequencer_send, replica_receive),
+        );
+        self.to_sequencer.record(to_sequencer);
+        self.in_sequencer.record(in_sequencer);
+        self.to_replica.record(to_replica)
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.to_sequencer.merge(&other.to_sequencer);
+        self.in_sequencer.merge(&other.in_sequencer);
+        self.to_replica.merge(&other.to_replica);
+        self.unstamped += other.unstamped;
+        self.skewed += other.skewed
+    }
+}
+
+// how the protocol thread spends a run, which tells its saturation apart from crypto or network
+// bottlenecks
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Utilization {
+    // handling messages and timers, including deserialization and verification
+    pub busy: Duration,
+    // in `on_pace`, e.g. flushing batches and signing
+    pub pacing: Duration,
+    // blocked on the empty event channel
+    pub idle: Duration,
+    pub num_event: u64,
+    pub num_pace: u64,
+}
+
+impl Utilization {
+    pub fn merge(&mut self, other: &Self) {
+        self.busy += other.busy;
+        self.pacing += other.pacing;
+        self.idle += other.idle;
+        self.num_event += other.num_event;
+        self.num_pace += other.num_pace
+    }
+
+    // portion of time not idle, none if no time is accounted
+    pub fn ratio(&self) -> Option<f64> {
+        let working = self.busy + self.pacing;
+        let total = working + self.idle;
+        if total.is_zero() {
+            return None;
+        }
+        Some(working.as_secs_f64() / total.as_secs_f64())
+    }
+
+    // events handled between consecutive paces, none if it never paces
+    pub fn events_per_pace(&self) -> Option<f64> {
+        if self.num_pace == 0 {
+            return None;
+        }
+        Some(self.num_event as f64 / self.num_pace as f64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct MacCoverageStats {
+    // number of ordered multicast with and without a MAC for the replica
+    pub verified: u64,
+    pub unverified: u64,
+    // the longest streak of messages without a MAC for the replica
+    pub max_unverified_run: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SignatureStats {
+    // ordered multicast with a verified signature
+    pub verified: u64,
+    // signed but covered by the link of a later verified one instead
+    pub skipped: u64,
+    // not signed
+    pub linked: u64,
+    // signed although not on the interval, to end a burst
+    pub fallback: u64,
+}
+
+impl SignatureStats {
+    // portion of ordered multicast accepted without verifying a signature of their own
+    pub fn fast_verify_ratio(&self) -> f64 {
+        let total = self.verified + self.skipped + self.linked;
+        if total == 0 {
+            return 0.;
+        }
+        (self.skipped + self.linked) as f64 / total as f64
+    }
+}
+
+// `counts[i]` is the number of durations in [2^(i-1), 2^i) microseconds, or below 1 microsecond
+// for `i == 0`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Log2Histogram {
+    pub counts: Vec<u64>,
+}
+
+impl Log2Histogram {
+    pub fn record(&mut self, duration: Duration) {
+        let micros = duration.as_micros() as u64;
+        let index = (u64::BITS - micros.leading_zeros()) as usize;
+        if self.counts.len() <= index {
+            self.counts.resize(index + 1, 0)
+        }
+        self.counts[index] += 1
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0)
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other
+        }
+    }
+
+    // the upper bound of the bucket that contains the `q` quantile
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let total = self.counts.iter().sum::<u64>();
+        let mut rank = (q * total as f64).ceil().max(1.) as u64;
+        for (index, &count) in self.counts.iter().enumerate() {
+            if rank <= count {
+                return Some(Duration::from_micros(1 << index));
+            }
+            rank -= count
+        }
+        None
+    }
+}
+
+// HDR-style, i.e. durations in nanoseconds are bucketed by their highest bit, and each bucket is
+// split linearly into `SUB_BUCKETS` sub-buckets, so a recorded duration is off by less than
+// 1 / `SUB_BUCKETS` of itself, while the counts stay within a few thousand even for seconds
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    pub counts: Vec<u64>,
+    pub max: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyPercentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    pub max: Duration,
+}
+
+impl LatencyHistogram {
+    const SUB_BITS: u32 = 7;
+    const SUB_BUCKETS: u64 = 1 << Self::SUB_BITS;
+
+    fn index(nanos: u64) -> usize {
+        let bits = u64::BITS - nanos.leading_zeros();
+        if bits <= Self::SUB_BITS + 1 {
+            // exact below `2 * SUB_BUCKETS`
+            return nanos as _;
+        }
+        let shift = bits - Self::SUB_BITS - 1;
+        (shift as u64 * Self::SUB_BUCKETS + (nanos >> shift)) as _
+    }
+
+    // the highest duration that falls into the bucket
+    fn value(index: usize) -> Duration {
+        let index = index as u64;
+        if index < 2 * Self::SUB_BUCKETS {
+            return Duration::from_nanos(index);
+        }
+        let shift = index / Self::SUB_BUCKETS - 1;
+        let top = index - shift * Self::SUB_BUCKETS;
+        Duration::from_nanos(((top + 1) << shift) - 1)
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        let index = Self::index(duration.as_nanos() as _);
+        if self.counts.len() <= index {
+            self.counts.resize(index + 1, 0)
+        }
+        self.counts[index] += 1;
+        self.max = self.max.max(duration)
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0)
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other
+        }
+        self.max = self.max.max(other.max)
+    }
+
+    pub fn num_op(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // nearest rank, no higher than the recorded maximum
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let mut rank = (q * self.num_op() as f64).ceil().max(1.) as u64;
+        for (index, &count) in self.counts.iter().enumerate() {
+            if rank <= count {
+                return Some(Self::value(index).min(self.max));
+            }
+            rank -= count
+        }
+        None
+    }
+
+    pub fn percentiles(&self) -> Option<LatencyPercentiles> {
+        Some(LatencyPercentiles {
+            p50: self.quantile(0.5)?,
+            p90: self.quantile(0.9)?,
+            p99: self.quantile(0.99)?,
+            p999: self.quantile(0.999)?,
+            max: self.max,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ClientTableStats {
+    // entries of idle clients removed to make room for new clients
+    pub evicted: u64,
+    // replies recorded past the capacity because no entry can be evicted yet
+    pub overflowed: u64,
+    // requests dropped because the client index is used by another remote
+    pub collided: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct WaitingStats {
+    pub evicted: u64,
+    pub rejected: u64,
+    // the most entries ever waiting at the same time
+    pub max_len: usize,
+}
+
+impl WaitingStats {
+    // combine the stats of the multiple maps of a replica
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            evicted: self.evicted + other.evicted,
+            rejected: self.rejected + other.rejected,
+            max_len: self.max_len.max(other.max_len),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct StrictnessPhase {
+    pub strictness: Strictness,
+    pub num_op: u64,
+    pub duration: Duration,
+}
+
+// estimation of the adaptive query trigger at the end of the run
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct QueryTriggerStats {
+    // ordered multicast per second
+    pub ordered_rate: f64,
+    pub query_rtt: Duration,
+    // portion of queries that find the hole still there when replied
+    pub loss_rate: f64,
+    // number of pending messages behind a hole that triggers querying
+    pub threshold: usize,
+    pub num_query: u64,
+    pub num_premature: u64,
+}
+
+// a deployment target of the evaluation, described by `scripts/environments/<name>.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Environment {
+    pub client_hosts: Vec<String>,
+    pub client_ips: Vec<IpAddr>,
+    pub replica_hosts: Vec<String>,
+    pub replica_ips: Vec<IpAddr>,
+    pub multicast_ip: IpAddr,
+    // the host that runs `neo-sequencer` in place of a switch, if any
+    pub sequencer_host: Option<String>,
+    pub relay_ips: Vec<IpAddr>,
+    // hosts of the relays in the order of `relay_ips`, which are restarted with the destinations of
+    // every run, see `Topology`
+    // relays are left as they are if empty
+    #[serde(default)]
+    pub relay_hosts: Vec<String>,
+    // the first relay forwarding to all the others if omitted
+    #[serde(default)]
+    pub relay_tree: Option<RelayTree>,
+    // hosts of standby replicas, which replace replicas mid-run in the `standby` campaign
+    #[serde(default)]
+    pub standby_hosts: Vec<String>,
+    #[serde(default)]
+    pub standby_ips: Vec<IpAddr>,
+    // the development environment, if it is also one of the servers
+    pub localhost: Option<String>,
+    pub work_dir: String,
+    // serve control endpoints over TLS, and require a token for the mutating ones
+    // credentials are generated by `reload`, see `Environment::tls_path`
+    #[serde(default)]
+    pub tls: bool,
+    // sign with keys generated by `reload`, of which each replica host receives only its own secret
+    // ones, see `Environment::keys_path`
+    #[serde(default)]
+    pub keys: bool,
+    // timestamping received packets on replicas, the hardware one only if every replica NIC
+    // supports it
+    #[serde(default)]
+    pub timestamping: Option<Timestamping>,
+    // local paths of WebAssembly modules, which `reload` copies into the work directory of every
+    // replica and standby host, see `WasmConfig::module`
+    #[serde(default)]
+    pub wasm_modules: Vec<String>,
+}
+
+// relays forward along a tree rooted at the first one in `relay_ips`, where relay `i` forwards to
+// relays `branching * i + 1..=branching * i + branching`, and the relays without child relays split
+// the replicas evenly
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RelayTree {
+    pub branching: usize,
+    // the number of levels including the root, as many as `relay_ips` fills if omitted
+    // the relays beyond are left idle
+    #[serde(default)]
+    pub depth: Option<usize>,
+}
+
+impl Environment {
+    pub const VAR: &'static str = "NEO_ENVIRONMENT";
+
+    pub fn path(name: &str) -> String {
+        format!("scripts/environments/{name}.toml")
+    }
+
+    // `ca.pem`, `cert.pem`, `key.pem` and `token` of the environment
+    pub fn tls_path(name: &str, file: &str) -> String {
+        format!("scripts/environments/{name}.tls/{file}")
+    }
+
+    pub fn keys_path(name: &str, file: &str) -> String {
+        format!("scripts/environments/{name}.keys/{file}")
+    }
+
+    // the environment named by `NEO_ENVIRONMENT`, or the local cluster if not set
+    pub fn name() -> String {
+        std::env::var(Self::VAR).unwrap_or(String::from("local"))
+    }
+
+    pub fn load() -> Self {
+        let path = Self::path(&Self::name());
+        let content =
+            std::fs::read_to_string(&path).unwrap_or_else(|err| panic!("reading {path}: {err}"));
+        toml::from_str(&content).unwrap_or_else(|err| panic!("parsing {path}: {err}"))
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        if self.tls {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    // the hosts taking part in a run of `liveness`, chosen from the front of the environment's
+    pub fn topology(&self, liveness: &Liveness) -> Topology {
+        assert!(
+            self.replica_ips.len() >= liveness.num_live,
+            "there are only {} replicas",
+            self.replica_ips.len()
+        );
+        let live_ips = &self.replica_ips[..liveness.num_live];
+        // the standbys must receive the ordered multicast as well
+        let fan_out = Vec::from_iter(live_ips.iter().chain(&self.standby_ips).copied());
+        let relay_destinations = self.relay_destinations(&fan_out);
+        Topology {
+            replica_hosts: self.replica_hosts[..liveness.num_live].to_vec(),
+            replica_addrs: Vec::from_iter(
+                self.replica_ips
+                    .iter()
+                    .map(|&ip| SocketAddr::from((ip, 10000)))
+                    .chain((30000..).map(|port| SocketAddr::from(([127, 0, 0, 1], port))))
+                    .take(liveness.num_replica),
+            ),
+            relay_destinations,
+        }
+    }
+
+    fn relay_destinations(&self, fan_out: &[IpAddr]) -> Vec<Vec<IpAddr>> {
+        let num_relay = self.relay_ips.len();
+        if num_relay == 0 {
+            return Vec::new();
+        }
+        let (branching, depth) = match &self.relay_tree {
+            Some(tree) => (tree.branching, tree.depth.unwrap_or(usize::MAX)),
+            None => ((num_relay - 1).max(1), usize::MAX),
+        };
+        assert!(
+            branching > 0 && depth > 0,
+            "invalid relay tree {:?}",
+            self.relay_tree
+        );
+        let mut num_used = 0;
+        let mut level_len = 1usize;
+        for _ in 0..depth {
+            if num_used == num_relay {
+                break;
+            }
+            num_used = (num_used + level_len).min(num_relay);
+            level_len = level_len.saturating_mul(branching)
+        }
+        let children = |index: usize| {
+            let start = (branching * index + 1).min(num_used);
+            start..(start + branching).min(num_used)
+        };
+        let mut destinations =
+            Vec::from_iter((0..num_used).map(|index| self.relay_ips[children(index)].to_vec()));
+        let leaves = Vec::from_iter((0..num_used).filter(|&index| children(index).is_empty()));
+        let chunk_len = fan_out.len().div_ceil(leaves.len()).max(1);
+        for (index, chunk) in leaves.into_iter().zip(fan_out.chunks(chunk_len)) {
+            destinations[index] = chunk.to_vec()
+        }
+        destinations.resize(num_relay, Vec::new());
+        destinations
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Topology {
+    // the started replicas
+    pub replica_hosts: Vec<String>,
+    // every replica of the protocol, where the omitted ones beyond the environment's get local
+    // placeholders that are never started nor sent to
+    pub replica_addrs: Vec<SocketAddr>,
+    // the destinations of each relay in `relay_ips`
+    pub relay_destinations: Vec<Vec<IpAddr>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_load_check() {
+        let mut config = BenchmarkClient {
+            num_group: 1,
+            num_client: 1,
+            offset: 0,
+            duration: Duration::from_secs(1),
+            skew: None,
+            load: ClientLoad::Open(1e3),
+            history: false,
+        };
+        assert_eq!(config.check(), Ok(()));
+        for load in [
+            ClientLoad::Open(0.),
+            ClientLoad::Open(-1.),
+            ClientLoad::Open(f64::NAN),
+            ClientLoad::Pipelined(0),
+        ] {
+            config.load = load;
+            assert!(config.check().is_err(), "{load}")
+        }
+        config.load = ClientLoad::Open(1e3);
+        config.skew = Some(ClientSkew {
+            heavy_fraction: 0.1,
+            weight: 10.,
+        });
+        assert_eq!(config.check(), Ok(()));
+        for (heavy_fraction, weight) in [(-0.1, 10.), (1.1, 10.), (0.1, 0.5), (0.1, f64::NAN)] {
+            config.skew = Some(ClientSkew {
+                heavy_fraction,
+                weight,
+            });
+            assert!(config.check().is_err())
+        }
+    }
+
+    #[test]
+    fn client_destination_check() {
+        assert_eq!(ClientDestination::Multicast.check("neo-hm"), Ok(()));
+        assert!(ClientDestination::All.check("neo-pk").is_err());
+        assert_eq!(ClientDestination::Multicast.check("pbft-sq"), Ok(()));
+        assert_eq!(ClientDestination::Primary.check("pbft-sq"), Ok(()));
+        assert!(ClientDestination::Multicast.check("hotstuff").is_err());
+        assert_eq!(ClientDestination::Random.check("zyzzyva"), Ok(()));
+    }
+
+    #[test]
+    fn ycsb_config_check() {
+        let mut config = YcsbConfig {
+            num_key: 10,
+            num_value: 10,
+            key_len: 8,
+            value_len: 8,
+            read_portion: 0,
+            update_portion: 0,
+            rmw_portion: 0,
+            scan_portion: 95,
+            insert_portion: 5,
+            max_scan_len: 100,
+            key_distribution: KeyDistribution::Latest(0.99),
+            num_field: 0,
+        };
+        assert_eq!(config.check(), Ok(()));
+        for theta in [0., 1., -0.5, f64::NAN] {
+            config.key_distribution = KeyDistribution::Zipfian(theta);
+            assert!(config.check().is_err(), "{theta}")
+        }
+        config.key_distribution = KeyDistribution::Uniform;
+        config.max_scan_len = 0;
+        assert!(config.check().is_err());
+        config.max_scan_len = 100;
+        config.insert_portion = 10;
+        assert!(config.check().is_err())
+    }
+
+    // sbft commits on the fallback with f replicas down, while zyzzyva is run on its fast path
+    #[test]
+    fn liveness() {
+        assert_eq!(
+            Liveness::new("sbft", 1),
+            Liveness {
+                num_replica: 4,
+                num_live: 3
+            }
+        );
+        assert_eq!(Liveness::new("zyzzyva", 1).num_live, 4);
+        assert_eq!(Liveness::new("minbft", 2).num_replica, 5)
+    }
+
+    #[test]
+    fn latency_fairness() {
+        assert_eq!(jain_index([]), None);
+        assert_eq!(jain_index([2., 2., 2.]), Some(1.));
+        assert_eq!(jain_index([1., 0., 0., 0.]), Some(0.25));
+
+        let mut latencies = Vec::from_iter((1..=100).rev().map(Duration::from_micros));
+        let group = GroupLatency::new(&mut latencies);
+        assert_eq!(group.num_op, 100);
+        assert_eq!(group.p50, Some(Duration::from_micros(50)));
+        assert_eq!(group.p99, Some(Duration::from_micros(99)));
+        assert_eq!(group.average, Some(Duration::from_nanos(50500)));
+    }
+
+    #[test]
+    fn log2_histogram() {
+        let mut histogram = Log2Histogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+        for micros in [1, 3, 100] {
+            histogram.record(Duration::from_micros(micros))
+        }
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_micros(4)));
+        assert_eq!(histogram.quantile(1.), Some(Duration::from_micros(128)));
+        assert_eq!(histogram.quantile(1.5), None)
+    }
+
+    #[test]
+    fn utilization() {
+        let mut utilization = Utilization::default();
+        assert_eq!(utilization.ratio(), None);
+        assert_eq!(utilization.events_per_pace(), None);
+        utilization.merge(&Utilization {
+            busy: Duration::from_millis(1),
+            pacing: Duration::from_millis(1),
+            idle: Duration::from_millis(2),
+            num_event: 10,
+            num_pace: 4,
+        });
+        assert_eq!(utilization.ratio(), Some(0.5));
+        assert_eq!(utilization.events_per_pace(), Some(2.5))
+    }
+
+    #[test]
+    fn latency_histogram() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentiles(), None);
+        for micros in 1..=1000 {
+            histogram.record(Duration::from_micros(micros))
+        }
+        histogram.record(Duration::from_secs(1));
+        let percentiles = histogram.percentiles().unwrap();
+        let close = |duration: Duration, micros: f64| {
+            (duration.as_secs_f64() * 1e6 / micros - 1.).abs() < 1. / 128.
+        };
+        assert!(close(percentiles.p50, 501.));
+        assert!(close(percentiles.p90, 901.));
+        assert!(close(percentiles.p99, 991.));
+        assert!(close(percentiles.p999, 1000.));
+        assert_eq!(percentiles.max, Duration::from_secs(1));
+        assert!(histogram.counts.len() < 4096);
+
+        let mut merged = LatencyHistogram::default();
+        merged.record(Duration::from_nanos(100));
+        merged.merge(&histogram);
+        assert_eq!(merged.num_op(), 1002);
+        assert_eq!(merged.quantile(0.), Some(Duration::from_nanos(100)));
+        for index in 0..histogram.counts.len() {
+            assert_eq!(
+                LatencyHistogram::index(LatencyHistogram::value(index).as_nanos() as _),
+                index
+            )
+        }
+    }
+
+    #[test]
+    fn client_skew() {
+        let skew = ClientSkew {
+            heavy_fraction: 0.2,
+            weight: 4.,
+        };
+        assert_eq!(skew.weights(5), [1., 0.25, 0.25, 0.25, 0.25]);
+        // at least one heavy hitter per group
+        assert_eq!(skew.weights(2), [1., 0.25]);
+    }
+
+    #[test]
+    fn topology_fan_out() {
+        let ips = |n: u8| Vec::from_iter((0..n).map(|i| IpAddr::from([10, 0, 0, i])));
+        let mut environment = Environment {
+            client_hosts: Vec::new(),
+            client_ips: Vec::new(),
+            replica_hosts: Vec::from_iter((0..10).map(|i| format!("replica{i}"))),
+            replica_ips: ips(10),
+            multicast_ip: [10, 0, 0, 255].into(),
+            sequencer_host: None,
+            relay_ips: ips(4),
+            relay_hosts: Vec::new(),
+            relay_tree: None,
+            standby_hosts: Vec::new(),
+            standby_ips: Vec::new(),
+            localhost: None,
+            work_dir: String::new(),
+            tls: false,
+            keys: false,
+            timestamping: None,
+            wasm_modules: Vec::new(),
+        };
+        let topology = environment.topology(&Liveness::new("pbft", 2));
+        assert_eq!(topology.replica_hosts.len(), 5);
+        assert_eq!(topology.replica_addrs.len(), 7);
+        assert_eq!(topology.relay_destinations[0], ips(4)[1..]);
+        assert_eq!(
+            Vec::from_iter(topology.relay_destinations[1..].iter().map(Vec::len)),
+            [2, 2, 1]
+        );
+
+        let topology = environment.topology(&Liveness::new("zyzzyva", 3));
+        assert_eq!(topology.replica_hosts.len(), 10);
+        environment.relay_ips = ips(8);
+        environment.relay_tree = Some(RelayTree {
+            branching: 2,
+            depth: None,
+        });
+        let topology = environment.topology(&Liveness::new("zyzzyva", 3));
+        assert_eq!(topology.relay_destinations[0], ips(3)[1..]);
+        assert_eq!(topology.relay_destinations[1], ips(5)[3..]);
+        // the last level is partial
+        assert_eq!(topology.relay_destinations[3], ips(8)[7..]);
+        assert_eq!(
+            Vec::from_iter(topology.relay_destinations.iter().map(Vec::len)),
+            [2, 2, 2, 1, 3, 3, 3, 1]
+        );
+        environment.relay_tree = Some(RelayTree {
+            branching: 2,
+            depth: Some(2),
+        });
+        let topology = environment.topology(&Liveness::new("zyzzyva", 3));
+        assert_eq!(
+            Vec::from_iter(topology.relay_destinations.iter().map(Vec::len)),
+            [2, 5, 5, 0, 0, 0, 0, 0]
+        );
+        environment.relay_tree = None;
+        environment.relay_ips.truncate(1);
+        let topology = environment.topology(&Liveness::new("zyzzyva", 3));
+        assert_eq!(topology.relay_destinations, [ips(10)]);
+    }
+
+    #[test]
+    fn parse_scenario() {
+        let scenario = "
+            # comment
+            2s isolate 1
+            0s drop 0.01
+            1.5ms reorder 0.1 500us # trailing comment
+        "
+        .parse::<Scenario>()
+        .unwrap();
+        assert_eq!(
+            scenario.0,
+            [
+                (Duration::ZERO, ChaosAction::Drop(0.01)),
+                (
+                    Duration::from_micros(1500),
+                    ChaosAction::Reorder(0.1, Duration::from_micros(500))
+                ),
+                (Duration::from_secs(2), ChaosAction::Isolate(1)),
+            ]
+        );
+        let scenario = "10s partition 0 1\n20s heal".parse::<Scenario>().unwrap();
+        assert_eq!(
+            scenario.0,
+            [
+                (Duration::from_secs(10), ChaosAction::Partition(0b11)),
+                (Duration::from_secs(20), ChaosAction::Heal),
+            ]
+        );
+        assert_eq!(
+            scenario.fault_window(),
+            Some((Duration::from_secs(10), Duration::from_secs(20)))
+        );
+        assert_eq!(scenario.check(4), Ok(()));
+        assert!(scenario.check(1).is_err());
+        // the rejoin of another replica leaves the isolated one out
+        let scenario = "1s isolate 1\n2s rejoin 2\n3s heal\n4s rejoin 1"
+            .parse::<Scenario>()
+            .unwrap();
+        assert_eq!(
+            scenario.fault_window(),
+            Some((Duration::from_secs(1), Duration::from_secs(4)))
+        );
+        assert!(scenario.check(2).is_err());
+        assert_eq!(
+            "1s partition 127".parse::<Scenario>().unwrap().0,
+            [(Duration::from_secs(1), ChaosAction::Partition(1 << 127))]
+        );
+        assert!("1s partition 128".parse::<Scenario>().is_err());
+        assert!("1s isolate 256".parse::<Scenario>().is_err());
+        assert!("1s isolate 1.5".parse::<Scenario>().is_err());
+        assert!("1s isolate".parse::<Scenario>().is_err());
+        assert!("1s partition".parse::<Scenario>().is_err());
+        assert!("1m drop 0.1".parse::<Scenario>().is_err());
+        assert!("1s reorder 1.5 1ms".parse::<Scenario>().is_err());
+        assert!("1s drop -0.1".parse::<Scenario>().is_err());
+        assert!("1s reorder nan 1ms".parse::<Scenario>().is_err());
+        include_str!("../../scenarios/isolate-backup.txt")
+            .parse::<Scenario>()
+            .unwrap();
+        include_str!("../../scenarios/partition-heal.txt")
+            .parse::<Scenario>()
+            .unwrap();
+    }
+
+    #[test]
+    fn parse_network_model() {
+        let model = toml::from_str::<NetworkModel>(
+            "
+            replica_links = [[0, 2, { delay_us = 40000, bandwidth = 12500000 }]]
+            client_link = { loss_rate = 0.001, delay_us = 1000, jitter_us = 200 }
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            model.replica_links,
+            [(
+                0,
+                2,
+                LinkModel {
+                    delay_us: 40000,
+                    bandwidth: Some(12500000),
+                    ..Default::default()
+                }
+            )]
+        );
+        assert_eq!(model.client_link.unwrap().jitter_us, 200);
+        assert_eq!(model.multicast_link, None);
+        assert_eq!(model.check(), Ok(()));
+        for link in [
+            "{ loss_rate = 1.5 }",
+            "{ loss_rate = -0.1 }",
+            "{ loss_rate = nan }",
+            "{ bandwidth = 0 }",
+        ] {
+            let model = toml::from_str::<NetworkModel>(&format!("client_link = {link}")).unwrap();
+            assert!(model.check().is_err(), "{link}")
+        }
+    }
+
+    #[test]
+    fn sequencer_args() {
+        let mut args = SequencerArgs::new(
+            SequencerScheme::HalfSipHash(MacPattern::Random(2)),
+            4,
+            Ipv4Addr::new(10, 0, 0, 1).into(),
+        );
+        args.seed = Some(3);
+        args.signing_threads = Some(2);
+        args.port = 60005;
+        let parsed = SequencerArgs::parse(args.to_string().split(' ').map(String::from));
+        assert_eq!(parsed, Ok(args));
+        let parsed = SequencerArgs::parse(
+            "--scheme k256 --num-replica 4 --multicast-ip 10.0.0.1 --sign-interval 10"
+                .split(' ')
+                .map(String::from),
+        )
+        .unwrap();
+        assert_eq!(
+            parsed.scheme,
+            SequencerScheme::Ecdsa(SequencerCurve::K256, 10)
+        );
+        assert_eq!(parsed.port, 60004);
+        let args = SequencerArgs::new(
+            SequencerScheme::Ecdsa(SequencerCurve::P256, 4),
+            4,
+            Ipv4Addr::new(10, 0, 0, 1).into(),
+        );
+        let parsed = SequencerArgs::parse(args.to_string().split(' ').map(String::from));
+        assert_eq!(parsed, Ok(args));
+        assert!(SequencerArgs::parse(["--scheme", "k256"].map(String::from)).is_err());
+        assert!(SequencerArgs::parse(
+            "--scheme ed25519 --num-replica 4 --multicast-ip 10.0.0.1"
+                .split(' ')
+                .map(String::from)
+        )
+        .is_err());
+        assert!(SequencerArgs::parse(["--seed"].map(String::from)).is_err());
+        assert!(SequencerArgs::parse(
+            "--scheme k256 --num-replica 4 --multicast-ip 10.0.0.1 --signing-threads 0"
+                .split(' ')
+                .map(String::from)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn recovery_time() {
+        let secs = Duration::from_secs;
+        let timeline = [50, 100, 100, 0, 0, 40, 95, 100];
+        assert_eq!(
+            super::recovery_time(&timeline, secs(3), secs(5)),
+            Some(secs(2))
+        );
+        // recovers within the second of healing
+        let timeline = [50, 100, 100, 0, 0, 95];
+        assert_eq!(
+            super::recovery_time(&timeline, secs(3), secs(5)),
+            Some(secs(1))
+        );
+        assert_eq!(super::recovery_time(&timeline[..5], secs(3), secs(5)), None);
+        assert_eq!(super::recovery_time(&timeline, secs(1), secs(5)), None);
+    }
 }
@@ -1,6 +1,7 @@
 use std::{
     fmt::Write,
     net::SocketAddr,
+    str::FromStr,
     sync::{
         atomic::{AtomicBool, Ordering::SeqCst},
         Arc,
@@ -8,13 +9,27 @@ use std::{
     time::Duration,
 };
 
-use control_messages::{App, BenchmarkClient, BenchmarkStats, Replica, Role, Task};
-use reqwest::Client;
+use control_messages::{
+    recovery_time, App, BenchmarkClient, BenchmarkStats, BuildInfo, ByzantineStrategy, ClientLoad,
+    ClientSkew, Environment, LatencyHistogram, LatencyPercentiles, Liveness, MacPattern,
+    NetworkModel, Replica, ReplicaStats, Role, Scenario, SequencerArgs, SequencerCurve,
+    SequencerScheme, SocketConfig, Strictness, SwitchStrictness, Task,
+};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    Certificate, Client, Method, StatusCode,
+};
 use tokio::{select, spawn, time::sleep};
 use tokio_util::sync::CancellationToken;
 
+// seed of every run unless overridden by `--seed`, from which hosts derive their own
+const SEED: u64 = 3603269_3604874;
+// for neo without confirm, which keeps what undoes the executed ops until a stable checkpoint
+const SPECULATE_CHECKPOINT_INTERVAL: u64 = 1 << 16;
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
+    control_messages::build_info!().exit_on_version_flag();
     let ycsb_app = App::Ycsb(control_messages::YcsbConfig {
         num_key: 10 * 1000,
         num_value: 100 * 1000,
@@ -23,7 +38,20 @@ async fn main() {
         read_portion: 50,
         update_portion: 40,
         rmw_portion: 10,
+        scan_portion: 0,
+        insert_portion: 0,
+        max_scan_len: 0,
+        key_distribution: Default::default(),
+        num_field: 0,
     });
+    if let App::Ycsb(config) = &ycsb_app {
+        config
+            .check()
+            .unwrap_or_else(|err| panic!("YCSB config: {err}"))
+    }
+    if !preflight(std::env::args().nth(1).as_deref() == Some("upgrade")).await {
+        std::process::exit(1)
+    }
     match std::env::args().nth(1).as_deref() {
         Some("fpga") => {
             let saved = std::fs::read_to_string("saved-fpga.csv").unwrap_or_default();
@@ -72,6 +100,15 @@ async fn main() {
                 &mut out,
             )
             .await;
+            run_clients(
+                "pbft-sq",
+                [1].into_iter()
+                    .chain((2..=60).step_by(2))
+                    .chain((60..=100).step_by(10)),
+                &saved_lines,
+                &mut out,
+            )
+            .await;
             run_clients(
                 "zyzzyva",
                 [1].into_iter().chain((2..=20).step_by(2)),
@@ -106,6 +143,24 @@ async fn main() {
                 &mut out,
             )
             .await;
+            run_clients(
+                "sbft",
+                [1].into_iter()
+                    .chain((2..=60).step_by(2))
+                    .chain((60..=100).step_by(10)),
+                &saved_lines,
+                &mut out,
+            )
+            .await;
+            run_clients(
+                "tendermint",
+                [1].into_iter()
+                    .chain((2..=60).step_by(2))
+                    .chain((60..=100).step_by(10)),
+                &saved_lines,
+                &mut out,
+            )
+            .await;
 
             for mode in [
                 "unreplicated",
@@ -115,11 +170,25 @@ async fn main() {
                 // "zyzzyva",
                 // "zyzzyva-f",
                 "hotstuff",
+                "hotstuff-mempool",
                 "minbft",
+                "sbft",
+                "tendermint",
             ] {
-                run_full_throughput(mode, ycsb_app, 0., &saved_lines, &mut out).await
+                run_full_throughput(mode, ycsb_app.clone(), 0., &saved_lines, &mut out).await
             }
-            run(5, 10, 1, "zyzzyva", ycsb_app, 0., 1, &saved_lines, &mut out).await;
+            run(
+                5,
+                10,
+                1,
+                "zyzzyva",
+                ycsb_app.clone(),
+                0.,
+                1,
+                &saved_lines,
+                &mut out,
+            )
+            .await;
             run(
                 5,
                 6,
@@ -163,9 +232,6 @@ async fn main() {
                 run_full_throughput("neo-hm", App::Null, drop_rate, &saved_lines, &mut out).await
             }
         }
-        #[cfg(not(feature = "aws"))]
-        Some("aws") => panic!("require enable aws feature"),
-        #[cfg(feature = "aws")]
         Some("aws") => {
             let saved = std::fs::read_to_string("saved-aws.csv").unwrap_or_default();
             let saved_lines = Vec::from_iter(saved.lines());
@@ -221,6 +287,452 @@ async fn main() {
                 .await
             }
         }
+        // scaling with the fault threshold, where every f gets its own series and the replicas and
+        // relays are templated from the environment, see `Environment::topology`
+        Some("f-sweep") => {
+            let num_client_host = Environment::load().client_hosts.len();
+            for num_faulty in 1..=5 {
+                let path = format!("saved-f-sweep-{num_faulty}.csv");
+                let saved = std::fs::read_to_string(&path).unwrap_or_default();
+                let saved_lines = Vec::from_iter(saved.lines());
+                let mut out = std::fs::File::options()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .unwrap();
+                for mode in [
+                    "neo-hm",
+                    "pbft",
+                    "zyzzyva",
+                    "hotstuff",
+                    "minbft",
+                    "sbft",
+                    "tendermint",
+                ] {
+                    run(
+                        1,
+                        1,
+                        num_client_host,
+                        mode,
+                        App::Null,
+                        0.,
+                        num_faulty,
+                        &saved_lines,
+                        &mut out,
+                    )
+                    .await
+                }
+            }
+        }
+        // security coverage of carrying fewer MACs per message, with the software sequencer
+        Some("mac") => {
+            assert!(
+                Environment::load().sequencer_host.is_some(),
+                "the switch does not follow MAC patterns"
+            );
+            let saved = std::fs::read_to_string("saved-mac.csv").unwrap_or_default();
+            let saved_lines = Vec::from_iter(saved.lines());
+            let mut out = std::fs::File::options()
+                .create(true)
+                .append(true)
+                .open("saved-mac.csv")
+                .unwrap();
+
+            for num_faulty in [2, 5, 10] {
+                for mac_pattern in [
+                    MacPattern::Windows,
+                    MacPattern::Rotating,
+                    MacPattern::Random(1),
+                    MacPattern::Random(2),
+                    MacPattern::Random(4),
+                ] {
+                    report(
+                        try_run(
+                            1,
+                            1,
+                            100,
+                            "neo-hm",
+                            App::Null,
+                            0.,
+                            num_faulty,
+                            Sequencing {
+                                mac_pattern,
+                                ..Default::default()
+                            },
+                            None,
+                            ClientLoad::Closed,
+                            None,
+                            &saved_lines,
+                            &mut out,
+                        )
+                        .await,
+                    )
+                }
+            }
+        }
+        // how much of the latency the sequencing hop contributes in each variant, with the software
+        // sequencer, whose clock must be synchronized with the clients' and replicas'
+        Some("sequencing") => {
+            assert!(
+                Environment::load().sequencer_host.is_some(),
+                "the switch does not timestamp"
+            );
+            let saved = std::fs::read_to_string("saved-sequencing.csv").unwrap_or_default();
+            let saved_lines = Vec::from_iter(saved.lines());
+            let mut out = std::fs::File::options()
+                .create(true)
+                .append(true)
+                .open("saved-sequencing.csv")
+                .unwrap();
+
+            for mode in ["unreplicated-om", "neo-hm", "neo-pk", "pbft-sq"] {
+                report(
+                    try_run(
+                        1,
+                        1,
+                        1,
+                        mode,
+                        App::Null,
+                        0.,
+                        1,
+                        Sequencing {
+                            timing: true,
+                            ..Default::default()
+                        },
+                        None,
+                        ClientLoad::Closed,
+                        None,
+                        &saved_lines,
+                        &mut out,
+                    )
+                    .await,
+                )
+            }
+        }
+        // recovery by replacing a replica with a warm standby, after it is silent for a while
+        Some("standby") => {
+            assert!(
+                !Environment::load().standby_hosts.is_empty(),
+                "no standby host"
+            );
+            let saved = std::fs::read_to_string("saved-standby.csv").unwrap_or_default();
+            let saved_lines = Vec::from_iter(saved.lines());
+            let mut out = std::fs::File::options()
+                .create(true)
+                .append(true)
+                .open("saved-standby.csv")
+                .unwrap();
+
+            for gap in [0, 100, 1000, 5000] {
+                report(
+                    try_run(
+                        5,
+                        20,
+                        1,
+                        "neo-hm",
+                        App::Null,
+                        0.,
+                        1,
+                        Sequencing::default(),
+                        None,
+                        ClientLoad::Closed,
+                        Some(Intervention::Replace(Duration::from_millis(gap))),
+                        &saved_lines,
+                        &mut out,
+                    )
+                    .await,
+                )
+            }
+        }
+        // replica verification load as the software sequencer signs fewer ordered multicast, and
+        // how often bursts end off the interval
+        Some("batch") => {
+            assert!(
+                Environment::load().sequencer_host.is_some(),
+                "the switch signs every message"
+            );
+            let saved = std::fs::read_to_string("saved-batch.csv").unwrap_or_default();
+            let saved_lines = Vec::from_iter(saved.lines());
+            let mut out = std::fs::File::options()
+                .create(true)
+                .append(true)
+                .open("saved-batch.csv")
+                .unwrap();
+
+            for num_client in [1, 20] {
+                for sign_interval in [1, 2, 4, 8, 16, 32, 64] {
+                    report(
+                        try_run(
+                            5,
+                            num_client,
+                            1,
+                            "neo-pk",
+                            App::Null,
+                            0.,
+                            1,
+                            Sequencing {
+                                sign_interval,
+                                ..Default::default()
+                            },
+                            None,
+                            ClientLoad::Closed,
+                            None,
+                            &saved_lines,
+                            &mut out,
+                        )
+                        .await,
+                    )
+                }
+            }
+        }
+        // throughput as the software sequencer merges requests into fewer ordered multicast
+        Some("request-batch") => {
+            assert!(
+                Environment::load().sequencer_host.is_some(),
+                "the switch does not batch"
+            );
+            let saved = std::fs::read_to_string("saved-request-batch.csv").unwrap_or_default();
+            let saved_lines = Vec::from_iter(saved.lines());
+            let mut out = std::fs::File::options()
+                .create(true)
+                .append(true)
+                .open("saved-request-batch.csv")
+                .unwrap();
+
+            for mode in ["neo-hm", "neo-pk"] {
+                for batch_size in [1, 2, 4, 8, 16, 32] {
+                    report(
+                        try_run(
+                            5,
+                            20,
+                            1,
+                            mode,
+                            App::Null,
+                            0.,
+                            1,
+                            Sequencing {
+                                batch_size,
+                                ..Default::default()
+                            },
+                            None,
+                            ClientLoad::Closed,
+                            None,
+                            &saved_lines,
+                            &mut out,
+                        )
+                        .await,
+                    )
+                }
+            }
+        }
+        // rolling upgrade by replacing every replica with a standby, after `reload --standby`
+        // deploys a newer build to the standby hosts
+        Some("upgrade") => {
+            let environment = Environment::load();
+            assert!(
+                environment.standby_hosts.len() >= Liveness::new("neo-hm", 1).num_replica,
+                "not every replica has a standby host"
+            );
+            let saved = std::fs::read_to_string("saved-upgrade.csv").unwrap_or_default();
+            let saved_lines = Vec::from_iter(saved.lines());
+            let mut out = std::fs::File::options()
+                .create(true)
+                .append(true)
+                .open("saved-upgrade.csv")
+                .unwrap();
+
+            for gap in [0, 100] {
+                report(
+                    try_run(
+                        5,
+                        20,
+                        1,
+                        "neo-hm",
+                        App::Null,
+                        0.,
+                        1,
+                        Sequencing::default(),
+                        None,
+                        ClientLoad::Closed,
+                        Some(Intervention::Upgrade(Duration::from_millis(gap))),
+                        &saved_lines,
+                        &mut out,
+                    )
+                    .await,
+                )
+            }
+        }
+        // protocols under a fault scenario, see `Scenario` for the format, e.g.
+        // `control chaos scripts/scenarios/isolate-backup.txt neo-hm pbft`, and the time for the
+        // throughput to recover after a partition heals goes to saved-recovery.csv
+        Some("chaos") => {
+            let path = std::env::args().nth(2).expect("scenario file");
+            let scenario = std::fs::read_to_string(&path)
+                .unwrap_or_else(|err| panic!("reading {path}: {err}"))
+                .parse::<Scenario>()
+                .unwrap_or_else(|err| panic!("parsing {path}: {err}"));
+            let name = std::path::Path::new(&path)
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            let saved = std::fs::read_to_string("saved-chaos.csv").unwrap_or_default();
+            let saved_lines = Vec::from_iter(saved.lines());
+            let mut out = std::fs::File::options()
+                .create(true)
+                .append(true)
+                .open("saved-chaos.csv")
+                .unwrap();
+
+            for mode in std::env::args()
+                .skip(3)
+                .take_while(|arg| !arg.starts_with("--"))
+            {
+                scenario
+                    .check(Liveness::new(&mode, 1).num_replica)
+                    .unwrap_or_else(|err| panic!("{path} for {mode}: {err}"));
+                report(
+                    try_run(
+                        5,
+                        20,
+                        1,
+                        &mode,
+                        App::Null,
+                        0.,
+                        1,
+                        Sequencing::default(),
+                        None,
+                        ClientLoad::Closed,
+                        Some(Intervention::Chaos(name.clone(), scenario.clone())),
+                        &saved_lines,
+                        &mut out,
+                    )
+                    .await,
+                )
+            }
+        }
+        // the same workload under every verification strictness back-to-back in a single run
+        Some("strictness") => {
+            let saved = std::fs::read_to_string("saved-strictness.csv").unwrap_or_default();
+            let saved_lines = Vec::from_iter(saved.lines());
+            let mut out = std::fs::File::options()
+                .create(true)
+                .append(true)
+                .open("saved-strictness.csv")
+                .unwrap();
+
+            for mode in ["neo-hm", "neo-pk"] {
+                report(
+                    try_run(
+                        5,
+                        20,
+                        1,
+                        mode,
+                        App::Null,
+                        0.,
+                        1,
+                        Sequencing::default(),
+                        None,
+                        ClientLoad::Closed,
+                        Some(Intervention::SwitchStrictness),
+                        &saved_lines,
+                        &mut out,
+                    )
+                    .await,
+                )
+            }
+        }
+        // fairness under a few heavy hitters, with the rest of clients issuing `1 / weight` as many
+        // requests each
+        Some("skew") => {
+            let saved = std::fs::read_to_string("saved-skew.csv").unwrap_or_default();
+            let saved_lines = Vec::from_iter(saved.lines());
+            let mut out = std::fs::File::options()
+                .create(true)
+                .append(true)
+                .open("saved-skew.csv")
+                .unwrap();
+
+            for mode in ["neo-hm", "neo-pk", "pbft", "zyzzyva"] {
+                for (heavy_fraction, weight) in [(1., 1.), (0.1, 10.), (0.1, 100.), (0.01, 100.)] {
+                    report(
+                        try_run(
+                            5,
+                            100,
+                            1,
+                            mode,
+                            App::Null,
+                            0.,
+                            1,
+                            Sequencing::default(),
+                            Some(ClientSkew {
+                                heavy_fraction,
+                                weight,
+                            }),
+                            ClientLoad::Closed,
+                            None,
+                            &saved_lines,
+                            &mut out,
+                        )
+                        .await,
+                    )
+                }
+            }
+        }
+        // latency against offered load, with requests arriving regardless of completions, and with
+        // a few clients keeping several requests outstanding each
+        Some("load") => {
+            let saved = std::fs::read_to_string("saved-load.csv").unwrap_or_default();
+            let saved_lines = Vec::from_iter(saved.lines());
+            let mut out = std::fs::File::options()
+                .create(true)
+                .append(true)
+                .open("saved-load.csv")
+                .unwrap();
+
+            for mode in ["neo-hm", "pbft", "hotstuff"] {
+                for rate in [1e4, 2e4, 4e4, 6e4, 8e4, 1e5] {
+                    report(
+                        try_run(
+                            5,
+                            100,
+                            1,
+                            mode,
+                            App::Null,
+                            0.,
+                            1,
+                            Sequencing::default(),
+                            None,
+                            ClientLoad::Open(rate),
+                            None,
+                            &saved_lines,
+                            &mut out,
+                        )
+                        .await,
+                    )
+                }
+                for window in [1, 2, 4, 8, 16] {
+                    report(
+                        try_run(
+                            5,
+                            10,
+                            1,
+                            mode,
+                            App::Null,
+                            0.,
+                            1,
+                            Sequencing::default(),
+                            None,
+                            ClientLoad::Pipelined(window),
+                            None,
+                            &saved_lines,
+                            &mut out,
+                        )
+                        .await,
+                    )
+                }
+            }
+        }
 
         _ => unimplemented!(),
     }
@@ -259,6 +771,35 @@ async fn run_clients(
     }
 }
 
+// why a run fails, after retrying transient errors
+#[derive(Debug)]
+enum Failure {
+    // connection to the worker cannot be established
+    Unreachable(String),
+    Timeout(String),
+    // the worker panics or fails to handle a request, it must be reloaded before next run
+    Panicked(String),
+    // the worker refuses the task because the ports are still bound, along with the owners
+    Busy(String, String),
+    // the worker is built without the task's mode or app, see `NEO_FEATURES` of reload
+    Unsupported(String, String),
+    // the environment lacks a host that the run needs
+    Misconfigured(String),
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unreachable(host) => write!(f, "{host} unreachable"),
+            Self::Timeout(host) => write!(f, "{host} timeout"),
+            Self::Panicked(host) => write!(f, "{host} panicked"),
+            Self::Busy(host, ports) => write!(f, "{host} has busy ports {ports}"),
+            Self::Unsupported(host, reason) => write!(f, "{host} refuses the task: {reason}"),
+            Self::Misconfigured(reason) => write!(f, "environment {reason}"),
+        }
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn run(
     num_group: usize,
@@ -269,128 +810,454 @@ async fn run(
     drop_rate: f64,
     num_faulty: usize,
     saved_lines: &[&str],
-    mut out: impl std::io::Write,
+    out: impl std::io::Write,
 ) {
-    let client_addrs;
-    let replica_addrs;
-    let multicast_addr;
-    let client_hosts;
-    let replica_hosts;
+    let result = try_run(
+        num_group,
+        num_client,
+        num_client_host,
+        mode,
+        app,
+        drop_rate,
+        num_faulty,
+        Sequencing::default(),
+        None,
+        ClientLoad::Closed,
+        None,
+        saved_lines,
+        out,
+    )
+    .await;
+    report(result)
+}
 
-    #[cfg(not(feature = "aws"))]
-    {
-        assert!(num_faulty <= 1);
-        client_addrs = (20000..).map(|port| SocketAddr::from(([10, 0, 0, 10], port)));
-        replica_addrs = vec![
-            SocketAddr::from(([10, 0, 0, 1], 10000)),
-            SocketAddr::from(([10, 0, 0, 2], 10000)),
-            SocketAddr::from(([10, 0, 0, 3], 10000)),
-            SocketAddr::from(([10, 0, 0, 4], 10000)),
-        ];
-        multicast_addr = SocketAddr::from(([10, 0, 0, 255], 60004));
-
-        client_hosts = ["nsl-node10.d2"];
-        assert_eq!(num_client_host, 1);
-        replica_hosts = [
-            "nsl-node1.d2",
-            "nsl-node2.d2",
-            "nsl-node3.d2",
-            "nsl-node4.d2",
-        ];
-    }
-
-    #[cfg(feature = "aws")]
-    let output = neo_aws::Output::new_terraform();
-    #[cfg(feature = "aws")]
-    {
-        use std::net::Ipv4Addr;
-        client_addrs = output
-            .client_ips
-            .into_iter()
-            .map(|ip| ip.parse::<Ipv4Addr>().unwrap())
-            .flat_map(|ip| {
-                (20000..)
-                    .take(num_group * num_client)
-                    .map(move |port| SocketAddr::from((ip, port)))
-            });
-        #[allow(clippy::int_plus_one)]
-        {
-            assert!(
-                output.replica_ips.len() >= 2 * num_faulty + 1,
-                "there are only {} replicas",
-                output.replica_ips.len()
-            )
+fn report(result: Result<(), Failure>) {
+    match result {
+        Ok(()) => {}
+        // completed runs are saved, so rerunning the campaign resumes from here
+        Err(Failure::Panicked(host)) => {
+            panic!("{host} panicked, reload workers and rerun to resume the campaign")
         }
-        replica_addrs = Vec::from_iter(
-            output
-                .replica_ips
-                .into_iter()
-                .map(|ip| SocketAddr::from((ip.parse::<Ipv4Addr>().unwrap(), 10000)))
-                // TODO clarify this and avoid pitfall
-                .chain((30000..).map(|port| SocketAddr::from(([127, 0, 0, 1], port))))
-                .take(3 * num_faulty + 1),
-        );
-        multicast_addr =
-            SocketAddr::from((output.sequencer_ip.parse::<Ipv4Addr>().unwrap(), 60004));
-        client_hosts = output.client_hosts;
-        replica_hosts = output.replica_hosts
+        Err(failure) => println!("! skip run because {failure}"),
     }
+}
+
+// how the software sequencer authenticates ordered multicast
+#[derive(Debug, Clone, Copy)]
+struct Sequencing {
+    // half-sip-hash only
+    mac_pattern: MacPattern,
+    // p256 and k256 only
+    sign_interval: u32,
+    // the software sequencer merges up to this many requests into one ordered multicast, holding a
+    // partial batch for the timeout, neo only
+    batch_size: usize,
+    batch_timeout: Duration,
+    // break down the latency of the sequencing hop, see `Task::sequencing_timing`
+    timing: bool,
+}
+
+impl Default for Sequencing {
+    fn default() -> Self {
+        Self {
+            mac_pattern: MacPattern::Windows,
+            sign_interval: 1,
+            batch_size: 1,
+            batch_timeout: Duration::from_micros(100),
+            timing: false,
+        }
+    }
+}
+
+// an action taken in the middle of the benchmark
+#[derive(Debug, Clone)]
+enum Intervention {
+    // silence replica 0, and promote its standby after this long
+    Replace(Duration),
+    // replace every replica that has a standby one by one as above, with the standbys deployed the
+    // new build, i.e. a rolling upgrade
+    Upgrade(Duration),
+    // step down verification strictness from full to MAC only to none
+    SwitchStrictness,
+    // the named scenario, injected to every replica
+    Chaos(String, Scenario),
+}
+
+// the value after `flag` among the arguments, which are given after the others, or none if the flag
+// is absent, panicking with the `usage` of the value if it is missing or does not parse
+fn parse_flag<T: FromStr>(flag: &str, usage: &str) -> Option<T> {
+    let args = Vec::from_iter(std::env::args());
+    let index = args.iter().position(|arg| arg == flag)?;
+    let value = args.get(index + 1).and_then(|value| value.parse().ok());
+    Some(value.unwrap_or_else(|| panic!("{flag} {usage}")))
+}
+
+// `--persist <dir>`, for replicas to keep write-ahead logs in `dir` on their hosts and recover from
+// them, e.g. for crash recovery experiments
+fn persist_dir() -> Option<String> {
+    parse_flag("--persist", "<dir>")
+}
+
+// `--trace <dir>`, for replicas to record the events of their dispatches into `dir` on their hosts,
+// to be replayed offline
+fn trace_dir() -> Option<String> {
+    parse_flag("--trace", "<dir>")
+}
+
+// `--network-model <file>`, for replicas to emulate the links in the TOML file, see `NetworkModel`
+fn network_model() -> NetworkModel {
+    parse_flag::<String>("--network-model", "<file>")
+        .map(|path| NetworkModel::load(&path))
+        .unwrap_or_default()
+}
+
+// `--byzantine <strategy>`, for the last f replicas to misbehave with one of silent, equivocate,
+// stale-vote and corrupt-digest, pbft only
+fn byzantine_replicas(num_faulty: usize, num_replica: usize) -> Vec<(u8, ByzantineStrategy)> {
+    const USAGE: &str = "silent|equivocate|stale-vote|corrupt-digest";
+    let Some(strategy) = parse_flag::<String>("--byzantine", USAGE) else {
+        return Default::default();
+    };
+    let strategy = match &*strategy {
+        "silent" => ByzantineStrategy::Silent,
+        "equivocate" => ByzantineStrategy::Equivocate,
+        "stale-vote" => ByzantineStrategy::StaleVote,
+        "corrupt-digest" => ByzantineStrategy::CorruptDigest,
+        _ => panic!("--byzantine {USAGE}"),
+    };
+    Vec::from_iter((num_replica - num_faulty..num_replica).map(|index| (index as u8, strategy)))
+}
+
+// `--multicast-groups <n>`, for clients to be spread across n multicast groups, each ordered by a
+// software sequencer of its own, neo only
+fn num_multicast_group() -> usize {
+    parse_flag("--multicast-groups", "<n>").unwrap_or(1)
+}
+
+// `--request-workers <n>`, for replicas to verify client requests on n threads
+fn request_workers() -> usize {
+    parse_flag("--request-workers", "<n>").unwrap_or(0)
+}
+
+// `--client-secret <secret>`, for clients to authenticate with keys of their own derived from the
+// secret, see `Task::client_secret`
+fn client_secret() -> Option<String> {
+    parse_flag("--client-secret", "<secret>")
+}
+
+// `--socket-batch <n>`, for every host to receive and send up to n datagrams per syscall, at most
+// `SocketConfig::MAX_BATCH`
+fn socket_batch() -> Option<usize> {
+    let batch = parse_flag("--socket-batch", "<n>")?;
+    assert!(
+        (1..=SocketConfig::MAX_BATCH).contains(&batch),
+        "--socket-batch <n> in 1..={}",
+        SocketConfig::MAX_BATCH
+    );
+    Some(batch)
+}
+
+// `--history`, for clients to record their invocations, which are saved into `history/<id>/` for
+// `scripts/check`
+fn record_history() -> bool {
+    std::env::args().any(|arg| arg == "--history")
+}
+
+// `--max-inflight <k>`, for neo replicas to ask clients to back off, and to hold back the ordered
+// multicast, once k ops are ordered but not executed yet
+fn max_inflight() -> u64 {
+    parse_flag("--max-inflight", "<k>").unwrap_or(0)
+}
+
+// `--seed <n>`, for the run to derive randomness from n in place of `SEED`
+fn seed() -> u64 {
+    parse_flag("--seed", "<n>").unwrap_or(SEED)
+}
+
+// `--self-check <rate>`, for replicas to compare the fraction of ops against a shadow app, see
+// `Task::self_check_rate`
+fn self_check_rate() -> f64 {
+    parse_flag("--self-check", "<rate>").unwrap_or(0.)
+}
+
+// `--sequencer-curve <p256|k256>`, for the software sequencer to sign on instead of P-256, e.g.
+// secp256k1 to compare against the switch, see `Task::sequencer_curve`
+fn sequencer_curve() -> SequencerCurve {
+    parse_flag("--sequencer-curve", "<p256|k256>").unwrap_or_default()
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn try_run(
+    num_group: usize,
+    num_client: usize,
+    num_client_host: usize,
+    mode: &str,
+    app: App,
+    drop_rate: f64,
+    num_faulty: usize,
+    sequencing: Sequencing,
+    skew: Option<ClientSkew>,
+    load: ClientLoad,
+    intervention: Option<Intervention>,
+    saved_lines: &[&str],
+    mut out: impl std::io::Write,
+) -> Result<(), Failure> {
+    let environment = Environment::load();
+    // an address per session, see `ClientLoad`
+    let num_session = num_group * num_client * load.num_session();
+    let client_addrs = environment.client_ips.iter().flat_map(|&ip| {
+        (20000..)
+            .take(num_session)
+            .map(move |port| SocketAddr::from((ip, port)))
+    });
+    let liveness = Liveness::new(mode, num_faulty);
+    let topology = environment.topology(&liveness);
+    let replica_addrs = topology.replica_addrs;
+    let num_multicast_group = num_multicast_group();
+    assert!(
+        num_multicast_group == 1 || environment.sequencer_host.is_some(),
+        "the switch sequences a single group"
+    );
+    let multicast_addrs = Vec::from_iter(
+        (0..num_multicast_group as u16)
+            .map(|group| SocketAddr::from((environment.multicast_ip, 60004 + group))),
+    );
+    // packets are either relayed, or forwarded by the switch with the clients' addresses kept
+    let multicast_sources = if environment.relay_ips.is_empty() {
+        environment.client_ips.clone()
+    } else {
+        environment.relay_ips.clone()
+    };
+    // the standby must receive the ordered multicast as well, i.e. be in the switch's multicast
+    // group or one of the relays' destinations
+    let standby_addrs = Vec::from_iter(
+        environment
+            .standby_ips
+            .iter()
+            .take(match intervention {
+                Some(Intervention::Replace(_)) => 1,
+                Some(Intervention::Upgrade(_)) => liveness.num_replica,
+                _ => 0,
+            })
+            .enumerate()
+            .map(|(index, &ip)| (index as u8, SocketAddr::from((ip, 10000)))),
+    );
+    let scheme = environment.scheme();
+    let tls = environment.tls;
+    // distributed by `reload` into the work directory of every host
+    let key_dir = Some(format!("{}/keys", environment.work_dir)).filter(|_| environment.keys);
+    assert!(
+        key_dir.is_none() || standby_addrs.is_empty(),
+        "the keys of standbys are derived rather than distributed"
+    );
+    let client_hosts = environment.client_hosts;
+    let replica_hosts = topology.replica_hosts;
 
     assert!(client_hosts.len() >= num_client_host);
-    let client_addrs = Vec::from_iter(client_addrs.take(num_group * num_client * num_client_host));
-    let id = format!(
+    let client_addrs = Vec::from_iter(client_addrs.take(num_session * num_client_host));
+    let mut id = format!(
         "{mode},{},{drop_rate},{},{num_faulty}",
         match app {
             App::Null => "null",
             App::Ycsb(_) => "ycsb",
+            App::KvStore(_) => "kvstore",
+            App::Wasm(_) => "wasm",
         },
         client_addrs.len(),
     );
+    let Sequencing {
+        mac_pattern,
+        sign_interval,
+        batch_size,
+        batch_timeout,
+        timing,
+    } = sequencing;
+    if mac_pattern != MacPattern::Windows {
+        id += &format!(",{mac_pattern}")
+    }
+    if sign_interval != 1 {
+        id += &format!(",sign-{sign_interval}")
+    }
+    if batch_size != 1 {
+        assert!(mode.starts_with("neo"), "only neo accepts batched requests");
+        id += &format!(",batch-{batch_size}-{}us", batch_timeout.as_micros())
+    }
+    if timing {
+        id += ",timing"
+    }
+    let max_inflight = max_inflight();
+    if max_inflight != 0 {
+        assert!(
+            mode.starts_with("neo"),
+            "only neo backs off on inflight ops"
+        );
+        id += &format!(",inflight-{max_inflight}")
+    }
+    if num_multicast_group != 1 {
+        assert!(mode.starts_with("neo"), "only neo merges multicast groups");
+        id += &format!(",groups-{num_multicast_group}")
+    }
+    let request_workers = request_workers();
+    if request_workers != 0 {
+        id += &format!(",workers-{request_workers}")
+    }
+    let client_secret = client_secret();
+    if client_secret.is_some() {
+        // the secret itself stays out of the records
+        id += ",client-keys"
+    }
+    let socket_batch = socket_batch();
+    let history = record_history();
+    if let Some(socket_batch) = socket_batch {
+        id += &format!(",io-batch-{socket_batch}")
+    }
+    let seed = seed();
+    if seed != SEED {
+        id += &format!(",seed-{seed}")
+    }
+    // debug runs, which are not comparable to the others
+    let self_check_rate = self_check_rate();
+    if self_check_rate != 0. {
+        id += &format!(",self-check-{self_check_rate}")
+    }
+    let sequencer_curve = sequencer_curve();
+    if sequencer_curve != SequencerCurve::default() && mode == "neo-pk" {
+        id += &format!(",{sequencer_curve}")
+    }
+    if let Some(ClientSkew {
+        heavy_fraction,
+        weight,
+    }) = skew
+    {
+        id += &format!(",skew-{heavy_fraction}-{weight}")
+    }
+    if load != ClientLoad::Closed {
+        id += &format!(",{load}")
+    }
+    match intervention {
+        None => {}
+        Some(Intervention::Replace(gap)) => id += &format!(",replace-{}ms", gap.as_millis()),
+        Some(Intervention::Upgrade(gap)) => id += &format!(",upgrade-{}ms", gap.as_millis()),
+        Some(Intervention::SwitchStrictness) => id += ",strictness",
+        Some(Intervention::Chaos(ref name, _)) => id += &format!(",chaos-{name}"),
+    }
     println!("* work on {id}");
     if saved_lines.iter().any(|line| line.starts_with(&id)) {
         println!("* skip because exist record found");
-        return;
+        return Ok(());
     }
 
-    #[cfg(feature = "aws")]
+    for (host, destinations) in environment
+        .relay_hosts
+        .iter()
+        .zip(&topology.relay_destinations)
     {
         std::process::Command::new("ssh")
-            .args([
-                &output.sequencer_host,
-                "pkill",
-                "-KILL",
-                "--full",
-                "neo-sequencer",
-            ])
+            .args([host, "pkill", "-KILL", "--full", "relay"])
             .status()
             .unwrap();
 
         let status = std::process::Command::new("ssh")
-                .arg(output.sequencer_host)
+            .arg(host)
+            .arg(format!(
+                "./relay {} 1>./relay-stdout.txt 2>./relay-stderr.txt &",
+                Vec::from_iter(
+                    multicast_addrs
+                        .iter()
+                        .map(|addr| format!("--port {}", addr.port()))
+                        .chain(destinations.iter().map(ToString::to_string))
+                )
+                .join(" ")
+            ))
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    if let Some(sequencer_host) = environment.sequencer_host {
+        std::process::Command::new("ssh")
+            .args([&sequencer_host, "pkill", "-KILL", "--full", "neo-sequencer"])
+            .status()
+            .unwrap();
+
+        let scheme = match mode {
+            "neo-hm" | "unreplicated-om" | "pbft-sq" => SequencerScheme::HalfSipHash(mac_pattern),
+            "neo-pk" => SequencerScheme::Ecdsa(sequencer_curve, sign_interval),
+            _ => unimplemented!(),
+        };
+        // the sequencer sends through the first relay
+        let Some(&relay_ip) = environment.relay_ips.first() else {
+            return Err(Failure::Misconfigured(String::from(
+                "has a sequencer host but no relay",
+            )));
+        };
+        // a sequencer per group on the group's port
+        for (group, addr) in multicast_addrs.iter().enumerate() {
+            let mut args = SequencerArgs::new(scheme, liveness.num_replica, relay_ip);
+            args.port = addr.port();
+            args.seed = Some(seed);
+            args.batch_size = batch_size;
+            args.batch_timeout = batch_timeout;
+            let status = std::process::Command::new("ssh")
+                .arg(&sequencer_host)
                 .arg(format!(
-                    "./neo-sequencer {} {} {} 1>./neo-sequencer-stdout.txt 2>./neo-sequencer-stderr.txt &",
-                    match mode {
-                        "neo-hm" => "half-sip-hash",
-                        "neo-pk" => "k256",
-                        _ => unimplemented!(),
-                    },
-                    num_faulty * 3 + 1,
-                    output.relay_ips[0],
+                    "./neo-sequencer {args} 1>./neo-sequencer-stdout-{group}.txt 2>./neo-sequencer-stderr-{group}.txt &",
                 ))
                 .status()
                 .unwrap();
-        assert!(status.success());
+            assert!(status.success());
+        }
     }
 
     let task = |role| Task {
         mode: String::from(mode),
-        app,
+        app: app.clone(),
         client_addrs: client_addrs.clone(),
         replica_addrs: replica_addrs.clone(),
-        multicast_addr,
+        multicast_addrs: multicast_addrs.clone(),
+        multicast_sources: Some(multicast_sources.clone()),
         num_faulty,
         drop_rate,
-        seed: 3603269_3604874,
+        socket: SocketConfig {
+            timestamping: environment.timestamping,
+            batch: socket_batch,
+            ..Default::default()
+        },
+        // replicas accept the messages without a MAC for them unless every one carries it
+        min_mac_coverage: if mac_pattern == MacPattern::Windows {
+            1.
+        } else {
+            0.
+        },
+        mac_pattern,
+        sign_interval,
+        sequencer_curve,
+        warm_up_entry_num: 1 << 20,
+        client_table_capacity: 1 << 16,
+        client_destination: None,
+        standby_addrs: standby_addrs.clone(),
+        signature_policy: Default::default(),
+        public_scheme: None,
+        self_check_rate,
+        reject_stale: false,
+        request_workers,
+        client_secret: client_secret.clone(),
+        key_dir: key_dir.clone(),
+        key_epoch: 0,
+        previous_key_dir: None,
+        key_grace: Duration::ZERO,
+        checkpoint_interval: if matches!(mode, "neo-hm" | "neo-pk") {
+            SPECULATE_CHECKPOINT_INTERVAL
+        } else {
+            0
+        },
+        persist: persist_dir(),
+        trace: trace_dir(),
+        byzantine: byzantine_replicas(num_faulty, replica_addrs.len()),
+        network_model: network_model(),
+        max_inflight,
+        sequencing_timing: timing,
+        seed,
         role,
     };
 
@@ -404,30 +1271,52 @@ async fn run(
         })
     });
 
-    let http_client = Arc::new(Client::new());
+    let http_client = Arc::new(http_client(tls));
     let panic = Arc::new(AtomicBool::new(false));
     println!("* start replicas");
-    let mut sessions = Vec::from_iter(
-        replica_hosts
-            .into_iter()
-            .enumerate()
-            .take(match mode {
-                "unreplicated" => 1,
-                "minbft" => num_faulty + 1,
-                "zyzzyva" => 3 * num_faulty + 1,
-                _ => 2 * num_faulty + 1,
-            })
-            .map(|(index, host)| {
-                spawn(host_session(
-                    host,
-                    task(Role::Replica(Replica { index: index as _ })),
-                    http_client.clone(),
-                    cancel.clone(),
-                    panic.clone(),
-                ))
-            }),
-    );
+    let mut replicas_ready = Vec::new();
+    let replica_hosts = Vec::from_iter(replica_hosts.into_iter().enumerate());
+    let mut sessions = Vec::from_iter(replica_hosts.iter().cloned().map(|(index, host)| {
+        let ready = CancellationToken::new();
+        replicas_ready.push(ready.clone());
+        spawn(host_session(
+            host,
+            scheme,
+            task(Role::Replica(Replica {
+                index: index as _,
+                standby: false,
+            })),
+            id.clone(),
+            http_client.clone(),
+            cancel.clone(),
+            panic.clone(),
+            ready,
+        ))
+    }));
+    for &(index, _) in &standby_addrs {
+        let ready = CancellationToken::new();
+        replicas_ready.push(ready.clone());
+        sessions.push(spawn(host_session(
+            environment.standby_hosts[index as usize].clone(),
+            scheme,
+            task(Role::Replica(Replica {
+                index,
+                standby: true,
+            })),
+            id.clone(),
+            http_client.clone(),
+            cancel.clone(),
+            panic.clone(),
+            ready,
+        )))
+    }
 
+    for ready in replicas_ready {
+        select! {
+            _ = ready.cancelled() => {}
+            _ = cancel.cancelled() => break,
+        }
+    }
     sleep(Duration::from_secs(1)).await;
     println!("* start clients");
     let mut benchmark = BenchmarkClient {
@@ -435,45 +1324,189 @@ async fn run(
         num_client,
         offset: 0,
         duration: Duration::from_secs(10),
+        skew,
+        load,
+        history,
     };
     let mut delay = Duration::from_millis(100);
     for client_host in client_hosts.iter().take(num_client_host) {
         sessions.push(spawn(host_session(
             client_host.to_string(),
+            scheme,
             task(Role::BenchmarkClient(benchmark)),
+            id.clone(),
             http_client.clone(),
             cancel.clone(),
             panic.clone(),
+            CancellationToken::new(),
         )));
-        benchmark.offset += num_group * num_client;
+        benchmark.offset += num_session;
         sleep(delay).await;
         delay = Duration::ZERO;
     }
+    let fault_window = match &intervention {
+        Some(Intervention::Chaos(_, scenario)) => scenario.fault_window(),
+        _ => None,
+    };
+    match intervention {
+        None => {}
+        Some(Intervention::Replace(gap)) => sessions.push(spawn(replace_replica(
+            replica_hosts[0].1.clone(),
+            environment.standby_hosts[0].clone(),
+            gap,
+            scheme,
+            http_client.clone(),
+            cancel.clone(),
+        ))),
+        Some(Intervention::Upgrade(gap)) => sessions.push(spawn(rolling_upgrade(
+            Vec::from_iter(standby_addrs.iter().map(|&(index, _)| {
+                let (_, replica_host) = replica_hosts
+                    .iter()
+                    .find(|(replica_index, _)| *replica_index == index as usize)
+                    .expect("standby of a participating replica");
+                (
+                    replica_host.clone(),
+                    environment.standby_hosts[index as usize].clone(),
+                )
+            })),
+            gap,
+            scheme,
+            http_client.clone(),
+            cancel.clone(),
+        ))),
+        Some(Intervention::SwitchStrictness) => sessions.push(spawn(step_down_strictness(
+            Vec::from_iter(replica_hosts.into_iter().map(|(_, host)| host)),
+            scheme,
+            http_client.clone(),
+            cancel.clone(),
+        ))),
+        Some(Intervention::Chaos(_, scenario)) => sessions.push(spawn(inject_chaos(
+            Vec::from_iter(replica_hosts.into_iter().map(|(_, host)| host)),
+            scenario,
+            scheme,
+            http_client.clone(),
+            cancel.clone(),
+        ))),
+    }
 
     let mut throughput = 0.;
+    let mut latency_histogram = LatencyHistogram::default();
     let mut result = String::new();
+    let mut failures = Vec::new();
     for (index, client_host) in client_hosts.into_iter().enumerate().take(num_client_host) {
         if index == 0 {
             sleep(Duration::from_secs(1)).await
         }
         loop {
-            let response = http_client
-                .get(format!("http://{client_host}:9999/benchmark"))
-                .send()
-                .await
-                .unwrap();
-            assert!(response.status().is_success());
+            let response = match send_with_retry(&client_host, || {
+                http_client.get(format!("{scheme}://{client_host}:9999/benchmark"))
+            })
+            .await
+            {
+                Ok(response) => response,
+                Err(failure) => {
+                    cancel.cancel();
+                    failures.push(failure);
+                    break;
+                }
+            };
             if let Some(stats) = response.json::<Option<BenchmarkStats>>().await.unwrap() {
-                println!("* {stats:?}");
+                println!(
+                    "* throughput {} average latency {:?} socket drops {} latency fairness {:?}",
+                    stats.throughput,
+                    stats.average_latency,
+                    stats.socket_drops,
+                    stats.latency_fairness
+                );
+                for (group_index, group) in stats.groups.iter().enumerate() {
+                    println!(
+                        "* group {group_index} {} ops average {:?} p50 {:?} p99 {:?}",
+                        group.num_op, group.average, group.p50, group.p99
+                    )
+                }
+                if let Some(group) = stats.heavy_hitters {
+                    println!(
+                        "* heavy hitters {} ops average {:?} p50 {:?} p99 {:?}",
+                        group.num_op, group.average, group.p50, group.p99
+                    )
+                }
+                if let Some(first_reply) = stats.first_reply {
+                    println!(
+                        "* first reply {} ops average {:?} p50 {:?} p99 {:?}",
+                        first_reply.num_op, first_reply.average, first_reply.p50, first_reply.p99
+                    )
+                }
+                let percentiles = stats.latency_histogram.percentiles();
+                if let Some(percentiles) = &percentiles {
+                    print_percentiles("*", percentiles)
+                }
+                println!("* throughput timeline {:?}", stats.throughput_timeline);
+                // second since the run starts and ops completed in it
+                let dir = format!("throughput-timeline/{id}");
+                std::fs::create_dir_all(&dir).unwrap();
+                let mut content = String::new();
+                for (second, ops) in stats.throughput_timeline.iter().enumerate() {
+                    writeln!(&mut content, "{second},{ops}").unwrap()
+                }
+                std::fs::write(format!("{dir}/{index}.csv"), content).unwrap();
+                if history {
+                    let history = http_client
+                        .get(format!("{scheme}://{client_host}:9999/history"))
+                        .send()
+                        .await
+                        .unwrap()
+                        .error_for_status()
+                        .unwrap()
+                        .bytes()
+                        .await
+                        .unwrap();
+                    let dir = format!("history/{id}");
+                    std::fs::create_dir_all(&dir).unwrap();
+                    std::fs::write(format!("{dir}/{index}.bin"), history).unwrap()
+                }
+                if let Some((faulted, healed)) = fault_window {
+                    let recovery = recovery_time(&stats.throughput_timeline, faulted, healed);
+                    println!("* recovery {recovery:?} after heal");
+                    // blank if the throughput does not recover in the run
+                    let mut recovery_out = std::fs::File::options()
+                        .create(true)
+                        .append(true)
+                        .open("saved-recovery.csv")
+                        .unwrap();
+                    let recovery = recovery
+                        .map(|recovery| recovery.as_secs_f64().to_string())
+                        .unwrap_or_default();
+                    std::io::Write::write_all(
+                        &mut recovery_out,
+                        format!("{id},{index},{recovery}\n").as_bytes(),
+                    )
+                    .unwrap()
+                }
                 assert_ne!(stats.throughput, 0.);
+                let micros = |latency: Duration| latency.as_nanos() as f64 / 1000.;
+                // percentile columns are blank if the stats carry no histogram
+                let percentiles = percentiles
+                    .map(|percentiles| {
+                        [
+                            percentiles.p50,
+                            percentiles.p90,
+                            percentiles.p99,
+                            percentiles.p999,
+                            percentiles.max,
+                        ]
+                        .map(|latency| micros(latency).to_string())
+                    })
+                    .unwrap_or_default()
+                    .join(",");
                 writeln!(
                     &mut result,
-                    "{id},{index},{},{}",
+                    "{id},{index},{},{},{percentiles}",
                     stats.throughput,
-                    stats.average_latency.unwrap().as_nanos() as f64 / 1000.,
+                    micros(stats.average_latency.unwrap()),
                 )
                 .unwrap();
                 throughput += stats.throughput;
+                latency_histogram.merge(&stats.latency_histogram);
                 break;
             }
             select! {
@@ -485,56 +1518,455 @@ async fn run(
 
     cancel.cancel();
     for session in sessions {
-        session.await.unwrap()
+        if let Err(failure) = session.await.unwrap() {
+            failures.push(failure)
+        }
+    }
+    // a panicked worker is the cause of other failures, e.g. the rest of workers time out waiting
+    // for it
+    if let Some(index) = failures
+        .iter()
+        .position(|failure| matches!(failure, Failure::Panicked(_)))
+    {
+        return Err(failures.swap_remove(index));
+    }
+    if let Some(failure) = failures.pop() {
+        if !result.is_empty() {
+            print!("! partial result\n{result}")
+        }
+        return Err(failure);
     }
     assert!(!panic.load(SeqCst));
     if num_client_host > 1 {
         println!("{throughput}");
-        out.write_all(result.as_bytes()).unwrap()
+        if let Some(percentiles) = latency_histogram.percentiles() {
+            print_percentiles("* overall", &percentiles)
+        }
+    }
+    out.write_all(result.as_bytes()).unwrap();
+    Ok(())
+}
+
+fn print_percentiles(prefix: &str, percentiles: &LatencyPercentiles) {
+    println!(
+        "{prefix} p50 {:?} p90 {:?} p99 {:?} p999 {:?} max {:?}",
+        percentiles.p50, percentiles.p90, percentiles.p99, percentiles.p999, percentiles.max
+    )
+}
+
+// silence the replica in the middle of the benchmark, i.e. after warm up and several seconds of
+// measurement, then promote the standby after `gap`
+async fn replace_replica(
+    replica_host: String,
+    standby_host: String,
+    gap: Duration,
+    scheme: &str,
+    client: Arc<Client>,
+    cancel: CancellationToken,
+) -> Result<(), Failure> {
+    select! {
+        _ = sleep(Duration::from_secs(5)) => {}
+        _ = cancel.cancelled() => return Ok(()),
+    }
+    println!("* silence replica 0 on {replica_host}");
+    assert!(!set_standby(&replica_host, true, scheme, &client).await?);
+    select! {
+        _ = sleep(gap) => {}
+        _ = cancel.cancelled() => return Ok(()),
+    }
+    println!("* promote standby on {standby_host}");
+    assert!(set_standby(&standby_host, false, scheme, &client).await?);
+    Ok(())
+}
+
+// replace the replicas with their standbys in turn, leaving a few seconds for the promoted one to
+// settle before the next, so at most one replica is unavailable at any time
+// the availability is the client throughput and latency of the run, along with the printed timeline
+async fn rolling_upgrade(
+    hosts: Vec<(String, String)>,
+    gap: Duration,
+    scheme: &str,
+    client: Arc<Client>,
+    cancel: CancellationToken,
+) -> Result<(), Failure> {
+    let start = tokio::time::Instant::now();
+    for (index, (replica_host, standby_host)) in hosts.into_iter().enumerate() {
+        select! {
+            _ = sleep(Duration::from_secs(if index == 0 { 3 } else { 2 })) => {}
+            _ = cancel.cancelled() => return Ok(()),
+        }
+        let (from, to) = (
+            build_info(&replica_host, scheme, &client).await?,
+            build_info(&standby_host, scheme, &client).await?,
+        );
+        if from.same_source(&to) {
+            println!("! {standby_host} runs the same build as {replica_host}")
+        }
+        println!(
+            "* {:?} upgrade replica {index} on {replica_host} from {} to {} on {standby_host}",
+            start.elapsed(),
+            from.git_hash,
+            to.git_hash
+        );
+        assert!(!set_standby(&replica_host, true, scheme, &client).await?);
+        select! {
+            _ = sleep(gap) => {}
+            _ = cancel.cancelled() => return Ok(()),
+        }
+        assert!(set_standby(&standby_host, false, scheme, &client).await?);
+        println!("* {:?} replica {index} upgraded", start.elapsed())
+    }
+    Ok(())
+}
+
+async fn build_info(host: &str, scheme: &str, client: &Client) -> Result<BuildInfo, Failure> {
+    send_with_retry(host, || {
+        client.get(format!("{scheme}://{host}:9999/build-info"))
+    })
+    .await?
+    .json()
+    .await
+    .map_err(|_| Failure::Panicked(host.into()))
+}
+
+// switch the replicas to MAC only and then no verification, each after a few seconds of
+// benchmark, at an op boundary slightly ahead of every replica
+async fn step_down_strictness(
+    replica_hosts: Vec<String>,
+    scheme: &str,
+    client: Arc<Client>,
+    cancel: CancellationToken,
+) -> Result<(), Failure> {
+    // well beyond the ops committed in a round of requests to every replica
+    const MARGIN: u64 = 100_000;
+    for (delay, strictness) in [(4, Strictness::MacOnly), (3, Strictness::Nop)] {
+        select! {
+            _ = sleep(Duration::from_secs(delay)) => {}
+            _ = cancel.cancelled() => return Ok(()),
+        }
+        let mut committed_num = 0;
+        for host in &replica_hosts {
+            let response = send_with_retry(host, || {
+                client.get(format!("{scheme}://{host}:9999/strictness"))
+            })
+            .await?;
+            let num = response
+                .json::<Option<u64>>()
+                .await
+                .map_err(|_| Failure::Panicked(host.clone()))?
+                .expect("verification enabled");
+            committed_num = committed_num.max(num)
+        }
+        let switch = SwitchStrictness {
+            strictness,
+            op_num: Some(committed_num + MARGIN),
+        };
+        println!("* switch {switch:?}");
+        for host in &replica_hosts {
+            send_with_retry(host, || {
+                client
+                    .post(format!("{scheme}://{host}:9999/strictness"))
+                    .json(&switch)
+            })
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+// the actions are timed from the start of clients, and the same scenario runs in simulation with
+// `Network::schedule_chaos`
+async fn inject_chaos(
+    replica_hosts: Vec<String>,
+    scenario: Scenario,
+    scheme: &str,
+    client: Arc<Client>,
+    cancel: CancellationToken,
+) -> Result<(), Failure> {
+    let start = tokio::time::Instant::now();
+    for (at, action) in scenario.0 {
+        select! {
+            _ = tokio::time::sleep_until(start + at) => {}
+            _ = cancel.cancelled() => return Ok(()),
+        }
+        println!("* {at:?} chaos {action:?}");
+        for host in &replica_hosts {
+            send_with_retry(host, || {
+                client
+                    .post(format!("{scheme}://{host}:9999/chaos"))
+                    .json(&action)
+            })
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+// returns whether the replica was in standby
+async fn set_standby(
+    host: &str,
+    standby: bool,
+    scheme: &str,
+    client: &Client,
+) -> Result<bool, Failure> {
+    let endpoint = format!("{scheme}://{host}:9999/standby");
+    send_with_retry(host, || client.post(&endpoint).json(&standby))
+        .await?
+        .json()
+        .await
+        .map_err(|_| Failure::Panicked(host.into()))
+}
+
+// retry with exponential backoff on errors that may be transient
+async fn send_with_retry(
+    host: &str,
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> Result<reqwest::Response, Failure> {
+    const MAX_RETRY: usize = 5;
+    let mut backoff = Duration::from_millis(100);
+    for num_retry in 0.. {
+        let (client, request) = request().build_split();
+        let request = request.unwrap();
+        // anything but a GET may have taken effect before failing, e.g. a task is started twice,
+        // so it is only retried when it never reached the host
+        let idempotent = request.method() == Method::GET;
+        let failure = match client.execute(request).await {
+            Ok(response) if response.status().is_success() => return Ok(response),
+            Ok(response) if response.status() == StatusCode::CONFLICT => {
+                return Err(Failure::Busy(
+                    host.into(),
+                    response.text().await.unwrap_or_default(),
+                ))
+            }
+            Ok(response) if response.status() == StatusCode::NOT_IMPLEMENTED => {
+                return Err(Failure::Unsupported(
+                    host.into(),
+                    response.text().await.unwrap_or_default(),
+                ))
+            }
+            Err(err) if err.is_connect() => Failure::Unreachable(host.into()),
+            Err(err) if err.is_timeout() && idempotent => Failure::Timeout(host.into()),
+            Err(err) if err.is_timeout() => return Err(Failure::Timeout(host.into())),
+            // error status, or connection closed when handling which most likely because the
+            // handler panics
+            Ok(_) | Err(_) => return Err(Failure::Panicked(host.into())),
+        };
+        if num_retry == MAX_RETRY {
+            return Err(failure);
+        }
+        println!("! {failure}, retry in {backoff:?}");
+        sleep(backoff).await;
+        backoff *= 2
+    }
+    unreachable!()
+}
+
+fn http_client(tls: bool) -> Client {
+    let mut http_client = Client::builder().timeout(Duration::from_secs(60));
+    if tls {
+        let name = Environment::name();
+        let read = |file| {
+            let path = Environment::tls_path(&name, file);
+            std::fs::read(&path)
+                .unwrap_or_else(|err| panic!("reading {path}: {err} (run reload first)"))
+        };
+        let token = String::from_utf8(read("token")).unwrap();
+        http_client = http_client
+            .use_rustls_tls()
+            .add_root_certificate(Certificate::from_pem(&read("ca.pem")).unwrap())
+            .default_headers(HeaderMap::from_iter([(
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {}", token.trim())).unwrap(),
+            )]))
     }
+    http_client.build().unwrap()
 }
 
+// every host must run a binary built from the same source as this one, or a campaign may
+// silently mix protocol versions
+// a rolling upgrade is the exception, where the replicas run the build that their standbys replace
+// every host is checked and reported before giving up, so one round of reload fixes all of them
+async fn preflight(upgrade: bool) -> bool {
+    let environment = Environment::load();
+    let expected = control_messages::build_info!();
+    let http_client = http_client(environment.tls);
+    let mut mismatched = false;
+    for host in [
+        &*environment.client_hosts,
+        &*environment.replica_hosts,
+        &*environment.standby_hosts,
+    ]
+    .concat()
+    {
+        let url = format!("{}://{host}:9999/build-info", environment.scheme());
+        let build_info = match http_client.get(url).send().await {
+            Ok(response) => response.json::<BuildInfo>().await,
+            Err(err) => Err(err),
+        };
+        let build_info = match build_info {
+            Ok(build_info) => build_info,
+            Err(err) => {
+                println!("! {host} is unreachable: {err}");
+                mismatched = true;
+                continue;
+            }
+        };
+        if !build_info.same_source(&expected)
+            && upgrade
+            && environment.replica_hosts.contains(&host)
+        {
+            println!("* {host} runs {build_info}, to be upgraded")
+        } else if !build_info.same_source(&expected) {
+            println!("! {host} runs {build_info}");
+            mismatched = true
+        } else if (&build_info.modes, &build_info.apps) != (&expected.modes, &expected.apps) {
+            // a slim build, tasks of the other modes and apps fail as `Failure::Unsupported`
+            println!(
+                "* {host} has modes {} apps {} only",
+                build_info.modes.join(" "),
+                build_info.apps.join(" ")
+            )
+        }
+    }
+    if mismatched {
+        println!("! expect all hosts to run {expected}, run reload first");
+        return false;
+    }
+    println!("* all hosts run {}", expected.git_hash);
+    true
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn host_session(
     host: impl Into<String>,
+    scheme: &'static str,
     task: Task,
+    id: String,
     client: Arc<Client>,
     cancel: CancellationToken,
     panic: Arc<AtomicBool>,
-) {
+    ready: CancellationToken,
+) -> Result<(), Failure> {
     let host = host.into();
+    let result =
+        host_session_internal(&host, scheme, task, &id, &client, &cancel, &panic, ready).await;
+    if result.is_err() {
+        cancel.cancel()
+    }
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn host_session_internal(
+    host: &str,
+    scheme: &str,
+    task: Task,
+    id: &str,
+    client: &Client,
+    cancel: &CancellationToken,
+    panic: &AtomicBool,
+    ready: CancellationToken,
+) -> Result<(), Failure> {
     // println!("{host}");
-    let endpoint = format!("http://{host}:9999");
-    let response = client
-        .post(format!("{endpoint}/task"))
-        .json(&task)
-        .send()
-        .await
-        .unwrap();
-    assert!(response.status().is_success());
+    let endpoint = format!("{scheme}://{host}:9999");
+    send_with_retry(host, || client.post(format!("{endpoint}/task")).json(&task)).await?;
+    ready.cancel();
     loop {
         select! {
             _ = sleep(Duration::from_secs(1)) => {}
             _ = cancel.cancelled() => break,
         }
-        let response = client
-            .get(format!("{endpoint}/panic"))
-            .send()
-            .await
-            .unwrap();
-        assert!(response.status().is_success());
+        let response = send_with_retry(host, || client.get(format!("{endpoint}/panic"))).await?;
         if response.json::<bool>().await.unwrap() {
             println!("! {host} panic");
             panic.store(true, SeqCst);
-            cancel.cancel();
-            break;
+            return Err(Failure::Panicked(host.into()));
         }
     }
     if !panic.load(SeqCst) {
-        let response = client
-            .post(format!("{endpoint}/reset"))
-            .send()
-            .await
-            .unwrap();
-        assert!(response.status().is_success())
+        let response = send_with_retry(host, || client.post(format!("{endpoint}/reset"))).await?;
+        if let Some(stats) = response.json::<Option<ReplicaStats>>().await.unwrap() {
+            println!(
+                "* {host} socket drops {} MAC coverage {:?} spoofed multicast {} invalid multicast {} stale {} client table {:?}",
+                stats.socket_drops,
+                stats.mac_coverage,
+                stats.spoofed_multicast,
+                stats.invalid_multicast,
+                stats.stale,
+                stats.client_table
+            );
+            if let Some(signatures) = stats.signatures {
+                println!(
+                    "* {host} signatures {signatures:?} fast verify ratio {}",
+                    signatures.fast_verify_ratio()
+                )
+            }
+            if let Some(query_trigger) = stats.query_trigger {
+                println!("* {host} query trigger {query_trigger:?}")
+            }
+            for source in &stats.multicast_sources {
+                println!("* {host} multicast {source:?}");
+                if source.equivocated != 0 {
+                    println!("! {host} sequencer equivocation from {}", source.source)
+                }
+            }
+            if let Some(sequencing) = &stats.sequencing {
+                let quantiles = |histogram: &control_messages::Log2Histogram| {
+                    (histogram.quantile(0.5), histogram.quantile(0.99))
+                };
+                println!(
+                    "* {host} sequencing p50/p99 to sequencer {:?} in sequencer {:?} to replica {:?} unstamped {} skewed {}",
+                    quantiles(&sequencing.to_sequencer),
+                    quantiles(&sequencing.in_sequencer),
+                    quantiles(&sequencing.to_replica),
+                    sequencing.unstamped,
+                    sequencing.skewed
+                )
+            }
+            if let Some(waiting) = stats.waiting {
+                println!("* {host} waiting {waiting:?}")
+            }
+            for phase in &stats.strictness_phases {
+                println!(
+                    "* {host} {:?} {} ops in {:?}",
+                    phase.strictness, phase.num_op, phase.duration
+                )
+            }
+            println!(
+                "* {host} queueing p50 {:?} p99 {:?} processing p50 {:?} p99 {:?}",
+                stats.queueing_delay.quantile(0.5),
+                stats.queueing_delay.quantile(0.99),
+                stats.processing_delay.quantile(0.5),
+                stats.processing_delay.quantile(0.99),
+            );
+            let utilization = stats.utilization;
+            println!(
+                "* {host} utilization {:.3?} busy {:?} pacing {:?} idle {:?} {:.1?} events per pace",
+                utilization.ratio(),
+                utilization.busy,
+                utilization.pacing,
+                utilization.idle,
+                utilization.events_per_pace()
+            );
+            // op number and unix time in nanoseconds, to be aligned with client-side samples
+            if !stats.execution_index.is_empty() {
+                let dir = format!("execution-index/{id}");
+                std::fs::create_dir_all(&dir).unwrap();
+                let mut content = String::new();
+                for (op_num, time) in &stats.execution_index {
+                    let time = time.duration_since(std::time::UNIX_EPOCH).unwrap();
+                    writeln!(&mut content, "{op_num},{}", time.as_nanos()).unwrap()
+                }
+                std::fs::write(format!("{dir}/{host}.csv"), content).unwrap()
+            }
+            if let Some(socket_delay) = &stats.socket_delay {
+                println!(
+                    "* {host} socket delay p50 {:?} p99 {:?}",
+                    socket_delay.quantile(0.5),
+                    socket_delay.quantile(0.99)
+                )
+            }
+        }
     }
+    Ok(())
 }
@@ -1,3 +1,4 @@
+use control_messages::Environment;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,4 +23,29 @@ impl Output {
         assert!(output.status.success());
         toml::from_str(std::str::from_utf8(&output.stdout).unwrap()).unwrap()
     }
+
+    pub fn environment(&self) -> Environment {
+        let parse = |ips: &[String]| Vec::from_iter(ips.iter().map(|ip| ip.parse().unwrap()));
+        Environment {
+            client_hosts: self.client_hosts.clone(),
+            client_ips: parse(&self.client_ips),
+            replica_hosts: self.replica_hosts.clone(),
+            replica_ips: parse(&self.replica_ips),
+            multicast_ip: self.sequencer_ip.parse().unwrap(),
+            sequencer_host: Some(self.sequencer_host.clone()),
+            relay_ips: parse(&self.relay_ips),
+            relay_hosts: self.relay_hosts.clone(),
+            relay_tree: None,
+            // not provisioned
+            standby_hosts: Default::default(),
+            standby_ips: Default::default(),
+            localhost: None,
+            work_dir: String::from("/home/ubuntu"),
+            // control endpoints are exposed to the public internet
+            tls: true,
+            keys: false,
+            timestamping: None,
+            wasm_modules: Default::default(),
+        }
+    }
 }
@@ -1,6 +1,9 @@
 use std::{process::Command, thread::spawn};
 
+use control_messages::deploy::sync_file;
+
 fn main() {
+    control_messages::build_info!().exit_on_version_flag();
     let status = Command::new("cargo")
         .args(["build", "--release", "--package", "relay"])
         .status()
@@ -13,6 +16,15 @@ fn main() {
     assert!(status.success());
 
     let output = neo_aws::Output::new_terraform();
+    let path = control_messages::Environment::path("aws");
+    let mut environment = output.environment();
+    // the relay tree is configured by hand, keep it across deployments
+    if let Ok(content) = std::fs::read_to_string(&path) {
+        if let Ok(previous) = toml::from_str::<control_messages::Environment>(&content) {
+            environment.relay_tree = previous.relay_tree
+        }
+    }
+    std::fs::write(path, toml::to_string(&environment).unwrap()).unwrap();
     // let sequencer_args = format!(
     //     "{} {}",
     //     match args().nth(1).as_deref() {
@@ -36,49 +48,19 @@ fn main() {
             assert!(status.success());
         })
     }));
-    let mut relay_args = vec![output.relay_ips[1..].join(" ")];
-    for i in 0..5 {
-        relay_args.push(
-            Vec::from_iter(output.replica_ips.iter().skip(i * 14).take(14).cloned()).join(" "),
-        )
-    }
-    sessions.extend(
-        output
-            .relay_hosts
-            .into_iter()
-            .zip(relay_args)
-            .map(|(host, args)| {
-                spawn(move || {
-                    let status = Command::new("rsync")
-                        .arg("target/release/relay")
-                        .arg(format!("{host}:"))
-                        .status()
-                        .unwrap();
-                    assert!(status.success());
-
-                    Command::new("ssh")
-                        .args([&host, "pkill", "-KILL", "--full", "relay"])
-                        .status()
-                        .unwrap();
-
-                    let status = Command::new("ssh")
-                        .arg(host)
-                        .arg(format!(
-                            "./relay {args} 1>./relay-stdout.txt 2>./relay-stderr.txt &"
-                        ))
-                        .status()
-                        .unwrap();
-                    assert!(status.success());
-                })
-            }),
-    );
+    // relays are started by `control` with the destinations of every run
+    sessions.extend(output.relay_hosts.into_iter().map(|host| {
+        spawn(move || {
+            sync_file(&host, "target/release/relay", "relay").unwrap();
+        })
+    }));
     sessions.push(spawn(move || {
-        let status = Command::new("rsync")
-            .arg("target/release/neo-sequencer")
-            .arg(format!("{}:", output.sequencer_host))
-            .status()
-            .unwrap();
-        assert!(status.success());
+        sync_file(
+            &output.sequencer_host,
+            "target/release/neo-sequencer",
+            "neo-sequencer",
+        )
+        .unwrap();
 
         // Command::new("ssh")
         //     .args([
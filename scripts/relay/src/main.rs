@@ -1,9 +1,14 @@
 use std::{
     env::args,
-    iter::repeat,
-    net::{Ipv4Addr, UdpSocket},
-    sync::Arc,
-    thread::{available_parallelism, spawn},
+    io::{Read, Write},
+    iter::{repeat, repeat_n},
+    net::{Ipv4Addr, TcpListener, UdpSocket},
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    thread::{available_parallelism, sleep, spawn},
+    time::{Duration, Instant},
 };
 
 use nix::{
@@ -17,44 +22,246 @@ pub fn set_affinity(index: usize) {
     sched_setaffinity(Pid::from_raw(0), &cpu_set).unwrap()
 }
 
+// the sequence numbers seen on one port during the last second, which tell whether the packets are
+// lost before this hop, i.e. by the sequencer or the parent relay, or after it
+#[derive(Debug, Default)]
+struct HopStats {
+    received: AtomicU64,
+    // skipped forward
+    missing: AtomicU64,
+    // behind the expected one, i.e. reordered, or counted as missing before
+    late: AtomicU64,
+    // the same as the previous one
+    duplicated: AtomicU64,
+}
+
+// a token bucket shared by the sending threads, one token per forwarded packet, which is sent to
+// every destination at once
+#[derive(Debug)]
+struct Pacer {
+    rate: f64,
+    burst: f64,
+    // negative if sends are reserved ahead
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Pacer {
+    fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            refilled: Instant::now(),
+        }
+    }
+
+    // take a token, and return how long to wait before spending it
+    fn reserve(&mut self) -> Duration {
+        let now = Instant::now();
+        self.tokens =
+            (self.tokens + (now - self.refilled).as_secs_f64() * self.rate).min(self.burst);
+        self.refilled = now;
+        self.tokens -= 1.;
+        Duration::from_secs_f64((-self.tokens).max(0.) / self.rate)
+    }
+}
+
+// `sleep` overshoots by tens of microseconds, which is the whole gap between packets at the rates
+// that the pacer runs at, so the last stretch is spun instead
+fn sleep_precisely(duration: Duration) {
+    const SPIN: Duration = Duration::from_micros(100);
+    let deadline = Instant::now() + duration;
+    if let Some(duration) = duration.checked_sub(SPIN) {
+        sleep(duration)
+    }
+    while Instant::now() < deadline {
+        std::hint::spin_loop()
+    }
+}
+
+// since the relay is started
+#[derive(Debug, Default)]
+struct QueueStats {
+    forwarded: AtomicU64,
+    // exceeding `--queue-len`
+    dropped: AtomicU64,
+    // held back by the pacer
+    delayed: AtomicU64,
+    max_depth: AtomicU64,
+}
+
+fn flag<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, usage: &str) -> T {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("{usage}"))
+}
+
+// relay [--port <port>]... [--rate <packets/s> [--burst <packets>]] [--queue-len <packets>]
+//     [--stats-port <port>] <ip>...
+// forward the packets received on each port to the same port of the ips, 60004 if no port is given,
+// i.e. one port per multicast group
+// with `--rate`, forwarding is paced so that bursts from the sequencer do not overflow the queues of
+// the replica NICs, and the packets that wait beyond `--queue-len` are dropped
+// `--stats-port` serves the queue stats over HTTP
 fn main() {
-    let ips = Vec::from_iter(args().skip(1).map(|ip| ip.parse::<Ipv4Addr>().unwrap()));
-    let socket = Arc::new(UdpSocket::bind("0.0.0.0:60004").unwrap());
-    // let messages = flume::bounded::<Vec<_>>(1024);
-    let messages = flume::unbounded::<Vec<_>>();
-    for ((index, messages), (socket, ips)) in repeat(messages.1)
-        .take(usize::from(available_parallelism().unwrap()) - 1)
-        .enumerate()
-        .zip(repeat((socket.clone(), ips)))
+    control_messages::build_info!().exit_on_version_flag();
+    let mut args = args().skip(1).peekable();
+    let mut ports = Vec::new();
+    let mut rate = None;
+    let mut burst = None;
+    let mut queue_len = None;
+    let mut stats_port = None;
+    while let Some(arg) = args.next_if(|arg| arg.starts_with("--")) {
+        match &*arg {
+            "--port" => ports.push(flag(&mut args, "--port <port>")),
+            "--rate" => rate = Some(flag::<f64>(&mut args, "--rate <packets/s>")),
+            "--burst" => burst = Some(flag::<f64>(&mut args, "--burst <packets>")),
+            "--queue-len" => queue_len = Some(flag::<usize>(&mut args, "--queue-len <packets>")),
+            "--stats-port" => stats_port = Some(flag::<u16>(&mut args, "--stats-port <port>")),
+            _ => panic!("unknown flag {arg}"),
+        }
+    }
+    if ports.is_empty() {
+        ports.push(60004)
+    }
+    assert!(rate.is_some() || burst.is_none(), "--burst without --rate");
+    let pacer = rate.map(|rate| {
+        assert!(rate > 0., "--rate must be positive");
+        Arc::new(Mutex::new(Pacer::new(rate, burst.unwrap_or(1.).max(1.))))
+    });
+    let queue_stats = Arc::new(QueueStats::default());
+    let ips = Vec::from_iter(args.map(|ip| ip.parse::<Ipv4Addr>().unwrap()));
+    let sockets =
+        Arc::new(Vec::from_iter(ports.iter().map(|&port| {
+            (UdpSocket::bind(("0.0.0.0", port)).unwrap(), port)
+        })));
+    // with the index of the socket
+    let messages = match queue_len {
+        Some(queue_len) => flume::bounded::<(usize, Vec<_>)>(queue_len),
+        None => flume::unbounded(),
+    };
+    for ((index, messages), (sockets, ips, pacer, queue_stats)) in repeat_n(
+        messages.1.clone(),
+        usize::from(available_parallelism().unwrap()) - 1,
+    )
+    .enumerate()
+    .zip(repeat((sockets.clone(), ips, pacer, queue_stats.clone())))
     {
         spawn(move || {
             set_affinity(index + 1);
             loop {
-                let buf = messages.recv().unwrap();
+                let (socket_index, buf) = messages.recv().unwrap();
+                if let Some(pacer) = &pacer {
+                    let delay = pacer.lock().unwrap().reserve();
+                    if !delay.is_zero() {
+                        queue_stats.delayed.fetch_add(1, Relaxed);
+                        sleep_precisely(delay)
+                    }
+                }
+                queue_stats.forwarded.fetch_add(1, Relaxed);
+                let (socket, port) = &sockets[socket_index];
                 for &ip in &ips {
-                    socket.send_to(&buf, (ip, 60004)).unwrap();
+                    socket.send_to(&buf, (ip, *port)).unwrap();
                 }
             }
         });
     }
 
-    // let counter = Arc::new(AtomicU32::new(0));
-    // spawn({
-    //     let counter = counter.clone();
-    //     move || loop {
-    //         std::thread::sleep(Duration::from_secs(1));
-    //         let count = counter.swap(0, std::sync::atomic::Ordering::SeqCst);
-    //         if count != 0 {
-    //             println!("{count}")
-    //         }
-    //     }
-    // });
-
-    set_affinity(0);
-    let mut buf = vec![0; 65536];
-    loop {
-        let (len, _) = socket.recv_from(&mut buf).unwrap();
-        messages.0.send(buf[..len].to_vec()).unwrap();
-        // counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    let stats = Arc::new(Vec::from_iter(ports.iter().map(|_| HopStats::default())));
+    spawn({
+        let stats = stats.clone();
+        let ports = ports.clone();
+        move || loop {
+            sleep(Duration::from_secs(1));
+            for (stats, port) in stats.iter().zip(&ports) {
+                let received = stats.received.swap(0, Relaxed);
+                if received != 0 {
+                    println!(
+                        "port {port} received {received} missing {} late {} duplicated {}",
+                        stats.missing.swap(0, Relaxed),
+                        stats.late.swap(0, Relaxed),
+                        stats.duplicated.swap(0, Relaxed)
+                    )
+                }
+            }
+        }
+    });
+
+    if let Some(stats_port) = stats_port {
+        let listener = TcpListener::bind(("0.0.0.0", stats_port)).unwrap();
+        let queue_stats = queue_stats.clone();
+        let messages = messages.1.clone();
+        spawn(move || {
+            for mut stream in listener.incoming().map_while(Result::ok) {
+                // any request gets the stats, so just take the request out of the way, without an
+                // idle connection holding back the ones after it
+                let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+                let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+                let _ = stream.read(&mut [0; 4096]);
+                let body = format!(
+                    "depth {}\nmax-depth {}\nforwarded {}\ndropped {}\ndelayed {}\n",
+                    messages.len(),
+                    queue_stats.max_depth.load(Relaxed),
+                    queue_stats.forwarded.load(Relaxed),
+                    queue_stats.dropped.load(Relaxed),
+                    queue_stats.delayed.load(Relaxed),
+                );
+                let _ = write!(
+                    stream,
+                    "HTTP/1.0 200 OK\r\ncontent-type: text/plain\r\ncontent-length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+    }
+
+    // every port is received on the first core
+    let receive = move |socket_index: usize| {
+        set_affinity(0);
+        let stats = &stats[socket_index];
+        let mut buf = vec![0; 65536];
+        let mut previous = None;
+        loop {
+            let (len, _) = sockets[socket_index].0.recv_from(&mut buf).unwrap();
+            // the sequence number leads the ordered multicast header
+            if len >= 4 {
+                let seq_num = u32::from_be_bytes(buf[..4].try_into().unwrap());
+                stats.received.fetch_add(1, Relaxed);
+                match previous.map(|previous: u32| seq_num.wrapping_sub(previous) as i32) {
+                    None | Some(1) => {}
+                    Some(0) => {
+                        stats.duplicated.fetch_add(1, Relaxed);
+                    }
+                    Some(distance @ 2..) => {
+                        stats.missing.fetch_add(distance as u64 - 1, Relaxed);
+                    }
+                    Some(_) => {
+                        stats.late.fetch_add(1, Relaxed);
+                    }
+                }
+                if previous.is_none_or(|previous| seq_num.wrapping_sub(previous) as i32 > 0) {
+                    previous = Some(seq_num)
+                }
+            }
+            match messages.0.try_send((socket_index, buf[..len].to_vec())) {
+                Ok(()) => {
+                    queue_stats
+                        .max_depth
+                        .fetch_max(messages.0.len() as _, Relaxed);
+                }
+                Err(flume::TrySendError::Full(_)) => {
+                    queue_stats.dropped.fetch_add(1, Relaxed);
+                }
+                Err(err) => panic!("{err}"),
+            }
+        }
+    };
+    for socket_index in 1..ports.len() {
+        spawn({
+            let receive = receive.clone();
+            move || receive(socket_index)
+        });
     }
+    receive(0)
 }
@@ -1,92 +1,264 @@
 use std::{
+    net::IpAddr,
+    path::Path,
     process::{Command, Stdio},
-    thread::{sleep, spawn},
+    thread::sleep,
     time::Duration,
 };
 
-#[cfg(not(feature = "aws"))]
-const HOSTS: &[&str] = &[
-    "nsl-node1.d2",
-    "nsl-node2.d2",
-    "nsl-node3.d2",
-    "nsl-node4.d2",
-    "nsl-node10.d2",
-];
-#[cfg(not(feature = "aws"))]
-const LOCALHOST: &str = "nsl-node1.d2";
-#[cfg(feature = "aws")]
-const LOCALHOST: &str = "localhost";
-#[cfg(not(feature = "aws"))]
-const WORK_DIR: &str = "/local/cowsay/artifacts";
-#[cfg(feature = "aws")]
-const WORK_DIR: &str = "/home/ubuntu";
+use control_messages::{
+    deploy::{for_each_host, sync_file, Transfer},
+    Environment,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, SanType};
+
 const PROGRAM: &str = "permissioned-blockchain";
 
 fn main() {
-    let status = Command::new("cargo")
-        .args(["build", "--release", "--bin", PROGRAM])
-        .status()
-        .unwrap();
+    control_messages::build_info!().exit_on_version_flag();
+    let mut build = Command::new("cargo");
+    build.args(["build", "--release", "--bin", PROGRAM, "--bin", "keygen"]);
+    // a slim binary with only these protocols and apps, e.g. `neo,pbft,ycsb`, which is faster to
+    // transfer
+    if let Ok(features) = std::env::var("NEO_FEATURES") {
+        build.args(["--no-default-features", "--features", &features]);
+    }
+    let status = build.status().unwrap();
     assert!(status.success());
 
-    #[cfg(not(feature = "aws"))]
-    let hosts = Vec::from_iter(HOSTS.iter().map(ToString::to_string));
-    #[cfg(feature = "aws")]
-    let hosts = {
-        let output = neo_aws::Output::new_terraform();
-        [&*output.client_hosts, &*output.replica_hosts].concat()
+    let environment = Environment::load();
+    // deploy only to the standby hosts, e.g. a newer build for the rolling upgrade of `control`,
+    // keeping the credentials that the running hosts use
+    let standby_only = std::env::args().nth(1).as_deref() == Some("--standby");
+    let hosts = if standby_only {
+        environment.standby_hosts.clone()
+    } else {
+        [
+            &*environment.client_hosts,
+            &*environment.replica_hosts,
+            &*environment.standby_hosts,
+        ]
+        .concat()
+    };
+    let localhost = environment.localhost.as_deref();
+    let tls = if environment.tls {
+        if !standby_only {
+            generate_credentials(&environment)
+        }
+        Some(Environment::name())
+    } else {
+        None
+    };
+
+    let keys = if environment.keys {
+        if !standby_only {
+            generate_keys(&environment)
+        }
+        Some(Environment::name())
+    } else {
+        None
     };
 
-    let rsync_threads =
-        Vec::from_iter(hosts.iter().filter(|&host| host != LOCALHOST).map(|host| {
-            let host = host.to_string();
-            spawn(move || host_session(&host))
-        }));
-    for thread in rsync_threads {
-        thread.join().unwrap()
+    let session = |host: &str| {
+        let keys = keys
+            .as_deref()
+            .map(|name| (name, key_files(&environment, host)));
+        let modules = if environment.client_hosts.iter().any(|client| client == host) {
+            &[][..]
+        } else {
+            &environment.wasm_modules
+        };
+        host_session(host, &environment.work_dir, tls.as_deref(), keys, modules)
+    };
+    let mut failures = for_each_host(
+        &Vec::from_iter(
+            hosts
+                .iter()
+                .filter(|&host| Some(&**host) != localhost)
+                .cloned(),
+        ),
+        session,
+    );
+    // deploy to the development machine after all remote hosts
+    if let Some(localhost) = localhost {
+        if hosts.iter().any(|host| host == localhost) {
+            failures.extend(for_each_host(&[localhost.into()], session))
+        }
     }
-    if hosts.contains(&LOCALHOST.to_string()) {
-        host_session(LOCALHOST)
+    assert!(failures.is_empty(), "{} hosts failed", failures.len())
+}
+
+// a fresh CA and a server certificate signed by it, shared by all hosts, and a token for mutating
+// endpoints, all of which are replaced on every reload
+fn generate_credentials(environment: &Environment) {
+    let name = Environment::name();
+    std::fs::create_dir_all(Environment::tls_path(&name, "")).unwrap();
+
+    let mut params = CertificateParams::default();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params
+        .distinguished_name
+        .push(DnType::CommonName, format!("neo control plane ({name})"));
+    let ca = Certificate::from_params(params).unwrap();
+
+    let names = [
+        &*environment.client_hosts,
+        &*environment.replica_hosts,
+        &*environment.standby_hosts,
+    ]
+    .concat();
+    let ips = [
+        &*environment.client_ips,
+        &*environment.replica_ips,
+        &*environment.standby_ips,
+    ]
+    .concat();
+    let mut params = CertificateParams::new(names);
+    params.subject_alt_names.extend(
+        ips.into_iter()
+            .chain([IpAddr::from([127, 0, 0, 1])])
+            .map(SanType::IpAddress),
+    );
+    let cert = Certificate::from_params(params).unwrap();
+
+    let token = String::from_iter(
+        rand::thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(32)
+            .map(char::from),
+    );
+    for (file, content) in [
+        ("ca.pem", ca.serialize_pem().unwrap()),
+        ("cert.pem", cert.serialize_pem_with_signer(&ca).unwrap()),
+        ("key.pem", cert.serialize_private_key_pem()),
+        ("token", token),
+    ] {
+        std::fs::write(Environment::tls_path(&name, file), content).unwrap()
     }
-    println!()
 }
 
-fn host_session(host: &str) {
-    let status = Command::new("rsync")
-        .arg(format!("target/release/{PROGRAM}"))
-        .arg(format!("{host}:{WORK_DIR}"))
+// fresh keys of every replica, which are replaced on every reload as the credentials are
+fn generate_keys(environment: &Environment) {
+    let dir = Environment::keys_path(&Environment::name(), "");
+    let _ = std::fs::remove_dir_all(&dir);
+    let status = Command::new("target/release/keygen")
+        .arg(environment.replica_hosts.len().to_string())
+        .arg(&dir)
         .status()
         .unwrap();
-    assert!(status.success());
+    assert!(status.success())
+}
+
+// the public keys for every host, and the secret keys of the replicas that the host runs, where a
+// standby may run any of them
+fn key_files(environment: &Environment, host: &str) -> Vec<String> {
+    let mut files = vec![String::from("public.keys")];
+    if environment
+        .standby_hosts
+        .iter()
+        .any(|standby| standby == host)
+    {
+        files.extend(
+            (0..environment.replica_hosts.len()).map(|index| format!("replica-{index}.key")),
+        )
+    } else {
+        files.extend(
+            (environment.replica_hosts.iter().enumerate())
+                .filter(|(_, replica)| *replica == host)
+                .map(|(index, _)| format!("replica-{index}.key")),
+        )
+    }
+    files
+}
+
+fn check(command: &mut Command) -> Result<(), String> {
+    let status = command.status().map_err(|err| err.to_string())?;
+    if !status.success() {
+        return Err(format!("{command:?} exits with {status}"));
+    }
+    Ok(())
+}
+
+fn host_session(
+    host: &str,
+    work_dir: &str,
+    tls: Option<&str>,
+    keys: Option<(&str, Vec<String>)>,
+    modules: &[String],
+) -> Result<Transfer, String> {
+    let transfer = sync_file(
+        host,
+        &format!("target/release/{PROGRAM}"),
+        &format!("{work_dir}/{PROGRAM}"),
+    )?;
+    for module in modules {
+        let name = Path::new(module)
+            .file_name()
+            .ok_or(format!("{module} is not a file"))?;
+        sync_file(
+            host,
+            module,
+            &format!("{work_dir}/{}", name.to_string_lossy()),
+        )?;
+    }
+    if let Some(name) = tls {
+        check(
+            Command::new("rsync")
+                .args(["--recursive", "--delete"])
+                .arg(Environment::tls_path(name, ""))
+                .arg(format!("{host}:{work_dir}/tls")),
+        )?
+    }
+    if let Some((name, files)) = keys {
+        // the secret keys of other replicas must not stay around from previous reloads
+        check(Command::new("ssh").arg(host).arg(format!(
+            "rm -rf {work_dir}/keys && mkdir -p -m 700 {work_dir}/keys"
+        )))?;
+        // keep the secret keys readable by the owner only, as `keygen` creates them
+        check(
+            Command::new("rsync")
+                .arg("--perms")
+                .args(files.iter().map(|file| Environment::keys_path(name, file)))
+                .arg(format!("{host}:{work_dir}/keys/")),
+        )?
+    }
     let status = Command::new("ssh")
         .args([host, "pkill", "-INT", "--full", PROGRAM])
         .status()
-        .unwrap();
+        .map_err(|err| err.to_string())?;
     // sleep(Duration::from_secs(1));
     if status.success() {
         let status = Command::new("ssh")
             .args([host, "pkill", "-KILL", "--full", PROGRAM])
             .status()
-            .unwrap();
+            .map_err(|err| err.to_string())?;
         // sleep(Duration::from_secs(1));
         if status.success() {
             println!("! cleaned nonresponsive server on {host}")
         }
     }
-    let status = Command::new("ssh")
-        .arg(host)
-        .arg(format!("{WORK_DIR}/{PROGRAM} 1>{WORK_DIR}/{PROGRAM}-stdout.txt 2>{WORK_DIR}/{PROGRAM}-stderr.txt &"))
-        .status()
-        .unwrap();
-    assert!(status.success());
+    check(Command::new("ssh").arg(host).arg(format!(
+        "{work_dir}/{PROGRAM} {} 1>{work_dir}/{PROGRAM}-stdout.txt 2>{work_dir}/{PROGRAM}-stderr.txt &",
+        if tls.is_some() { format!("--tls {work_dir}/tls") } else { String::new() }
+    )))?;
     sleep(Duration::from_secs(1));
-    let status = Command::new("curl")
-        .arg("--silent")
-        .arg(format!("http://{host}:9999/panic"))
-        .stdout(Stdio::null())
-        // .stderr(Stdio::null())
-        .status()
-        .unwrap();
-    assert!(status.success());
-    eprint!("* server started on {host}        \r")
+    let mut curl = Command::new("curl");
+    curl.arg("--silent");
+    if let Some(name) = tls {
+        let path = Environment::tls_path(name, "token");
+        let token = std::fs::read_to_string(&path).map_err(|err| format!("{path}: {err}"))?;
+        curl.arg("--cacert")
+            .arg(Environment::tls_path(name, "ca.pem"))
+            .arg("--header")
+            .arg(format!("Authorization: Bearer {}", token.trim()));
+    }
+    check(
+        curl.arg(format!(
+            "{}://{host}:9999/panic",
+            if tls.is_some() { "https" } else { "http" }
+        ))
+        .stdout(Stdio::null()),
+    )?;
+    Ok(transfer)
 }
@@ -1,34 +1,477 @@
-use std::{future::Future, pin::Pin};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
 
-use rand::Rng;
+use rand::{rngs::StdRng, Rng};
 use tokio_util::sync::CancellationToken;
 
 use crate::Client;
 
+#[cfg(feature = "kvstore")]
+pub mod kvstore;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "ycsb")]
 pub mod ycsb;
 
 #[derive(Debug, Clone)]
 pub enum App {
     Null,
+    #[cfg(feature = "ycsb")]
     Ycsb(ycsb::App),
+    #[cfg(feature = "kvstore")]
+    KvStore(kvstore::App),
+    #[cfg(feature = "wasm")]
+    Wasm(Box<wasm::App>),
+    SelfCheck(Box<SelfCheck>),
+    Indexed(Box<ExecutionIndex>),
 }
 
 impl App {
     pub fn execute(&mut self, op: &[u8]) -> Vec<u8> {
         match self {
             Self::Null => Default::default(),
+            #[cfg(feature = "ycsb")]
             Self::Ycsb(app) => app.execute(op),
+            #[cfg(feature = "kvstore")]
+            Self::KvStore(app) => app.execute(op),
+            #[cfg(feature = "wasm")]
+            Self::Wasm(app) => app.execute(op),
+            Self::SelfCheck(app) => app.execute(op),
+            Self::Indexed(app) => app.execute(op),
         }
     }
+
+    // the result of `op` on the current state if `op` is read-only, without executing it in order,
+    // or none if it may write, so it has to be ordered
+    pub fn execute_read(&self, op: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Self::Null => Some(Default::default()),
+            #[cfg(feature = "ycsb")]
+            Self::Ycsb(app) => app.execute_read(op),
+            #[cfg(feature = "kvstore")]
+            Self::KvStore(app) => app.execute_read(op),
+            // any call may write
+            #[cfg(feature = "wasm")]
+            Self::Wasm(_) => None,
+            Self::SelfCheck(app) => app.app.execute_read(op),
+            Self::Indexed(app) => app.app.execute_read(op),
+        }
+    }
+
+    // called on the checkpoint schedule of the protocol, after executing `op_num` ops
+    pub fn checkpoint(&mut self, op_num: u64) -> SnapshotHandle {
+        let delta = match self {
+            Self::Null => Delta::Null,
+            #[cfg(feature = "ycsb")]
+            Self::Ycsb(app) => Delta::Ycsb(Arc::new(app.checkpoint(op_num))),
+            #[cfg(feature = "kvstore")]
+            Self::KvStore(app) => Delta::KvStore(Arc::new(app.checkpoint(op_num))),
+            #[cfg(feature = "wasm")]
+            Self::Wasm(app) => Delta::Wasm(Arc::new(app.checkpoint(op_num))),
+            Self::SelfCheck(app) => {
+                // keep the shadow from accumulating written keys
+                app.shadow.checkpoint(op_num);
+                return app.app.checkpoint(op_num);
+            }
+            Self::Indexed(app) => return app.app.checkpoint(op_num),
+        };
+        SnapshotHandle { op_num, delta }
+    }
+
+    // the ops executed from now on are of `op_num` of the protocol, which every protocol tells before
+    // executing, e.g. the requests of a neo batch share one, and the blocks of the protocols without
+    // op numbers have their heights
+    pub fn set_op_num(&mut self, op_num: u64) {
+        match self {
+            Self::SelfCheck(app) => {
+                app.app.set_op_num(op_num);
+                app.shadow.set_op_num(op_num)
+            }
+            Self::Indexed(app) => app.set_op_num(op_num),
+            _ => {}
+        }
+    }
+
+    // the ops executed from now on are of `op_num` of the protocol, and are recorded to be undone by
+    // `rollback` until `truncate_undo` passes them, while nothing is recorded before the first call
+    pub fn speculate(&mut self, op_num: u64) {
+        match self {
+            Self::Null => {}
+            #[cfg(feature = "ycsb")]
+            Self::Ycsb(app) => app.speculate(op_num),
+            #[cfg(feature = "kvstore")]
+            Self::KvStore(app) => app.speculate(op_num),
+            #[cfg(feature = "wasm")]
+            Self::Wasm(app) => app.speculate(op_num),
+            Self::SelfCheck(app) => {
+                app.app.speculate(op_num);
+                app.shadow.speculate(op_num)
+            }
+            Self::Indexed(app) => app.app.speculate(op_num),
+        }
+    }
+
+    // undo the ops after `op_num`, along with the checkpoints after it, whose handles the protocol
+    // discards
+    pub fn rollback(&mut self, op_num: u64) {
+        match self {
+            Self::Null => {}
+            #[cfg(feature = "ycsb")]
+            Self::Ycsb(app) => app.rollback(op_num),
+            #[cfg(feature = "kvstore")]
+            Self::KvStore(app) => app.rollback(op_num),
+            #[cfg(feature = "wasm")]
+            Self::Wasm(app) => app.rollback(op_num),
+            Self::SelfCheck(app) => {
+                app.app.rollback(op_num);
+                app.shadow.rollback(op_num)
+            }
+            Self::Indexed(app) => app.rollback(op_num),
+        }
+    }
+
+    // the ops up to `op_num` are committed and never rolled back, e.g. on a stable checkpoint
+    pub fn truncate_undo(&mut self, op_num: u64) {
+        match self {
+            Self::Null => {}
+            #[cfg(feature = "ycsb")]
+            Self::Ycsb(app) => app.truncate_undo(op_num),
+            #[cfg(feature = "kvstore")]
+            Self::KvStore(app) => app.truncate_undo(op_num),
+            #[cfg(feature = "wasm")]
+            Self::Wasm(app) => app.truncate_undo(op_num),
+            Self::SelfCheck(app) => {
+                app.app.truncate_undo(op_num);
+                app.shadow.truncate_undo(op_num)
+            }
+            Self::Indexed(app) => app.app.truncate_undo(op_num),
+        }
+    }
+
+    // bring the state of the previous checkpoint to `snapshot`
+    pub fn apply(&mut self, snapshot: &SnapshotHandle) {
+        match (self, &snapshot.delta) {
+            (Self::Null, Delta::Null) => {}
+            #[cfg(feature = "ycsb")]
+            (Self::Ycsb(app), Delta::Ycsb(delta)) => app.apply(delta),
+            #[cfg(feature = "kvstore")]
+            (Self::KvStore(app), Delta::KvStore(delta)) => app.apply(delta),
+            #[cfg(feature = "wasm")]
+            (Self::Wasm(app), Delta::Wasm(delta)) => app.apply(delta),
+            (Self::SelfCheck(app), _) => {
+                app.app.apply(snapshot);
+                app.shadow.apply(snapshot)
+            }
+            (Self::Indexed(app), _) => app.app.apply(snapshot),
+            #[allow(unreachable_patterns)]
+            (app, delta) => panic!("{delta:?} does not apply to {app:?}"),
+        }
+    }
+
+    // the whole state, which a replica behind the log that its peers have truncated restores
+    // instead of replaying the log
+    pub fn snapshot(&self) -> Vec<u8> {
+        match self {
+            Self::Null => Default::default(),
+            #[cfg(feature = "ycsb")]
+            Self::Ycsb(app) => app.snapshot(),
+            #[cfg(feature = "kvstore")]
+            Self::KvStore(app) => app.snapshot(),
+            #[cfg(feature = "wasm")]
+            Self::Wasm(app) => app.snapshot(),
+            Self::SelfCheck(app) => app.app.snapshot(),
+            Self::Indexed(app) => app.app.snapshot(),
+        }
+    }
+
+    // replace the state with a `snapshot` of the same app, false if it does not decode
+    pub fn restore(&mut self, snapshot: &[u8]) -> bool {
+        match self {
+            Self::Null => snapshot.is_empty(),
+            #[cfg(feature = "ycsb")]
+            Self::Ycsb(app) => match ycsb::App::restore(snapshot) {
+                Some(restored) => {
+                    *app = restored;
+                    true
+                }
+                None => false,
+            },
+            #[cfg(feature = "kvstore")]
+            Self::KvStore(app) => match kvstore::App::restore(snapshot) {
+                Some(restored) => {
+                    *app = restored;
+                    true
+                }
+                None => false,
+            },
+            #[cfg(feature = "wasm")]
+            Self::Wasm(app) => app.restore(snapshot),
+            Self::SelfCheck(app) => app.app.restore(snapshot) && app.shadow.restore(snapshot),
+            Self::Indexed(app) => app.app.restore(snapshot),
+        }
+    }
+}
+
+// the state written since the previous checkpoint, which is taken without copying the rest of the
+// state so execution does not stall on large app state
+// the state of a checkpoint is the initial state, e.g. the populated YCSB table, with the handles of
+// every checkpoint up to it applied in order
+#[derive(Debug, Clone)]
+pub struct SnapshotHandle {
+    pub op_num: u64,
+    pub delta: Delta,
+}
+
+#[derive(Debug, Clone)]
+pub enum Delta {
+    Null,
+    #[cfg(feature = "ycsb")]
+    Ycsb(Arc<ycsb::Delta>),
+    #[cfg(feature = "kvstore")]
+    KvStore(Arc<kvstore::Delta>),
+    #[cfg(feature = "wasm")]
+    Wasm(Arc<wasm::Delta>),
+}
+
+impl SnapshotHandle {
+    // fold the following `snapshot` into this one, so applying this one brings the state of both,
+    // e.g. compact the handles up to a stable checkpoint, whose state is then the initial state
+    // with the merged handle applied
+    pub fn merge(&mut self, snapshot: &SnapshotHandle) {
+        assert!(snapshot.op_num >= self.op_num);
+        self.op_num = snapshot.op_num;
+        match (&mut self.delta, &snapshot.delta) {
+            (Delta::Null, Delta::Null) => {}
+            #[cfg(feature = "ycsb")]
+            (Delta::Ycsb(delta), Delta::Ycsb(later)) => Arc::make_mut(delta).extend(
+                later
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone())),
+            ),
+            #[cfg(feature = "kvstore")]
+            (Delta::KvStore(delta), Delta::KvStore(later)) => Arc::make_mut(delta).extend(
+                later
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone())),
+            ),
+            #[cfg(feature = "wasm")]
+            (Delta::Wasm(delta), Delta::Wasm(later)) => Arc::make_mut(delta).extend(
+                later
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone())),
+            ),
+            #[allow(unreachable_patterns)]
+            (delta, later) => panic!("{later:?} does not merge into {delta:?}"),
+        }
+    }
+
+    // merge the handles of `snapshots` up to the one of `op_num` into it, which becomes the first
+    pub fn compact(snapshots: &mut Vec<SnapshotHandle>, op_num: u64) {
+        let num_merged = snapshots.partition_point(|snapshot| snapshot.op_num <= op_num);
+        let mut merged = snapshots.drain(..num_merged);
+        let mut stable = merged.next().unwrap();
+        for snapshot in merged {
+            stable.merge(&snapshot)
+        }
+        assert_eq!(stable.op_num, op_num);
+        snapshots.insert(0, stable)
+    }
+}
+
+// the previous values of the entries that ops write, by the op numbers of the protocol, so the ops
+// after an op number can be undone, for the protocols that execute speculatively
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndoLog<K, V> {
+    // of the ops being executed, none before speculating
+    op_num: Option<u64>,
+    entries: Vec<(u64, K, Option<V>)>,
+    // the checkpoints taken while speculating, which take the written keys out of the dirty ones
+    checkpoints: Vec<u64>,
+}
+
+impl<K, V> Default for UndoLog<K, V> {
+    fn default() -> Self {
+        Self {
+            op_num: None,
+            entries: Default::default(),
+            checkpoints: Default::default(),
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> UndoLog<K, V> {
+    pub fn speculate(&mut self, op_num: u64) {
+        self.op_num = Some(op_num)
+    }
+
+    // before `key` is written in `table`
+    pub fn record(&mut self, table: &BTreeMap<K, V>, key: &K) {
+        if let Some(op_num) = self.op_num {
+            self.entries
+                .push((op_num, key.clone(), table.get(key).cloned()))
+        }
+    }
+
+    pub fn checkpoint(&mut self, op_num: u64) {
+        if self.op_num.is_some() {
+            self.checkpoints.push(op_num)
+        }
+    }
+
+    // the restored keys are dirty, and so are the ones written since the latest checkpoint that is
+    // kept, as the discarded checkpoints have taken them
+    pub fn rollback(&mut self, op_num: u64, table: &mut BTreeMap<K, V>, dirty: &mut BTreeSet<K>) {
+        let num_kept = self
+            .entries
+            .partition_point(|(entry_num, ..)| *entry_num <= op_num);
+        for (_, key, value) in self.entries.drain(num_kept..).rev() {
+            match value {
+                Some(value) => table.insert(key.clone(), value),
+                None => table.remove(&key),
+            };
+            dirty.insert(key);
+        }
+        let num_checkpoint = self
+            .checkpoints
+            .partition_point(|&checkpoint_num| checkpoint_num <= op_num);
+        if num_checkpoint < self.checkpoints.len() {
+            self.checkpoints.truncate(num_checkpoint);
+            let checkpoint_num = self.checkpoints.last().copied().unwrap_or_default();
+            for (entry_num, key, _) in &self.entries {
+                if *entry_num > checkpoint_num {
+                    dirty.insert(key.clone());
+                }
+            }
+        }
+    }
+
+    pub fn truncate(&mut self, op_num: u64) {
+        let num_truncated = self
+            .entries
+            .partition_point(|(entry_num, ..)| *entry_num <= op_num);
+        self.entries.drain(..num_truncated);
+        // keep the checkpoint at `op_num`, which the later writes are dirty since
+        let num_truncated = self
+            .checkpoints
+            .partition_point(|&checkpoint_num| checkpoint_num < op_num);
+        self.checkpoints.drain(..num_truncated);
+    }
+}
+
+// debug mode that executes every op on a separately constructed shadow of the app as well, and
+// compares the results of a sampled fraction of ops, so a nondeterministic app panics at the op
+// that diverges instead of showing up as mismatched replies on clients
+// the shadow has to execute every op to stay in step, so this costs about twice the execution
+#[derive(Debug, Clone)]
+pub struct SelfCheck {
+    app: App,
+    shadow: App,
+    sample_rate: f64,
+    rng: StdRng,
+    op_num: u64,
+    pub num_checked: u64,
+}
+
+impl SelfCheck {
+    // `shadow` must be constructed the same way as `app` but not cloned from it, or state that is
+    // cloned along, e.g. hasher keys, hides the nondeterminism
+    pub fn new(app: App, shadow: App, sample_rate: f64, rng: StdRng) -> Self {
+        Self {
+            app,
+            shadow,
+            sample_rate,
+            rng,
+            op_num: 0,
+            num_checked: 0,
+        }
+    }
+
+    fn execute(&mut self, op: &[u8]) -> Vec<u8> {
+        self.op_num += 1;
+        let result = self.app.execute(op);
+        let shadow_result = self.shadow.execute(op);
+        if self.rng.gen_bool(self.sample_rate) {
+            self.num_checked += 1;
+            assert!(
+                result == shadow_result,
+                "op {} {op:?} results in {result:?} but {shadow_result:?} on the shadow app",
+                self.op_num
+            )
+        }
+        result
+    }
+}
+
+// records the wall clock of executing every `interval`th op, so that replica-side events can be
+// placed on a common timeline with client-side latency samples and the fault schedule after a run
+// keyed by the op number that the protocol tells with `App::set_op_num`, or the block height for
+// the protocols that number blocks rather than ops, so re-executed and re-derived ops do not shift
+// it
+#[derive(Debug, Clone)]
+pub struct ExecutionIndex {
+    app: App,
+    interval: u64,
+    op_num: u64,
+    pub entries: Arc<Mutex<Vec<(u64, SystemTime)>>>,
+}
+
+impl ExecutionIndex {
+    pub fn new(app: App, interval: u64) -> Self {
+        Self {
+            app,
+            interval,
+            op_num: 0,
+            entries: Default::default(),
+        }
+    }
+
+    fn set_op_num(&mut self, op_num: u64) {
+        self.app.set_op_num(op_num);
+        self.op_num = op_num
+    }
+
+    // the rolled back ops are executed again, and recorded anew
+    fn rollback(&mut self, op_num: u64) {
+        self.app.rollback(op_num);
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|&(entry_num, _)| entry_num <= op_num);
+        self.op_num = op_num
+    }
+
+    fn execute(&mut self, op: &[u8]) -> Vec<u8> {
+        let result = self.app.execute(op);
+        // not numbered yet
+        if self.op_num != 0 && self.op_num.is_multiple_of(self.interval) {
+            let entries = &mut *self.entries.lock().unwrap();
+            // once for the ops sharing the op number
+            if entries.last().map(|&(op_num, _)| op_num) != Some(self.op_num) {
+                entries.push((self.op_num, SystemTime::now()))
+            }
+        }
+        result
+    }
 }
 
 #[derive(Debug)]
 pub enum Workload {
     Null,
+    #[cfg(feature = "ycsb")]
     Ycsb(ycsb::Workload),
+    #[cfg(feature = "kvstore")]
+    KvStore(kvstore::Workload),
+    #[cfg(feature = "wasm")]
+    Wasm(wasm::Workload),
 }
 
 impl Workload {
+    #[cfg_attr(not(feature = "ycsb"), allow(unused_variables))]
     pub fn generate(
         &self,
         client: impl Client + Send + Sync + 'static,
@@ -43,7 +486,171 @@ impl Workload {
                 });
                 finish.cancelled().await
             }),
+            #[cfg(feature = "ycsb")]
             Self::Ycsb(workload) => workload.generate(client, rng),
+            #[cfg(feature = "kvstore")]
+            Self::KvStore(workload) => workload.generate(client, rng),
+            #[cfg(feature = "wasm")]
+            Self::Wasm(workload) => workload.generate(client, rng),
         }
     }
 }
+
+#[cfg(all(test, feature = "ycsb"))]
+mod tests {
+    use bincode::Options;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn incremental_checkpoint() {
+        let op = |op| bincode::options().serialize(&op).unwrap();
+        let mut app = App::Ycsb(Default::default());
+        let mut replica = app.clone();
+        app.execute(&op(ycsb::Op::Insert("a".into(), "1".into())));
+        app.execute(&op(ycsb::Op::Insert("b".into(), "2".into())));
+        let snapshot1 = app.checkpoint(2);
+        app.execute(&op(ycsb::Op::Update("a".into(), "3".into())));
+        app.execute(&op(ycsb::Op::Delete("b".into())));
+        app.execute(&op(ycsb::Op::Read("a".into())));
+        let snapshot2 = app.checkpoint(5);
+        let Delta::Ycsb(delta) = &snapshot2.delta else {
+            unreachable!()
+        };
+        assert_eq!(delta.len(), 2);
+        let mut merged = snapshot1.clone();
+        merged.merge(&snapshot2);
+        let mut restored = replica.clone();
+        replica.apply(&snapshot1);
+        replica.apply(&snapshot2);
+        restored.apply(&merged);
+        let (App::Ycsb(app), App::Ycsb(replica), App::Ycsb(restored)) = (app, replica, restored)
+        else {
+            unreachable!()
+        };
+        assert_eq!(app, replica);
+        assert_eq!(app, restored)
+    }
+
+    #[test]
+    fn execution_index() {
+        let mut app = ExecutionIndex::new(App::Null, 2);
+        // not numbered
+        app.execute(&[]);
+        for op_num in 1..=2 {
+            app.set_op_num(op_num);
+            app.execute(&[]);
+        }
+        // the ops of a batch share the op number
+        for op_num in 3..=4 {
+            app.set_op_num(op_num);
+            for _ in 0..3 {
+                app.execute(&[]);
+            }
+        }
+        let entries = app.entries.clone();
+        let op_nums = || Vec::from_iter(entries.lock().unwrap().iter().map(|&(op_num, _)| op_num));
+        assert_eq!(op_nums(), [2, 4]);
+        // the ops after 3 are executed again
+        app.rollback(3);
+        assert_eq!(op_nums(), [2]);
+        app.set_op_num(4);
+        app.execute(&[]);
+        assert_eq!(op_nums(), [2, 4])
+    }
+
+    #[test]
+    fn ycsb_fields() {
+        let op = |op| bincode::options().serialize(&op).unwrap();
+        let result = |result: Vec<u8>| {
+            bincode::options()
+                .deserialize::<ycsb::Result>(&result)
+                .unwrap()
+        };
+        let config = ycsb::WorkloadConfig {
+            num_key: 1,
+            num_value: 1,
+            key_len: 8,
+            value_len: 8,
+            read_portion: 100,
+            update_portion: 0,
+            rmw_portion: 0,
+            scan_portion: 0,
+            insert_portion: 0,
+            max_scan_len: 0,
+            key_distribution: Default::default(),
+            num_field: 3,
+        };
+        let mut app = ycsb::Workload::app(config, &mut StdRng::seed_from_u64(0));
+        let ycsb::Result::ScanOk(values) = result(app.execute(&op(ycsb::Op::Scan("".into(), 1))))
+        else {
+            unreachable!()
+        };
+        assert_eq!(values[0].split(ycsb::FIELD_SEPARATOR).count(), 3);
+        app.execute(&op(ycsb::Op::Insert("k".into(), "a\x1fb\x1fc".into())));
+        let update = app.execute(&op(ycsb::Op::UpdateField("k".into(), 1, "x".into())));
+        assert_eq!(result(update), ycsb::Result::UpdateOk);
+        assert_eq!(
+            result(app.execute(&op(ycsb::Op::Read("k".into())))),
+            ycsb::Result::ReadOk("a\x1fx\x1fc".into())
+        );
+        assert_eq!(
+            result(app.execute(&op(ycsb::Op::ReadField("k".into(), 2)))),
+            ycsb::Result::ReadOk("c".into())
+        );
+        assert_eq!(
+            result(app.execute(&op(ycsb::Op::UpdateField("k".into(), 3, "x".into())))),
+            ycsb::Result::NotFound
+        );
+    }
+
+    #[test]
+    fn zipfian_skew() {
+        let zipfian = ycsb::Zipfian::new(1000, 0.99);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut counts = vec![0; 1000];
+        for _ in 0..100_000 {
+            counts[zipfian.sample(&mut rng)] += 1
+        }
+        // about 13% for the most popular of 1000 keys
+        assert!((10_000..16_000).contains(&counts[0]));
+        assert!(counts[0] > counts[1] && counts[1] > counts[10] && counts[10] > counts[999])
+    }
+
+    #[test]
+    #[should_panic(expected = "on the shadow app")]
+    fn self_check_diverged() {
+        let config = ycsb::WorkloadConfig {
+            num_key: 10,
+            num_value: 10,
+            key_len: 8,
+            value_len: 8,
+            read_portion: 100,
+            update_portion: 0,
+            rmw_portion: 0,
+            scan_portion: 0,
+            insert_portion: 0,
+            max_scan_len: 0,
+            key_distribution: Default::default(),
+            num_field: 0,
+        };
+        let new_app = |seed| {
+            App::Ycsb(ycsb::Workload::app(
+                config,
+                &mut StdRng::seed_from_u64(seed),
+            ))
+        };
+        // tables populated differently stand for an app that does not execute deterministically
+        let mut app = App::SelfCheck(Box::new(SelfCheck::new(
+            new_app(0),
+            new_app(1),
+            1.,
+            StdRng::seed_from_u64(0),
+        )));
+        let op = bincode::options()
+            .serialize(&ycsb::Op::Scan(Default::default(), 1))
+            .unwrap();
+        app.execute(&op);
+    }
+}
@@ -0,0 +1,335 @@
+// an ordered key-value store, which serves range queries besides the point ops, for evaluations on
+// a state machine closer to a real one than the YCSB table
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    iter::repeat_with,
+    ops::Bound,
+    pin::Pin,
+};
+
+use bincode::Options;
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::Client;
+
+use super::UndoLog;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Op {
+    Get(Vec<u8>),
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+    // the first `limit` entries from `start` in key order, up to `end` exclusively if set
+    Range {
+        start: Vec<u8>,
+        end: Option<Vec<u8>>,
+        limit: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Result {
+    Get(Option<Vec<u8>>),
+    Put,
+    // whether the key was there
+    Delete(bool),
+    Range(Vec<(Vec<u8>, Vec<u8>)>),
+    // the op does not decode, e.g. from a faulty client, and is executed as a no-op
+    Malformed,
+}
+
+// the encoding of ops and results on the wire, for clients to invoke with and decode replies of
+impl Op {
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::options().serialize(self).unwrap()
+    }
+}
+
+impl Result {
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        bincode::options().deserialize(buf).ok()
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        bincode::options().serialize(self).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct App {
+    table: BTreeMap<Vec<u8>, Vec<u8>>,
+    // keys written since the last checkpoint
+    #[serde(skip)]
+    dirty: BTreeSet<Vec<u8>>,
+    #[serde(skip)]
+    undo: UndoLog<Vec<u8>, Vec<u8>>,
+}
+
+// the written keys since the previous checkpoint, to their values or none if deleted
+pub type Delta = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+impl App {
+    // none if `op` writes
+    fn read(&self, op: &Op) -> Option<Result> {
+        let result = match op {
+            Op::Get(key) => Result::Get(self.table.get(key).cloned()),
+            Op::Range { start, end, limit } => {
+                let end = match end {
+                    Some(end) if end <= start => return Some(Result::Range(Default::default())),
+                    Some(end) => Bound::Excluded(end.clone()),
+                    None => Bound::Unbounded,
+                };
+                let entries = self
+                    .table
+                    .range((Bound::Included(start.clone()), end))
+                    .take(*limit)
+                    .map(|(key, value)| (key.clone(), value.clone()));
+                Result::Range(entries.collect())
+            }
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    // none for a malformed op as well, as it comes from an unordered client message
+    pub fn execute_read(&self, op: &[u8]) -> Option<Vec<u8>> {
+        let op = bincode::options()
+            .allow_trailing_bytes()
+            .deserialize(op)
+            .ok()?;
+        Some(self.read(&op)?.encode())
+    }
+
+    pub fn execute(&mut self, op: &[u8]) -> Vec<u8> {
+        let Ok(op) = bincode::options().allow_trailing_bytes().deserialize(op) else {
+            return Result::Malformed.encode();
+        };
+        if let Some(result) = self.read(&op) {
+            return result.encode();
+        }
+        let Self { table, dirty, undo } = self;
+        let result = match op {
+            Op::Get(_) | Op::Range { .. } => unreachable!(),
+            Op::Put(key, value) => {
+                undo.record(table, &key);
+                table.insert(key.clone(), value);
+                dirty.insert(key);
+                Result::Put
+            }
+            Op::Delete(key) => {
+                let deleted = table.contains_key(&key);
+                if deleted {
+                    undo.record(table, &key);
+                    table.remove(&key);
+                    dirty.insert(key);
+                }
+                Result::Delete(deleted)
+            }
+        };
+        result.encode()
+    }
+
+    // copies only the written entries, see `ycsb::App::checkpoint`
+    pub fn checkpoint(&mut self, op_num: u64) -> Delta {
+        self.undo.checkpoint(op_num);
+        Delta::from_iter(
+            std::mem::take(&mut self.dirty)
+                .into_iter()
+                .map(|key| (key.clone(), self.table.get(&key).cloned())),
+        )
+    }
+
+    pub fn apply(&mut self, delta: &Delta) {
+        for (key, value) in delta {
+            match value {
+                Some(value) => self.table.insert(key.clone(), value.clone()),
+                None => self.table.remove(key),
+            };
+        }
+    }
+
+    // see `super::App::speculate`
+    pub fn speculate(&mut self, op_num: u64) {
+        self.undo.speculate(op_num)
+    }
+
+    pub fn rollback(&mut self, op_num: u64) {
+        self.undo.rollback(op_num, &mut self.table, &mut self.dirty)
+    }
+
+    pub fn truncate_undo(&mut self, op_num: u64) {
+        self.undo.truncate(op_num)
+    }
+
+    // the whole state, to transfer to a replica behind the truncated log, see `super::App::snapshot`
+    pub fn snapshot(&self) -> Vec<u8> {
+        bincode::options().serialize(&self.table).unwrap()
+    }
+
+    pub fn restore(snapshot: &[u8]) -> Option<Self> {
+        Some(Self {
+            table: bincode::options().deserialize(snapshot).ok()?,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WorkloadConfig {
+    pub num_key: usize,
+    pub key_len: usize,
+    pub value_len: usize,
+    pub get_portion: u32,
+    pub put_portion: u32,
+    pub delete_portion: u32,
+    pub range_portion: u32,
+    pub max_range_len: usize,
+}
+
+impl From<control_messages::KvStoreConfig> for WorkloadConfig {
+    fn from(value: control_messages::KvStoreConfig) -> Self {
+        let control_messages::KvStoreConfig {
+            num_key,
+            key_len,
+            value_len,
+            get_portion,
+            put_portion,
+            delete_portion,
+            range_portion,
+            max_range_len,
+        } = value;
+        Self {
+            num_key,
+            key_len,
+            value_len,
+            get_portion,
+            put_portion,
+            delete_portion,
+            range_portion,
+            max_range_len,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Workload {
+    keys: Vec<Vec<u8>>,
+    config: WorkloadConfig,
+}
+
+impl Workload {
+    fn iter_bytes(rng: &mut impl Rng, len: usize) -> impl Iterator<Item = Vec<u8>> + '_ {
+        repeat_with(move || Vec::from_iter(repeat_with(|| rng.gen()).take(len)))
+    }
+
+    // the keys are the same as of `new` with the same rng
+    pub fn app(config: WorkloadConfig, rng: &mut impl Rng) -> App {
+        let keys = Vec::from_iter(Self::iter_bytes(rng, config.key_len).take(config.num_key));
+        let table = keys
+            .into_iter()
+            .zip(Self::iter_bytes(rng, config.value_len))
+            .collect();
+        App {
+            table,
+            ..Default::default()
+        }
+    }
+
+    pub fn new(config: WorkloadConfig, rng: &mut impl Rng) -> Self {
+        assert_eq!(
+            config.get_portion + config.put_portion + config.delete_portion + config.range_portion,
+            100
+        );
+        assert!(config.range_portion == 0 || config.max_range_len > 0);
+        Self {
+            keys: Self::iter_bytes(rng, config.key_len)
+                .take(config.num_key)
+                .collect(),
+            config,
+        }
+    }
+
+    pub fn generate(
+        &self,
+        client: impl Client + Send + Sync + 'static,
+        rng: &mut impl Rng,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + Sync>> {
+        let key = self.keys.choose(rng).unwrap().clone();
+        let txn_type = rng.gen_range(0..100);
+        let config = &self.config;
+        let op = if txn_type < config.get_portion {
+            Op::Get(key)
+        } else if txn_type < config.get_portion + config.put_portion {
+            Op::Put(key, Self::iter_bytes(rng, config.value_len).next().unwrap())
+        } else if txn_type < config.get_portion + config.put_portion + config.delete_portion {
+            Op::Delete(key)
+        } else {
+            Op::Range {
+                start: key,
+                end: None,
+                limit: rng.gen_range(1..=config.max_range_len),
+            }
+        };
+        Box::pin(async move {
+            let finish = CancellationToken::new();
+            client.invoke(op.encode(), {
+                let finish = finish.clone();
+                move |result: Vec<u8>| {
+                    assert!(!matches!(
+                        Result::decode(&result),
+                        None | Some(Result::Malformed)
+                    ));
+                    finish.cancel()
+                }
+            });
+            finish.cancelled().await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_and_snapshot() {
+        let mut app = App::default();
+        let mut execute = |op: Op| Result::decode(&app.execute(&op.encode())).unwrap();
+        for key in [b"a", b"b", b"c", b"d"] {
+            assert_eq!(execute(Op::Put(key.to_vec(), key.to_vec())), Result::Put);
+        }
+        assert_eq!(execute(Op::Delete(b"c".to_vec())), Result::Delete(true));
+        assert_eq!(execute(Op::Delete(b"c".to_vec())), Result::Delete(false));
+        let keys = |result| {
+            let Result::Range(entries) = result else {
+                unreachable!()
+            };
+            Vec::from_iter(entries.into_iter().map(|(key, _)| key))
+        };
+        let range = |start: &[u8], end: Option<&[u8]>, limit| Op::Range {
+            start: start.to_vec(),
+            end: end.map(<[u8]>::to_vec),
+            limit,
+        };
+        assert_eq!(keys(execute(range(b"b", None, 10))), [b"b", b"d"]);
+        assert_eq!(keys(execute(range(b"a", Some(b"d"), 10))), [b"a", b"b"]);
+        assert_eq!(keys(execute(range(b"a", None, 1))), [b"a"]);
+        assert!(keys(execute(range(b"d", Some(b"a"), 10))).is_empty());
+        assert_eq!(
+            Result::decode(&app.execute(b"\xff")).unwrap(),
+            Result::Malformed
+        );
+
+        let delta = app.checkpoint(6);
+        assert_eq!(delta.get(b"c".as_slice()), Some(&None));
+        let mut replica = App::default();
+        replica.apply(&delta);
+        assert_eq!(replica, app);
+        assert_eq!(App::restore(&app.snapshot()).unwrap(), app);
+        assert!(app.checkpoint(6).is_empty())
+    }
+}
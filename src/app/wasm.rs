@@ -0,0 +1,420 @@
+// executes ops as calls into a WebAssembly module, i.e. user-defined transactions of a contract, over
+// a key-value store that the host keeps and checkpoints
+// every op runs on a fresh instance of the module, so all state lives in the store, and replicas
+// stay in step as long as the module is deterministic, which WebAssembly is except for floats NaN
+// bits. every op runs with bounded fuel, so a looping export fails instead of hanging the replica
+//
+// a module exports `memory` and the functions that ops call, which take and return nothing, and
+// imports from `env`:
+//   input_len() -> i32                    length of the argument of the op
+//   input_read(ptr: i32)                  copy the argument to `ptr`
+//   output_write(ptr: i32, len: i32)      set the result of the op
+//   kv_get(key_ptr, key_len, value_ptr, value_cap: i32) -> i32
+//                                         length of the value, -1 if not found, which is copied to
+//                                         `value_ptr` only if it fits in `value_cap`
+//   kv_put(key_ptr, key_len, value_ptr, value_len: i32)
+//   kv_delete(key_ptr, key_len: i32) -> i32
+//                                         1 if the key was there, 0 otherwise
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    iter::repeat_with,
+    pin::Pin,
+    sync::Arc,
+};
+
+use bincode::Options;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use wasmi::{Caller, Engine, Extern, Linker, Module, Store};
+
+use crate::Client;
+
+use super::UndoLog;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Op {
+    pub function: String,
+    pub arg: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Result {
+    Ok(Vec<u8>),
+    // the function is not exported, or traps e.g. by running out of fuel, in which case its writes
+    // are discarded
+    Failed,
+    // the op does not decode, e.g. from a faulty client, and is executed as a no-op
+    Malformed,
+}
+
+impl Op {
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::options().serialize(self).unwrap()
+    }
+}
+
+impl Result {
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        bincode::options().deserialize(buf).ok()
+    }
+}
+
+// of every op, if the task does not set any
+pub const DEFAULT_FUEL: u64 = 10_000_000;
+
+#[derive(Debug, Clone)]
+pub struct App {
+    engine: Engine,
+    module: Arc<Module>,
+    // with the host functions, built once and instantiated for every op
+    linker: Arc<Linker<Host>>,
+    // of every op
+    fuel: u64,
+    table: BTreeMap<Vec<u8>, Vec<u8>>,
+    // keys written since the last checkpoint
+    dirty: BTreeSet<Vec<u8>>,
+    undo: UndoLog<Vec<u8>, Vec<u8>>,
+}
+
+// the written keys since the previous checkpoint, to their values or none if deleted
+pub type Delta = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+// the state of an instance during an op, which holds the table for the while, where writes are
+// buffered so a trapped op leaves the table untouched
+#[derive(Debug)]
+struct Host {
+    table: BTreeMap<Vec<u8>, Vec<u8>>,
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    input: Vec<u8>,
+    output: Vec<u8>,
+}
+
+impl Host {
+    fn get(&self, key: &[u8]) -> Option<&Vec<u8>> {
+        match self.writes.get(key) {
+            Some(value) => value.as_ref(),
+            None => self.table.get(key),
+        }
+    }
+}
+
+fn memory(caller: &Caller<'_, Host>) -> std::result::Result<wasmi::Memory, wasmi::Error> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or(wasmi::Error::new("no exported memory"))
+}
+
+fn read(
+    caller: &Caller<'_, Host>,
+    ptr: i32,
+    len: i32,
+) -> std::result::Result<Vec<u8>, wasmi::Error> {
+    let memory = memory(caller)?;
+    // the guest controls `len`, so nothing is allocated before it is checked against the memory
+    let start = ptr as u32 as usize;
+    let end = start.checked_add(len as u32 as usize);
+    end.and_then(|end| memory.data(caller).get(start..end))
+        .map(<[u8]>::to_vec)
+        .ok_or(wasmi::Error::new("out of bounds memory access"))
+}
+
+impl App {
+    pub fn new(wasm: &[u8], fuel: u64) -> std::result::Result<Self, wasmi::Error> {
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm)?;
+        Ok(Self {
+            linker: Arc::new(Self::linker(&engine)),
+            engine,
+            module: Arc::new(module),
+            fuel,
+            table: Default::default(),
+            dirty: Default::default(),
+            undo: Default::default(),
+        })
+    }
+
+    fn linker(engine: &Engine) -> Linker<Host> {
+        let mut linker = Linker::new(engine);
+        linker
+            .func_wrap("env", "input_len", |caller: Caller<'_, Host>| {
+                caller.data().input.len() as i32
+            })
+            .unwrap()
+            .func_wrap(
+                "env",
+                "input_read",
+                |mut caller: Caller<'_, Host>, ptr: i32| {
+                    let input = std::mem::take(&mut caller.data_mut().input);
+                    let result = memory(&caller)?.write(&mut caller, ptr as u32 as usize, &input);
+                    caller.data_mut().input = input;
+                    Ok(result?)
+                },
+            )
+            .unwrap()
+            .func_wrap(
+                "env",
+                "output_write",
+                |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+                    caller.data_mut().output = read(&caller, ptr, len)?;
+                    Ok(())
+                },
+            )
+            .unwrap()
+            .func_wrap(
+                "env",
+                "kv_get",
+                |mut caller: Caller<'_, Host>,
+                 key_ptr: i32,
+                 key_len: i32,
+                 value_ptr: i32,
+                 value_cap: i32| {
+                    let key = read(&caller, key_ptr, key_len)?;
+                    let Some(value) = caller.data().get(&key).cloned() else {
+                        return Ok(-1);
+                    };
+                    if value.len() <= value_cap as u32 as usize {
+                        memory(&caller)?.write(&mut caller, value_ptr as u32 as usize, &value)?
+                    }
+                    Ok(value.len() as i32)
+                },
+            )
+            .unwrap()
+            .func_wrap(
+                "env",
+                "kv_put",
+                |mut caller: Caller<'_, Host>,
+                 key_ptr: i32,
+                 key_len: i32,
+                 value_ptr: i32,
+                 value_len: i32| {
+                    let key = read(&caller, key_ptr, key_len)?;
+                    let value = read(&caller, value_ptr, value_len)?;
+                    caller.data_mut().writes.insert(key, Some(value));
+                    Ok(())
+                },
+            )
+            .unwrap()
+            .func_wrap(
+                "env",
+                "kv_delete",
+                |mut caller: Caller<'_, Host>, key_ptr: i32, key_len: i32| {
+                    let key = read(&caller, key_ptr, key_len)?;
+                    let found = caller.data().get(&key).is_some();
+                    caller.data_mut().writes.insert(key, None);
+                    Ok(found as i32)
+                },
+            )
+            .unwrap();
+        linker
+    }
+
+    pub fn execute(&mut self, op: &[u8]) -> Vec<u8> {
+        let Ok(op) = bincode::options()
+            .allow_trailing_bytes()
+            .deserialize::<Op>(op)
+        else {
+            return bincode::options().serialize(&Result::Malformed).unwrap();
+        };
+        let mut store = Store::new(
+            &self.engine,
+            Host {
+                table: std::mem::take(&mut self.table),
+                writes: Default::default(),
+                input: op.arg,
+                output: Default::default(),
+            },
+        );
+        store.set_fuel(self.fuel).unwrap();
+        let called = self
+            .linker
+            .instantiate(&mut store, &self.module)
+            .and_then(|instance| instance.start(&mut store))
+            .and_then(|instance| {
+                instance
+                    .get_typed_func::<(), ()>(&store, &op.function)?
+                    .call(&mut store, ())
+            });
+        let Host {
+            table,
+            writes,
+            output,
+            ..
+        } = store.into_data();
+        self.table = table;
+        let result = if called.is_ok() {
+            for (key, value) in writes {
+                self.undo.record(&self.table, &key);
+                match value {
+                    Some(value) => self.table.insert(key.clone(), value),
+                    None => self.table.remove(&key),
+                };
+                self.dirty.insert(key);
+            }
+            Result::Ok(output)
+        } else {
+            Result::Failed
+        };
+        bincode::options().serialize(&result).unwrap()
+    }
+
+    // copies only the written entries, see `ycsb::App::checkpoint`
+    pub fn checkpoint(&mut self, op_num: u64) -> Delta {
+        self.undo.checkpoint(op_num);
+        Delta::from_iter(
+            std::mem::take(&mut self.dirty)
+                .into_iter()
+                .map(|key| (key.clone(), self.table.get(&key).cloned())),
+        )
+    }
+
+    pub fn apply(&mut self, delta: &Delta) {
+        for (key, value) in delta {
+            match value {
+                Some(value) => self.table.insert(key.clone(), value.clone()),
+                None => self.table.remove(key),
+            };
+        }
+    }
+
+    // see `super::App::speculate`
+    pub fn speculate(&mut self, op_num: u64) {
+        self.undo.speculate(op_num)
+    }
+
+    pub fn rollback(&mut self, op_num: u64) {
+        self.undo.rollback(op_num, &mut self.table, &mut self.dirty)
+    }
+
+    pub fn truncate_undo(&mut self, op_num: u64) {
+        self.undo.truncate(op_num)
+    }
+
+    pub fn snapshot(&self) -> Vec<u8> {
+        bincode::options().serialize(&self.table).unwrap()
+    }
+
+    // in place, since the compiled module is not part of the state
+    pub fn restore(&mut self, snapshot: &[u8]) -> bool {
+        let Ok(table) = bincode::options().deserialize(snapshot) else {
+            return false;
+        };
+        self.table = table;
+        self.dirty.clear();
+        self.undo = Default::default();
+        true
+    }
+}
+
+#[derive(Debug)]
+pub struct Workload {
+    function: String,
+    arg_len: usize,
+}
+
+impl Workload {
+    pub fn new(config: &control_messages::WasmConfig) -> Self {
+        Self {
+            function: config.function.clone(),
+            arg_len: config.arg_len,
+        }
+    }
+
+    // every op calls the same function with a random argument
+    pub fn generate(
+        &self,
+        client: impl Client + Send + Sync + 'static,
+        rng: &mut impl Rng,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + Sync>> {
+        let op = Op {
+            function: self.function.clone(),
+            arg: Vec::from_iter(repeat_with(|| rng.gen()).take(self.arg_len)),
+        };
+        Box::pin(async move {
+            let finish = CancellationToken::new();
+            client.invoke(op.encode(), {
+                let finish = finish.clone();
+                move |result: Vec<u8>| {
+                    assert!(matches!(Result::decode(&result), Some(Result::Ok(_))));
+                    finish.cancel()
+                }
+            });
+            finish.cancelled().await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `put` stores its argument under key "k", `get` returns the value of "k", `put_trap` and `spin`
+    // fail after writing, and `output_huge` sets a result far beyond the memory
+    const MODULE: &str = r#"
+        (module
+            (import "env" "input_len" (func $input_len (result i32)))
+            (import "env" "input_read" (func $input_read (param i32)))
+            (import "env" "output_write" (func $output_write (param i32 i32)))
+            (import "env" "kv_get" (func $kv_get (param i32 i32 i32 i32) (result i32)))
+            (import "env" "kv_put" (func $kv_put (param i32 i32 i32 i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "k")
+            (func (export "put")
+                (call $input_read (i32.const 16))
+                (call $kv_put (i32.const 0) (i32.const 1) (i32.const 16) (call $input_len)))
+            (func (export "get")
+                (call $output_write
+                    (i32.const 16)
+                    (call $kv_get (i32.const 0) (i32.const 1) (i32.const 16) (i32.const 1024))))
+            (func (export "put_trap")
+                (call $kv_put (i32.const 0) (i32.const 1) (i32.const 0) (i32.const 1))
+                unreachable)
+            (func (export "spin")
+                (call $kv_put (i32.const 0) (i32.const 1) (i32.const 0) (i32.const 1))
+                (loop br 0))
+            (func (export "output_huge")
+                (call $output_write (i32.const 0) (i32.const -1))))
+    "#;
+
+    #[test]
+    fn contract_calls() {
+        let mut app = App::new(&wat::parse_str(MODULE).unwrap(), 10_000).unwrap();
+        let mut call = |function: &str, arg: &[u8]| {
+            let op = Op {
+                function: function.into(),
+                arg: arg.to_vec(),
+            };
+            Result::decode(&app.execute(&op.encode())).unwrap()
+        };
+        assert_eq!(call("put", b"hello"), Result::Ok(Default::default()));
+        assert_eq!(call("get", b""), Result::Ok(b"hello".to_vec()));
+        // writes of failed ops are discarded
+        assert_eq!(call("put_trap", b""), Result::Failed);
+        assert_eq!(call("spin", b""), Result::Failed);
+        assert_eq!(call("output_huge", b""), Result::Failed);
+        assert_eq!(call("missing", b""), Result::Failed);
+        assert_eq!(call("get", b""), Result::Ok(b"hello".to_vec()));
+        assert_eq!(
+            Result::decode(&app.execute(b"\xff")),
+            Some(Result::Malformed)
+        );
+
+        let delta = app.checkpoint(7);
+        assert_eq!(delta.len(), 1);
+        let mut replica = App::new(&wat::parse_str(MODULE).unwrap(), DEFAULT_FUEL).unwrap();
+        replica.apply(&delta);
+        let op = Op {
+            function: "get".into(),
+            arg: Default::default(),
+        };
+        assert_eq!(
+            Result::decode(&replica.execute(&op.encode())),
+            Some(Result::Ok(b"hello".to_vec()))
+        )
+    }
+}
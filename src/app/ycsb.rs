@@ -1,12 +1,21 @@
-use std::{collections::BTreeMap, future::Future, iter::repeat_with, pin::Pin};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    iter::repeat_with,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
 
 use bincode::Options;
+use control_messages::KeyDistribution;
 use rand::{distributions::Alphanumeric, seq::SliceRandom, Rng};
 use serde::{Deserialize, Serialize};
 use tokio_util::sync::CancellationToken;
 
 use crate::Client;
 
+use super::UndoLog;
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Op {
     Read(String),
@@ -14,8 +23,14 @@ pub enum Op {
     Update(String, String),
     Insert(String, String),
     Delete(String),
+    // of the records that consist of fields, see `FIELD_SEPARATOR`
+    ReadField(String, usize),
+    UpdateField(String, usize, String),
 }
 
+// between the fields of a record in its value, which the alphanumeric values never contain
+pub const FIELD_SEPARATOR: char = '\x1f';
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Result {
     ReadOk(String),
@@ -28,53 +43,162 @@ pub enum Result {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
-pub struct App(BTreeMap<String, String>);
+pub struct App {
+    table: BTreeMap<String, String>,
+    // keys written since the last checkpoint
+    #[serde(skip)]
+    dirty: BTreeSet<String>,
+    #[serde(skip)]
+    undo: UndoLog<String, String>,
+}
+
+// the written keys since the previous checkpoint, to their values or none if deleted
+pub type Delta = BTreeMap<String, Option<String>>;
+
 impl App {
-    pub fn execute(&mut self, op: &[u8]) -> Vec<u8> {
-        let Self(table) = self;
-        let result = match bincode::options()
-            .allow_trailing_bytes()
-            .deserialize(op)
-            .unwrap()
-        {
+    // none if `op` writes
+    fn read(&self, op: &Op) -> Option<Result> {
+        let result = match op {
             Op::Read(key) => {
-                if let Some(value) = table.get(&key).cloned() {
+                if let Some(value) = self.table.get(key).cloned() {
                     Result::ReadOk(value)
                 } else {
                     Result::NotFound
                 }
             }
             Op::Scan(key, count) => {
-                let values = table
-                    .range(key..)
+                let values = self
+                    .table
+                    .range(key.clone()..)
                     .map(|(_, value)| value.clone())
-                    .take(count)
+                    .take(*count)
                     .collect();
                 Result::ScanOk(values)
             }
+            Op::ReadField(key, field) => {
+                match self
+                    .table
+                    .get(key)
+                    .and_then(|value| value.split(FIELD_SEPARATOR).nth(*field))
+                {
+                    Some(value) => Result::ReadOk(value.into()),
+                    None => Result::NotFound,
+                }
+            }
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    // none for a malformed op as well, as it comes from an unordered client message
+    pub fn execute_read(&self, op: &[u8]) -> Option<Vec<u8>> {
+        let op = bincode::options()
+            .allow_trailing_bytes()
+            .deserialize(op)
+            .ok()?;
+        let result = self.read(&op)?;
+        Some(bincode::options().serialize(&result).unwrap())
+    }
+
+    pub fn execute(&mut self, op: &[u8]) -> Vec<u8> {
+        let op = bincode::options()
+            .allow_trailing_bytes()
+            .deserialize(op)
+            .unwrap();
+        if let Some(result) = self.read(&op) {
+            return bincode::options().serialize(&result).unwrap();
+        }
+        let Self { table, dirty, undo } = self;
+        let result = match op {
+            Op::Read(_) | Op::Scan(..) | Op::ReadField(..) => unreachable!(),
             Op::Update(key, value) => {
-                if let Some(value_mut) = table.get_mut(&key) {
-                    *value_mut = value;
+                if table.contains_key(&key) {
+                    undo.record(table, &key);
+                    table.insert(key.clone(), value);
+                    dirty.insert(key);
                     Result::UpdateOk
                 } else {
                     Result::NotFound
                 }
             }
             Op::Insert(key, value) => {
-                table.insert(key, value); // check for override?
+                undo.record(table, &key);
+                table.insert(key.clone(), value); // check for override?
+                dirty.insert(key);
                 Result::InsertOk
             }
             Op::Delete(key) => {
-                if table.remove(&key).is_some() {
+                if table.contains_key(&key) {
+                    undo.record(table, &key);
+                    table.remove(&key);
+                    dirty.insert(key);
                     Result::DeleteOk
                 } else {
                     Result::NotFound
                 }
             }
+            Op::UpdateField(key, field, value) => {
+                let Some(record) = table.get(&key) else {
+                    return bincode::options().serialize(&Result::NotFound).unwrap();
+                };
+                let mut fields = Vec::from_iter(record.split(FIELD_SEPARATOR));
+                let Some(field_mut) = fields.get_mut(field) else {
+                    return bincode::options().serialize(&Result::NotFound).unwrap();
+                };
+                *field_mut = &value;
+                let record = fields.join(&FIELD_SEPARATOR.to_string());
+                undo.record(table, &key);
+                table.insert(key.clone(), record);
+                dirty.insert(key);
+                Result::UpdateOk
+            }
         };
-        assert_ne!(result, Result::NotFound);
         bincode::options().serialize(&result).unwrap()
     }
+
+    // copies only the written entries, so it takes as long as the ops since the last checkpoint
+    // rather than the whole table
+    pub fn checkpoint(&mut self, op_num: u64) -> Delta {
+        self.undo.checkpoint(op_num);
+        Delta::from_iter(
+            std::mem::take(&mut self.dirty)
+                .into_iter()
+                .map(|key| (key.clone(), self.table.get(&key).cloned())),
+        )
+    }
+
+    pub fn apply(&mut self, delta: &Delta) {
+        for (key, value) in delta {
+            match value {
+                Some(value) => self.table.insert(key.clone(), value.clone()),
+                None => self.table.remove(key),
+            };
+        }
+    }
+
+    // see `super::App::speculate`
+    pub fn speculate(&mut self, op_num: u64) {
+        self.undo.speculate(op_num)
+    }
+
+    pub fn rollback(&mut self, op_num: u64) {
+        self.undo.rollback(op_num, &mut self.table, &mut self.dirty)
+    }
+
+    pub fn truncate_undo(&mut self, op_num: u64) {
+        self.undo.truncate(op_num)
+    }
+
+    pub fn snapshot(&self) -> Vec<u8> {
+        bincode::options().serialize(&self.table).unwrap()
+    }
+
+    pub fn restore(snapshot: &[u8]) -> Option<Self> {
+        Some(Self {
+            table: bincode::options().deserialize(snapshot).ok()?,
+            ..Default::default()
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -83,7 +207,57 @@ pub struct Workload {
     values: Vec<String>,
     read_portion: u32,
     update_portion: u32,
-    // rmw_portion: u32,
+    rmw_portion: u32,
+    scan_portion: u32,
+    // the rest inserts
+    max_scan_len: usize,
+    key_len: usize,
+    value_len: usize,
+    num_field: usize,
+    key_distribution: KeyDistribution,
+    zipfian: Option<Zipfian>,
+    // the keys that this host's clients have inserted, in the order that the inserts complete, so
+    // they are in the table when chosen
+    inserted: Arc<Mutex<Vec<String>>>,
+}
+
+// the generator of YCSB after Gray et al., "Quickly generating billion-record synthetic databases",
+// where the item of rank 0 is the most popular
+#[derive(Debug, Clone)]
+pub struct Zipfian {
+    num_item: usize,
+    theta: f64,
+    alpha: f64,
+    zeta_n: f64,
+    eta: f64,
+}
+
+impl Zipfian {
+    pub fn new(num_item: usize, theta: f64) -> Self {
+        assert!(theta > 0. && theta < 1.);
+        let zeta = |n: usize| (1..=n).map(|i| 1. / (i as f64).powf(theta)).sum::<f64>();
+        let zeta_n = zeta(num_item);
+        Self {
+            num_item,
+            theta,
+            alpha: 1. / (1. - theta),
+            zeta_n,
+            eta: (1. - (2. / num_item as f64).powf(1. - theta)) / (1. - zeta(2) / zeta_n),
+        }
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> usize {
+        let u = rng.gen::<f64>();
+        let uz = u * self.zeta_n;
+        if uz < 1. {
+            return 0;
+        }
+        if uz < 1. + 0.5f64.powf(self.theta) {
+            return 1;
+        }
+        let rank = self.num_item as f64 * (self.eta * u - self.eta + 1.).powf(self.alpha);
+        (rank as usize).min(self.num_item - 1)
+    }
 }
 
 impl Workload {
@@ -96,13 +270,21 @@ impl Workload {
         })
     }
 
+    fn record(rng: &mut impl Rng, value_len: usize, num_field: usize) -> String {
+        let fields = Vec::from_iter(Self::iter_strings(rng, value_len).take(num_field.max(1)));
+        fields.join(&FIELD_SEPARATOR.to_string())
+    }
+
     pub fn app(config: WorkloadConfig, rng: &mut impl Rng) -> App {
         let keys = Vec::from_iter(Self::iter_strings(rng, config.key_len).take(config.num_key));
         let entries = keys
             .into_iter()
-            .zip(Self::iter_strings(rng, config.value_len))
+            .map(|key| (key, Self::record(rng, config.value_len, config.num_field)))
             .collect();
-        App(entries)
+        App {
+            table: entries,
+            ..Default::default()
+        }
     }
 }
 
@@ -115,6 +297,11 @@ pub struct WorkloadConfig {
     pub read_portion: u32,
     pub update_portion: u32,
     pub rmw_portion: u32,
+    pub scan_portion: u32,
+    pub insert_portion: u32,
+    pub max_scan_len: usize,
+    pub key_distribution: KeyDistribution,
+    pub num_field: usize,
 }
 
 impl From<control_messages::YcsbConfig> for WorkloadConfig {
@@ -127,6 +314,11 @@ impl From<control_messages::YcsbConfig> for WorkloadConfig {
             read_portion,
             update_portion,
             rmw_portion,
+            scan_portion,
+            insert_portion,
+            max_scan_len,
+            key_distribution,
+            num_field,
         } = value;
         Self {
             num_key,
@@ -136,6 +328,11 @@ impl From<control_messages::YcsbConfig> for WorkloadConfig {
             read_portion,
             update_portion,
             rmw_portion,
+            scan_portion,
+            insert_portion,
+            max_scan_len,
+            key_distribution,
+            num_field,
         }
     }
 }
@@ -149,15 +346,60 @@ impl Workload {
             .take(config.num_value)
             .collect();
         assert_eq!(
-            config.read_portion + config.update_portion + config.rmw_portion,
+            config.read_portion
+                + config.update_portion
+                + config.rmw_portion
+                + config.scan_portion
+                + config.insert_portion,
             100
         );
+        assert!(config.scan_portion == 0 || config.max_scan_len > 0);
         Self {
             keys,
             values,
             read_portion: config.read_portion,
             update_portion: config.update_portion,
-            // rmw_portion,
+            rmw_portion: config.rmw_portion,
+            scan_portion: config.scan_portion,
+            max_scan_len: config.max_scan_len,
+            key_len: config.key_len,
+            value_len: config.value_len,
+            num_field: config.num_field,
+            key_distribution: config.key_distribution,
+            zipfian: match config.key_distribution {
+                KeyDistribution::Uniform => None,
+                KeyDistribution::Zipfian(theta) | KeyDistribution::Latest(theta) => {
+                    Some(Zipfian::new(config.num_key, theta))
+                }
+            },
+            inserted: Default::default(),
+        }
+    }
+
+    // among the preloaded keys followed by the inserted ones
+    fn choose_key(&self, rng: &mut impl Rng) -> String {
+        let inserted = self.inserted.lock().unwrap();
+        let num_key = self.keys.len() + inserted.len();
+        let index = match (self.key_distribution, &self.zipfian) {
+            (KeyDistribution::Zipfian(_), Some(zipfian)) => zipfian.sample(rng),
+            // ranked backward from the latest inserted key, over no more than the preloaded number
+            // of keys
+            (KeyDistribution::Latest(_), Some(zipfian)) => num_key - 1 - zipfian.sample(rng),
+            _ => rng.gen_range(0..num_key),
+        };
+        match index.checked_sub(self.keys.len()) {
+            Some(index) => inserted[index].clone(),
+            None => self.keys[index].clone(),
+        }
+    }
+
+    // the whole value, or a random field of it
+    fn update(&self, key: String, rng: &mut impl Rng) -> Op {
+        let value = self.values.choose(rng).unwrap().clone();
+        if self.num_field == 0 {
+            Op::Update(key, value)
+        } else {
+            Op::UpdateField(key, rng.gen_range(0..self.num_field), value)
         }
     }
 
@@ -166,35 +408,65 @@ impl Workload {
         client: impl Client + Send + Sync + 'static,
         rng: &mut impl Rng,
     ) -> Pin<Box<dyn Future<Output = ()> + Send + Sync>> {
-        async fn invoke(client: &(impl Client + Send + Sync), op: Vec<u8>) {
+        // reads and scans take the protocol's read path if it has one, while the read of a
+        // read-modify-write is ordered along with its update
+        async fn invoke(client: &(impl Client + Send + Sync), op: Vec<u8>, read_only: bool) {
             let finish = CancellationToken::new();
-            client.invoke(op, {
+            let consume = {
                 let finish = finish.clone();
-                move |_| finish.cancel()
-            });
+                move |result: Vec<u8>| {
+                    // the workload only touches the preloaded keys and the completely inserted ones
+                    let result = bincode::options().deserialize::<Result>(&result).unwrap();
+                    assert_ne!(result, Result::NotFound);
+                    finish.cancel()
+                }
+            };
+            if read_only {
+                client.invoke_read(op, consume)
+            } else {
+                client.invoke(op, consume)
+            }
             finish.cancelled().await
         }
         let serialize = |op| bincode::options().serialize(&op).unwrap();
 
-        let txn_type = rng.gen_range(0..100);
-        if txn_type < self.read_portion {
-            // TODO zipf distribution
-            let op = serialize(Op::Read(self.keys.choose(rng).unwrap().clone()));
-            Box::pin(async move { invoke(&client, op).await })
-        } else if txn_type < self.read_portion + self.update_portion {
-            let op = serialize(Op::Update(
-                self.keys.choose(rng).unwrap().clone(),
-                self.values.choose(rng).unwrap().clone(),
+        let mut txn_type = rng.gen_range(0..100u32);
+        let mut next_portion = |portion| {
+            let chosen = txn_type < portion;
+            txn_type = txn_type.saturating_sub(portion);
+            chosen
+        };
+        if next_portion(self.read_portion) {
+            let op = serialize(Op::Read(self.choose_key(rng)));
+            Box::pin(async move { invoke(&client, op, true).await })
+        } else if next_portion(self.update_portion) {
+            let op = serialize(self.update(self.choose_key(rng), rng));
+            Box::pin(async move { invoke(&client, op, false).await })
+        } else if next_portion(self.rmw_portion) {
+            let key = self.choose_key(rng);
+            let op1 = serialize(Op::Read(key.clone()));
+            let op2 = serialize(self.update(key, rng));
+            Box::pin(async move {
+                invoke(&client, op1, false).await;
+                invoke(&client, op2, false).await
+            })
+        } else if next_portion(self.scan_portion) {
+            let op = serialize(Op::Scan(
+                self.choose_key(rng),
+                rng.gen_range(1..=self.max_scan_len),
             ));
-            Box::pin(async move { invoke(&client, op).await })
+            Box::pin(async move { invoke(&client, op, true).await })
         } else {
-            let key = self.keys.choose(rng).unwrap();
-            let value = self.values.choose(rng).unwrap();
-            let op1 = serialize(Op::Read(key.clone()));
-            let op2 = serialize(Op::Update(key.clone(), value.clone()));
+            // a fresh key, which the other ops choose once the insert completes
+            let key = Self::iter_strings(rng, self.key_len).next().unwrap();
+            let op = serialize(Op::Insert(
+                key.clone(),
+                Self::record(rng, self.value_len, self.num_field),
+            ));
+            let inserted = self.inserted.clone();
             Box::pin(async move {
-                invoke(&client, op1).await;
-                invoke(&client, op2).await
+                invoke(&client, op, false).await;
+                inserted.lock().unwrap().push(key)
             })
         }
     }
@@ -0,0 +1,20 @@
+use std::{env::args, path::Path, process::exit};
+
+use permissioned_blockchain::context::keys;
+
+fn main() {
+    control_messages::build_info!().exit_on_version_flag();
+    let args = args().skip(1).collect::<Vec<_>>();
+    let [num_replica, dir] = &args[..] else {
+        eprintln!("usage: keygen <num-replica> <dir>");
+        exit(2)
+    };
+    let Ok(num_replica) = num_replica.parse() else {
+        eprintln!("invalid number of replicas {num_replica:?}");
+        exit(2)
+    };
+    keys::generate(Path::new(dir), num_replica, &mut rand::rngs::OsRng).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        exit(1)
+    })
+}
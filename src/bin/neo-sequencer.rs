@@ -1,40 +1,131 @@
 use std::{
     env::args,
-    iter::repeat,
-    net::{Ipv4Addr, UdpSocket},
+    io::ErrorKind,
+    iter::{repeat, repeat_n},
+    net::UdpSocket,
+    process::exit,
     sync::Arc,
     thread::{available_parallelism, spawn},
+    time::Instant,
 };
 
-use permissioned_blockchain::{common::set_affinity, context::ordered_multicast::Sequencer};
+use control_messages::{SequencerArgs, SequencerScheme};
+use permissioned_blockchain::{
+    common::{set_affinity, Request},
+    context::ordered_multicast::{Batcher, Sequencer, SequencerProcess},
+};
 
 fn main() {
-    // let ip = args().nth(1).unwrap().parse::<Ipv4Addr>().unwrap();
-    let mut sequencer = match args().nth(1).as_deref() {
-        Some("half-sip-hash") => {
-            Sequencer::new_half_sip_hash(args().nth(2).unwrap().parse().unwrap())
+    control_messages::build_info!().exit_on_version_flag();
+    permissioned_blockchain::init_tracing();
+    let args = SequencerArgs::parse(args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{err}\n{}", include_str!("neo-sequencer-usage.txt"));
+        exit(2)
+    });
+    let mut sign_interval = 1;
+    let mut sequencer = match args.scheme {
+        SequencerScheme::HalfSipHash(pattern) => {
+            Sequencer::new_half_sip_hash(args.num_replica, pattern)
+        }
+        SequencerScheme::Ecdsa(curve, interval) => {
+            sign_interval = interval;
+            Sequencer::new_ecdsa(curve, sign_interval)
         }
-        Some("k256") => Sequencer::new_k256(),
-        _ => unimplemented!(),
     };
-    let multicast_ip = args().nth(3).unwrap().parse::<Ipv4Addr>().unwrap();
+    if let Some(seed) = args.seed {
+        sequencer = sequencer.with_seed(seed)
+    }
+    let multicast_addr = (args.multicast_ip, args.port);
+    let mut batcher = Some(args.batch_size)
+        .filter(|&batch_size| batch_size != 1)
+        .map(Batcher::<Request>::new);
+    let batch_timeout = args.batch_timeout;
+    let signing_threads = args
+        .signing_threads
+        .unwrap_or(usize::from(available_parallelism().unwrap()) - 1)
+        // sharing the receiving core on a single core host
+        .max(1);
 
-    let socket = Arc::new(UdpSocket::bind(("0.0.0.0", 60004)).unwrap());
+    let socket = Arc::new(UdpSocket::bind(("0.0.0.0", args.port)).unwrap());
     let messages = flume::bounded(1024);
 
     // this has to go first or compiler cannot guess `messages` type
     let mut run = || {
         set_affinity(0);
         let mut buf = vec![0; 65536];
+        if sign_interval == 1 && batcher.is_none() {
+            loop {
+                let (len, _) = socket.recv_from(&mut buf).unwrap();
+                let process = sequencer.process(buf[..len].to_vec());
+                messages.0.send(process).unwrap()
+            }
+        }
+        // hold back the latest message until the next one arrives, so the last one of a burst can
+        // be signed off the interval instead of waiting for the next signature to cover it
+        let mut held = None::<SequencerProcess>;
+        // when the first request of the partial batch arrives
+        let mut batch_start = None::<Instant>;
+        // the receiving only polls while a held message waits for the burst to end, and only waits
+        // for the rest of the batch timeout while a partial batch is pending, otherwise it blocks
+        let (mut nonblocking, mut timeout) = (false, false);
         loop {
-            let (len, _) = socket.recv_from(&mut buf).unwrap();
-            let process = sequencer.process(buf[..len].to_vec());
-            messages.0.send(process).unwrap()
+            let sequenced = if batch_start.is_some_and(|start| start.elapsed() >= batch_timeout) {
+                batcher.as_mut().unwrap().flush()
+            } else {
+                if nonblocking != (held.is_some() && batch_start.is_none()) {
+                    nonblocking = !nonblocking;
+                    socket.set_nonblocking(nonblocking).unwrap()
+                }
+                if let Some(start) = batch_start {
+                    socket
+                        .set_read_timeout(Some(batch_timeout - start.elapsed()))
+                        .unwrap();
+                    timeout = true
+                } else if timeout {
+                    socket.set_read_timeout(None).unwrap();
+                    timeout = false
+                }
+                match socket.recv_from(&mut buf) {
+                    Ok((len, _)) => match &mut batcher {
+                        None => Some(buf[..len].to_vec()),
+                        Some(batcher) => {
+                            let sequenced = batcher.push(&buf[..len]);
+                            if !batcher.is_empty() {
+                                batch_start.get_or_insert_with(Instant::now);
+                            }
+                            sequenced
+                        }
+                    },
+                    Err(err)
+                        if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                    {
+                        // the partial batch follows up soon, otherwise the burst ends here
+                        if batch_start.is_none() {
+                            if let Some(mut process) = held.take() {
+                                process.sign();
+                                messages.0.send(process).unwrap()
+                            }
+                        }
+                        None
+                    }
+                    Err(err) => panic!("{err}"),
+                }
+            };
+            let Some(sequenced) = sequenced else {
+                continue;
+            };
+            batch_start = None;
+            let process = sequencer.process(sequenced);
+            if sign_interval == 1 {
+                messages.0.send(process).unwrap()
+            } else if let Some(process) = held.replace(process) {
+                messages.0.send(process).unwrap()
+            }
         }
     };
 
-    for ((index, messages), socket) in repeat(messages.1)
-        .take(usize::from(available_parallelism().unwrap()) - 1)
+    // sign and send, off the receiving thread
+    for ((index, messages), socket) in repeat_n(messages.1, signing_threads)
         .enumerate()
         .zip(repeat(socket.clone()))
     {
@@ -43,7 +134,7 @@ fn main() {
             loop {
                 let process = messages.recv().unwrap();
                 process.apply(|buf| {
-                    socket.send_to(buf, (multicast_ip, 60004)).unwrap();
+                    socket.send_to(buf, multicast_addr).unwrap();
                 })
             }
         });
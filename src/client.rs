@@ -1,11 +1,23 @@
 use std::{
-    collections::HashMap,
-    iter::repeat,
-    sync::{Arc, Barrier},
+    any::Any,
+    collections::{HashMap, VecDeque},
+    future::Future,
+    iter::repeat_n,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc, Barrier,
+    },
     thread::JoinHandle,
     time::{Duration, Instant},
 };
 
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    rngs::StdRng,
+    Rng, SeedableRng,
+};
 use serde::de::DeserializeOwned;
 use tokio_util::sync::CancellationToken;
 
@@ -13,21 +25,89 @@ use crate::{
     app::Workload,
     common::set_affinity,
     context::{
+        clock::Timestamp,
         crypto::Verify,
         ordered_multicast::Variant,
+        shaping::Shaping,
         tokio::{Dispatch, DispatchHandle},
         ClientIndex, Config, Host,
     },
     Context,
 };
 
+pub mod history;
+
+// what a client learns from a completed invocation, the optional parts are filled if the protocol
+// provides them to clients
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub result: Vec<u8>,
+    // where the request is ordered, e.g. the sequence number in neo
+    pub op_num: Option<u32>,
+    // view or epoch that the replies are sent in
+    pub view_num: Option<u32>,
+    // from invoking to completing, as observed by the client
+    pub latency: Duration,
+    // from invoking to the first reply, for protocols that send replies speculatively before the
+    // outcome completes, i.e. what a client acting on the speculative result would observe
+    pub first_reply: Option<Duration>,
+    pub proof: Option<Proof>,
+    // the replicas ask the client to back off, as their execution lags behind ordering, see
+    // `Config::max_inflight`
+    pub backpressure: bool,
+}
+
+impl Outcome {
+    pub fn new(result: Vec<u8>, start: Timestamp) -> Self {
+        Self {
+            result,
+            op_num: None,
+            view_num: None,
+            latency: start.elapsed(),
+            first_reply: None,
+            proof: None,
+            backpressure: false,
+        }
+    }
+}
+
+// the replies that the client accepts the outcome on, in the protocol's own message type
+#[derive(Clone)]
+pub struct Proof(Arc<dyn Any + Send + Sync>);
+
+impl Proof {
+    pub fn new(replies: impl Any + Send + Sync) -> Self {
+        Self(Arc::new(replies))
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.0.downcast_ref()
+    }
+}
+
+impl std::fmt::Debug for Proof {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Proof").field(&"..").finish()
+    }
+}
+
 pub trait OnResult {
-    fn apply(self: Box<Self>, result: Vec<u8>);
+    fn apply(self: Box<Self>, outcome: Outcome);
 }
 
+// most consumers only care about the result
 impl<F: FnOnce(Vec<u8>)> OnResult for F {
-    fn apply(self: Box<Self>, result: Vec<u8>) {
-        self(result)
+    fn apply(self: Box<Self>, outcome: Outcome) {
+        self(outcome.result)
+    }
+}
+
+// consume the whole outcome
+pub struct OnOutcome<F>(pub F);
+
+impl<F: FnOnce(Outcome)> OnResult for OnOutcome<F> {
+    fn apply(self: Box<Self>, outcome: Outcome) {
+        (self.0)(outcome)
     }
 }
 
@@ -50,13 +130,23 @@ pub trait Client {
 
     fn invoke(&self, op: Vec<u8>, consume: impl Into<BoxedConsume>);
 
+    // an op that leaves the app state unchanged, which protocols with a read path answer from the
+    // replicas' committed state without ordering it, and the others order as any op
+    // only pbft has a read path for now
+    fn invoke_read(&self, op: Vec<u8>, consume: impl Into<BoxedConsume>) {
+        self.invoke(op, consume)
+    }
+
     fn abort(&self) -> Option<BoxedConsume> {
         unimplemented!()
     }
 
     fn handle(&self, message: Self::Message);
 
-    // on timer
+    // the resend timer expires, which the protocols that do not resend take as a failure
+    fn on_timer(&self) {
+        panic!("timeout")
+    }
 }
 
 impl<T: Client> Client for Arc<T> {
@@ -66,6 +156,10 @@ impl<T: Client> Client for Arc<T> {
         T::invoke(self, op, consume)
     }
 
+    fn invoke_read(&self, op: Vec<u8>, consume: impl Into<BoxedConsume>) {
+        T::invoke_read(self, op, consume)
+    }
+
     fn abort(&self) -> Option<BoxedConsume> {
         T::abort(self)
     }
@@ -73,6 +167,60 @@ impl<T: Client> Client for Arc<T> {
     fn handle(&self, message: Self::Message) {
         T::handle(self, message)
     }
+
+    fn on_timer(&self) {
+        T::on_timer(self)
+    }
+}
+
+// forwards to the wrapped client, and reports the first reply latency of every outcome that has one,
+// and whether any outcome asks for backing off
+struct FirstReply<C> {
+    client: Arc<C>,
+    sender: flume::Sender<Duration>,
+    backpressure: Arc<AtomicBool>,
+}
+
+impl<C> FirstReply<C> {
+    fn report(&self, consume: impl Into<BoxedConsume>) -> impl OnResult + Send + Sync + 'static {
+        let consume = consume.into();
+        let sender = self.sender.clone();
+        let backpressure = self.backpressure.clone();
+        OnOutcome(move |outcome: Outcome| {
+            if let Some(first_reply) = outcome.first_reply {
+                // the benchmark may be finished and dropped the receiver
+                let _ = sender.send(first_reply);
+            }
+            if outcome.backpressure {
+                backpressure.store(true, SeqCst)
+            }
+            consume.apply(outcome)
+        })
+    }
+}
+
+impl<C: Client> Client for FirstReply<C> {
+    type Message = C::Message;
+
+    fn invoke(&self, op: Vec<u8>, consume: impl Into<BoxedConsume>) {
+        self.client.invoke(op, self.report(consume))
+    }
+
+    fn invoke_read(&self, op: Vec<u8>, consume: impl Into<BoxedConsume>) {
+        self.client.invoke_read(op, self.report(consume))
+    }
+
+    fn abort(&self) -> Option<BoxedConsume> {
+        self.client.abort()
+    }
+
+    fn handle(&self, message: Self::Message) {
+        self.client.handle(message)
+    }
+
+    fn on_timer(&self) {
+        self.client.on_timer()
+    }
 }
 
 #[derive(Debug)]
@@ -81,7 +229,104 @@ pub struct Benchmark<C> {
     bootstrap: bool,
     finish_sender: flume::Sender<(Host, Duration)>,
     finish_receiver: flume::Receiver<(Host, Duration)>,
+    first_reply_sender: flume::Sender<Duration>,
+    first_reply_receiver: flume::Receiver<Duration>,
+    // rate weights relative to heavy hitters, which are the clients weighted 1, every client is a
+    // heavy hitter if empty
+    weights: HashMap<Host, f64>,
     pub latencies: Vec<Duration>,
+    // empty if the protocol does not reply speculatively
+    pub first_reply_latencies: Vec<Duration>,
+    // empty if the clients are not weighted
+    pub heavy_latencies: Vec<Duration>,
+    // ops completed in each second since the latest run starts, sampled along with `latencies`
+    pub timeline: Vec<u64>,
+    run_start: Instant,
+    // generates the workload, seeded per client group by `run_benchmark`
+    pub rng: StdRng,
+    // open loop only, the clients without an outstanding request, the arrivals waiting for one, and
+    // when the next request arrives. an arrival is for any client, or for the client drawn by the
+    // weights if there are any
+    idle: VecDeque<Host>,
+    arrivals: HashMap<Option<Host>, VecDeque<Timestamp>>,
+    arrival_weights: Option<(Vec<Host>, WeightedIndex<f64>)>,
+    next_arrival: Option<Instant>,
+    // every invocation is recorded into it if set, see `history`
+    pub history: Option<flume::Sender<history::Entry>>,
+    // closed loop only
+    windows: Windows,
+}
+
+// the sessions of every client, whose requests are issued within a window from the earliest
+// outstanding one of the client, so a slow request holds back the ones after it, see
+// `ClientLoad::Pipelined`
+#[derive(Debug, Default)]
+struct Windows {
+    windows: Vec<Window>,
+    // session => the window of its client, and the sequence number of its latest request
+    sessions: HashMap<Host, (usize, u64)>,
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    idle: Vec<Host>,
+    // the number of sessions
+    size: usize,
+    // the sequence number of the earliest outstanding request
+    base: u64,
+    // of the requests from `base` on, whether each is completed
+    completed: VecDeque<bool>,
+}
+
+impl Windows {
+    fn insert(&mut self, client: Host, session: Host) {
+        let window = if client == session {
+            self.windows.push(Default::default());
+            self.windows.len() - 1
+        } else {
+            self.sessions[&client].0
+        };
+        self.windows[window].size += 1;
+        self.windows[window].idle.push(session);
+        self.sessions.insert(session, (window, 0));
+    }
+
+    // the sessions to issue the next requests of the client on, as many as its window allows
+    fn issue(&mut self, window: usize) -> Vec<Host> {
+        let Window {
+            idle,
+            size,
+            base,
+            completed,
+        } = &mut self.windows[window];
+        let mut sessions = Vec::new();
+        while completed.len() < *size {
+            // as many sessions as the window, so one is idle whenever the window is not full
+            let session = idle.pop().unwrap();
+            self.sessions
+                .insert(session, (window, *base + completed.len() as u64));
+            completed.push_back(false);
+            sessions.push(session)
+        }
+        sessions
+    }
+
+    fn complete(&mut self, session: Host) -> Vec<Host> {
+        let (window, seq_num) = self.sessions[&session];
+        let Window {
+            idle,
+            base,
+            completed,
+            ..
+        } = &mut self.windows[window];
+        completed[(seq_num - *base) as usize] = true;
+        idle.push(session);
+        while completed.front() == Some(&true) {
+            completed.pop_front();
+            *base += 1
+        }
+        self.issue(window)
+    }
 }
 
 impl<C> Default for Benchmark<C> {
@@ -93,18 +338,49 @@ impl<C> Default for Benchmark<C> {
 impl<C> Benchmark<C> {
     pub fn new() -> Self {
         let (finish_sender, finish_receiver) = flume::unbounded();
+        let (first_reply_sender, first_reply_receiver) = flume::unbounded();
         Self {
             clients: Default::default(),
             bootstrap: true,
             finish_sender,
             finish_receiver,
+            first_reply_sender,
+            first_reply_receiver,
+            weights: Default::default(),
             latencies: Default::default(),
+            first_reply_latencies: Default::default(),
+            heavy_latencies: Default::default(),
+            timeline: Default::default(),
+            run_start: Instant::now(),
+            rng: StdRng::seed_from_u64(0),
+            idle: Default::default(),
+            arrivals: Default::default(),
+            arrival_weights: None,
+            next_arrival: None,
+            history: None,
+            windows: Default::default(),
         }
     }
 
     pub fn insert_client(&mut self, index: ClientIndex, client: C) {
+        self.insert_session(index, index, client)
+    }
+
+    // a session of the client of `client_index`, which is the first session of the client and
+    // inserted before, see `Windows`
+    pub fn insert_session(&mut self, client_index: ClientIndex, index: ClientIndex, client: C) {
         let evicted = self.clients.insert(Host::Client(index), Arc::new(client));
-        assert!(evicted.is_none())
+        assert!(evicted.is_none());
+        self.windows
+            .insert(Host::Client(client_index), Host::Client(index))
+    }
+
+    // in the closed loop the client thinks between requests for so long that it issues `weight` as
+    // many requests as it would back to back, and in the open loop it receives `weight` as many
+    // arrivals as a heavy hitter
+    pub fn set_weight(&mut self, index: ClientIndex, weight: f64) {
+        assert!(weight > 0. && weight <= 1.);
+        self.weights.insert(Host::Client(index), weight);
     }
 
     pub fn close_loop(
@@ -115,60 +391,317 @@ impl<C> Benchmark<C> {
     ) where
         C: Client + Send + Sync + 'static,
     {
-        let invoke = |index, client: Arc<C>| {
-            let txn = workload.generate(client.clone(), &mut rand::thread_rng());
+        let mut invoke = |index, client: Arc<C>| {
+            let backpressure = Arc::<AtomicBool>::default();
+            let client = FirstReply {
+                client,
+                sender: self.first_reply_sender.clone(),
+                backpressure: backpressure.clone(),
+            };
+            let txn = generate(
+                workload,
+                client,
+                index,
+                self.history.as_ref(),
+                &mut self.rng,
+            );
             let finish_sender = self.finish_sender.clone();
+            let weight = self.weights.get(&index).copied().unwrap_or(1.);
             async move {
-                let start = Instant::now();
+                let start = Timestamp::now();
                 txn.await;
-                finish_sender.send((index, start.elapsed())).unwrap()
+                let latency = start.elapsed();
+                if weight != 1. {
+                    tokio::time::sleep(latency.mul_f64(1. / weight - 1.)).await
+                }
+                // hold back the next request for as long as this one takes, which halves the rate
+                // of the client while the replicas lag
+                if backpressure.load(SeqCst) {
+                    tokio::time::sleep(latency).await
+                }
+                finish_sender.send((index, latency)).unwrap()
             }
         };
 
         if self.bootstrap {
-            for (i, (&index, client)) in self.clients.iter().enumerate() {
+            let sessions =
+                (0..self.windows.windows.len()).flat_map(|window| self.windows.issue(window));
+            for (i, index) in Vec::from_iter(sessions).into_iter().enumerate() {
+                let client = self.clients[&index].clone();
                 // synchronously finish the first invocation, to avoid first-packet reordering
                 if i == 0 {
-                    runtime.block_on(invoke(index, client.clone()))
+                    runtime.block_on(invoke(index, client))
                 } else {
-                    runtime.spawn(invoke(index, client.clone()));
+                    runtime.spawn(invoke(index, client));
                 }
             }
             self.bootstrap = false;
         }
-        let deadline = Instant::now() + duration;
+        self.run_start = Instant::now();
+        let deadline = self.run_start + duration;
         while let Ok((index, latency)) = self.finish_receiver.recv_deadline(deadline) {
             self.latencies.push(latency);
-            runtime.spawn(invoke(index, self.clients[&index].clone()));
+            count_finish(&mut self.timeline, self.run_start);
+            if self.weights.get(&index) == Some(&1.) {
+                self.heavy_latencies.push(latency)
+            }
+            for index in self.windows.complete(index) {
+                runtime.spawn(invoke(index, self.clients[&index].clone()));
+            }
         }
+        self.first_reply_latencies
+            .extend(self.first_reply_receiver.try_iter());
     }
 
-    pub fn run_dispatch(&self) -> impl FnOnce(&mut crate::context::tokio::Dispatch) + Send
-    where
+    // issue requests as they arrive at `rate` per second, see `ClientLoad::Open`
+    pub fn open_loop(
+        &mut self,
+        duration: Duration,
+        rate: f64,
+        workload: &Workload,
+        runtime: tokio::runtime::Handle,
+    ) where
         C: Client + Send + Sync + 'static,
-        C::Message: DeserializeOwned + Verify,
     {
-        struct R<C>(HashMap<Host, Arc<C>>);
-        impl<C> crate::context::Receivers for R<C>
-        where
-            C: Client,
-        {
-            type Message = C::Message;
-
-            fn handle(&mut self, receiver: Host, _: Host, message: Self::Message) {
-                self.0[&receiver].handle(message)
+        if self.bootstrap {
+            let mut clients = Vec::from_iter(self.clients.keys().copied());
+            clients.sort_unstable();
+            // synchronously finish the first invocation, to avoid first-packet reordering
+            let first = clients.remove(0);
+            runtime.block_on(self.invoke_open(first, Timestamp::now(), workload));
+            self.idle.extend(clients);
+            if !self.weights.is_empty() {
+                let mut targets = Vec::from_iter(self.clients.keys().copied());
+                targets.sort_unstable();
+                let weights = WeightedIndex::new(
+                    targets
+                        .iter()
+                        .map(|index| self.weights.get(index).unwrap_or(&1.)),
+                )
+                .unwrap();
+                self.arrival_weights = Some((targets, weights))
             }
+            self.next_arrival = Some(Instant::now());
+            self.bootstrap = false
+        }
+        self.run_start = Instant::now();
+        let deadline = self.run_start + duration;
+        loop {
+            let next_arrival = self.next_arrival.unwrap();
+            match self
+                .finish_receiver
+                .recv_deadline(next_arrival.min(deadline))
+            {
+                Ok((index, latency)) => {
+                    self.latencies.push(latency);
+                    count_finish(&mut self.timeline, self.run_start);
+                    if self.weights.get(&index) == Some(&1.) {
+                        self.heavy_latencies.push(latency)
+                    }
+                    let arrival = [Some(index), None]
+                        .iter()
+                        .find_map(|target| self.arrivals.get_mut(target)?.pop_front());
+                    if let Some(start) = arrival {
+                        runtime.spawn(self.invoke_open(index, start, workload));
+                    } else {
+                        self.idle.push_back(index)
+                    }
+                }
+                Err(flume::RecvTimeoutError::Timeout) if Instant::now() >= deadline => break,
+                Err(flume::RecvTimeoutError::Timeout) => {
+                    let start = Timestamp::now();
+                    let target = self
+                        .arrival_weights
+                        .as_ref()
+                        .map(|(clients, weights)| clients[weights.sample(&mut self.rng)]);
+                    let index = if let Some(target) = target {
+                        let position = self.idle.iter().position(|&index| index == target);
+                        position.and_then(|position| self.idle.remove(position))
+                    } else {
+                        self.idle.pop_front()
+                    };
+                    if let Some(index) = index {
+                        runtime.spawn(self.invoke_open(index, start, workload));
+                    } else {
+                        self.arrivals.entry(target).or_default().push_back(start)
+                    }
+                    // exponentially distributed interval
+                    let interval = -(1. - self.rng.gen::<f64>()).ln() / rate;
+                    self.next_arrival = Some(next_arrival + Duration::from_secs_f64(interval))
+                }
+                Err(flume::RecvTimeoutError::Disconnected) => unreachable!(),
+            }
+        }
+        self.first_reply_latencies
+            .extend(self.first_reply_receiver.try_iter());
+    }
 
-            fn on_timer(&mut self, receiver: Host, _: crate::context::TimerId) {
-                panic!("{receiver:?} timeout")
+    // the latency is from the arrival at `start`, which the request may have waited since
+    fn invoke_open(
+        &mut self,
+        index: Host,
+        start: Timestamp,
+        workload: &Workload,
+    ) -> impl Future<Output = ()> + Send + 'static
+    where
+        C: Client + Send + Sync + 'static,
+    {
+        let backpressure = Arc::<AtomicBool>::default();
+        let client = FirstReply {
+            client: self.clients[&index].clone(),
+            sender: self.first_reply_sender.clone(),
+            backpressure: backpressure.clone(),
+        };
+        let txn = generate(
+            workload,
+            client,
+            index,
+            self.history.as_ref(),
+            &mut self.rng,
+        );
+        let finish_sender = self.finish_sender.clone();
+        async move {
+            let invoke = Timestamp::now();
+            txn.await;
+            let latency = start.elapsed();
+            // the client stays busy, so the arrivals meanwhile queue up, see `close_loop`
+            if backpressure.load(SeqCst) {
+                tokio::time::sleep(invoke.elapsed()).await
             }
+            finish_sender.send((index, latency)).unwrap()
         }
+    }
 
-        let mut receivers = R(self.clients.clone());
+    pub fn run_dispatch(&self) -> impl FnOnce(&mut crate::context::tokio::Dispatch) + Send
+    where
+        C: Client + Send + Sync + 'static,
+        C::Message: DeserializeOwned + Verify + Send + 'static,
+    {
+        let mut receivers = ClientReceivers(self.clients.clone());
         move |runtime| runtime.run(&mut receivers)
     }
 }
 
+fn generate<C: Client + Send + Sync + 'static>(
+    workload: &Workload,
+    client: FirstReply<C>,
+    index: Host,
+    history: Option<&flume::Sender<history::Entry>>,
+    rng: &mut StdRng,
+) -> Pin<Box<dyn Future<Output = ()> + Send + Sync>> {
+    let Some(sender) = history else {
+        return workload.generate(client, rng);
+    };
+    let Host::Client(client_index) = index else {
+        unreachable!()
+    };
+    let client = history::Recorder {
+        client,
+        client_index,
+        sender: sender.clone(),
+    };
+    workload.generate(client, rng)
+}
+
+fn count_finish(timeline: &mut Vec<u64>, run_start: Instant) {
+    let second = run_start.elapsed().as_secs() as usize;
+    if timeline.len() <= second {
+        timeline.resize(second + 1, 0)
+    }
+    timeline[second] += 1
+}
+
+struct ClientReceivers<C>(HashMap<Host, Arc<C>>);
+
+impl<C: Client> crate::context::Receivers for ClientReceivers<C> {
+    type Message = C::Message;
+
+    fn handle(&mut self, receiver: Host, _: Host, message: Self::Message) {
+        self.0[&receiver].handle(message)
+    }
+
+    fn on_timer(&mut self, receiver: Host, _: crate::context::TimerId) {
+        self.0[&receiver].on_timer()
+    }
+}
+
+// a client that runs on its own, for embedding into async applications and integration tests
+// sockets run on the runtime that `connect` is called in, and messages are handled on a dedicated
+// thread, which is stopped by `close`, or in the background when the handle is dropped
+pub struct Handle<C> {
+    client: Arc<C>,
+    dispatch: Option<(DispatchHandle, JoinHandle<()>)>,
+}
+
+// e.g. `connect(config, 0, pbft::Client::new).await`, where `config` has the addresses of the
+// replicas and this client
+pub async fn connect<C>(
+    config: Config,
+    index: ClientIndex,
+    new_client: impl FnOnce(Context<C::Message>, ClientIndex) -> C + Send + 'static,
+) -> Handle<C>
+where
+    C: Client + Send + Sync + 'static,
+    C::Message: DeserializeOwned + Verify + Send + 'static,
+{
+    let runtime = tokio::runtime::Handle::current();
+    let (sender, receiver) = flume::bounded(1);
+    // registering blocks on binding the socket, which must not happen on the runtime
+    let dispatch_thread = std::thread::spawn(move || {
+        let dispatch = Dispatch::new(config, runtime, false, Variant::Unreachable);
+        let client = Arc::new(new_client(dispatch.register(Host::Client(index)), index));
+        sender.send((client.clone(), dispatch.handle())).unwrap();
+        dispatch.run(&mut ClientReceivers(HashMap::from([(
+            Host::Client(index),
+            client,
+        )])))
+    });
+    let (client, dispatch) = receiver.recv_async().await.unwrap();
+    Handle {
+        client,
+        dispatch: Some((dispatch, dispatch_thread)),
+    }
+}
+
+impl<C: Client> Handle<C> {
+    // one invocation at a time, as the protocols' clients are closed-loop
+    pub async fn invoke(&mut self, op: Vec<u8>) -> Outcome {
+        let (sender, receiver) = flume::bounded(1);
+        self.client.invoke(
+            op,
+            OnOutcome(move |outcome| {
+                // the caller may have given up on the invocation
+                let _ = sender.send(outcome);
+            }),
+        );
+        receiver.recv_async().await.unwrap()
+    }
+}
+
+impl<C> Handle<C> {
+    // stopping blocks until the dispatch takes it, which must not happen on the runtime
+    fn stop(dispatch: DispatchHandle, dispatch_thread: JoinHandle<()>) {
+        dispatch.stop();
+        dispatch_thread.join().unwrap()
+    }
+
+    // stop the dispatch and wait for its thread to exit
+    pub async fn close(mut self) {
+        let (dispatch, dispatch_thread) = self.dispatch.take().unwrap();
+        tokio::task::spawn_blocking(move || Self::stop(dispatch, dispatch_thread))
+            .await
+            .unwrap()
+    }
+}
+
+impl<C> Drop for Handle<C> {
+    fn drop(&mut self) {
+        if let Some((dispatch, dispatch_thread)) = self.dispatch.take() {
+            std::thread::spawn(move || Self::stop(dispatch, dispatch_thread));
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct RunBenchmarkConfig {
     pub dispatch_config: Config,
@@ -177,29 +710,56 @@ pub struct RunBenchmarkConfig {
     pub num_client: usize,
     pub duration: Duration,
     pub workload: Workload,
+    pub skew: Option<control_messages::ClientSkew>,
+    pub load: control_messages::ClientLoad,
+    // the file to record the history of invocations into, see `history`
+    pub history: Option<PathBuf>,
+    // shapes the replies, see `Shaping::new_client`
+    pub network_model: control_messages::NetworkModel,
+}
+
+#[derive(Debug)]
+pub struct BenchmarkResult {
+    // one per client group
+    pub latencies: Vec<Vec<Duration>>,
+    pub first_reply_latencies: Vec<Vec<Duration>>,
+    pub heavy_latencies: Vec<Vec<Duration>>,
+    // of `latencies` over all groups
+    pub latency_histogram: control_messages::LatencyHistogram,
+    // ops completed in each second, summed over all groups
+    pub timeline: Vec<u64>,
+    pub socket_drops: u64,
 }
 
 pub fn run_benchmark<C>(
     config: RunBenchmarkConfig,
     new_client: impl Fn(Context<C::Message>, ClientIndex) -> C,
-) -> Vec<Duration>
+) -> BenchmarkResult
 where
     C: Client + Send + Sync + 'static,
-    C::Message: DeserializeOwned + Verify,
+    C::Message: DeserializeOwned + Verify + Send + 'static,
 {
     struct Group<C> {
         benchmark_thread: JoinHandle<Benchmark<C>>,
         runtime_thread: JoinHandle<()>,
-        dispatch_thread: JoinHandle<()>,
+        dispatch_thread: JoinHandle<u64>,
         dispatch_handle: DispatchHandle,
     }
 
     // println!("{config:?}");
+    use control_messages::ClientLoad;
+    assert!(
+        config.skew.is_none() || !matches!(config.load, ClientLoad::Open(_)),
+        "open loop clients are not weighted"
+    );
+    let num_session = config.load.num_session();
+    let history = config
+        .history
+        .map(|path| history::write(&path).expect("create history file"));
     let barrier = Arc::new(Barrier::new(config.num_group));
     let dispatch_config = Arc::new(config.dispatch_config);
     let groups = Vec::from_iter(
-        repeat((barrier, Arc::new(config.workload)))
-            .take(config.num_group)
+        repeat_n((barrier, Arc::new(config.workload)), config.num_group)
             .enumerate()
             .map(|(group_index, (barrier, workload))| {
                 let runtime = tokio::runtime::Builder::new_current_thread()
@@ -213,13 +773,24 @@ where
                     false,
                     Variant::Unreachable,
                 );
+                dispatch.shaping =
+                    Shaping::new_client(&config.network_model, dispatch_config.num_replica);
 
                 let mut benchmark = Benchmark::new();
-                for group_offset in 0..config.num_client {
-                    let index = (config.offset + group_index * config.num_client + group_offset)
-                        as ClientIndex;
+                benchmark.rng = dispatch_config.rng(("benchmark", group_index));
+                benchmark.history = history.as_ref().map(|(sender, _)| sender.clone());
+                let weights = config.skew.map(|skew| skew.weights(config.num_client));
+                // the sessions of a client are consecutive
+                for group_offset in 0..config.num_client * num_session {
+                    let index = (config.offset
+                        + group_index * config.num_client * num_session
+                        + group_offset) as ClientIndex;
                     let client = new_client(dispatch.register(Host::Client(index)), index);
-                    benchmark.insert_client(index, client);
+                    let client_index = index - (group_offset % num_session) as ClientIndex;
+                    benchmark.insert_session(client_index, index, client);
+                    if let Some(weights) = &weights {
+                        benchmark.set_weight(index, weights[group_offset / num_session])
+                    }
                 }
 
                 let cancel = CancellationToken::new();
@@ -234,18 +805,30 @@ where
                 let dispatch_thread = std::thread::spawn(move || {
                     set_affinity(group_index * 2 + 1);
                     run(&mut dispatch);
-                    cancel.cancel()
+                    let socket_drops = dispatch.socket_drops();
+                    cancel.cancel();
+                    socket_drops
                 });
 
+                let load = config.load;
                 let benchmark_thread = std::thread::spawn(move || {
                     set_affinity(group_index * 2 + 1);
+                    let run = |benchmark: &mut Benchmark<C>, duration, handle| match load {
+                        ClientLoad::Open(rate) => {
+                            benchmark.open_loop(duration, rate, &workload, handle)
+                        }
+                        _ => benchmark.close_loop(duration, &workload, handle),
+                    };
                     if group_index == 0 {
-                        benchmark.close_loop(Duration::from_secs(1), &workload, handle.clone());
+                        run(&mut benchmark, Duration::from_secs(1), handle.clone());
                     }
                     barrier.wait();
-                    benchmark.close_loop(Duration::from_secs(1), &workload, handle.clone());
+                    run(&mut benchmark, Duration::from_secs(1), handle.clone());
                     benchmark.latencies.clear();
-                    benchmark.close_loop(config.duration, &workload, handle);
+                    benchmark.first_reply_latencies.clear();
+                    benchmark.heavy_latencies.clear();
+                    benchmark.timeline.clear();
+                    run(&mut benchmark, config.duration, handle);
                     benchmark
                 });
 
@@ -258,13 +841,144 @@ where
             }),
     );
 
-    let mut latencies = Vec::new();
+    let mut result = BenchmarkResult {
+        latencies: Vec::new(),
+        first_reply_latencies: Vec::new(),
+        heavy_latencies: Vec::new(),
+        latency_histogram: Default::default(),
+        timeline: Vec::new(),
+        socket_drops: 0,
+    };
     for group in groups {
         let benchmark = group.benchmark_thread.join().unwrap();
-        latencies.extend(benchmark.latencies);
+        for &latency in &benchmark.latencies {
+            result.latency_histogram.record(latency)
+        }
+        if result.timeline.len() < benchmark.timeline.len() {
+            result.timeline.resize(benchmark.timeline.len(), 0)
+        }
+        for (ops, group_ops) in result.timeline.iter_mut().zip(&benchmark.timeline) {
+            *ops += group_ops
+        }
+        result.latencies.push(benchmark.latencies);
+        result
+            .first_reply_latencies
+            .push(benchmark.first_reply_latencies);
+        result.heavy_latencies.push(benchmark.heavy_latencies);
         group.dispatch_handle.stop();
-        group.dispatch_thread.join().unwrap();
+        result.socket_drops += group.dispatch_thread.join().unwrap();
         group.runtime_thread.join().unwrap();
     }
-    latencies
+    // the outstanding invocations are recorded as the clients are dropped along with the groups
+    if let Some((sender, writer)) = history {
+        drop(sender);
+        writer.join().unwrap().expect("write history file")
+    }
+    result
+}
+
+#[cfg(test)]
+#[cfg(feature = "unreplicated")]
+mod tests {
+    use crate::{context::tokio::unused_addr, unreplicated, App};
+
+    use super::*;
+
+    #[test]
+    fn connect_invoke() {
+        let config = Config::new(
+            HashMap::from([
+                (Host::Replica(0), unused_addr()),
+                (Host::Client(0), unused_addr()),
+            ]),
+            0,
+        );
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let dispatch = Dispatch::new(
+            config.clone(),
+            runtime.handle().clone(),
+            false,
+            Variant::Unreachable,
+        );
+        let mut replica =
+            unreplicated::Replica::new(dispatch.register(Host::Replica(0)), App::Null);
+        let replica_handle = dispatch.handle();
+        let replica_thread = std::thread::spawn(move || dispatch.run(&mut replica));
+
+        runtime.block_on(async {
+            let mut handle = connect(config, 0, unreplicated::Client::new).await;
+            for _ in 0..2 {
+                handle.invoke(Default::default()).await;
+            }
+            handle.close().await
+        });
+        replica_handle.stop();
+        replica_thread.join().unwrap()
+    }
+
+    #[test]
+    fn window_slides_past_completed() {
+        let mut windows = Windows::default();
+        windows.insert(Host::Client(0), Host::Client(0));
+        windows.insert(Host::Client(0), Host::Client(1));
+        let sessions = windows.issue(0);
+        assert_eq!(sessions.len(), 2);
+        // the later request completes first, and the earliest one still holds the window
+        assert!(windows.complete(sessions[1]).is_empty());
+        assert_eq!(windows.complete(sessions[0]).len(), 2);
+    }
+
+    #[test]
+    fn open_loop() {
+        let config = Config::new(
+            HashMap::from([
+                (Host::Replica(0), unused_addr()),
+                (Host::Client(0), unused_addr()),
+                (Host::Client(1), unused_addr()),
+            ]),
+            0,
+        );
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let handle = runtime.handle().clone();
+        let cancel = CancellationToken::new();
+        let runtime_thread = std::thread::spawn({
+            let cancel = cancel.clone();
+            move || runtime.block_on(cancel.cancelled())
+        });
+        let dispatch = Dispatch::new(config.clone(), handle.clone(), false, Variant::Unreachable);
+        let mut replica =
+            unreplicated::Replica::new(dispatch.register(Host::Replica(0)), App::Null);
+        let replica_handle = dispatch.handle();
+        let replica_thread = std::thread::spawn(move || dispatch.run(&mut replica));
+
+        let mut dispatch = Dispatch::new(config, handle.clone(), false, Variant::Unreachable);
+        let mut benchmark = Benchmark::new();
+        for index in 0..2 {
+            let client = unreplicated::Client::new(dispatch.register(Host::Client(index)), index);
+            benchmark.insert_client(index, client)
+        }
+        let run = benchmark.run_dispatch();
+        let client_handle = dispatch.handle();
+        let client_thread = std::thread::spawn(move || run(&mut dispatch));
+        benchmark.open_loop(Duration::from_millis(200), 1000., &Workload::Null, handle);
+        // the arrivals depend on the scheduling, but every completed one is on the timeline
+        assert!(!benchmark.latencies.is_empty());
+        assert_eq!(
+            benchmark.timeline.iter().sum::<u64>(),
+            benchmark.latencies.len() as u64
+        );
+
+        client_handle.stop();
+        client_thread.join().unwrap();
+        replica_handle.stop();
+        replica_thread.join().unwrap();
+        cancel.cancel();
+        runtime_thread.join().unwrap()
+    }
 }
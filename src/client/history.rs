@@ -0,0 +1,135 @@
+// the history of ops that benchmark clients invoke, for checking offline whether the results are
+// linearizable, see `scripts/check`
+// a history file is a sequence of bincode encoded `Entry`s, one file per host. the timestamps are
+// wall clocks so that the histories of hosts can be merged, which is as accurate as the clocks are
+// synchronized
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    thread::JoinHandle,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::context::{read_entries, ClientIndex};
+
+use super::{BoxedConsume, Client, OnOutcome, OnResult, Outcome};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub client_index: ClientIndex,
+    // nanoseconds since unix epoch
+    pub invoke: u64,
+    // none if the op is still outstanding when the benchmark finishes, which may or may not have
+    // taken effect
+    pub complete: Option<u64>,
+    pub op: Vec<u8>,
+    pub result: Option<Vec<u8>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as _
+}
+
+// sends the entry of an invocation once it completes, or once it is dropped without completing,
+// e.g. by the client on the end of the benchmark
+struct Pending {
+    entry: Option<Entry>,
+    sender: flume::Sender<Entry>,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            // the writer may be gone with its file failed
+            let _ = self.sender.send(entry);
+        }
+    }
+}
+
+// forwards to the wrapped client, and records every invocation through it
+pub struct Recorder<C> {
+    pub client: C,
+    pub client_index: ClientIndex,
+    pub sender: flume::Sender<Entry>,
+}
+
+impl<C> Recorder<C> {
+    fn record(
+        &self,
+        op: &[u8],
+        consume: impl Into<BoxedConsume>,
+    ) -> impl OnResult + Send + Sync + 'static {
+        let consume = consume.into();
+        let mut pending = Pending {
+            entry: Some(Entry {
+                client_index: self.client_index,
+                invoke: now(),
+                complete: None,
+                op: op.to_vec(),
+                result: None,
+            }),
+            sender: self.sender.clone(),
+        };
+        OnOutcome(move |outcome: Outcome| {
+            let entry = pending.entry.as_mut().unwrap();
+            entry.complete = Some(now());
+            entry.result = Some(outcome.result.clone());
+            drop(pending);
+            consume.apply(outcome)
+        })
+    }
+}
+
+impl<C: Client> Client for Recorder<C> {
+    type Message = C::Message;
+
+    fn invoke(&self, op: Vec<u8>, consume: impl Into<BoxedConsume>) {
+        let consume = self.record(&op, consume);
+        self.client.invoke(op, consume)
+    }
+
+    fn invoke_read(&self, op: Vec<u8>, consume: impl Into<BoxedConsume>) {
+        let consume = self.record(&op, consume);
+        self.client.invoke_read(op, consume)
+    }
+
+    fn abort(&self) -> Option<BoxedConsume> {
+        self.client.abort()
+    }
+
+    fn handle(&self, message: Self::Message) {
+        self.client.handle(message)
+    }
+
+    fn on_timer(&self) {
+        self.client.on_timer()
+    }
+}
+
+// appends the entries sent through the returned sender to the file at `path` until every sender is
+// dropped
+pub fn write(path: &Path) -> io::Result<(flume::Sender<Entry>, JoinHandle<io::Result<()>>)> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let (sender, receiver) = flume::unbounded();
+    let writer = std::thread::spawn(move || {
+        for entry in receiver {
+            bincode::options()
+                .serialize_into(&mut file, &entry)
+                .map_err(io::Error::other)?
+        }
+        file.flush()
+    });
+    Ok((sender, writer))
+}
+
+pub fn read(path: &Path) -> io::Result<Vec<Entry>> {
+    read_entries(path)
+}
@@ -1,5 +1,10 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::Hash,
+    time::Duration,
+};
 
+use control_messages::{ClientTableStats, WaitingStats};
 use k256::sha2::Digest;
 use nix::{
     sched::{sched_setaffinity, CpuSet},
@@ -9,22 +14,43 @@ use serde::{Deserialize, Serialize};
 
 use crate::context::{
     crypto::{DigestHash, Hasher},
-    ClientIndex, Context, TimerId,
+    ClientIndex, Context, Host, TimerId,
 };
 
+pub mod mempool;
+pub mod state_transfer;
+
 #[derive(Debug)]
 pub struct Timer {
     pub id: Option<TimerId>,
     duration: Duration,
+    sentinel: bool,
 }
 
 impl Timer {
     pub fn new(duration: Duration) -> Self {
-        Self { id: None, duration }
+        Self {
+            id: None,
+            duration,
+            sentinel: false,
+        }
+    }
+
+    pub fn new_sentinel(duration: Duration) -> Self {
+        Self {
+            id: None,
+            duration,
+            sentinel: true,
+        }
     }
 
     pub fn set<M>(&mut self, context: &mut Context<M>) {
-        let evicted = self.id.replace(context.set(self.duration));
+        let id = if self.sentinel {
+            context.set_sentinel(self.duration)
+        } else {
+            context.set(self.duration)
+        };
+        let evicted = self.id.replace(id);
         assert!(evicted.is_none())
     }
 
@@ -44,6 +70,16 @@ pub fn set_affinity(index: usize) {
     sched_setaffinity(Pid::from_raw(0), &cpu_set).unwrap()
 }
 
+// reserve capacity for `entry_num` entries and touch the reserved pages, so neither reallocation
+// nor page fault happens until the log grows beyond that
+pub fn warm_up_log<T>(log: &mut Vec<T>, entry_num: usize) {
+    log.reserve(entry_num.saturating_sub(log.len()));
+    let spare = log.spare_capacity_mut();
+    // SAFETY: the pointer and length come from the spare capacity slice, so every written byte is
+    // in bounds, and any byte pattern is valid for `MaybeUninit`, which the length stays unaware of
+    unsafe { std::ptr::write_bytes(spare.as_mut_ptr(), 0, spare.len()) }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Request {
     pub client_index: ClientIndex,
@@ -66,13 +102,18 @@ pub struct Block {
     pub requests: Vec<Request>,
     pub parent_digest: BlockDigest,
     pub height: u32,
+    // of the batches in the mempool that the block orders after its requests, see `mempool`
+    pub batches: Vec<mempool::BatchDigest>,
 }
 
 impl DigestHash for Block {
     fn hash(&self, hasher: &mut impl std::hash::Hasher) {
         self.requests.hash(hasher);
         hasher.write(&self.parent_digest);
-        hasher.write_u32(self.height)
+        hasher.write_u32(self.height);
+        for batch_digest in &self.batches {
+            hasher.write(batch_digest)
+        }
     }
 }
 
@@ -87,7 +128,9 @@ pub struct Chain {
     pub digest_parent: BlockDigest,
     pub digest_execute: BlockDigest,
     height: u32,
-    pending_execute: HashMap<BlockDigest, BlockDigest>,
+    executed_height: u32,
+    // parent digest => the committed block waiting for it and its height
+    pending_execute: HashMap<BlockDigest, (BlockDigest, u32)>,
 }
 
 impl Chain {
@@ -96,6 +139,7 @@ impl Chain {
             digest_parent: Self::genesis().digest(),
             height: 0,
             digest_execute: Self::genesis().digest(),
+            executed_height: 0,
             pending_execute: Default::default(),
         }
     }
@@ -123,6 +167,7 @@ impl Chain {
                 .collect(),
             parent_digest: self.digest_parent,
             height: self.height,
+            batches: Default::default(),
         };
         self.digest_parent = block.digest();
         block
@@ -134,6 +179,7 @@ impl Chain {
             requests: Default::default(),
             parent_digest: self.digest_parent,
             height: self.height,
+            batches: Default::default(),
         };
         self.digest_parent = block.digest();
         block
@@ -142,22 +188,443 @@ impl Chain {
     pub fn commit(&mut self, block: &Block) -> bool {
         if block.parent_digest == self.digest_execute {
             self.digest_execute = block.digest();
+            self.executed_height = block.height;
             true
         } else {
             let evicted = self
                 .pending_execute
-                .insert(block.parent_digest, block.digest());
+                .insert(block.parent_digest, (block.digest(), block.height));
             assert!(evicted.is_none(), "commit conflicting blocks");
             false
         }
     }
 
+    // execute a block recovered from storage, which has been committed and executed before the
+    // crash, and also propose after it unless a later block is proposed, see `replay_proposed`
+    pub fn replay(&mut self, block: &Block) {
+        assert_eq!(block.parent_digest, self.digest_execute);
+        self.digest_execute = block.digest();
+        self.executed_height = block.height;
+        self.replay_proposed(block)
+    }
+
+    // a block recovered from storage, which has been proposed or accepted before the crash, so the
+    // next proposal follows it instead of conflicting with it at the same height
+    pub fn replay_proposed(&mut self, block: &Block) {
+        if block.height > self.height {
+            self.digest_parent = block.digest();
+            self.height = block.height
+        }
+    }
+
+    // continue after `block`, whose state is restored rather than executed, dropping the waiting
+    // blocks up to it, and return the waiting block that follows it if any, to execute next
+    pub fn restore(&mut self, block: &Block) -> Option<BlockDigest> {
+        self.digest_execute = block.digest();
+        self.executed_height = block.height;
+        self.pending_execute
+            .retain(|_, &mut (_, height)| height > block.height);
+        self.replay_proposed(block);
+        self.pending_execute
+            .remove(&self.digest_execute)
+            .map(|(block_digest, _)| block_digest)
+    }
+
+    // some committed blocks are waiting for blocks that are not committed yet
+    pub fn is_behind(&self) -> bool {
+        !self.pending_execute.is_empty()
+    }
+
+    // the block is committed and waiting, or is an ancestor of such block since blocks are chained
+    // by their digests, i.e. a fetched block is committed if it leads to a waiting one
+    pub fn waits_for(&self, block_digest: &BlockDigest) -> bool {
+        self.pending_execute.contains_key(block_digest)
+            || self
+                .pending_execute
+                .values()
+                .any(|(digest, _)| digest == block_digest)
+    }
+
+    // of the last block that is returned to execute
+    pub fn executed_height(&self) -> u32 {
+        self.executed_height
+    }
+
     pub fn next_execute(&mut self) -> Option<BlockDigest> {
-        if let Some(block_digest) = self.pending_execute.remove(&self.digest_execute) {
+        if let Some((block_digest, height)) = self.pending_execute.remove(&self.digest_execute) {
             self.digest_execute = block_digest;
+            self.executed_height = height;
             Some(block_digest)
         } else {
             None
         }
     }
 }
+
+// the latest reply of each client, for replying resent requests
+#[derive(Debug)]
+pub struct ClientTable<R> {
+    capacity: usize,
+    entries: HashMap<ClientIndex, ClientEntry<R>>,
+    // op number of the latest executed request of each client, which the requests of a batch share
+    op_nums: BTreeSet<(u64, ClientIndex)>,
+    // remote, request number and op number of the evicted entries, so an old request that is
+    // resent after its entry is evicted is still ignored instead of executed again. much smaller
+    // than an entry, and the oldest committed ones are pruned beyond `capacity` of them
+    evicted: HashMap<ClientIndex, Evicted>,
+    evicted_nums: BTreeSet<(u64, ClientIndex)>,
+    // what each insert replaced, by op number, so a rollback restores the earlier requests of the
+    // clients instead of forgetting them, none recorded before `speculate`
+    speculative: bool,
+    undo: Vec<UndoEntry<R>>,
+    pub stats: ClientTableStats,
+}
+
+type Evicted = (Host, u32, u64);
+
+type UndoEntry<R> = (u64, ClientIndex, Option<ClientEntry<R>>, Option<Evicted>);
+
+#[derive(Debug)]
+struct ClientEntry<R> {
+    remote: Host,
+    op_num: u64,
+    request_num: u32,
+    reply: R,
+}
+
+#[derive(Debug)]
+pub enum ClientLookup<'a, R> {
+    Execute,
+    Resend(&'a R),
+    Ignore,
+}
+
+impl<R> ClientTable<R> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Default::default(),
+            op_nums: Default::default(),
+            evicted: Default::default(),
+            evicted_nums: Default::default(),
+            speculative: false,
+            undo: Default::default(),
+            stats: Default::default(),
+        }
+    }
+
+    pub fn lookup(
+        &mut self,
+        client_index: ClientIndex,
+        request_num: u32,
+        remote: Host,
+    ) -> ClientLookup<'_, R> {
+        let Some(entry) = self.entries.get(&client_index) else {
+            return match self.evicted.get(&client_index) {
+                Some(&(evicted_remote, ..)) if evicted_remote != remote => {
+                    self.stats.collided += 1;
+                    ClientLookup::Ignore
+                }
+                Some(&(_, evicted_num, _)) if evicted_num >= request_num => ClientLookup::Ignore,
+                _ => ClientLookup::Execute,
+            };
+        };
+        if entry.remote != remote {
+            self.stats.collided += 1;
+            return ClientLookup::Ignore;
+        }
+        match entry.request_num.cmp(&request_num) {
+            std::cmp::Ordering::Less => ClientLookup::Execute,
+            std::cmp::Ordering::Equal => ClientLookup::Resend(&entry.reply),
+            std::cmp::Ordering::Greater => ClientLookup::Ignore,
+        }
+    }
+
+    // a full table makes room by evicting the clients that are idle for longest, if their latest
+    // requests are committed i.e. at or below `committed_num`, otherwise it grows past the capacity
+    // until the commit point moves forward, as the reply is always recorded
+    pub fn insert(
+        &mut self,
+        client_index: ClientIndex,
+        remote: Host,
+        op_num: u64,
+        request_num: u32,
+        reply: R,
+        committed_num: u64,
+    ) {
+        if !self.entries.contains_key(&client_index) {
+            while self.entries.len() >= self.capacity {
+                match self.op_nums.first() {
+                    Some(&(oldest_num, evicted_index)) if oldest_num <= committed_num => {
+                        self.op_nums.pop_first();
+                        let evicted = self.entries.remove(&evicted_index).unwrap();
+                        self.evicted.insert(
+                            evicted_index,
+                            (evicted.remote, evicted.request_num, evicted.op_num),
+                        );
+                        self.evicted_nums.insert((evicted.op_num, evicted_index));
+                        self.stats.evicted += 1
+                    }
+                    _ => {
+                        self.stats.overflowed += 1;
+                        break;
+                    }
+                }
+            }
+            // a resend of a request this old is taken as a new one
+            while self.evicted.len() > self.capacity {
+                match self.evicted_nums.first() {
+                    Some(&(evicted_num, evicted_index)) if evicted_num <= committed_num => {
+                        self.evicted_nums.pop_first();
+                        self.evicted.remove(&evicted_index);
+                    }
+                    _ => break,
+                }
+            }
+        }
+        let entry = ClientEntry {
+            remote,
+            op_num,
+            request_num,
+            reply,
+        };
+        let replaced = self.entries.insert(client_index, entry);
+        if let Some(replaced) = &replaced {
+            self.op_nums.remove(&(replaced.op_num, client_index));
+        }
+        let evicted = self.evicted.remove(&client_index);
+        if let Some((_, _, evicted_num)) = evicted {
+            self.evicted_nums.remove(&(evicted_num, client_index));
+        }
+        if self.speculative {
+            self.undo.push((op_num, client_index, replaced, evicted))
+        }
+        self.op_nums.insert((op_num, client_index));
+    }
+
+    // the requests inserted from now on may be rolled back, until `truncate_undo` passes them
+    pub fn speculate(&mut self) {
+        self.speculative = true
+    }
+
+    // forget the requests executed after `op_num`, which are rolled back and executed again once
+    // ordered anew, and restore what they replaced. without speculating there is nothing to
+    // restore, and the clients' earlier requests are not recorded anymore either
+    pub fn rollback(&mut self, op_num: u64) {
+        let num_kept = self
+            .undo
+            .partition_point(|(entry_num, ..)| *entry_num <= op_num);
+        for (_, client_index, replaced, evicted) in self.undo.drain(num_kept..).rev() {
+            if let Some(entry) = self.entries.remove(&client_index) {
+                self.op_nums.remove(&(entry.op_num, client_index));
+            }
+            if let Some(entry) = replaced {
+                self.op_nums.insert((entry.op_num, client_index));
+                self.entries.insert(client_index, entry);
+            }
+            if let Some(evicted) = evicted {
+                self.evicted_nums.insert((evicted.2, client_index));
+                self.evicted.insert(client_index, evicted);
+            }
+        }
+        for (_, client_index) in self.op_nums.split_off(&(op_num + 1, 0)) {
+            self.entries.remove(&client_index);
+        }
+    }
+
+    // the requests up to `op_num` are committed and never rolled back
+    pub fn truncate_undo(&mut self, op_num: u64) {
+        let num_truncated = self
+            .undo
+            .partition_point(|(entry_num, ..)| *entry_num <= op_num);
+        self.undo.drain(..num_truncated);
+    }
+}
+
+// entries waiting for something that may never arrive, e.g. garbage from a Byzantine peer that
+// references unknown blocks or far future op numbers
+// bounded by evicting the entry of the greatest priority, i.e. the farthest from the current
+// frontier, and a new entry farther than all existing ones is rejected instead
+#[derive(Debug)]
+pub struct Waiting<K, P, V> {
+    entries: HashMap<K, (P, V)>,
+    order: BTreeSet<(P, K)>,
+    capacity: usize,
+    pub stats: WaitingStats,
+}
+
+impl<K: Hash + Eq + Ord + Clone, P: Ord + Clone, V> Waiting<K, P, V> {
+    pub fn new(capacity: usize) -> Self {
+        assert_ne!(capacity, 0);
+        Self {
+            entries: Default::default(),
+            order: Default::default(),
+            capacity,
+            stats: Default::default(),
+        }
+    }
+
+    // the entry of `key`, which is created with `priority` if not present, or `None` if it is
+    // rejected
+    pub fn entry(&mut self, key: K, priority: P) -> Option<&mut V>
+    where
+        V: Default,
+    {
+        if !self.entries.contains_key(&key) && !self.insert(key.clone(), priority, V::default()) {
+            return None;
+        }
+        Some(&mut self.entries.get_mut(&key).unwrap().1)
+    }
+
+    // replace the value if `key` is present, otherwise return false if rejected
+    pub fn insert(&mut self, key: K, priority: P, value: V) -> bool {
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.1 = value;
+            return true;
+        }
+        if self.entries.len() == self.capacity {
+            let farthest = self.order.last().unwrap();
+            if priority >= farthest.0 {
+                self.stats.rejected += 1;
+                return false;
+            }
+            let (_, evicted) = self.order.pop_last().unwrap();
+            self.entries.remove(&evicted);
+            self.stats.evicted += 1
+        }
+        self.order.insert((priority.clone(), key.clone()));
+        self.entries.insert(key, (priority, value));
+        self.stats.max_len = self.stats.max_len.max(self.entries.len());
+        true
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (priority, value) = self.entries.remove(key)?;
+        self.order.remove(&(priority, key.clone()));
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_table_bounded() {
+        let mut table = ClientTable::new(2);
+        table.insert(0, Host::Client(0), 1, 1, "a", 1);
+        table.insert(1, Host::Client(1), 2, 1, "b", 2);
+        // client 0 is idle for longest but its request is not committed yet
+        table.insert(2, Host::Client(2), 3, 1, "c", 0);
+        assert_eq!(table.stats.overflowed, 1);
+        // recorded past the capacity, so a resend is replied rather than executed again
+        assert!(matches!(
+            table.lookup(2, 1, Host::Client(2)),
+            ClientLookup::Resend(&"c")
+        ));
+        // the table shrinks back once the commit point moves forward
+        table.insert(3, Host::Client(3), 4, 1, "d", 3);
+        assert_eq!(table.stats.evicted, 2);
+        assert_eq!(table.entries.len(), 2);
+        assert!(matches!(
+            table.lookup(1, 1, Host::Client(1)),
+            ClientLookup::Ignore
+        ));
+        // the evicted client's executed request is not executed again, but the next one is
+        assert!(matches!(
+            table.lookup(0, 1, Host::Client(0)),
+            ClientLookup::Ignore
+        ));
+        assert!(matches!(
+            table.lookup(0, 2, Host::Client(0)),
+            ClientLookup::Execute
+        ));
+        assert!(matches!(
+            table.lookup(0, 2, Host::Client(3)),
+            ClientLookup::Ignore
+        ));
+        assert_eq!(table.stats.collided, 1);
+        assert!(matches!(
+            table.lookup(3, 1, Host::Client(3)),
+            ClientLookup::Resend(&"d")
+        ));
+        assert!(matches!(
+            table.lookup(3, 1, Host::Client(4)),
+            ClientLookup::Ignore
+        ));
+        assert_eq!(table.stats.collided, 2);
+        // the evicted entries beyond the capacity are pruned from the oldest
+        table.insert(4, Host::Client(4), 5, 1, "e", 4);
+        assert_eq!(table.evicted.len(), 2);
+        assert!(matches!(
+            table.lookup(0, 1, Host::Client(0)),
+            ClientLookup::Execute
+        ));
+    }
+
+    #[test]
+    fn client_table_rollback() {
+        let mut table = ClientTable::new(2);
+        table.speculate();
+        table.insert(0, Host::Client(0), 1, 1, "a", 1);
+        table.insert(0, Host::Client(0), 2, 2, "b", 2);
+        table.insert(1, Host::Client(1), 2, 1, "c", 2);
+        table.rollback(1);
+        // a late duplicate of the earlier request is replied instead of executed again
+        assert!(matches!(
+            table.lookup(0, 1, Host::Client(0)),
+            ClientLookup::Resend(&"a")
+        ));
+        assert!(matches!(
+            table.lookup(0, 2, Host::Client(0)),
+            ClientLookup::Execute
+        ));
+        assert!(matches!(
+            table.lookup(1, 1, Host::Client(1)),
+            ClientLookup::Execute
+        ));
+        table.truncate_undo(1);
+        assert!(table.undo.is_empty())
+    }
+
+    #[test]
+    fn waiting_evicts_farthest() {
+        let mut waiting = Waiting::<u64, u64, Vec<u64>>::new(2);
+        waiting.entry(1, 1).unwrap().push(1);
+        waiting.entry(3, 3).unwrap().push(3);
+        // farther than every entry
+        assert!(waiting.entry(4, 4).is_none());
+        waiting.entry(2, 2).unwrap().push(2);
+        assert_eq!(waiting.remove(&3), None);
+        // existing entry is not affected by capacity
+        waiting.entry(2, 2).unwrap().push(2);
+        assert_eq!(waiting.remove(&2), Some(vec![2, 2]));
+        assert_eq!(waiting.len(), 1);
+        assert_eq!(waiting.stats.evicted, 1);
+        assert_eq!(waiting.stats.rejected, 1);
+        assert_eq!(waiting.stats.max_len, 2);
+    }
+
+    #[test]
+    fn warm_up_log_keeps_entries() {
+        let mut log = vec![String::from("a"), String::from("b")];
+        warm_up_log(&mut log, 1000);
+        assert_eq!(log, ["a", "b"]);
+        assert!(log.capacity() >= 1000);
+        // no reallocation within the reserved capacity
+        let ptr = log.as_ptr();
+        log.extend((2..1000).map(|i| i.to_string()));
+        assert_eq!(log.as_ptr(), ptr);
+        // already large enough
+        warm_up_log(&mut log, 10);
+        assert_eq!(log.len(), 1000)
+    }
+}
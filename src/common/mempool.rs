@@ -0,0 +1,382 @@
+// a mempool in the style of Narwhal's workers, which disseminates the requests apart from the
+// consensus, so that the blocks of the consensus only carry the digests of batches
+// - every replica runs a worker, which the clients submit their requests to, and which broadcasts
+//   them in batches
+// - the replicas store the batches they receive and acknowledge them to the batch's worker
+// - f + 1 acknowledgements are the availability certificate of a batch, i.e. at least one correct
+//   replica serves it, and are broadcast for the consensus to order the batch
+// - a replica that executes a batch that it does not store fetches it from the certificate's signers
+// the DAG of Narwhal's primaries is not built, the consensus orders the certified batches directly
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use k256::sha2::Digest;
+use serde::{Deserialize, Serialize};
+
+use crate::context::{
+    crypto::{DigestHash, Hasher, Invalid, Sign, Signed, Verifier},
+    Context, Host, ReplicaIndex, To,
+};
+
+use super::{BlockDigest, Chain, Request};
+
+pub type BatchDigest = BlockDigest;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Batch {
+    pub requests: Vec<Request>,
+    // the worker and its sequence of batches, so that equal requests still form distinct batches
+    pub replica_index: ReplicaIndex,
+    pub batch_num: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchAck {
+    pub batch_digest: BatchDigest,
+    pub replica_index: ReplicaIndex,
+}
+
+// the availability certificate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Available {
+    pub batch_digest: BatchDigest,
+    pub acks: Vec<Signed<BatchAck>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchBatch {
+    pub batch_digest: BatchDigest,
+    pub replica_index: ReplicaIndex,
+}
+
+#[derive(Debug)]
+pub struct Mempool {
+    index: ReplicaIndex,
+    // submitted to the worker and not batched yet
+    requests: Vec<Request>,
+    batch_num: u32,
+    // signed by their workers, so they are served as is to the fetching replicas
+    pub batches: HashMap<BatchDigest, Signed<Batch>>,
+    // the number of stored batches of each worker, which are not acknowledged beyond
+    // `MAX_STORED_BATCHES` until some of them is executed
+    num_stored: HashMap<ReplicaIndex, usize>,
+    // of the worker's own batches, until they are certified
+    acks: HashMap<BatchDigest, HashMap<ReplicaIndex, Signed<BatchAck>>>,
+    pub certificates: HashMap<BatchDigest, Available>,
+    // certified and not executed yet, in the order they are certified
+    pub available: Vec<BatchDigest>,
+    // the latest `MAX_EXECUTED` ones, in the order they are executed
+    executed: HashSet<BatchDigest>,
+    executed_order: VecDeque<BatchDigest>,
+    fetching: HashSet<BatchDigest>,
+}
+
+impl Mempool {
+    pub const MAX_BATCH_SIZE: usize = Chain::MAX_BATCH_SIZE;
+    pub const MAX_STORED_BATCHES: usize = 1024;
+    pub const MAX_EXECUTED: usize = 1 << 16;
+
+    pub fn new(index: ReplicaIndex) -> Self {
+        Self {
+            index,
+            requests: Default::default(),
+            batch_num: 0,
+            batches: Default::default(),
+            num_stored: Default::default(),
+            acks: Default::default(),
+            certificates: Default::default(),
+            available: Default::default(),
+            executed: Default::default(),
+            executed_order: Default::default(),
+            fetching: Default::default(),
+        }
+    }
+
+    pub fn on_request(&mut self, request: Request) {
+        self.requests.push(request)
+    }
+
+    pub fn on_pace<M>(&mut self, context: &mut Context<M>)
+    where
+        M: Sign<Batch> + Serialize,
+    {
+        if self.requests.is_empty() {
+            return;
+        }
+        self.batch_num += 1;
+        let batch = Batch {
+            requests: self
+                .requests
+                .drain(..self.requests.len().min(Self::MAX_BATCH_SIZE))
+                .collect(),
+            replica_index: self.index,
+            batch_num: self.batch_num,
+        };
+        self.acks.insert(batch.digest(), Default::default());
+        context.send(To::AllReplicaWithLoopback, batch)
+    }
+
+    // a broadcast batch, or a fetched one
+    pub fn on_batch<M>(&mut self, batch: Signed<Batch>, context: &mut Context<M>)
+    where
+        M: Sign<BatchAck> + Serialize,
+    {
+        let batch_digest = batch.digest();
+        let worker_index = batch.replica_index;
+        let fetched = self.fetching.remove(&batch_digest);
+        if self.executed.contains(&batch_digest) || self.batches.contains_key(&batch_digest) {
+            return;
+        }
+        let num_stored = self.num_stored.entry(worker_index).or_default();
+        // a worker that floods batches, which are never certified, cannot exhaust the memory,
+        // while the fetched ones are ordered already
+        if *num_stored >= Self::MAX_STORED_BATCHES && !fetched {
+            return;
+        }
+        *num_stored += 1;
+        self.batches.insert(batch_digest, batch);
+        if fetched {
+            return;
+        }
+        let ack = BatchAck {
+            batch_digest,
+            replica_index: self.index,
+        };
+        let to = if worker_index == self.index {
+            To::Loopback
+        } else {
+            To::replica(worker_index)
+        };
+        context.send(to, ack)
+    }
+
+    pub fn on_ack<M>(&mut self, ack: Signed<BatchAck>, context: &mut Context<M>)
+    where
+        M: Sign<Available> + Serialize,
+    {
+        let Some(acks) = self.acks.get_mut(&ack.batch_digest) else {
+            return;
+        };
+        acks.insert(ack.replica_index, ack.clone());
+        if acks.len() == context.config().num_faulty + 1 {
+            let acks = self.acks.remove(&ack.batch_digest).unwrap();
+            let available = Available {
+                batch_digest: ack.batch_digest,
+                acks: acks.into_values().collect(),
+            };
+            context.send(To::AllReplicaWithLoopback, available)
+        }
+    }
+
+    pub fn on_available<M>(&mut self, available: Available, context: &Context<M>) {
+        if !self.is_certified(&available, context)
+            || self.executed.contains(&available.batch_digest)
+            || self.certificates.contains_key(&available.batch_digest)
+        {
+            return;
+        }
+        self.available.push(available.batch_digest);
+        self.certificates.insert(available.batch_digest, available);
+    }
+
+    pub fn is_certified<M>(&self, available: &Available, context: &Context<M>) -> bool {
+        available
+            .acks
+            .iter()
+            .filter(|ack| ack.batch_digest == available.batch_digest)
+            .map(|ack| ack.replica_index)
+            .collect::<HashSet<_>>()
+            .len()
+            > context.config().num_faulty
+    }
+
+    pub fn on_fetch<M>(&self, fetch: Signed<FetchBatch>, context: &mut Context<M>)
+    where
+        M: Sign<Signed<Batch>> + Serialize,
+    {
+        if let Some(batch) = self.batches.get(&fetch.batch_digest) {
+            context.send(To::replica(fetch.replica_index), batch.clone())
+        }
+    }
+
+    // the requests of the batches in order, or none if some batch is not stored yet, which is
+    // fetched from the signers of its certificate then, or from every replica if the certificate is
+    // not known
+    // the batches executed already, e.g. ordered twice, add no request
+    pub fn payload<M>(
+        &mut self,
+        batch_digests: &[BatchDigest],
+        context: &mut Context<M>,
+    ) -> Option<Vec<Request>>
+    where
+        M: Sign<FetchBatch> + Serialize,
+    {
+        let missing = Vec::from_iter(
+            batch_digests
+                .iter()
+                .filter(|batch_digest| {
+                    !self.batches.contains_key(*batch_digest)
+                        && !self.executed.contains(*batch_digest)
+                })
+                .copied(),
+        );
+        if missing.is_empty() {
+            let mut requests = Vec::new();
+            let mut included = HashSet::new();
+            for batch_digest in batch_digests {
+                let Some(batch) = self.batches.get(batch_digest) else {
+                    continue;
+                };
+                // the same request may be submitted to several workers
+                for request in &batch.requests {
+                    if included.insert((request.client_index, request.request_num)) {
+                        requests.push(request.clone())
+                    }
+                }
+            }
+            return Some(requests);
+        }
+        for batch_digest in missing {
+            if !self.fetching.insert(batch_digest) {
+                continue;
+            }
+            let fetch = FetchBatch {
+                batch_digest,
+                replica_index: self.index,
+            };
+            let to = match self.certificates.get(&batch_digest) {
+                Some(available) => To::Hosts(Vec::from_iter(
+                    available
+                        .acks
+                        .iter()
+                        .map(|ack| Host::Replica(ack.replica_index)),
+                )),
+                None => To::AllReplica,
+            };
+            context.send(to, fetch)
+        }
+        None
+    }
+
+    pub fn on_executed(&mut self, batch_digests: &[BatchDigest]) {
+        for batch_digest in batch_digests {
+            if let Some(batch) = self.batches.remove(batch_digest) {
+                *self.num_stored.get_mut(&batch.replica_index).unwrap() -= 1
+            }
+            self.certificates.remove(batch_digest);
+            if self.executed.insert(*batch_digest) {
+                self.executed_order.push_back(*batch_digest)
+            }
+        }
+        while self.executed_order.len() > Self::MAX_EXECUTED {
+            let batch_digest = self.executed_order.pop_front().unwrap();
+            self.executed.remove(&batch_digest);
+        }
+        self.available
+            .retain(|batch_digest| !batch_digests.contains(batch_digest))
+    }
+}
+
+impl Batch {
+    pub fn digest(&self) -> BatchDigest {
+        Hasher::sha256(self).finalize().into()
+    }
+}
+
+impl Available {
+    pub fn verify(&self, verifier: &Verifier) -> Result<(), Invalid> {
+        verifier.verify_batch(self.acks.iter().map(|ack| (ack, ack.replica_index)))
+    }
+}
+
+impl DigestHash for Batch {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        self.requests.hash(hasher);
+        hasher.write_u8(self.replica_index);
+        hasher.write_u32(self.batch_num)
+    }
+}
+
+impl DigestHash for Available {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write(&self.batch_digest);
+        self.acks.hash(hasher)
+    }
+}
+
+impl DigestHash for BatchAck {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write(&self.batch_digest);
+        hasher.write_u8(self.replica_index)
+    }
+}
+
+impl DigestHash for FetchBatch {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write(&self.batch_digest);
+        hasher.write_u8(self.replica_index)
+    }
+}
+
+#[cfg(all(test, feature = "hotstuff"))]
+mod tests {
+    use crate::context::{
+        crypto::Signer,
+        ordered_multicast::Variant,
+        simulated::{fixture::config, Network},
+    };
+
+    use super::*;
+
+    fn batch(batch_num: u32, request_nums: impl IntoIterator<Item = u32>) -> Signed<Batch> {
+        let batch = Batch {
+            requests: Vec::from_iter(request_nums.into_iter().map(|request_num| Request {
+                client_index: 0,
+                request_num,
+                op: Default::default(),
+            })),
+            replica_index: 1,
+            batch_num,
+        };
+        Signer::new(&config(4, 1), Host::Replica(1)).sign_private(batch)
+    }
+
+    #[test]
+    fn payload() {
+        let network = Network::new(config(4, 1), false, |_| Variant::Unreachable);
+        let mut context = network.register::<crate::hotstuff::Message>(Host::Replica(0));
+        let mut mempool = Mempool::new(0);
+        let (batch1, batch2) = (batch(1, [1, 2]), batch(2, [2, 3]));
+        let digests = [batch1.digest(), batch2.digest()];
+        // fetched from every replica without a known certificate
+        assert!(mempool.payload(&digests, &mut context).is_none());
+        assert_eq!(mempool.fetching.len(), 2);
+        mempool.on_batch(batch1, &mut context);
+        mempool.on_batch(batch2, &mut context);
+        let payload = mempool.payload(&digests, &mut context).unwrap();
+        assert_eq!(
+            Vec::from_iter(payload.iter().map(|request| request.request_num)),
+            [1, 2, 3]
+        );
+        mempool.on_executed(&digests);
+        assert!(mempool.batches.is_empty());
+        // ordered again
+        assert!(mempool.payload(&digests, &mut context).unwrap().is_empty())
+    }
+
+    #[test]
+    fn stored_bound() {
+        let network = Network::new(config(4, 1), false, |_| Variant::Unreachable);
+        let mut context = network.register::<crate::hotstuff::Message>(Host::Replica(0));
+        let mut mempool = Mempool::new(0);
+        for batch_num in 0..Mempool::MAX_STORED_BATCHES as u32 + 1 {
+            mempool.on_batch(batch(batch_num, [batch_num]), &mut context)
+        }
+        assert_eq!(mempool.batches.len(), Mempool::MAX_STORED_BATCHES);
+        // still stored once ordered
+        let fetched = batch(u32::MAX, []);
+        assert!(mempool.payload(&[fetched.digest()], &mut context).is_none());
+        mempool.on_batch(fetched, &mut context);
+        assert_eq!(mempool.batches.len(), Mempool::MAX_STORED_BATCHES + 1)
+    }
+}
@@ -0,0 +1,167 @@
+// catching up a replica that falls behind by fetching the log entries it misses from peers, e.g.
+// the committed blocks of pbft, in chunks of at most `CHUNK` entries
+// the protocol decides when it is behind and what an entry is, and checks the fetched entries
+// itself, as any single peer may be faulty, e.g. an entry may only be checkable after the ones
+// in later chunks are fetched, so fetched entries are kept until applied. this only tracks the
+// progress: the transfer starts after being behind for a timeout, so entries that are merely
+// reordered do not trigger it. after the first chunk the next one is fetched as soon as a complete
+// one is accepted, and on a timeout, e.g. a lost chunk or a peer that sends no further entries,
+// the transfer starts over from the first entry that is not applied with the next peer
+// a peer that has truncated its log past the first entry answers with the state of its latest
+// truncated entry instead, which the protocol checks and restores before fetching the rest
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::context::{crypto::DigestHash, Context, ReplicaIndex, To};
+
+use super::Timer;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fetch {
+    pub start: u64,
+    pub replica_index: ReplicaIndex,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fetched<E> {
+    pub start: u64,
+    pub entries: Vec<E>,
+}
+
+#[derive(Debug)]
+pub struct StateTransfer<E> {
+    index: ReplicaIndex,
+    timer: Timer,
+    // the start of the chunk in flight
+    fetching: Option<u64>,
+    peer: ReplicaIndex,
+    // fetched and not applied yet, following the applied entries, the protocol removes the ones it
+    // applies, or clears them if they turn out to be faulty
+    pub fetched: Vec<E>,
+    pub num_applied: u64,
+    // the states restored in place of the truncated entries
+    pub num_restored: u64,
+}
+
+pub const CHUNK: usize = 16;
+
+impl<E> StateTransfer<E> {
+    pub fn new(index: ReplicaIndex, timeout: Duration) -> Self {
+        Self {
+            index,
+            timer: Timer::new(timeout),
+            fetching: None,
+            peer: index,
+            fetched: Default::default(),
+            num_applied: 0,
+            num_restored: 0,
+        }
+    }
+
+    pub fn on_behind<M>(&mut self, context: &mut Context<M>) {
+        if self.timer.id.is_none() {
+            self.timer.set(context)
+        }
+    }
+
+    // `start` is the first entry that is not applied yet
+    pub fn on_timer<M>(&mut self, start: u64, behind: bool, context: &mut Context<M>)
+    where
+        M: crate::context::crypto::Sign<Fetch> + Serialize,
+    {
+        if !behind {
+            self.timer.unset(context);
+            self.fetching = None;
+            self.fetched.clear();
+            return;
+        }
+        let num_replica = context.config().num_replica;
+        self.peer = ((self.peer as usize + 1) % num_replica) as _;
+        if self.peer == self.index {
+            self.peer = ((self.peer as usize + 1) % num_replica) as _
+        }
+        self.fetched.clear();
+        self.do_fetch(start, context)
+    }
+
+    // the number of entries of the chunk in flight, if it is the one, then the protocol checks and
+    // applies `fetched` before calling `on_applied`
+    pub fn on_fetched(&mut self, fetched: Fetched<E>) -> Option<usize> {
+        if self.fetching != Some(fetched.start) {
+            return None;
+        }
+        self.fetching = None;
+        let num_entry = fetched.entries.len();
+        self.fetched.extend(fetched.entries);
+        Some(num_entry)
+    }
+
+    // whether the state answers the chunk in flight, then the protocol checks and restores it
+    // before calling `on_applied` with no entry applied
+    pub fn on_fetched_state(&mut self, start: u64) -> bool {
+        if self.fetching != Some(start) {
+            return false;
+        }
+        self.fetching = None;
+        self.fetched.clear();
+        true
+    }
+
+    // `start` is the first entry that is not applied yet, and `more` tells the chunk is complete
+    // and accepted, so the peer probably has further entries
+    pub fn on_applied<M>(
+        &mut self,
+        start: u64,
+        num_applied: usize,
+        more: bool,
+        behind: bool,
+        context: &mut Context<M>,
+    ) where
+        M: crate::context::crypto::Sign<Fetch> + Serialize,
+    {
+        self.num_applied += num_applied as u64;
+        if !behind {
+            self.timer.unset(context);
+            self.fetched.clear();
+            return;
+        }
+        if more {
+            self.timer.reset(context);
+            self.do_fetch(start + self.fetched.len() as u64, context)
+        }
+    }
+
+    fn do_fetch<M>(&mut self, start: u64, context: &mut Context<M>)
+    where
+        M: crate::context::crypto::Sign<Fetch> + Serialize,
+    {
+        self.fetching = Some(start);
+        let fetch = Fetch {
+            start,
+            replica_index: self.index,
+        };
+        context.send(To::replica(self.peer), fetch)
+    }
+}
+
+// the chunk from `fetch.start` of the log that `entry` looks up
+pub fn serve<E>(fetch: &Fetch, entry: impl Fn(u64) -> Option<E>) -> Fetched<E> {
+    Fetched {
+        start: fetch.start,
+        entries: Vec::from_iter(
+            (fetch.start..)
+                .map(entry)
+                .take(CHUNK)
+                .map_while(|entry| entry),
+        ),
+    }
+}
+
+impl DigestHash for Fetch {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u64(self.start);
+        hasher.write_u8(self.replica_index)
+    }
+}
@@ -1,25 +1,50 @@
-use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{Hash, Hasher},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Mutex,
+    time::Duration,
+};
 
+use bincode::Options;
+use control_messages::{ClientDestination, SignatureScheme, SocketConfig, Timestamping, Transport};
 use hmac::{Hmac, Mac};
-use k256::{ecdsa::SigningKey, sha2::Sha256};
-use serde::Serialize;
+use k256::sha2::Sha256;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use self::{crypto::DigestHash, ordered_multicast::OrderedMulticast};
+use self::{
+    crypto::{DigestHash, KeyEpoch, SignaturePolicy},
+    keys::{PublicKeys, SecretKeys},
+    ordered_multicast::OrderedMulticast,
+};
 
+pub mod byzantine;
+pub mod chaos;
+pub mod clock;
 pub mod crypto;
+pub mod fragment;
+pub mod keys;
+pub mod metrics;
 pub mod ordered_multicast;
+pub mod shaping;
+pub mod simulated;
+pub mod storage;
 pub mod tokio;
+pub mod trace;
 
 pub type ReplicaIndex = u8;
 pub type ClientIndex = u16;
 
 #[derive(Debug)]
 pub enum Context<M> {
-    Tokio(tokio::Context),
+    Tokio(Box<tokio::Context>),
+    Simulated(Box<simulated::Context>),
     Phantom(std::marker::PhantomData<M>),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Host {
     Client(ClientIndex),
     Replica(ReplicaIndex),
@@ -34,6 +59,9 @@ pub enum To {
     AllReplica,
     Loopback,
     AllReplicaWithLoopback,
+    // the standby of the replica at its own address, see `Config::insert_standby`, or the replica if
+    // it has none, e.g. in simulation where the standby takes the replica's place
+    Standby(ReplicaIndex),
 }
 
 impl To {
@@ -50,6 +78,29 @@ impl<M> Context<M> {
     pub fn config(&self) -> &Config {
         match self {
             Self::Tokio(context) => &context.config,
+            Self::Simulated(context) => &context.config,
+            _ => unimplemented!(),
+        }
+    }
+
+    // misbehave on sending from now on, see `byzantine::Byzantine`
+    pub fn set_byzantine(&mut self, strategy: control_messages::ByzantineStrategy)
+    where
+        M: byzantine::Tamper + Serialize + DeserializeOwned,
+    {
+        let byzantine = Some(Mutex::new(byzantine::Byzantine::new::<M>(strategy)));
+        match self {
+            Self::Tokio(context) => context.byzantine = byzantine,
+            Self::Simulated(context) => context.byzantine = byzantine,
+            _ => unimplemented!(),
+        }
+    }
+
+    // seeded from the host's config, see `Config::rng`
+    pub fn rng(&mut self) -> &mut StdRng {
+        match self {
+            Self::Tokio(context) => &mut context.rng,
+            Self::Simulated(context) => &mut context.rng,
             _ => unimplemented!(),
         }
     }
@@ -57,9 +108,11 @@ impl<M> Context<M> {
     pub fn send<N>(&mut self, to: To, message: N)
     where
         M: crypto::Sign<N> + Serialize,
+        N: Send + 'static,
     {
         match self {
             Self::Tokio(context) => context.send::<M, _>(to, message),
+            Self::Simulated(context) => context.send::<M, _>(to, message),
             _ => unimplemented!(),
         }
     }
@@ -71,13 +124,42 @@ impl<M> Context<M> {
     {
         match self {
             Self::Tokio(context) => context.send_ordered_multicast(message),
+            Self::Simulated(context) => context.send_ordered_multicast(message),
             _ => unimplemented!(),
         }
     }
 
+    // to the sequencer of `group` rather than the one of the sender
+    pub fn send_ordered_multicast_to<N>(&mut self, group: u8, message: N)
+    where
+        N: Serialize + DigestHash,
+        OrderedMulticast<N>: Into<M>,
+    {
+        match self {
+            Self::Tokio(context) => context.send_ordered_multicast_to(group, message),
+            Self::Simulated(context) => context.send_ordered_multicast_to(group, message),
+            _ => unimplemented!(),
+        }
+    }
+
+    // where a client should send its request to, `None` for ordered multicast
+    // primary is always replica 0 since clients do not track view
+    pub fn request_destination(&mut self, default: ClientDestination) -> Option<To> {
+        match self.config().client_destination.unwrap_or(default) {
+            ClientDestination::Primary => Some(To::replica(0)),
+            ClientDestination::Random => {
+                let num_replica = self.config().num_replica;
+                Some(To::replica(self.rng().gen_range(0..num_replica) as _))
+            }
+            ClientDestination::All => Some(To::AllReplica),
+            ClientDestination::Multicast => None,
+        }
+    }
+
     pub fn idle_hint(&self) -> bool {
         match self {
             Self::Tokio(context) => context.idle_hint(),
+            Self::Simulated(context) => context.idle_hint(),
             _ => unimplemented!(),
         }
     }
@@ -86,12 +168,24 @@ impl<M> Context<M> {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum TimerId {
     Tokio(tokio::TimerId),
+    Simulated(simulated::TimerId),
 }
 
 impl<M> Context<M> {
     pub fn set(&mut self, duration: Duration) -> TimerId {
         match self {
             Self::Tokio(context) => TimerId::Tokio(context.set(duration)),
+            Self::Simulated(context) => TimerId::Simulated(context.set(duration)),
+            _ => unimplemented!(),
+        }
+    }
+
+    // a timer that is expected to stay armed even when there is nothing to do, e.g. a progress
+    // watchdog, so it does not prevent a simulated network from being quiescent
+    pub fn set_sentinel(&mut self, duration: Duration) -> TimerId {
+        match self {
+            Self::Tokio(context) => TimerId::Tokio(context.set(duration)),
+            Self::Simulated(context) => TimerId::Simulated(context.set_sentinel(duration)),
             _ => unimplemented!(),
         }
     }
@@ -99,6 +193,7 @@ impl<M> Context<M> {
     pub fn unset(&mut self, id: TimerId) {
         match (self, id) {
             (Self::Tokio(context), TimerId::Tokio(id)) => context.unset(id),
+            (Self::Simulated(context), TimerId::Simulated(id)) => context.unset(id),
             _ => unimplemented!(),
         }
     }
@@ -117,6 +212,18 @@ pub trait Receivers {
     fn on_timer(&mut self, receiver: Host, id: TimerId);
 
     fn on_pace(&mut self) {}
+
+    // sampled by the dispatch on every pace, see `metrics::Metrics`
+    fn gauges(&self) -> metrics::Gauges {
+        Default::default()
+    }
+
+    // whether the message is too old to matter, e.g. of a past view or an executed block, which is
+    // dropped before verification if `Config::reject_stale` is set
+    #[allow(unused_variables)]
+    fn is_stale(&self, remote: Host, message: &Self::Message) -> bool {
+        false
+    }
 }
 
 pub trait OrderedMulticastReceivers
@@ -132,14 +239,90 @@ pub struct Config {
     pub num_replica: usize,
     pub hosts: HashMap<Host, ConfigHost>,
     pub remotes: HashMap<SocketAddr, Host>,
-    pub multicast_addr: Option<SocketAddr>,
+    // replica index => its standby, which signs with keys of its own that are accepted as the
+    // replica's, see `Config::insert_standby`
+    pub standbys: HashMap<ReplicaIndex, ConfigHost>,
+    // one per multicast group, each with a sequencer and sequence numbers of its own, which the
+    // clients are spread across by index, see `ordered_multicast::Merge`
+    pub multicast_addrs: Vec<SocketAddr>,
+    // admit ordered multicast only from these IPs if set, e.g. the relays, or the clients when the
+    // switch forwards their packets
+    pub multicast_sources: Option<HashSet<IpAddr>>,
     pub hmac: Hmac<Sha256>,
+    // clients and replicas MAC the messages between them with a key per pair derived from this
+    // secret, instead of `hmac` that every host shares, see `crypto::ClientKeys`
+    pub client_secret: Option<Vec<u8>>,
+    pub signature_policy: SignaturePolicy,
+    // the scheme of replicas' public key signatures, K256, Ed25519 or BLS, unless `signature_policy`
+    // overrides it for the message class
+    pub public_scheme: SignatureScheme,
+    // the epoch of the replica keys in `hosts`, which counts up as they are rotated, see
+    // `crypto::KeyRing`
+    pub key_epoch: KeyEpoch,
+    // the public keys of replicas of the epoch before `key_epoch`, which are still accepted for the
+    // duration after starting, so a host that starts after the others rotate accepts what they
+    // signed just before
+    pub previous_keys: Option<(HashMap<ReplicaIndex, PublicKeys>, Duration)>,
+    // test only, replace K256 signatures of replicas with an insecure but cheap keyed hash
+    pub fake_crypto: bool,
+    pub socket: SocketConfig,
+    // maximum number of clients whose latest reply is kept by a replica
+    pub client_table_capacity: usize,
+    // maximum number of entries of each map of messages waiting for something not known yet, see
+    // `Waiting`
+    pub waiting_capacity: usize,
+    // overrides the request destination of clients
+    pub client_destination: Option<ClientDestination>,
+    // drop stale messages before verifying them, see `Receivers::is_stale`
+    pub reject_stale: bool,
+    // threads that deserialize and verify client requests for the tokio dispatch, and that sign and
+    // send the replies of every replica over udp, where the dispatch handles every message on its
+    // own thread and replicas send inline if 0
+    pub request_workers: usize,
+    // take a checkpoint of the app at the first block boundary after every this many ops, 0 to not
+    // take any, see `App::checkpoint`
+    pub checkpoint_interval: u64,
+    // replicas ask clients to back off once this many ops are ordered but not executed yet, and hold
+    // back ordering past as many confirmed ones, 0 to do neither, neo only
+    pub max_inflight: u64,
+    // clients append `ordered_multicast::Timing` to ordered multicast, for replicas to break down
+    // the latency of the sequencing hop
+    pub sequencing_timing: bool,
+    // all randomness of the host is derived from this, see `Config::rng`
+    pub seed: u64,
+}
+
+// derive a seed for `component` from `seed`, so components are independently random yet
+// reproducible from the single seed
+pub fn sub_seed(seed: u64, component: impl Hash) -> u64 {
+    // SipHash with fixed keys rather than the default hasher, whose algorithm is free to change
+    // between Rust releases, so a seed reproduces its run with binaries built by any toolchain
+    let mut hasher = siphasher::sip::SipHasher13::new();
+    seed.hash(&mut hasher);
+    component.hash(&mut hasher);
+    hasher.finish()
+}
+
+// the values in a file that are bincode encoded back to back, e.g. a trace or a history
+pub fn read_entries<T: DeserializeOwned>(path: &Path) -> std::io::Result<Vec<T>> {
+    let buf = std::fs::read(path)?;
+    let mut buf = &*buf;
+    let mut entries = Vec::new();
+    while !buf.is_empty() {
+        let entry = bincode::options()
+            .deserialize_from(&mut buf)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        entries.push(entry)
+    }
+    Ok(entries)
 }
 
 #[derive(Debug, Clone)]
 pub struct ConfigHost {
     pub addr: SocketAddr,
-    pub signing_key: Option<SigningKey>,
+    // of replicas, where only the own ones are known if loaded by `Config::load_keys`
+    pub secret_keys: Option<SecretKeys>,
+    pub public_keys: Option<PublicKeys>,
 }
 
 impl Config {
@@ -149,16 +332,22 @@ impl Config {
         let mut num_replica = 0;
         for (&host, &addr) in &addrs {
             remotes.insert(addr, host);
-            let signing_key;
-            match host {
-                Host::Client(_) => signing_key = None,
+            let secret_keys = match host {
+                Host::Client(_) => None,
                 Host::Replica(index) => {
-                    signing_key = Some(Self::k256(index));
                     num_replica += 1;
+                    Some(SecretKeys::from_seed(&Self::seed(index)).unwrap())
                 }
                 Host::Multicast | Host::UnkownMulticastSender => unimplemented!(),
             };
-            hosts.insert(host, ConfigHost { addr, signing_key });
+            hosts.insert(
+                host,
+                ConfigHost {
+                    addr,
+                    public_keys: secret_keys.as_ref().map(SecretKeys::public),
+                    secret_keys,
+                },
+            );
         }
         assert_eq!(remotes.len(), addrs.len());
         assert!(num_faulty * 3 < num_replica);
@@ -167,17 +356,114 @@ impl Config {
             num_replica,
             hosts,
             remotes,
-            multicast_addr: None,
+            standbys: Default::default(),
+            multicast_addrs: Default::default(),
+            multicast_sources: None,
             // simplified symmetrical keys setup
             // also reduce client-side overhead a little bit by only need to sign once for broadcast
             hmac: Hmac::new_from_slice("shared".as_bytes()).unwrap(),
+            client_secret: None,
+            signature_policy: Default::default(),
+            public_scheme: SignatureScheme::K256,
+            key_epoch: 0,
+            previous_keys: None,
+            fake_crypto: false,
+            socket: Default::default(),
+            client_table_capacity: usize::MAX,
+            waiting_capacity: 1 << 16,
+            client_destination: None,
+            reject_stale: false,
+            request_workers: 0,
+            checkpoint_interval: 0,
+            max_inflight: 0,
+            sequencing_timing: false,
+            seed: 0,
+        }
+    }
+
+    // one group if there is no address, e.g. in simulation, which only counts the addresses
+    pub fn num_multicast_group(&self) -> usize {
+        self.multicast_addrs.len().max(1)
+    }
+
+    pub fn multicast_group(&self, index: ClientIndex) -> u8 {
+        (index as usize % self.num_multicast_group()) as _
+    }
+
+    pub fn rng(&self, component: impl Hash) -> StdRng {
+        StdRng::seed_from_u64(sub_seed(self.seed, component))
+    }
+
+    // accept messages from `addr` as from the replica, i.e. its standby
+    pub fn insert_standby(&mut self, index: ReplicaIndex, addr: SocketAddr) {
+        let evicted = self.remotes.insert(addr, Host::Replica(index));
+        assert!(evicted.is_none());
+        let public_keys = Self::standby_keys(index).public();
+        self.standbys.insert(
+            index,
+            ConfigHost {
+                addr,
+                secret_keys: None,
+                public_keys: Some(public_keys),
+            },
+        );
+    }
+
+    // run as the standby of replica `index`, i.e. sign with the standby's keys instead of the
+    // replica's
+    pub fn sign_as_standby(&mut self, index: ReplicaIndex) {
+        let keys = Self::standby_keys(index);
+        let host_config = self.hosts.get_mut(&Host::Replica(index)).unwrap();
+        host_config.public_keys = Some(keys.public());
+        host_config.secret_keys = Some(keys)
+    }
+
+    // derived as the replicas' ones, there is no standby in the key files of `keygen`
+    fn standby_keys(index: ReplicaIndex) -> SecretKeys {
+        let k = format!("standby-{index}");
+        let mut seed = [0; 32];
+        seed[..k.len()].copy_from_slice(k.as_bytes());
+        SecretKeys::from_seed(&seed).unwrap()
+    }
+
+    // replace the keys derived from replica indexes with the ones in `dir` generated by `keygen`,
+    // keeping the secret keys of replica `index` only, or of none if not a replica
+    pub fn load_keys(
+        &mut self,
+        dir: impl AsRef<Path>,
+        index: Option<ReplicaIndex>,
+    ) -> std::io::Result<()> {
+        let (secret_keys, mut public_keys) = keys::load(dir.as_ref(), index)?;
+        for (&host, host_config) in &mut self.hosts {
+            let Host::Replica(host_index) = host else {
+                continue;
+            };
+            let keys = public_keys.remove(&host_index).ok_or(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no public keys of replica {host_index}"),
+            ))?;
+            host_config.secret_keys = secret_keys.clone().filter(|_| Some(host_index) == index);
+            host_config.public_keys = Some(keys)
         }
+        Ok(())
     }
 
-    fn k256(index: ReplicaIndex) -> SigningKey {
+    fn seed(index: ReplicaIndex) -> [u8; 32] {
         let k = format!("replica-{index}");
         let mut buf = [0; 32];
-        buf[..k.as_bytes().len()].copy_from_slice(k.as_bytes());
-        SigningKey::from_slice(&buf).unwrap()
+        buf[..k.len()].copy_from_slice(k.as_bytes());
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // pinned, so that a change of the hash breaks the test rather than the reproducibility of the
+    // recorded seeds
+    #[test]
+    fn sub_seed_stable() {
+        assert_eq!(sub_seed(42, "client"), 8265551698151109044);
     }
 }
@@ -0,0 +1,119 @@
+// a faulty replica that deviates on what it sends to the others, installed on its context with
+// `Context::set_byzantine`, so the experiments of f faulty replicas run f real replicas that
+// misbehave, instead of counting unreachable ones as faulty
+// messages to itself are genuine, i.e. the replica does not fool itself
+
+use bincode::Options;
+use control_messages::ByzantineStrategy as Strategy;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{crypto::Signer, Host};
+
+// how a protocol's messages are tampered, the ones that a strategy does not apply to are sent as
+// they are
+pub trait Tamper: Sized {
+    // a proposal that conflicts with this one
+    #[allow(unused_variables)]
+    fn conflict(&self, signer: &Signer) -> Option<Self> {
+        None
+    }
+
+    fn is_vote(&self) -> bool {
+        false
+    }
+
+    // this message with the digest it carries garbled
+    #[allow(unused_variables)]
+    fn corrupt(&self, signer: &Signer) -> Option<Self> {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct Byzantine {
+    pub strategy: Strategy,
+    last_vote: Option<Vec<u8>>,
+    // bound to the message type on installing
+    tamper: fn(&mut Self, &Signer, &[u8]) -> Tampered,
+    pub num_tampered: u64,
+}
+
+#[derive(Debug)]
+pub enum Tampered {
+    Genuine,
+    Drop,
+    Replace(Vec<u8>),
+    // the replicas of odd index receive the conflicting message, the others the genuine one
+    Equivocate(Vec<u8>),
+}
+
+impl Byzantine {
+    pub fn new<M>(strategy: Strategy) -> Self
+    where
+        M: Tamper + Serialize + DeserializeOwned,
+    {
+        Self {
+            strategy,
+            last_vote: None,
+            tamper: tamper::<M>,
+            num_tampered: 0,
+        }
+    }
+
+    pub fn tamper(&mut self, signer: &Signer, buf: &[u8]) -> Tampered {
+        let tampered = (self.tamper)(self, signer, buf);
+        if !matches!(tampered, Tampered::Genuine) {
+            self.num_tampered += 1
+        }
+        tampered
+    }
+}
+
+impl Tampered {
+    // what `remote` receives in place of `buf`, none if nothing
+    pub fn remote<'a>(&'a self, buf: &'a [u8], remote: Host) -> Option<&'a [u8]> {
+        match (self, remote) {
+            (Self::Genuine, _) => Some(buf),
+            (Self::Drop, _) => None,
+            (Self::Replace(buf), _) => Some(buf),
+            (Self::Equivocate(conflict), Host::Replica(index)) if index % 2 == 1 => Some(conflict),
+            (Self::Equivocate(_), _) => Some(buf),
+        }
+    }
+}
+
+fn tamper<M>(byzantine: &mut Byzantine, signer: &Signer, buf: &[u8]) -> Tampered
+where
+    M: Tamper + Serialize + DeserializeOwned,
+{
+    if byzantine.strategy == Strategy::Silent {
+        return Tampered::Drop;
+    }
+    let message = bincode::options().deserialize::<M>(buf).unwrap();
+    let serialize = |message: M| bincode::options().serialize(&message).unwrap();
+    match byzantine.strategy {
+        Strategy::Silent => unreachable!(),
+        Strategy::Equivocate => match message.conflict(signer) {
+            Some(conflict) => Tampered::Equivocate(serialize(conflict)),
+            None => Tampered::Genuine,
+        },
+        Strategy::StaleVote if message.is_vote() => {
+            match byzantine.last_vote.replace(buf.to_vec()) {
+                Some(last_vote) => Tampered::Replace(last_vote),
+                None => Tampered::Drop,
+            }
+        }
+        Strategy::StaleVote => Tampered::Genuine,
+        Strategy::CorruptDigest => match message.corrupt(signer) {
+            Some(corrupted) => Tampered::Replace(serialize(corrupted)),
+            None => Tampered::Genuine,
+        },
+    }
+}
+
+// flip every bit, so the digest matches nothing
+pub fn garble(digest: &mut [u8; 32]) {
+    for byte in digest {
+        *byte = !*byte
+    }
+}
@@ -0,0 +1,113 @@
+// faults injected where replicas receive messages, so a `control_messages::Scenario` runs the same
+// on the simulated network and on the testbed, where every replica applies the actions to itself
+
+use std::{collections::HashSet, sync::Mutex, time::Duration};
+
+use control_messages::ChaosAction;
+use rand::Rng;
+
+use super::{Host, ReplicaIndex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ingress {
+    Deliver,
+    Drop,
+    Delay(Duration),
+}
+
+impl Ingress {
+    // both apply, i.e. dropped by either, or delayed by the sum
+    pub fn and(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Drop, _) | (_, Self::Drop) => Self::Drop,
+            (Self::Delay(delay), Self::Delay(other_delay)) => Self::Delay(delay + other_delay),
+            (Self::Delay(delay), _) | (_, Self::Delay(delay)) => Self::Delay(delay),
+            (Self::Deliver, Self::Deliver) => Self::Deliver,
+        }
+    }
+
+    // same as `and`, but `other` is not taken for a dropped message, e.g. the link it would queue on
+    pub fn and_then(self, other: impl FnOnce() -> Self) -> Self {
+        match self {
+            Self::Drop => Self::Drop,
+            _ => self.and(other()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Chaos {
+    // the replica that receives ordered multicast, which the tokio context does not tell apart
+    local: Option<ReplicaIndex>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    drop_rate: f64,
+    delay: Duration,
+    reorder: (f64, Duration),
+    isolated: HashSet<ReplicaIndex>,
+    partition: Option<u128>,
+}
+
+impl Chaos {
+    pub fn new(local: Option<ReplicaIndex>) -> Self {
+        Self {
+            local,
+            state: Default::default(),
+        }
+    }
+
+    pub fn apply(&self, action: ChaosAction) {
+        let state = &mut *self.state.lock().unwrap();
+        match action {
+            ChaosAction::Drop(rate) => state.drop_rate = rate,
+            ChaosAction::Delay(delay) => state.delay = delay,
+            ChaosAction::Reorder(rate, delay) => state.reorder = (rate, delay),
+            ChaosAction::Isolate(index) => {
+                state.isolated.insert(index);
+            }
+            ChaosAction::Rejoin(index) => {
+                state.isolated.remove(&index);
+            }
+            ChaosAction::Partition(side) => state.partition = Some(side),
+            ChaosAction::Heal => state.partition = None,
+        }
+    }
+
+    // `receiver` is `Host::Multicast` for ordered multicast, which is received by the local replica
+    // if there is one
+    pub fn ingress(&self, receiver: Host, remote: Host, rng: &mut impl Rng) -> Ingress {
+        let state = &*self.state.lock().unwrap();
+        let receiver = match receiver {
+            Host::Multicast => self.local.map(Host::Replica),
+            receiver => Some(receiver),
+        };
+        let isolated =
+            |host| matches!(host, Some(Host::Replica(index)) if state.isolated.contains(&index));
+        if isolated(receiver) || isolated(Some(remote)) {
+            return Ingress::Drop;
+        }
+        if let (Some(side), Some(Host::Replica(receiver)), Host::Replica(remote)) =
+            (state.partition, receiver, remote)
+        {
+            let on_side = |index: ReplicaIndex| side.checked_shr(index as _).unwrap_or(0) & 1;
+            if on_side(receiver) != on_side(remote) {
+                return Ingress::Drop;
+            }
+        }
+        if state.drop_rate != 0. && rng.gen_bool(state.drop_rate) {
+            return Ingress::Drop;
+        }
+        let mut delay = state.delay;
+        if state.reorder.0 != 0. && rng.gen_bool(state.reorder.0) {
+            delay += state.reorder.1
+        }
+        if delay.is_zero() {
+            Ingress::Deliver
+        } else {
+            Ingress::Delay(delay)
+        }
+    }
+}
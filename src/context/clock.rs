@@ -0,0 +1,119 @@
+// cheap timestamps for latency instrumentation, which takes one on every message
+// on x86_64 this reads TSC (assumed invariant, i.e. constant rate and synchronized across cores)
+// and converts to wall time with a rate calibrated against `Instant` once per process
+// elsewhere it falls back to `Instant`, with the rate calibrated to roughly one nanosecond per tick
+
+use std::{
+    ops::Sub,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(u64);
+
+#[derive(Debug)]
+struct Calibration {
+    nanos_per_tick: f64,
+}
+
+static CALIBRATION: OnceLock<Calibration> = OnceLock::new();
+#[cfg(not(target_arch = "x86_64"))]
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+const CALIBRATION_DURATION: Duration = Duration::from_millis(20);
+
+// blocks for the calibration duration on the first call, so call this on startup instead of
+// paying it on the first timestamp
+pub fn calibrate() {
+    calibration();
+}
+
+fn calibration() -> &'static Calibration {
+    CALIBRATION.get_or_init(|| {
+        let instant = Instant::now();
+        let start = ticks();
+        // busy wait instead of sleeping, so the measured interval is not stretched by scheduling
+        while instant.elapsed() < CALIBRATION_DURATION {
+            std::hint::spin_loop()
+        }
+        let end = ticks();
+        let elapsed = instant.elapsed();
+        Calibration {
+            nanos_per_tick: elapsed.as_nanos() as f64 / end.saturating_sub(start).max(1) as f64,
+        }
+    })
+}
+
+#[cfg(target_arch = "x86_64")]
+fn ticks() -> u64 {
+    // SAFETY: RDTSC is available on every x86_64 processor and reads no memory
+    #[allow(unused_unsafe)]
+    unsafe {
+        std::arch::x86_64::_rdtsc()
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn ticks() -> u64 {
+    EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as _
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        calibration();
+        Self(ticks())
+    }
+
+    pub fn duration_since(&self, earlier: Self) -> Duration {
+        let ticks = self.0.saturating_sub(earlier.0);
+        Duration::from_nanos((ticks as f64 * calibration().nanos_per_tick) as _)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+}
+
+impl Sub for Timestamp {
+    type Output = Duration;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.duration_since(rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monotonic() {
+        let mut last = Timestamp::now();
+        for _ in 0..1000 {
+            let now = Timestamp::now();
+            assert!(now >= last);
+            last = now
+        }
+    }
+
+    // a preemption between taking the timestamp and the instant at either end skews one run, so
+    // the best of a few runs is checked, against a tolerance loose enough for a loaded machine
+    #[test]
+    fn accuracy() {
+        calibrate();
+        for duration in [Duration::from_millis(10), Duration::from_millis(50)] {
+            let mut errors = Vec::new();
+            for _ in 0..5 {
+                let (start, instant) = (Timestamp::now(), Instant::now());
+                while instant.elapsed() < duration {
+                    std::hint::spin_loop()
+                }
+                let (measured, expected) = (start.elapsed(), instant.elapsed());
+                errors.push((measured.as_secs_f64() / expected.as_secs_f64() - 1.).abs());
+            }
+            let error = errors.iter().copied().fold(f64::INFINITY, f64::min);
+            assert!(error < 0.1, "{duration:?} errors {errors:?}");
+        }
+    }
+}
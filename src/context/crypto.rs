@@ -1,22 +1,38 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering::SeqCst},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
+use arc_swap::ArcSwap;
+use control_messages::{SignatureScheme, Strictness, StrictnessPhase};
 use hmac::{Hmac, Mac};
 use k256::{
-    ecdsa::{SigningKey, VerifyingKey},
     schnorr::signature::{DigestSigner, DigestVerifier},
     sha2::{Digest, Sha256},
 };
 use serde::{Deserialize, Serialize};
 
 use super::{
+    clock::Timestamp,
+    keys::{PublicKeys, SecretKeys},
     ordered_multicast::{OrderedMulticast, Variant},
-    Config, Host, ReplicaIndex,
+    ClientIndex, Config, Host, ReplicaIndex,
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+// the version of replica keys that a public key signature is made with, see `KeyRing`
+pub type KeyEpoch = u8;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Signed<M> {
     pub inner: M,
     pub signature: Signature,
+    // ignored by MACs, which are not rotated
+    pub key_epoch: KeyEpoch,
 }
 
 impl<M> std::ops::Deref for Signed<M> {
@@ -27,24 +43,89 @@ impl<M> std::ops::Deref for Signed<M> {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Signature {
     Plain,
     K256(k256::ecdsa::Signature),
+    Ed25519(ed25519_dalek::Signature),
+    // boxed as it is several times larger than the others
+    Bls(Box<blst::min_pk::Signature>),
     Hmac([u8; 32]),
+    // a client's MACs of the message for each replica, indexed by replica, see `ClientKeys`
+    Hmacs(Vec<[u8; 32]>),
+    // test only stand-in of `K256`, see `Config::fake_crypto`
+    Fake(u64),
+}
+
+// domain separation tag of BLS signatures, the basic scheme of the IETF draft
+const BLS_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_NUL_";
+
+// overrides the scheme chosen by `Sign` implementations, keyed by message class i.e. the name of
+// the signed message type without its module path, e.g. "Prepare"
+#[derive(Debug, Clone, Default)]
+pub struct SignaturePolicy(HashMap<String, SignatureScheme>);
+
+impl SignaturePolicy {
+    pub fn insert(&mut self, class: impl Into<String>, scheme: SignatureScheme) {
+        self.0.insert(class.into(), scheme);
+    }
+
+    fn scheme<M>(&self) -> Option<SignatureScheme> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let class = std::any::type_name::<M>().rsplit("::").next().unwrap();
+        self.0.get(class).copied()
+    }
 }
 
 impl<M: DigestHash> DigestHash for Signed<M> {
     fn hash(&self, hasher: &mut impl std::hash::Hasher) {
         self.inner.hash(hasher);
-        match &self.signature {
+        self.signature.hash(hasher)
+    }
+}
+
+impl DigestHash for Signature {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        match self {
             Signature::Plain => {} // TODO
             Signature::K256(signature) => hasher.write(&signature.to_bytes()),
+            Signature::Ed25519(signature) => hasher.write(&signature.to_bytes()),
+            Signature::Bls(signature) => hasher.write(&signature.to_bytes()),
             Signature::Hmac(codes) => hasher.write(codes),
+            Signature::Hmacs(codes) => codes.iter().for_each(|code| hasher.write(code)),
+            Signature::Fake(code) => hasher.write_u64(*code),
         }
     }
 }
 
+impl Signature {
+    // one signature that stands for the BLS signatures of the same message by several replicas,
+    // and is checked at the cost of one, see `Verifier::verify_aggregated`, none if any of the
+    // signatures is not BLS, or they are made with the keys of different epochs
+    pub fn aggregate<'a, M: 'a>(messages: impl IntoIterator<Item = &'a Signed<M>>) -> Option<Self> {
+        let mut key_epoch = None;
+        let signatures = messages
+            .into_iter()
+            .map(|message| match &message.signature {
+                Self::Bls(signature)
+                    if *key_epoch.get_or_insert(message.key_epoch) == message.key_epoch =>
+                {
+                    Some(&**signature)
+                }
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if signatures.is_empty() {
+            return None;
+        }
+        // every signature is checked on receiving
+        let signature = blst::min_pk::AggregateSignature::aggregate(&signatures, false).ok()?;
+        Some(Self::Bls(Box::new(signature.to_signature())))
+    }
+}
+
 pub enum Hasher {
     Sha256(Sha256),
     Hmac(Hmac<Sha256>),
@@ -88,6 +169,12 @@ impl<T: DigestHash> DigestHash for [T] {
     }
 }
 
+impl<T: DigestHash> DigestHash for Vec<T> {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        self[..].hash(hasher)
+    }
+}
+
 impl Hasher {
     pub fn sha256(message: &impl DigestHash) -> Sha256 {
         let mut hasher = Self::Sha256(Sha256::new());
@@ -108,6 +195,16 @@ impl Hasher {
         };
     }
 
+    // deterministic and cheap keyed hash, which provides no security at all since the key is
+    // simply the signer's index
+    pub fn fake(message: &impl DigestHash, index: ReplicaIndex) -> u64 {
+        use std::hash::Hasher as _;
+        // fixed SipHash keys, so hosts built with different toolchains agree
+        let mut hasher = siphasher::sip::SipHasher13::new_with_keys(index as _, 0);
+        message.hash(&mut hasher);
+        hasher.finish()
+    }
+
     pub fn hmac(message: &impl DigestHash, hmac: Hmac<Sha256>) -> [u8; 32] {
         let mut hasher = Self::Hmac(hmac);
         message.hash(&mut hasher);
@@ -120,29 +217,309 @@ impl Hasher {
 
 #[derive(Debug, Clone)]
 pub struct Signer {
-    pub signing_key: Option<SigningKey>,
+    // none for clients
+    pub index: Option<ReplicaIndex>,
+    pub key_ring: KeyRing,
+    // the scheme of `sign_public`
+    pub public_scheme: SignatureScheme,
     pub hmac: Hmac<Sha256>,
+    // the keys shared with the other side if `Config::client_secret` is set, which MAC the
+    // messages between clients and replicas in place of `hmac`
+    pub client_keys: Option<ClientKeys>,
+    // the host that the message being signed is sent to, if it is the only one, so replies are
+    // MACed with the key of the client they are sent to, see `Context::send`
+    pub receiver: Cell<Option<Host>>,
+    pub policy: SignaturePolicy,
+    // sign with `Signature::Fake` keyed by this index instead of the signing key
+    pub fake_index: Option<ReplicaIndex>,
+}
+
+// the MAC key of a client and a replica, so neither a client nor a replica can forge the messages
+// between another pair
+pub fn client_hmac(secret: &[u8], index: ClientIndex, replica_index: ReplicaIndex) -> Hmac<Sha256> {
+    let key = Hmac::<Sha256>::new_from_slice(secret)
+        .unwrap()
+        .chain_update(b"client")
+        .chain_update(index.to_be_bytes())
+        .chain_update(replica_index.to_be_bytes())
+        .finalize()
+        .into_bytes();
+    Hmac::new_from_slice(&key).unwrap()
+}
+
+// the keys that `host` shares with each host of the other side, i.e. every replica for a client
+// and every client for a replica, derived ahead for the configured hosts and on demand for the
+// others
+#[derive(Debug, Clone)]
+pub struct ClientKeys {
+    secret: Vec<u8>,
+    host: Host,
+    num_replica: usize,
+    keys: HashMap<Host, Hmac<Sha256>>,
+}
+
+impl ClientKeys {
+    pub fn new(config: &Config, host: Host) -> Option<Self> {
+        let secret = config.client_secret.clone()?;
+        let mut keys = Self {
+            secret,
+            host,
+            num_replica: config.num_replica,
+            keys: Default::default(),
+        };
+        keys.keys = HashMap::from_iter(config.hosts.keys().filter_map(|&peer| {
+            let key = keys.derive(peer)?;
+            Some((peer, key))
+        }));
+        Some(keys)
+    }
+
+    fn derive(&self, peer: Host) -> Option<Hmac<Sha256>> {
+        match (self.host, peer) {
+            (Host::Client(index), Host::Replica(replica_index))
+            | (Host::Replica(replica_index), Host::Client(index)) => {
+                Some(client_hmac(&self.secret, index, replica_index))
+            }
+            _ => None,
+        }
+    }
+
+    fn get(&self, peer: Host) -> Option<Hmac<Sha256>> {
+        self.keys.get(&peer).cloned().or_else(|| self.derive(peer))
+    }
+}
+
+// the keys of replicas, versioned by epoch and shared by the signer and verifier of a dispatch, so
+// that they can be rotated while running
+// after a rotation, signatures of the previous epoch are still accepted for a grace window, so the
+// messages signed before every replica rotates are not lost
+// the keys are loaded on every signature and verification, so a rotation swaps in a new state
+// instead of them being locked
+#[derive(Debug, Clone)]
+pub struct KeyRing(Arc<ArcSwap<KeyRingState>>);
+
+#[derive(Debug, Clone)]
+struct KeyRingState {
+    epoch: KeyEpoch,
+    secret_keys: HashMap<ReplicaIndex, SecretKeys>,
+    public_keys: HashMap<ReplicaIndex, PublicKeys>,
+    // of the epoch before, and until when they are accepted
+    previous: Option<(HashMap<ReplicaIndex, PublicKeys>, Instant)>,
+    // of the replicas' standbys, accepted as the replicas' in every epoch, see `Config::standbys`
+    standby_keys: HashMap<ReplicaIndex, PublicKeys>,
+}
+
+impl KeyRing {
+    pub fn new(config: &Config) -> Self {
+        let (mut secret_keys, mut public_keys) = (HashMap::new(), HashMap::new());
+        for (&host, host_config) in &config.hosts {
+            let Host::Replica(index) = host else {
+                continue;
+            };
+            if let Some(keys) = &host_config.secret_keys {
+                secret_keys.insert(index, keys.clone());
+            }
+            if let Some(keys) = &host_config.public_keys {
+                public_keys.insert(index, keys.clone());
+            }
+        }
+        let previous = config
+            .previous_keys
+            .clone()
+            .map(|(keys, grace)| (keys, Instant::now() + grace));
+        let standby_keys =
+            HashMap::from_iter(config.standbys.iter().filter_map(|(&index, host_config)| {
+                Some((index, host_config.public_keys.clone()?))
+            }));
+        Self(Arc::new(ArcSwap::from_pointee(KeyRingState {
+            epoch: config.key_epoch,
+            secret_keys,
+            public_keys,
+            previous,
+            standby_keys,
+        })))
+    }
+
+    pub fn epoch(&self) -> KeyEpoch {
+        self.0.load().epoch
+    }
+
+    // sign with `secret_keys` from now on, and accept the signatures of the current epoch for
+    // `grace` more
+    // the rotation is refused if it leaves out the keys of any replica, so every host keeps
+    // signing and verifying
+    pub fn rotate(
+        &self,
+        secret_keys: HashMap<ReplicaIndex, SecretKeys>,
+        public_keys: HashMap<ReplicaIndex, PublicKeys>,
+        grace: Duration,
+    ) -> Result<KeyEpoch, String> {
+        let state = self.0.load_full();
+        for (index, kind, rotated) in state
+            .secret_keys
+            .keys()
+            .map(|index| (index, "secret", secret_keys.contains_key(index)))
+            .chain(
+                state
+                    .public_keys
+                    .keys()
+                    .map(|index| (index, "public", public_keys.contains_key(index))),
+            )
+        {
+            if !rotated {
+                return Err(format!("no {kind} keys of replica {index}"));
+            }
+        }
+        let epoch = state.epoch.wrapping_add(1);
+        self.0.store(Arc::new(KeyRingState {
+            epoch,
+            secret_keys,
+            public_keys,
+            previous: Some((state.public_keys.clone(), Instant::now() + grace)),
+            standby_keys: state.standby_keys.clone(),
+        }));
+        Ok(epoch)
+    }
+
+    fn public_keys(&self, epoch: KeyEpoch, index: ReplicaIndex) -> Result<PublicKeys, Invalid> {
+        let state = self.0.load();
+        let keys = if epoch == state.epoch {
+            &state.public_keys
+        } else {
+            match &state.previous {
+                Some((keys, deadline))
+                    if epoch == state.epoch.wrapping_sub(1) && Instant::now() < *deadline =>
+                {
+                    keys
+                }
+                _ => return Err(Invalid::Public),
+            }
+        };
+        keys.get(&index).cloned().ok_or(Invalid::Public)
+    }
+
+    // with the keys of the replica, or else of its standby
+    fn verify_public(
+        &self,
+        epoch: KeyEpoch,
+        index: ReplicaIndex,
+        verify: impl Fn(&PublicKeys) -> bool,
+    ) -> Result<(), Invalid> {
+        let keys = self.public_keys(epoch, index);
+        if keys.as_ref().is_ok_and(&verify) {
+            return Ok(());
+        }
+        match self.0.load().standby_keys.get(&index) {
+            Some(standby_keys) if verify(standby_keys) => Ok(()),
+            _ => keys.and(Err(Invalid::Public)),
+        }
+    }
+
+    fn has_standby(&self, index: ReplicaIndex) -> bool {
+        self.0.load().standby_keys.contains_key(&index)
+    }
 }
 
 impl Signer {
+    pub fn new(config: &Config, host: Host) -> Self {
+        Self {
+            index: match host {
+                Host::Replica(index) => Some(index),
+                _ => None,
+            },
+            key_ring: KeyRing::new(config),
+            public_scheme: config.public_scheme,
+            hmac: config.hmac.clone(),
+            client_keys: ClientKeys::new(config, host),
+            receiver: Default::default(),
+            policy: config.signature_policy.clone(),
+            fake_index: match host {
+                Host::Replica(index) if config.fake_crypto => Some(index),
+                _ => None,
+            },
+        }
+    }
+
     pub fn sign_public<M>(&self, message: M) -> Signed<M>
     where
         M: DigestHash,
     {
-        let digest = Hasher::sha256(&message);
-        Signed {
-            inner: message,
-            signature: Signature::K256(self.signing_key.as_ref().unwrap().sign_digest(digest)),
-        }
+        self.sign(message, self.public_scheme)
     }
 
     pub fn sign_private<M>(&self, message: M) -> Signed<M>
     where
         M: DigestHash,
     {
+        self.sign(message, SignatureScheme::Hmac)
+    }
+
+    // for the shares that are aggregated into one signature by whoever collects them, see
+    // `Signature::aggregate`
+    pub fn sign_aggregatable<M>(&self, message: M) -> Signed<M>
+    where
+        M: DigestHash,
+    {
+        self.sign(message, SignatureScheme::Bls)
+    }
+
+    fn sign<M>(&self, message: M, scheme: SignatureScheme) -> Signed<M>
+    where
+        M: DigestHash,
+    {
+        let scheme = self.policy.scheme::<M>().unwrap_or(scheme);
+        let key_ring = self.key_ring.0.load();
+        let key_epoch = key_ring.epoch;
+        let keys = match scheme {
+            SignatureScheme::K256 | SignatureScheme::Ed25519 if self.fake_index.is_some() => None,
+            SignatureScheme::K256 | SignatureScheme::Ed25519 | SignatureScheme::Bls => {
+                let index = self.index.unwrap();
+                Some(
+                    key_ring
+                        .secret_keys
+                        .get(&index)
+                        .unwrap_or_else(|| panic!("no secret keys of replica {index}")),
+                )
+            }
+            SignatureScheme::Plain | SignatureScheme::Hmac => None,
+        };
+        let signature = match scheme {
+            SignatureScheme::Plain => Signature::Plain,
+            SignatureScheme::K256 | SignatureScheme::Ed25519 if self.fake_index.is_some() => {
+                Signature::Fake(Hasher::fake(&message, self.fake_index.unwrap()))
+            }
+            SignatureScheme::K256 => {
+                Signature::K256(keys.unwrap().k256.sign_digest(Hasher::sha256(&message)))
+            }
+            // over the digest rather than the message, so signatures can be verified in batches,
+            // see `Verifier::verify_batch`
+            SignatureScheme::Ed25519 => Signature::Ed25519(ed25519_dalek::Signer::sign(
+                &keys.unwrap().ed25519,
+                &Hasher::sha256(&message).finalize(),
+            )),
+            SignatureScheme::Bls => Signature::Bls(Box::new(keys.unwrap().bls.sign(
+                &Hasher::sha256(&message).finalize(),
+                BLS_DST,
+                &[],
+            ))),
+            SignatureScheme::Hmac => match (&self.client_keys, self.index, self.receiver.get()) {
+                // the replicas' keys with the client, as the request may be sent to any of them
+                (Some(keys), None, _) => {
+                    Signature::Hmacs(Vec::from_iter((0..keys.num_replica).map(|index| {
+                        let hmac = keys.get(Host::Replica(index as _)).unwrap();
+                        Hasher::hmac(&message, hmac)
+                    })))
+                }
+                (Some(keys), Some(_), Some(receiver @ Host::Client(_))) => {
+                    Signature::Hmac(Hasher::hmac(&message, keys.get(receiver).unwrap()))
+                }
+                _ => Signature::Hmac(Hasher::hmac(&message, self.hmac.clone())),
+            },
+        };
         Signed {
-            signature: Signature::Hmac(Hasher::hmac(&message, self.hmac.clone())),
             inner: message,
+            signature,
+            key_epoch,
         }
     }
 }
@@ -155,9 +532,109 @@ pub enum Verifier {
 
 #[derive(Debug, Clone)]
 pub struct VerifierStandard {
-    verifying_keys: HashMap<ReplicaIndex, VerifyingKey>,
+    key_ring: KeyRing,
     hmac: Hmac<Sha256>,
+    // whether `Config::client_secret` is set, so the messages between clients and replicas are
+    // MACed with the keys of the pair, which are known once the receiver is, see `receiving`
+    client_secret: bool,
+    client_keys: Option<ClientKeys>,
+    policy: SignaturePolicy,
+    fake: bool,
     variant: Arc<Variant>,
+    strictness: StrictnessSwitch,
+}
+
+// verification strictness shared by the clones of a verifier, which can be switched while running
+// a switch scheduled at an op number takes effect when the replica commits it, so that every
+// replica switches at the same point of the log
+#[derive(Debug, Clone)]
+pub struct StrictnessSwitch(Arc<StrictnessState>);
+
+#[derive(Debug)]
+struct StrictnessState {
+    current: AtomicU8,
+    scheduled: AtomicU8,
+    // u64::MAX if nothing is scheduled
+    scheduled_num: AtomicU64,
+    committed_num: AtomicU64,
+    // the op number, i.e. the last committed one, and time of every switch
+    switches: Mutex<Vec<(Strictness, u64, Timestamp)>>,
+}
+
+impl Default for StrictnessSwitch {
+    fn default() -> Self {
+        Self(Arc::new(StrictnessState {
+            current: AtomicU8::new(Strictness::Full as _),
+            scheduled: AtomicU8::new(Strictness::Full as _),
+            scheduled_num: AtomicU64::new(u64::MAX),
+            committed_num: AtomicU64::new(0),
+            switches: Mutex::new(vec![(Strictness::Full, 0, Timestamp::now())]),
+        }))
+    }
+}
+
+impl StrictnessSwitch {
+    pub fn get(&self) -> Strictness {
+        match self.0.current.load(SeqCst) {
+            0 => Strictness::Nop,
+            1 => Strictness::MacOnly,
+            _ => Strictness::Full,
+        }
+    }
+
+    // switch after committing `op_num`, or immediately for `None`, i.e. for the protocols that do
+    // not report commits
+    pub fn schedule(&self, strictness: Strictness, op_num: Option<u64>) {
+        match op_num {
+            Some(op_num) if op_num > self.committed_num() => {
+                self.0.scheduled.store(strictness as _, SeqCst);
+                self.0.scheduled_num.store(op_num, SeqCst)
+            }
+            _ => self.switch(strictness),
+        }
+    }
+
+    pub fn on_commit(&self, op_num: u64) {
+        self.0.committed_num.store(op_num, SeqCst);
+        if op_num >= self.0.scheduled_num.load(SeqCst) {
+            self.0.scheduled_num.store(u64::MAX, SeqCst);
+            let scheduled = self.0.scheduled.load(SeqCst);
+            self.switch(match scheduled {
+                0 => Strictness::Nop,
+                1 => Strictness::MacOnly,
+                _ => Strictness::Full,
+            })
+        }
+    }
+
+    pub fn committed_num(&self) -> u64 {
+        self.0.committed_num.load(SeqCst)
+    }
+
+    fn switch(&self, strictness: Strictness) {
+        self.0.current.store(strictness as _, SeqCst);
+        self.0
+            .switches
+            .lock()
+            .unwrap()
+            .push((strictness, self.committed_num(), Timestamp::now()))
+    }
+
+    // committed ops and elapsed time under each strictness so far
+    pub fn phases(&self) -> Vec<StrictnessPhase> {
+        let mut switches = self.0.switches.lock().unwrap().clone();
+        switches.push((self.get(), self.committed_num(), Timestamp::now()));
+        Vec::from_iter(switches.windows(2).map(|window| {
+            let [(strictness, start_num, start), (_, end_num, end)] = window else {
+                unreachable!()
+            };
+            StrictnessPhase {
+                strictness: *strictness,
+                num_op: end_num - start_num,
+                duration: *end - *start,
+            }
+        }))
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -174,29 +651,58 @@ impl std::fmt::Display for Invalid {
 
 impl std::error::Error for Invalid {}
 
+fn verify_hmac(
+    message: &impl DigestHash,
+    hmac: Hmac<Sha256>,
+    code: &[u8; 32],
+) -> Result<(), Invalid> {
+    // well...
+    let mut hasher = Hasher::Hmac(hmac);
+    message.hash(&mut hasher);
+    let Hasher::Hmac(hmac) = hasher else {
+        unreachable!()
+    };
+    hmac.verify(code.into()).map_err(|_| Invalid::Private)
+}
+
 impl Verifier {
     pub fn new_standard(config: &Config, variant: Arc<Variant>) -> Self {
-        let verifying_keys = config
-            .hosts
-            .iter()
-            .filter_map(|(&host, host_config)| {
-                if let Host::Replica(index) = host {
-                    Some((
-                        index,
-                        *host_config.signing_key.as_ref().unwrap().verifying_key(),
-                    ))
-                } else {
-                    None
-                }
-            })
-            .collect();
         Self::Standard(Box::new(VerifierStandard {
-            verifying_keys,
+            key_ring: KeyRing::new(config),
             hmac: config.hmac.clone(),
+            client_secret: config.client_secret.is_some(),
+            client_keys: None,
+            policy: config.signature_policy.clone(),
+            fake: config.fake_crypto,
             variant,
+            strictness: Default::default(),
         }))
     }
 
+    // for the messages sent to `host`, whose keys with the other side verify the MACs between
+    // clients and replicas
+    pub fn receiving(&self, config: &Config, host: Host) -> Self {
+        let mut verifier = self.clone();
+        if let Self::Standard(verifier) = &mut verifier {
+            verifier.client_keys = ClientKeys::new(config, host)
+        }
+        verifier
+    }
+
+    pub fn strictness(&self) -> Option<StrictnessSwitch> {
+        match self {
+            Self::Nop => None,
+            Self::Standard(verifier) => Some(verifier.strictness.clone()),
+        }
+    }
+
+    pub fn key_ring(&self) -> Option<KeyRing> {
+        match self {
+            Self::Nop => None,
+            Self::Standard(verifier) => Some(verifier.key_ring.clone()),
+        }
+    }
+
     pub fn verify<M>(
         &self,
         message: &Signed<M>,
@@ -205,32 +711,217 @@ impl Verifier {
     where
         M: DigestHash,
     {
+        self.verify_internal(message, index.into(), None)
+    }
+
+    // a message MACed by client `index`, i.e. with its keys of the replicas if
+    // `Config::client_secret` is set
+    pub fn verify_client<M>(&self, message: &Signed<M>, index: ClientIndex) -> Result<(), Invalid>
+    where
+        M: DigestHash,
+    {
+        self.verify_internal(message, None, Some(index))
+    }
+
+    fn verify_internal<M>(
+        &self,
+        message: &Signed<M>,
+        index: Option<ReplicaIndex>,
+        client_index: Option<ClientIndex>,
+    ) -> Result<(), Invalid>
+    where
+        M: DigestHash,
+    {
+        if let Self::Standard(verifier) = self {
+            match (verifier.strictness.get(), &message.signature) {
+                (Strictness::Nop, _)
+                | (
+                    Strictness::MacOnly,
+                    Signature::K256(_)
+                    | Signature::Ed25519(_)
+                    | Signature::Bls(_)
+                    | Signature::Fake(_),
+                ) => return Ok(()),
+                _ => {}
+            }
+            match (verifier.policy.scheme::<M>(), &message.signature) {
+                (None, _)
+                | (Some(SignatureScheme::K256), Signature::K256(_) | Signature::Fake(_))
+                | (Some(SignatureScheme::Ed25519), Signature::Ed25519(_) | Signature::Fake(_))
+                | (Some(SignatureScheme::Bls), Signature::Bls(_))
+                | (Some(SignatureScheme::Hmac), Signature::Hmac(_) | Signature::Hmacs(_)) => {}
+                (Some(SignatureScheme::Plain), Signature::Plain) => return Ok(()),
+                // downgraded or otherwise mismatched scheme
+                (
+                    Some(SignatureScheme::K256 | SignatureScheme::Ed25519 | SignatureScheme::Bls),
+                    _,
+                ) => return Err(Invalid::Public),
+                (Some(_), _) => return Err(Invalid::Private),
+            }
+        }
         match (self, &message.signature) {
             (Self::Nop, _) => Ok(()),
             (Self::Standard(_), Signature::Plain) => unimplemented!(),
-            (Self::Standard(verifier), Signature::K256(signature)) => verifier.verifying_keys
-                [&index.into().unwrap()]
-                .verify_digest(Hasher::sha256(&**message), signature)
-                .map_err(|_| Invalid::Public),
+            (Self::Standard(verifier), Signature::K256(signature)) => {
+                let digest = Hasher::sha256(&**message);
+                verifier
+                    .key_ring
+                    .verify_public(message.key_epoch, index.unwrap(), |keys| {
+                        keys.k256.verify_digest(digest.clone(), signature).is_ok()
+                    })
+            }
+            (Self::Standard(verifier), Signature::Ed25519(signature)) => {
+                let digest = Hasher::sha256(&**message).finalize();
+                verifier
+                    .key_ring
+                    .verify_public(message.key_epoch, index.unwrap(), |keys| {
+                        ed25519_dalek::Verifier::verify(&keys.ed25519, &digest, signature).is_ok()
+                    })
+            }
+            (Self::Standard(verifier), Signature::Bls(signature)) => {
+                let digest = Hasher::sha256(&**message).finalize();
+                verifier
+                    .key_ring
+                    .verify_public(message.key_epoch, index.unwrap(), |keys| {
+                        signature.verify(true, &digest, BLS_DST, &[], &keys.bls, true)
+                            == blst::BLST_ERROR::BLST_SUCCESS
+                    })
+            }
+            (Self::Standard(verifier), Signature::Fake(code)) => {
+                if verifier.fake && Hasher::fake(&**message, index.unwrap()) == *code {
+                    Ok(())
+                } else {
+                    Err(Invalid::Public)
+                }
+            }
             (Self::Standard(verifier), Signature::Hmac(code)) => {
-                // well...
-                let mut hasher = Hasher::Hmac(verifier.hmac.clone());
-                M::hash(message, &mut hasher);
-                let Hasher::Hmac(hmac) = hasher else {
-                    unreachable!()
+                let hmac = match (verifier.client_secret, client_index, index) {
+                    // clients MAC for each replica instead
+                    (true, Some(_), _) => return Err(Invalid::Private),
+                    // the reply of a replica to the receiving client
+                    (true, None, Some(index)) => match &verifier.client_keys {
+                        Some(keys) if matches!(keys.host, Host::Client(_)) => {
+                            keys.get(Host::Replica(index)).unwrap()
+                        }
+                        _ => verifier.hmac.clone(),
+                    },
+                    _ => verifier.hmac.clone(),
+                };
+                verify_hmac(&**message, hmac, code)
+            }
+            (Self::Standard(verifier), Signature::Hmacs(codes)) => {
+                let (Some(client_index), Some(keys)) = (client_index, &verifier.client_keys) else {
+                    return Err(Invalid::Private);
                 };
-                hmac.verify(code.into()).map_err(|_| Invalid::Private)
+                let Host::Replica(index) = keys.host else {
+                    return Err(Invalid::Private);
+                };
+                let code = codes.get(index as usize).ok_or(Invalid::Private)?;
+                verify_hmac(
+                    &**message,
+                    keys.get(Host::Client(client_index)).unwrap(),
+                    code,
+                )
             }
         }
     }
 
+    // the same as verifying the messages one by one, except that their Ed25519 signatures are
+    // checked together, which costs less than checking each, e.g. for the votes of a certificate
+    pub fn verify_batch<'a, M>(
+        &self,
+        messages: impl IntoIterator<Item = (&'a Signed<M>, ReplicaIndex)>,
+    ) -> Result<(), Invalid>
+    where
+        M: DigestHash + 'a,
+    {
+        let Self::Standard(verifier) = self else {
+            return Ok(());
+        };
+        let (mut digests, mut signatures, mut keys) = (Vec::new(), Vec::new(), Vec::new());
+        for (message, index) in messages {
+            match &message.signature {
+                // the ones that its standby may have signed are checked against either key
+                Signature::Ed25519(signature)
+                    if verifier.strictness.get() == Strictness::Full
+                        && matches!(
+                            verifier.policy.scheme::<M>(),
+                            None | Some(SignatureScheme::Ed25519)
+                        )
+                        && !verifier.key_ring.has_standby(index) =>
+                {
+                    digests.push(Hasher::sha256(&**message).finalize());
+                    signatures.push(*signature);
+                    keys.push(
+                        verifier
+                            .key_ring
+                            .public_keys(message.key_epoch, index)?
+                            .ed25519,
+                    )
+                }
+                _ => self.verify(message, index)?,
+            }
+        }
+        if signatures.is_empty() {
+            return Ok(());
+        }
+        let digests = Vec::from_iter(digests.iter().map(|digest| &digest[..]));
+        ed25519_dalek::verify_batch(&digests, &signatures, &keys).map_err(|_| Invalid::Public)
+    }
+
+    // `signature` aggregates the BLS signatures of `message` by each of `indices`, see
+    // `Signature::aggregate`, all of which are made with the keys of the current epoch, or of the
+    // previous one in its grace window
+    pub fn verify_aggregated<M>(
+        &self,
+        message: &M,
+        indices: &[ReplicaIndex],
+        signature: &Signature,
+    ) -> Result<(), Invalid>
+    where
+        M: DigestHash,
+    {
+        let Self::Standard(verifier) = self else {
+            return Ok(());
+        };
+        if verifier.strictness.get() != Strictness::Full {
+            return Ok(());
+        }
+        let Signature::Bls(signature) = signature else {
+            return Err(Invalid::Public);
+        };
+        // so no signer is counted twice
+        if indices.iter().collect::<HashSet<_>>().len() != indices.len() {
+            return Err(Invalid::Public);
+        }
+        let digest = Hasher::sha256(message).finalize();
+        let verify = |epoch| {
+            let keys = indices
+                .iter()
+                .map(|&index| Ok(verifier.key_ring.public_keys(epoch, index)?.bls))
+                .collect::<Result<Vec<_>, _>>()?;
+            let keys = Vec::from_iter(keys.iter());
+            let result = signature.fast_aggregate_verify(true, &digest, BLS_DST, &keys);
+            if result == blst::BLST_ERROR::BLST_SUCCESS {
+                Ok(())
+            } else {
+                Err(Invalid::Public)
+            }
+        };
+        let epoch = verifier.key_ring.epoch();
+        verify(epoch).or_else(|_| verify(epoch.wrapping_sub(1)))
+    }
+
     pub fn verify_ordered_multicast<M>(&self, message: &OrderedMulticast<M>) -> Result<(), Invalid>
     where
         M: DigestHash,
     {
         match self {
             Self::Nop => Ok(()),
-            Self::Standard(verifier) => verifier.variant.verify(message),
+            Self::Standard(verifier) => match (verifier.strictness.get(), &*verifier.variant) {
+                (Strictness::Nop, _) | (Strictness::MacOnly, Variant::Ecdsa(_)) => Ok(()),
+                _ => verifier.variant.verify(message),
+            },
         }
     }
 }
@@ -248,3 +939,411 @@ impl<M, N: Into<M>> Sign<N> for M {
 pub trait Verify {
     fn verify(&self, verifier: &Verifier) -> Result<(), Invalid>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct M(u32);
+
+    impl DigestHash for M {
+        fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+            hasher.write_u32(self.0)
+        }
+    }
+
+    fn config(fake_crypto: bool) -> Config {
+        let mut config = Config::new(
+            (0..4)
+                .map(|index| {
+                    let addr = ([127, 0, 0, 1], 10000 + index as u16).into();
+                    (Host::Replica(index), addr)
+                })
+                .collect(),
+            1,
+        );
+        config.fake_crypto = fake_crypto;
+        config
+    }
+
+    fn signer(config: &Config, index: ReplicaIndex) -> Signer {
+        Signer::new(config, Host::Replica(index))
+    }
+
+    #[test]
+    fn standby_keys() {
+        let mut standby_config = config(false);
+        standby_config.sign_as_standby(1);
+        let message = signer(&standby_config, 1).sign_public(M(42));
+        // not signed as the replica
+        let verifier = Verifier::new_standard(&config(false), Arc::new(Variant::Unreachable));
+        assert!(verifier.verify(&message, 1).is_err());
+        let mut replica_config = config(false);
+        replica_config.insert_standby(1, ([127, 0, 0, 1], 20001).into());
+        let verifier = Verifier::new_standard(&replica_config, Arc::new(Variant::Unreachable));
+        assert!(verifier.verify(&message, 1).is_ok());
+        assert!(verifier.verify(&message, 2).is_err());
+        assert!(verifier
+            .verify(&signer(&replica_config, 1).sign_public(M(42)), 1)
+            .is_ok());
+        // neither batched
+        let ed25519_message = Signer {
+            public_scheme: SignatureScheme::Ed25519,
+            ..signer(&standby_config, 1)
+        }
+        .sign_public(M(42));
+        assert!(verifier.verify_batch([(&ed25519_message, 1)]).is_ok());
+    }
+
+    #[test]
+    fn fake_signature() {
+        let config = config(true);
+        let verifier = Verifier::new_standard(&config, Arc::new(Variant::Unreachable));
+        let message = signer(&config, 1).sign_public(M(42));
+        assert!(matches!(message.signature, Signature::Fake(_)));
+        assert!(verifier.verify(&message, 1).is_ok());
+        assert!(verifier.verify(&message, 2).is_err());
+        let forged = Signed {
+            inner: M(43),
+            signature: message.signature.clone(),
+            key_epoch: 0,
+        };
+        assert!(verifier.verify(&forged, 1).is_err());
+        // deterministic across signers of the same index
+        let Signature::Fake(code) = message.signature else {
+            unreachable!()
+        };
+        assert_eq!(Hasher::fake(&M(42), 1), code);
+    }
+
+    #[test]
+    fn client_keys() {
+        let mut config = Config::new(
+            (0..2)
+                .map(|index| {
+                    let addr = ([127, 0, 0, 1], 10000 + index).into();
+                    (Host::Client(index), addr)
+                })
+                .chain([(Host::Replica(0), ([127, 0, 0, 1], 10002).into())])
+                .collect(),
+            0,
+        );
+        let message = Signer::new(&config, Host::Client(0)).sign_private(M(42));
+        // every host shares the key
+        assert!(
+            Verifier::new_standard(&config, Arc::new(Variant::Unreachable))
+                .verify_client(&message, 1)
+                .is_ok()
+        );
+
+        config.client_secret = Some(b"secret".to_vec());
+        let verifier = Verifier::new_standard(&config, Arc::new(Variant::Unreachable));
+        let replica_verifier = verifier.receiving(&config, Host::Replica(0));
+        let message = Signer::new(&config, Host::Client(0)).sign_private(M(42));
+        assert!(matches!(&message.signature, Signature::Hmacs(codes) if codes.len() == 1));
+        assert!(replica_verifier.verify_client(&message, 0).is_ok());
+        assert!(replica_verifier.verify_client(&message, 1).is_err());
+        assert!(replica_verifier.verify(&message, None).is_err());
+        // another client, even with the key of its own, cannot pass for the replica
+        assert!(verifier
+            .receiving(&config, Host::Client(1))
+            .verify_client(&message, 0)
+            .is_err());
+        // nor MAC with the key that every host shares
+        let message = Signer {
+            client_keys: None,
+            ..Signer::new(&config, Host::Client(0))
+        }
+        .sign_private(M(42));
+        assert!(replica_verifier.verify_client(&message, 0).is_err());
+
+        // replies are MACed with the key of the client they are sent to
+        let signer = Signer::new(&config, Host::Replica(0));
+        signer.receiver.set(Some(Host::Client(0)));
+        let message = signer.sign_private(M(42));
+        assert!(verifier
+            .receiving(&config, Host::Client(0))
+            .verify(&message, 0)
+            .is_ok());
+        assert!(verifier
+            .receiving(&config, Host::Client(1))
+            .verify(&message, 0)
+            .is_err());
+        // derived on demand for the clients unknown ahead
+        let signer = Signer::new(&config, Host::Replica(0));
+        signer.receiver.set(Some(Host::Client(2)));
+        let message = signer.sign_private(M(42));
+        assert!(verifier
+            .receiving(&config, Host::Client(2))
+            .verify(&message, 0)
+            .is_ok());
+        let message = Signer::new(&config, Host::Client(2)).sign_private(M(42));
+        assert!(replica_verifier.verify_client(&message, 2).is_ok());
+    }
+
+    #[test]
+    fn loaded_keys() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("keys-{}", std::process::id()));
+        crate::context::keys::generate(&dir, 4, &mut rand::thread_rng()).unwrap();
+        let metadata = std::fs::metadata(crate::context::keys::secret_path(&dir, 1)).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        let mut replica_config = config(false);
+        replica_config.load_keys(&dir, Some(1)).unwrap();
+        assert!(replica_config.hosts[&Host::Replica(1)]
+            .secret_keys
+            .is_some());
+        assert!(replica_config.hosts[&Host::Replica(2)]
+            .secret_keys
+            .is_none());
+        let mut client_config = config(false);
+        client_config.load_keys(&dir, None).unwrap();
+        let verifier = Verifier::new_standard(&client_config, Arc::new(Variant::Unreachable));
+        let message = signer(&replica_config, 1).sign_public(M(42));
+        assert!(verifier.verify(&message, 1).is_ok());
+        assert!(verifier.verify(&message, 2).is_err());
+        // the keys derived from replica indexes are no longer trusted
+        let message = signer(&config(false), 1).sign_public(M(42));
+        assert!(verifier.verify(&message, 1).is_err());
+        // the secret keys of another replica
+        std::fs::copy(
+            crate::context::keys::secret_path(&dir, 0),
+            crate::context::keys::secret_path(&dir, 1),
+        )
+        .unwrap();
+        assert!(config(false).load_keys(&dir, Some(1)).is_err());
+        std::fs::remove_dir_all(&dir).unwrap()
+    }
+
+    // fresh keys of 4 replicas, for rotating to
+    fn rotation_keys(
+        name: &str,
+    ) -> (
+        HashMap<ReplicaIndex, SecretKeys>,
+        HashMap<ReplicaIndex, PublicKeys>,
+    ) {
+        let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        crate::context::keys::generate(&dir, 4, &mut rand::thread_rng()).unwrap();
+        let public_keys = HashMap::from_iter(crate::context::keys::load_public(&dir).unwrap());
+        let secret_keys = HashMap::from_iter((0..4).map(|index| {
+            (
+                index,
+                crate::context::keys::load_secret(&dir, index).unwrap(),
+            )
+        }));
+        std::fs::remove_dir_all(&dir).unwrap();
+        (secret_keys, public_keys)
+    }
+
+    #[test]
+    fn rotated_keys() {
+        let config = config(false);
+        let verifier = Verifier::new_standard(&config, Arc::new(Variant::Unreachable));
+        let key_ring = verifier.key_ring().unwrap();
+        let mut signer = signer(&config, 1);
+        signer.key_ring = key_ring.clone();
+        let signed = signer.sign_public(M(42));
+        assert_eq!(signed.key_epoch, 0);
+
+        let (secret_keys, public_keys) = rotation_keys("rotated-keys");
+        // leaving out the secret keys that the ring signs with
+        let mut partial = secret_keys.clone();
+        partial.remove(&1);
+        assert!(key_ring
+            .rotate(partial, public_keys.clone(), Duration::from_secs(60))
+            .is_err());
+        assert_eq!(key_ring.epoch(), 0);
+        let epoch = key_ring.rotate(
+            secret_keys.clone(),
+            public_keys.clone(),
+            Duration::from_secs(60),
+        );
+        assert_eq!(epoch, Ok(1));
+        let rotated = signer.sign_public(M(42));
+        assert_eq!(rotated.key_epoch, 1);
+        assert!(verifier.verify(&rotated, 1).is_ok());
+        // in the grace window
+        assert!(verifier.verify(&signed, 1).is_ok());
+        // the old keys claimed for the new epoch
+        let mislabeled = Signed {
+            inner: M(42),
+            signature: signed.signature.clone(),
+            key_epoch: 1,
+        };
+        assert!(verifier.verify(&mislabeled, 1).is_err());
+
+        key_ring
+            .rotate(secret_keys, public_keys, Duration::ZERO)
+            .unwrap();
+        assert!(verifier.verify(&rotated, 1).is_err());
+        assert!(verifier.verify(&signed, 1).is_err())
+    }
+
+    #[test]
+    fn fake_signature_rejected_by_real_verifier() {
+        let fake_config = config(true);
+        let verifier = Verifier::new_standard(&config(false), Arc::new(Variant::Unreachable));
+        let message = signer(&fake_config, 1).sign_public(M(42));
+        assert!(verifier.verify(&message, 1).is_err());
+        let message = signer(&config(false), 1).sign_public(M(42));
+        assert!(verifier.verify(&message, 1).is_ok());
+    }
+
+    #[test]
+    fn ed25519_batch() {
+        let mut config = config(false);
+        config.public_scheme = SignatureScheme::Ed25519;
+        let verifier = Verifier::new_standard(&config, Arc::new(Variant::Unreachable));
+        let messages =
+            Vec::from_iter((0..4).map(|index| (signer(&config, index).sign_public(M(42)), index)));
+        assert!(matches!(messages[0].0.signature, Signature::Ed25519(_)));
+        assert!(verifier.verify(&messages[1].0, 1).is_ok());
+        assert!(verifier
+            .verify_batch(messages.iter().map(|(message, index)| (message, *index)))
+            .is_ok());
+        let mut messages = messages;
+        messages[2].0.inner = M(43);
+        assert!(verifier.verify(&messages[2].0, 2).is_err());
+        assert!(verifier
+            .verify_batch(messages.iter().map(|(message, index)| (message, *index)))
+            .is_err());
+        // a K256 signature where Ed25519 ones are expected
+        let mut policy_config = config.clone();
+        policy_config
+            .signature_policy
+            .insert("M", SignatureScheme::Ed25519);
+        let verifier = Verifier::new_standard(&policy_config, Arc::new(Variant::Unreachable));
+        assert!(verifier
+            .verify(&signer(&self::config(false), 1).sign_public(M(42)), 1)
+            .is_err())
+    }
+
+    #[test]
+    fn bls_aggregated() {
+        let mut config = config(false);
+        config.public_scheme = SignatureScheme::Bls;
+        let verifier = Verifier::new_standard(&config, Arc::new(Variant::Unreachable));
+        let messages =
+            Vec::from_iter((0..3).map(|index| signer(&config, index).sign_public(M(42))));
+        assert!(verifier.verify(&messages[1], 1).is_ok());
+        assert!(verifier.verify(&messages[1], 2).is_err());
+        let signature = Signature::aggregate(&messages).unwrap();
+        assert!(verifier
+            .verify_aggregated(&M(42), &[0, 1, 2], &signature)
+            .is_ok());
+        assert!(verifier
+            .verify_aggregated(&M(43), &[0, 1, 2], &signature)
+            .is_err());
+        assert!(verifier
+            .verify_aggregated(&M(42), &[0, 1, 3], &signature)
+            .is_err());
+        assert!(verifier
+            .verify_aggregated(&M(42), &[0, 1, 1, 2], &signature)
+            .is_err());
+        let message = signer(&self::config(false), 0).sign_public(M(42));
+        assert!(Signature::aggregate([&messages[0], &message]).is_none())
+    }
+
+    // a host that starts after the others rotate
+    #[test]
+    fn previous_keys_from_config() {
+        let config = self::config(false);
+        let signed = signer(&config, 1).sign_public(M(42));
+        let (_, public_keys) = rotation_keys("previous-keys-from-config");
+        let mut rotated_config = config.clone();
+        for (index, keys) in &public_keys {
+            let host_config = rotated_config
+                .hosts
+                .get_mut(&Host::Replica(*index))
+                .unwrap();
+            host_config.public_keys = Some(keys.clone())
+        }
+        rotated_config.key_epoch = 1;
+        let previous_keys = HashMap::from_iter((0..4).map(|index| {
+            let host_config = &config.hosts[&Host::Replica(index)];
+            (index, host_config.public_keys.clone().unwrap())
+        }));
+        rotated_config.previous_keys = Some((previous_keys.clone(), Duration::from_secs(60)));
+        let verifier = Verifier::new_standard(&rotated_config, Arc::new(Variant::Unreachable));
+        assert!(verifier.verify(&signed, 1).is_ok());
+        rotated_config.previous_keys = Some((previous_keys, Duration::ZERO));
+        let verifier = Verifier::new_standard(&rotated_config, Arc::new(Variant::Unreachable));
+        assert!(verifier.verify(&signed, 1).is_err())
+    }
+
+    #[test]
+    fn aggregated_across_rotation() {
+        let mut config = config(false);
+        config.public_scheme = SignatureScheme::Bls;
+        let verifier = Verifier::new_standard(&config, Arc::new(Variant::Unreachable));
+        let key_ring = verifier.key_ring().unwrap();
+        let signers = Vec::from_iter((0..3).map(|index| {
+            let mut signer = signer(&config, index);
+            signer.key_ring = key_ring.clone();
+            signer
+        }));
+        let messages = Vec::from_iter(signers.iter().map(|signer| signer.sign_public(M(42))));
+        let signature = Signature::aggregate(&messages).unwrap();
+
+        let (secret_keys, public_keys) = rotation_keys("aggregated-across-rotation");
+        key_ring
+            .rotate(
+                secret_keys.clone(),
+                public_keys.clone(),
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        // the shares signed just before the rotation
+        assert!(verifier
+            .verify_aggregated(&M(42), &[0, 1, 2], &signature)
+            .is_ok());
+        let rotated = signers[0].sign_public(M(42));
+        assert!(Signature::aggregate([&rotated, &messages[1]]).is_none());
+        let rotated = Vec::from_iter(signers.iter().map(|signer| signer.sign_public(M(42))));
+        let rotated_signature = Signature::aggregate(&rotated).unwrap();
+        assert!(verifier
+            .verify_aggregated(&M(42), &[0, 1, 2], &rotated_signature)
+            .is_ok());
+
+        key_ring
+            .rotate(secret_keys, public_keys, Duration::ZERO)
+            .unwrap();
+        assert!(verifier
+            .verify_aggregated(&M(42), &[0, 1, 2], &signature)
+            .is_err())
+    }
+
+    #[test]
+    fn strictness_switched_at_op_boundary() {
+        let config = config(true);
+        let verifier = Verifier::new_standard(&config, Arc::new(Variant::Unreachable));
+        let strictness = verifier.strictness().unwrap();
+        let signed = signer(&config, 1).sign_public(M(42));
+        let forged = Signed {
+            inner: M(43),
+            signature: signed.signature.clone(),
+            key_epoch: 0,
+        };
+        let forged_mac = Signed {
+            inner: M(43),
+            signature: signer(&config, 1).sign_private(M(42)).signature,
+            key_epoch: 0,
+        };
+        strictness.schedule(Strictness::MacOnly, Some(10));
+        strictness.on_commit(9);
+        assert!(verifier.verify(&forged, 1).is_err());
+        strictness.on_commit(10);
+        assert_eq!(strictness.get(), Strictness::MacOnly);
+        assert!(verifier.clone().verify(&forged, 1).is_ok());
+        assert!(verifier.verify(&forged_mac, None).is_err());
+        strictness.schedule(Strictness::Nop, None);
+        assert!(verifier.verify(&forged_mac, None).is_ok());
+        let phases = strictness.phases();
+        assert_eq!(phases.len(), 3);
+        assert_eq!(
+            (phases[0].strictness, phases[0].num_op),
+            (Strictness::Full, 10)
+        );
+    }
+}
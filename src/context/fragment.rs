@@ -0,0 +1,245 @@
+// messages larger than a datagram, e.g. replies of long scans, are sent as numbered fragments with
+// a digest over the whole message, and reassembled by the receiving socket task before dispatch,
+// so protocols always handle whole messages
+// a fragment starts with `MARKER`, which never starts a message, as bincode encodes enum tags as
+// varints whose first byte is at most 0xfe
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use bincode::Options;
+use k256::sha2::{Digest, Sha256};
+use serde::{Deserialize, Serialize};
+use tokio_util::bytes::Bytes;
+
+// leave room for the fragment header under the 65507 bytes limit of UDP over IPv4
+pub const MAX_CHUNK: usize = 64000;
+// the largest message that is reassembled, so a forged fragment count cannot make the receiver
+// allocate more than this
+pub const MAX_MESSAGE: usize = 16 << 20;
+const MAX_COUNT: u32 = MAX_MESSAGE.div_ceil(MAX_CHUNK) as _;
+// incomplete messages kept at once, beyond which the oldest one is discarded
+pub const MAX_PENDING: usize = 64;
+const MARKER: u8 = 0xff;
+// incomplete messages are discarded after this long since their first fragment, i.e. a lost
+// fragment loses the message, which is resent the same way as a lost datagram
+pub const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Fragment {
+    id: u64,
+    index: u32,
+    count: u32,
+    digest: [u8; 32],
+    chunk: Vec<u8>,
+}
+
+// the datagrams to send `buf` in, or none if it fits in one
+// `id` tells apart the messages of the same sender
+pub fn split(id: u64, buf: &[u8]) -> Option<Vec<Vec<u8>>> {
+    if buf.len() <= MAX_CHUNK {
+        return None;
+    }
+    assert!(buf.len() <= MAX_MESSAGE, "message of {} bytes", buf.len());
+    let digest = Sha256::digest(buf).into();
+    let count = buf.len().div_ceil(MAX_CHUNK) as u32;
+    Some(Vec::from_iter(buf.chunks(MAX_CHUNK).enumerate().map(
+        |(index, chunk)| {
+            let fragment = Fragment {
+                id,
+                index: index as _,
+                count,
+                digest,
+                chunk: chunk.to_vec(),
+            };
+            let mut datagram = vec![MARKER];
+            bincode::options()
+                .serialize_into(&mut datagram, &fragment)
+                .unwrap();
+            datagram
+        },
+    )))
+}
+
+#[derive(Debug, Default)]
+pub struct Reassembly {
+    pending: HashMap<(SocketAddr, u64), Pending>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    digest: [u8; 32],
+    chunks: Vec<Option<Vec<u8>>>,
+    num_received: u32,
+    start: Instant,
+}
+
+impl Reassembly {
+    // the message that `datagram` completes, `datagram` itself if it is not a fragment, or none if
+    // the message is incomplete or corrupted
+    pub fn receive(&mut self, remote: SocketAddr, datagram: Bytes, now: Instant) -> Option<Bytes> {
+        if datagram.first() != Some(&MARKER) {
+            return Some(datagram);
+        }
+        self.pending
+            .retain(|_, pending| now.duration_since(pending.start) < TIMEOUT);
+        let fragment = bincode::options()
+            .deserialize::<Fragment>(&datagram[1..])
+            .ok()?;
+        if fragment.index >= fragment.count
+            || fragment.count > MAX_COUNT
+            || fragment.chunk.len() > MAX_CHUNK
+        {
+            return None;
+        }
+        let key = (remote, fragment.id);
+        if self.pending.len() >= MAX_PENDING && !self.pending.contains_key(&key) {
+            let oldest = *self
+                .pending
+                .iter()
+                .min_by_key(|(_, pending)| pending.start)
+                .unwrap()
+                .0;
+            self.pending.remove(&oldest);
+        }
+        let pending = self.pending.entry(key).or_insert_with(|| Pending {
+            digest: fragment.digest,
+            chunks: vec![None; fragment.count as usize],
+            num_received: 0,
+            start: now,
+        });
+        if pending.digest != fragment.digest || pending.chunks.len() != fragment.count as usize {
+            return None;
+        }
+        let chunk = &mut pending.chunks[fragment.index as usize];
+        if chunk.is_none() {
+            *chunk = Some(fragment.chunk);
+            pending.num_received += 1
+        }
+        if pending.num_received != fragment.count {
+            return None;
+        }
+        let pending = self.pending.remove(&key).unwrap();
+        let buf = pending
+            .chunks
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect::<Vec<_>>();
+        if <[u8; 32]>::from(Sha256::digest(&buf)) != pending.digest {
+            return None;
+        }
+        Some(buf.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote() -> SocketAddr {
+        ([10, 0, 0, 1], 10000).into()
+    }
+
+    #[test]
+    fn reassemble_out_of_order() {
+        let buf = Vec::from_iter((0..3 * MAX_CHUNK).map(|i| i as u8));
+        assert!(split(0, &buf[..MAX_CHUNK]).is_none());
+        let datagrams = split(0, &buf).unwrap();
+        assert_eq!(datagrams.len(), 3);
+        let mut reassembly = Reassembly::default();
+        let now = Instant::now();
+        assert_eq!(
+            reassembly.receive(remote(), datagrams[2].clone().into(), now),
+            None
+        );
+        assert_eq!(
+            reassembly.receive(remote(), datagrams[0].clone().into(), now),
+            None
+        );
+        // duplicated
+        assert_eq!(
+            reassembly.receive(remote(), datagrams[0].clone().into(), now),
+            None
+        );
+        assert_eq!(
+            reassembly.receive(remote(), datagrams[1].clone().into(), now),
+            Some(buf.into())
+        );
+        // whole messages pass through
+        assert_eq!(
+            reassembly.receive(remote(), Bytes::from_static(&[0, 1]), now),
+            Some(Bytes::from_static(&[0, 1]))
+        );
+    }
+
+    #[test]
+    fn incomplete_expired() {
+        let buf = vec![0; 2 * MAX_CHUNK];
+        let datagrams = split(0, &buf).unwrap();
+        let mut reassembly = Reassembly::default();
+        let now = Instant::now();
+        assert_eq!(
+            reassembly.receive(remote(), datagrams[0].clone().into(), now),
+            None
+        );
+        assert_eq!(
+            reassembly.receive(remote(), datagrams[1].clone().into(), now + TIMEOUT),
+            None
+        );
+        assert_eq!(reassembly.pending.len(), 1)
+    }
+
+    #[test]
+    fn bounded() {
+        let mut reassembly = Reassembly::default();
+        let now = Instant::now();
+        let fragment = |id, count| {
+            let fragment = Fragment {
+                id,
+                index: 0,
+                count,
+                digest: Default::default(),
+                chunk: Default::default(),
+            };
+            let mut datagram = vec![MARKER];
+            bincode::options()
+                .serialize_into(&mut datagram, &fragment)
+                .unwrap();
+            Bytes::from(datagram)
+        };
+        assert_eq!(
+            reassembly.receive(remote(), fragment(0, u32::MAX), now),
+            None
+        );
+        assert!(reassembly.pending.is_empty());
+        for id in 0..MAX_PENDING as u64 + 1 {
+            let now = now + Duration::from_millis(id);
+            assert_eq!(reassembly.receive(remote(), fragment(id, 2), now), None)
+        }
+        assert_eq!(reassembly.pending.len(), MAX_PENDING);
+        // the oldest one is evicted
+        assert!(!reassembly.pending.contains_key(&(remote(), 0)))
+    }
+
+    #[test]
+    fn corrupted_dropped() {
+        let buf = vec![0; 2 * MAX_CHUNK];
+        let mut datagrams = split(0, &buf).unwrap();
+        *datagrams[1].last_mut().unwrap() = 1;
+        let mut reassembly = Reassembly::default();
+        let now = Instant::now();
+        assert_eq!(
+            reassembly.receive(remote(), datagrams[0].clone().into(), now),
+            None
+        );
+        assert_eq!(
+            reassembly.receive(remote(), datagrams[1].clone().into(), now),
+            None
+        );
+        assert!(reassembly.pending.is_empty())
+    }
+}
@@ -0,0 +1,157 @@
+// keys of replicas generated once by `keygen` and distributed as files, so that a replica holds only
+// its own secret keys, in place of the ones `Config::new` derives from replica indexes, which any
+// process can derive as well
+// a directory of keys has `public.keys` with the public keys of every replica, and
+// `replica-<index>.key` with the seed of one replica's secret keys
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::Path,
+};
+
+use bincode::Options;
+use k256::ecdsa::{SigningKey, VerifyingKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use super::ReplicaIndex;
+
+#[derive(Debug, Clone)]
+pub struct SecretKeys {
+    pub k256: SigningKey,
+    pub ed25519: ed25519_dalek::SigningKey,
+    pub bls: blst::min_pk::SecretKey,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublicKeys {
+    pub k256: VerifyingKey,
+    pub ed25519: ed25519_dalek::VerifyingKey,
+    pub bls: blst::min_pk::PublicKey,
+}
+
+// the encoding of `PublicKeys` in `public.keys`
+#[derive(Debug, Serialize, Deserialize)]
+struct PublicKeysFile {
+    replica_index: ReplicaIndex,
+    k256: Vec<u8>,
+    ed25519: [u8; 32],
+    bls: Vec<u8>,
+}
+
+impl SecretKeys {
+    // none if the seed is not a valid K256 scalar
+    pub fn from_seed(seed: &[u8; 32]) -> Option<Self> {
+        Some(Self {
+            k256: SigningKey::from_slice(seed).ok()?,
+            ed25519: ed25519_dalek::SigningKey::from_bytes(seed),
+            bls: blst::min_pk::SecretKey::key_gen(seed, &[]).ok()?,
+        })
+    }
+
+    pub fn public(&self) -> PublicKeys {
+        PublicKeys {
+            k256: *self.k256.verifying_key(),
+            ed25519: self.ed25519.verifying_key(),
+            bls: self.bls.sk_to_pk(),
+        }
+    }
+}
+
+pub fn public_path(dir: &Path) -> std::path::PathBuf {
+    dir.join("public.keys")
+}
+
+pub fn secret_path(dir: &Path, index: ReplicaIndex) -> std::path::PathBuf {
+    dir.join(format!("replica-{index}.key"))
+}
+
+// fresh keys of `num_replica` replicas into `dir`
+pub fn generate(dir: &Path, num_replica: usize, rng: &mut impl RngCore) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut public_keys = Vec::new();
+    for index in 0..num_replica as ReplicaIndex {
+        let (seed, keys) = loop {
+            let mut seed = [0; 32];
+            rng.fill_bytes(&mut seed);
+            if let Some(keys) = SecretKeys::from_seed(&seed) {
+                break (seed, keys);
+            }
+        };
+        // readable by the owner only, as the default umask leaves it to everyone
+        let mut file = std::fs::File::options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(secret_path(dir, index))?;
+        // the mode applies on creation only, so tighten a file from an earlier generation as well
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        file.write_all(&seed)?;
+        let keys = keys.public();
+        public_keys.push(PublicKeysFile {
+            replica_index: index,
+            k256: keys.k256.to_sec1_bytes().to_vec(),
+            ed25519: keys.ed25519.to_bytes(),
+            bls: keys.bls.compress().to_vec(),
+        })
+    }
+    std::fs::write(
+        public_path(dir),
+        bincode::options().serialize(&public_keys).unwrap(),
+    )
+}
+
+pub fn load_public(dir: &Path) -> io::Result<Vec<(ReplicaIndex, PublicKeys)>> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    let files = bincode::options()
+        .deserialize::<Vec<PublicKeysFile>>(&std::fs::read(public_path(dir))?)
+        .map_err(|_| invalid("malformed public keys"))?;
+    files
+        .into_iter()
+        .map(|file| {
+            let keys = PublicKeys {
+                k256: VerifyingKey::from_sec1_bytes(&file.k256)
+                    .map_err(|_| invalid("invalid K256 key"))?,
+                ed25519: ed25519_dalek::VerifyingKey::from_bytes(&file.ed25519)
+                    .map_err(|_| invalid("invalid Ed25519 key"))?,
+                bls: blst::min_pk::PublicKey::uncompress(&file.bls)
+                    .map_err(|_| invalid("invalid BLS key"))?,
+            };
+            Ok((file.replica_index, keys))
+        })
+        .collect()
+}
+
+pub fn load_secret(dir: &Path, index: ReplicaIndex) -> io::Result<SecretKeys> {
+    let seed = std::fs::read(secret_path(dir, index))?;
+    seed.try_into()
+        .ok()
+        .and_then(|seed| SecretKeys::from_seed(&seed))
+        .ok_or(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid secret key of replica {index}"),
+        ))
+}
+
+// the public keys of every replica in `dir`, and the secret keys of replica `index` if any, which
+// must match its public ones
+pub fn load(
+    dir: &Path,
+    index: Option<ReplicaIndex>,
+) -> io::Result<(Option<SecretKeys>, HashMap<ReplicaIndex, PublicKeys>)> {
+    let public_keys = HashMap::from_iter(load_public(dir)?);
+    let Some(index) = index else {
+        return Ok((None, public_keys));
+    };
+    let secret_keys = load_secret(dir, index)?;
+    if public_keys.get(&index) != Some(&secret_keys.public()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("secret keys of replica {index} do not match the public ones"),
+        ));
+    }
+    Ok((Some(secret_keys), public_keys))
+}
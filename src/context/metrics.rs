@@ -0,0 +1,245 @@
+// live counters and gauges of a running replica, exported in Prometheus text format
+// the dispatch counts into them as it receives and publishes the gauges on every pace, with relaxed
+// atomics that a scrape reads without holding back the protocol thread, and sees values at most one
+// pace old
+
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc, OnceLock,
+    },
+};
+
+use serde::{de::DeserializeOwned, forward_to_deserialize_any, Deserializer};
+
+// sampled from the protocol on every pace, none for what the protocol does not keep
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Gauges {
+    // entries kept in the log, e.g. the executed ones since the stable checkpoint
+    pub log_len: Option<u64>,
+    // the last committed op number, or block height of the protocols that number blocks
+    pub commit_num: Option<u64>,
+    // ordered multicast held back for a gap before it
+    pub reorder_len: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    // shared with the dispatch once it registers the receivers, before which nothing is received
+    received: OnceLock<Arc<Received>>,
+    // received and not verified yet, i.e. the events queued in the dispatch and its workers
+    verify_queue_depth: AtomicU64,
+    log_len: Gauge,
+    commit_num: Gauge,
+    reorder_len: Gauge,
+}
+
+// `u64::MAX` for none
+#[derive(Debug)]
+struct Gauge(AtomicU64);
+
+impl Default for Gauge {
+    fn default() -> Self {
+        Self(AtomicU64::new(u64::MAX))
+    }
+}
+
+impl Gauge {
+    fn store(&self, value: Option<u64>) {
+        self.0.store(value.unwrap_or(u64::MAX), Relaxed)
+    }
+
+    fn load(&self) -> Option<u64> {
+        Some(self.0.load(Relaxed)).filter(|&value| value != u64::MAX)
+    }
+}
+
+// the counts of messages received per variant of `M`
+#[derive(Debug)]
+pub struct Received {
+    kinds: &'static [&'static str],
+    // the last count is of the messages of none of the variants
+    counts: Vec<AtomicU64>,
+    ordered_multicast: AtomicU64,
+}
+
+impl Received {
+    fn new<M: DeserializeOwned>() -> Self {
+        let kinds = variants::<M>();
+        Self {
+            kinds,
+            counts: Vec::from_iter((0..=kinds.len()).map(|_| AtomicU64::new(0))),
+            ordered_multicast: AtomicU64::new(0),
+        }
+    }
+
+    // the variant index leads a serialized enum, which fits in the first byte with bincode's
+    // variable length integers as long as there are less than 251 variants
+    pub fn record(&self, buf: &[u8]) {
+        let index = buf
+            .first()
+            .map(|&index| index as usize)
+            .filter(|&index| index < self.kinds.len())
+            .unwrap_or(self.kinds.len());
+        self.counts[index].fetch_add(1, Relaxed);
+    }
+
+    pub fn record_ordered_multicast(&self) {
+        self.ordered_multicast.fetch_add(1, Relaxed);
+    }
+}
+
+impl Metrics {
+    // the counts that the dispatch of `M` records into
+    pub fn received<M: DeserializeOwned>(&self) -> Arc<Received> {
+        self.received
+            .get_or_init(|| Arc::new(Received::new::<M>()))
+            .clone()
+    }
+
+    pub fn publish(&self, verify_queue_depth: usize, gauges: Gauges) {
+        self.verify_queue_depth
+            .store(verify_queue_depth as _, Relaxed);
+        self.log_len.store(gauges.log_len);
+        self.commit_num.store(gauges.commit_num);
+        self.reorder_len.store(gauges.reorder_len)
+    }
+
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        writeln!(
+            &mut text,
+            "# HELP replica_received_messages_total Messages received by type.\n\
+            # TYPE replica_received_messages_total counter"
+        )
+        .unwrap();
+        if let Some(received) = self.received.get() {
+            let kinds = received.kinds.iter().chain(["unknown"].iter());
+            for (kind, count) in kinds.zip(&received.counts) {
+                writeln!(
+                    &mut text,
+                    "replica_received_messages_total{{type=\"{kind}\"}} {}",
+                    count.load(Relaxed)
+                )
+                .unwrap()
+            }
+            writeln!(
+                &mut text,
+                "replica_received_messages_total{{type=\"OrderedMulticast\"}} {}",
+                received.ordered_multicast.load(Relaxed)
+            )
+            .unwrap();
+        }
+        let mut gauge = |name, help, value: Option<u64>| {
+            if let Some(value) = value {
+                writeln!(
+                    &mut text,
+                    "# HELP replica_{name} {help}\n# TYPE replica_{name} gauge\nreplica_{name} {value}"
+                )
+                .unwrap()
+            }
+        };
+        gauge(
+            "verify_queue_depth",
+            "Messages received and not verified yet.",
+            Some(self.verify_queue_depth.load(Relaxed)),
+        );
+        gauge(
+            "log_length",
+            "Entries kept in the log.",
+            self.log_len.load(),
+        );
+        gauge(
+            "commit_number",
+            "The last committed op number or block height.",
+            self.commit_num.load(),
+        );
+        gauge(
+            "reorder_buffer_size",
+            "Ordered multicast held back for a gap.",
+            self.reorder_len.load(),
+        );
+        text
+    }
+}
+
+// the variant names that `M` deserializes from, empty if it is not an enum, probed with a
+// deserializer that stops as soon as it is asked for an enum
+fn variants<M: DeserializeOwned>() -> &'static [&'static str] {
+    struct Probe(&'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for &mut Probe {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: serde::de::Visitor<'de>>(
+            self,
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            Err(serde::de::Error::custom("not an enum"))
+        }
+
+        fn deserialize_enum<V: serde::de::Visitor<'de>>(
+            self,
+            _: &'static str,
+            variants: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            self.0 = variants;
+            Err(serde::de::Error::custom("probed"))
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map struct identifier
+            ignored_any
+        }
+    }
+
+    let mut probe = Probe(&[]);
+    let _ = M::deserialize(&mut probe);
+    probe.0
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::Options;
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[test]
+    fn render() {
+        #[derive(Serialize, Deserialize)]
+        enum M {
+            Request(u32),
+            Reply { result: Vec<u8> },
+        }
+        let metrics = Metrics::default();
+        let received = metrics.received::<M>();
+        for message in [M::Reply { result: vec![1] }, M::Request(1), M::Request(2)] {
+            received.record(&bincode::options().serialize(&message).unwrap())
+        }
+        received.record(&[]);
+        received.record_ordered_multicast();
+        metrics.publish(
+            3,
+            Gauges {
+                commit_num: Some(10),
+                ..Default::default()
+            },
+        );
+        let text = metrics.render();
+        for line in [
+            "replica_received_messages_total{type=\"Request\"} 2",
+            "replica_received_messages_total{type=\"Reply\"} 1",
+            "replica_received_messages_total{type=\"unknown\"} 1",
+            "replica_received_messages_total{type=\"OrderedMulticast\"} 1",
+            "replica_verify_queue_depth 3",
+            "replica_commit_number 10",
+        ] {
+            assert!(text.lines().any(|l| l == line), "{line} missing in\n{text}")
+        }
+        assert!(!text.contains("replica_log_length"));
+    }
+}
@@ -1,44 +1,145 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::{BuildHasher, RandomState},
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use bincode::Options;
+use control_messages::{MacCoverageStats, MacPattern, SequencerCurve, SignatureStats};
 use k256::{
-    ecdsa::{SigningKey, VerifyingKey},
     schnorr::signature::{DigestSigner, DigestVerifier},
     sha2::{Digest, Sha256},
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use super::{
     crypto::{DigestHash, Hasher, Invalid, Verifier, Verify},
-    Host, Receivers, ReplicaIndex,
+    sub_seed, Host, Receivers, ReplicaIndex,
 };
 
+// the signature, the digest, the linked digest and the flags that precede the payload
+pub const HEADER_LEN: usize = 101;
+// the byte of flags, which is neither hashed nor signed
+const FLAGS: usize = 100;
+
 pub fn serialize(message: &(impl Serialize + DigestHash)) -> Vec<u8> {
     let digest = Hasher::sha256(message).finalize();
     [
         &[0; 20],
         &digest[..8], // read by HalfSipHash
         &[0; 40],
-        &*digest, // read by K256
+        &*digest, // read by ECDSA
+        &[0],     // flags
         &bincode::options().serialize(message).unwrap(),
     ]
     .concat()
 }
 
+// debug trailer for breaking down the latency of the sequencing hop, appended by clients and filled
+// in by the software sequencer, see `Config::sequencing_timing`
+// messages are deserialized with trailing bytes allowed, so protocols never see it. a flag in the
+// header tells whether it is there, so no payload is mistaken for one
+// times are nanoseconds since unix epoch, which are comparable across hosts only as far as their
+// clocks are synchronized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timing {
+    pub client_send: u64,
+    // zero if the message is not sequenced by the software sequencer, e.g. by the switch
+    pub sequencer_receive: u64,
+    pub sequencer_send: u64,
+}
+
+impl Timing {
+    const FLAG: u8 = 1;
+    const LEN: usize = 24;
+
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as _
+    }
+
+    pub fn append(buf: &mut Vec<u8>) {
+        buf[FLAGS] |= Self::FLAG;
+        buf.extend(Self::now().to_le_bytes());
+        buf.extend([0; 16])
+    }
+
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.get(FLAGS)? & Self::FLAG == 0 || buf.len() < HEADER_LEN + Self::LEN {
+            return None;
+        }
+        let trailer = &buf[buf.len() - Self::LEN..];
+        let field = |i: usize| u64::from_le_bytes(trailer[i * 8..i * 8 + 8].try_into().unwrap());
+        Some(Self {
+            client_send: field(0),
+            sequencer_receive: field(1),
+            sequencer_send: field(2),
+        })
+    }
+
+    // write the current time to the `index`th field of the trailer, if there is one
+    fn stamp(buf: &mut [u8], index: usize) {
+        if Self::parse(buf).is_some() {
+            let offset = buf.len() - Self::LEN + index * 8;
+            buf[offset..offset + 8].copy_from_slice(&Self::now().to_le_bytes())
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderedMulticast<M> {
     pub seq_num: u32,
     pub signature: Signature,
     pub linked: [u8; 32],
     pub inner: M,
+    // not on the wire, the receiving dispatch tells it by the socket, see `Config::multicast_addrs`
+    pub group: u8,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Signature {
     HalfSipHash([[u8; 4]; 4]),
-    K256Linked,
+    Linked,
+    Ecdsa(EcdsaSignature),
+    EcdsaUnverified(EcdsaSignature),
+}
+
+// on the curve of `Variant::Ecdsa`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EcdsaSignature {
     K256(k256::ecdsa::Signature),
-    K256Unverified(k256::ecdsa::Signature),
+    P256(p256::ecdsa::Signature),
+}
+
+impl EcdsaSignature {
+    // r and s, big endian
+    fn from_bytes(curve: SequencerCurve, bytes: [u8; 64]) -> Option<Self> {
+        match curve {
+            SequencerCurve::K256 => k256::ecdsa::Signature::from_bytes(&bytes.into())
+                .ok()
+                .map(Self::K256),
+            SequencerCurve::P256 => p256::ecdsa::Signature::from_bytes(&bytes.into())
+                .ok()
+                .map(Self::P256),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 64] {
+        let mut bytes = [0; 64];
+        match self {
+            Self::K256(signature) => bytes.copy_from_slice(&signature.to_bytes()),
+            Self::P256(signature) => bytes.copy_from_slice(&signature.to_bytes()),
+        }
+        bytes
+    }
 }
 
 impl<M> std::ops::Deref for OrderedMulticast<M> {
@@ -58,9 +159,9 @@ impl DigestHash for Signature {
                 hasher.write(&code2[..]);
                 hasher.write(&code3[..])
             }
-            Self::K256Linked => {} // TODO
-            Self::K256(signature) => hasher.write(&signature.to_bytes()),
-            Self::K256Unverified(signature) => hasher.write(&signature.to_bytes()),
+            Self::Linked => {} // TODO
+            Self::Ecdsa(signature) => hasher.write(&signature.to_bytes()),
+            Self::EcdsaUnverified(signature) => hasher.write(&signature.to_bytes()),
         }
     }
 }
@@ -68,7 +169,7 @@ impl DigestHash for Signature {
 impl<M> OrderedMulticast<M> {
     pub fn verified(&self) -> bool {
         use Signature::*;
-        matches!(self.signature, HalfSipHash(_) | K256(_))
+        matches!(self.signature, HalfSipHash(_) | Ecdsa(_))
     }
 
     pub fn state(&self) -> Sha256
@@ -78,7 +179,7 @@ impl<M> OrderedMulticast<M> {
         use Signature::*;
         assert!(matches!(
             self.signature,
-            K256(_) | K256Unverified(_) | K256Linked
+            Ecdsa(_) | EcdsaUnverified(_) | Linked
         ));
         state_internal(
             self.linked,
@@ -98,39 +199,586 @@ fn state_internal(linked: [u8; 32], digest: [u8; 32], seq_num: u32) -> Sha256 {
     Sha256::new().chain_update(state)
 }
 
+// turn sequence numbers into consecutive op numbers starting from 1, and hold the messages that
+// arrive early until the gap before them is filled
+// the sequence number on the wire is 32-bit as the switch writes it, and wraps e.g. after about an
+// hour at 1M/s, or anytime since the switch is not reset between runs. it is extended to the 64-bit
+// op number that is nearest to the next expected one, which is correct as long as messages are
+// reordered by less than 2^31
+#[derive(Debug)]
+pub struct Reorder<M> {
+    // Jialin's trick to avoid resetting switch for every run
+    seq_num_offset: Option<u32>,
+    ordered_num: u64,
+    pending: HashMap<u64, OrderedMulticast<M>>,
+    // keyed hashes of the last ordered messages up to `ordered_num`, which tell a duplicated packet
+    // from the sequencer assigning the op number to another message, along with whether the
+    // message is authenticated on its own
+    // not a cryptographic digest, to keep it off the critical path, but the key is drawn by each
+    // receiver and never leaves it, so no sender can craft collisions
+    history: VecDeque<(u64, bool)>,
+    hasher: RandomState,
+    pub stats: HashMap<Host, SourceStats>,
+}
+
+// of the ordered multicast from a source, i.e. the switch forwarding for a client, or an unknown
+// sender such as a relay
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SourceStats {
+    // the op number is ordered or held already with the same message
+    pub duplicated: u64,
+    // the op number is lower than one received from the source before, i.e. reordered on the way
+    pub regressed: u64,
+    // the op number is ordered or held already with another message, i.e. evidence of the
+    // sequencer equivocating
+    pub equivocated: u64,
+    pub max_op_num: u64,
+}
+
+#[derive(Debug)]
+pub enum Reordered<M> {
+    // sequenced before the first received message, or ordered too long ago to tell whether it is
+    // duplicated, returned along with the message so receivers can check it against their logs
+    // op number 0 for the messages sequenced before the first received one
+    Stale(u64, OrderedMulticast<M>),
+    // the op number is ordered or held already with the same message
+    Duplicated,
+    // the op number is ordered or held already with another message, both authenticated on their
+    // own, i.e. proven to be sequenced by the sequencer
+    Equivocated(u64, OrderedMulticast<M>),
+    // the op number is ordered or held already with another message, and either of them is not
+    // authenticated on its own, see `OrderedMulticast::verified`, so it may as well be forged
+    Conflicted,
+    // held until the gap is filled
+    Pending,
+    // too far ahead of the ordered ones to be held, e.g. numbered by the sequencer of another
+    // epoch, as the header has no room for the epoch
+    Beyond,
+    // the received message, followed by the pending ones that are in order now
+    Ordered(Vec<(u64, OrderedMulticast<M>)>),
+}
+
+impl<M> Default for Reorder<M> {
+    fn default() -> Self {
+        Self {
+            seq_num_offset: None,
+            ordered_num: 0,
+            pending: Default::default(),
+            history: Default::default(),
+            hasher: Default::default(),
+            stats: Default::default(),
+        }
+    }
+}
+
+impl<M> Reorder<M> {
+    // continue the op numbers after `ordered_num` with the messages of another sequencer, whose
+    // sequence numbers start anew from 1, see `Sequencer::restart`
+    // unlike the first sequencer, the offset is not taken from the first received message, which
+    // may be any one of the new sequencer's if the ones before are dropped, e.g. while the epoch
+    // changes
+    pub fn resume(ordered_num: u64) -> Self {
+        Self {
+            seq_num_offset: Some(1u32.wrapping_sub(ordered_num as u32)),
+            ordered_num,
+            ..Default::default()
+        }
+    }
+}
+
+impl<M: DigestHash> Reorder<M> {
+    // more than the reordering that `QueryTrigger` tolerates
+    const HISTORY_LEN: usize = 1 << 16;
+
+    fn hash(&self, message: &OrderedMulticast<M>) -> u64 {
+        let mut hasher = self.hasher.build_hasher();
+        message.inner.hash(&mut hasher);
+        std::hash::Hasher::finish(&hasher)
+    }
+
+    // the multicast from `remote`, see `receive`
+    pub fn receive_from(&mut self, remote: Host, message: OrderedMulticast<M>) -> Reordered<M> {
+        let op_num = self.op_num(message.seq_num);
+        let stats = self.stats.entry(remote).or_default();
+        if op_num < stats.max_op_num {
+            stats.regressed += 1
+        }
+        stats.max_op_num = stats.max_op_num.max(op_num);
+        let reordered = self.receive(message);
+        let stats = self.stats.get_mut(&remote).unwrap();
+        match reordered {
+            Reordered::Duplicated => stats.duplicated += 1,
+            Reordered::Equivocated(..) => stats.equivocated += 1,
+            _ => {}
+        }
+        reordered
+    }
+
+    pub fn receive(&mut self, message: OrderedMulticast<M>) -> Reordered<M> {
+        let op_num = self.op_num(message.seq_num);
+        // held back no further ahead than remembered behind
+        if op_num > self.ordered_num + Self::HISTORY_LEN as u64 {
+            return Reordered::Beyond;
+        }
+        let ordered = if let Some(pending) = self.pending.get(&op_num) {
+            Some((self.hash(pending), pending.verified()))
+        } else if op_num <= self.ordered_num {
+            let offset = self.ordered_num - op_num;
+            if op_num == 0 || offset >= self.history.len() as u64 {
+                return Reordered::Stale(op_num, message);
+            }
+            Some(self.history[self.history.len() - 1 - offset as usize])
+        } else {
+            None
+        };
+        if let Some((ordered_hash, ordered_verified)) = ordered {
+            if self.hash(&message) == ordered_hash {
+                return Reordered::Duplicated;
+            }
+            if ordered_verified && message.verified() {
+                return Reordered::Equivocated(op_num, message);
+            }
+            // a forged message held for the op number does not keep out the authenticated one
+            if message.verified() && op_num > self.ordered_num {
+                self.pending.insert(op_num, message);
+            }
+            return Reordered::Conflicted;
+        }
+        if op_num != self.ordered_num + 1 {
+            self.pending.insert(op_num, message);
+            return Reordered::Pending;
+        }
+        self.ordered_num += 1;
+        self.push_history(&message);
+        let mut ordered = vec![(op_num, message)];
+        while let Some(message) = self.pending.remove(&(self.ordered_num + 1)) {
+            self.ordered_num += 1;
+            self.push_history(&message);
+            ordered.push((self.ordered_num, message))
+        }
+        Reordered::Ordered(ordered)
+    }
+
+    fn push_history(&mut self, message: &OrderedMulticast<M>) {
+        if self.history.len() == Self::HISTORY_LEN {
+            self.history.pop_front();
+        }
+        let hash = self.hash(message);
+        self.history.push_back((hash, message.verified()))
+    }
+}
+
+impl<M> Reorder<M> {
+    pub fn source_stats(&self) -> Vec<control_messages::MulticastSourceStats> {
+        Vec::from_iter(self.stats.iter().map(|(source, stats)| {
+            control_messages::MulticastSourceStats {
+                source: format!("{source:?}"),
+                duplicated: stats.duplicated,
+                regressed: stats.regressed,
+                equivocated: stats.equivocated,
+                max_op_num: stats.max_op_num,
+            }
+        }))
+    }
+
+    pub fn ordered_num(&self) -> u64 {
+        self.ordered_num
+    }
+
+    pub fn num_pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn get_pending(&self, op_num: u64) -> Option<&OrderedMulticast<M>> {
+        self.pending.get(&op_num)
+    }
+
+    fn op_num(&mut self, seq_num: u32) -> u64 {
+        let offset = *self
+            .seq_num_offset
+            .get_or_insert(seq_num.wrapping_sub(self.ordered_num as u32));
+        // serial number arithmetic of RFC 1982, relative to the next expected sequence number
+        let expected = offset.wrapping_add(self.ordered_num as u32);
+        let distance = seq_num.wrapping_sub(expected) as i32;
+        (self.ordered_num + 1).saturating_add_signed(distance as _)
+    }
+}
+
+// the streams of the multicast groups, each reordered on its own and merged into one op sequence by
+// taking their messages round-robin, i.e. the `k`th message of group `g` is op
+// `(k - 1) * num_group + g + 1`
+// a group without traffic holds back the others, so the epoch leader fills it with empty
+// messages, see `fills`
+#[derive(Debug)]
+pub struct Merge<M> {
+    reorders: Vec<Reorder<M>>,
+    // ordered in their groups and waiting for their turns
+    ready: Vec<VecDeque<OrderedMulticast<M>>>,
+    ordered_num: u64,
+}
+
+impl<M> Merge<M> {
+    pub fn new(num_group: usize) -> Self {
+        Self {
+            reorders: Vec::from_iter((0..num_group).map(|_| Default::default())),
+            ready: Vec::from_iter((0..num_group).map(|_| Default::default())),
+            ordered_num: 0,
+        }
+    }
+
+    // see `Reorder::resume`, every group has a new sequencer
+    pub fn resume(num_group: usize, ordered_num: u64) -> Self {
+        Self {
+            reorders: Vec::from_iter(
+                (0..num_group)
+                    .map(|group| Reorder::resume(group_op_num(ordered_num, num_group, group))),
+            ),
+            ready: Vec::from_iter((0..num_group).map(|_| Default::default())),
+            ordered_num,
+        }
+    }
+
+    pub fn num_group(&self) -> usize {
+        self.reorders.len()
+    }
+
+    fn op_num(&self, group: usize, group_op_num: u64) -> u64 {
+        if group_op_num == 0 {
+            return 0;
+        }
+        (group_op_num - 1) * self.reorders.len() as u64 + group as u64 + 1
+    }
+
+    pub fn ordered_num(&self) -> u64 {
+        self.ordered_num
+    }
+
+    // held back for a gap in their groups or for their turns
+    pub fn num_pending(&self) -> usize {
+        self.num_reordering() + self.ready.iter().map(VecDeque::len).sum::<usize>()
+    }
+
+    // held back for a gap in their groups only, which is missing from the sequencers' streams
+    pub fn num_reordering(&self) -> usize {
+        self.reorders.iter().map(Reorder::num_pending).sum()
+    }
+
+    // whether the op to merge next is missing from its group's stream, i.e. later messages of the
+    // group are received, rather than not sent yet
+    pub fn is_next_missing(&self) -> bool {
+        self.reorders[(self.ordered_num % self.reorders.len() as u64) as usize].num_pending() != 0
+    }
+
+    // whether the ready messages of some groups wait for the turns of the others
+    pub fn is_held_back(&self) -> bool {
+        self.ready.iter().any(|ready| !ready.is_empty())
+    }
+
+    // the groups without pending messages that hold back the others, each with its op numbers to
+    // fill so every ready message is merged, numbered as in `Reorder::resume`
+    pub fn fills(&self) -> Vec<(u8, Range<u64>)> {
+        let num_group = self.reorders.len();
+        let Some(last_num) = (0..num_group)
+            .filter(|&group| !self.ready[group].is_empty())
+            .map(|group| {
+                let group_op_num = group_op_num(self.ordered_num, num_group, group);
+                self.op_num(group, group_op_num + self.ready[group].len() as u64)
+            })
+            .max()
+        else {
+            return Vec::new();
+        };
+        Vec::from_iter((0..num_group).filter_map(|group| {
+            if !self.ready[group].is_empty() || self.reorders[group].num_pending() != 0 {
+                return None;
+            }
+            let group_op_nums = group_op_num(self.ordered_num, num_group, group) + 1
+                ..group_op_num(last_num, num_group, group) + 1;
+            Some((group as u8, group_op_nums)).filter(|(_, op_nums)| !op_nums.is_empty())
+        }))
+    }
+
+    pub fn get_pending(&self, op_num: u64) -> Option<&OrderedMulticast<M>> {
+        let num_group = self.reorders.len();
+        let group = ((op_num - 1) % num_group as u64) as usize;
+        let group_op_num = (op_num - 1) / num_group as u64 + 1;
+        // the first one ready is the next of the group to merge
+        let ready_num =
+            group_op_num.checked_sub(self::group_op_num(self.ordered_num, num_group, group) + 1)?;
+        self.ready[group]
+            .get(ready_num as usize)
+            .or_else(|| self.reorders[group].get_pending(group_op_num))
+    }
+
+    pub fn source_stats(&self) -> Vec<control_messages::MulticastSourceStats> {
+        if self.reorders.len() == 1 {
+            return self.reorders[0].source_stats();
+        }
+        Vec::from_iter(
+            self.reorders
+                .iter()
+                .enumerate()
+                .flat_map(|(group, reorder)| {
+                    reorder.source_stats().into_iter().map(move |mut stats| {
+                        stats.source = format!("{} group {group}", stats.source);
+                        stats
+                    })
+                }),
+        )
+    }
+}
+
+// the messages of `group` among the first `ordered_num` ops
+fn group_op_num(ordered_num: u64, num_group: usize, group: usize) -> u64 {
+    (ordered_num + (num_group - 1 - group) as u64) / num_group as u64
+}
+
+impl<M: DigestHash> Merge<M> {
+    // same as `Reorder::receive_from` with op numbers of the merged sequence, and `Pending` for the
+    // messages waiting for their turns as well
+    pub fn receive_from(&mut self, remote: Host, message: OrderedMulticast<M>) -> Reordered<M> {
+        let group = message.group as usize;
+        let Some(reorder) = self.reorders.get_mut(group) else {
+            // of no group, so it is never ordered
+            return Reordered::Stale(0, message);
+        };
+        match reorder.receive_from(remote, message) {
+            Reordered::Stale(op_num, message) => {
+                Reordered::Stale(self.op_num(group, op_num), message)
+            }
+            Reordered::Equivocated(op_num, message) => {
+                Reordered::Equivocated(self.op_num(group, op_num), message)
+            }
+            Reordered::Duplicated => Reordered::Duplicated,
+            Reordered::Pending => Reordered::Pending,
+            Reordered::Conflicted => Reordered::Conflicted,
+            Reordered::Beyond => Reordered::Beyond,
+            Reordered::Ordered(messages) => {
+                self.ready[group].extend(messages.into_iter().map(|(_, message)| message));
+                let mut ordered = Vec::new();
+                while let Some(message) =
+                    self.ready[(self.ordered_num % self.reorders.len() as u64) as usize].pop_front()
+                {
+                    self.ordered_num += 1;
+                    ordered.push((self.ordered_num, message))
+                }
+                if ordered.is_empty() {
+                    Reordered::Pending
+                } else {
+                    Reordered::Ordered(ordered)
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Variant {
     Unreachable,
     HalfSipHash(HalfSipHash),
-    K256(K256),
+    Ecdsa(Ecdsa),
 }
 
 #[derive(Debug, Clone)]
 pub struct HalfSipHash {
     index: ReplicaIndex,
-    //
+    // the sequencer only carries codes for a window of 4 replicas per packet, so a replica may
+    // receive multicast without a code for itself
+    // reject such packets once the covered portion drops below this ratio, i.e. 1.0 rejects all
+    min_coverage: f64,
+    pattern: MacPattern,
+    coverage: Arc<Coverage>,
+}
+
+#[derive(Debug, Default)]
+struct Coverage {
+    verified: AtomicU64,
+    unverified: AtomicU64,
+    // consecutive unverified messages so far, and the most ever
+    unverified_run: AtomicU64,
+    max_unverified_run: AtomicU64,
+}
+
+impl Coverage {
+    fn check(&self, covered: bool, min_coverage: f64) -> Result<(), Invalid> {
+        if covered {
+            self.verified.fetch_add(1, Relaxed);
+            self.unverified_run.store(0, Relaxed);
+            return Ok(());
+        }
+        let run = self.unverified_run.fetch_add(1, Relaxed) + 1;
+        self.max_unverified_run.fetch_max(run, Relaxed);
+        let unverified = self.unverified.fetch_add(1, Relaxed) + 1;
+        let verified = self.verified.load(Relaxed);
+        if (verified as f64) < min_coverage * (verified + unverified) as f64 {
+            Err(Invalid::Private)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl HalfSipHash {
+    // the sequencer writes placeholder codes that carry the replica index in the last byte,
+    // while the switch writes real codes for the window that the packet is sent for
+    fn covered(&self, codes: &[[u8; 4]; 4]) -> bool {
+        let code = codes[self.index as usize % 4];
+        code != [0; 4] && (code[..3] != [0xcc; 3] || code[3] == self.index)
+    }
 }
 
 #[derive(Debug, Clone)]
-pub struct K256 {
-    verifying_key: VerifyingKey,
+pub struct Ecdsa {
+    curve: SequencerCurve,
+    verifying_keys: Arc<RwLock<SequencerKeys>>,
+    // the sequencer signs every this many messages, see `Sequencer::new_ecdsa`
+    sign_interval: u32,
+    signatures: Arc<Signatures>,
+}
+
+// the header has no room for a key epoch, so after the sequencer key is recycled, signatures are
+// checked against the previous key as well for a grace window, see `Variant::rotate_sequencer_key`
+#[derive(Debug)]
+struct SequencerKeys {
+    current: SequencerVerifyingKey,
+    previous: Option<(SequencerVerifyingKey, Instant)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequencerVerifyingKey {
+    K256(k256::ecdsa::VerifyingKey),
+    P256(p256::ecdsa::VerifyingKey),
+}
+
+impl SequencerVerifyingKey {
+    pub fn from_sec1_bytes(curve: SequencerCurve, bytes: &[u8]) -> Option<Self> {
+        match curve {
+            SequencerCurve::K256 => k256::ecdsa::VerifyingKey::from_sec1_bytes(bytes)
+                .ok()
+                .map(Self::K256),
+            SequencerCurve::P256 => p256::ecdsa::VerifyingKey::from_sec1_bytes(bytes)
+                .ok()
+                .map(Self::P256),
+        }
+    }
+
+    fn verify(&self, state: Sha256, signature: &EcdsaSignature) -> bool {
+        match (self, signature) {
+            (Self::K256(key), EcdsaSignature::K256(signature)) => {
+                key.verify_digest(state, signature).is_ok()
+            }
+            (Self::P256(key), EcdsaSignature::P256(signature)) => {
+                key.verify_digest(state, signature).is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SequencerSigningKey {
+    K256(k256::ecdsa::SigningKey),
+    P256(p256::ecdsa::SigningKey),
+}
+
+impl SequencerSigningKey {
+    // none if the secret is not a valid scalar of the curve
+    pub fn from_slice(curve: SequencerCurve, secret: &[u8]) -> Option<Self> {
+        match curve {
+            SequencerCurve::K256 => k256::ecdsa::SigningKey::from_slice(secret)
+                .ok()
+                .map(Self::K256),
+            SequencerCurve::P256 => p256::ecdsa::SigningKey::from_slice(secret)
+                .ok()
+                .map(Self::P256),
+        }
+    }
+
+    // the one of the switch on secp256k1, which the software sequencer also uses on P-256
+    fn switch(curve: SequencerCurve) -> Self {
+        Self::from_slice(curve, SWITCH_SIGNING_KEY).unwrap()
+    }
+
+    pub fn verifying_key(&self) -> SequencerVerifyingKey {
+        match self {
+            Self::K256(key) => SequencerVerifyingKey::K256(*key.verifying_key()),
+            Self::P256(key) => SequencerVerifyingKey::P256(*key.verifying_key()),
+        }
+    }
+
+    fn sign(&self, state: Sha256) -> EcdsaSignature {
+        match self {
+            Self::K256(key) => EcdsaSignature::K256(key.sign_digest(state)),
+            Self::P256(key) => EcdsaSignature::P256(key.sign_digest(state)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Signatures {
+    verified: AtomicU64,
+    // signed but accepted through the link of a later verified message
+    skipped: AtomicU64,
+    // not signed, i.e. accepted through the link only
+    linked: AtomicU64,
+    // signed off the interval, because the message ends a burst
+    fallback: AtomicU64,
 }
 
 const SWITCH_SIGNING_KEY: &[u8] = include_bytes!("switch_signing_key");
 
 impl Variant {
-    pub fn new_half_sip_hash(index: ReplicaIndex) -> Self {
-        Self::HalfSipHash(HalfSipHash { index })
+    pub fn new_half_sip_hash(index: ReplicaIndex, min_coverage: f64) -> Self {
+        Self::HalfSipHash(HalfSipHash {
+            index,
+            min_coverage,
+            pattern: Default::default(),
+            coverage: Default::default(),
+        })
     }
 
-    pub fn new_k256() -> Self {
-        Self::K256(K256 {
-            verifying_key: *SigningKey::from_slice(SWITCH_SIGNING_KEY)
-                .unwrap()
-                .verifying_key(),
+    // the pattern of the sequencer, which replicas need to know to accept every message once
+    pub fn with_mac_pattern(mut self, pattern: MacPattern) -> Self {
+        let Self::HalfSipHash(variant) = &mut self else {
+            panic!("MAC pattern only applies to HalfSipHash")
+        };
+        variant.pattern = pattern;
+        self
+    }
+
+    pub fn new_ecdsa(curve: SequencerCurve) -> Self {
+        Self::Ecdsa(Ecdsa {
+            curve,
+            verifying_keys: Arc::new(RwLock::new(SequencerKeys {
+                current: SequencerSigningKey::switch(curve).verifying_key(),
+                previous: None,
+            })),
+            sign_interval: 1,
+            signatures: Default::default(),
         })
     }
 
+    pub fn with_sign_interval(mut self, sign_interval: u32) -> Self {
+        let Self::Ecdsa(variant) = &mut self else {
+            panic!("sign interval only applies to ECDSA")
+        };
+        assert_ne!(sign_interval, 0);
+        variant.sign_interval = sign_interval;
+        self
+    }
+
+    // accept the signatures of the sequencer made with `key` from now on, and the ones made with the
+    // replaced key for `grace` more, while the sequencer switches over, see
+    // `Sequencer::rotate_signing_key`
+    pub fn rotate_sequencer_key(&self, key: SequencerVerifyingKey, grace: Duration) {
+        let Self::Ecdsa(variant) = self else {
+            panic!("sequencer key only applies to ECDSA")
+        };
+        let mut keys = variant.verifying_keys.write().unwrap();
+        let previous = std::mem::replace(&mut keys.current, key);
+        keys.previous = Some((previous, Instant::now() + grace))
+    }
+
     pub fn deserialize<M>(&self, buf: impl AsRef<[u8]>) -> OrderedMulticast<M>
     where
         M: DeserializeOwned,
@@ -157,17 +805,17 @@ impl Variant {
                 codes[3].copy_from_slice(&buf[16..20]);
                 Signature::HalfSipHash(codes)
             }
-            Self::K256(_) if buf[4..68].iter().all(|&b| b == 0) => Signature::K256Linked,
-            Self::K256(_) => {
+            Self::Ecdsa(_) if buf[4..68].iter().all(|&b| b == 0) => Signature::Linked,
+            Self::Ecdsa(variant) => {
                 let mut signature = [0; 64];
                 signature.copy_from_slice(&buf[4..68]);
                 signature.reverse();
                 // println!("{:02x?}", signature);
-                Signature::K256(k256::ecdsa::Signature::from_bytes(&signature.into()).unwrap())
+                Signature::Ecdsa(EcdsaSignature::from_bytes(variant.curve, signature).unwrap())
             }
         };
         let mut linked = [0; 32];
-        if matches!(self, Self::K256(_)) {
+        if matches!(self, Self::Ecdsa(_)) {
             linked.copy_from_slice(&buf[68..100]);
         }
         OrderedMulticast {
@@ -176,8 +824,9 @@ impl Variant {
             linked,
             inner: bincode::options()
                 .allow_trailing_bytes()
-                .deserialize(&buf[100..])
+                .deserialize(&buf[HEADER_LEN..])
                 .unwrap(),
+            group: 0,
         }
     }
 
@@ -185,6 +834,19 @@ impl Variant {
     where
         M: DigestHash,
     {
+        if let Self::Ecdsa(variant) = self {
+            let signatures = &variant.signatures;
+            match message.signature {
+                Signature::Linked => signatures.linked.fetch_add(1, Relaxed),
+                Signature::EcdsaUnverified(_) => signatures.skipped.fetch_add(1, Relaxed),
+                _ => signatures.verified.fetch_add(1, Relaxed),
+            };
+            if message.signature != Signature::Linked
+                && !message.seq_num.is_multiple_of(variant.sign_interval)
+            {
+                signatures.fallback.fetch_add(1, Relaxed);
+            }
+        }
         let digest = <[_; 32]>::from(Hasher::sha256(&**message).finalize());
         match (self, message.signature) {
             (Self::Unreachable, _) => unreachable!(),
@@ -194,109 +856,179 @@ impl Variant {
                 if std::collections::hash_map::RandomState::new().hash_one(digest) == 0 {
                     return Err(Invalid::Private);
                 }
-                if codes[variant.index as usize % 4] == [0; 4] {
-                    return Err(Invalid::Private);
+                variant
+                    .coverage
+                    .check(variant.covered(&codes), variant.min_coverage)
+            }
+            (Self::Ecdsa(_), Signature::Linked)
+            | (Self::Ecdsa(_), Signature::EcdsaUnverified(_)) => Ok(()),
+            (Self::Ecdsa(variant), Signature::Ecdsa(signature)) => {
+                let keys = variant.verifying_keys.read().unwrap();
+                let mut verified = keys.current.verify(message.state(), &signature);
+                if let (false, Some((previous, deadline))) = (verified, &keys.previous) {
+                    if Instant::now() < *deadline {
+                        verified = previous.verify(message.state(), &signature)
+                    }
+                }
+                if verified {
+                    Ok(())
+                } else {
+                    Err(Invalid::Public)
                 }
-                Ok(())
             }
-            (Self::K256(_), Signature::K256Linked)
-            | (Self::K256(_), Signature::K256Unverified(_)) => Ok(()),
-            (Self::K256(k256), Signature::K256(signature)) => k256
-                .verifying_key
-                .verify_digest(message.state(), &signature)
-                .map_err(|_| Invalid::Public),
             _ => unimplemented!(),
         }
     }
 }
 
+impl Variant {
+    // the sequencer that produces multicast acceptable by this variant
+    pub fn sequencer(&self, num_replica: usize) -> Option<Sequencer> {
+        match self {
+            Self::Unreachable => None,
+            Self::HalfSipHash(variant) => {
+                Some(Sequencer::new_half_sip_hash(num_replica, variant.pattern))
+            }
+            Self::Ecdsa(variant) => {
+                Some(Sequencer::new_ecdsa(variant.curve, variant.sign_interval))
+            }
+        }
+    }
+
+    // the curve that the sequencer keys are on, ECDSA only
+    pub fn sequencer_curve(&self) -> Option<SequencerCurve> {
+        if let Self::Ecdsa(variant) = self {
+            Some(variant.curve)
+        } else {
+            None
+        }
+    }
+
+    pub fn signatures(&self) -> Option<SignatureStats> {
+        if let Self::Ecdsa(variant) = self {
+            Some(SignatureStats {
+                verified: variant.signatures.verified.load(Relaxed),
+                skipped: variant.signatures.skipped.load(Relaxed),
+                linked: variant.signatures.linked.load(Relaxed),
+                fallback: variant.signatures.fallback.load(Relaxed),
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn coverage(&self) -> Option<MacCoverageStats> {
+        if let Self::HalfSipHash(variant) = self {
+            Some(MacCoverageStats {
+                verified: variant.coverage.verified.load(Relaxed),
+                unverified: variant.coverage.unverified.load(Relaxed),
+                max_unverified_run: variant.coverage.max_unverified_run.load(Relaxed),
+            })
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Delegate<M> {
-    Nop(ReplicaIndex),
-    K256(Option<(Host, OrderedMulticast<M>)>),
+    // the replica to keep only its own packet for, when there is a packet per window
+    Nop(Option<ReplicaIndex>),
+    Ecdsa(Option<(Host, OrderedMulticast<M>)>),
 }
 
 impl Variant {
     pub fn delegate<M>(&self) -> Delegate<M> {
         match self {
-            Self::Unreachable => Delegate::Nop(ReplicaIndex::MAX),
-            Self::HalfSipHash(variant) => Delegate::Nop(variant.index),
-            Self::K256(_) => Delegate::K256(Default::default()),
+            Self::Unreachable => Delegate::Nop(None),
+            Self::HalfSipHash(variant) => Delegate::Nop(
+                Some(variant.index).filter(|_| variant.pattern == MacPattern::Windows),
+            ),
+            Self::Ecdsa(_) => Delegate::Ecdsa(Default::default()),
         }
     }
 }
 
+// the messages that fail verification are dropped rather than handled, as anyone may send to the
+// multicast address, and the error is returned for the caller to count
 impl<M> Delegate<M> {
     pub fn on_receive<N>(
         &mut self,
         remote: Host,
         message: OrderedMulticast<M>,
-        receivers: &mut impl Receivers<Message = N>,
+        receivers: &mut (impl Receivers<Message = N> + ?Sized),
         verifier: &Verifier,
         into: impl Fn(OrderedMulticast<M>) -> N,
-    ) where
+    ) -> Result<(), Invalid>
+    where
         N: Verify,
     {
         match self {
             &mut Self::Nop(index) => {
-                if let Signature::HalfSipHash(codes) = &message.signature {
+                if let (Some(index), Signature::HalfSipHash(codes)) = (index, &message.signature) {
                     let code = codes[index as usize % 4];
                     if code[0] == 0xcc && code[1] == 0xcc && code[2] == 0xcc && code[3] != index {
-                        return;
+                        return Ok(());
                     }
                 }
                 let message = into(message);
-                message.verify(verifier).unwrap();
-                receivers.handle(Host::Multicast, remote, message)
+                message.verify(verifier)?;
+                receivers.handle(Host::Multicast, remote, message);
+                Ok(())
             }
-            Self::K256(saved) => {
+            Self::Ecdsa(saved) => {
                 let (remote, message) = if !message.verified() {
                     (remote, message)
                 } else if let Some((saved_remote, saved_message)) = saved.replace((remote, message))
                 {
                     let OrderedMulticast {
                         seq_num,
-                        signature: Signature::K256(signature),
+                        signature: Signature::Ecdsa(signature),
                         linked,
                         inner,
+                        group,
                     } = saved_message
                     else {
                         unreachable!()
                     };
                     let saved_message = OrderedMulticast {
                         seq_num,
-                        signature: Signature::K256Unverified(signature),
+                        signature: Signature::EcdsaUnverified(signature),
                         linked,
                         inner,
+                        group,
                     };
                     (saved_remote, saved_message)
                 } else {
-                    return;
+                    return Ok(());
                 };
                 let message = into(message);
-                message.verify(verifier).unwrap();
-                receivers.handle(Host::Multicast, remote, message)
+                message.verify(verifier)?;
+                receivers.handle(Host::Multicast, remote, message);
+                Ok(())
             }
         }
     }
 
     pub fn on_pace<N>(
         &mut self,
-        receivers: &mut impl Receivers<Message = N>,
+        receivers: &mut (impl Receivers<Message = N> + ?Sized),
         verifier: &Verifier,
         into: impl Fn(OrderedMulticast<M>) -> N,
-    ) where
+    ) -> Result<(), Invalid>
+    where
         N: Verify,
     {
-        if let Self::K256(saved) = self {
+        if let Self::Ecdsa(saved) = self {
             if let Some((remote, message)) = saved.take() {
                 let message = into(message);
-                message.verify(verifier).unwrap();
+                message.verify(verifier)?;
                 receivers.handle(Host::Multicast, remote, message)
             } else {
                 // println!("! no signed ordered multicast buffer")
             }
         }
+        Ok(())
     }
 }
 
@@ -304,35 +1036,97 @@ impl<M> Delegate<M> {
 pub struct Sequencer {
     seq_num: u32,
     crypto: SequencerCrypto,
+    fault: Option<SequencerFault>,
+    seed: u64,
+}
+
+// misbehavior of a faulty sequencer, injected in simulation to test that replicas detect and
+// contain it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequencerFault {
+    // never assign the sequence number to any message
+    Skip(u32),
+    // assign the sequence number to the message after as well
+    Duplicate(u32),
+    // sign a link hash that does not extend the previous message, ECDSA only
+    BreakLink(u32),
 }
 
 #[derive(Debug, Clone)]
 enum SequencerCrypto {
     HalfSipHash {
         num_replica: usize,
+        pattern: MacPattern,
     },
-    K256 {
+    Ecdsa {
         state: Sha256,
-        signing_key: Arc<SigningKey>,
+        signing_key: Arc<SequencerSigningKey>,
+        sign_interval: u32,
     },
 }
 
 impl Sequencer {
-    pub fn new_half_sip_hash(num_replica: usize) -> Self {
+    // randomness of `MacPattern::Random` is derived from this and the sequence number, so it is
+    // reproducible regardless of which thread applies the process
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn new_half_sip_hash(num_replica: usize, pattern: MacPattern) -> Self {
         Self {
             seq_num: 0,
-            crypto: SequencerCrypto::HalfSipHash { num_replica },
+            crypto: SequencerCrypto::HalfSipHash {
+                num_replica,
+                pattern,
+            },
+            fault: None,
+            seed: 0,
         }
     }
 
-    pub fn new_k256() -> Self {
+    // sign every `sign_interval` messages, leaving the ones in between verified by the link only,
+    // see `SequencerProcess::sign` for not leaving the last ones of a burst unsigned
+    pub fn new_ecdsa(curve: SequencerCurve, sign_interval: u32) -> Self {
+        assert_ne!(sign_interval, 0);
         Self {
             seq_num: 0,
-            crypto: SequencerCrypto::K256 {
+            crypto: SequencerCrypto::Ecdsa {
                 state: Default::default(),
-                signing_key: Arc::new(SigningKey::from_slice(SWITCH_SIGNING_KEY).unwrap()),
+                signing_key: Arc::new(SequencerSigningKey::switch(curve)),
+                sign_interval,
             },
+            fault: None,
+            seed: 0,
+        }
+    }
+
+    // a sequencer that takes over from this one, with the same keys and its own numbering
+    pub fn restart(&self) -> Self {
+        let mut crypto = self.crypto.clone();
+        if let SequencerCrypto::Ecdsa { state, .. } = &mut crypto {
+            *state = Default::default()
         }
+        Self {
+            seq_num: 0,
+            crypto,
+            fault: None,
+            seed: self.seed,
+        }
+    }
+
+    // sign with `key` from the next message on, which replicas must be told about first, see
+    // `Variant::rotate_sequencer_key`
+    pub fn rotate_signing_key(&mut self, key: SequencerSigningKey) {
+        let SequencerCrypto::Ecdsa { signing_key, .. } = &mut self.crypto else {
+            panic!("signing key only applies to ECDSA")
+        };
+        *signing_key = Arc::new(key)
+    }
+
+    // the fault takes effect once, replacing the previously injected one if it has not
+    pub fn inject_fault(&mut self, fault: SequencerFault) {
+        self.fault = Some(fault)
     }
 }
 
@@ -347,30 +1141,59 @@ pub struct SequencerProcess {
 enum SequencerProcessCrypto {
     HalfSipHash {
         num_replica: usize,
+        pattern: MacPattern,
+        seed: u64,
     },
-    K256 {
+    Ecdsa {
         linked: [u8; 32],
         state: Sha256,
-        signing_key: Arc<SigningKey>,
+        signing_key: Arc<SequencerSigningKey>,
+        sign: bool,
     },
 }
 
 impl Sequencer {
-    pub fn process(&mut self, buf: Vec<u8>) -> SequencerProcess {
+    pub fn process(&mut self, mut buf: Vec<u8>) -> SequencerProcess {
+        Timing::stamp(&mut buf, 1);
         self.seq_num += 1;
-        let crypto = match &mut self.crypto {
-            &mut SequencerCrypto::HalfSipHash { num_replica } => {
-                SequencerProcessCrypto::HalfSipHash { num_replica }
+        match self.fault {
+            Some(SequencerFault::Skip(seq_num)) if seq_num == self.seq_num => {
+                self.fault = None;
+                self.seq_num += 1
+            }
+            Some(SequencerFault::Duplicate(seq_num)) if seq_num + 1 == self.seq_num => {
+                self.fault = None;
+                self.seq_num = seq_num
             }
-            SequencerCrypto::K256 { state, signing_key } => {
+            _ => {}
+        }
+        let crypto = match &mut self.crypto {
+            &mut SequencerCrypto::HalfSipHash {
+                num_replica,
+                pattern,
+            } => SequencerProcessCrypto::HalfSipHash {
+                num_replica,
+                pattern,
+                seed: self.seed,
+            },
+            SequencerCrypto::Ecdsa {
+                state,
+                signing_key,
+                sign_interval,
+            } => {
                 let mut digest = [0; 32];
                 digest.copy_from_slice(&buf[68..100]);
-                let linked = std::mem::take(state).finalize().into();
+                let mut linked = std::mem::take(state).finalize().into();
+                if self.fault == Some(SequencerFault::BreakLink(self.seq_num)) {
+                    self.fault = None;
+                    linked = [0xff; 32]
+                }
                 *state = state_internal(linked, digest, self.seq_num);
-                SequencerProcessCrypto::K256 {
+                SequencerProcessCrypto::Ecdsa {
                     linked,
                     state: state.clone(),
                     signing_key: signing_key.clone(),
+                    sign: self.seq_num.is_multiple_of(*sign_interval),
                 }
             }
         };
@@ -383,32 +1206,520 @@ impl Sequencer {
 }
 
 impl SequencerProcess {
+    pub fn seq_num(&self) -> u32 {
+        self.seq_num
+    }
+
+    // sign off the interval, for the last message before the sequencer goes idle, which no later
+    // signature covers
+    pub fn sign(&mut self) {
+        if let SequencerProcessCrypto::Ecdsa { sign, .. } = &mut self.crypto {
+            *sign = true
+        }
+    }
+
     pub fn apply(mut self, send: impl Fn(&[u8])) {
         self.buf[0..4].copy_from_slice(&self.seq_num.to_be_bytes());
+        Timing::stamp(&mut self.buf, 2);
         match self.crypto {
-            SequencerProcessCrypto::HalfSipHash { num_replica } => {
-                let mut offset = 0;
-                while offset < num_replica as u8 {
-                    self.buf[4..8].copy_from_slice(&[0xcc, 0xcc, 0xcc, offset]);
-                    self.buf[8..12].copy_from_slice(&[0xcc, 0xcc, 0xcc, offset + 1]);
-                    self.buf[12..16].copy_from_slice(&[0xcc, 0xcc, 0xcc, offset + 2]);
-                    self.buf[16..20].copy_from_slice(&[0xcc, 0xcc, 0xcc, offset + 3]);
-                    send(&self.buf);
-                    offset += 4
+            SequencerProcessCrypto::HalfSipHash {
+                num_replica,
+                pattern,
+                seed,
+            } => {
+                let num_window = num_replica.div_ceil(4);
+                let mut send_window = |window: usize| {
+                    for slot in 0..4 {
+                        let index = (window * 4 + slot) as u8;
+                        self.buf[4 + slot * 4..8 + slot * 4]
+                            .copy_from_slice(&[0xcc, 0xcc, 0xcc, index]);
+                    }
+                    send(&self.buf)
+                };
+                match pattern {
+                    MacPattern::Windows => (0..num_window).for_each(send_window),
+                    MacPattern::Rotating => send_window(self.seq_num as usize % num_window),
+                    MacPattern::Random(num_slot) => {
+                        let mut rng = StdRng::seed_from_u64(sub_seed(seed, self.seq_num));
+                        self.buf[4..20].fill(0);
+                        for slot in rand::seq::index::sample(&mut rng, 4, num_slot) {
+                            let num_candidate = (num_replica - slot).div_ceil(4);
+                            if num_candidate == 0 {
+                                continue;
+                            }
+                            let index = (rng.gen_range(0..num_candidate) * 4 + slot) as u8;
+                            self.buf[4 + slot * 4..8 + slot * 4]
+                                .copy_from_slice(&[0xcc, 0xcc, 0xcc, index]);
+                        }
+                        send(&self.buf)
+                    }
                 }
             }
-            SequencerProcessCrypto::K256 {
+            SequencerProcessCrypto::Ecdsa {
                 linked,
                 state,
                 signing_key,
+                sign,
             } => {
                 self.buf[68..100].copy_from_slice(&linked);
-                let signature: k256::ecdsa::Signature = signing_key.sign_digest(state);
-                let mut signature = signature.to_bytes();
-                signature.reverse();
-                self.buf[4..68].copy_from_slice(&signature);
+                if sign {
+                    let mut signature = signing_key.sign(state).to_bytes();
+                    signature.reverse();
+                    self.buf[4..68].copy_from_slice(&signature);
+                } else {
+                    // deserialized as `Signature::Linked`
+                    self.buf[4..68].fill(0)
+                }
                 send(&self.buf)
             }
         }
     }
 }
+
+// merge consecutive multicast into one before sequencing, so a sequence number, a signature and a
+// header are spent per batch instead of per message
+// the payload is a batch already, i.e. `Vec<T>` which clients send with a single element, so the
+// switch sequences the same payload without batching. the caller decides when a partial batch is
+// held for long enough and flushes it
+#[derive(Debug)]
+pub struct Batcher<T> {
+    max_batch_size: usize,
+    batch: Vec<T>,
+    // of the first message in the batch, so the sequencing timing covers the time it is held
+    trailer: Option<Vec<u8>>,
+}
+
+impl<T> Batcher<T>
+where
+    T: Serialize + DeserializeOwned + DigestHash,
+{
+    pub fn new(max_batch_size: usize) -> Self {
+        assert_ne!(max_batch_size, 0);
+        Self {
+            max_batch_size,
+            batch: Default::default(),
+            trailer: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batch.is_empty()
+    }
+
+    // the batch to sequence, if the message fills it
+    // a message that is not a batch is dropped, as it would fail to deserialize on replicas anyway
+    // an empty message fills an idle multicast group, so it is sequenced without waiting, along
+    // with the held batch if any
+    pub fn push(&mut self, buf: &[u8]) -> Option<Vec<u8>> {
+        let batch: Vec<T> = bincode::options()
+            .allow_trailing_bytes()
+            .deserialize(buf.get(HEADER_LEN..)?)
+            .ok()?;
+        if batch.is_empty() {
+            return self.flush().or_else(|| Some(serialize(&batch)));
+        }
+        if self.batch.is_empty() && Timing::parse(buf).is_some() {
+            self.trailer = Some(buf[buf.len() - Timing::LEN..].to_vec())
+        }
+        self.batch.extend(batch);
+        if self.batch.len() >= self.max_batch_size {
+            self.flush()
+        } else {
+            None
+        }
+    }
+
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        if self.batch.is_empty() {
+            return None;
+        }
+        let mut buf = serialize(&std::mem::take(&mut self.batch));
+        if let Some(trailer) = self.trailer.take() {
+            buf[FLAGS] |= Timing::FLAG;
+            buf.extend(trailer)
+        }
+        Some(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl DigestHash for u8 {
+        fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+            hasher.write_u8(*self)
+        }
+    }
+
+    impl Verify for OrderedMulticast<crate::common::Request> {
+        fn verify(&self, verifier: &Verifier) -> Result<(), Invalid> {
+            verifier.verify_ordered_multicast(self)
+        }
+    }
+
+    fn message(seq_num: u32) -> OrderedMulticast<u8> {
+        message_with(seq_num, 0)
+    }
+
+    fn message_with(seq_num: u32, inner: u8) -> OrderedMulticast<u8> {
+        OrderedMulticast {
+            seq_num,
+            signature: Signature::HalfSipHash(Default::default()),
+            linked: Default::default(),
+            inner,
+            group: 0,
+        }
+    }
+
+    fn op_nums(reordered: Reordered<u8>) -> Vec<u64> {
+        let Reordered::Ordered(ordered) = reordered else {
+            panic!("{reordered:?}")
+        };
+        Vec::from_iter(ordered.into_iter().map(|(op_num, _)| op_num))
+    }
+
+    #[test]
+    fn reorder_wraparound() {
+        let mut reorder = Reorder::default();
+        assert_eq!(op_nums(reorder.receive(message(u32::MAX - 1))), [1]);
+        assert!(matches!(reorder.receive(message(1)), Reordered::Pending));
+        assert_eq!(op_nums(reorder.receive(message(u32::MAX))), [2]);
+        assert_eq!(op_nums(reorder.receive(message(0))), [3, 4]);
+        assert!(matches!(
+            reorder.receive(message(u32::MAX)),
+            Reordered::Duplicated
+        ));
+        // sequenced before the first received message
+        assert!(matches!(
+            reorder.receive(message(u32::MAX - 2)),
+            Reordered::Stale(0, _)
+        ));
+    }
+
+    #[test]
+    fn reorder_beyond_32_bits() {
+        let mut reorder = Reorder {
+            seq_num_offset: Some(5),
+            ordered_num: (1 << 32) + 10,
+            ..Default::default()
+        };
+        // op number 2^32 + 11 shares the sequence number with op number 11
+        let seq_num = 5 + 10;
+        assert!(matches!(
+            reorder.receive(message(seq_num + 1)),
+            Reordered::Pending
+        ));
+        assert_eq!(
+            op_nums(reorder.receive(message(seq_num))),
+            [(1 << 32) + 11, (1 << 32) + 12]
+        );
+        assert!(matches!(
+            reorder.receive(message(seq_num - 1)),
+            Reordered::Stale(op_num, _) if op_num == (1 << 32) + 10
+        ));
+    }
+
+    #[test]
+    fn reorder_resume() {
+        let mut reorder = Reorder::resume(10);
+        // the new sequencer's first message is dropped, and the gap before the second one is
+        // still noticed
+        assert!(matches!(reorder.receive(message(2)), Reordered::Pending));
+        assert_eq!(op_nums(reorder.receive(message(1))), [11, 12]);
+        // numbered by the sequencer before, far ahead of the new one
+        assert!(matches!(
+            reorder.receive(message(1 << 20)),
+            Reordered::Beyond
+        ));
+        assert_eq!(reorder.num_pending(), 0)
+    }
+
+    // the unsigned messages of ECDSA are authenticated by the link of the next signed one only
+    #[test]
+    fn reorder_conflicted() {
+        let unsigned = |seq_num, inner| OrderedMulticast {
+            signature: Signature::Linked,
+            ..message_with(seq_num, inner)
+        };
+        let mut reorder = Reorder::default();
+        assert_eq!(op_nums(reorder.receive(message(10))), [1]);
+        assert!(matches!(
+            reorder.receive(unsigned(12, 1)),
+            Reordered::Pending
+        ));
+        assert!(matches!(
+            reorder.receive(message(12)),
+            Reordered::Conflicted
+        ));
+        // the signed one replaces the held one
+        assert_eq!(op_nums(reorder.receive(message(11))), [2, 3]);
+        assert!(matches!(
+            reorder.receive(message(12)),
+            Reordered::Duplicated
+        ));
+        assert!(matches!(
+            reorder.receive(unsigned(11, 1)),
+            Reordered::Conflicted
+        ))
+    }
+
+    #[test]
+    fn reorder_source_stats() {
+        let relay = Host::UnkownMulticastSender;
+        let mut reorder = Reorder::default();
+        assert_eq!(op_nums(reorder.receive_from(relay, message(10))), [1]);
+        assert!(matches!(
+            reorder.receive_from(relay, message(12)),
+            Reordered::Pending
+        ));
+        assert!(matches!(
+            reorder.receive_from(relay, message_with(12, 1)),
+            Reordered::Equivocated(3, _)
+        ));
+        assert_eq!(op_nums(reorder.receive_from(relay, message(11))), [2, 3]);
+        assert!(matches!(
+            reorder.receive_from(relay, message(10)),
+            Reordered::Duplicated
+        ));
+        assert!(matches!(
+            reorder.receive_from(relay, message_with(11, 1)),
+            Reordered::Equivocated(2, _)
+        ));
+        let stats = reorder.stats[&relay];
+        assert_eq!(stats.duplicated, 1);
+        assert_eq!(stats.regressed, 3);
+        assert_eq!(stats.equivocated, 2);
+        assert_eq!(stats.max_op_num, 3)
+    }
+
+    #[test]
+    fn merge_round_robin() {
+        let relay = Host::UnkownMulticastSender;
+        let mut merge = Merge::new(2);
+        let grouped = |seq_num, group| OrderedMulticast {
+            group,
+            ..message(seq_num)
+        };
+        // the sequence numbers of the groups are unrelated
+        assert_eq!(op_nums(merge.receive_from(relay, grouped(10, 0))), [1]);
+        assert!(matches!(
+            merge.receive_from(relay, grouped(11, 0)),
+            Reordered::Pending
+        ));
+        assert_eq!(merge.get_pending(3).unwrap().seq_num, 11);
+        assert_eq!(merge.num_pending(), 1);
+        assert_eq!(merge.num_reordering(), 0);
+        assert!(!merge.is_next_missing());
+        // the first message of the idle group merges op 3
+        assert_eq!(merge.fills(), [(1, 1..2)]);
+        assert_eq!(op_nums(merge.receive_from(relay, grouped(100, 1))), [2, 3]);
+        assert!(matches!(
+            merge.receive_from(relay, grouped(13, 0)),
+            Reordered::Pending
+        ));
+        assert_eq!(merge.num_reordering(), 1);
+        // op 4 of the other group is not sent yet, and nothing is ready to fill it for
+        assert!(!merge.is_next_missing());
+        assert!(merge.fills().is_empty());
+        assert_eq!(op_nums(merge.receive_from(relay, grouped(101, 1))), [4]);
+        assert!(merge.is_next_missing());
+        assert_eq!(op_nums(merge.receive_from(relay, grouped(12, 0))), [5]);
+        assert!(matches!(
+            merge.receive_from(relay, grouped(11, 0)),
+            Reordered::Duplicated
+        ));
+        // the next epoch continues after op 4, i.e. the second message of each group
+        let mut merge = Merge::<u8>::resume(2, 4);
+        assert!(matches!(
+            merge.receive_from(relay, grouped(1, 1)),
+            Reordered::Pending
+        ));
+        assert_eq!(op_nums(merge.receive_from(relay, grouped(1, 0))), [5, 6])
+    }
+
+    #[test]
+    fn timing_trailer() {
+        let mut buf = serialize(&crate::common::Request {
+            client_index: 0,
+            request_num: 1,
+            op: Default::default(),
+        });
+        assert_eq!(Timing::parse(&buf), None);
+        Timing::append(&mut buf);
+        let variant = Variant::new_half_sip_hash(0, 1.);
+        let mut sequencer = variant.sequencer(4).unwrap();
+        sequencer.process(buf).apply(|buf| {
+            let timing = Timing::parse(buf).unwrap();
+            assert!(timing.client_send <= timing.sequencer_receive);
+            assert!(timing.sequencer_receive <= timing.sequencer_send);
+            // invisible to the protocol
+            let message = variant.deserialize::<crate::common::Request>(buf);
+            assert_eq!(message.request_num, 1)
+        });
+        // a payload that happens to end like a trailer is left alone
+        let buf = serialize(&crate::common::Request {
+            client_index: 0,
+            request_num: 2,
+            op: [0; 32].into_iter().chain(*b"TMNG").collect(),
+        });
+        assert_eq!(Timing::parse(&buf), None);
+        sequencer
+            .process(buf.clone())
+            .apply(|sequenced| assert_eq!(sequenced[HEADER_LEN..], buf[HEADER_LEN..]))
+    }
+
+    #[test]
+    fn sign_interval() {
+        let variant = Variant::new_ecdsa(SequencerCurve::P256).with_sign_interval(4);
+        let mut sequencer = variant.sequencer(4).unwrap();
+        for request_num in 1..=10 {
+            let request = crate::common::Request {
+                client_index: 0,
+                request_num,
+                op: Default::default(),
+            };
+            let mut process = sequencer.process(serialize(&request));
+            // end of burst
+            if request_num == 10 {
+                process.sign()
+            }
+            process.apply(|buf| {
+                let message = variant.deserialize::<crate::common::Request>(buf);
+                assert_eq!(
+                    message.signature == Signature::Linked,
+                    !matches!(request_num, 4 | 8 | 10)
+                );
+                variant.verify(&message).unwrap()
+            })
+        }
+        let signatures = variant.signatures().unwrap();
+        assert_eq!(signatures.verified, 3);
+        assert_eq!(signatures.linked, 7);
+        assert_eq!(signatures.fallback, 1)
+    }
+
+    #[test]
+    fn rotate_sequencer_key() {
+        let variant = Variant::new_ecdsa(SequencerCurve::K256);
+        let mut sequencer = variant.sequencer(4).unwrap();
+        let sequence = |sequencer: &mut Sequencer, request_num| {
+            let request = crate::common::Request {
+                client_index: 0,
+                request_num,
+                op: Default::default(),
+            };
+            let message = std::cell::RefCell::new(None);
+            sequencer.process(serialize(&request)).apply(|buf| {
+                *message.borrow_mut() = Some(variant.deserialize::<crate::common::Request>(buf))
+            });
+            message.into_inner().unwrap()
+        };
+        let signed = sequence(&mut sequencer, 1);
+        let key = SequencerSigningKey::from_slice(SequencerCurve::K256, &[1; 32]).unwrap();
+        variant.rotate_sequencer_key(key.verifying_key(), Duration::from_secs(60));
+        // signed before the sequencer switches
+        assert!(variant.verify(&signed).is_ok());
+        sequencer.rotate_signing_key(key.clone());
+        assert!(variant.verify(&sequence(&mut sequencer, 2)).is_ok());
+
+        variant.rotate_sequencer_key(key.verifying_key(), Duration::ZERO);
+        assert!(variant.verify(&signed).is_err())
+    }
+
+    #[test]
+    fn batch_requests() {
+        let variant = Variant::new_ecdsa(SequencerCurve::K256);
+        let mut sequencer = variant.sequencer(4).unwrap();
+        let mut batcher = Batcher::<crate::common::Request>::new(3);
+        let request = |request_num| {
+            serialize(&vec![crate::common::Request {
+                client_index: 0,
+                request_num,
+                op: Default::default(),
+            }])
+        };
+        assert!(batcher.push(&request(1)).is_none());
+        assert!(batcher.push(&request(2)).is_none());
+        let full = batcher.push(&request(3)).unwrap();
+        assert!(batcher.is_empty());
+        assert!(batcher.push(&request(4)).is_none());
+        // short and garbage datagrams are dropped
+        assert!(batcher.push(&[0; 10]).is_none());
+        assert!(batcher.push(&[0xff; 2 * HEADER_LEN]).is_none());
+        let partial = batcher.flush().unwrap();
+        assert!(batcher.flush().is_none());
+        for (buf, request_nums) in [(full, vec![1, 2, 3]), (partial, vec![4])] {
+            sequencer.process(buf).apply(|buf| {
+                let message = variant.deserialize::<Vec<crate::common::Request>>(buf);
+                variant.verify(&message).unwrap();
+                assert_eq!(
+                    Vec::from_iter(message.iter().map(|request| request.request_num)),
+                    request_nums
+                )
+            })
+        }
+    }
+
+    #[test]
+    fn delegate_drops_invalid() {
+        struct Handled(Vec<u32>);
+        impl Receivers for Handled {
+            type Message = OrderedMulticast<crate::common::Request>;
+
+            fn handle(&mut self, _: Host, _: Host, message: Self::Message) {
+                self.0.push(message.request_num)
+            }
+
+            fn on_timer(&mut self, _: Host, _: crate::context::TimerId) {
+                unreachable!()
+            }
+        }
+
+        let variant = Arc::new(Variant::new_ecdsa(SequencerCurve::P256));
+        let config = crate::context::simulated::fixture::config(4, 1);
+        let verifier = Verifier::new_standard(&config, variant.clone());
+        let mut sequencer = variant.sequencer(4).unwrap();
+        let mut delegate = variant.delegate();
+        let mut handled = Handled(Vec::new());
+        for request_num in 1..=2 {
+            let request = crate::common::Request {
+                client_index: 0,
+                request_num,
+                op: Default::default(),
+            };
+            let message = std::cell::RefCell::new(None);
+            sequencer.process(serialize(&request)).apply(|buf| {
+                *message.borrow_mut() = Some(variant.deserialize::<crate::common::Request>(buf))
+            });
+            let mut message = message.into_inner().unwrap();
+            if request_num == 2 {
+                message.inner.op = b"forged".to_vec()
+            }
+            // held back until the next one or pace
+            let result =
+                delegate.on_receive(Host::Client(0), message, &mut handled, &verifier, |m| m);
+            assert!(result.is_ok());
+            let result = delegate.on_pace(&mut handled, &verifier, |m| m);
+            assert_eq!(result.is_err(), request_num == 2)
+        }
+        assert_eq!(handled.0, [1])
+    }
+
+    #[test]
+    fn random_mac_pattern_seeded() {
+        let codes = |seed| {
+            let mut sequencer =
+                Sequencer::new_half_sip_hash(16, MacPattern::Random(2)).with_seed(seed);
+            let codes = std::sync::Mutex::new(Vec::new());
+            for _ in 0..10 {
+                sequencer
+                    .process(vec![0; 100])
+                    .apply(|buf| codes.lock().unwrap().push(buf[4..20].to_vec()))
+            }
+            codes.into_inner().unwrap()
+        };
+        assert_eq!(codes(1), codes(1));
+        assert_ne!(codes(1), codes(2))
+    }
+}
@@ -0,0 +1,178 @@
+// per-peer network model emulated where replicas receive messages, so WAN topologies run on a LAN
+// testbed without root access to tc
+// unlike `Chaos`, the model is fixed for a run and tells peers apart, while the uniform drop rate of
+// the dispatch still applies on top of it
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+use super::{chaos::Ingress, Host, ReplicaIndex};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Link {
+    pub loss_rate: f64,
+    pub delay: Duration,
+    // further delay up to this long, uniformly
+    pub jitter: Duration,
+    // bytes per second, and the messages that exceed it queue up behind each other
+    pub bandwidth: Option<u64>,
+}
+
+impl From<control_messages::LinkModel> for Link {
+    fn from(value: control_messages::LinkModel) -> Self {
+        Self {
+            loss_rate: value.loss_rate,
+            delay: Duration::from_micros(value.delay_us),
+            jitter: Duration::from_micros(value.jitter_us),
+            bandwidth: value.bandwidth,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Shaping {
+    // remote replica => the link from it
+    pub replica_links: HashMap<ReplicaIndex, Link>,
+    // from every client, each of which has a link of its own
+    pub client_link: Option<Link>,
+    pub multicast_link: Option<Link>,
+    // remote => when its link is done with the messages received so far
+    busy_until: Mutex<HashMap<Host, Instant>>,
+}
+
+impl Shaping {
+    // the links to the replica of `local` in `model`
+    pub fn new(model: &control_messages::NetworkModel, local: ReplicaIndex) -> Self {
+        Self {
+            replica_links: HashMap::from_iter(
+                model
+                    .replica_links
+                    .iter()
+                    .filter(|(_, to, _)| *to == local)
+                    .map(|&(from, _, link)| (from, link.into())),
+            ),
+            client_link: model.client_link.map(Into::into),
+            multicast_link: model.multicast_link.map(Into::into),
+            busy_until: Default::default(),
+        }
+    }
+
+    // the links to a client in `model`, i.e. the client link from every one of `num_replica`
+    pub fn new_client(model: &control_messages::NetworkModel, num_replica: usize) -> Self {
+        Self {
+            replica_links: HashMap::from_iter(model.client_link.iter().flat_map(|&link| {
+                (0..num_replica).map(move |index| (index as ReplicaIndex, link.into()))
+            })),
+            ..Default::default()
+        }
+    }
+
+    // `remote` is `Host::Multicast` for ordered multicast
+    pub fn ingress(&self, remote: Host, len: usize, now: Instant, rng: &mut impl Rng) -> Ingress {
+        let link = match remote {
+            Host::Replica(index) => self.replica_links.get(&index),
+            Host::Client(_) => self.client_link.as_ref(),
+            Host::Multicast => self.multicast_link.as_ref(),
+            Host::UnkownMulticastSender => None,
+        };
+        let Some(link) = link else {
+            return Ingress::Deliver;
+        };
+        if link.loss_rate != 0. && rng.gen_bool(link.loss_rate) {
+            return Ingress::Drop;
+        }
+        let mut delay = link.delay;
+        if !link.jitter.is_zero() {
+            delay += rng.gen_range(Duration::ZERO..=link.jitter)
+        }
+        if let Some(bandwidth) = link.bandwidth {
+            let mut busy_until = self.busy_until.lock().unwrap();
+            let busy_until = busy_until.entry(remote).or_insert(now);
+            *busy_until =
+                (*busy_until).max(now) + Duration::from_secs_f64(len as f64 / bandwidth as f64);
+            delay += *busy_until - now
+        }
+        if delay.is_zero() {
+            Ingress::Deliver
+        } else {
+            Ingress::Delay(delay)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn bandwidth_queues() {
+        let link = Link {
+            delay: Duration::from_millis(10),
+            bandwidth: Some(1000),
+            ..Default::default()
+        };
+        let mut shaping = Shaping::default();
+        shaping.replica_links.insert(1, link);
+        let mut rng = StdRng::seed_from_u64(0);
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+        assert_eq!(
+            shaping.ingress(Host::Replica(1), 100, now, &mut rng),
+            Ingress::Delay(ms(110))
+        );
+        // behind the previous one
+        assert_eq!(
+            shaping.ingress(Host::Replica(1), 100, now, &mut rng),
+            Ingress::Delay(ms(210))
+        );
+        // the link has drained by then
+        assert_eq!(
+            shaping.ingress(Host::Replica(1), 100, now + ms(1000), &mut rng),
+            Ingress::Delay(ms(110))
+        );
+        assert_eq!(
+            shaping.ingress(Host::Replica(2), 100, now, &mut rng),
+            Ingress::Deliver
+        );
+    }
+
+    #[test]
+    fn client_link_to_client() {
+        let model = control_messages::NetworkModel {
+            client_link: Some(control_messages::LinkModel {
+                delay_us: 1000,
+                bandwidth: Some(1000),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let shaping = Shaping::new_client(&model, 4);
+        let mut rng = StdRng::seed_from_u64(0);
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+        // a dropped message does not queue on the link
+        assert_eq!(
+            Ingress::Drop.and_then(|| shaping.ingress(Host::Replica(3), 100, now, &mut rng)),
+            Ingress::Drop
+        );
+        assert_eq!(
+            shaping.ingress(Host::Replica(3), 100, now, &mut rng),
+            Ingress::Delay(ms(101))
+        );
+        assert_eq!(
+            shaping.ingress(Host::Replica(0), 100, now, &mut rng),
+            Ingress::Delay(ms(101))
+        );
+        assert_eq!(
+            shaping.ingress(Host::Replica(4), 100, now, &mut rng),
+            Ingress::Deliver
+        );
+    }
+}
@@ -0,0 +1,2650 @@
+//! A simulated context that runs every host in the current thread on a virtual clock.
+//!
+//! Messages still go through signing, serialization and verification, so protocol code behaves
+//! the same as with the tokio context, only deterministically and without real delays.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bincode::Options;
+use rand::{rngs::StdRng, Rng};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+    byzantine::{Byzantine, Tampered},
+    chaos::{Chaos, Ingress},
+    crypto::{DigestHash, KeyRing, Sign, Signer, Verifier, Verify},
+    ordered_multicast::{Batcher, Delegate, OrderedMulticast, Sequencer, SequencerFault, Variant},
+    Config, Host, Receivers, ReplicaIndex, To,
+};
+use crate::common::Request;
+
+#[derive(Debug)]
+enum Event {
+    Message(Host, Host, Vec<u8>),
+    LoopbackMessage(Host, Vec<u8>),
+    // with the multicast group
+    OrderedMulticastMessage(Host, Host, u8, Vec<u8>),
+    Timer(Host, TimerId),
+    // the timeout of the batch of a group's sequencer with the number, which is flushed if it is
+    // still held
+    FlushBatch(u8, u64),
+    // a message held back by chaos, which is not subject to it again
+    Delayed(Box<Event>),
+    Chaos(control_messages::ChaosAction),
+}
+
+pub type TimerId = u32;
+
+#[derive(Debug)]
+struct State {
+    now: Duration,
+    latency: Duration,
+    // every message takes `latency` plus a uniformly random part of it, drawn from a seeded rng
+    // so a run is reproducible from `Config::seed`
+    jitter: Duration,
+    jitter_rng: StdRng,
+    // the sequence number breaks ties so events of the same instant are delivered in sending order
+    event_num: u64,
+    events: BTreeMap<(Duration, u64), Event>,
+    num_in_flight: usize,
+    timer_id: TimerId,
+    timers: HashMap<TimerId, TimerState>,
+    // one per multicast group, none if ordered multicast is not enabled
+    sequencers: Vec<Sequencer>,
+    // of neo's requests, one per multicast group with the number of the held batch, none if batching
+    // is not enabled
+    batchers: Vec<(Batcher<Request>, u64)>,
+    batch_timeout: Duration,
+    equivocation: Option<Equivocation>,
+    trace: Option<Vec<TraceEvent>>,
+    chaos: Chaos,
+    chaos_rng: StdRng,
+    faults: Faults,
+    // of its own, so adding faults does not change what chaos does
+    faults_rng: StdRng,
+    num_stale: u64,
+    num_invalid_multicast: u64,
+}
+
+// the two messages sharing a sequence number are multicast to complementary sets of replicas
+#[derive(Debug)]
+struct Equivocation {
+    group: u8,
+    seq_num: u32,
+    replicas: Vec<ReplicaIndex>,
+    first_sent: bool,
+}
+
+// a programmable adversary of the simulated network, as opposite to `Chaos` which is shared with the
+// testbed, e.g.
+//     Faults::default()
+//         .rule(Link::to(Host::Replica(0)), Window::Count(0..2), Fault::Drop)
+//         .partition([vec![Host::Replica(0)], vec![Host::Replica(1)]], start..end)
+// times are from when the faults are injected, and the faults apply on top of chaos to the messages
+// that are not held back already
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    rules: Vec<Rule>,
+    // messages between hosts of different groups are dropped within the window, i.e. the
+    // partition heals at its end, and hosts in no group are not affected
+    partitions: Vec<(Vec<HashSet<Host>>, Range<Duration>)>,
+}
+
+// the messages from `from` to `to`, either of them matches any host if none, and ordered multicast
+// comes from `Host::UnkownMulticastSender`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Link {
+    pub from: Option<Host>,
+    pub to: Option<Host>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Window {
+    Always,
+    Time(Range<Duration>),
+    // the indices of the messages on the link, counted from zero, including the ones dropped by
+    // chaos, partitions or other rules
+    Count(Range<u64>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Fault {
+    Drop,
+    // delivered twice, the copy right after the original
+    Duplicate,
+    Delay(Duration),
+    // delayed with the probability, so the message is overtaken by the ones sent after it
+    Reorder(f64, Duration),
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    link: Link,
+    window: Window,
+    fault: Fault,
+    num_matched: u64,
+}
+
+impl Link {
+    pub fn any() -> Self {
+        Self::default()
+    }
+
+    pub fn from(host: Host) -> Self {
+        Self {
+            from: Some(host),
+            to: None,
+        }
+    }
+
+    pub fn to(host: Host) -> Self {
+        Self {
+            from: None,
+            to: Some(host),
+        }
+    }
+
+    pub fn between(from: Host, to: Host) -> Self {
+        Self {
+            from: Some(from),
+            to: Some(to),
+        }
+    }
+
+    fn matches(&self, from: Host, to: Host) -> bool {
+        self.from.unwrap_or(from) == from && self.to.unwrap_or(to) == to
+    }
+}
+
+impl Faults {
+    pub fn rule(mut self, link: Link, window: Window, fault: Fault) -> Self {
+        if let Fault::Reorder(rate, _) = fault {
+            assert!(
+                (0. ..=1.).contains(&rate),
+                "reorder rate {rate} not in [0, 1]"
+            )
+        }
+        self.rules.push(Rule {
+            link,
+            window,
+            fault,
+            num_matched: 0,
+        });
+        self
+    }
+
+    pub fn partition(
+        mut self,
+        groups: impl IntoIterator<Item = Vec<Host>>,
+        window: Range<Duration>,
+    ) -> Self {
+        let groups = Vec::from_iter(groups.into_iter().map(HashSet::from_iter));
+        self.partitions.push((groups, window));
+        self
+    }
+
+    fn shift(&mut self, offset: Duration) {
+        for rule in &mut self.rules {
+            if let Window::Time(window) = &mut rule.window {
+                *window = window.start + offset..window.end.saturating_add(offset)
+            }
+        }
+        for (_, window) in &mut self.partitions {
+            *window = window.start + offset..window.end.saturating_add(offset)
+        }
+    }
+
+    // and whether the message is duplicated
+    fn ingress(
+        &mut self,
+        now: Duration,
+        receiver: Host,
+        remote: Host,
+        rng: &mut impl Rng,
+    ) -> (Ingress, bool) {
+        let partitioned = self.partitions.iter().any(|(groups, window)| {
+            let group = |host| groups.iter().position(|group| group.contains(&host));
+            window.contains(&now)
+                && matches!((group(remote), group(receiver)), (Some(a), Some(b)) if a != b)
+        });
+        // every rule counts the message, even if an earlier one drops it
+        let (mut dropped, mut delay, mut duplicate) = (partitioned, Duration::ZERO, false);
+        for rule in &mut self.rules {
+            if !rule.link.matches(remote, receiver) {
+                continue;
+            }
+            let index = rule.num_matched;
+            rule.num_matched += 1;
+            let active = match &rule.window {
+                Window::Always => true,
+                Window::Time(window) => window.contains(&now),
+                Window::Count(window) => window.contains(&index),
+            };
+            if !active || dropped {
+                continue;
+            }
+            match rule.fault {
+                Fault::Drop => dropped = true,
+                Fault::Duplicate => duplicate = true,
+                Fault::Delay(fault_delay) => delay += fault_delay,
+                Fault::Reorder(rate, fault_delay) => {
+                    if rng.gen_bool(rate) {
+                        delay += fault_delay
+                    }
+                }
+            }
+        }
+        if dropped {
+            (Ingress::Drop, false)
+        } else if delay.is_zero() {
+            (Ingress::Deliver, duplicate)
+        } else {
+            (Ingress::Delay(delay), duplicate)
+        }
+    }
+}
+
+// a delivered message, recorded if tracing is enabled
+#[derive(Debug, Clone)]
+pub struct TraceEvent {
+    pub time: Duration,
+    pub sender: Host,
+    pub receiver: Host,
+    // the `Debug` format of the delivered message
+    pub message: String,
+}
+
+#[derive(Debug)]
+struct TimerState {
+    duration: Duration,
+    sentinel: bool,
+}
+
+impl State {
+    fn push(&mut self, delay: Duration, event: Event) {
+        if !matches!(event, Event::Timer(..) | Event::Chaos(_)) {
+            self.num_in_flight += 1
+        }
+        self.event_num += 1;
+        self.events
+            .insert((self.now + delay, self.event_num), event);
+    }
+
+    fn delay(&mut self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.latency;
+        }
+        self.latency + self.jitter_rng.gen_range(Duration::ZERO..=self.jitter)
+    }
+
+    fn quiescent(&self) -> bool {
+        self.num_in_flight == 0 && self.timers.values().all(|timer| timer.sentinel)
+    }
+
+    // sequence the message and send it to the replicas
+    fn multicast(&mut self, config: &Config, group: u8, buf: Vec<u8>) {
+        let process = self
+            .sequencers
+            .get_mut(group as usize)
+            .expect("ordered multicast enabled")
+            .process(buf);
+        let mut receivers = None;
+        if let Some(equivocation) = &mut self.equivocation {
+            if group == equivocation.group && equivocation.seq_num == process.seq_num() {
+                receivers = Some((equivocation.replicas.clone(), !equivocation.first_sent));
+                equivocation.first_sent = true
+            }
+        }
+        let receive = |index| {
+            receivers
+                .as_ref()
+                .map(|(replicas, first)| replicas.contains(&index) == *first)
+                .unwrap_or(true)
+        };
+        let bufs = RefCell::new(Vec::new());
+        process.apply(|buf| bufs.borrow_mut().push(buf.to_vec()));
+        for buf in bufs.into_inner() {
+            for host in sorted_hosts(config) {
+                if matches!(host, Host::Replica(index) if receive(index)) {
+                    let event = Event::OrderedMulticastMessage(
+                        host,
+                        Host::UnkownMulticastSender,
+                        group,
+                        buf.clone(),
+                    );
+                    let delay = self.delay();
+                    self.push(delay, event)
+                }
+            }
+        }
+    }
+}
+
+// in a fixed order, as the order of sending draws the jitter
+fn sorted_hosts(config: &Config) -> Vec<Host> {
+    let mut hosts = Vec::from_iter(config.hosts.keys().copied());
+    hosts.sort_unstable();
+    hosts
+}
+
+#[derive(Debug)]
+pub struct Context {
+    pub config: Arc<Config>,
+    source: Host,
+    signer: Signer,
+    state: Arc<Mutex<State>>,
+    pub rng: StdRng,
+    pub byzantine: Option<Mutex<Byzantine>>,
+}
+
+impl Context {
+    pub fn send<M, N>(&self, to: To, message: N)
+    where
+        M: Sign<N> + Serialize,
+    {
+        self.signer.receiver.set(match &to {
+            To::Host(host) => Some(*host),
+            _ => None,
+        });
+        let message = M::sign(message, &self.signer);
+        let buf = bincode::options().serialize(&message).unwrap();
+        let tampered = match &self.byzantine {
+            Some(byzantine) => byzantine.lock().unwrap().tamper(&self.signer, &buf),
+            None => Tampered::Genuine,
+        };
+        let state = &mut *self.state.lock().unwrap();
+        let mut send = |host| {
+            if let Some(buf) = tampered.remote(&buf, host) {
+                let delay = state.delay();
+                state.push(delay, Event::Message(host, self.source, buf.to_vec()))
+            }
+        };
+        match &to {
+            To::Host(host) => send(*host),
+            To::Hosts(hosts) => hosts.iter().copied().for_each(send),
+            To::AllReplica | To::AllReplicaWithLoopback => {
+                for host in sorted_hosts(&self.config) {
+                    if matches!(host, Host::Replica(_)) && host != self.source {
+                        send(host)
+                    }
+                }
+            }
+            To::Loopback => {}
+            To::Standby(index) => send(Host::Replica(*index)),
+        }
+        if matches!(to, To::Loopback | To::AllReplicaWithLoopback) {
+            state.push(Duration::ZERO, Event::LoopbackMessage(self.source, buf))
+        }
+    }
+
+    pub fn send_ordered_multicast(&self, message: impl Serialize + DigestHash) {
+        let group = match self.source {
+            Host::Client(index) => self.config.multicast_group(index),
+            _ => 0,
+        };
+        self.send_ordered_multicast_to(group, message)
+    }
+
+    pub fn send_ordered_multicast_to(&self, group: u8, message: impl Serialize + DigestHash) {
+        let state = &mut *self.state.lock().unwrap();
+        let buf = super::ordered_multicast::serialize(&message);
+        let timeout = state.batch_timeout;
+        let Some((batcher, batch_num)) = state.batchers.get_mut(group as usize) else {
+            return state.multicast(&self.config, group, buf);
+        };
+        let flush = batcher.is_empty().then(|| {
+            *batch_num += 1;
+            (timeout, Event::FlushBatch(group, *batch_num))
+        });
+        let buf = batcher.push(&buf);
+        // a batch flushed right away leaves its flush event behind, which finds nothing to flush
+        if let Some((timeout, event)) = flush {
+            state.push(timeout, event)
+        }
+        if let Some(buf) = buf {
+            state.multicast(&self.config, group, buf)
+        }
+    }
+
+    pub fn idle_hint(&self) -> bool {
+        self.state.lock().unwrap().num_in_flight == 0
+    }
+
+    pub fn set(&mut self, duration: Duration) -> TimerId {
+        self.set_internal(duration, false)
+    }
+
+    pub fn set_sentinel(&mut self, duration: Duration) -> TimerId {
+        self.set_internal(duration, true)
+    }
+
+    fn set_internal(&mut self, duration: Duration, sentinel: bool) -> TimerId {
+        let state = &mut *self.state.lock().unwrap();
+        state.timer_id += 1;
+        let id = state.timer_id;
+        state.timers.insert(id, TimerState { duration, sentinel });
+        state.push(duration, Event::Timer(self.source, id));
+        id
+    }
+
+    pub fn unset(&mut self, id: TimerId) {
+        self.state.lock().unwrap().timers.remove(&id).unwrap();
+    }
+}
+
+pub type Nodes<'a, M> = HashMap<Host, &'a mut dyn Receivers<Message = M>>;
+
+#[derive(Debug)]
+pub struct Network {
+    config: Arc<Config>,
+    state: Arc<Mutex<State>>,
+    replicas: HashMap<ReplicaIndex, (Arc<Variant>, Verifier)>,
+}
+
+impl Network {
+    // the variant is per replica, as opposite to per process in tokio context
+    pub fn new(
+        config: impl Into<Arc<Config>>,
+        verify: bool,
+        variant: impl Fn(ReplicaIndex) -> Variant,
+    ) -> Self {
+        let config = config.into();
+        let replicas = HashMap::from_iter((0..config.num_replica).map(|index| {
+            let index = index as ReplicaIndex;
+            let variant = Arc::new(variant(index));
+            let verifier = if verify {
+                Verifier::new_standard(&config, variant.clone())
+                    .receiving(&config, Host::Replica(index))
+            } else {
+                Verifier::Nop
+            };
+            (index, (variant, verifier))
+        }));
+        let state = State {
+            now: Duration::ZERO,
+            latency: Duration::from_micros(100),
+            jitter: Duration::ZERO,
+            jitter_rng: config.rng("jitter"),
+            event_num: 0,
+            events: Default::default(),
+            num_in_flight: 0,
+            timer_id: 0,
+            timers: Default::default(),
+            sequencers: Vec::from_iter((0..config.num_multicast_group()).filter_map(|group| {
+                let seed = super::sub_seed(config.seed, ("sequencer", group));
+                let (variant, _) = replicas.get(&0)?;
+                Some(variant.sequencer(config.num_replica)?.with_seed(seed))
+            })),
+            batchers: Default::default(),
+            batch_timeout: Duration::ZERO,
+            equivocation: None,
+            trace: None,
+            chaos: Default::default(),
+            chaos_rng: config.rng("chaos"),
+            faults_rng: config.rng("faults"),
+            faults: Default::default(),
+            num_stale: 0,
+            num_invalid_multicast: 0,
+        };
+        Self {
+            config,
+            state: Arc::new(Mutex::new(state)),
+            replicas,
+        }
+    }
+
+    pub fn register<M>(&self, host: Host) -> super::Context<M> {
+        let mut signer = Signer::new(&self.config, host);
+        if let Some(key_ring) = self.key_ring(host) {
+            signer.key_ring = key_ring
+        }
+        super::Context::Simulated(Box::new(Context {
+            config: self.config.clone(),
+            source: host,
+            signer,
+            state: self.state.clone(),
+            rng: self.config.rng(host),
+            byzantine: None,
+        }))
+    }
+
+    // shared by the signer and verifier of a replica, none for clients or if verification is
+    // disabled
+    pub fn key_ring(&self, host: Host) -> Option<KeyRing> {
+        let Host::Replica(index) = host else {
+            return None;
+        };
+        self.replicas[&index].1.key_ring()
+    }
+
+    pub fn now(&self) -> Duration {
+        self.state.lock().unwrap().now
+    }
+
+    // ordered multicast dropped for failing verification, see `Delegate`
+    pub fn num_invalid_multicast(&self) -> u64 {
+        self.state.lock().unwrap().num_invalid_multicast
+    }
+
+    // messages dropped for `Receivers::is_stale`
+    pub fn num_stale(&self) -> u64 {
+        self.state.lock().unwrap().num_stale
+    }
+
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency
+    }
+
+    // messages may overtake each other by up to `jitter`, in the same order for the same seed
+    pub fn set_jitter(&self, jitter: Duration) {
+        self.state.lock().unwrap().jitter = jitter
+    }
+
+    // the sequencer merges neo's requests into batches of at most `max_batch_size`, holding a
+    // partial batch for `timeout`, on every multicast group
+    pub fn enable_batching(&self, max_batch_size: usize, timeout: Duration) {
+        let batchers = Vec::from_iter(
+            (0..self.config.num_multicast_group()).map(|_| (Batcher::new(max_batch_size), 0)),
+        );
+        let state = &mut *self.state.lock().unwrap();
+        state.batchers = batchers;
+        state.batch_timeout = timeout
+    }
+
+    // to the sequencer of the multicast group
+    pub fn inject_sequencer_fault(&self, group: u8, fault: SequencerFault) {
+        self.state
+            .lock()
+            .unwrap()
+            .sequencers
+            .get_mut(group as usize)
+            .expect("ordered multicast enabled")
+            .inject_fault(fault)
+    }
+
+    // replace the sequencers with ones that number from the start, i.e. the re-sequencers that the
+    // replicas move to on an epoch change
+    pub fn restart_sequencer(&self) {
+        let state = &mut *self.state.lock().unwrap();
+        assert!(!state.sequencers.is_empty(), "ordered multicast enabled");
+        for sequencer in &mut state.sequencers {
+            *sequencer = sequencer.restart()
+        }
+    }
+
+    // assign `seq_num` to two messages, multicasting the first one only to `replicas` and the
+    // second one only to the others, on the multicast group
+    pub fn equivocate(&self, group: u8, seq_num: u32, replicas: Vec<ReplicaIndex>) {
+        self.inject_sequencer_fault(group, SequencerFault::Duplicate(seq_num));
+        self.state.lock().unwrap().equivocation = Some(Equivocation {
+            group,
+            seq_num,
+            replicas,
+            first_sent: false,
+        })
+    }
+
+    // take the actions at their times from now, which do not keep the network from being quiescent
+    // i.e. the ones after that are taken on later runs
+    pub fn schedule_chaos(&self, scenario: &control_messages::Scenario) {
+        let state = &mut *self.state.lock().unwrap();
+        for &(at, action) in &scenario.0 {
+            state.push(at, Event::Chaos(action))
+        }
+    }
+
+    // replace the injected faults, see `Faults`
+    pub fn inject_faults(&self, mut faults: Faults) {
+        let state = &mut *self.state.lock().unwrap();
+        faults.shift(state.now);
+        state.faults = faults
+    }
+
+    // start recording delivered messages, discarding what is recorded so far
+    pub fn record_trace(&self) {
+        self.state.lock().unwrap().trace = Some(Default::default())
+    }
+
+    pub fn take_trace(&self) -> Vec<TraceEvent> {
+        self.state
+            .lock()
+            .unwrap()
+            .trace
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    fn trace(&self, sender: Host, receiver: Host, message: &dyn std::fmt::Debug) {
+        let state = &mut *self.state.lock().unwrap();
+        if let Some(trace) = &mut state.trace {
+            trace.push(TraceEvent {
+                time: state.now,
+                sender,
+                receiver,
+                message: format!("{message:?}"),
+            })
+        }
+    }
+}
+
+impl Network {
+    // a bound of processed events that no reasonable test should reach, to turn livelocks e.g.
+    // endless resending into failures
+    const MAX_NUM_EVENT: usize = 10_000_000;
+
+    fn run_internal<M, N>(&self, nodes: &mut Nodes<'_, M>, into: fn(OrderedMulticast<N>) -> M)
+    where
+        M: DeserializeOwned + Verify + std::fmt::Debug,
+        N: DeserializeOwned + DigestHash + std::fmt::Debug,
+    {
+        let mut lockstep = self.lockstep_internal(into);
+        for _ in 0..Self::MAX_NUM_EVENT {
+            if lockstep.step_internal(nodes, false).is_none() {
+                return;
+            }
+        }
+        panic!("not quiescent after {} events", Self::MAX_NUM_EVENT)
+    }
+
+    // deliver messages and fire timers until there is no message in flight and no timer other
+    // than sentinels
+    pub fn run_until_quiescent<M>(&self, nodes: &mut Nodes<'_, M>)
+    where
+        M: DeserializeOwned + Verify + std::fmt::Debug,
+    {
+        self.run_internal::<_, Unordered>(nodes, |_| unimplemented!())
+    }
+
+    pub fn run_ordered_multicast_until_quiescent<M, N>(&self, nodes: &mut Nodes<'_, M>)
+    where
+        M: DeserializeOwned + Verify + std::fmt::Debug,
+        N: DeserializeOwned + DigestHash + std::fmt::Debug,
+        OrderedMulticast<N>: Into<M>,
+    {
+        self.run_internal(nodes, Into::into)
+    }
+
+    fn lockstep_internal<M, N>(&self, into: fn(OrderedMulticast<N>) -> M) -> Lockstep<'_, M, N> {
+        Lockstep {
+            network: self,
+            delegates: HashMap::from_iter(self.replicas.iter().map(|(&index, (variant, _))| {
+                let delegates = (0..self.config.num_multicast_group()).map(|_| variant.delegate());
+                (Host::Replica(index), Vec::from_iter(delegates))
+            })),
+            into,
+        }
+    }
+
+    pub fn lockstep<M>(&self) -> Lockstep<'_, M, Unordered> {
+        self.lockstep_internal(|_| unimplemented!())
+    }
+
+    pub fn lockstep_ordered_multicast<M, N>(&self) -> Lockstep<'_, M, N>
+    where
+        OrderedMulticast<N>: Into<M>,
+    {
+        self.lockstep_internal(Into::into)
+    }
+}
+
+// the ordered multicast payload of protocols that do not use it
+#[derive(Debug, Deserialize)]
+pub enum Unordered {}
+
+impl DigestHash for Unordered {
+    fn hash(&self, _: &mut impl std::hash::Hasher) {
+        unreachable!()
+    }
+}
+
+// drives the network one delivered event at a time, so an interleaving can be stepped through
+// and the nodes inspected in between, which is what the `run_*_until_quiescent` do in a loop
+// the nodes are passed to every step instead of being owned, so they are free to borrow between
+// steps
+pub struct Lockstep<'a, M, N> {
+    network: &'a Network,
+    // persist across steps, as they hold ordered multicast that is not yet delivered
+    // per multicast group
+    delegates: HashMap<Host, Vec<Delegate<N>>>,
+    into: fn(OrderedMulticast<N>) -> M,
+}
+
+impl<M, N> Lockstep<'_, M, N>
+where
+    M: DeserializeOwned + Verify + std::fmt::Debug,
+    N: DeserializeOwned + DigestHash + std::fmt::Debug,
+{
+    // deliver the next message or fire the next timer, skipping the events that do not reach a
+    // node, e.g. canceled timers and messages dropped by chaos, and return what is delivered, or
+    // none if the network is quiescent
+    pub fn step(&mut self, nodes: &mut Nodes<'_, M>) -> Option<TraceEvent> {
+        self.step_internal(nodes, true)
+    }
+
+    // the message of the returned event is only formatted if `describe`
+    fn step_internal(&mut self, nodes: &mut Nodes<'_, M>, describe: bool) -> Option<TraceEvent> {
+        let network = self.network;
+        let deserialize = |buf: &[u8]| {
+            bincode::options()
+                .allow_trailing_bytes()
+                .deserialize::<M>(buf)
+                .unwrap()
+        };
+        loop {
+            let event = {
+                let state = &mut *network.state.lock().unwrap();
+                if state.quiescent() {
+                    return None;
+                }
+                let ((now, _), event) = state.events.pop_first().unwrap();
+                state.now = now;
+                match &event {
+                    Event::Timer(_, id) => {
+                        let Some(timer) = state.timers.get(id) else {
+                            continue;
+                        };
+                        let duration = timer.duration;
+                        state.push(duration, Event::Timer(event_host(&event), *id))
+                    }
+                    &Event::Chaos(action) => {
+                        state.chaos.apply(action);
+                        continue;
+                    }
+                    &Event::FlushBatch(group, batch_num) => {
+                        state.num_in_flight -= 1;
+                        let buf = match state.batchers.get_mut(group as usize) {
+                            Some((batcher, held_num)) if *held_num == batch_num => batcher.flush(),
+                            _ => None,
+                        };
+                        if let Some(buf) = buf {
+                            state.multicast(&network.config, group, buf)
+                        }
+                        continue;
+                    }
+                    _ => state.num_in_flight -= 1,
+                }
+                event
+            };
+            let (event, delayed) = match event {
+                Event::Delayed(event) => (*event, true),
+                event => (event, false),
+            };
+            if !delayed {
+                if let Event::Message(receiver, remote, _)
+                | Event::OrderedMulticastMessage(receiver, remote, ..) = &event
+                {
+                    let state = &mut *network.state.lock().unwrap();
+                    let mut ingress = state
+                        .chaos
+                        .ingress(*receiver, *remote, &mut state.chaos_rng);
+                    // counted on the link even if chaos drops it
+                    let (fault_ingress, duplicate) =
+                        state
+                            .faults
+                            .ingress(state.now, *receiver, *remote, &mut state.faults_rng);
+                    ingress = ingress.and(fault_ingress);
+                    if duplicate && ingress != Ingress::Drop {
+                        let copy = match &event {
+                            Event::Message(receiver, remote, buf) => {
+                                Event::Message(*receiver, *remote, buf.clone())
+                            }
+                            Event::OrderedMulticastMessage(receiver, remote, group, buf) => {
+                                Event::OrderedMulticastMessage(
+                                    *receiver,
+                                    *remote,
+                                    *group,
+                                    buf.clone(),
+                                )
+                            }
+                            _ => unreachable!(),
+                        };
+                        let delay = match ingress {
+                            Ingress::Delay(delay) => delay,
+                            _ => Duration::ZERO,
+                        };
+                        state.push(delay, Event::Delayed(Box::new(copy)))
+                    }
+                    match ingress {
+                        Ingress::Deliver => {}
+                        Ingress::Drop => continue,
+                        Ingress::Delay(delay) => {
+                            state.push(delay, Event::Delayed(Box::new(event)));
+                            continue;
+                        }
+                    }
+                }
+            }
+            let host = event_host(&event);
+            let node = nodes
+                .get_mut(&host)
+                .unwrap_or_else(|| panic!("{host:?} is not running"));
+            let verifier = match host {
+                Host::Replica(index) => &network.replicas[&index].1,
+                _ => &Verifier::Nop,
+            };
+            let delivered = |sender, message: &dyn std::fmt::Debug| {
+                network.trace(sender, host, message);
+                TraceEvent {
+                    time: network.now(),
+                    sender,
+                    receiver: host,
+                    message: if describe {
+                        format!("{message:?}")
+                    } else {
+                        Default::default()
+                    },
+                }
+            };
+            let delivered = match event {
+                Event::Message(receiver, remote, buf) => {
+                    let message = deserialize(&buf);
+                    let delivered = delivered(remote, &message);
+                    if network.config.reject_stale && node.is_stale(remote, &message) {
+                        network.state.lock().unwrap().num_stale += 1
+                    } else {
+                        message.verify(verifier).unwrap();
+                        node.handle(receiver, remote, message)
+                    }
+                    delivered
+                }
+                Event::LoopbackMessage(receiver, buf) => {
+                    let message = deserialize(&buf);
+                    let delivered = delivered(receiver, &message);
+                    node.handle_loopback(receiver, message);
+                    delivered
+                }
+                Event::OrderedMulticastMessage(receiver, remote, group, buf) => {
+                    let Host::Replica(index) = receiver else {
+                        unreachable!()
+                    };
+                    let mut message = network.replicas[&index].0.deserialize::<N>(buf);
+                    message.group = group;
+                    let delivered = delivered(remote, &message);
+                    let result = self.delegates.get_mut(&receiver).unwrap()[group as usize]
+                        .on_receive(remote, message, &mut **node, verifier, self.into);
+                    if result.is_err() {
+                        network.state.lock().unwrap().num_invalid_multicast += 1
+                    }
+                    delivered
+                }
+                Event::Timer(receiver, id) => {
+                    node.on_timer(receiver, super::TimerId::Simulated(id));
+                    // not traced, as it is not a message
+                    TraceEvent {
+                        time: network.now(),
+                        sender: receiver,
+                        receiver,
+                        message: if describe {
+                            format!("Timer({id})")
+                        } else {
+                            Default::default()
+                        },
+                    }
+                }
+                Event::Delayed(_) | Event::Chaos(_) | Event::FlushBatch(..) => unreachable!(),
+            };
+            // every host is considered to be idle after each event
+            for delegate in self.delegates.get_mut(&host).into_iter().flatten() {
+                if delegate.on_pace(&mut **node, verifier, self.into).is_err() {
+                    network.state.lock().unwrap().num_invalid_multicast += 1
+                }
+            }
+            node.on_pace();
+            return Some(delivered);
+        }
+    }
+}
+
+fn event_host(event: &Event) -> Host {
+    match event {
+        Event::Message(host, ..)
+        | Event::LoopbackMessage(host, _)
+        | Event::OrderedMulticastMessage(host, ..)
+        | Event::Timer(host, _) => *host,
+        Event::Delayed(event) => event_host(event),
+        Event::Chaos(_) | Event::FlushBatch(..) => unreachable!(),
+    }
+}
+
+// render the selected part of a trace as a mermaid sequence diagram, labeling each arrow with the
+// message variant and the delivery time
+pub fn sequence_diagram(trace: &[TraceEvent], select: impl Fn(&TraceEvent) -> bool) -> String {
+    fn participant(host: Host) -> String {
+        match host {
+            Host::Client(index) => format!("C{index}"),
+            Host::Replica(index) => format!("R{index}"),
+            Host::Multicast => String::from("Multicast"),
+            Host::UnkownMulticastSender => String::from("Sequencer"),
+        }
+    }
+
+    let mut diagram = String::from("sequenceDiagram\n");
+    for event in trace.iter().filter(|event| select(event)) {
+        // strip the payload of the outermost variant, e.g. `PrePrepare(Signed { .. })`
+        let label = event
+            .message
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()
+            .unwrap();
+        diagram += &format!(
+            "    {}->>{}: {label} @{:?}\n",
+            participant(event.sender),
+            participant(event.receiver),
+            event.time
+        )
+    }
+    diagram
+}
+
+// adapt a client into a receiver, which is done by the benchmark in tokio context
+#[derive(Debug)]
+pub struct ClientNode<C>(pub C);
+
+impl<C: crate::Client> Receivers for ClientNode<C> {
+    type Message = C::Message;
+
+    fn handle(&mut self, _: Host, _: Host, message: Self::Message) {
+        self.0.handle(message)
+    }
+
+    fn on_timer(&mut self, _: Host, _: super::TimerId) {
+        self.0.on_timer()
+    }
+}
+
+// the setup that the protocols' tests share, i.e. a network of replicas and clients that is run
+// until quiescent for a number of rounds
+#[cfg(test)]
+pub(crate) mod fixture {
+    use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+
+    // how a round is delivered, i.e. `Network::run_until_quiescent` or the ordered multicast one
+    pub type Run<M> = fn(&Network, &mut Nodes<'_, M>);
+
+    // `num_replica` replicas and `num_client` clients with cheap signatures, seeded with
+    // `SIMULATION_SEED` if set, so a failed run can be replayed
+    pub fn config(num_replica: usize, num_client: usize) -> Config {
+        let addrs = (0..num_replica)
+            .map(|index| Host::Replica(index as _))
+            .chain((0..num_client).map(|index| Host::Client(index as _)))
+            .enumerate()
+            .map(|(i, host)| (host, ([10, 0, 0, 1], 10000 + i as u16).into()))
+            .collect();
+        let mut config = Config::new(addrs, (num_replica - 1) / 3);
+        config.fake_crypto = true;
+        if let Ok(seed) = std::env::var("SIMULATION_SEED") {
+            config.seed = seed.parse().unwrap()
+        }
+        config
+    }
+
+    // tells the seed to replay with if the run panics
+    struct ReportSeed(u64);
+
+    impl Drop for ReportSeed {
+        fn drop(&mut self) {
+            if std::thread::panicking() {
+                eprintln!("replay with SIMULATION_SEED={}", self.0)
+            }
+        }
+    }
+
+    // run the replicas, indexed by position, along with `others` e.g. clients until quiescent
+    pub fn deliver<'a, M: 'a, R>(
+        network: &Network,
+        replicas: &'a mut [R],
+        others: impl IntoIterator<Item = (Host, &'a mut dyn Receivers<Message = M>)>,
+        run: Run<M>,
+    ) where
+        R: Receivers<Message = M>,
+    {
+        let _report = ReportSeed(network.config.seed);
+        let mut nodes = Nodes::new();
+        for (index, replica) in replicas.iter_mut().enumerate() {
+            nodes.insert(Host::Replica(index as _), replica);
+        }
+        nodes.extend(others);
+        run(network, &mut nodes)
+    }
+
+    // every client invokes an empty op and the network runs until quiescent, `num_round` times,
+    // returning the number of completed invocations
+    pub fn run_rounds<R, C>(
+        network: &Network,
+        replicas: &mut [R],
+        clients: &mut [ClientNode<C>],
+        num_round: u32,
+        run: Run<C::Message>,
+    ) -> u32
+    where
+        R: Receivers<Message = C::Message>,
+        C: crate::Client,
+        C::Message: DeserializeOwned,
+    {
+        let count = Arc::new(AtomicU32::new(0));
+        for _ in 0..num_round {
+            for client in &*clients {
+                let count = count.clone();
+                client.0.invoke(Default::default(), move |_| {
+                    count.fetch_add(1, SeqCst);
+                })
+            }
+            let clients = clients.iter_mut().enumerate().map(|(index, client)| {
+                let client: &mut dyn Receivers<Message = _> = client;
+                (Host::Client(index as _), client)
+            });
+            deliver(network, replicas, clients, run)
+        }
+        count.load(SeqCst)
+    }
+}
+
+#[cfg(all(test, feature = "neo", feature = "pbft", feature = "unreplicated"))]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+    use control_messages::ByzantineStrategy;
+
+    use crate::{
+        common::{state_transfer, Chain},
+        context::{storage::Storage, ClientIndex},
+        neo, pbft, unreplicated, App, Client,
+    };
+
+    use super::{
+        super::byzantine::Tamper,
+        fixture::{config, deliver, run_rounds},
+        *,
+    };
+
+    fn invoke_all<C: Client>(clients: &[ClientNode<C>], count: &Arc<AtomicU32>) {
+        for client in clients {
+            let count = count.clone();
+            client.0.invoke(Default::default(), move |_| {
+                count.fetch_add(1, SeqCst);
+            })
+        }
+    }
+
+    #[test]
+    fn unreplicated_quiescent() {
+        let network = Network::new(config(1, 2), true, |_| Variant::Unreachable);
+        let mut replica = unreplicated::Replica::new(network.register(Host::Replica(0)), App::Null);
+        let mut clients = Vec::from_iter((0..2).map(|index| {
+            ClientNode(unreplicated::Client::new(
+                network.register(Host::Client(index)),
+                index,
+            ))
+        }));
+        let count = run_rounds(
+            &network,
+            std::slice::from_mut(&mut replica),
+            &mut clients,
+            1,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 2);
+        // one round trip
+        assert_eq!(network.now(), Duration::from_micros(200));
+    }
+
+    #[test]
+    fn pbft_quiescent() {
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 10);
+    }
+
+    #[cfg(feature = "hotstuff")]
+    #[test]
+    fn hotstuff_bls_quiescent() {
+        let mut config = config(4, 1);
+        config
+            .signature_policy
+            .insert("Vote", control_messages::SignatureScheme::Bls);
+        let network = Network::new(config, true, |_| Variant::Unreachable);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            crate::hotstuff::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(crate::hotstuff::Client::new(
+            network.register(Host::Client(0)),
+            0,
+        ));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 10);
+    }
+
+    #[cfg(feature = "hotstuff")]
+    #[test]
+    fn hotstuff_leader_crashed() {
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        network.schedule_chaos(&"0s isolate 0".parse().unwrap());
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            crate::hotstuff::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(crate::hotstuff::Client::new(
+            network.register(Host::Client(0)),
+            0,
+        ));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 10);
+        // the views led by replica 0 are timed out
+        assert!(replicas[1].view > 4);
+        for replica in &replicas[2..] {
+            assert_eq!(
+                replica.chain.digest_execute,
+                replicas[1].chain.digest_execute
+            )
+        }
+    }
+
+    #[cfg(feature = "hotstuff")]
+    #[test]
+    fn hotstuff_mempool() {
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            crate::hotstuff::Replica::new_mempool(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+            )
+        }));
+        let mut client = ClientNode(crate::hotstuff::Client::new_mempool(
+            network.register(Host::Client(0)),
+            0,
+        ));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 10);
+        for replica in &replicas[1..] {
+            assert_eq!(
+                replica.chain.digest_execute,
+                replicas[0].chain.digest_execute
+            )
+        }
+    }
+
+    #[cfg(feature = "sbft")]
+    fn sbft_run(chaos: Option<&str>) {
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        if let Some(chaos) = chaos {
+            network.schedule_chaos(&chaos.parse().unwrap())
+        }
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            crate::sbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(crate::sbft::Client::new(
+            network.register(Host::Client(0)),
+            0,
+        ));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 10);
+    }
+
+    #[cfg(feature = "sbft")]
+    #[test]
+    fn sbft_fast_path() {
+        sbft_run(None)
+    }
+
+    // the fast path needs every replica, so every block commits on the fallback
+    #[cfg(feature = "sbft")]
+    #[test]
+    fn sbft_fallback() {
+        sbft_run(Some("0s isolate 3"))
+    }
+
+    // every request reaches the primary twice, and the copy is answered from the client table
+    // instead of being executed again
+    #[cfg(feature = "sbft")]
+    #[test]
+    fn sbft_duplicated_request() {
+        use crate::common::ClientLookup;
+
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        network.inject_faults(Faults::default().rule(
+            Link::between(Host::Client(0), Host::Replica(0)),
+            Window::Always,
+            Fault::Duplicate,
+        ));
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            crate::sbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(crate::sbft::Client::new(
+            network.register(Host::Client(0)),
+            0,
+        ));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 10);
+        for replica in &mut replicas {
+            assert!(matches!(
+                replica.client_table.lookup(0, 10, Host::Client(0)),
+                ClientLookup::Resend(_)
+            ))
+        }
+    }
+
+    #[cfg(feature = "tendermint")]
+    fn tendermint_run(chaos: Option<&str>) -> Vec<crate::tendermint::Replica> {
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        if let Some(chaos) = chaos {
+            network.schedule_chaos(&chaos.parse().unwrap())
+        }
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            crate::tendermint::Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+            )
+        }));
+        let mut client = ClientNode(crate::tendermint::Client::new(
+            network.register(Host::Client(0)),
+            0,
+        ));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 10);
+        replicas
+    }
+
+    #[cfg(feature = "tendermint")]
+    #[test]
+    fn tendermint_quiescent() {
+        let replicas = tendermint_run(None);
+        for replica in &replicas {
+            assert_eq!(replica.height, 11);
+            assert_eq!(
+                replica.chain.digest_execute,
+                replicas[0].chain.digest_execute
+            )
+        }
+    }
+
+    // the heights that replica 1 proposes first are decided in later rounds
+    #[cfg(feature = "tendermint")]
+    #[test]
+    fn tendermint_proposer_crashed() {
+        let replicas = tendermint_run(Some("0s isolate 1"));
+        for replica in [&replicas[0], &replicas[2], &replicas[3]] {
+            assert_eq!(
+                replica.chain.digest_execute,
+                replicas[0].chain.digest_execute
+            )
+        }
+    }
+
+    #[test]
+    fn pbft_lockstep() {
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let count = Arc::new(AtomicU32::new(0));
+        invoke_all(std::slice::from_ref(&client), &count);
+        let mut lockstep = network.lockstep();
+        let mut events = Vec::new();
+        loop {
+            let mut nodes = Nodes::new();
+            for (index, replica) in replicas.iter_mut().enumerate() {
+                nodes.insert(Host::Replica(index as _), replica);
+            }
+            nodes.insert(Host::Client(0), &mut client);
+            let Some(event) = lockstep.step(&mut nodes) else {
+                break;
+            };
+            events.push(event);
+            // the nodes are free to inspect between steps
+            if count.load(SeqCst) == 1 {
+                break;
+            }
+        }
+        assert!(events[0].message.starts_with("Request"));
+        assert_eq!(events[0].receiver, Host::Replica(0));
+        assert_eq!(events.last().unwrap().receiver, Host::Client(0));
+        // the client completes on f + 1 replies, by when at least that many replicas executed
+        let num_executed = replicas
+            .iter()
+            .filter(|replica| replica.chain.digest_execute != Chain::genesis().digest())
+            .count();
+        assert!(num_executed >= 2);
+        assert!(replicas.iter().all(|replica| replica.view_num == 0));
+    }
+
+    #[test]
+    fn pbft_certificates_pruned() {
+        let network = Network::new(config(4, 4), true, |_| Variant::Unreachable);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut clients = Vec::from_iter((0..4).map(|index| {
+            ClientNode(pbft::Client::new(
+                network.register(Host::Client(index)),
+                index,
+            ))
+        }));
+        let mut count = 0;
+        for _ in 0..100 {
+            count += run_rounds(
+                &network,
+                &mut replicas,
+                &mut clients,
+                1,
+                Network::run_until_quiescent,
+            );
+            // every block is executed once quiescent, and late messages do not bring them back
+            assert!(replicas
+                .iter()
+                .all(|replica| replica.num_certificates() == 0));
+        }
+        assert_eq!(count, 400);
+    }
+
+    #[test]
+    fn pbft_checkpoint_schedule() {
+        let mut config = config(4, 1);
+        config.checkpoint_interval = 3;
+        let network = Network::new(config, true, |_| Variant::Unreachable);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 10);
+        for replica in &replicas {
+            let op_nums =
+                Vec::from_iter(replica.checkpoints.iter().map(|snapshot| snapshot.op_num));
+            // the earlier ones are merged into the stable one
+            assert_eq!(op_nums, [9]);
+            assert_eq!(replica.stable_op_num, 9);
+            assert_eq!(replica.stable_certificate.len(), 3);
+            // one block per op, the ones up to the stable checkpoint are truncated
+            assert_eq!(replica.num_blocks(), 1)
+        }
+    }
+
+    #[test]
+    fn pbft_stale_rejected() {
+        let mut config = config(4, 1);
+        config.reject_stale = true;
+        let network = Network::new(config, true, |_| Variant::Unreachable);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 10);
+        // the commits beyond the quorum arrive after the block is executed
+        assert!(network.num_stale() > 0)
+    }
+
+    #[test]
+    fn pbft_client_secret() {
+        let mut config = config(4, 2);
+        config.client_secret = Some(b"secret".to_vec());
+        let network = Network::new(config, true, |_| Variant::Unreachable);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut clients = Vec::from_iter((0..2).map(|index| {
+            ClientNode(pbft::Client::new(
+                network.register(Host::Client(index)),
+                index,
+            ))
+        }));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            &mut clients,
+            10,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 20);
+    }
+
+    #[test]
+    fn pbft_random_destination_quiescent() {
+        let mut config = config(4, 1);
+        config.client_destination = Some(control_messages::ClientDestination::Random);
+        let network = Network::new(config, true, |_| Variant::Unreachable);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 10);
+    }
+
+    #[test]
+    fn pbft_chaos_quiescent() {
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        network.schedule_chaos(
+            &"
+                0s delay 50us
+                0s reorder 0.3 200us
+                1ms isolate 3
+                3ms rejoin 3
+            "
+            .parse()
+            .unwrap(),
+        );
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            20,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 20);
+        // went through the whole scenario
+        assert!(network.now() > Duration::from_millis(3))
+    }
+
+    fn pbft_faults_run(faults: Faults, num_op: u32) -> (Network, Vec<pbft::Replica>) {
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        network.inject_faults(faults);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            num_op,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, num_op);
+        for replica in &replicas {
+            assert_eq!(
+                replica.chain.digest_execute,
+                replicas[0].chain.digest_execute
+            )
+        }
+        (network, replicas)
+    }
+
+    #[test]
+    fn pbft_faults_duplicate() {
+        let faults = Faults::default()
+            .rule(Link::any(), Window::Always, Fault::Duplicate)
+            .rule(
+                Link::any(),
+                Window::Always,
+                Fault::Reorder(0.3, Duration::from_micros(200)),
+            );
+        // every request completes once and the replicas agree on the chain, though every message
+        // is delivered twice
+        pbft_faults_run(faults, 10);
+    }
+
+    #[test]
+    fn faults_count_dropped() {
+        let ms = Duration::from_millis;
+        let mut faults = Faults::default()
+            .partition(
+                [vec![Host::Replica(0)], vec![Host::Replica(1)]],
+                Duration::ZERO..ms(1),
+            )
+            .rule(Link::to(Host::Replica(1)), Window::Count(0..1), Fault::Drop)
+            .rule(Link::any(), Window::Count(0..2), Fault::Drop)
+            .rule(
+                Link::to(Host::Replica(1)),
+                Window::Count(2..3),
+                Fault::Duplicate,
+            );
+        let mut rng = config(2, 0).rng("faults");
+        let mut ingress = |now| faults.ingress(now, Host::Replica(1), Host::Replica(0), &mut rng);
+        // the partitioned message and the one dropped by the first rule are counted by every rule
+        assert_eq!(ingress(Duration::ZERO), (Ingress::Drop, false));
+        assert_eq!(ingress(ms(1)), (Ingress::Drop, false));
+        assert_eq!(ingress(ms(1)), (Ingress::Deliver, true));
+        assert_eq!(ingress(ms(1)), (Ingress::Deliver, false))
+    }
+
+    #[test]
+    fn pbft_faults_drop() {
+        // replica 3 misses the first messages and catches up with state transfer
+        let faults = Faults::default().rule(
+            Link::to(Host::Replica(3)),
+            Window::Count(0..100),
+            Fault::Drop,
+        );
+        let (_, replicas) = pbft_faults_run(faults, 40);
+        assert!(replicas[3].state_transfer.num_applied > 0)
+    }
+
+    // replica 3 misses the first blocks, which the others truncate on their stable checkpoints, and
+    // restores the state of `app` after the `ops`, each with its expected result
+    #[cfg(any(feature = "kvstore", feature = "ycsb"))]
+    fn pbft_restore_truncated_run(app: App, ops: Vec<(Vec<u8>, Vec<u8>)>) {
+        let mut config = config(4, 1);
+        config.checkpoint_interval = 2;
+        let network = Network::new(config, true, |_| Variant::Unreachable);
+        network.inject_faults(Faults::default().rule(
+            Link::to(Host::Replica(3)),
+            Window::Count(0..100),
+            Fault::Drop,
+        ));
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, app.clone())
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let count = Arc::new(AtomicU32::new(0));
+        let num_op = ops.len() as u32;
+        for (op, expected) in ops {
+            let count = count.clone();
+            client.0.invoke(op, move |result: Vec<u8>| {
+                assert_eq!(result, expected);
+                count.fetch_add(1, SeqCst);
+            });
+            deliver(
+                &network,
+                &mut replicas,
+                [(Host::Client(0), &mut client as _)],
+                Network::run_until_quiescent,
+            )
+        }
+        assert_eq!(count.load(SeqCst), num_op);
+        assert!(replicas[3].state_transfer.num_restored > 0);
+        for replica in &replicas {
+            assert_eq!(
+                replica.chain.digest_execute,
+                replicas[0].chain.digest_execute
+            );
+            assert_eq!(replica.op_num, replicas[0].op_num)
+        }
+    }
+
+    #[cfg(feature = "kvstore")]
+    #[test]
+    fn pbft_restore_truncated() {
+        use bincode::Options;
+
+        use crate::app::kvstore;
+
+        let ops = Vec::from_iter((0..40).map(|index| {
+            let op = kvstore::Op::Put(vec![index], vec![index]);
+            let result = bincode::options().serialize(&kvstore::Result::Put);
+            (op.encode(), result.unwrap())
+        }));
+        pbft_restore_truncated_run(App::KvStore(Default::default()), ops)
+    }
+
+    #[cfg(feature = "ycsb")]
+    #[test]
+    fn pbft_restore_truncated_ycsb() {
+        use bincode::Options;
+
+        use crate::app::ycsb;
+
+        let ops = Vec::from_iter((0..40).map(|index| {
+            let op = ycsb::Op::Insert(format!("key{index}"), format!("value{index}"));
+            let result = bincode::options().serialize(&ycsb::Result::InsertOk);
+            (bincode::options().serialize(&op).unwrap(), result.unwrap())
+        }));
+        pbft_restore_truncated_run(App::Ycsb(Default::default()), ops)
+    }
+
+    #[test]
+    fn pbft_faults_partition_heal() {
+        let faults = Faults::default()
+            .partition(
+                [
+                    vec![
+                        Host::Replica(0),
+                        Host::Replica(1),
+                        Host::Replica(2),
+                        Host::Client(0),
+                    ],
+                    vec![Host::Replica(3)],
+                ],
+                Duration::ZERO..Duration::from_millis(10),
+            )
+            .rule(
+                Link::from(Host::Replica(1)),
+                Window::Always,
+                Fault::Delay(Duration::from_micros(100)),
+            );
+        // the majority side keeps committing, and replica 3 catches up after the partition heals
+        let (network, replicas) = pbft_faults_run(faults, 40);
+        assert!(network.now() > Duration::from_millis(10));
+        assert!(replicas[3].state_transfer.num_applied > 0)
+    }
+
+    #[test]
+    fn pbft_chaos_partition_heal() {
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        // the side of replica 3 alone, while the rest keeps a quorum
+        network.schedule_chaos(&"1ms partition 3\n10ms heal".parse().unwrap());
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            40,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 40);
+        assert!(replicas[3].state_transfer.num_applied > 0);
+        for replica in &replicas {
+            assert_eq!(
+                replica.chain.digest_execute,
+                replicas[0].chain.digest_execute
+            )
+        }
+    }
+
+    // 4 replicas of which `faulty` misbehaves with `strategy`, and a client
+    fn byzantine_nodes<R, C>(
+        faulty: ReplicaIndex,
+        strategy: ByzantineStrategy,
+        new_replica: impl Fn(crate::Context<C::Message>, ReplicaIndex) -> R,
+        new_client: impl Fn(crate::Context<C::Message>, ClientIndex) -> C,
+    ) -> (Network, Vec<R>, ClientNode<C>)
+    where
+        C: Client,
+        C::Message: Tamper + Serialize + DeserializeOwned,
+    {
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        let replicas = Vec::from_iter((0..4).map(|index| {
+            let mut context = network.register(Host::Replica(index));
+            if index == faulty {
+                context.set_byzantine(strategy)
+            }
+            new_replica(context, index)
+        }));
+        let client = ClientNode(new_client(network.register(Host::Client(0)), 0));
+        (network, replicas, client)
+    }
+
+    // runs every strategy on the last replica, which the others must tolerate, and returns the
+    // replicas of each run
+    fn byzantine_backup<R, C>(
+        new_replica: impl Fn(crate::Context<C::Message>, ReplicaIndex) -> R,
+        new_client: impl Fn(crate::Context<C::Message>, ClientIndex) -> C,
+    ) -> Vec<Vec<R>>
+    where
+        R: Receivers<Message = C::Message>,
+        C: Client,
+        C::Message: Tamper + Serialize + DeserializeOwned + Verify + std::fmt::Debug,
+    {
+        Vec::from_iter(
+            [
+                ByzantineStrategy::Silent,
+                ByzantineStrategy::Equivocate,
+                ByzantineStrategy::StaleVote,
+                ByzantineStrategy::CorruptDigest,
+            ]
+            .map(|strategy| {
+                let (network, mut replicas, mut client) =
+                    byzantine_nodes(3, strategy, &new_replica, &new_client);
+                let count = run_rounds(
+                    &network,
+                    &mut replicas,
+                    std::slice::from_mut(&mut client),
+                    20,
+                    Network::run_until_quiescent,
+                );
+                assert_eq!(count, 20, "{strategy:?}");
+                replicas
+            }),
+        )
+    }
+
+    #[test]
+    fn pbft_byzantine_backup() {
+        for replicas in byzantine_backup(
+            |context, index| pbft::Replica::new(context, index, App::Null),
+            pbft::Client::new,
+        ) {
+            for replica in &replicas[..3] {
+                assert_eq!(
+                    replica.chain.digest_execute,
+                    replicas[0].chain.digest_execute
+                )
+            }
+        }
+    }
+
+    #[cfg(feature = "hotstuff")]
+    #[test]
+    fn hotstuff_byzantine_backup() {
+        byzantine_backup(
+            |context, index| crate::hotstuff::Replica::new(context, index, App::Null),
+            crate::hotstuff::Client::new,
+        );
+    }
+
+    #[cfg(feature = "sbft")]
+    #[test]
+    fn sbft_byzantine_backup() {
+        byzantine_backup(
+            |context, index| crate::sbft::Replica::new(context, index, App::Null),
+            crate::sbft::Client::new,
+        );
+    }
+
+    #[test]
+    fn pbft_byzantine_primary_equivocate() {
+        let (network, mut replicas, _) = byzantine_nodes(
+            0,
+            ByzantineStrategy::Equivocate,
+            |context, index| pbft::Replica::new(context, index, App::Null),
+            pbft::Client::new,
+        );
+        // the request is sent without a client, which would time out as there is no view change
+        // to recover with
+        let mut context = network.register::<pbft::Message>(Host::Client(0));
+        context.send(
+            To::replica(0),
+            Request {
+                client_index: 0,
+                request_num: 1,
+                op: Default::default(),
+            },
+        );
+        let mut inbox = Inbox(Vec::new());
+        deliver(
+            &network,
+            &mut replicas,
+            [(Host::Client(0), &mut inbox as _)],
+            Network::run_until_quiescent,
+        );
+        assert!(inbox.0.is_empty());
+        // neither of the conflicting blocks gathers a commit quorum
+        for replica in &replicas {
+            assert_eq!(replica.chain.digest_execute, Chain::new().digest_execute)
+        }
+    }
+
+    #[cfg(feature = "ycsb")]
+    #[test]
+    fn pbft_read_only() {
+        use crate::app::ycsb;
+
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            let app = App::Ycsb(Default::default());
+            pbft::Replica::new(network.register(Host::Replica(index)), index, app)
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let mut invoke_read = |replicas: &mut Vec<pbft::Replica>, op| {
+            let outcome = Arc::new(Mutex::new(None));
+            client.0.invoke_read(
+                bincode::options().serialize(&op).unwrap(),
+                crate::client::OnOutcome({
+                    let outcome = outcome.clone();
+                    move |value| *outcome.lock().unwrap() = Some(value)
+                }),
+            );
+            deliver(
+                &network,
+                replicas,
+                [(Host::Client(0), &mut client as _)],
+                Network::run_until_quiescent,
+            );
+            let outcome = outcome.lock().unwrap().take().unwrap();
+            let result = bincode::options().deserialize(&outcome.result).unwrap();
+            (result, outcome.op_num)
+        };
+        let read = || ycsb::Op::Read("k".into());
+        assert_eq!(
+            invoke_read(&mut replicas, read()),
+            (ycsb::Result::NotFound, Some(0))
+        );
+        // not read-only, so ordered after the replicas refuse to answer it unordered
+        let insert = ycsb::Op::Insert("k".into(), "v".into());
+        assert_eq!(
+            invoke_read(&mut replicas, insert),
+            (ycsb::Result::InsertOk, None)
+        );
+        assert_eq!(
+            invoke_read(&mut replicas, read()),
+            (ycsb::Result::ReadOk("v".into()), Some(1))
+        );
+        for replica in &replicas {
+            assert_eq!(
+                replica.chain.digest_execute,
+                replicas[0].chain.digest_execute
+            )
+        }
+        // a silent replica and one answering at another op number leave two matched answers, which
+        // neither reach the quorum nor rule it out, so the op is ordered after the timeout
+        network.schedule_chaos(&control_messages::Scenario(vec![(
+            Duration::ZERO,
+            control_messages::ChaosAction::Isolate(3),
+        )]));
+        replicas[2].op_num += 1;
+        assert_eq!(
+            invoke_read(&mut replicas, read()),
+            (ycsb::Result::ReadOk("v".into()), None)
+        );
+    }
+
+    #[test]
+    fn pbft_isolated_catch_up() {
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        network.schedule_chaos(&"0s isolate 3\n10ms rejoin 3".parse().unwrap());
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            40,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 40);
+        // more blocks are missed than fit in a chunk
+        assert!(replicas[3].state_transfer.num_applied > state_transfer::CHUNK as u64);
+        for replica in &replicas {
+            assert_eq!(
+                replica.chain.digest_execute,
+                replicas[0].chain.digest_execute
+            )
+        }
+    }
+
+    #[test]
+    fn pbft_recover_from_storage() {
+        let dir = std::env::temp_dir().join(format!("pbft-recover-{}", std::process::id()));
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            let mut replica =
+                pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null);
+            replica.recover(Storage::open(&dir, Host::Replica(index)));
+            replica
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 10);
+
+        // restart replica 3 on its log
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        let mut replica = pbft::Replica::new(network.register(Host::Replica(3)), 3, App::Null);
+        replica.recover(Storage::open(&dir, Host::Replica(3)));
+        assert_eq!(
+            replica.chain.digest_execute,
+            replicas[0].chain.digest_execute
+        );
+        std::fs::remove_dir_all(&dir).unwrap()
+    }
+
+    #[test]
+    fn pbft_crash_mid_view() {
+        let dir = std::env::temp_dir().join(format!("pbft-crash-{}", std::process::id()));
+        let network = Network::new(config(4, 1), true, |_| Variant::Unreachable);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            let mut replica =
+                pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null);
+            replica.recover(Storage::open(&dir, Host::Replica(index)));
+            replica
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let count = Arc::new(AtomicU32::new(0));
+        invoke_all(std::slice::from_ref(&client), &count);
+        // the primary proposes on receiving the request, and crashes before anything else
+        let mut lockstep = network.lockstep();
+        let mut nodes = Nodes::new();
+        for (index, replica) in replicas.iter_mut().enumerate() {
+            nodes.insert(Host::Replica(index as _), replica);
+        }
+        nodes.insert(Host::Client(0), &mut client);
+        let event = lockstep.step(&mut nodes).unwrap();
+        assert_eq!(event.receiver, Host::Replica(0));
+        drop(nodes);
+        let proposed = replicas[0].chain.digest_parent;
+        assert_ne!(proposed, Chain::genesis().digest());
+        replicas[0] = pbft::Replica::new(network.register(Host::Replica(0)), 0, App::Null);
+        replicas[0].recover(Storage::open(&dir, Host::Replica(0)));
+        // proposes after the block in flight instead of another one at its height
+        assert_eq!(replicas[0].chain.digest_parent, proposed);
+        deliver(
+            &network,
+            &mut replicas,
+            [(Host::Client(0), &mut client as _)],
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count.load(SeqCst), 1);
+
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 10);
+        for replica in &replicas {
+            assert_eq!(
+                replica.chain.digest_execute,
+                replicas[1].chain.digest_execute
+            )
+        }
+        std::fs::remove_dir_all(&dir).unwrap()
+    }
+
+    #[test]
+    fn pbft_sequenced_quiescent() {
+        let network = Network::new(config(4, 1), true, |index| {
+            Variant::new_half_sip_hash(index, 1.)
+        });
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new_sequenced(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(pbft::Client::new_ordered(
+            network.register(Host::Client(0)),
+            0,
+        ));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_ordered_multicast_until_quiescent::<_, Request>,
+        );
+        assert_eq!(count, 10);
+        assert!(replicas
+            .iter()
+            .all(|replica| replica.num_certificates() == 0));
+    }
+
+    fn pbft_jitter_trace(seed: u64) -> Vec<String> {
+        let mut config = config(4, 1);
+        config.seed = seed;
+        let network = Network::new(config, true, |_| Variant::Unreachable);
+        network.set_jitter(Duration::from_micros(100));
+        network.record_trace();
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            pbft::Replica::new(network.register(Host::Replica(index)), index, App::Null)
+        }));
+        let mut client = ClientNode(pbft::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            5,
+            Network::run_until_quiescent,
+        );
+        assert_eq!(count, 5, "seed {seed}");
+        Vec::from_iter(
+            network
+                .take_trace()
+                .into_iter()
+                .map(|event| format!("{:?} {event:?}", event.time)),
+        )
+    }
+
+    #[test]
+    fn pbft_jitter_replay() {
+        assert_eq!(pbft_jitter_trace(1), pbft_jitter_trace(1));
+        assert_ne!(pbft_jitter_trace(1), pbft_jitter_trace(2));
+    }
+
+    #[test]
+    fn unreplicated_sequence_diagram() {
+        let network = Network::new(config(1, 1), true, |_| Variant::Unreachable);
+        let mut replica = unreplicated::Replica::new(network.register(Host::Replica(0)), App::Null);
+        let mut client = ClientNode(unreplicated::Client::new(
+            network.register(Host::Client(0)),
+            0,
+        ));
+        network.record_trace();
+        run_rounds(
+            &network,
+            std::slice::from_mut(&mut replica),
+            std::slice::from_mut(&mut client),
+            1,
+            Network::run_until_quiescent,
+        );
+        let trace = network.take_trace();
+        assert_eq!(
+            sequence_diagram(&trace, |event| event.message.contains("request_num: 1,")),
+            "sequenceDiagram\n    C0->>R0: Request @100µs\n    R0->>C0: Reply @200µs\n"
+        );
+    }
+
+    #[test]
+    fn unreplicated_ordered_quiescent() {
+        let network = Network::new(config(1, 2), true, |index| {
+            Variant::new_half_sip_hash(index, 1.)
+        });
+        let mut replica = unreplicated::Replica::new(network.register(Host::Replica(0)), App::Null);
+        let mut clients = Vec::from_iter((0..2).map(|index| {
+            ClientNode(unreplicated::Client::new_ordered(
+                network.register(Host::Client(index)),
+                index,
+            ))
+        }));
+        let count = run_rounds(
+            &network,
+            std::slice::from_mut(&mut replica),
+            &mut clients,
+            1,
+            Network::run_ordered_multicast_until_quiescent::<_, Request>,
+        );
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn neo_quiescent() {
+        let network = Network::new(config(4, 1), true, |index| {
+            Variant::new_half_sip_hash(index, 1.)
+        });
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            neo::Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+                false,
+            )
+        }));
+        let mut client = ClientNode(neo::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+        );
+        assert_eq!(count, 10);
+
+        let outcome = Arc::new(Mutex::new(None));
+        client.0.invoke(
+            Default::default(),
+            crate::client::OnOutcome({
+                let outcome = outcome.clone();
+                move |value| *outcome.lock().unwrap() = Some(value)
+            }),
+        );
+        deliver(
+            &network,
+            &mut replicas,
+            [(Host::Client(0), &mut client as _)],
+            Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+        );
+        let outcome = outcome.lock().unwrap().take().unwrap();
+        assert_eq!((outcome.op_num, outcome.view_num), (Some(11), Some(0)));
+        assert!(outcome.first_reply.unwrap() <= outcome.latency);
+        let proof = outcome.proof.unwrap();
+        assert!(proof.downcast_ref::<Vec<neo::Reply>>().unwrap().len() >= 3);
+    }
+
+    #[test]
+    fn neo_batching() {
+        let network = Network::new(config(4, 3), true, |_| {
+            Variant::new_ecdsa(Default::default())
+        });
+        network.enable_batching(2, Duration::from_micros(50));
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            neo::Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+                false,
+            )
+        }));
+        let mut clients = Vec::from_iter((0..3).map(|index| {
+            ClientNode(neo::Client::new(
+                network.register(Host::Client(index)),
+                index,
+            ))
+        }));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            &mut clients,
+            10,
+            Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+        );
+        assert_eq!(count, 30);
+        // a full batch of two and the third request flushed on timeout, every round
+        for replica in &replicas {
+            assert_eq!(replica.reorder.ordered_num(), 20)
+        }
+    }
+
+    #[test]
+    fn neo_multicast_groups() {
+        let mut config = config(4, 4);
+        // only counted
+        config.multicast_addrs = vec![([10, 0, 0, 255], 60004).into(); 2];
+        let network = Network::new(config, true, |_| Variant::new_ecdsa(Default::default()));
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            neo::Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+                true,
+            )
+        }));
+        let mut clients = Vec::from_iter((0..4).map(|index| {
+            ClientNode(neo::Client::new(
+                network.register(Host::Client(index)),
+                index,
+            ))
+        }));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            &mut clients,
+            10,
+            Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+        );
+        assert_eq!(count, 40);
+        // each group links its own requests, which are merged without gaps
+        for replica in &replicas {
+            assert_eq!(replica.reorder.ordered_num(), 40);
+            assert!(replica.misbehaviors.is_empty())
+        }
+    }
+
+    #[test]
+    fn neo_idle_multicast_group() {
+        let mut config = config(4, 1);
+        config.multicast_addrs = vec![([10, 0, 0, 255], 60004).into(); 2];
+        let network = Network::new(config, true, |_| Variant::new_ecdsa(Default::default()));
+        // the fills of the idle group pass the batching sequencer without waiting
+        network.enable_batching(2, Duration::from_millis(10));
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            neo::Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+                true,
+            )
+        }));
+        let mut client = ClientNode(neo::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+        );
+        assert_eq!(count, 10);
+        // every request after the first one waits for a fill of the other group
+        for replica in &replicas {
+            assert_eq!(replica.reorder.ordered_num(), 19);
+            assert_eq!(replica.epoch_num, 0);
+            assert!(replica.misbehaviors.is_empty())
+        }
+    }
+
+    #[test]
+    fn neo_checkpoint_stable() {
+        let mut config = config(4, 1);
+        config.checkpoint_interval = 3;
+        let network = Network::new(config, true, |index| Variant::new_half_sip_hash(index, 1.));
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            neo::Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+                true,
+            )
+        }));
+        let mut client = ClientNode(neo::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+        );
+        assert_eq!(count, 10);
+        for replica in &replicas {
+            let op_nums =
+                Vec::from_iter(replica.checkpoints.iter().map(|snapshot| snapshot.op_num));
+            assert_eq!(op_nums, [9]);
+            assert_eq!(replica.stable_op_num, 9);
+            assert!(replica.misbehaviors.is_empty())
+        }
+    }
+
+    #[test]
+    fn neo_standby_promoted() {
+        let network = Network::new(config(4, 4), true, |index| {
+            Variant::new_half_sip_hash(index, 1.)
+        });
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            neo::Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+                false,
+            )
+        }));
+        replicas[3].standby.store(true, SeqCst);
+        replicas[3].standby_host = true;
+        // the standby queries the others for what it misses, or it is stuck once promoted, which
+        // it does once more than the adaptive threshold, at most `QueryTrigger::MAX_THRESHOLD`, is
+        // ordered after
+        network.inject_faults(Faults::default().rule(
+            Link::between(Host::UnkownMulticastSender, Host::Replica(3)),
+            Window::Count(3..6),
+            Fault::Drop,
+        ));
+        let mut clients = Vec::from_iter((0..4).map(|index| {
+            ClientNode(neo::Client::new(
+                network.register(Host::Client(index)),
+                index,
+            ))
+        }));
+        let run = Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>;
+        let mut count = run_rounds(&network, &mut replicas, &mut clients, 260, run);
+        // the standby replies with the results of the requests it executed silently
+        replicas[0].standby.store(true, SeqCst);
+        replicas[3].standby.store(false, SeqCst);
+        count += run_rounds(&network, &mut replicas, &mut clients, 10, run);
+        assert_eq!(count, 1080);
+        assert!(replicas[3].query_trigger.stats.num_query > 0)
+    }
+
+    // a demoted replica keeps ordering silently, and replies again once promoted back
+    #[test]
+    fn neo_standby_demoted() {
+        let network = Network::new(config(4, 1), true, |index| {
+            Variant::new_half_sip_hash(index, 1.)
+        });
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            neo::Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+                false,
+            )
+        }));
+        let mut client = network.register::<neo::Message>(Host::Client(0));
+        let mut inbox = Inbox(Vec::new());
+        let mut num_replies = Vec::new();
+        for request_num in 1..=4 {
+            replicas[0]
+                .standby
+                .store(matches!(request_num, 2 | 3), SeqCst);
+            client.send_ordered_multicast(vec![crate::common::Request {
+                client_index: 0,
+                request_num,
+                op: Default::default(),
+            }]);
+            deliver(
+                &network,
+                &mut replicas,
+                [(Host::Client(0), &mut inbox as _)],
+                Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+            );
+            num_replies.push(inbox.0.drain(..).count())
+        }
+        assert_eq!(num_replies, [4, 3, 3, 4]);
+    }
+
+    // a replica that hears no confirm holds back the ordered multicast past `max_inflight`
+    // unconfirmed ops, and drops it past as many held back, while the others keep going
+    #[test]
+    fn neo_max_inflight() {
+        let mut config = config(4, 1);
+        config.max_inflight = 2;
+        let network = Network::new(config, true, |index| Variant::new_half_sip_hash(index, 1.));
+        let mut faults = Faults::default();
+        for index in 1..4 {
+            faults = faults.rule(
+                Link::between(Host::Replica(index), Host::Replica(0)),
+                Window::Always,
+                Fault::Drop,
+            )
+        }
+        network.inject_faults(faults);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            neo::Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+                true,
+            )
+        }));
+        let mut client = ClientNode(neo::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            8,
+            Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+        );
+        assert_eq!(count, 8);
+        assert_eq!(replicas[0].gauges().log_len, Some(3));
+        for replica in &replicas[1..] {
+            assert_eq!(replica.gauges().log_len, Some(8))
+        }
+    }
+
+    #[test]
+    fn neo_rotating_mac_quiescent() {
+        let network = Network::new(config(8, 1), true, |index| {
+            Variant::new_half_sip_hash(index, 0.)
+                .with_mac_pattern(control_messages::MacPattern::Rotating)
+        });
+        let mut replicas = Vec::from_iter((0..8).map(|index| {
+            neo::Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+                false,
+            )
+        }));
+        let mut client = ClientNode(neo::Client::new(network.register(Host::Client(0)), 0));
+        let count = run_rounds(
+            &network,
+            &mut replicas,
+            std::slice::from_mut(&mut client),
+            10,
+            Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+        );
+        assert_eq!(count, 10);
+        // one packet per message, alternating between the two windows
+        for (variant, _) in network.replicas.values() {
+            let coverage = variant.coverage().unwrap();
+            assert_eq!((coverage.verified, coverage.unverified), (5, 5));
+            assert_eq!(coverage.max_unverified_run, 1)
+        }
+    }
+
+    // stands in for clients, which would time out on the requests that faulty sequencer stalls
+    #[derive(Debug)]
+    struct Inbox<M>(Vec<M>);
+
+    impl<M> Receivers for Inbox<M> {
+        type Message = M;
+
+        fn handle(&mut self, _: Host, _: Host, message: Self::Message) {
+            self.0.push(message)
+        }
+
+        fn on_timer(&mut self, _: Host, _: super::super::TimerId) {
+            unreachable!()
+        }
+    }
+
+    // multicast four requests one by one with `inject` called before each, and return the number
+    // of replies along with the replicas
+    fn neo_faulty_sequencer(
+        variant: impl Fn(ReplicaIndex) -> Variant,
+        confirm: bool,
+        inject: impl Fn(&Network, u32),
+    ) -> (usize, Vec<neo::Replica>) {
+        let network = Network::new(config(4, 1), true, variant);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            neo::Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+                confirm,
+            )
+        }));
+        let mut client = network.register::<neo::Message>(Host::Client(0));
+        let mut inbox = Inbox(Vec::new());
+        for request_num in 1..=4 {
+            inject(&network, request_num);
+            client.send_ordered_multicast(vec![crate::common::Request {
+                client_index: 0,
+                request_num,
+                op: Default::default(),
+            }]);
+            deliver(
+                &network,
+                &mut replicas,
+                [(Host::Client(0), &mut inbox as _)],
+                Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+            );
+        }
+        (inbox.0.len(), replicas)
+    }
+
+    #[test]
+    fn neo_sequencer_skip() {
+        let (num_reply, replicas) = neo_faulty_sequencer(
+            |index| Variant::new_half_sip_hash(index, 1.),
+            false,
+            |network, request_num| match request_num {
+                2 => network.inject_sequencer_fault(0, SequencerFault::Skip(2)),
+                // the next epoch has a sequencer of its own
+                3 => network.restart_sequencer(),
+                _ => {}
+            },
+        );
+        // indistinguishable from a dropped multicast, until no replica fills the gap in time and
+        // they move on to the next epoch, where the requests after the lost one are committed
+        assert_eq!(num_reply, 12);
+        for replica in replicas {
+            assert!(replica.misbehaviors.is_empty());
+            assert_eq!(replica.epoch_num, 1)
+        }
+    }
+
+    // the message after the skipped one is linked to the one before, which proves the skip
+    #[test]
+    fn neo_sequencer_skip_k256() {
+        let (num_reply, replicas) = neo_faulty_sequencer(
+            |_| Variant::new_ecdsa(Default::default()),
+            false,
+            |network, request_num| match request_num {
+                2 => network.inject_sequencer_fault(0, SequencerFault::Skip(2)),
+                3 => network.restart_sequencer(),
+                _ => {}
+            },
+        );
+        assert_eq!(num_reply, 12);
+        for replica in replicas {
+            assert_eq!(replica.misbehaviors, [neo::Misbehavior::Skipped(2)]);
+            assert_eq!(replica.epoch_num, 1)
+        }
+    }
+
+    #[test]
+    fn neo_sequencer_duplicate() {
+        let (num_reply, replicas) = neo_faulty_sequencer(
+            |index| Variant::new_half_sip_hash(index, 1.),
+            false,
+            |network, request_num| match request_num {
+                2 => network.inject_sequencer_fault(0, SequencerFault::Duplicate(2)),
+                4 => network.restart_sequencer(),
+                _ => {}
+            },
+        );
+        // the replicas halt on the third request, and resume with the fourth one in the next epoch
+        assert_eq!(num_reply, 12);
+        for replica in replicas {
+            assert_eq!(replica.misbehaviors, [neo::Misbehavior::Duplicated(2)]);
+            assert_eq!(replica.epoch_num, 1)
+        }
+    }
+
+    #[test]
+    fn neo_sequencer_equivocate() {
+        let (num_reply, replicas) = neo_faulty_sequencer(
+            |index| Variant::new_half_sip_hash(index, 1.),
+            true,
+            |network, request_num| {
+                if request_num == 2 {
+                    network.equivocate(0, 2, vec![0, 1])
+                }
+            },
+        );
+        // without matching confirms from 2f + 1 replicas nothing after the equivocation commits
+        assert_eq!(num_reply, 4);
+        for replica in replicas {
+            assert!(replica
+                .misbehaviors
+                .iter()
+                .any(|misbehavior| matches!(misbehavior, neo::Misbehavior::Diverged(_, op_nums) if op_nums.contains(&2))))
+        }
+    }
+
+    #[test]
+    fn neo_sequencer_equivocate_group() {
+        let mut config = config(4, 2);
+        config.multicast_addrs = vec![([10, 0, 0, 255], 60004).into(); 2];
+        let network = Network::new(config, true, |index| Variant::new_half_sip_hash(index, 1.));
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            neo::Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+                true,
+            )
+        }));
+        // of the second group, whose second message is op 4
+        let mut client = network.register::<neo::Message>(Host::Client(1));
+        let mut inbox = Inbox(Vec::new());
+        for request_num in 1..=4 {
+            if request_num == 2 {
+                network.equivocate(1, 2, vec![0, 1])
+            }
+            client.send_ordered_multicast(vec![crate::common::Request {
+                client_index: 1,
+                request_num,
+                op: Default::default(),
+            }]);
+            deliver(
+                &network,
+                &mut replicas,
+                [(Host::Client(1), &mut inbox as _)],
+                Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+            );
+        }
+        for replica in replicas {
+            assert!(replica
+                .misbehaviors
+                .iter()
+                .any(|misbehavior| matches!(misbehavior, neo::Misbehavior::Diverged(_, op_nums) if op_nums.contains(&4))))
+        }
+    }
+
+    #[test]
+    fn neo_sequencer_break_link() {
+        let (num_reply, replicas) = neo_faulty_sequencer(
+            |_| Variant::new_ecdsa(Default::default()),
+            false,
+            |network, request_num| match request_num {
+                2 => network.inject_sequencer_fault(0, SequencerFault::BreakLink(2)),
+                3 => network.restart_sequencer(),
+                _ => {}
+            },
+        );
+        assert_eq!(num_reply, 12);
+        for replica in replicas {
+            assert_eq!(replica.misbehaviors, [neo::Misbehavior::BrokenLink(2)]);
+            assert_eq!(replica.epoch_num, 1)
+        }
+    }
+
+    #[test]
+    fn neo_sequencer_failover() {
+        for confirm in [false, true] {
+            let (num_reply, replicas) = neo_faulty_sequencer(
+                |index| Variant::new_half_sip_hash(index, 1.),
+                confirm,
+                |network, request_num| match request_num {
+                    2 => network.inject_sequencer_fault(0, SequencerFault::Duplicate(2)),
+                    // the replicas have moved on from the faulty sequencer during the third one
+                    4 => network.restart_sequencer(),
+                    _ => {}
+                },
+            );
+            assert_eq!(num_reply, 12);
+            for replica in replicas {
+                assert_eq!(replica.epoch_num, 1);
+                // the re-sequencer's first request follows the ones of the epoch before
+                assert_eq!(replica.gauges().commit_num, Some(3))
+            }
+        }
+    }
+}
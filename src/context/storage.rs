@@ -0,0 +1,132 @@
+// a write-ahead log on disk, for a replica to rebuild its state after a crash by replaying the
+// records appended before it
+// records are only durable after `sync`, which the protocol calls before it externalizes anything
+// that depends on them, e.g. before replying for a block, so one fsync is shared by the records
+// appended in between
+// a record torn by the crash, i.e. a tail shorter than its length or failing its checksum, is
+// discarded on recovery along with anything after it
+
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use bincode::Options;
+use k256::sha2::{Digest, Sha256};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::Host;
+
+// length and checksum of the payload
+const HEADER_LEN: usize = 4 + 8;
+
+#[derive(Debug)]
+pub struct Storage {
+    file: BufWriter<File>,
+    pub path: PathBuf,
+    pub num_record: u64,
+    pub num_sync: u64,
+}
+
+fn checksum(payload: &[u8]) -> [u8; 8] {
+    Sha256::digest(payload)[..8].try_into().unwrap()
+}
+
+impl Storage {
+    // the log of `host` in `dir`, created if missing, and the records already in it
+    pub fn open<T: DeserializeOwned>(dir: impl AsRef<Path>, host: Host) -> (Self, Vec<T>) {
+        let name = match host {
+            Host::Replica(index) => format!("replica-{index}.wal"),
+            Host::Client(index) => format!("client-{index}.wal"),
+            _ => unimplemented!(),
+        };
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.as_ref().join(name);
+        let mut file = File::options()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .unwrap();
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).unwrap();
+
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while let Some(header) = buf.get(offset..offset + HEADER_LEN) {
+            let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+            let Some(payload) = buf.get(offset + HEADER_LEN..offset + HEADER_LEN + len) else {
+                break;
+            };
+            if header[4..] != checksum(payload) {
+                break;
+            }
+            records.push(bincode::options().deserialize(payload).unwrap());
+            offset += HEADER_LEN + len
+        }
+        if offset != buf.len() {
+            tracing::warn!(
+                len = buf.len() - offset,
+                path = %path.display(),
+                "discard torn record"
+            );
+            file.set_len(offset as _).unwrap();
+            file.sync_data().unwrap()
+        }
+        let storage = Self {
+            file: BufWriter::new(file),
+            path,
+            num_record: records.len() as _,
+            num_sync: 0,
+        };
+        (storage, records)
+    }
+
+    pub fn append(&mut self, record: &impl Serialize) {
+        let payload = bincode::options().serialize(record).unwrap();
+        self.file
+            .write_all(&(payload.len() as u32).to_le_bytes())
+            .unwrap();
+        self.file.write_all(&checksum(&payload)).unwrap();
+        self.file.write_all(&payload).unwrap();
+        self.num_record += 1
+    }
+
+    pub fn sync(&mut self) {
+        self.file.flush().unwrap();
+        self.file.get_ref().sync_data().unwrap();
+        self.num_sync += 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recover_torn_tail() {
+        let dir = std::env::temp_dir().join(format!("storage-{}", std::process::id()));
+        let (mut storage, records) = Storage::open::<String>(&dir, Host::Replica(0));
+        assert!(records.is_empty());
+        storage.append(&"a");
+        storage.append(&"b");
+        storage.sync();
+        let path = storage.path.clone();
+        drop(storage);
+        // a record cut short by the crash
+        let mut file = File::options().append(true).open(&path).unwrap();
+        file.write_all(&[4, 0, 0, 0, 1, 2]).unwrap();
+        drop(file);
+
+        let (mut storage, records) = Storage::open::<String>(&dir, Host::Replica(0));
+        assert_eq!(records, ["a", "b"]);
+        storage.append(&"c");
+        storage.sync();
+        drop(storage);
+        let (_, records) = Storage::open::<String>(&dir, Host::Replica(0));
+        assert_eq!(records, ["a", "b", "c"]);
+        std::fs::remove_dir_all(&dir).unwrap()
+    }
+}
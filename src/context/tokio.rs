@@ -3,28 +3,66 @@
 //! Although supported by an asynchronous reactor, protocol code, i.e.,
 //! `impl Receivers` is still synchronous and running in a separated thread.
 
-use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{IoSlice, IoSliceMut},
+    net::{SocketAddr, SocketAddrV4, SocketAddrV6},
+    os::{
+        fd::{AsFd, AsRawFd},
+        unix::fs::MetadataExt,
+    },
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use bincode::Options;
-use rand::Rng;
+use control_messages::{Log2Histogram, SequencingStats, Utilization};
+use nix::sys::socket::{
+    recvmmsg, recvmsg, sendmmsg, setsockopt, sockopt, ControlMessage, ControlMessageOwned,
+    MsgFlags, MultiHeaders, SockaddrLike, SockaddrStorage, TimestampingFlag, Timestamps,
+};
+use rand::{rngs::StdRng, Rng};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use tokio::{net::UdpSocket, runtime::Handle};
-use tokio_util::{bytes::Bytes, sync::CancellationToken};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, Interest},
+    net::{TcpListener, TcpStream, UdpSocket},
+    runtime::Handle,
+};
+use tokio_util::{
+    bytes::{Bytes, BytesMut},
+    sync::CancellationToken,
+};
 
-use crate::context::crypto::Verifier;
+use crate::context::crypto::{KeyRing, StrictnessSwitch, Verifier};
 
 use super::{
+    byzantine::{Byzantine, Tampered},
+    chaos::{Chaos, Ingress},
+    clock::Timestamp,
     crypto::{DigestHash, Sign, Signer, Verify},
-    ordered_multicast::{OrderedMulticast, Variant},
-    Config, Host, OrderedMulticastReceivers, Receivers, To,
+    fragment::{self, Reassembly},
+    metrics::Metrics,
+    ordered_multicast::{OrderedMulticast, Timing, Variant},
+    shaping::Shaping,
+    trace, Config, Host, OrderedMulticastReceivers, Receivers, SocketConfig, Timestamping, To,
+    Transport,
 };
 
 #[derive(Debug, Clone)]
 enum Event {
-    Message(Host, Host, Vec<u8>),
+    // the instant is when the message is received from socket
+    Message(Host, Host, Bytes, Timestamp),
     LoopbackMessage(Host, Bytes),
-    OrderedMulticastMessage(Host, Vec<u8>),
-    Timer(Host, TimerId),
+    // with the multicast group
+    OrderedMulticastMessage(Host, u8, Bytes, Timestamp),
+    Timer(Host, TimerId, CancellationToken),
+    // a message held back by chaos, which is not subject to it again
+    Delayed(Box<Event>),
     Stop,
 }
 
@@ -39,31 +77,98 @@ pub struct Context {
     timer_tasks: HashMap<TimerId, CancellationToken>,
     event: flume::Sender<Event>,
     rdv_event: flume::Sender<Event>,
+    pub rng: StdRng,
+    fragment_id: Arc<AtomicU64>,
+    // remote address => queue of the writer task of the connection to it, tcp transport only
+    streams: Mutex<HashMap<SocketAddr, flume::Sender<Bytes>>>,
+    cancel: CancellationToken,
+    pub byzantine: Option<Mutex<Byzantine>>,
+    // replicas sign and send the messages to clients on these threads, sharded by client index so
+    // the replies to a client stay in order, see `Config::request_workers`
+    reply_workers: Vec<flume::Sender<Reply>>,
+    // of a replica with `SocketConfig::batch`, see `Outgoing`
+    outgoing: Option<Arc<Outgoing>>,
+}
+
+// signs a reply into its datagrams
+type Reply = Box<dyn FnOnce(&mut Signer) -> Vec<(SocketAddr, Bytes)> + Send>;
+
+// the datagrams that a replica sends while the dispatch has events queued, which go out a batch at
+// a time, and the rest once the dispatch is about to wait, so the replies to the clients of many
+// requests share syscalls as well as the datagrams of one message do
+#[derive(Debug)]
+struct Outgoing {
+    socket: Arc<UdpSocket>,
+    datagrams: Mutex<Vec<(SocketAddr, Bytes)>>,
 }
 
 impl Context {
     pub fn send<M, N>(&self, to: To, message: N)
     where
         M: Sign<N> + Serialize,
+        N: Send + 'static,
     {
+        if let (To::Host(host @ Host::Client(index)), None, false) =
+            (&to, &self.byzantine, self.reply_workers.is_empty())
+        {
+            let (host, addr) = (*host, self.config.hosts[host].addr);
+            let fragment_id = self.fragment_id.clone();
+            // not capturing `M`, which may not outlive the worker
+            let sign: fn(N, &Signer) -> Vec<u8> = |message, signer| {
+                bincode::options()
+                    .serialize(&M::sign(message, signer))
+                    .unwrap()
+            };
+            let reply = move |signer: &mut Signer| {
+                signer.receiver.set(Some(host));
+                let buf = Bytes::from(sign(message, signer));
+                let mut datagrams = Vec::new();
+                split(&fragment_id, addr, buf, &mut datagrams);
+                datagrams
+            };
+            let worker = &self.reply_workers[*index as usize % self.reply_workers.len()];
+            worker.send(Box::new(reply)).unwrap();
+            return;
+        }
+        self.signer.receiver.set(match &to {
+            To::Host(host) => Some(*host),
+            _ => None,
+        });
         let message = M::sign(message, &self.signer);
         let buf = Bytes::from(bincode::options().serialize(&message).unwrap());
+        let tampered = match &self.byzantine {
+            Some(byzantine) => byzantine.lock().unwrap().tamper(&self.signer, &buf),
+            None => Tampered::Genuine,
+        };
+        let mut datagrams = Vec::new();
+        let mut send_to = |host: Host, addr: SocketAddr| {
+            let remote_buf = match &tampered {
+                Tampered::Genuine => buf.clone(),
+                tampered => match tampered.remote(&buf, host) {
+                    Some(remote_buf) => Bytes::copy_from_slice(remote_buf),
+                    None => return,
+                },
+            };
+            self.send_internal(addr, remote_buf, &mut datagrams)
+        };
+        let mut send = |host: Host| send_to(host, self.config.hosts[&host].addr);
         match &to {
-            To::Host(host) => self.send_internal(self.config.hosts[&host].addr, buf.clone()),
-            To::Hosts(hosts) => {
-                for host in hosts {
-                    self.send_internal(self.config.hosts[host].addr, buf.clone())
-                }
-            }
+            To::Host(host) => send(*host),
+            To::Hosts(hosts) => hosts.iter().copied().for_each(send),
             To::AllReplica | To::AllReplicaWithLoopback => {
-                for (&host, host_config) in &self.config.hosts {
+                for &host in self.config.hosts.keys() {
                     if matches!(host, Host::Replica(_)) && host != self.source {
-                        self.send_internal(host_config.addr, buf.clone())
+                        send(host)
                     }
                 }
             }
             To::Loopback => {}
+            To::Standby(index) => match self.config.standbys.get(index) {
+                Some(standby) => send_to(Host::Replica(*index), standby.addr),
+                None => send(Host::Replica(*index)),
+            },
         }
+        self.send_datagrams(datagrams);
         if matches!(to, To::Loopback | To::AllReplicaWithLoopback) {
             self.event
                 .send(Event::LoopbackMessage(self.source, buf))
@@ -71,21 +176,111 @@ impl Context {
         }
     }
 
-    fn send_internal(&self, addr: SocketAddr, buf: impl AsRef<[u8]> + Send + Sync + 'static) {
-        let socket = self.socket.clone();
+    // the datagrams are sent together by `send_datagrams`
+    fn send_internal(
+        &self,
+        addr: SocketAddr,
+        buf: Bytes,
+        datagrams: &mut Vec<(SocketAddr, Bytes)>,
+    ) {
+        if self.config.socket.transport == Transport::Tcp {
+            let mut streams = self.streams.lock().unwrap();
+            let stream = streams.entry(addr).or_insert_with(|| self.connect(addr));
+            // the writer task is gone if the dispatch is dropped
+            let _ = stream.send(buf);
+            return;
+        }
+        split(&self.fragment_id, addr, buf, datagrams)
+    }
+
+    fn send_datagrams(&self, datagrams: Vec<(SocketAddr, Bytes)>) {
+        if let (Some(outgoing), Some(batch)) = (&self.outgoing, self.config.socket.batch) {
+            let mut queued = outgoing.datagrams.lock().unwrap();
+            queued.extend(datagrams);
+            if queued.len() < batch {
+                return;
+            }
+            let datagrams = std::mem::take(&mut *queued);
+            drop(queued);
+            return transmit(&self.socket, &self.runtime, Some(batch), datagrams);
+        }
+        transmit(
+            &self.socket,
+            &self.runtime,
+            self.config.socket.batch,
+            datagrams,
+        )
+    }
+
+    // spawn the writer task that keeps a connection to `addr` and sends the queued messages on it
+    // in order, the message whose write fails is resent on the next connection, so it may be
+    // received twice, which protocols tolerate as for datagrams
+    fn connect(&self, addr: SocketAddr) -> flume::Sender<Bytes> {
+        let (sender, receiver) = flume::unbounded::<Bytes>();
+        // the remote tells the sender by the address it is configured with, not the connection's
+        let hello = bincode::options()
+            .serialize(&self.config.hosts[&self.source].addr)
+            .unwrap();
+        let cancel = self.cancel.clone();
         self.runtime.spawn(async move {
-            socket
-                .send_to(buf.as_ref(), addr)
+            let mut pending = None;
+            cancel
+                .run_until_cancelled(async move {
+                    loop {
+                        let mut stream = match TcpStream::connect(addr).await {
+                            Ok(stream) => stream,
+                            Err(err) => {
+                                tracing::debug!(%addr, %err, "connect");
+                                tokio::time::sleep(RECONNECT_INTERVAL).await;
+                                continue;
+                            }
+                        };
+                        stream.set_nodelay(true).unwrap();
+                        if let Err(err) = write_frame(&mut stream, &hello).await {
+                            tracing::debug!(%addr, %err, "hello");
+                            tokio::time::sleep(RECONNECT_INTERVAL).await;
+                            continue;
+                        }
+                        loop {
+                            let buf = match pending.take() {
+                                Some(buf) => buf,
+                                None => match receiver.recv_async().await {
+                                    Ok(buf) => buf,
+                                    Err(_) => return,
+                                },
+                            };
+                            if let Err(err) = write_frame(&mut stream, &buf).await {
+                                tracing::debug!(%addr, %err, "reconnect");
+                                pending = Some(buf);
+                                tokio::time::sleep(RECONNECT_INTERVAL).await;
+                                break;
+                            }
+                        }
+                    }
+                })
                 .await
-                .unwrap_or_else(|err| panic!("{err} target: {addr:?}"))
         });
+        sender
     }
 
     pub fn send_ordered_multicast(&self, message: impl Serialize + DigestHash) {
-        self.send_internal(
-            self.config.multicast_addr.unwrap(),
-            super::ordered_multicast::serialize(&message),
-        )
+        let group = match self.source {
+            Host::Client(index) => self.config.multicast_group(index),
+            _ => 0,
+        };
+        self.send_ordered_multicast_to(group, message)
+    }
+
+    pub fn send_ordered_multicast_to(&self, group: u8, message: impl Serialize + DigestHash) {
+        let mut buf = super::ordered_multicast::serialize(&message);
+        // only the requests of clients are sequenced for the timing
+        if self.config.sequencing_timing && matches!(self.source, Host::Client(_)) {
+            Timing::append(&mut buf)
+        }
+        self.send_datagrams(vec![(
+            self.config.multicast_addrs[group as usize],
+            buf.into(),
+        )])
     }
 
     pub fn idle_hint(&self) -> bool {
@@ -93,8 +288,136 @@ impl Context {
     }
 }
 
+// the datagrams that carry `buf` to `addr`, see `fragment::split`
+fn split(
+    fragment_id: &AtomicU64,
+    addr: SocketAddr,
+    buf: Bytes,
+    datagrams: &mut Vec<(SocketAddr, Bytes)>,
+) {
+    if let Some(fragments) = fragment::split(fragment_id.fetch_add(1, Relaxed), &buf) {
+        datagrams.extend(
+            fragments
+                .into_iter()
+                .map(|fragment| (addr, fragment.into())),
+        )
+    } else {
+        datagrams.push((addr, buf))
+    }
+}
+
+fn transmit(
+    socket: &Arc<UdpSocket>,
+    runtime: &Handle,
+    batch: Option<usize>,
+    datagrams: Vec<(SocketAddr, Bytes)>,
+) {
+    let Some(batch) = batch else {
+        for (addr, buf) in datagrams {
+            let socket = socket.clone();
+            runtime.spawn(async move {
+                socket
+                    .send_to(&buf, addr)
+                    .await
+                    .unwrap_or_else(|err| panic!("{err} target: {addr:?}"))
+            });
+        }
+        return;
+    };
+    if datagrams.is_empty() {
+        return;
+    }
+    runtime.spawn(send_batches(socket.clone(), batch, datagrams));
+}
+
+async fn send_batches(socket: Arc<UdpSocket>, batch: usize, datagrams: Vec<(SocketAddr, Bytes)>) {
+    for mut chunk in datagrams.chunks(batch) {
+        while !chunk.is_empty() {
+            // sendmmsg may stop early if the send buffer fills up
+            let num_sent = socket
+                .async_io(Interest::WRITABLE, || send_batch(&socket, chunk))
+                .await
+                .unwrap_or_else(|err| panic!("{err} target: {:?}", chunk[0].0));
+            chunk = &chunk[num_sent..]
+        }
+    }
+}
+
+// on the calling thread, which is not one of the runtime's, so the datagrams leave in the order of
+// the calls rather than of the spawned tasks
+fn transmit_blocking(
+    socket: &Arc<UdpSocket>,
+    runtime: &Handle,
+    batch: Option<usize>,
+    datagrams: Vec<(SocketAddr, Bytes)>,
+) {
+    let socket = socket.clone();
+    runtime.block_on(async move {
+        let Some(batch) = batch else {
+            for (addr, buf) in datagrams {
+                socket
+                    .send_to(&buf, addr)
+                    .await
+                    .unwrap_or_else(|err| panic!("{err} target: {addr:?}"));
+            }
+            return;
+        };
+        send_batches(socket, batch, datagrams).await
+    })
+}
+
 pub type TimerId = u32;
 
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(100);
+
+async fn write_frame(stream: &mut TcpStream, buf: &[u8]) -> std::io::Result<()> {
+    let mut frame = Vec::with_capacity(4 + buf.len());
+    frame.extend((buf.len() as u32).to_le_bytes());
+    frame.extend(buf);
+    stream.write_all(&frame).await
+}
+
+// a frame carries a whole message, so it is bounded the same as a reassembled one, before the
+// length that the remote claims is allocated
+async fn read_frame(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let len = stream.read_u32_le().await?;
+    if len as usize > fragment::MAX_MESSAGE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("frame of {len} bytes"),
+        ));
+    }
+    let mut buf = vec![0; len as usize];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+// dispatch the messages on a connection accepted for `receiver` until it is closed
+async fn receive_stream(
+    mut stream: TcpStream,
+    receiver: Host,
+    config: &Config,
+    event: &flume::Sender<Event>,
+) -> std::io::Result<()> {
+    let remote = bincode::options()
+        .deserialize::<SocketAddr>(&read_frame(&mut stream).await?)
+        .map_err(std::io::Error::other)?;
+    let Some(&remote) = config.remotes.get(&remote) else {
+        return Err(std::io::Error::other(format!("unknown remote {remote}")));
+    };
+    loop {
+        let message = read_frame(&mut stream).await?;
+        event
+            .try_send(Event::Message(
+                receiver,
+                remote,
+                message.into(),
+                Timestamp::now(),
+            ))
+            .unwrap()
+    }
+}
+
 impl Context {
     pub fn set(&mut self, duration: Duration) -> TimerId {
         self.timer_id += 1;
@@ -109,16 +432,17 @@ impl Context {
                 if cancel.is_cancelled() {
                     return;
                 }
-                event.send_async(Event::Timer(source, id)).await.unwrap()
+                event
+                    .send_async(Event::Timer(source, id, cancel.clone()))
+                    .await
+                    .unwrap()
             }
         });
         id
     }
 
-    // only works in current thread runtime
-    // in multi-thread runtime, there will always be a chance that the timer task wakes concurrent
-    // to this `unset` call, then this call has no way to prevent false alarm
-    // TODO mutual exclusive between `Receivers` callbacks and timer tasks
+    // the timer task may already be blocking on sending the event when this is called, so the
+    // event carries the token and `Dispatch` discards it if it is cancelled in the meantime
     pub fn unset(&mut self, id: TimerId) {
         self.timer_tasks.remove(&id).unwrap().cancel()
     }
@@ -132,7 +456,39 @@ pub struct Dispatch {
     variant: Arc<Variant>,
     event: (flume::Sender<Event>, flume::Receiver<Event>),
     rdv_event: (flume::Sender<Event>, flume::Receiver<Event>),
+    sockets: Mutex<Vec<Arc<UdpSocket>>>,
+    // of the registered replicas, flushed before waiting for events
+    outgoing: Mutex<Vec<Arc<Outgoing>>>,
+    ingress_delays: Mutex<(Log2Histogram, Log2Histogram)>,
+    utilization: Mutex<Utilization>,
+    sequencing: Mutex<SequencingStats>,
+    spoofed_multicast: Arc<AtomicU64>,
+    invalid_multicast: AtomicU64,
+    stale: AtomicU64,
+    socket_delay: Arc<Mutex<Log2Histogram>>,
+    // stops the socket tasks, so the sockets are closed once dropped
+    cancel: CancellationToken,
     pub drop_rate: f64,
+    pub chaos: Arc<Chaos>,
+    pub shaping: Shaping,
+    pub metrics: Arc<Metrics>,
+    // record the events handed to the receivers into this file while running, see `trace`
+    pub trace: Option<PathBuf>,
+    // the pool that the request workers run on, which must have a thread for each of them, or one
+    // of its own for each run that is shut down after it, see `executor`
+    pub executor: Option<Arc<rayon::ThreadPool>>,
+}
+
+// a thread for each of `num_thread` request workers, see `Config::request_workers`, which hold on
+// to theirs for the whole run, so the pool can be shared by the runs one after another only
+pub fn executor(num_thread: usize) -> Arc<rayon::ThreadPool> {
+    Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(num_thread)
+            .thread_name(|index| format!("request-worker-{index}"))
+            .build()
+            .unwrap(),
+    )
 }
 
 impl Dispatch {
@@ -143,6 +499,10 @@ impl Dispatch {
         variant: impl Into<Arc<Variant>>,
     ) -> Self {
         let config = config.into();
+        assert!(config
+            .socket
+            .batch
+            .is_none_or(|batch| (1..=MAX_BATCH).contains(&batch)));
         let variant = variant.into();
         let verifier = if verify {
             Verifier::new_standard(&config, variant.clone())
@@ -156,70 +516,380 @@ impl Dispatch {
             variant,
             event: flume::unbounded(),
             rdv_event: flume::bounded(0),
+            sockets: Default::default(),
+            outgoing: Default::default(),
+            ingress_delays: Default::default(),
+            utilization: Default::default(),
+            sequencing: Default::default(),
+            spoofed_multicast: Default::default(),
+            invalid_multicast: Default::default(),
+            stale: Default::default(),
+            socket_delay: Default::default(),
+            cancel: Default::default(),
             drop_rate: 0.,
+            chaos: Default::default(),
+            shaping: Default::default(),
+            metrics: Default::default(),
+            trace: None,
+            executor: None,
+        }
+    }
+
+    fn init_socket(&self, socket: UdpSocket) -> Arc<UdpSocket> {
+        let SocketConfig {
+            rcvbuf,
+            sndbuf,
+            busy_poll,
+            tos,
+            timestamping,
+            transport: _,
+            batch: _,
+        } = self.config.socket;
+        if let Some(rcvbuf) = rcvbuf {
+            setsockopt(&socket, sockopt::RcvBuf, &rcvbuf).unwrap()
+        }
+        if let Some(sndbuf) = sndbuf {
+            setsockopt(&socket, sockopt::SndBuf, &sndbuf).unwrap()
+        }
+        if let Some(tos) = tos {
+            setsockopt(&socket, sockopt::IpTos, &(tos as _)).unwrap()
+        }
+        if let Some(busy_poll) = busy_poll {
+            // nix does not wrap SO_BUSY_POLL
+            let busy_poll = busy_poll as nix::libc::c_int;
+            // SAFETY: the fd is owned by `socket` and open for the call, and the option value
+            // points to a live `c_int` whose size is passed along, which is what the kernel reads
+            // for SO_BUSY_POLL
+            let result = unsafe {
+                nix::libc::setsockopt(
+                    socket.as_raw_fd(),
+                    nix::libc::SOL_SOCKET,
+                    nix::libc::SO_BUSY_POLL,
+                    &busy_poll as *const _ as *const _,
+                    std::mem::size_of_val(&busy_poll) as _,
+                )
+            };
+            nix::errno::Errno::result(result).unwrap();
+        }
+        if let Some(timestamping) = timestamping {
+            let flags = match timestamping {
+                Timestamping::Software => {
+                    TimestampingFlag::SOF_TIMESTAMPING_RX_SOFTWARE
+                        | TimestampingFlag::SOF_TIMESTAMPING_SOFTWARE
+                }
+                Timestamping::Hardware => {
+                    TimestampingFlag::SOF_TIMESTAMPING_RX_HARDWARE
+                        | TimestampingFlag::SOF_TIMESTAMPING_RAW_HARDWARE
+                }
+            };
+            setsockopt(&socket, sockopt::Timestamping, &flags).unwrap()
+        }
+        let socket = Arc::new(socket);
+        self.sockets.lock().unwrap().push(socket.clone());
+        socket
+    }
+
+    // packets dropped by kernel on the sockets of this dispatch so far, including the ones dropped
+    // because of receive buffer overflow
+    pub fn socket_drops(&self) -> u64 {
+        let Ok(table) = std::fs::read_to_string("/proc/net/udp") else {
+            return 0;
+        };
+        let drops = HashMap::<u64, u64>::from_iter(table.lines().skip(1).filter_map(|line| {
+            let fields = Vec::from_iter(line.split_whitespace());
+            Some((fields.get(9)?.parse().ok()?, fields.get(12)?.parse().ok()?))
+        }));
+        self.sockets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|socket| {
+                let fd = socket.as_fd().as_raw_fd();
+                let inode = std::fs::metadata(format!("/proc/self/fd/{fd}")).ok()?.ino();
+                drops.get(&inode).copied()
+            })
+            .sum()
+    }
+
+    fn flush_outgoing(&self) {
+        for outgoing in &*self.outgoing.lock().unwrap() {
+            let datagrams = std::mem::take(&mut *outgoing.datagrams.lock().unwrap());
+            transmit(
+                &outgoing.socket,
+                &self.runtime,
+                self.config.socket.batch,
+                datagrams,
+            )
         }
     }
 
     pub fn register<M>(&self, receiver: Host) -> super::Context<M> {
-        let socket = Arc::new(
+        let socket = self.init_socket(
             self.runtime
                 .block_on(UdpSocket::bind(self.config.hosts[&receiver].addr))
-                .unwrap_or_else(|_| panic!("binding {:?}", self.config.hosts[&receiver].addr)),
+                .unwrap_or_else(|err| {
+                    panic!("binding {:?}: {err}", self.config.hosts[&receiver].addr)
+                }),
         );
         socket.set_broadcast(true).unwrap();
+        let mut signer = Signer::new(&self.config, receiver);
+        if let Some(key_ring) = self.key_ring() {
+            signer.key_ring = key_ring
+        }
+        let num_reply_worker = match receiver {
+            Host::Replica(_) if self.config.socket.transport == Transport::Udp => {
+                self.config.request_workers
+            }
+            _ => 0,
+        };
+        let reply_workers = Vec::from_iter((0..num_reply_worker).map(|_| {
+            let (sender, replies) = flume::bounded::<Reply>(MAX_QUEUED);
+            let mut signer = signer.clone();
+            let (socket, runtime) = (socket.clone(), self.runtime.clone());
+            let batch = self.config.socket.batch;
+            // until the context is dropped
+            std::thread::spawn(move || {
+                while let Ok(reply) = replies.recv() {
+                    let mut datagrams = reply(&mut signer);
+                    // the replies queued meanwhile go out in the same batch
+                    while datagrams.len() < batch.unwrap_or(1) {
+                        let Ok(reply) = replies.try_recv() else {
+                            break;
+                        };
+                        datagrams.extend(reply(&mut signer))
+                    }
+                    transmit_blocking(&socket, &runtime, batch, datagrams)
+                }
+            });
+            sender
+        }));
+        let outgoing = match (receiver, self.config.socket.batch) {
+            (Host::Replica(_), Some(_)) if self.config.socket.transport == Transport::Udp => {
+                let outgoing = Arc::new(Outgoing {
+                    socket: socket.clone(),
+                    datagrams: Default::default(),
+                });
+                self.outgoing.lock().unwrap().push(outgoing.clone());
+                Some(outgoing)
+            }
+            _ => None,
+        };
         let context = Context {
             config: self.config.clone(),
             socket: socket.clone(),
             runtime: self.runtime.clone(),
             source: receiver,
-            signer: Signer {
-                signing_key: self.config.hosts[&receiver].signing_key.clone(),
-                hmac: self.config.hmac.clone(),
-            },
+            signer,
             timer_id: Default::default(),
             event: self.event.0.clone(),
             rdv_event: self.rdv_event.0.clone(),
             timer_tasks: Default::default(),
+            rng: self.config.rng(receiver),
+            fragment_id: Default::default(),
+            streams: Default::default(),
+            cancel: self.cancel.clone(),
+            byzantine: None,
+            reply_workers,
+            outgoing,
         };
+        if self.config.socket.transport == Transport::Tcp {
+            self.listen(receiver)
+        }
         let event = self.event.0.clone();
         let config = self.config.clone();
+        let socket_delay = self.socket_delay.clone();
+        let cancel = self.cancel.clone();
         self.runtime.spawn(async move {
-            let mut buf = vec![0; 65536];
+            let mut pool = RecvPool::new();
+            let mut received = Vec::new();
+            let mut reassembly = Reassembly::default();
             loop {
-                let (len, remote) = socket.recv_from(&mut buf).await.unwrap();
-                event
-                    .try_send(Event::Message(
-                        receiver,
-                        config.remotes[&remote],
-                        buf[..len].to_vec(),
+                let Some(result) = cancel
+                    .run_until_cancelled(recv_batch(
+                        &socket,
+                        &mut pool,
+                        &mut received,
+                        &config,
+                        &socket_delay,
                     ))
-                    .unwrap()
+                    .await
+                else {
+                    break;
+                };
+                result.unwrap();
+                for (buf, remote) in received.drain(..) {
+                    let Some(&host) = config.remotes.get(&remote) else {
+                        tracing::debug!(%remote, "unknown remote");
+                        continue;
+                    };
+                    let Some(message) = reassembly.receive(remote, buf, Instant::now()) else {
+                        continue;
+                    };
+                    event
+                        .try_send(Event::Message(receiver, host, message, Timestamp::now()))
+                        .unwrap()
+                }
+            }
+        });
+        super::Context::Tokio(Box::new(context))
+    }
+}
+
+impl Dispatch {
+    // accept the connections that the other hosts send messages to `receiver` through
+    fn listen(&self, receiver: Host) {
+        let addr = self.config.hosts[&receiver].addr;
+        let listener = self
+            .runtime
+            .block_on(TcpListener::bind(addr))
+            .unwrap_or_else(|err| panic!("listening {addr:?}: {err}"));
+        let event = self.event.0.clone();
+        let config = self.config.clone();
+        let cancel = self.cancel.clone();
+        let runtime = self.runtime.clone();
+        self.runtime.spawn(async move {
+            while let Some(result) = cancel.run_until_cancelled(listener.accept()).await {
+                let (stream, _) = result.unwrap();
+                stream.set_nodelay(true).unwrap();
+                let (event, config, cancel) = (event.clone(), config.clone(), cancel.clone());
+                runtime.spawn(async move {
+                    let result = cancel
+                        .run_until_cancelled(receive_stream(stream, receiver, &config, &event))
+                        .await;
+                    if let Some(Err(err)) = result {
+                        tracing::debug!(?receiver, %err, "connection closed")
+                    }
+                });
             }
         });
-        super::Context::Tokio(context)
     }
 }
 
+// the most events and requests that wait for the dispatch, beyond which the receivers are
+// overwhelmed
+const MAX_QUEUED: usize = 4096;
+
+// a client request from a worker, with the raw message if it is traced
+type Verified<M> = (
+    Host,
+    Host,
+    std::result::Result<M, String>,
+    Timestamp,
+    Option<Bytes>,
+);
+
+fn deserialize<M: DeserializeOwned>(buf: &[u8]) -> M {
+    bincode::options()
+        .allow_trailing_bytes()
+        .deserialize(buf)
+        .unwrap()
+}
+
 impl Dispatch {
     fn run_internal<R, M, N>(&self, receivers: &mut R, into: impl Fn(OrderedMulticast<N>) -> M)
     where
         R: Receivers<Message = M>,
-        M: DeserializeOwned + Verify,
+        M: DeserializeOwned + Verify + Send + 'static,
         N: DeserializeOwned + DigestHash,
     {
-        let deserialize = |buf: &_| {
-            bincode::options()
-                .allow_trailing_bytes()
-                .deserialize::<M>(buf)
-                .unwrap()
-        };
-        let mut delegate = self.variant.delegate();
+        // client requests are deserialized and verified by `Config::request_workers` workers on
+        // the threads of the executor, sharded by client index so that the requests of a client
+        // stay in order, and merged back to be handled here along with the other events
+        // the raw message is kept along for the trace if it is recorded
+        // a request that fails either is handed back as the error, so it fails the dispatch as it
+        // does when verified here, instead of only the worker
+        // the queues are bounded as the event one is, which workers block on only for the verified
+        // requests that are handled here
+        let verified = flume::bounded::<Verified<M>>(MAX_QUEUED);
+        let executor = Some(self.config.request_workers)
+            .filter(|&num_worker| num_worker != 0)
+            .map(|num_worker| {
+                let executor = self
+                    .executor
+                    .clone()
+                    .unwrap_or_else(|| executor(num_worker));
+                assert!(executor.current_num_threads() >= num_worker);
+                executor
+            });
+        // every worker drops its end on returning, so the run waits for the others to close
+        let (running, stopped) = flume::bounded::<()>(0);
+        let workers = Vec::from_iter((0..self.config.request_workers).map(|_| {
+            let (sender, requests) = flume::bounded::<(Host, Host, Bytes, Timestamp)>(MAX_QUEUED);
+            let verified = verified.0.clone();
+            let (verifier, config) = (self.verifier.clone(), self.config.clone());
+            let keep_raw = self.trace.is_some();
+            let running = running.clone();
+            executor.as_ref().unwrap().spawn(move || {
+                let _running = running;
+                let mut verifiers = HashMap::new();
+                while let Ok((receiver, remote, buf, ingress)) = requests.recv() {
+                    let message = bincode::options()
+                        .allow_trailing_bytes()
+                        .deserialize::<M>(&buf)
+                        .map_err(|err| err.to_string())
+                        .and_then(|message| {
+                            let verifier = verifiers
+                                .entry(receiver)
+                                .or_insert_with(|| verifier.receiving(&config, receiver));
+                            message.verify(verifier).map_err(|err| format!("{err:?}"))?;
+                            Ok(message)
+                        });
+                    let raw = Some(buf).filter(|_| keep_raw);
+                    // the dispatch stops
+                    if verified
+                        .send((receiver, remote, message, ingress, raw))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+            sender
+        }));
+        drop(running);
+        enum Selected<M> {
+            Event(Event),
+            Verified(Verified<M>),
+        }
+        let mut trace = self.trace.as_ref().and_then(|path| {
+            trace::Writer::create(path)
+                .inspect_err(|err| tracing::warn!(%err, path = %path.display(), "trace"))
+                .ok()
+        });
+        // one per multicast group, as the ECDSA delegate holds back the latest of the stream
+        let mut delegates =
+            Vec::from_iter((0..self.config.num_multicast_group()).map(|_| self.variant.delegate()));
+        let mut drop_rng = self.config.rng("drop");
         let mut pace_count = 1;
+        let mut queueing_delay = Log2Histogram::default();
+        let mut processing_delay = Log2Histogram::default();
+        let mut utilization = Utilization::default();
+        let mut sequencing = SequencingStats::default();
+        let received = self.metrics.received::<M>();
+        // per receiving host, for the MACs between clients and replicas, see `Verifier::receiving`
+        let mut verifiers = HashMap::new();
+        // the event handled in the previous iteration, which may end in any of the `continue`s
+        let mut busy_start = Instant::now();
         loop {
+            utilization.busy += busy_start.elapsed();
             if pace_count == 0 {
                 // println!("* pace");
-                delegate.on_pace(receivers, &self.verifier, &into);
+                let start = Instant::now();
+                for delegate in &mut delegates {
+                    if delegate.on_pace(receivers, &self.verifier, &into).is_err() {
+                        self.invalid_multicast.fetch_add(1, Relaxed);
+                    }
+                }
                 receivers.on_pace();
+                if let Some(trace) = &mut trace {
+                    trace.record(trace::Event::Pace)
+                }
+                // the client requests queued on the workers are not verified yet either
+                let verify_queue_depth =
+                    self.event.0.len() + workers.iter().map(flume::Sender::len).sum::<usize>();
+                self.metrics.publish(verify_queue_depth, receivers.gauges());
+                utilization.pacing += start.elapsed();
+                utilization.num_pace += 1;
                 pace_count = if self.event.0.is_empty() {
                     1
                 } else {
@@ -228,49 +898,265 @@ impl Dispatch {
                 // println!("* pace count {pace_count}");
             }
 
-            assert!(self.event.1.len() < 4096, "receivers overwhelmed");
-            let event = flume::Selector::new()
-                .recv(&self.event.1, Result::unwrap)
-                .recv(&self.rdv_event.1, Result::unwrap)
+            assert!(self.event.1.len() < MAX_QUEUED, "receivers overwhelmed");
+            if self.event.1.is_empty() && verified.1.is_empty() {
+                self.flush_outgoing()
+            }
+            let wait_start = Instant::now();
+            let selected = flume::Selector::new()
+                .recv(&self.event.1, |event| Selected::Event(event.unwrap()))
+                .recv(&self.rdv_event.1, |event| Selected::Event(event.unwrap()))
+                .recv(&verified.1, |request| Selected::Verified(request.unwrap()))
                 .wait();
+            busy_start = Instant::now();
+            utilization.idle += busy_start - wait_start;
+            utilization.num_event += 1;
+            let event = match selected {
+                Selected::Event(event) => event,
+                Selected::Verified((receiver, remote, message, ingress, raw)) => {
+                    let message = message.unwrap_or_else(|err| panic!("{remote:?}: {err}"));
+                    let start = Timestamp::now();
+                    queueing_delay.record(start - ingress);
+                    // after verification, unlike the requests handled here
+                    if self.config.reject_stale && receivers.is_stale(remote, &message) {
+                        self.stale.fetch_add(1, Relaxed);
+                        continue;
+                    }
+                    if let (Some(trace), Some(raw)) = (&mut trace, raw) {
+                        trace.record(trace::Event::Message(receiver, remote, raw.to_vec()))
+                    }
+                    receivers.handle(receiver, remote, message);
+                    processing_delay.record(start.elapsed());
+                    continue;
+                }
+            };
+            let (event, delayed) = match event {
+                Event::Delayed(event) => (*event, true),
+                event => (event, false),
+            };
             match event {
                 Event::Stop => break,
-                Event::Message(receiver, remote, message) => {
+                Event::Message(receiver, remote, message, ingress) => {
                     pace_count -= 1;
-                    if self.drop_rate != 0. && rand::thread_rng().gen_bool(self.drop_rate) {
+                    if self.drop_rate != 0. && drop_rng.gen_bool(self.drop_rate) {
                         continue;
                     }
-                    let message = deserialize(&message);
-                    message.verify(&self.verifier).unwrap();
-                    receivers.handle(receiver, remote, message)
+                    if !delayed {
+                        // chaos drops it before it takes up the shaped link
+                        let fate = self
+                            .chaos
+                            .ingress(receiver, remote, &mut drop_rng)
+                            .and_then(|| {
+                                self.shaping.ingress(
+                                    remote,
+                                    message.len(),
+                                    Instant::now(),
+                                    &mut drop_rng,
+                                )
+                            });
+                        match fate {
+                            Ingress::Deliver => {}
+                            Ingress::Drop => continue,
+                            Ingress::Delay(delay) => {
+                                self.delay(
+                                    delay,
+                                    Event::Message(receiver, remote, message, ingress),
+                                );
+                                continue;
+                            }
+                        }
+                    }
+                    received.record(&message);
+                    if let (Host::Client(index), false) = (remote, workers.is_empty()) {
+                        let worker = &workers[index as usize % workers.len()];
+                        match worker.try_send((receiver, remote, message, ingress)) {
+                            Ok(()) => {}
+                            Err(flume::TrySendError::Full(_)) => panic!("receivers overwhelmed"),
+                            Err(flume::TrySendError::Disconnected(_)) => unreachable!(),
+                        }
+                        continue;
+                    }
+                    let start = Timestamp::now();
+                    queueing_delay.record(start - ingress);
+                    let buf = message;
+                    let message = deserialize(&buf);
+                    if self.config.reject_stale && receivers.is_stale(remote, &message) {
+                        self.stale.fetch_add(1, Relaxed);
+                        continue;
+                    }
+                    let verifier = verifiers
+                        .entry(receiver)
+                        .or_insert_with(|| self.verifier.receiving(&self.config, receiver));
+                    message.verify(verifier).unwrap();
+                    if let Some(trace) = &mut trace {
+                        trace.record(trace::Event::Message(receiver, remote, buf.to_vec()))
+                    }
+                    receivers.handle(receiver, remote, message);
+                    processing_delay.record(start.elapsed())
                 }
                 Event::LoopbackMessage(receiver, message) => {
                     pace_count -= 1;
+                    if let Some(trace) = &mut trace {
+                        trace.record(trace::Event::Loopback(receiver, message.to_vec()))
+                    }
                     receivers.handle_loopback(receiver, deserialize(&message))
                 }
-                Event::OrderedMulticastMessage(remote, message) => {
+                Event::OrderedMulticastMessage(remote, group, message, ingress) => {
                     pace_count -= 1;
-                    if self.drop_rate != 0. && rand::thread_rng().gen_bool(self.drop_rate) {
+                    if self.drop_rate != 0. && drop_rng.gen_bool(self.drop_rate) {
                         continue;
                     }
-                    delegate.on_receive(
+                    if !delayed {
+                        // chaos drops it before it takes up the shaped link
+                        let fate = self
+                            .chaos
+                            .ingress(Host::Multicast, remote, &mut drop_rng)
+                            .and_then(|| {
+                                self.shaping.ingress(
+                                    Host::Multicast,
+                                    message.len(),
+                                    Instant::now(),
+                                    &mut drop_rng,
+                                )
+                            });
+                        match fate {
+                            Ingress::Deliver => {}
+                            Ingress::Drop => continue,
+                            Ingress::Delay(delay) => {
+                                self.delay(
+                                    delay,
+                                    Event::OrderedMulticastMessage(remote, group, message, ingress),
+                                );
+                                continue;
+                            }
+                        }
+                    }
+                    let start = Timestamp::now();
+                    queueing_delay.record(start - ingress);
+                    received.record_ordered_multicast();
+                    if let Some(trace) = &mut trace {
+                        trace.record(trace::Event::OrderedMulticast(
+                            remote,
+                            group,
+                            message.to_vec(),
+                        ))
+                    }
+                    if self.config.sequencing_timing {
+                        if let Some(timing) = Timing::parse(&message) {
+                            let received = Timing::now() - (start - ingress).as_nanos() as u64;
+                            sequencing.record(
+                                timing.client_send,
+                                timing.sequencer_receive,
+                                timing.sequencer_send,
+                                received,
+                            )
+                        }
+                    }
+                    let mut message = self.variant.deserialize(message);
+                    message.group = group;
+                    let result = delegates[group as usize].on_receive(
                         remote,
-                        self.variant.deserialize(message),
+                        message,
                         receivers,
                         &self.verifier,
                         &into,
-                    )
+                    );
+                    if result.is_err() {
+                        self.invalid_multicast.fetch_add(1, Relaxed);
+                    }
+                    processing_delay.record(start.elapsed())
                 }
-                Event::Timer(receiver, id) => {
-                    receivers.on_timer(receiver, super::TimerId::Tokio(id))
+                Event::Timer(receiver, id, cancel) => {
+                    if !cancel.is_cancelled() {
+                        if let Some(trace) = &mut trace {
+                            trace.record(trace::Event::Timer(receiver, id))
+                        }
+                        receivers.on_timer(receiver, super::TimerId::Tokio(id))
+                    }
                 }
+                Event::Delayed(_) => unreachable!(),
+            }
+        }
+        // the workers may be blocked on the verified queue
+        drop(verified);
+        drop(workers);
+        let _ = stopped.recv();
+        let ingress_delays = &mut *self.ingress_delays.lock().unwrap();
+        ingress_delays.0.merge(&queueing_delay);
+        ingress_delays.1.merge(&processing_delay);
+        self.utilization.lock().unwrap().merge(&utilization);
+        self.sequencing.lock().unwrap().merge(&sequencing)
+    }
+
+    fn delay(&self, delay: Duration, mut event: Event) {
+        let sender = self.event.0.clone();
+        self.runtime.spawn(async move {
+            tokio::time::sleep(delay).await;
+            // received again, so the queueing delay does not include the time held back
+            if let Event::Message(.., ingress) | Event::OrderedMulticastMessage(.., ingress) =
+                &mut event
+            {
+                *ingress = Timestamp::now()
             }
+            // the dispatch may have stopped meanwhile
+            let _ = sender.send_async(Event::Delayed(Box::new(event))).await;
+        });
+    }
+
+    // ordered multicast rejected for coming from an unexpected source, see `Config::multicast_sources`
+    pub fn spoofed_multicast(&self) -> u64 {
+        self.spoofed_multicast.load(Relaxed)
+    }
+
+    // ordered multicast dropped for failing verification
+    pub fn invalid_multicast(&self) -> u64 {
+        self.invalid_multicast.load(Relaxed)
+    }
+
+    pub fn stale(&self) -> u64 {
+        self.stale.load(Relaxed)
+    }
+
+    // none if verification is disabled
+    pub fn strictness(&self) -> Option<StrictnessSwitch> {
+        self.verifier.strictness()
+    }
+
+    // shared by the signers of the registered hosts, none if verification is disabled
+    pub fn key_ring(&self) -> Option<KeyRing> {
+        self.verifier.key_ring()
+    }
+
+    // none if timestamping is disabled
+    pub fn socket_delay(&self) -> Option<Log2Histogram> {
+        self.config
+            .socket
+            .timestamping
+            .map(|_| self.socket_delay.lock().unwrap().clone())
+    }
+
+    // queueing and processing delays of received messages, recorded when `run` returns
+    pub fn ingress_delays(&self) -> (Log2Histogram, Log2Histogram) {
+        self.ingress_delays.lock().unwrap().clone()
+    }
+
+    // none unless `Config::sequencing_timing`, recorded when `run` returns
+    pub fn sequencing(&self) -> Option<SequencingStats> {
+        if self.config.sequencing_timing {
+            Some(self.sequencing.lock().unwrap().clone())
+        } else {
+            None
         }
     }
 
+    // recorded when `run` returns
+    pub fn utilization(&self) -> Utilization {
+        *self.utilization.lock().unwrap()
+    }
+
     pub fn run<M>(&self, receivers: &mut impl Receivers<Message = M>)
     where
-        M: DeserializeOwned + Verify,
+        M: DeserializeOwned + Verify + Send + 'static,
     {
         #[derive(Deserialize)]
         enum O {}
@@ -281,6 +1167,258 @@ impl Dispatch {
         }
         self.run_internal::<_, _, O>(receivers, |_| unimplemented!())
     }
+
+    // hand the events of a trace to `receivers` in order, as `run` did when recording it, with
+    // neither sockets nor timers involved, so the receivers go through the same steps as long as
+    // they are set up the same and are deterministic themselves
+    // the messages are verified again if the dispatch verifies, and the timer ids match the ones
+    // the receivers get from contexts registered on this dispatch, as they set timers in the same
+    // order
+    fn replay_internal<R, M, N>(
+        &self,
+        trace: impl IntoIterator<Item = trace::Entry>,
+        receivers: &mut R,
+        into: impl Fn(OrderedMulticast<N>) -> M,
+    ) where
+        R: Receivers<Message = M>,
+        M: DeserializeOwned + Verify,
+        N: DeserializeOwned + DigestHash,
+    {
+        let mut delegates =
+            Vec::from_iter((0..self.config.num_multicast_group()).map(|_| self.variant.delegate()));
+        let mut verifiers = HashMap::new();
+        for entry in trace {
+            match entry.event {
+                trace::Event::Message(receiver, remote, message) => {
+                    let message = deserialize::<M>(&message);
+                    let verifier = verifiers
+                        .entry(receiver)
+                        .or_insert_with(|| self.verifier.receiving(&self.config, receiver));
+                    message.verify(verifier).unwrap();
+                    receivers.handle(receiver, remote, message)
+                }
+                trace::Event::Loopback(receiver, message) => {
+                    receivers.handle_loopback(receiver, deserialize(&message))
+                }
+                trace::Event::OrderedMulticast(remote, group, message) => {
+                    let mut message = self.variant.deserialize(message);
+                    message.group = group;
+                    // dropped, as they were when recorded
+                    let _ = delegates[group as usize].on_receive(
+                        remote,
+                        message,
+                        receivers,
+                        &self.verifier,
+                        &into,
+                    );
+                }
+                trace::Event::Timer(receiver, id) => {
+                    receivers.on_timer(receiver, super::TimerId::Tokio(id))
+                }
+                trace::Event::Pace => {
+                    for delegate in &mut delegates {
+                        let _ = delegate.on_pace(receivers, &self.verifier, &into);
+                    }
+                    receivers.on_pace()
+                }
+            }
+        }
+    }
+
+    pub fn replay<M>(
+        &self,
+        trace: impl IntoIterator<Item = trace::Entry>,
+        receivers: &mut impl Receivers<Message = M>,
+    ) where
+        M: DeserializeOwned + Verify,
+    {
+        #[derive(Deserialize)]
+        enum O {}
+        impl DigestHash for O {
+            fn hash(&self, _: &mut impl std::hash::Hasher) {
+                unreachable!()
+            }
+        }
+        self.replay_internal::<_, _, O>(trace, receivers, |_| unimplemented!())
+    }
+}
+
+// `recv_from`, and record the delay since the packet is timestamped if timestamping is enabled
+async fn recv(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    config: &Config,
+    socket_delay: &Mutex<Log2Histogram>,
+) -> std::io::Result<(usize, SocketAddr)> {
+    let Some(timestamping) = config.socket.timestamping else {
+        return socket.recv_from(buf).await;
+    };
+    let (len, remote, timestamps) = socket
+        .async_io(Interest::READABLE, || {
+            let mut cmsg = nix::cmsg_space!(Timestamps);
+            let mut iov = [IoSliceMut::new(buf)];
+            let message = recvmsg::<SockaddrStorage>(
+                socket.as_raw_fd(),
+                &mut iov,
+                Some(&mut cmsg),
+                MsgFlags::empty(),
+            )?;
+            let timestamps = message.cmsgs().find_map(|cmsg| match cmsg {
+                ControlMessageOwned::ScmTimestampsns(timestamps) => Some(timestamps),
+                _ => None,
+            });
+            Ok((message.bytes, message.address, timestamps))
+        })
+        .await?;
+    let remote = socket_addr(remote.unwrap());
+    if let Some(timestamps) = timestamps {
+        let timestamp = match timestamping {
+            Timestamping::Software => timestamps.system,
+            Timestamping::Hardware => timestamps.hw_raw,
+        };
+        // zero if the NIC does not timestamp this packet, and the first packets after enabling may
+        // not be timestamped at all, as kernel enables timestamping asynchronously
+        if Duration::from(timestamp) != Duration::ZERO {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            socket_delay
+                .lock()
+                .unwrap()
+                .record(now.saturating_sub(timestamp.into()))
+        }
+    }
+    Ok((len, remote))
+}
+
+fn socket_addr(addr: SockaddrStorage) -> SocketAddr {
+    if let Some(addr) = addr.as_sockaddr_in() {
+        SocketAddr::from(SocketAddrV4::from(*addr))
+    } else if let Some(addr) = addr.as_sockaddr_in6() {
+        SocketAddr::from(SocketAddrV6::from(*addr))
+    } else {
+        unreachable!("{:?}", addr.family())
+    }
+}
+
+// the largest UDP datagram
+const MAX_DATAGRAM: usize = 65536;
+
+// received datagrams are carved out of a large chunk, which is reused once every message received
+// into it is dropped, so receiving neither allocates nor copies per datagram
+#[derive(Debug)]
+struct RecvPool(BytesMut);
+
+impl RecvPool {
+    const CHUNK_LEN: usize = 4 << 20;
+
+    fn new() -> Self {
+        Self(BytesMut::zeroed(Self::CHUNK_LEN))
+    }
+
+    // at least `len` bytes to receive into, the front of which is taken afterward
+    fn reserve(&mut self, len: usize) -> &mut [u8] {
+        if self.0.len() < len {
+            let chunk_len = Self::CHUNK_LEN.max(len);
+            self.0.clear();
+            if self.0.try_reclaim(chunk_len) {
+                // SAFETY: the reclaimed capacity is either the rest of the current chunk, or the
+                // whole chunk rewound to its start, and every chunk is zeroed on allocation
+                unsafe { self.0.set_len(chunk_len) }
+            } else {
+                self.0 = BytesMut::zeroed(chunk_len)
+            }
+        }
+        &mut self.0[..len]
+    }
+
+    fn take(&mut self, len: usize) -> Bytes {
+        self.0.split_to(len).freeze()
+    }
+}
+
+// receive into `pool` with the remote of each datagram in `received`
+// one datagram at a time through `recv` unless `SocketConfig::batch` is set
+async fn recv_batch(
+    socket: &UdpSocket,
+    pool: &mut RecvPool,
+    received: &mut Vec<(Bytes, SocketAddr)>,
+    config: &Config,
+    socket_delay: &Mutex<Log2Histogram>,
+) -> std::io::Result<()> {
+    let batch = config.socket.batch.unwrap_or(1);
+    if batch == 1 || config.socket.timestamping.is_some() {
+        let (len, remote) = recv(socket, pool.reserve(MAX_DATAGRAM), config, socket_delay).await?;
+        received.push((pool.take(len), remote));
+        return Ok(());
+    }
+    let region = pool.reserve(batch * MAX_DATAGRAM);
+    let messages = socket
+        .async_io(Interest::READABLE, || {
+            let mut slots = region.chunks_mut(MAX_DATAGRAM);
+            let iovs: [_; MAX_BATCH] =
+                std::array::from_fn(|_| [IoSliceMut::new(slots.next().unwrap_or_default())]);
+            RECV_HEADERS.with_borrow_mut(|headers| {
+                let messages = recvmmsg(
+                    socket.as_raw_fd(),
+                    headers,
+                    &iovs[..batch],
+                    MsgFlags::MSG_DONTWAIT,
+                    None,
+                )?;
+                Ok(Vec::from_iter(messages.map(|message| {
+                    (message.bytes, socket_addr(message.address.unwrap()))
+                })))
+            })
+        })
+        .await?;
+    // pack the datagrams to the front, so they take as much of the chunk as their lengths
+    let mut packed_len = 0;
+    for (index, &(len, _)) in messages.iter().enumerate() {
+        let start = index * MAX_DATAGRAM;
+        region.copy_within(start..start + len, packed_len);
+        packed_len += len
+    }
+    received.extend(
+        messages
+            .into_iter()
+            .map(|(len, remote)| (pool.take(len), remote)),
+    );
+    Ok(())
+}
+
+const MAX_BATCH: usize = control_messages::SocketConfig::MAX_BATCH;
+
+thread_local! {
+    // preallocated once for the largest batch rather than on every syscall, and the iovecs and
+    // addresses of a batch are on the stack
+    // not shared between the two, as sending points the headers to the addresses of the batch,
+    // where receiving would write the remote addresses after they are gone
+    static RECV_HEADERS: RefCell<MultiHeaders<SockaddrStorage>> =
+        RefCell::new(MultiHeaders::preallocate(MAX_BATCH, None));
+    static SEND_HEADERS: RefCell<MultiHeaders<SockaddrStorage>> =
+        RefCell::new(MultiHeaders::preallocate(MAX_BATCH, None));
+}
+
+// sendmmsg, which returns the number of sent datagrams
+fn send_batch(socket: &UdpSocket, datagrams: &[(SocketAddr, Bytes)]) -> std::io::Result<usize> {
+    let datagrams = &datagrams[..datagrams.len().min(MAX_BATCH)];
+    let iovs: [_; MAX_BATCH] =
+        std::array::from_fn(|i| [IoSlice::new(datagrams.get(i).map_or(&[], |(_, buf)| buf))]);
+    let addrs: [_; MAX_BATCH] = std::array::from_fn(|i| {
+        datagrams
+            .get(i)
+            .map(|&(addr, _)| SockaddrStorage::from(addr))
+    });
+    SEND_HEADERS.with_borrow_mut(|headers| {
+        let results = sendmmsg(
+            socket.as_raw_fd(),
+            headers,
+            &iovs[..datagrams.len()],
+            &addrs[..datagrams.len()],
+            [] as [ControlMessage; 0],
+            MsgFlags::MSG_DONTWAIT,
+        )?;
+        Ok(results.count())
+    })
 }
 
 #[derive(Debug)]
@@ -295,34 +1433,66 @@ impl std::ops::Deref for OrderedMulticastDispatch {
 }
 
 impl Dispatch {
+    // a socket per multicast group, which tells the group of the messages received on it
     pub fn enable_ordered_multicast(self) -> OrderedMulticastDispatch {
-        let socket = self
-            .runtime
-            // .block_on(UdpSocket::bind(self.config.multicast_addr.unwrap()))
-            .block_on(UdpSocket::bind((
-                "0.0.0.0",
-                self.config.multicast_addr.unwrap().port(),
-            )))
-            .unwrap();
+        for (group, addr) in self.config.multicast_addrs.iter().enumerate() {
+            self.receive_ordered_multicast(group as _, addr.port())
+        }
+        OrderedMulticastDispatch(self)
+    }
+
+    fn receive_ordered_multicast(&self, group: u8, port: u16) {
+        let socket = self.init_socket(
+            self.runtime
+                // .block_on(UdpSocket::bind(self.config.multicast_addr.unwrap()))
+                .block_on(UdpSocket::bind(("0.0.0.0", port)))
+                .unwrap(),
+        );
         let event = self.event.0.clone();
         let config = self.config.clone();
+        let spoofed_multicast = self.spoofed_multicast.clone();
+        let socket_delay = self.socket_delay.clone();
+        let cancel = self.cancel.clone();
         self.runtime.spawn(async move {
-            let mut buf = vec![0; 65536];
+            let mut pool = RecvPool::new();
+            let mut received = Vec::new();
             loop {
-                let (len, remote) = socket.recv_from(&mut buf).await.unwrap();
-                event
-                    .try_send(Event::OrderedMulticastMessage(
-                        config
-                            .remotes
-                            .get(&remote)
-                            .copied()
-                            .unwrap_or(Host::UnkownMulticastSender),
-                        buf[..len].to_vec(),
+                let Some(result) = cancel
+                    .run_until_cancelled(recv_batch(
+                        &socket,
+                        &mut pool,
+                        &mut received,
+                        &config,
+                        &socket_delay,
                     ))
-                    .unwrap()
-            }
-        });
-        OrderedMulticastDispatch(self)
+                    .await
+                else {
+                    break;
+                };
+                result.unwrap();
+                for (buf, remote) in received.drain(..) {
+                    if let Some(sources) = &config.multicast_sources {
+                        if !sources.contains(&remote.ip()) {
+                            tracing::debug!(%remote, "spoofed multicast");
+                            spoofed_multicast.fetch_add(1, Relaxed);
+                            continue;
+                        }
+                    }
+                    event
+                        .try_send(Event::OrderedMulticastMessage(
+                            config
+                                .remotes
+                                .get(&remote)
+                                .copied()
+                                .unwrap_or(Host::UnkownMulticastSender),
+                            group,
+                            buf,
+                            Timestamp::now(),
+                        ))
+                        .unwrap()
+                }
+            }
+        });
     }
 }
 
@@ -331,12 +1501,30 @@ impl OrderedMulticastDispatch {
         &self,
         receivers: &mut (impl Receivers<Message = M> + OrderedMulticastReceivers<Message = N>),
     ) where
-        M: DeserializeOwned + Verify,
+        M: DeserializeOwned + Verify + Send + 'static,
         N: DeserializeOwned + DigestHash,
         OrderedMulticast<N>: Into<M>,
     {
         self.run_internal(receivers, Into::into)
     }
+
+    pub fn replay<M, N>(
+        &self,
+        trace: impl IntoIterator<Item = trace::Entry>,
+        receivers: &mut (impl Receivers<Message = M> + OrderedMulticastReceivers<Message = N>),
+    ) where
+        M: DeserializeOwned + Verify,
+        N: DeserializeOwned + DigestHash,
+        OrderedMulticast<N>: Into<M>,
+    {
+        self.replay_internal(trace, receivers, Into::into)
+    }
+}
+
+impl Drop for Dispatch {
+    fn drop(&mut self) {
+        self.cancel.cancel()
+    }
 }
 
 pub struct DispatchHandle {
@@ -373,6 +1561,19 @@ impl DispatchHandle {
     }
 }
 
+// an address whose port is free for both the datagram socket and the listener that hosts bind, for
+// the tests that run hosts on the loopback
+#[cfg(test)]
+pub fn unused_addr() -> SocketAddr {
+    loop {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        if std::net::TcpListener::bind(addr).is_ok() {
+            return addr;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use serde::Deserialize;
@@ -412,14 +1613,15 @@ mod tests {
 
         let handle = dispatch.handle();
         let event = dispatch.event.0.clone();
-        std::thread::spawn(move || {
+        let runtime_thread = std::thread::spawn(move || {
             runtime.block_on(async move {
                 tokio::time::sleep(Duration::from_millis(9)).await;
                 event
                     .send_async(Event::Message(
                         Host::Replica(0),
                         Host::Client(0),
-                        bincode::options().serialize(&M).unwrap(),
+                        bincode::options().serialize(&M).unwrap().into(),
+                        Timestamp::now(),
                     ))
                     .await
                     .unwrap();
@@ -452,6 +1654,8 @@ mod tests {
         }
 
         dispatch.run(&mut R(false, context, id));
+        // release the socket before the next round binds the same address
+        runtime_thread.join().unwrap()
     }
 
     #[test]
@@ -460,4 +1664,574 @@ mod tests {
             false_alarm()
         }
     }
+
+    #[test]
+    fn software_timestamping() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let mut config = Config::new([(Host::Replica(0), unused_addr())].into(), 0);
+        config.socket.timestamping = Some(Timestamping::Software);
+        let dispatch = Dispatch::new(
+            config,
+            runtime.handle().clone(),
+            false,
+            Variant::Unreachable,
+        );
+        let socket =
+            dispatch.init_socket(runtime.block_on(UdpSocket::bind("127.0.0.1:0")).unwrap());
+        let addr = socket.local_addr().unwrap();
+        let socket_delay = Mutex::new(Log2Histogram::default());
+        // kernel enables timestamping asynchronously, so the first packets may not be timestamped
+        let num_recorded = runtime.block_on(async {
+            let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let mut buf = [0; 16];
+            for _ in 0..100 {
+                sender.send_to(b"hello", addr).await.unwrap();
+                let (len, remote) = recv(&socket, &mut buf, &dispatch.config, &socket_delay)
+                    .await
+                    .unwrap();
+                assert_eq!((len, remote), (5, sender.local_addr().unwrap()));
+                let num_recorded = socket_delay.lock().unwrap().counts.iter().sum::<u64>();
+                if num_recorded != 0 {
+                    return num_recorded;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await
+            }
+            0
+        });
+        assert_eq!(num_recorded, 1)
+    }
+
+    #[test]
+    fn tcp_transport() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let mut config = Config::new(
+            [
+                (Host::Replica(0), unused_addr()),
+                (Host::Replica(1), unused_addr()),
+            ]
+            .into_iter()
+            .collect(),
+            0,
+        );
+        config.socket.transport = Transport::Tcp;
+        let dispatch = Dispatch::new(
+            config,
+            runtime.handle().clone(),
+            false,
+            Variant::Unreachable,
+        );
+
+        #[derive(Serialize, Deserialize)]
+        struct M(Vec<u8>);
+        impl Verify for M {
+            fn verify(&self, _: &Verifier) -> Result<(), crate::context::crypto::Invalid> {
+                Ok(())
+            }
+        }
+
+        let mut context = dispatch.register::<M>(Host::Replica(0));
+        let _context = dispatch.register::<M>(Host::Replica(1));
+        let shutdown = CancellationToken::new();
+        let runtime_thread = std::thread::spawn({
+            let shutdown = shutdown.clone();
+            move || runtime.block_on(shutdown.cancelled())
+        });
+        // larger than a datagram, and received in the sent order
+        for i in 0..3 {
+            context.send(To::replica(1), M(vec![i; 3 * fragment::MAX_CHUNK]))
+        }
+
+        struct R(u8, Option<DispatchHandle>);
+        impl Receivers for R {
+            type Message = M;
+
+            fn handle(&mut self, receiver: Host, remote: Host, M(buf): Self::Message) {
+                assert_eq!((receiver, remote), (Host::Replica(1), Host::Replica(0)));
+                assert_eq!(buf, vec![self.0; 3 * fragment::MAX_CHUNK]);
+                self.0 += 1;
+                if self.0 == 3 {
+                    // stopping blocks until `run` takes it
+                    let handle = self.1.take().unwrap();
+                    std::thread::spawn(move || handle.stop());
+                }
+            }
+
+            fn on_timer(&mut self, _: Host, _: crate::context::TimerId) {
+                unreachable!()
+            }
+        }
+
+        dispatch.run(&mut R(0, Some(dispatch.handle())));
+        shutdown.cancel();
+        runtime_thread.join().unwrap()
+    }
+
+    #[test]
+    fn sharded_requests() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let mut config = Config::new([(Host::Replica(0), unused_addr())].into(), 0);
+        config.request_workers = 2;
+        let mut dispatch = Dispatch::new(
+            config,
+            runtime.handle().clone(),
+            false,
+            Variant::Unreachable,
+        );
+        // shared by the two runs below
+        dispatch.executor = Some(executor(2));
+
+        #[derive(Serialize, Deserialize)]
+        struct M(u8);
+        impl Verify for M {
+            fn verify(&self, _: &Verifier) -> Result<(), crate::context::crypto::Invalid> {
+                let name = std::thread::current().name().map(String::from);
+                assert!(name.unwrap().starts_with("request-worker-"));
+                Ok(())
+            }
+        }
+
+        let shutdown = CancellationToken::new();
+        let runtime_thread = std::thread::spawn({
+            let shutdown = shutdown.clone();
+            move || runtime.block_on(shutdown.cancelled())
+        });
+        // as received from the sockets, since sends of a client may overtake each other
+        let send = |dispatch: &Dispatch| {
+            for i in 0..10 {
+                for client in 0..3 {
+                    let buf = bincode::options().serialize(&M(i)).unwrap();
+                    dispatch
+                        .event
+                        .0
+                        .send(Event::Message(
+                            Host::Replica(0),
+                            Host::Client(client),
+                            buf.into(),
+                            Timestamp::now(),
+                        ))
+                        .unwrap()
+                }
+            }
+        };
+
+        // each client's requests are handled in the sent order, though they are verified on
+        // different workers
+        struct R(HashMap<Host, u8>, Option<DispatchHandle>);
+        impl Receivers for R {
+            type Message = M;
+
+            fn handle(&mut self, receiver: Host, remote: Host, M(i): Self::Message) {
+                assert_eq!(receiver, Host::Replica(0));
+                let next = self.0.entry(remote).or_default();
+                assert_eq!(i, *next);
+                *next += 1;
+                if self.0.values().sum::<u8>() == 30 {
+                    let handle = self.1.take().unwrap();
+                    std::thread::spawn(move || handle.stop());
+                }
+            }
+
+            fn on_timer(&mut self, _: Host, _: crate::context::TimerId) {
+                unreachable!()
+            }
+        }
+
+        for _ in 0..2 {
+            send(&dispatch);
+            dispatch.run(&mut R(Default::default(), Some(dispatch.handle())))
+        }
+        shutdown.cancel();
+        runtime_thread.join().unwrap()
+    }
+
+    #[test]
+    fn sharded_replies() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let mut config = Config::new(
+            HashMap::from([
+                (Host::Replica(0), unused_addr()),
+                (Host::Client(0), unused_addr()),
+                (Host::Client(1), unused_addr()),
+            ]),
+            0,
+        );
+        config.request_workers = 2;
+        let dispatch = Dispatch::new(
+            config.clone(),
+            runtime.handle().clone(),
+            false,
+            Variant::Unreachable,
+        );
+        let sockets = [0, 1].map(|index| {
+            let socket = std::net::UdpSocket::bind(config.hosts[&Host::Client(index)].addr);
+            let socket = socket.unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap();
+            socket
+        });
+
+        #[derive(Serialize, Deserialize)]
+        struct M(u8);
+        let mut context = dispatch.register::<M>(Host::Replica(0));
+        let shutdown = CancellationToken::new();
+        let runtime_thread = std::thread::spawn({
+            let shutdown = shutdown.clone();
+            move || runtime.block_on(shutdown.cancelled())
+        });
+        for i in 0..10 {
+            for client in 0..2 {
+                context.send(To::client(client), M(i))
+            }
+        }
+        // each client receives its replies in the sent order, though they are sent by different
+        // workers
+        for socket in &sockets {
+            for i in 0..10 {
+                let mut buf = [0; 1024];
+                let len = socket.recv(&mut buf).unwrap();
+                let M(j) = bincode::options().deserialize(&buf[..len]).unwrap();
+                assert_eq!(j, i)
+            }
+        }
+        shutdown.cancel();
+        runtime_thread.join().unwrap()
+    }
+
+    #[test]
+    fn trace_replay() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let mut config = Config::new([(Host::Replica(0), unused_addr())].into(), 0);
+        config.request_workers = 2;
+        let mut dispatch = Dispatch::new(
+            config.clone(),
+            runtime.handle().clone(),
+            false,
+            Variant::Unreachable,
+        );
+        let path = std::env::temp_dir().join(format!("trace-replay-{}", std::process::id()));
+        dispatch.trace = Some(path.clone());
+
+        #[derive(Serialize, Deserialize)]
+        struct M(u8);
+        impl Verify for M {
+            fn verify(&self, _: &Verifier) -> Result<(), crate::context::crypto::Invalid> {
+                Ok(())
+            }
+        }
+
+        let mut context = dispatch.register::<M>(Host::Replica(0));
+        context.set(Duration::from_millis(1));
+        let shutdown = CancellationToken::new();
+        let runtime_thread = std::thread::spawn({
+            let shutdown = shutdown.clone();
+            move || runtime.block_on(shutdown.cancelled())
+        });
+        for i in 0..10 {
+            for client in 0..3 {
+                let buf = bincode::options().serialize(&M(i)).unwrap();
+                dispatch
+                    .event
+                    .0
+                    .send(Event::Message(
+                        Host::Replica(0),
+                        Host::Client(client),
+                        buf.into(),
+                        Timestamp::now(),
+                    ))
+                    .unwrap()
+            }
+        }
+
+        // the order in which the requests from workers interleave with each other and with the
+        // timer varies from run to run, and is the same in the replay
+        #[derive(Default)]
+        struct R(Vec<String>, Option<DispatchHandle>);
+        impl Receivers for R {
+            type Message = M;
+
+            fn handle(&mut self, _: Host, remote: Host, M(i): Self::Message) {
+                self.0.push(format!("{remote:?} {i}"))
+            }
+
+            fn on_timer(&mut self, _: Host, id: crate::context::TimerId) {
+                self.0.push(format!("{id:?}"));
+                // once every request is handled
+                if self
+                    .0
+                    .iter()
+                    .filter(|event| event.starts_with("Client"))
+                    .count()
+                    == 30
+                {
+                    if let Some(handle) = self.1.take() {
+                        std::thread::spawn(move || handle.stop());
+                    }
+                }
+            }
+
+            fn on_pace(&mut self) {
+                self.0.push(String::from("pace"))
+            }
+        }
+
+        let mut receivers = R(Default::default(), Some(dispatch.handle()));
+        dispatch.run(&mut receivers);
+        shutdown.cancel();
+        runtime_thread.join().unwrap();
+        let trace = trace::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let dispatch = Dispatch::new(
+            config,
+            runtime.handle().clone(),
+            false,
+            Variant::Unreachable,
+        );
+        let mut replayed = R::default();
+        dispatch.replay(trace, &mut replayed);
+        assert_eq!(replayed.0, receivers.0)
+    }
+
+    #[test]
+    fn recv_pool_reuse() {
+        let mut pool = RecvPool::new();
+        let start = pool.reserve(MAX_DATAGRAM).as_ptr();
+        let first = pool.take(100);
+        assert_eq!(first.as_ptr(), start);
+        // the rest of the chunk is not enough, and the first message still holds it
+        pool.reserve(RecvPool::CHUNK_LEN);
+        let second = pool.take(100);
+        assert_ne!(second.as_ptr(), start);
+        drop(first);
+        let second_start = second.as_ptr();
+        drop(second);
+        pool.reserve(RecvPool::CHUNK_LEN);
+        assert_eq!(pool.take(100).as_ptr(), second_start)
+    }
+
+    #[test]
+    fn batched_udp() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let mut config = Config::new(
+            (0..3)
+                .map(|i| (Host::Replica(i as _), unused_addr()))
+                .collect(),
+            0,
+        );
+        config.socket.batch = Some(4);
+        let dispatch = Dispatch::new(
+            config,
+            runtime.handle().clone(),
+            false,
+            Variant::Unreachable,
+        );
+
+        #[derive(Serialize, Deserialize)]
+        struct M(Vec<u8>);
+        impl Verify for M {
+            fn verify(&self, _: &Verifier) -> Result<(), crate::context::crypto::Invalid> {
+                Ok(())
+            }
+        }
+
+        let mut context = dispatch.register::<M>(Host::Replica(0));
+        let _contexts = [1, 2].map(|i| dispatch.register::<M>(Host::Replica(i)));
+        let shutdown = CancellationToken::new();
+        let runtime_thread = std::thread::spawn({
+            let shutdown = shutdown.clone();
+            move || runtime.block_on(shutdown.cancelled())
+        });
+        // the fragments of every message to both replicas make more datagrams than a batch
+        for i in 0..5 {
+            context.send(To::AllReplica, M(vec![i; 3 * fragment::MAX_CHUNK]))
+        }
+
+        struct R(HashMap<Host, u8>, Option<DispatchHandle>);
+        impl Receivers for R {
+            type Message = M;
+
+            fn handle(&mut self, receiver: Host, remote: Host, M(buf): Self::Message) {
+                assert_eq!(remote, Host::Replica(0));
+                assert_eq!(buf.len(), 3 * fragment::MAX_CHUNK);
+                *self.0.entry(receiver).or_default() += 1;
+                if self.0.values().sum::<u8>() == 10 {
+                    let handle = self.1.take().unwrap();
+                    std::thread::spawn(move || handle.stop());
+                }
+            }
+
+            fn on_timer(&mut self, _: Host, _: crate::context::TimerId) {
+                unreachable!()
+            }
+        }
+
+        let mut receivers = R(Default::default(), Some(dispatch.handle()));
+        dispatch.run(&mut receivers);
+        assert_eq!(receivers.0[&Host::Replica(1)], 5);
+        assert_eq!(receivers.0[&Host::Replica(2)], 5);
+        shutdown.cancel();
+        runtime_thread.join().unwrap()
+    }
+
+    #[test]
+    fn socket_drops() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let mut config = Config::new([(Host::Replica(0), unused_addr())].into(), 0);
+        // the kernel rounds it up to its minimum, which still holds only a few datagrams
+        config.socket.rcvbuf = Some(1);
+        let dispatch = Dispatch::new(
+            config,
+            runtime.handle().clone(),
+            false,
+            Variant::Unreachable,
+        );
+        let socket =
+            dispatch.init_socket(runtime.block_on(UdpSocket::bind("127.0.0.1:0")).unwrap());
+        assert_eq!(dispatch.socket_drops(), 0);
+        let addr = socket.local_addr().unwrap();
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        // nothing is received, so the receive buffer overflows
+        for _ in 0..100 {
+            sender.send_to(&[0; 1000], addr).unwrap();
+        }
+        assert_ne!(dispatch.socket_drops(), 0)
+    }
+
+    #[test]
+    fn socket_options() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let mut config = Config::new([(Host::Replica(0), unused_addr())].into(), 0);
+        config.socket.sndbuf = Some(1 << 20);
+        config.socket.tos = Some(0x10);
+        // lowering it needs no privilege
+        config.socket.busy_poll = Some(0);
+        let dispatch = Dispatch::new(
+            config,
+            runtime.handle().clone(),
+            false,
+            Variant::Unreachable,
+        );
+        let socket =
+            dispatch.init_socket(runtime.block_on(UdpSocket::bind("127.0.0.1:0")).unwrap());
+        // the kernel doubles the buffer size for its bookkeeping
+        assert!(nix::sys::socket::getsockopt(&*socket, sockopt::SndBuf).unwrap() >= 1 << 20);
+        assert_eq!(
+            nix::sys::socket::getsockopt(&*socket, sockopt::IpTos).unwrap(),
+            0x10
+        );
+        let mut busy_poll: nix::libc::c_int = -1;
+        let mut len = std::mem::size_of_val(&busy_poll) as nix::libc::socklen_t;
+        // SAFETY: the fd is open for the call, and the kernel writes at most `len` bytes into the
+        // live `c_int` and the actual size back into `len`
+        let result = unsafe {
+            nix::libc::getsockopt(
+                socket.as_raw_fd(),
+                nix::libc::SOL_SOCKET,
+                nix::libc::SO_BUSY_POLL,
+                &mut busy_poll as *mut _ as *mut _,
+                &mut len,
+            )
+        };
+        nix::errno::Errno::result(result).unwrap();
+        assert_eq!(
+            (busy_poll, len as usize),
+            (0, std::mem::size_of_val(&busy_poll))
+        )
+    }
+
+    #[test]
+    fn ingress_delays() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let config = Config::new(
+            [
+                (Host::Replica(0), unused_addr()),
+                (Host::Replica(1), unused_addr()),
+            ]
+            .into_iter()
+            .collect(),
+            0,
+        );
+        let dispatch = Dispatch::new(
+            config,
+            runtime.handle().clone(),
+            false,
+            Variant::Unreachable,
+        );
+
+        #[derive(Serialize, Deserialize)]
+        struct M;
+        impl Verify for M {
+            fn verify(&self, _: &Verifier) -> Result<(), crate::context::crypto::Invalid> {
+                Ok(())
+            }
+        }
+
+        let mut context = dispatch.register::<M>(Host::Replica(0));
+        let _context = dispatch.register::<M>(Host::Replica(1));
+        let shutdown = CancellationToken::new();
+        let runtime_thread = std::thread::spawn({
+            let shutdown = shutdown.clone();
+            move || runtime.block_on(shutdown.cancelled())
+        });
+        for _ in 0..3 {
+            context.send(To::replica(1), M)
+        }
+
+        struct R(usize, Option<DispatchHandle>);
+        impl Receivers for R {
+            type Message = M;
+
+            fn handle(&mut self, _: Host, _: Host, M: Self::Message) {
+                self.0 += 1;
+                if self.0 == 3 {
+                    let handle = self.1.take().unwrap();
+                    std::thread::spawn(move || handle.stop());
+                }
+            }
+
+            fn on_timer(&mut self, _: Host, _: crate::context::TimerId) {
+                unreachable!()
+            }
+        }
+
+        dispatch.run(&mut R(0, Some(dispatch.handle())));
+        shutdown.cancel();
+        runtime_thread.join().unwrap();
+        // every received message is recorded once for both its queueing and processing
+        let (queueing_delay, processing_delay) = dispatch.ingress_delays();
+        assert_eq!(queueing_delay.counts.iter().sum::<u64>(), 3);
+        assert_eq!(processing_delay.counts.iter().sum::<u64>(), 3)
+    }
 }
@@ -0,0 +1,90 @@
+// the inbound events of a `tokio::Dispatch` as they are handed to the receivers, recorded with the
+// raw bytes of the messages, so that a run on the cluster can be replayed into the same receivers in
+// the same order, e.g. under a debugger, see `tokio::Dispatch::trace` and `tokio::Dispatch::replay`
+// a trace file is a sequence of bincode encoded `Entry`s. the events are recorded after the
+// dispatch drops or delays them, and after rejecting stale messages, so the replay takes every
+// event as is
+
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use super::{read_entries, tokio::TimerId, Host};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Event {
+    // receiver, remote and the message
+    Message(Host, Host, Vec<u8>),
+    Loopback(Host, Vec<u8>),
+    // remote, multicast group and the message
+    OrderedMulticast(Host, u8, Vec<u8>),
+    Timer(Host, TimerId),
+    Pace,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    // since the dispatch starts running
+    pub time: Duration,
+    pub event: Event,
+}
+
+#[derive(Debug)]
+pub struct Writer {
+    // flushed when dropped, which includes unwinding from a panic of the receivers
+    // none after failing to write, e.g. on a full disk, which stops recording rather than the
+    // dispatch
+    file: Option<BufWriter<File>>,
+    start: Instant,
+}
+
+impl Writer {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?
+        }
+        Ok(Self {
+            file: Some(BufWriter::new(File::create(path)?)),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, event: Event) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        let entry = Entry {
+            time: self.start.elapsed(),
+            event,
+        };
+        if let Err(err) = bincode::options().serialize_into(file, &entry) {
+            tracing::warn!(%err, "stop recording trace");
+            self.file = None
+        }
+    }
+}
+
+pub fn read(path: &Path) -> io::Result<Vec<Entry>> {
+    read_entries(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_on_write_error() {
+        // every write fails with no space left
+        let mut writer = Writer::create(Path::new("/dev/full")).unwrap();
+        // larger than the buffer, so it is written through
+        writer.record(Event::Loopback(Host::Replica(0), vec![0; 1 << 16]));
+        assert!(writer.file.is_none());
+        writer.record(Event::Pace)
+    }
+}
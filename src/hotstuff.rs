@@ -1,16 +1,23 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     sync::{Arc, Mutex},
     time::Duration,
 };
 
+use control_messages::{ClientDestination, WaitingStats};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    client::BoxedConsume,
-    common::{Block, BlockDigest, Chain, Request, Timer},
+    client::{BoxedConsume, Outcome, Proof},
+    common::{
+        mempool::{Available, Batch, BatchAck, FetchBatch, Mempool},
+        Block, BlockDigest, Chain, Request, Timer, Waiting,
+    },
     context::{
-        crypto::{DigestHash, Sign, Signed, Verify},
+        byzantine::{garble, Tamper},
+        clock::Timestamp,
+        crypto::{DigestHash, Sign, Signature, Signed, Signer, Verify},
+        metrics::Gauges,
         ClientIndex, Host, Receivers, ReplicaIndex, To,
     },
     App, Context,
@@ -22,6 +29,11 @@ pub enum Message {
     Reply(Signed<Reply>),
     Generic(Signed<Generic>),
     Vote(Signed<Vote>),
+    Timeout(Signed<Timeout>),
+    Batch(Signed<Batch>),
+    BatchAck(Signed<BatchAck>),
+    Available(Available),
+    FetchBatch(Signed<FetchBatch>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,19 +47,43 @@ pub struct Reply {
 pub struct Generic {
     block: Block,
     certified_digest: BlockDigest,
-    certificate: Vec<Signed<Vote>>,
+    certificate: Certificate,
+    // of the previous view, if the certified block is not of it
+    timeout_certificate: Vec<Signed<Timeout>>,
+    // of the block's batches, so the voters know they can be fetched
+    batch_certificates: Vec<Available>,
     replica_index: ReplicaIndex,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Certificate {
+    Votes(Vec<Signed<Vote>>),
+    // the BLS signatures of the votes aggregated into one, for hotstuff-bls where votes are signed
+    // with BLS, which keeps proposals small and checks the certificate at the cost of one vote
+    Aggregated(Vec<ReplicaIndex>, Signature),
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Vote {
     block_digest: BlockDigest,
     replica_index: ReplicaIndex,
 }
 
+// the replica gives up the view without a proposal to vote for, and tells its highest certified
+// block for the next leader to extend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Timeout {
+    view: u32,
+    certified_digest: BlockDigest,
+    certificate: Certificate,
+    replica_index: ReplicaIndex,
+}
+
 #[derive(Debug)]
 pub struct Client {
     index: ClientIndex,
+    // submit to one replica's worker instead of every replica
+    mempool: bool,
     shared: Arc<Mutex<ClientShared>>,
 }
 
@@ -64,12 +100,22 @@ struct ClientInvoke {
     op: Vec<u8>,
     replies: HashMap<ReplicaIndex, Reply>,
     consume: BoxedConsume,
+    start: Timestamp,
 }
 
 impl Client {
     pub fn new(context: Context<Message>, index: ClientIndex) -> Self {
+        Self::new_internal(context, index, false)
+    }
+
+    pub fn new_mempool(context: Context<Message>, index: ClientIndex) -> Self {
+        Self::new_internal(context, index, true)
+    }
+
+    fn new_internal(context: Context<Message>, index: ClientIndex, mempool: bool) -> Self {
         Self {
             index,
+            mempool,
             shared: Arc::new(Mutex::new(ClientShared {
                 context,
                 request_num: 0,
@@ -91,13 +137,22 @@ impl crate::Client for Client {
             op: op.clone(),
             replies: Default::default(),
             consume: consume.into(),
+            start: Timestamp::now(),
         });
         let request = Request {
             client_index: self.index,
             request_num: shared.request_num,
             op,
         };
-        shared.context.send(To::AllReplica, request);
+        let default = if self.mempool {
+            ClientDestination::Random
+        } else {
+            ClientDestination::All
+        };
+        let Some(to) = shared.context.request_destination(default) else {
+            unreachable!("hotstuff does not accept ordered multicast requests, see `ClientDestination::check`")
+        };
+        shared.context.send(to, request);
         shared.resend_timer.set(&mut shared.context)
     }
 
@@ -115,17 +170,22 @@ impl crate::Client for Client {
         invoke
             .replies
             .insert(message.replica_index, Reply::clone(&message));
-        let num_match = invoke
-            .replies
-            .values()
-            .filter(|reply| reply.result == message.result)
-            .count();
-        assert!(num_match <= shared.context.config().num_faulty + 1);
-        if num_match == shared.context.config().num_faulty + 1 {
+        let matched = Vec::from_iter(
+            invoke
+                .replies
+                .values()
+                .filter(|reply| reply.result == message.result)
+                .cloned(),
+        );
+        assert!(matched.len() <= shared.context.config().num_faulty + 1);
+        if matched.len() == shared.context.config().num_faulty + 1 {
             shared.resend_timer.unset(&mut shared.context);
             let invoke = shared.invoke.take().unwrap();
             let _op = invoke.op;
-            invoke.consume.apply(message.inner.result)
+            invoke.consume.apply(Outcome {
+                proof: Some(Proof::new(matched)),
+                ..Outcome::new(message.inner.result, invoke.start)
+            })
         }
     }
 }
@@ -134,17 +194,32 @@ pub struct Replica {
     context: Context<Message>,
     index: ReplicaIndex,
 
+    // the view that a proposal is waited for, blocks are proposed at the height of their views
+    pub view: u32,
     view_height: u32,
     propose_height: u32,
     digest_certified: BlockDigest, // qc_{high}
+    certificate: Certificate,      // of `digest_certified`
     digest_lock: BlockDigest,
+    // the timeouts of the previous view if the replica enters the view on them, for its leader to
+    // justify the proposal that does not follow a certified block of the previous view
+    timeout_certificate: Vec<Signed<Timeout>>,
+    timeouts: HashMap<u32, HashMap<ReplicaIndex, Signed<Timeout>>>,
+    // runs while some request is not executed, and restarts on entering each view
+    view_timer: Timer,
 
+    // not executed yet, kept by every replica as any of them may lead a later view
     requests: Vec<Request>,
     replies: HashMap<ClientIndex, (u32, Option<Reply>)>,
     generics: HashMap<BlockDigest, Signed<Generic>>,
     votes: HashMap<BlockDigest, HashMap<ReplicaIndex, Signed<Vote>>>,
-    reordering_generics: HashMap<BlockDigest, Vec<Signed<Generic>>>,
-    chain: Chain,
+    // keyed by the missing digest, prioritized by height so generics far ahead are evicted first
+    reordering_generics: Waiting<BlockDigest, u32, Vec<Signed<Generic>>>,
+    pub chain: Chain,
+    // committed and not executed yet, as the batches of a block may be still fetched
+    execute_queue: VecDeque<BlockDigest>,
+    // the requests are submitted to it instead, and the blocks order its batches
+    mempool: Option<Mempool>,
     app: App,
 }
 
@@ -161,28 +236,56 @@ impl Replica {
                 inner: Generic {
                     block: genesis_block,
                     certified_digest: Chain::genesis().digest(),
-                    certificate: Default::default(),
+                    certificate: Certificate::Votes(Default::default()),
+                    timeout_certificate: Default::default(),
+                    batch_certificates: Default::default(),
                     replica_index: u8::MAX,
                 },
-                signature: crate::context::crypto::Signature::Plain,
+                signature: Signature::Plain,
+                key_epoch: 0,
             },
         );
         Self {
+            reordering_generics: Waiting::new(context.config().waiting_capacity),
             context,
             index,
+            view: 1,
             view_height: 0,
             propose_height: 0,
             digest_certified: Chain::genesis().digest(),
+            certificate: Certificate::Votes(Default::default()),
             digest_lock: Chain::genesis().digest(),
+            timeout_certificate: Default::default(),
+            timeouts: Default::default(),
+            view_timer: Timer::new(Duration::from_millis(20)),
             requests: Default::default(),
             replies: Default::default(),
             generics,
             votes,
-            reordering_generics: Default::default(),
             chain: Default::default(),
+            execute_queue: Default::default(),
+            mempool: None,
             app,
         }
     }
+
+    pub fn new_mempool(context: Context<Message>, index: ReplicaIndex, app: App) -> Self {
+        Self {
+            mempool: Some(Mempool::new(index)),
+            ..Self::new(context, index, app)
+        }
+    }
+
+    // preallocate the log for `entry_num` entries, so that growing it does not stall the first
+    // seconds of a run
+    pub fn warm_up(&mut self, entry_num: usize) {
+        self.generics.reserve(entry_num);
+        self.votes.reserve(entry_num)
+    }
+
+    pub fn waiting_stats(&self) -> WaitingStats {
+        self.reordering_generics.stats
+    }
 }
 
 impl Receivers for Replica {
@@ -195,6 +298,11 @@ impl Receivers for Replica {
             Message::Request(message) => self.handle_request(remote, message),
             Message::Generic(message) => self.handle_generic(remote, message),
             Message::Vote(message) => self.handle_vote(remote, message),
+            Message::Timeout(message) => self.handle_timeout(message),
+            Message::Batch(message) => self.handle_batch(message),
+            Message::BatchAck(message) => self.handle_batch_ack(message),
+            Message::Available(message) => self.handle_available(message),
+            Message::FetchBatch(message) => self.handle_fetch_batch(message),
             _ => unimplemented!(),
         }
     }
@@ -205,19 +313,45 @@ impl Receivers for Replica {
         match message {
             Message::Generic(message) => self.insert_generic(message),
             Message::Vote(message) => self.handle_vote(receiver, message),
+            Message::Timeout(message) => self.handle_timeout(message),
+            Message::Batch(message) => self.handle_batch(message),
+            Message::BatchAck(message) => self.handle_batch_ack(message),
+            Message::Available(message) => self.handle_available(message),
             _ => unimplemented!(),
         }
     }
 
+    // the view timer, which keeps sending the timeout until the replica leaves the view
     fn on_timer(&mut self, receiver: Host, _: crate::context::TimerId) {
         assert_eq!(receiver, Host::Replica(self.index));
-        todo!()
+        // no longer vote in the view
+        self.view_height = self.view_height.max(self.view);
+        let timeout = Timeout {
+            view: self.view,
+            certified_digest: self.digest_certified,
+            certificate: self.certificate.clone(),
+            replica_index: self.index,
+        };
+        self.context.send(To::AllReplicaWithLoopback, timeout)
+    }
+
+    fn gauges(&self) -> Gauges {
+        Gauges {
+            log_len: Some(self.generics.len() as _),
+            commit_num: Some(self.chain.executed_height() as _),
+            reorder_len: None,
+        }
     }
 
     fn on_pace(&mut self) {
-        if self.index == self.primary_index()
-            && self.replies.values().any(|(_, reply)| reply.is_none())
-            && self.generics[&self.digest_certified].block.height >= self.propose_height
+        if let Some(mempool) = &mut self.mempool {
+            mempool.on_pace(&mut self.context)
+        }
+        if self.index == self.leader(self.view)
+            && self.propose_height < self.view
+            && (self.block_height(&self.digest_certified) + 1 == self.view
+                || !self.timeout_certificate.is_empty())
+            && self.has_pending()
         {
             self.do_propose()
         }
@@ -225,8 +359,24 @@ impl Receivers for Replica {
 }
 
 impl Replica {
-    fn primary_index(&self) -> ReplicaIndex {
-        0 // TODO rotate
+    // the views ahead of the current one that timeouts are collected for
+    const TIMEOUT_WINDOW: u32 = 64;
+
+    fn leader(&self, view: u32) -> ReplicaIndex {
+        (view as usize % self.context.config().num_replica) as _
+    }
+
+    fn quorum(&self) -> usize {
+        self.context.config().num_replica - self.context.config().num_faulty
+    }
+
+    // some request is not executed, or some certified batch with the mempool
+    fn has_pending(&self) -> bool {
+        if let Some(mempool) = &self.mempool {
+            !mempool.available.is_empty()
+        } else {
+            self.replies.values().any(|(_, reply)| reply.is_none())
+        }
     }
 
     fn handle_request(&mut self, remote: Host, message: Signed<Request>) {
@@ -242,66 +392,184 @@ impl Replica {
         }
         self.replies
             .insert(message.client_index, (message.request_num, None));
+        if let Some(mempool) = &mut self.mempool {
+            mempool.on_request(message.inner);
+            return;
+        }
+        self.requests.push(message.inner);
+        if self.view_timer.id.is_none() {
+            self.view_timer.set(&mut self.context)
+        }
+    }
 
-        if self.index != self.primary_index() {
+    fn handle_batch(&mut self, message: Signed<Batch>) {
+        let Some(mempool) = &mut self.mempool else {
             return;
+        };
+        mempool.on_batch(message, &mut self.context);
+        // may be the fetched one that the execution waits for
+        self.do_execute()
+    }
+
+    fn handle_batch_ack(&mut self, message: Signed<BatchAck>) {
+        if let Some(mempool) = &mut self.mempool {
+            mempool.on_ack(message, &mut self.context)
         }
+    }
 
-        self.requests.push(message.inner)
+    fn handle_available(&mut self, message: Available) {
+        let Some(mempool) = &mut self.mempool else {
+            return;
+        };
+        mempool.on_available(message, &self.context);
+        if self.view_timer.id.is_none() && self.has_pending() {
+            self.view_timer.set(&mut self.context)
+        }
+    }
+
+    fn handle_fetch_batch(&mut self, message: Signed<FetchBatch>) {
+        if let Some(mempool) = &self.mempool {
+            mempool.on_fetch(message, &mut self.context)
+        }
     }
 
     fn handle_generic(&mut self, _remote: Host, message: Signed<Generic>) {
+        if message.replica_index != self.leader(message.block.height)
+            || !message
+                .certificate
+                .is_quorum(&message.certified_digest, self.quorum())
+        {
+            return;
+        }
         self.do_reorder_generic(message)
     }
 
     fn handle_vote(&mut self, _remote: Host, message: Signed<Vote>) {
         let block_digest = message.block_digest;
-        assert!(self.generics.contains_key(&block_digest)); // TODO
+        let quorum = self.quorum();
         let votes = self.votes.entry(block_digest).or_default();
-        if votes.len() == self.context.config().num_replica - self.context.config().num_faulty {
+        if votes.len() == quorum {
             return;
         }
         votes.insert(message.replica_index, message);
-        if votes.len() == self.context.config().num_replica - self.context.config().num_faulty {
-            self.do_update_certified(&block_digest)
+        // otherwise checked on inserting the block, as the votes may arrive before it
+        if votes.len() == quorum && self.generics.contains_key(&block_digest) {
+            let certificate = Certificate::new(votes.values());
+            self.do_update_certified(&block_digest, &certificate)
+        }
+    }
+
+    fn handle_timeout(&mut self, message: Signed<Timeout>) {
+        // the views far ahead are entered on the generics instead, so a faulty replica cannot fill
+        // the map with timeouts of arbitrary views
+        if message.view < self.view
+            || message.view >= self.view + Self::TIMEOUT_WINDOW
+            || !message
+                .certificate
+                .is_quorum(&message.certified_digest, self.quorum())
+        {
+            return;
+        }
+        let view = message.view;
+        let timeouts = self.timeouts.entry(view).or_default();
+        timeouts.insert(message.replica_index, message);
+        if timeouts.len() < self.quorum() {
+            return;
+        }
+        let timeout_certificate =
+            Vec::from_iter(self.timeouts.remove(&view).unwrap().into_values());
+        self.timeouts.retain(|&timeout_view, _| timeout_view > view);
+        // so the leader of the next view extends the highest certified block that is told
+        for timeout in &timeout_certificate {
+            if self.generics.contains_key(&timeout.certified_digest) {
+                self.do_update_certified(&timeout.certified_digest, &timeout.certificate)
+            }
+        }
+        self.enter_view(view + 1, timeout_certificate)
+    }
+
+    fn enter_view(&mut self, view: u32, timeout_certificate: Vec<Signed<Timeout>>) {
+        self.view = view;
+        self.timeout_certificate = timeout_certificate;
+        if self.view_timer.id.is_some() {
+            self.view_timer.reset(&mut self.context)
         }
     }
 
     fn do_propose(&mut self) {
-        self.chain.digest_parent = self.digest_certified; // careful
-        let block = if !self.requests.is_empty() {
-            self.chain.propose(&mut self.requests)
+        // the requests and batches that are in the extended blocks and not executed yet
+        let mut proposed = HashSet::new();
+        let mut proposed_batches = HashSet::new();
+        let execute_height = self.block_height(&self.chain.digest_execute);
+        let mut digest = self.digest_certified;
+        while self.block_height(&digest) > execute_height {
+            let block = &self.generics[&digest].block;
+            proposed.extend(
+                block
+                    .requests
+                    .iter()
+                    .map(|request| (request.client_index, request.request_num)),
+            );
+            proposed_batches.extend(block.batches.iter().copied());
+            digest = block.parent_digest
+        }
+        for digest in &self.execute_queue {
+            proposed_batches.extend(self.generics[digest].block.batches.iter().copied())
+        }
+        let mut batches = Vec::new();
+        let mut batch_certificates = Vec::new();
+        if let Some(mempool) = &self.mempool {
+            for batch_digest in mempool
+                .available
+                .iter()
+                .filter(|batch_digest| !proposed_batches.contains(*batch_digest))
+                .take(Chain::MAX_BATCH_SIZE)
+            {
+                batches.push(*batch_digest);
+                batch_certificates.push(mempool.certificates[batch_digest].clone())
+            }
+        }
+        let requests = Vec::from_iter(
+            self.requests
+                .iter()
+                .filter(|request| !proposed.contains(&(request.client_index, request.request_num)))
+                .take(Chain::MAX_BATCH_SIZE)
+                .cloned(),
+        );
+        // at the height of the view rather than following the parent, so that the heights of the
+        // views without certified blocks are skipped
+        let block = Block {
+            requests,
+            parent_digest: self.digest_certified,
+            height: self.view,
+            batches,
+        };
+        let timeout_certificate = if self.block_height(&self.digest_certified) + 1 == self.view {
+            Default::default()
         } else {
-            self.chain.propose_empty()
+            self.timeout_certificate.clone()
         };
         let generic = Generic {
             replica_index: self.index,
             block,
             certified_digest: self.digest_certified,
-            certificate: self.votes[&self.digest_certified]
-                .values()
-                .cloned()
-                .collect(),
+            certificate: self.certificate.clone(),
+            timeout_certificate,
+            batch_certificates,
         };
-        self.propose_height = generic.block.height;
+        self.propose_height = self.view;
         self.context.send(To::AllReplicaWithLoopback, generic)
     }
 
     fn do_reorder_generic(&mut self, generic: Signed<Generic>) {
-        if !self.generics.contains_key(&generic.block.parent_digest) {
-            self.reordering_generics
-                .entry(generic.block.parent_digest)
-                .or_default()
-                .push(generic);
-            return;
-        }
-
-        if !self.generics.contains_key(&generic.certified_digest) {
-            self.reordering_generics
-                .entry(generic.certified_digest)
-                .or_default()
-                .push(generic);
-            return;
+        for digest in [generic.block.parent_digest, generic.certified_digest] {
+            if !self.generics.contains_key(&digest) {
+                if let Some(generics) = self.reordering_generics.entry(digest, generic.block.height)
+                {
+                    generics.push(generic)
+                }
+                return;
+            }
         }
 
         let block_digest = generic.block.digest();
@@ -313,12 +581,35 @@ impl Replica {
         }
     }
 
+    // the block follows a certified block of the previous view, or the previous view is timed out
+    // by a quorum
+    fn justified(&self, generic: &Generic) -> bool {
+        let height = generic.block.height;
+        let certified_height = self.block_height(&generic.certified_digest);
+        if certified_height >= height {
+            return false;
+        }
+        certified_height + 1 == height
+            || generic
+                .timeout_certificate
+                .iter()
+                .filter(|timeout| timeout.view + 1 == height)
+                .map(|timeout| timeout.replica_index)
+                .collect::<HashSet<_>>()
+                .len()
+                >= self.quorum()
+    }
+
     fn insert_generic(&mut self, generic: Signed<Generic>) {
         // println!("> insert {:02x?}", generic.inner);
-        self.generics
-            .insert(generic.block.digest(), generic.clone());
+        let block_digest = generic.block.digest();
+        self.generics.insert(block_digest, generic.clone());
+        if !self.justified(&generic) {
+            return;
+        }
 
         if generic.block.height > self.view_height
+            && self.is_available(&generic)
             && (self.extend(&generic.block, &self.digest_lock)
                 || self.block_height(&generic.certified_digest)
                     > self.block_height(&self.digest_lock))
@@ -326,18 +617,29 @@ impl Replica {
             // println!("> vote   {:02x?}", generic.inner);
             self.view_height = generic.block.height;
             let vote = Vote {
-                block_digest: generic.block.digest(),
+                block_digest,
                 replica_index: self.index,
             };
-            let to = if self.index == self.primary_index() {
+            // to the leader of the next view, which proposes with the certificate
+            let leader = self.leader(generic.block.height + 1);
+            let to = if self.index == leader {
                 To::Loopback
             } else {
-                To::replica(self.primary_index())
+                To::replica(leader)
             };
             // println!("! send vote {to:?}");
             self.context.send(to, vote)
         }
-        self.do_update(&generic.block.digest())
+        if generic.block.height >= self.view {
+            self.enter_view(generic.block.height + 1, Default::default())
+        }
+        if let Some(votes) = self.votes.get(&block_digest) {
+            if votes.len() == self.quorum() {
+                let certificate = Certificate::new(votes.values());
+                self.do_update_certified(&block_digest, &certificate)
+            }
+        }
+        self.do_update(&block_digest)
     }
 
     fn do_update(&mut self, block_digest: &BlockDigest) {
@@ -345,7 +647,8 @@ impl Replica {
         let block_digest2 = self.generics[&block_digest3].certified_digest;
         let block_digest1 = self.generics[&block_digest2].certified_digest;
         let block_digest0 = self.generics[&block_digest1].certified_digest;
-        self.do_update_certified(&block_digest2);
+        let certificate = self.generics[&block_digest3].certificate.clone();
+        self.do_update_certified(&block_digest2, &certificate);
         if self.block_height(&block_digest1) > self.block_height(&self.digest_lock) {
             self.digest_lock = block_digest1
         }
@@ -353,29 +656,116 @@ impl Replica {
             && self.generics[&block_digest1].block.parent_digest == block_digest0
             && block_digest0 != Chain::genesis().digest()
         {
-            // commit block0
-            let block = &self.generics[&block_digest0].block;
-            let execute = self.chain.commit(block);
+            self.do_commit(block_digest0)
+        }
+    }
+
+    fn do_commit(&mut self, block_digest: BlockDigest) {
+        if self.block_height(&block_digest) <= self.block_height(&self.chain.digest_execute) {
+            return;
+        }
+        // the ancestors that are not committed yet, e.g. the ones followed by timed out views, are
+        // committed along with the block
+        let mut block_digests = vec![block_digest];
+        while self.generics[block_digests.last().unwrap()]
+            .block
+            .parent_digest
+            != self.chain.digest_execute
+        {
+            let parent_digest = self.generics[block_digests.last().unwrap()]
+                .block
+                .parent_digest;
+            block_digests.push(parent_digest)
+        }
+        for block_digest in block_digests.into_iter().rev() {
+            let execute = self.chain.commit(&self.generics[&block_digest].block);
             assert!(execute);
-            for request in &block.requests {
+            assert!(self.chain.next_execute().is_none());
+            self.execute_queue.push_back(block_digest)
+        }
+        self.do_execute()
+    }
+
+    // every batch of the block is certified available
+    fn is_available(&mut self, generic: &Generic) -> bool {
+        let Some(mempool) = &mut self.mempool else {
+            return generic.block.batches.is_empty();
+        };
+        let available = generic.block.batches.len() == generic.batch_certificates.len()
+            && generic
+                .block
+                .batches
+                .iter()
+                .zip(&generic.batch_certificates)
+                .all(|(batch_digest, available)| {
+                    available.batch_digest == *batch_digest
+                        && mempool.is_certified(available, &self.context)
+                });
+        if available {
+            for available in &generic.batch_certificates {
+                mempool.on_available(available.clone(), &self.context)
+            }
+        }
+        available
+    }
+
+    fn do_execute(&mut self) {
+        while let Some(block_digest) = self.execute_queue.front() {
+            let block = &self.generics[block_digest].block;
+            let payload = if block.batches.is_empty() {
+                Vec::new()
+            } else {
+                let mempool = self.mempool.as_mut().unwrap();
+                let Some(payload) = mempool.payload(&block.batches, &mut self.context) else {
+                    break;
+                };
+                mempool.on_executed(&block.batches);
+                payload
+            };
+            self.app.set_op_num(block.height as _);
+            for request in block.requests.iter().chain(&payload) {
+                // ordered again, e.g. submitted to several workers
+                if matches!(
+                    self.replies.get(&request.client_index),
+                    Some((request_num, Some(_))) if *request_num >= request.request_num
+                ) {
+                    continue;
+                }
                 let reply = Reply {
                     request_num: request.request_num,
                     result: self.app.execute(&request.op),
                     replica_index: self.index,
                 };
-                self.replies.insert(
-                    request.client_index,
-                    (request.request_num, Some(reply.clone())),
-                );
+                if self
+                    .replies
+                    .get(&request.client_index)
+                    .is_none_or(|(request_num, _)| *request_num <= request.request_num)
+                {
+                    self.replies.insert(
+                        request.client_index,
+                        (request.request_num, Some(reply.clone())),
+                    );
+                }
                 self.context.send(To::client(request.client_index), reply)
             }
-            assert!(self.chain.next_execute().is_none())
+            self.execute_queue.pop_front();
+        }
+        let replies = &self.replies;
+        self.requests.retain(|request| {
+            matches!(
+                replies.get(&request.client_index),
+                Some((request_num, None)) if *request_num == request.request_num
+            )
+        });
+        if self.view_timer.id.is_some() && !self.has_pending() {
+            self.view_timer.unset(&mut self.context)
         }
     }
 
-    fn do_update_certified(&mut self, digest_certified: &BlockDigest) {
+    fn do_update_certified(&mut self, digest_certified: &BlockDigest, certificate: &Certificate) {
         if self.block_height(digest_certified) > self.block_height(&self.digest_certified) {
-            self.digest_certified = *digest_certified
+            self.digest_certified = *digest_certified;
+            self.certificate = certificate.clone()
         }
     }
 
@@ -394,6 +784,39 @@ impl Replica {
     }
 }
 
+impl Certificate {
+    // voted for `digest` by at least `quorum` distinct replicas, whose signatures are checked by
+    // `verify_certificate`, or the genesis block that needs no votes
+    fn is_quorum(&self, digest: &BlockDigest, quorum: usize) -> bool {
+        if *digest == Chain::genesis().digest() {
+            return true;
+        }
+        let voters = match self {
+            Self::Votes(votes) => {
+                if votes.iter().any(|vote| vote.block_digest != *digest) {
+                    return false;
+                }
+                HashSet::<_>::from_iter(votes.iter().map(|vote| vote.replica_index))
+            }
+            Self::Aggregated(replica_indices, _) => {
+                HashSet::from_iter(replica_indices.iter().copied())
+            }
+        };
+        voters.len() >= quorum
+    }
+
+    fn new<'a>(votes: impl Iterator<Item = &'a Signed<Vote>> + Clone) -> Self {
+        if let Some(signature) = Signature::aggregate(votes.clone()) {
+            Self::Aggregated(
+                Vec::from_iter(votes.map(|vote| vote.replica_index)),
+                signature,
+            )
+        } else {
+            Self::Votes(votes.cloned().collect())
+        }
+    }
+}
+
 impl DigestHash for Reply {
     fn hash(&self, hasher: &mut impl std::hash::Hasher) {
         hasher.write_u32(self.request_num);
@@ -407,17 +830,78 @@ impl DigestHash for Generic {
         self.block.hash(hasher);
         hasher.write(&self.certified_digest);
         self.certificate.hash(hasher);
+        self.timeout_certificate.hash(hasher);
+        self.batch_certificates.hash(hasher);
         hasher.write_u8(self.replica_index)
     }
 }
 
-impl DigestHash for Vote {
+impl DigestHash for Timeout {
     fn hash(&self, hasher: &mut impl std::hash::Hasher) {
-        hasher.write(&self.block_digest);
+        hasher.write_u32(self.view);
+        hasher.write(&self.certified_digest);
+        self.certificate.hash(hasher);
         hasher.write_u8(self.replica_index)
     }
 }
 
+impl DigestHash for Certificate {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        match self {
+            Self::Votes(votes) => votes.hash(hasher),
+            Self::Aggregated(replica_indices, signature) => {
+                hasher.write(replica_indices);
+                signature.hash(hasher)
+            }
+        }
+    }
+}
+
+// the voter is bound by its key rather than the hash, so the votes of a block are signed over the
+// same digest, which BLS signatures are aggregated over
+impl DigestHash for Vote {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write(&self.block_digest)
+    }
+}
+
+impl Tamper for Message {
+    // the same view proposed without the requests
+    fn conflict(&self, signer: &Signer) -> Option<Self> {
+        let Self::Generic(message) = self else {
+            return None;
+        };
+        let mut generic = message.inner.clone();
+        if generic.block.requests.is_empty() {
+            return None;
+        }
+        generic.block.requests.clear();
+        Some(Self::sign(generic, signer))
+    }
+
+    fn is_vote(&self) -> bool {
+        matches!(self, Self::Vote(_) | Self::Timeout(_))
+    }
+
+    fn corrupt(&self, signer: &Signer) -> Option<Self> {
+        match self {
+            Self::Generic(message) => {
+                let mut generic = message.inner.clone();
+                garble(&mut generic.block.parent_digest);
+                Some(Self::sign(generic, signer))
+            }
+            Self::Vote(message) => {
+                let mut vote = message.inner.clone();
+                garble(&mut vote.block_digest);
+                Some(Self::sign(vote, signer))
+            }
+            // a timeout carries the certificate of its digest, so a garbled one fails verification
+            // instead of misleading the next leader
+            _ => None,
+        }
+    }
+}
+
 impl Sign<Request> for Message {
     fn sign(message: Request, signer: &crate::context::crypto::Signer) -> Self {
         Self::Request(signer.sign_private(message))
@@ -442,26 +926,138 @@ impl Sign<Vote> for Message {
     }
 }
 
+impl Sign<Timeout> for Message {
+    fn sign(message: Timeout, signer: &crate::context::crypto::Signer) -> Self {
+        Self::Timeout(signer.sign_public(message))
+    }
+}
+
+impl Sign<BatchAck> for Message {
+    fn sign(message: BatchAck, signer: &crate::context::crypto::Signer) -> Self {
+        Self::BatchAck(signer.sign_public(message))
+    }
+}
+
+impl Sign<Batch> for Message {
+    fn sign(message: Batch, signer: &crate::context::crypto::Signer) -> Self {
+        Self::Batch(signer.sign_private(message))
+    }
+}
+
+// a fetched batch, as signed by its worker
+impl From<Signed<Batch>> for Message {
+    fn from(value: Signed<Batch>) -> Self {
+        Self::Batch(value)
+    }
+}
+
+impl From<Available> for Message {
+    fn from(value: Available) -> Self {
+        Self::Available(value)
+    }
+}
+
+impl Sign<FetchBatch> for Message {
+    fn sign(message: FetchBatch, signer: &crate::context::crypto::Signer) -> Self {
+        Self::FetchBatch(signer.sign_private(message))
+    }
+}
+
+fn verify_certificate(
+    verifier: &crate::context::crypto::Verifier,
+    certified_digest: &BlockDigest,
+    certificate: &Certificate,
+) -> Result<(), crate::context::crypto::Invalid> {
+    if *certified_digest == Chain::genesis().digest() {
+        return Ok(());
+    }
+    // the size is checked by the receiving replica that knows the quorum, see
+    // `Certificate::is_quorum`
+    match certificate {
+        Certificate::Votes(votes) => {
+            if votes
+                .iter()
+                .any(|vote| vote.block_digest != *certified_digest)
+            {
+                return Err(crate::context::crypto::Invalid::Public);
+            }
+            verifier.verify_batch(votes.iter().map(|vote| (vote, vote.replica_index)))
+        }
+        Certificate::Aggregated(replica_indices, signature) => {
+            // any voter index stands for all, as it is not hashed
+            let vote = Vote {
+                block_digest: *certified_digest,
+                replica_index: 0,
+            };
+            verifier.verify_aggregated(&vote, replica_indices, signature)
+        }
+    }
+}
+
 impl Verify for Message {
     fn verify(
         &self,
         verifier: &crate::context::crypto::Verifier,
     ) -> Result<(), crate::context::crypto::Invalid> {
         match self {
-            Self::Request(message) => verifier.verify(message, None),
+            Self::Request(message) => verifier.verify_client(message, message.client_index),
             Self::Reply(message) => verifier.verify(message, message.replica_index),
             Self::Generic(message) => {
                 verifier.verify(message, message.replica_index)?;
-                if message.certified_digest == Chain::genesis().digest() {
-                    return Ok(());
-                }
-                // TODO check certification size
-                for vote in &message.certificate {
-                    verifier.verify(vote, vote.replica_index)?
+                verify_certificate(verifier, &message.certified_digest, &message.certificate)?;
+                for available in &message.batch_certificates {
+                    available.verify(verifier)?
                 }
-                Ok(())
+                verifier.verify_batch(
+                    message
+                        .timeout_certificate
+                        .iter()
+                        .map(|timeout| (timeout, timeout.replica_index)),
+                )
             }
             Self::Vote(message) => verifier.verify(message, message.replica_index),
+            Self::Timeout(message) => {
+                verifier.verify(message, message.replica_index)?;
+                verify_certificate(verifier, &message.certified_digest, &message.certificate)
+            }
+            Self::BatchAck(message) => verifier.verify(message, message.replica_index),
+            Self::Available(message) => message.verify(verifier),
+            Self::Batch(message) => verifier.verify(message, message.replica_index),
+            Self::FetchBatch(message) => verifier.verify(message, message.replica_index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(block_digest: BlockDigest, replica_index: ReplicaIndex) -> Signed<Vote> {
+        Signed {
+            inner: Vote {
+                block_digest,
+                replica_index,
+            },
+            signature: Signature::Plain,
+            key_epoch: 0,
         }
     }
+
+    // a faulty replica's timeout cannot raise qc_high with fewer votes than a quorum
+    #[test]
+    fn certificate_quorum() {
+        let digest = [1; 32];
+        assert!(Certificate::Votes(Default::default()).is_quorum(&Chain::genesis().digest(), 3));
+        assert!(!Certificate::Votes(Default::default()).is_quorum(&digest, 3));
+        let votes = Vec::from_iter((0..3).map(|index| vote(digest, index)));
+        assert!(Certificate::Votes(votes.clone()).is_quorum(&digest, 3));
+        assert!(!Certificate::Votes(votes.clone()).is_quorum(&[2; 32], 3));
+        let mut repeated = votes.clone();
+        repeated[2] = vote(digest, 0);
+        assert!(!Certificate::Votes(repeated).is_quorum(&digest, 3));
+        let mut mixed = votes;
+        mixed[2] = vote([2; 32], 2);
+        assert!(!Certificate::Votes(mixed).is_quorum(&digest, 3));
+        assert!(!Certificate::Aggregated(vec![0, 0, 1], Signature::Plain).is_quorum(&digest, 3))
+    }
 }
@@ -2,13 +2,103 @@ pub mod app;
 pub mod client;
 pub mod common;
 pub mod context;
+#[cfg(feature = "hotstuff")]
 pub mod hotstuff;
+#[cfg(feature = "minbft")]
 pub mod minbft;
+#[cfg(feature = "neo")]
 pub mod neo;
+#[cfg(feature = "pbft")]
 pub mod pbft;
+#[cfg(feature = "sbft")]
+pub mod sbft;
+#[cfg(feature = "tendermint")]
+pub mod tendermint;
+#[cfg(feature = "unreplicated")]
 pub mod unreplicated;
+#[cfg(feature = "zyzzyva")]
 pub mod zyzzyva;
 
 pub use app::App;
 pub use client::Client;
 pub use context::Context;
+
+// the modes and apps compiled into this build, reported on `/build-info`
+// filtered from the lists of control messages, so a mode or app is named in one place only
+pub fn modes() -> Vec<&'static str> {
+    // a mode is named after its protocol, optionally followed by a variant
+    let compiled = [
+        ("unreplicated", cfg!(feature = "unreplicated")),
+        ("neo", cfg!(feature = "neo")),
+        ("pbft", cfg!(feature = "pbft")),
+        ("zyzzyva", cfg!(feature = "zyzzyva")),
+        ("hotstuff", cfg!(feature = "hotstuff")),
+        ("minbft", cfg!(feature = "minbft")),
+        ("sbft", cfg!(feature = "sbft")),
+        ("tendermint", cfg!(feature = "tendermint")),
+    ];
+    Vec::from_iter(
+        control_messages::MODES
+            .iter()
+            .copied()
+            .filter(|mode| compiled.contains(&(mode.split('-').next().unwrap(), true))),
+    )
+}
+
+// diagnostics go to stdout, or as JSON lines to the file at `TRACING_FILE`, e.g. to be collected from
+// remote hosts after a run, filtered by `RUST_LOG` which defaults to info level
+pub fn init_tracing() {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    if let Some(path) = std::env::var_os("TRACING_FILE") {
+        let file = std::fs::File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        subscriber
+            .json()
+            .with_writer(std::sync::Mutex::new(file))
+            .init()
+    } else {
+        subscriber.init()
+    }
+}
+
+pub fn apps() -> Vec<&'static str> {
+    let compiled = [
+        ("null", true),
+        ("ycsb", cfg!(feature = "ycsb")),
+        ("kvstore", cfg!(feature = "kvstore")),
+        ("wasm", cfg!(feature = "wasm")),
+    ];
+    Vec::from_iter(
+        control_messages::APPS
+            .iter()
+            .copied()
+            .filter(|&app| compiled.contains(&(app, true))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    #[cfg(all(
+        feature = "unreplicated",
+        feature = "neo",
+        feature = "pbft",
+        feature = "zyzzyva",
+        feature = "hotstuff",
+        feature = "minbft",
+        feature = "sbft",
+        feature = "tendermint",
+        feature = "ycsb",
+        feature = "kvstore",
+        feature = "wasm"
+    ))]
+    fn registry_complete() {
+        assert_eq!(super::modes(), control_messages::MODES);
+        assert_eq!(super::apps(), control_messages::APPS);
+    }
+}
@@ -1,27 +1,90 @@
 use std::{
     collections::HashMap,
+    io::ErrorKind,
     mem::replace,
-    sync::{Arc, Mutex},
+    net::{SocketAddr, UdpSocket},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc, Mutex,
+    },
     time::Duration,
 };
 
 use axum::{
     extract::State,
+    http::{header::AUTHORIZATION, Request, StatusCode},
+    middleware::{self, Next},
+    response::Response,
     routing::{get, post},
     Json, Router, Server,
 };
-use control_messages::{BenchmarkStats, Role, Task};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use control_messages::{
+    jain_index, BenchmarkStats, BuildInfo, ByzantineStrategy, ChaosAction, GroupLatency, Liveness,
+    ReplicaStats, Role, RotateKeys, SignatureScheme, SwitchStrictness, Task,
+};
+#[cfg(feature = "kvstore")]
+use permissioned_blockchain::app::kvstore;
+#[cfg(feature = "wasm")]
+use permissioned_blockchain::app::wasm;
+#[cfg(feature = "ycsb")]
+use permissioned_blockchain::app::ycsb;
+#[cfg(feature = "pbft")]
+use permissioned_blockchain::context::storage::Storage;
+#[cfg(feature = "hotstuff")]
+use permissioned_blockchain::hotstuff;
+#[cfg(feature = "minbft")]
+use permissioned_blockchain::minbft;
+#[cfg(feature = "neo")]
+use permissioned_blockchain::neo;
+#[cfg(feature = "pbft")]
+use permissioned_blockchain::pbft;
+#[cfg(feature = "sbft")]
+use permissioned_blockchain::sbft;
+#[cfg(feature = "tendermint")]
+use permissioned_blockchain::tendermint;
+#[cfg(feature = "unreplicated")]
+use permissioned_blockchain::unreplicated;
+#[cfg(feature = "zyzzyva")]
+use permissioned_blockchain::zyzzyva;
 use permissioned_blockchain::{
-    app::{ycsb, Workload},
+    app::{ExecutionIndex, SelfCheck, Workload},
     client::{run_benchmark, RunBenchmarkConfig},
     common::set_affinity,
-    context::{ordered_multicast::Variant, tokio::Dispatch, Config, Host},
-    hotstuff, minbft, neo, pbft, unreplicated, zyzzyva, App,
+    context::{
+        byzantine::Tamper,
+        chaos::Chaos,
+        clock,
+        crypto::{KeyRing, StrictnessSwitch},
+        keys,
+        metrics::Metrics,
+        ordered_multicast::{SequencerVerifyingKey, Variant},
+        shaping::Shaping,
+        sub_seed,
+        tokio::Dispatch,
+        Config, Host, ReplicaIndex,
+    },
+    App, Context,
 };
 use rand::{rngs::StdRng, SeedableRng};
+use serde::{de::DeserializeOwned, Serialize};
+use subtle::ConstantTimeEq;
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
+#[cfg(not(any(
+    feature = "unreplicated",
+    feature = "neo",
+    feature = "pbft",
+    feature = "zyzzyva",
+    feature = "hotstuff",
+    feature = "minbft",
+    feature = "sbft",
+    feature = "tendermint"
+)))]
+compile_error!("enable at least one protocol feature");
+
 #[derive(Debug)]
 enum AppState {
     Idle, // TODO exit on timeout
@@ -29,17 +92,157 @@ enum AppState {
 
     BenchmarkClientRunning,
     BenchmarkClientFinish {
-        stats: BenchmarkStats,
+        stats: Box<BenchmarkStats>,
     },
     ReplicaRunning {
         cancel: CancellationToken,
-        task: JoinHandle<()>,
+        task: JoinHandle<ReplicaStats>,
+        // only for the protocols that support standby
+        standby: Option<Arc<AtomicBool>>,
+        // available once the replica is ready, if verification is enabled
+        strictness: Option<StrictnessSwitch>,
+        key_ring: Option<KeyRing>,
+        index: ReplicaIndex,
+        variant: Arc<Variant>,
+        chaos: Arc<Chaos>,
+        metrics: Arc<Metrics>,
     },
 }
 
-async fn set_task(State(state): State<Arc<Mutex<AppState>>>, Json(task): Json<Task>) {
+async fn set_task(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Json(task): Json<Task>,
+) -> Result<(), (StatusCode, String)> {
     assert!(matches!(*state.lock().unwrap(), AppState::Idle));
+    // refused without panicking, the worker is fine for the next task of a compiled in mode
+    let app = match task.app {
+        control_messages::App::Null => "null",
+        control_messages::App::Ycsb(_) => "ycsb",
+        control_messages::App::KvStore(_) => "kvstore",
+        control_messages::App::Wasm(_) => "wasm",
+    };
+    for (kind, name, compiled) in [
+        ("mode", &*task.mode, permissioned_blockchain::modes()),
+        ("app", app, permissioned_blockchain::apps()),
+    ] {
+        if !compiled.contains(&name) {
+            return Err((
+                StatusCode::NOT_IMPLEMENTED,
+                format!("{kind} {name} is not compiled in, available: {compiled:?}"),
+            ));
+        }
+    }
+    if let Some(destination) = task.client_destination {
+        destination
+            .check(&task.mode)
+            .map_err(|err| (StatusCode::BAD_REQUEST, err))?
+    }
+    if let Err(err) = task.network_model.check() {
+        return Err((StatusCode::BAD_REQUEST, format!("network model: {err}")));
+    }
+    if let Some(batch) = task.socket.batch {
+        if !(1..=control_messages::SocketConfig::MAX_BATCH).contains(&batch) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "socket batch {batch} not in 1..={}",
+                    control_messages::SocketConfig::MAX_BATCH
+                ),
+            ));
+        }
+    }
+    if let Role::BenchmarkClient(config) = &task.role {
+        config
+            .check()
+            .map_err(|err| (StatusCode::BAD_REQUEST, err))?
+    }
+    if let control_messages::App::Ycsb(config) = &task.app {
+        config
+            .check()
+            .map_err(|err| (StatusCode::BAD_REQUEST, format!("YCSB: {err}")))?
+    }
+    if task.persist.is_some() && task.mode != "pbft" {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("{} does not support persistence", task.mode),
+        ));
+    }
+    // before the addresses of the role are looked up
+    if let Role::Replica(replica) = &task.role {
+        let liveness = Liveness::new(&task.mode, task.num_faulty);
+        if replica.index as usize >= liveness.num_replica {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "replica {} does not participate in {}",
+                    replica.index, task.mode
+                ),
+            ));
+        }
+        if replica.standby {
+            // confirm mode cannot work with standby, as confirms are sent to the replaced replica
+            if !matches!(&*task.mode, "neo-hm" | "neo-pk") {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("{} does not support standby", task.mode),
+                ));
+            }
+            if !task
+                .standby_addrs
+                .iter()
+                .any(|&(index, _)| index == replica.index)
+            {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("standby of replica {} has no address", replica.index),
+                ));
+            }
+        } else if replica.index as usize >= task.replica_addrs.len() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("replica {} has no address", replica.index),
+            ));
+        }
+    }
+    // loaded before anything starts, so a missing or invalid module fails the task
+    #[cfg(feature = "wasm")]
+    let wasm_app = match (&task.role, &task.app) {
+        (Role::Replica(_), control_messages::App::Wasm(config)) => {
+            let app = std::fs::read(&config.module)
+                .map_err(|err| err.to_string())
+                .and_then(|module| {
+                    wasm::App::new(&module, config.fuel.unwrap_or(wasm::DEFAULT_FUEL))
+                        .map_err(|err| err.to_string())
+                });
+            match app {
+                Ok(app) => Some(app),
+                Err(err) => {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        format!("loading {}: {err}", config.module),
+                    ))
+                }
+            }
+        }
+        _ => None,
+    };
+    let addrs = task_addrs(&task);
+    // distinct per host, as no two hosts bind the same address
+    let seed = sub_seed(task.seed, &addrs);
+    let busy = busy_addrs(addrs).await;
+    if !busy.is_empty() {
+        let busy = Vec::from_iter(busy.into_iter().map(|addr| {
+            let owners = socket_owners(addr.port());
+            format!("{addr} owned by {owners:?}")
+        }));
+        tracing::warn!(?busy, "task addresses busy");
+        return Err((StatusCode::CONFLICT, busy.join(", ")));
+    }
 
+    let standby_index = match &task.role {
+        Role::Replica(replica) if replica.standby => Some(replica.index),
+        _ => None,
+    };
     let mut addrs = HashMap::new();
     for (index, addr) in task.client_addrs.into_iter().enumerate() {
         addrs.insert(Host::Client(index as _), addr);
@@ -47,18 +250,117 @@ async fn set_task(State(state): State<Arc<Mutex<AppState>>>, Json(task): Json<Ta
     for (index, addr) in task.replica_addrs.into_iter().enumerate() {
         addrs.insert(Host::Replica(index as _), addr);
     }
+    // a standby runs as the replica it stands by for, only at its own address
+    for &(index, addr) in &task.standby_addrs {
+        if Some(index) == standby_index {
+            addrs.insert(Host::Replica(index), addr);
+        }
+    }
     let mut dispatch_config = Config::new(addrs, task.num_faulty);
-    dispatch_config.multicast_addr = Some(task.multicast_addr);
+    for &(index, addr) in &task.standby_addrs {
+        if Some(index) != standby_index {
+            dispatch_config.insert_standby(index, addr)
+        }
+    }
+    dispatch_config
+        .multicast_addrs
+        .clone_from(&task.multicast_addrs);
+    dispatch_config.multicast_sources = task
+        .multicast_sources
+        .map(|sources| sources.into_iter().collect());
+    dispatch_config.socket = task.socket;
+    dispatch_config.client_table_capacity = task.client_table_capacity;
+    dispatch_config.reject_stale = task.reject_stale;
+    dispatch_config.request_workers = task.request_workers;
+    dispatch_config.client_secret = task.client_secret.map(String::into_bytes);
+    if task.key_dir.is_some() && !task.standby_addrs.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("the keys of standbys are derived rather than loaded"),
+        ));
+    }
+    if let Some(key_dir) = &task.key_dir {
+        let index = match &task.role {
+            Role::Replica(replica) => Some(replica.index),
+            Role::BenchmarkClient(_) => None,
+        };
+        if let Err(err) = dispatch_config.load_keys(key_dir, index) {
+            tracing::warn!(%err, key_dir, "loading keys");
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("loading keys from {key_dir}: {err}"),
+            ));
+        }
+    }
+    if let Some(index) = standby_index {
+        dispatch_config.sign_as_standby(index)
+    }
+    dispatch_config.key_epoch = task.key_epoch;
+    if let Some(key_dir) = &task.previous_key_dir {
+        match keys::load(key_dir.as_ref(), None) {
+            Ok((_, public_keys)) => {
+                dispatch_config.previous_keys = Some((public_keys, task.key_grace))
+            }
+            Err(err) => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("loading previous keys from {key_dir}: {err}"),
+                ))
+            }
+        }
+    }
+    // neo without confirm executes speculatively, and keeps what undoes the executed ops until they
+    // pass a stable checkpoint
+    if matches!(task.role, Role::Replica(_))
+        && matches!(&*task.mode, "neo-hm" | "neo-pk")
+        && task.checkpoint_interval == 0
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("{} requires a checkpoint interval", task.mode),
+        ));
+    }
+    dispatch_config.checkpoint_interval = task.checkpoint_interval;
+    dispatch_config.max_inflight = task.max_inflight;
+    dispatch_config.sequencing_timing = task.sequencing_timing;
+    dispatch_config.client_destination = task.client_destination;
+    dispatch_config.seed = seed;
+    if let Some(scheme) = task.public_scheme {
+        dispatch_config.public_scheme = scheme
+    }
+    // votes signed with BLS, which the leader aggregates into one signature per certificate
+    if task.mode == "hotstuff-bls" {
+        dispatch_config
+            .signature_policy
+            .insert("Vote", SignatureScheme::Bls)
+    }
+    for (class, scheme) in task.signature_policy {
+        dispatch_config.signature_policy.insert(class, scheme)
+    }
 
+    // not derived per host, as the replicas must populate identical YCSB tables
     let mut rng = StdRng::seed_from_u64(task.seed);
     match task.role {
         Role::BenchmarkClient(config) => {
             *state.lock().unwrap() = AppState::BenchmarkClientRunning;
             let workload = match task.app {
                 control_messages::App::Null => Workload::Null,
+                #[cfg(feature = "ycsb")]
                 control_messages::App::Ycsb(config) => {
                     Workload::Ycsb(ycsb::Workload::new(config.into(), &mut rng))
                 }
+                #[cfg(not(feature = "ycsb"))]
+                control_messages::App::Ycsb(_) => unreachable!(),
+                #[cfg(feature = "kvstore")]
+                control_messages::App::KvStore(config) => {
+                    Workload::KvStore(kvstore::Workload::new(config.into(), &mut rng))
+                }
+                #[cfg(not(feature = "kvstore"))]
+                control_messages::App::KvStore(_) => unreachable!(),
+                #[cfg(feature = "wasm")]
+                control_messages::App::Wasm(config) => Workload::Wasm(wasm::Workload::new(&config)),
+                #[cfg(not(feature = "wasm"))]
+                control_messages::App::Wasm(_) => unreachable!(),
             };
 
             let benchmark_config = RunBenchmarkConfig {
@@ -68,45 +370,153 @@ async fn set_task(State(state): State<Arc<Mutex<AppState>>>, Json(task): Json<Ta
                 num_client: config.num_client,
                 duration: config.duration,
                 workload,
+                skew: config.skew,
+                load: config.load,
+                history: config.history.then(history_path),
+                network_model: task.network_model,
             };
             // println!("{benchmark_config:?}");
             let state = state.clone();
             tokio::task::spawn_blocking(move || {
-                let latencies = match &*task.mode {
+                let mut result = match &*task.mode {
+                    #[cfg(feature = "unreplicated")]
                     "unreplicated" => run_benchmark(benchmark_config, unreplicated::Client::new),
+                    #[cfg(feature = "unreplicated")]
+                    "unreplicated-om" => {
+                        run_benchmark(benchmark_config, unreplicated::Client::new_ordered)
+                    }
+                    #[cfg(feature = "neo")]
                     "neo-hm" | "neo-pk" | "neo-bn" => {
                         run_benchmark(benchmark_config, neo::Client::new)
                     }
+                    #[cfg(feature = "pbft")]
                     "pbft" => run_benchmark(benchmark_config, pbft::Client::new),
+                    #[cfg(feature = "pbft")]
+                    "pbft-sq" => run_benchmark(benchmark_config, pbft::Client::new_ordered),
+                    #[cfg(feature = "zyzzyva")]
                     "zyzzyva" | "zyzzyva-f" => run_benchmark(benchmark_config, |context, index| {
                         zyzzyva::Client::new(context, index, task.mode == "zyzzyva-f")
                     }),
-                    "hotstuff" => run_benchmark(benchmark_config, hotstuff::Client::new),
+                    #[cfg(feature = "hotstuff")]
+                    "hotstuff" | "hotstuff-bls" => {
+                        run_benchmark(benchmark_config, hotstuff::Client::new)
+                    }
+                    #[cfg(feature = "hotstuff")]
+                    "hotstuff-mempool" => {
+                        run_benchmark(benchmark_config, hotstuff::Client::new_mempool)
+                    }
+                    #[cfg(feature = "minbft")]
                     "minbft" => run_benchmark(benchmark_config, minbft::Client::new),
+                    #[cfg(feature = "sbft")]
+                    "sbft" => run_benchmark(benchmark_config, sbft::Client::new),
+                    #[cfg(feature = "tendermint")]
+                    "tendermint" => run_benchmark(benchmark_config, tendermint::Client::new),
                     _ => unimplemented!(),
                 };
+                let num_op = result.latencies.iter().map(Vec::len).sum::<usize>();
+                let average_latency = result
+                    .latencies
+                    .iter()
+                    .flatten()
+                    .sum::<Duration>()
+                    .checked_div(num_op as u32);
+                let groups = Vec::from_iter(
+                    result
+                        .latencies
+                        .iter_mut()
+                        .map(|latencies| GroupLatency::new(latencies)),
+                );
+                let merged = |latencies: Vec<Vec<Duration>>| {
+                    let mut latencies = Vec::from_iter(latencies.into_iter().flatten());
+                    if latencies.is_empty() {
+                        None
+                    } else {
+                        Some(GroupLatency::new(&mut latencies))
+                    }
+                };
+                let first_reply = merged(result.first_reply_latencies);
+                let heavy_hitters = merged(result.heavy_latencies);
                 *state.lock().unwrap() = AppState::BenchmarkClientFinish {
-                    stats: BenchmarkStats {
-                        throughput: latencies.len() as f32 / config.duration.as_secs_f32(),
-                        average_latency: latencies
-                            .iter()
-                            .sum::<Duration>()
-                            .checked_div(latencies.len() as u32),
-                    },
+                    stats: Box::new(BenchmarkStats {
+                        throughput: num_op as f32 / config.duration.as_secs_f32(),
+                        average_latency,
+                        socket_drops: result.socket_drops,
+                        latency_fairness: jain_index(
+                            groups
+                                .iter()
+                                .filter_map(|group| group.average)
+                                .map(|average| average.as_secs_f64()),
+                        ),
+                        groups,
+                        first_reply,
+                        heavy_hitters,
+                        latency_histogram: result.latency_histogram,
+                        throughput_timeline: result.timeline,
+                    }),
                 };
             });
         }
         Role::Replica(replica) => {
-            let app = match task.app {
+            let supports_standby = matches!(&*task.mode, "neo-hm" | "neo-pk" | "neo-bn");
+            let standby = Arc::new(AtomicBool::new(replica.standby));
+            let chaos = Arc::new(Chaos::new(Some(replica.index)));
+            let metrics = Arc::new(Metrics::default());
+            #[cfg_attr(
+                not(any(feature = "ycsb", feature = "kvstore")),
+                allow(unused_variables)
+            )]
+            let new_app = |rng: &mut StdRng| match &task.app {
                 control_messages::App::Null => App::Null,
-                control_messages::App::Ycsb(config) => {
-                    App::Ycsb(ycsb::Workload::app(config.into(), &mut rng))
+                #[cfg(feature = "ycsb")]
+                &control_messages::App::Ycsb(config) => {
+                    App::Ycsb(ycsb::Workload::app(config.into(), rng))
+                }
+                #[cfg(not(feature = "ycsb"))]
+                control_messages::App::Ycsb(_) => unreachable!(),
+                #[cfg(feature = "kvstore")]
+                &control_messages::App::KvStore(config) => {
+                    App::KvStore(kvstore::Workload::app(config.into(), rng))
                 }
+                #[cfg(not(feature = "kvstore"))]
+                control_messages::App::KvStore(_) => unreachable!(),
+                #[cfg(feature = "wasm")]
+                control_messages::App::Wasm(_) => App::Wasm(Box::new(wasm_app.clone().unwrap())),
+                #[cfg(not(feature = "wasm"))]
+                control_messages::App::Wasm(_) => unreachable!(),
             };
+            let mut app = new_app(&mut rng);
+            if task.self_check_rate != 0. {
+                app = App::SelfCheck(Box::new(SelfCheck::new(
+                    app,
+                    new_app(&mut StdRng::seed_from_u64(task.seed)),
+                    task.self_check_rate,
+                    dispatch_config.rng("self-check"),
+                )))
+            }
+            let execution_index = ExecutionIndex::new(app, ReplicaStats::EXECUTION_INDEX_INTERVAL);
+            let execution_entries = execution_index.entries.clone();
+            let app = App::Indexed(Box::new(execution_index));
+
+            let variant = Arc::new(match &*task.mode {
+                "neo-hm" | "unreplicated-om" | "pbft-sq" => {
+                    Variant::new_half_sip_hash(replica.index, task.min_mac_coverage)
+                        .with_mac_pattern(task.mac_pattern)
+                }
+                "neo-pk" | "neo-bn" => {
+                    Variant::new_ecdsa(task.sequencer_curve).with_sign_interval(task.sign_interval)
+                }
+                _ => Variant::Unreachable,
+            });
 
             let cancel = CancellationToken::new();
+            let (ready, ready_receiver) = flume::bounded(1);
             let task = tokio::task::spawn_blocking({
                 let cancel = cancel.clone();
+                let variant = variant.clone();
+                #[cfg(feature = "neo")]
+                let standby = standby.clone();
+                let chaos = chaos.clone();
+                let metrics = metrics.clone();
                 move || {
                     let runtime = tokio::runtime::Builder::new_current_thread()
                         .enable_all()
@@ -116,91 +526,381 @@ async fn set_task(State(state): State<Arc<Mutex<AppState>>>, Json(task): Json<Ta
                         dispatch_config,
                         runtime.handle().clone(),
                         true,
-                        match &*task.mode {
-                            "neo-hm" => Variant::new_half_sip_hash(replica.index),
-                            "neo-pk" | "neo-bn" => Variant::new_k256(),
-                            _ => Variant::Unreachable,
-                        },
+                        variant.clone(),
                     );
+                    let strictness = dispatch.strictness();
+                    let key_ring = dispatch.key_ring();
+                    dispatch.chaos = chaos.clone();
+                    dispatch.shaping = Shaping::new(&task.network_model, replica.index);
+                    dispatch.metrics = metrics.clone();
+                    // for replaying the run of this replica offline, see `Dispatch::replay`
+                    dispatch.trace = task
+                        .trace
+                        .as_ref()
+                        .map(|dir| PathBuf::from(dir).join(format!("trace-{}.bin", replica.index)));
+                    dispatch.executor = executor(task.request_workers);
 
                     let handle = dispatch.handle();
-                    std::thread::spawn(move || {
-                        set_affinity(0);
-                        runtime.block_on(async move {
-                            cancel.cancelled().await;
-                            handle.stop_async().await
-                        });
-                        runtime.shutdown_background()
-                    });
+                    // joined after the replica stops, so the sockets are released before the task
+                    // is reset and the next one binds them
+                    let runtime_thread = std::thread::Builder::new()
+                        .name(String::from("replica-runtime"))
+                        .spawn(move || {
+                            set_affinity(0);
+                            runtime.block_on(async move {
+                                cancel.cancelled().await;
+                                handle.stop_async().await
+                            });
+                            runtime.shutdown_background()
+                        })
+                        .unwrap();
 
                     set_affinity(1);
-                    match &*task.mode {
+                    let stats = |dispatch: &Dispatch| {
+                        let (queueing_delay, processing_delay) = dispatch.ingress_delays();
+                        ReplicaStats {
+                            socket_drops: dispatch.socket_drops(),
+                            mac_coverage: variant.coverage(),
+                            signatures: variant.signatures(),
+                            spoofed_multicast: dispatch.spoofed_multicast(),
+                            invalid_multicast: dispatch.invalid_multicast(),
+                            stale: dispatch.stale(),
+                            client_table: None,
+                            query_trigger: None,
+                            waiting: None,
+                            strictness_phases: Default::default(),
+                            queueing_delay,
+                            processing_delay,
+                            socket_delay: dispatch.socket_delay(),
+                            utilization: dispatch.utilization(),
+                            multicast_sources: Default::default(),
+                            sequencing: dispatch.sequencing(),
+                            execution_index: execution_entries.lock().unwrap().clone(),
+                        }
+                    };
+                    let byzantine = (task.byzantine.iter())
+                        .find(|(faulty, _)| *faulty == replica.index)
+                        .map(|&(_, strategy)| strategy);
+                    let replica_stats = match &*task.mode {
+                        #[cfg(feature = "unreplicated")]
                         "unreplicated" => {
-                            assert_eq!(replica.index, 0);
                             let mut replica = unreplicated::Replica::new(
-                                dispatch.register(Host::Replica(0)),
+                                register_replica(&dispatch, 0, byzantine),
                                 app,
                             );
                             // replica.make_blocks = true;
-                            dispatch.run(&mut replica)
+                            replica.warm_up(task.warm_up_entry_num);
+                            ready.send((strictness.clone(), key_ring.clone())).unwrap();
+                            dispatch.run(&mut replica);
+                            ReplicaStats {
+                                client_table: Some(replica.client_table.stats),
+                                ..stats(&dispatch)
+                            }
                         }
+                        #[cfg(feature = "unreplicated")]
+                        "unreplicated-om" => {
+                            let mut replica = unreplicated::Replica::new(
+                                register_replica(&dispatch, 0, byzantine),
+                                app,
+                            );
+                            replica.warm_up(task.warm_up_entry_num);
+                            let dispatch = dispatch.enable_ordered_multicast();
+                            ready.send((strictness.clone(), key_ring.clone())).unwrap();
+                            dispatch.run(&mut replica);
+                            ReplicaStats {
+                                client_table: Some(replica.client_table.stats),
+                                multicast_sources: replica.reorder.source_stats(),
+                                ..stats(&dispatch)
+                            }
+                        }
+                        #[cfg(feature = "neo")]
                         "neo-hm" | "neo-pk" | "neo-bn" => {
                             let mut replica = neo::Replica::new(
-                                dispatch.register(Host::Replica(replica.index)),
+                                register_replica(&dispatch, replica.index, byzantine),
                                 replica.index,
                                 app,
                                 task.mode == "neo-bn",
                             );
+                            replica.standby = standby;
+                            replica.standby_host = standby_index.is_some();
+                            replica.strictness = strictness.clone();
                             dispatch.drop_rate = task.drop_rate;
-                            dispatch.enable_ordered_multicast().run(&mut replica)
+                            replica.warm_up(task.warm_up_entry_num);
+                            let dispatch = dispatch.enable_ordered_multicast();
+                            ready.send((strictness.clone(), key_ring.clone())).unwrap();
+                            dispatch.run(&mut replica);
+                            ReplicaStats {
+                                client_table: Some(replica.client_table.stats),
+                                query_trigger: Some(replica.query_trigger.stats),
+                                multicast_sources: replica.reorder.source_stats(),
+                                waiting: Some(replica.waiting_stats()),
+                                strictness_phases: Vec::from_iter(
+                                    strictness.iter().flat_map(StrictnessSwitch::phases),
+                                ),
+                                ..stats(&dispatch)
+                            }
                         }
+                        #[cfg(feature = "pbft")]
                         "pbft" => {
+                            let index = replica.index;
                             let mut replica = pbft::Replica::new(
-                                dispatch.register(Host::Replica(replica.index)),
+                                register_replica(&dispatch, index, byzantine),
+                                index,
+                                app,
+                            );
+                            replica.warm_up(task.warm_up_entry_num);
+                            if let Some(dir) = &task.persist {
+                                replica.recover(Storage::open(dir, Host::Replica(index)))
+                            }
+                            ready.send((strictness.clone(), key_ring.clone())).unwrap();
+                            dispatch.run(&mut replica);
+                            ReplicaStats {
+                                waiting: Some(replica.waiting_stats()),
+                                ..stats(&dispatch)
+                            }
+                        }
+                        #[cfg(feature = "pbft")]
+                        "pbft-sq" => {
+                            let mut replica = pbft::Replica::new_sequenced(
+                                register_replica(&dispatch, replica.index, byzantine),
                                 replica.index,
                                 app,
                             );
-                            dispatch.run(&mut replica)
+                            replica.warm_up(task.warm_up_entry_num);
+                            let dispatch = dispatch.enable_ordered_multicast();
+                            ready.send((strictness.clone(), key_ring.clone())).unwrap();
+                            dispatch.run(&mut replica);
+                            ReplicaStats {
+                                waiting: Some(replica.waiting_stats()),
+                                multicast_sources: replica.reorder.source_stats(),
+                                ..stats(&dispatch)
+                            }
                         }
+                        #[cfg(feature = "zyzzyva")]
                         "zyzzyva" | "zyzzyva-f" => {
                             let mut replica = zyzzyva::Replica::new(
-                                dispatch.register(Host::Replica(replica.index)),
+                                register_replica(&dispatch, replica.index, byzantine),
                                 replica.index,
                                 app,
                             );
-                            dispatch.run(&mut replica)
+                            replica.warm_up(task.warm_up_entry_num);
+                            ready.send((strictness.clone(), key_ring.clone())).unwrap();
+                            dispatch.run(&mut replica);
+                            stats(&dispatch)
                         }
-                        "hotstuff" => {
-                            let mut replica = hotstuff::Replica::new(
-                                dispatch.register(Host::Replica(replica.index)),
+                        #[cfg(feature = "hotstuff")]
+                        "hotstuff" | "hotstuff-bls" | "hotstuff-mempool" => {
+                            let new = if task.mode == "hotstuff-mempool" {
+                                hotstuff::Replica::new_mempool
+                            } else {
+                                hotstuff::Replica::new
+                            };
+                            let mut replica = new(
+                                register_replica(&dispatch, replica.index, byzantine),
                                 replica.index,
                                 app,
                             );
-                            dispatch.run(&mut replica)
+                            replica.warm_up(task.warm_up_entry_num);
+                            ready.send((strictness.clone(), key_ring.clone())).unwrap();
+                            dispatch.run(&mut replica);
+                            ReplicaStats {
+                                waiting: Some(replica.waiting_stats()),
+                                ..stats(&dispatch)
+                            }
                         }
+                        #[cfg(feature = "minbft")]
                         "minbft" => {
                             let mut replica = minbft::Replica::new(
-                                dispatch.register(Host::Replica(replica.index)),
+                                register_replica(&dispatch, replica.index, byzantine),
+                                replica.index,
+                                app,
+                            );
+                            replica.warm_up(task.warm_up_entry_num);
+                            ready.send((strictness.clone(), key_ring.clone())).unwrap();
+                            dispatch.run(&mut replica);
+                            ReplicaStats {
+                                waiting: Some(replica.waiting_stats()),
+                                ..stats(&dispatch)
+                            }
+                        }
+                        #[cfg(feature = "sbft")]
+                        "sbft" => {
+                            let mut replica = sbft::Replica::new(
+                                register_replica(&dispatch, replica.index, byzantine),
+                                replica.index,
+                                app,
+                            );
+                            replica.warm_up(task.warm_up_entry_num);
+                            ready.send((strictness.clone(), key_ring.clone())).unwrap();
+                            dispatch.run(&mut replica);
+                            ReplicaStats {
+                                client_table: Some(replica.client_table.stats),
+                                ..stats(&dispatch)
+                            }
+                        }
+                        #[cfg(feature = "tendermint")]
+                        "tendermint" => {
+                            let mut replica = tendermint::Replica::new(
+                                register_replica(&dispatch, replica.index, byzantine),
                                 replica.index,
                                 app,
                             );
-                            dispatch.run(&mut replica)
+                            replica.warm_up(task.warm_up_entry_num);
+                            ready.send((strictness.clone(), key_ring.clone())).unwrap();
+                            dispatch.run(&mut replica);
+                            stats(&dispatch)
                         }
                         _ => unimplemented!(),
-                    }
-                    // TODO return stats
+                    };
+                    runtime_thread.join().unwrap();
+                    replica_stats
                 }
             });
-            *state.lock().unwrap() = AppState::ReplicaRunning { cancel, task };
+            *state.lock().unwrap() = AppState::ReplicaRunning {
+                cancel,
+                task,
+                standby: Some(standby).filter(|_| supports_standby),
+                strictness: None,
+                key_ring: None,
+                index: replica.index,
+                variant,
+                chaos,
+                metrics,
+            };
+            // respond to the task only after replica is warmed up
+            if let Ok((switch, ring)) = ready_receiver.recv_async().await {
+                if let AppState::ReplicaRunning {
+                    strictness,
+                    key_ring,
+                    ..
+                } = &mut *state.lock().unwrap()
+                {
+                    *strictness = switch;
+                    *key_ring = ring
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// the replica misbehaves as the task tells, see `context::byzantine`
+fn register_replica<M>(
+    dispatch: &Dispatch,
+    index: ReplicaIndex,
+    byzantine: Option<ByzantineStrategy>,
+) -> Context<M>
+where
+    M: Tamper + Serialize + DeserializeOwned,
+{
+    let mut context = dispatch.register(Host::Replica(index));
+    if let Some(strategy) = byzantine {
+        tracing::warn!(?strategy, "misbehave as byzantine replica");
+        context.set_byzantine(strategy)
+    }
+    context
+}
+
+// the addresses that the task binds on this host
+fn task_addrs(task: &Task) -> Vec<SocketAddr> {
+    match &task.role {
+        Role::BenchmarkClient(config) => task
+            .client_addrs
+            .iter()
+            .skip(config.offset)
+            .take(config.num_group * config.num_client)
+            .copied()
+            .collect(),
+        Role::Replica(replica) => {
+            let addr = if replica.standby {
+                task.standby_addrs
+                    .iter()
+                    .find(|(index, _)| *index == replica.index)
+                    .expect("standby address")
+                    .1
+            } else {
+                task.replica_addrs[replica.index as usize]
+            };
+            let mut addrs = vec![addr];
+            if matches!(
+                &*task.mode,
+                "neo-hm" | "neo-pk" | "neo-bn" | "unreplicated-om" | "pbft-sq"
+            ) {
+                addrs.extend(
+                    task.multicast_addrs
+                        .iter()
+                        .map(|addr| SocketAddr::from(([0, 0, 0, 0], addr.port()))),
+                )
+            }
+            addrs
+        }
+    }
+}
+
+// the sockets of the last run may be released a moment after it is reset, e.g. the receiving
+// tasks of a runtime that is shut down in background, so wait for a while before reporting
+// binding is done without SO_REUSEADDR on purpose: with it a straggler would silently share the
+// traffic of the new run instead of failing here
+async fn busy_addrs(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let mut busy = Vec::new();
+    for _ in 0..50 {
+        busy = Vec::from_iter(addrs.iter().copied().filter(
+            |&addr| matches!(UdpSocket::bind(addr), Err(err) if err.kind() == ErrorKind::AddrInUse),
+        ));
+        if busy.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await
+    }
+    busy
+}
+
+// "<pid> <command>" of the processes that have a UDP socket bound to `port`
+fn socket_owners(port: u16) -> Vec<String> {
+    let mut inodes = Vec::new();
+    for table in ["/proc/net/udp", "/proc/net/udp6"] {
+        let Ok(table) = std::fs::read_to_string(table) else {
+            continue;
+        };
+        for line in table.lines().skip(1) {
+            let fields = Vec::from_iter(line.split_whitespace());
+            let local_port = fields
+                .get(1)
+                .and_then(|addr| addr.rsplit(':').next())
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok());
+            if local_port == Some(port) {
+                inodes.extend(fields.get(9).map(|inode| format!("socket:[{inode}]")))
+            }
+        }
+    }
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut owners = Vec::new();
+    for process in processes.flatten() {
+        let Ok(fds) = std::fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+        if fds.flatten().any(|fd| {
+            std::fs::read_link(fd.path())
+                .is_ok_and(|link| inodes.iter().any(|inode| link.as_os_str() == &**inode))
+        }) {
+            let command = std::fs::read_to_string(process.path().join("comm")).unwrap_or_default();
+            owners.push(format!(
+                "{} {}",
+                process.file_name().to_string_lossy(),
+                command.trim()
+            ))
         }
     }
+    owners
 }
 
 async fn poll_benchmark(State(state): State<Arc<Mutex<AppState>>>) -> Json<Option<BenchmarkStats>> {
     let state = state.lock().unwrap();
     match &*state {
         AppState::BenchmarkClientRunning | AppState::Panicked => Json(None),
-        &AppState::BenchmarkClientFinish { stats } => Json(Some(stats)),
+        AppState::BenchmarkClientFinish { stats } => Json(Some((**stats).clone())),
         _ => {
             drop(state);
             unimplemented!()
@@ -212,22 +912,206 @@ async fn poll_panic(State(state): State<Arc<Mutex<AppState>>>) -> Json<bool> {
     Json(matches!(*state.lock().unwrap(), AppState::Panicked))
 }
 
-async fn reset(State(state): State<Arc<Mutex<AppState>>>) {
+async fn reset(State(state): State<Arc<Mutex<AppState>>>) -> Json<Option<ReplicaStats>> {
     let state = {
         let mut state = state.lock().unwrap();
         replace(&mut *state, AppState::Idle)
     };
     match state {
-        AppState::BenchmarkClientFinish { .. } => {}
-        AppState::ReplicaRunning { cancel, task } => {
+        AppState::BenchmarkClientFinish { .. } => Json(None),
+        AppState::ReplicaRunning { cancel, task, .. } => {
             cancel.cancel();
-            task.await.unwrap()
+            Json(Some(task.await.unwrap()))
         }
         _ => unimplemented!(),
     }
 }
 
+// silence (true) or promote (false) the running replica, returning whether it was in standby
+async fn set_standby(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Json(standby): Json<bool>,
+) -> Result<Json<bool>, StatusCode> {
+    match &*state.lock().unwrap() {
+        AppState::ReplicaRunning {
+            standby: Some(flag),
+            ..
+        } => Ok(Json(flag.swap(standby, SeqCst))),
+        _ => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+// the last op number committed by the running replica, for choosing a boundary to switch
+// verification strictness at
+async fn poll_strictness(State(state): State<Arc<Mutex<AppState>>>) -> Json<Option<u64>> {
+    match &*state.lock().unwrap() {
+        AppState::ReplicaRunning {
+            strictness: Some(strictness),
+            ..
+        } => Json(Some(strictness.committed_num())),
+        _ => Json(None),
+    }
+}
+
+async fn switch_strictness(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Json(switch): Json<SwitchStrictness>,
+) -> StatusCode {
+    match &*state.lock().unwrap() {
+        AppState::ReplicaRunning {
+            strictness: Some(strictness),
+            ..
+        } => {
+            strictness.schedule(switch.strictness, switch.op_num);
+            StatusCode::OK
+        }
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
+
+// the replica keys are rotated only if verification is enabled, and the sequencer key only for the
+// variants signed with it
+async fn rotate_keys(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Json(rotate): Json<RotateKeys>,
+) -> Result<(), (StatusCode, String)> {
+    let AppState::ReplicaRunning {
+        key_ring,
+        index,
+        variant,
+        ..
+    } = &*state.lock().unwrap()
+    else {
+        return Err((StatusCode::BAD_REQUEST, String::from("no replica running")));
+    };
+    if let Some(key_dir) = &rotate.key_dir {
+        let Some(key_ring) = key_ring else {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("verification is disabled"),
+            ));
+        };
+        let (secret_keys, public_keys) =
+            keys::load(key_dir.as_ref(), Some(*index)).map_err(|err| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!("loading keys from {key_dir}: {err}"),
+                )
+            })?;
+        let secret_keys = HashMap::from_iter(secret_keys.map(|keys| (*index, keys)));
+        let epoch = key_ring
+            .rotate(secret_keys, public_keys, rotate.grace)
+            .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+        tracing::info!(epoch, key_dir, "rotated keys")
+    }
+    if let Some(key) = &rotate.sequencer_key {
+        let Some(curve) = variant.sequencer_curve() else {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("no sequencer key to rotate"),
+            ));
+        };
+        let key = SequencerVerifyingKey::from_sec1_bytes(curve, key).ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                String::from("invalid sequencer key"),
+            )
+        })?;
+        variant.rotate_sequencer_key(key, rotate.grace)
+    }
+    Ok(())
+}
+
+async fn inject_chaos(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Json(action): Json<ChaosAction>,
+) -> StatusCode {
+    if action.check().is_err() {
+        return StatusCode::BAD_REQUEST;
+    }
+    match &*state.lock().unwrap() {
+        AppState::ReplicaRunning { chaos, .. } => {
+            chaos.apply(action);
+            StatusCode::OK
+        }
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
+
+// the pool of the request workers, kept for the next tasks that ask for as many rather than spawning
+// the threads anew for each
+fn executor(num_worker: usize) -> Option<Arc<rayon::ThreadPool>> {
+    static EXECUTOR: Mutex<Option<Arc<rayon::ThreadPool>>> = Mutex::new(None);
+    let mut executor = EXECUTOR.lock().unwrap();
+    if num_worker == 0 {
+        // shut down once the dispatches on it stop
+        *executor = None
+    } else if executor
+        .as_ref()
+        .is_none_or(|executor| executor.current_num_threads() != num_worker)
+    {
+        *executor = Some(permissioned_blockchain::context::tokio::executor(
+            num_worker,
+        ))
+    }
+    executor.clone()
+}
+
+// overwritten by every benchmark of this process that records its history, and named after the
+// process, so the clients of other processes on the same host keep their own
+fn history_path() -> PathBuf {
+    std::env::temp_dir().join(format!("history-{}.bin", std::process::id()))
+}
+
+// the recorded history once the benchmark finishes, see `BenchmarkClient::history`
+async fn poll_history(State(state): State<Arc<Mutex<AppState>>>) -> Result<Vec<u8>, StatusCode> {
+    if !matches!(
+        *state.lock().unwrap(),
+        AppState::BenchmarkClientFinish { .. }
+    ) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    std::fs::read(history_path()).map_err(|_| StatusCode::NOT_FOUND)
+}
+
+// Prometheus text format, empty unless a replica is running
+async fn poll_metrics(State(state): State<Arc<Mutex<AppState>>>) -> String {
+    match &*state.lock().unwrap() {
+        AppState::ReplicaRunning { metrics, .. } => metrics.render(),
+        _ => String::new(),
+    }
+}
+
+async fn authorize<B>(
+    State(token): State<Arc<String>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, StatusCode> {
+    let expected = format!("Bearer {token}");
+    // compare in constant time, so that the latency of a rejection does not tell how much of a
+    // guessed token is right
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .is_some_and(|value| bool::from(value.as_bytes().ct_eq(expected.as_bytes())));
+    if !authorized {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(next.run(request).await)
+}
+
+fn build_info() -> BuildInfo {
+    control_messages::build_info!().with_registry(
+        permissioned_blockchain::modes(),
+        permissioned_blockchain::apps(),
+    )
+}
+
 fn main() {
+    build_info().exit_on_version_flag();
+    permissioned_blockchain::init_tracing();
+    // calibrate before taking any timestamp, so that the first messages are not delayed by it
+    clock::calibrate();
     let state = Arc::new(Mutex::new(AppState::Idle));
     let hook = std::panic::take_hook();
     std::panic::set_hook({
@@ -242,22 +1126,69 @@ fn main() {
         })
     });
 
+    // `--tls <dir>` where dir contains `cert.pem`, `key.pem` and `token` deployed by `reload`
+    let args = Vec::from_iter(std::env::args().skip(1));
+    let tls_dir = match &*args {
+        [] => None,
+        [flag, dir] if flag == "--tls" => Some(dir.clone()),
+        _ => panic!("usage: permissioned-blockchain [--version | --tls <dir>]"),
+    };
+
+    // with TLS every endpoint requires the token, the polled stats and history included
     let app = Router::new()
-        .route("/panic", get(poll_panic))
         .route("/task", post(set_task))
         .route("/reset", post(reset))
+        .route("/standby", post(set_standby))
+        .route("/strictness", post(switch_strictness).get(poll_strictness))
+        .route("/keys", post(rotate_keys))
+        .route("/chaos", post(inject_chaos))
+        .route("/panic", get(poll_panic))
         .route("/benchmark", get(poll_benchmark))
-        .with_state(state);
+        .route("/history", get(poll_history))
+        .route("/metrics", get(poll_metrics))
+        .route("/build-info", get(|| async { Json(build_info()) }));
+    let app = if let Some(dir) = &tls_dir {
+        let token = std::fs::read_to_string(format!("{dir}/token")).unwrap();
+        app.route_layer(middleware::from_fn_with_state(
+            Arc::new(String::from(token.trim())),
+            authorize,
+        ))
+    } else {
+        app
+    };
+    let app = app.with_state(state);
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .unwrap();
+    let addr = "0.0.0.0:9999".parse().unwrap();
     runtime
         .block_on(async move {
-            Server::bind(&"0.0.0.0:9999".parse().unwrap())
-                .serve(app.into_make_service())
-                .with_graceful_shutdown(async move { tokio::signal::ctrl_c().await.unwrap() })
-                .await
+            if let Some(dir) = tls_dir {
+                let config = RustlsConfig::from_pem_file(
+                    format!("{dir}/cert.pem"),
+                    format!("{dir}/key.pem"),
+                )
+                .await?;
+                let handle = Handle::new();
+                tokio::spawn({
+                    let handle = handle.clone();
+                    async move {
+                        tokio::signal::ctrl_c().await.unwrap();
+                        handle.graceful_shutdown(None)
+                    }
+                });
+                axum_server::bind_rustls(addr, config)
+                    .handle(handle)
+                    .serve(app.into_make_service())
+                    .await
+            } else {
+                Server::bind(&addr)
+                    .serve(app.into_make_service())
+                    .with_graceful_shutdown(async move { tokio::signal::ctrl_c().await.unwrap() })
+                    .await
+                    .map_err(std::io::Error::other)
+            }
         })
         .unwrap();
     runtime.shutdown_background()
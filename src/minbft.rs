@@ -1,21 +1,30 @@
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
-    time::{Duration, Instant},
+    time::Duration,
 };
 
+use control_messages::{ClientDestination, WaitingStats};
+use k256::sha2::Digest;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    client::BoxedConsume,
-    common::{Block, BlockDigest, Chain, Request, Timer},
+    client::{BoxedConsume, Outcome, Proof},
+    common::{Block, BlockDigest, Chain, Request, Timer, Waiting},
     context::{
-        crypto::{DigestHash, Sign, Signature, Signed, Verify},
+        byzantine::{garble, Tamper},
+        clock::Timestamp,
+        crypto::{DigestHash, Hasher, Sign, Signature, Signed, Signer, Verify},
+        metrics::Gauges,
         ClientIndex, Host, Receivers, ReplicaIndex, To,
     },
     App, Context,
 };
 
+use self::usig::{SignatureUsig, Ui, Usig};
+
+pub mod usig;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Message {
     Request(Signed<Request>),
@@ -36,6 +45,8 @@ pub struct Reply {
 pub struct Prepare {
     view_num: u32,
     block: Block,
+    // of the primary's USIG, over the rest of the message
+    ui: Ui,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,6 +54,8 @@ pub struct Commit {
     view_num: u32,
     block_digest: BlockDigest,
     replica_index: ReplicaIndex,
+    // of the sender's USIG, over the rest of the message
+    ui: Ui,
 }
 
 #[derive(Debug)]
@@ -64,6 +77,7 @@ struct ClientInvoke {
     op: Vec<u8>,
     replies: HashMap<ReplicaIndex, Reply>,
     consume: BoxedConsume,
+    start: Timestamp,
 }
 
 impl Client {
@@ -91,13 +105,19 @@ impl crate::Client for Client {
             op: op.clone(),
             replies: Default::default(),
             consume: consume.into(),
+            start: Timestamp::now(),
         });
         let request = Request {
             client_index: self.index,
             request_num: shared.request_num,
             op,
         };
-        shared.context.send(To::AllReplica, request);
+        let Some(to) = shared.context.request_destination(ClientDestination::All) else {
+            unreachable!(
+                "minbft does not accept ordered multicast requests, see `ClientDestination::check`"
+            )
+        };
+        shared.context.send(to, request);
         shared.resend_timer.set(&mut shared.context)
     }
 
@@ -115,19 +135,24 @@ impl crate::Client for Client {
         invoke
             .replies
             .insert(message.replica_index, Reply::clone(&message));
-        let num_match = invoke
-            .replies
-            .values()
-            .filter(|reply| {
-                (reply.block_digest, &reply.result) == (message.block_digest, &message.result)
-            })
-            .count();
-        assert!(num_match <= shared.context.config().num_faulty + 1);
-        if num_match == shared.context.config().num_faulty + 1 {
+        let matched = Vec::from_iter(
+            invoke
+                .replies
+                .values()
+                .filter(|reply| {
+                    (reply.block_digest, &reply.result) == (message.block_digest, &message.result)
+                })
+                .cloned(),
+        );
+        assert!(matched.len() <= shared.context.config().num_faulty + 1);
+        if matched.len() == shared.context.config().num_faulty + 1 {
             shared.resend_timer.unset(&mut shared.context);
             let invoke = shared.invoke.take().unwrap();
             let _op = invoke.op;
-            invoke.consume.apply(message.inner.result)
+            invoke.consume.apply(Outcome {
+                proof: Some(Proof::new(matched)),
+                ..Outcome::new(message.inner.result, invoke.start)
+            })
         }
     }
 }
@@ -142,11 +167,21 @@ pub struct Replica {
     commit_certificates: HashMap<BlockDigest, HashMap<ReplicaIndex, Signed<Commit>>>,
     chain: Chain,
     app: App,
+
+    usig: Box<dyn Usig>,
+    // the counter value of the latest UI accepted from each remote replica, whose messages are
+    // accepted in the order of their UIs without any gap, so none of them can be left out
+    ui_counters: HashMap<ReplicaIndex, u64>,
+    // prioritized by the gap to the expected counter value
+    reordering_uis: Waiting<(ReplicaIndex, u64), u64, Message>,
 }
 
 impl Replica {
     pub fn new(context: Context<Message>, index: ReplicaIndex, app: App) -> Self {
         Self {
+            usig: Box::new(SignatureUsig::new(context.config(), index)),
+            ui_counters: Default::default(),
+            reordering_uis: Waiting::new(context.config().waiting_capacity),
             context,
             index,
             view_num: 0,
@@ -157,6 +192,23 @@ impl Replica {
             app,
         }
     }
+
+    // in place of the software USIG, e.g. one backed by an enclave
+    pub fn with_usig(mut self, usig: impl Usig + 'static) -> Self {
+        self.usig = Box::new(usig);
+        self
+    }
+
+    pub fn waiting_stats(&self) -> WaitingStats {
+        self.reordering_uis.stats
+    }
+
+    // preallocate the log for `entry_num` entries, so that growing it does not stall the first
+    // seconds of a run
+    pub fn warm_up(&mut self, entry_num: usize) {
+        self.prepares.reserve(entry_num);
+        self.commit_certificates.reserve(entry_num)
+    }
 }
 
 impl Receivers for Replica {
@@ -167,8 +219,9 @@ impl Receivers for Replica {
         // println!("{message:?}");
         match message {
             Message::Request(message) => self.handle_request(remote, message),
-            Message::Prepare(message) => self.handle_prepare(remote, message),
-            Message::Commit(message) => self.handle_commit(remote, message),
+            message @ (Message::Prepare(_) | Message::Commit(_)) => {
+                self.handle_certified(remote, message)
+            }
             _ => unimplemented!(),
         }
     }
@@ -187,6 +240,14 @@ impl Receivers for Replica {
         }
     }
 
+    fn gauges(&self) -> Gauges {
+        Gauges {
+            log_len: Some(self.prepares.len() as _),
+            commit_num: Some(self.chain.executed_height() as _),
+            reorder_len: None,
+        }
+    }
+
     fn on_pace(&mut self) {
         if self.index == self.primary_index() && !self.requests.is_empty() {
             self.do_propose()
@@ -196,7 +257,66 @@ impl Receivers for Replica {
 
 impl Replica {
     fn primary_index(&self) -> ReplicaIndex {
-        (self.view_num as usize % self.context.config().num_replica) as _
+        self.view_primary_index(self.view_num)
+    }
+
+    fn view_primary_index(&self, view_num: u32) -> ReplicaIndex {
+        (view_num as usize % self.context.config().num_replica) as _
+    }
+
+    // the sender of a prepare or commit, and the UI that it certifies the message with
+    fn certified(&self, message: &Message) -> (ReplicaIndex, [u8; 32], Ui) {
+        match message {
+            Message::Prepare(prepare) => (
+                self.view_primary_index(prepare.view_num),
+                Hasher::sha256(&prepare.inner).finalize().into(),
+                prepare.ui.clone(),
+            ),
+            Message::Commit(commit) => (
+                commit.replica_index,
+                Hasher::sha256(&commit.inner).finalize().into(),
+                commit.ui.clone(),
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    fn handle_certified(&mut self, remote: Host, message: Message) {
+        let (replica_index, digest, ui) = self.certified(&message);
+        if remote != Host::Replica(replica_index) {
+            tracing::warn!(?remote, replica_index, "forwarded certified message");
+            return;
+        }
+        if let Err(err) = self.usig.verify_ui(replica_index, &digest, &ui) {
+            tracing::warn!(%err, replica_index, counter = ui.counter, "invalid UI");
+            return;
+        }
+        let counter = *self.ui_counters.entry(replica_index).or_default();
+        // replayed, or another message with a counter value that is used already
+        if ui.counter <= counter {
+            return;
+        }
+        if ui.counter != counter + 1 {
+            self.reordering_uis
+                .insert((replica_index, ui.counter), ui.counter - counter, message);
+            return;
+        }
+        self.do_accept(remote, replica_index, message);
+        while let Some(message) = self
+            .reordering_uis
+            .remove(&(replica_index, self.ui_counters[&replica_index] + 1))
+        {
+            self.do_accept(remote, replica_index, message)
+        }
+    }
+
+    fn do_accept(&mut self, remote: Host, replica_index: ReplicaIndex, message: Message) {
+        *self.ui_counters.get_mut(&replica_index).unwrap() += 1;
+        match message {
+            Message::Prepare(message) => self.handle_prepare(remote, message),
+            Message::Commit(message) => self.handle_commit(remote, message),
+            _ => unreachable!(),
+        }
     }
 
     fn handle_request(&mut self, _remote: Host, message: Signed<Request>) {
@@ -223,11 +343,15 @@ impl Replica {
         let block_digest = message.block.digest();
         self.prepares.insert(block_digest, message);
         // assert_ne!(self.index, self.primary_index());
-        let commit = Commit {
+        let mut commit = Commit {
             view_num: self.view_num,
             block_digest,
             replica_index: self.index,
+            ui: Default::default(),
         };
+        commit.ui = self
+            .usig
+            .create_ui(&Hasher::sha256(&commit).finalize().into());
         self.context.send(To::AllReplicaWithLoopback, commit)
     }
 
@@ -246,10 +370,14 @@ impl Replica {
 
     fn do_propose(&mut self) {
         assert_eq!(self.index, self.primary_index());
-        let prepare = Prepare {
+        let mut prepare = Prepare {
             view_num: self.view_num,
             block: self.chain.propose(&mut self.requests),
+            ui: Default::default(),
         };
+        prepare.ui = self
+            .usig
+            .create_ui(&Hasher::sha256(&prepare).finalize().into());
         self.context.send(To::AllReplicaWithLoopback, prepare)
     }
 
@@ -276,6 +404,7 @@ impl Replica {
             return;
         }
         loop {
+            self.app.set_op_num(block.height as _);
             for request in &block.requests {
                 let reply = Reply {
                     request_num: request.request_num,
@@ -303,6 +432,7 @@ impl DigestHash for Reply {
     }
 }
 
+// the UIs are over the digests, so they are left out
 impl DigestHash for Prepare {
     fn hash(&self, hasher: &mut impl std::hash::Hasher) {
         hasher.write_u32(self.view_num);
@@ -318,6 +448,42 @@ impl DigestHash for Commit {
     }
 }
 
+// the tampered certified messages keep their UIs, which do not match them, see `usig`
+impl Tamper for Message {
+    // the same slot proposed without the requests
+    fn conflict(&self, signer: &Signer) -> Option<Self> {
+        let Self::Prepare(message) = self else {
+            return None;
+        };
+        let mut prepare = message.inner.clone();
+        if prepare.block.requests.is_empty() {
+            return None;
+        }
+        prepare.block.requests.clear();
+        Some(Self::sign(prepare, signer))
+    }
+
+    fn is_vote(&self) -> bool {
+        matches!(self, Self::Commit(_))
+    }
+
+    fn corrupt(&self, signer: &Signer) -> Option<Self> {
+        match self {
+            Self::Prepare(message) => {
+                let mut prepare = message.inner.clone();
+                garble(&mut prepare.block.parent_digest);
+                Some(Self::sign(prepare, signer))
+            }
+            Self::Commit(message) => {
+                let mut commit = message.inner.clone();
+                garble(&mut commit.block_digest);
+                Some(Self::sign(commit, signer))
+            }
+            _ => None,
+        }
+    }
+}
+
 impl Sign<Request> for Message {
     fn sign(message: Request, signer: &crate::context::crypto::Signer) -> Self {
         Self::Request(signer.sign_private(message))
@@ -330,27 +496,23 @@ impl Sign<Reply> for Message {
     }
 }
 
-fn simulate_sgx() {
-    let start = Instant::now();
-    while start.elapsed() < Duration::from_micros(16) {}
-}
-
+// certified by the UIs instead of signatures, see `usig`
 impl Sign<Prepare> for Message {
     fn sign(message: Prepare, _: &crate::context::crypto::Signer) -> Self {
-        simulate_sgx();
         Self::Prepare(Signed {
             inner: message,
             signature: Signature::Plain,
+            key_epoch: 0,
         })
     }
 }
 
 impl Sign<Commit> for Message {
     fn sign(message: Commit, _: &crate::context::crypto::Signer) -> Self {
-        simulate_sgx();
         Self::Commit(Signed {
             inner: message,
             signature: Signature::Plain,
+            key_epoch: 0,
         })
     }
 }
@@ -361,12 +523,91 @@ impl Verify for Message {
         verifier: &crate::context::crypto::Verifier,
     ) -> Result<(), crate::context::crypto::Invalid> {
         match self {
-            Self::Request(message) => verifier.verify(message, None),
+            Self::Request(message) => verifier.verify_client(message, message.client_index),
             Self::Reply(message) => verifier.verify(message, message.replica_index),
-            Self::Prepare(_) | Self::Commit(_) => {
-                simulate_sgx();
-                Ok(())
-            }
+            // the replica verifies the UIs, along with whether the counter values are the expected
+            // ones, see `Replica::handle_certified`
+            Self::Prepare(_) | Self::Commit(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::{
+        ordered_multicast::Variant,
+        simulated::{
+            fixture::{config, deliver},
+            Network,
+        },
+    };
+
+    use super::*;
+
+    struct Replies(usize);
+
+    impl Receivers for Replies {
+        type Message = Message;
+
+        fn handle(&mut self, _: Host, _: Host, message: Self::Message) {
+            assert!(matches!(message, Message::Reply(_)));
+            self.0 += 1
+        }
+
+        fn on_timer(&mut self, _: Host, _: crate::context::TimerId) {
+            unreachable!()
         }
     }
+
+    #[test]
+    fn reused_counter() {
+        // the config assumes 3f + 1 replicas, which is one more than minbft needs
+        let config = config(4, 1);
+        let network = Network::new(config.clone(), true, |_| Variant::Unreachable);
+        let mut replicas =
+            Vec::from_iter((0..4).map(|index| {
+                Replica::new(network.register(Host::Replica(index)), index, App::Null)
+            }));
+        let mut client = network.register::<Message>(Host::Client(0));
+        client.send(
+            To::AllReplica,
+            Request {
+                client_index: 0,
+                request_num: 1,
+                op: Default::default(),
+            },
+        );
+        let mut replies = Replies(0);
+        deliver(
+            &network,
+            &mut replicas,
+            [(Host::Client(0), &mut replies as _)],
+            Network::run_until_quiescent,
+        );
+        assert_eq!(replies.0, 4);
+        // the prepare and the commit of the primary
+        assert_eq!(replicas[1].ui_counters[&0], 2);
+
+        // the primary proposes another block with the counter value of the first one, which the USIG
+        // of a correct replica never allows
+        let mut usig = SignatureUsig::new(&config, 0);
+        let mut prepare = Prepare {
+            view_num: 0,
+            block: Chain::default().propose(&mut vec![Request {
+                client_index: 0,
+                request_num: 2,
+                op: Default::default(),
+            }]),
+            ui: Default::default(),
+        };
+        prepare.ui = usig.create_ui(&Hasher::sha256(&prepare).finalize().into());
+        let message = Message::Prepare(Signed {
+            inner: prepare,
+            signature: Signature::Plain,
+            key_epoch: 0,
+        });
+        replicas[1].handle(Host::Replica(1), Host::Replica(0), message);
+        assert_eq!(replicas[1].prepares.len(), 1);
+        assert_eq!(replicas[1].ui_counters[&0], 2)
+    }
 }
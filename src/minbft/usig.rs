@@ -0,0 +1,165 @@
+// the unique sequential identifier generator of MinBFT, a trusted component on every replica that
+// binds a counter value to each message the replica sends. the counter only ever counts up, so a
+// faulty replica cannot send different messages with the same identifier, which is what takes the
+// replicas down to 2f + 1 and the quorums down to f + 1
+// the software one here certifies with the replica's own signing key, so a faulty replica cannot
+// create the UIs of another one, but it is only as trustworthy as the replica that hosts it for its
+// own counter. an enclave, e.g. SGX or a TPM, implements `Usig` by keeping the counter and the key
+// out of reach of the replica
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::context::{
+    crypto::{DigestHash, Invalid, KeyEpoch, Signature, Signed, Signer, Verifier},
+    ordered_multicast::Variant,
+    Config, Host, ReplicaIndex,
+};
+
+// the unique identifier of a message, which is the certificate along with the counter value
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ui {
+    pub counter: u64,
+    // over the replica index, the counter value and the digest of the message, see `Statement`
+    pub certificate: Signature,
+    pub key_epoch: KeyEpoch,
+}
+
+impl Default for Ui {
+    fn default() -> Self {
+        Self {
+            counter: 0,
+            certificate: Signature::Plain,
+            key_epoch: 0,
+        }
+    }
+}
+
+pub trait Usig: std::fmt::Debug + Send {
+    // the next counter value for the message of `digest`, i.e. the first one is 1
+    fn create_ui(&mut self, digest: &[u8; 32]) -> Ui;
+
+    // whether `ui` is created by the USIG of `replica_index` for the message of `digest`, which says
+    // nothing about whether the counter value is the expected one, as that is up to the receiver
+    fn verify_ui(
+        &self,
+        replica_index: ReplicaIndex,
+        digest: &[u8; 32],
+        ui: &Ui,
+    ) -> Result<(), Invalid>;
+}
+
+#[derive(Debug)]
+pub struct SignatureUsig {
+    index: ReplicaIndex,
+    signer: Signer,
+    verifier: Verifier,
+    counter: u64,
+}
+
+// what a UI certifies
+struct Statement<'a> {
+    replica_index: ReplicaIndex,
+    counter: u64,
+    digest: &'a [u8; 32],
+}
+
+impl DigestHash for Statement<'_> {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u8(self.replica_index);
+        hasher.write_u64(self.counter);
+        hasher.write(self.digest)
+    }
+}
+
+impl SignatureUsig {
+    pub fn new(config: &Config, index: ReplicaIndex) -> Self {
+        Self {
+            index,
+            signer: Signer::new(config, Host::Replica(index)),
+            verifier: Verifier::new_standard(config, Arc::new(Variant::Unreachable)),
+            counter: 0,
+        }
+    }
+}
+
+// the cost of a call into an enclave, which a software USIG does not pay otherwise
+fn simulate_sgx() {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_micros(16) {}
+}
+
+impl Usig for SignatureUsig {
+    fn create_ui(&mut self, digest: &[u8; 32]) -> Ui {
+        simulate_sgx();
+        self.counter += 1;
+        let signed = self.signer.sign_public(Statement {
+            replica_index: self.index,
+            counter: self.counter,
+            digest,
+        });
+        Ui {
+            counter: self.counter,
+            certificate: signed.signature,
+            key_epoch: signed.key_epoch,
+        }
+    }
+
+    fn verify_ui(
+        &self,
+        replica_index: ReplicaIndex,
+        digest: &[u8; 32],
+        ui: &Ui,
+    ) -> Result<(), Invalid> {
+        simulate_sgx();
+        let signed = Signed {
+            inner: Statement {
+                replica_index,
+                counter: ui.counter,
+                digest,
+            },
+            signature: ui.certificate.clone(),
+            key_epoch: ui.key_epoch,
+        };
+        self.verifier.verify(&signed, replica_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::simulated::fixture::config;
+
+    use super::*;
+
+    #[test]
+    fn signature_usig() {
+        let mut config = config(4, 1);
+        config.fake_crypto = false;
+        let mut usig = SignatureUsig::new(&config, 0);
+        let verifier = SignatureUsig::new(&config, 1);
+        let ui1 = usig.create_ui(&[1; 32]);
+        let ui2 = usig.create_ui(&[1; 32]);
+        assert_eq!((ui1.counter, ui2.counter), (1, 2));
+        assert!(verifier.verify_ui(0, &[1; 32], &ui1).is_ok());
+        assert!(verifier.verify_ui(0, &[1; 32], &ui2).is_ok());
+        // bound to the message, the counter value and the replica
+        assert!(verifier.verify_ui(0, &[2; 32], &ui1).is_err());
+        let ui = Ui {
+            counter: 3,
+            ..ui2.clone()
+        };
+        assert!(verifier.verify_ui(0, &[1; 32], &ui).is_err());
+        assert!(verifier.verify_ui(1, &[1; 32], &ui1).is_err());
+        // another replica, which cannot certify for replica 0 with its own key
+        let mut other = SignatureUsig::new(&config, 1);
+        let ui = Ui {
+            counter: 1,
+            ..other.create_ui(&[1; 32])
+        };
+        assert!(verifier.verify_ui(0, &[1; 32], &ui).is_err())
+    }
+}
@@ -1,21 +1,29 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     ops::RangeInclusive,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc, Mutex,
+    },
     time::Duration,
 };
 
+use control_messages::{ClientDestination, QueryTriggerStats, WaitingStats};
 use k256::sha2::{Digest, Sha256};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    client::BoxedConsume,
-    common::{Request, Timer},
+    app::SnapshotHandle,
+    client::{BoxedConsume, Outcome, Proof},
+    common::{warm_up_log, ClientLookup, ClientTable, Request, Timer, Waiting},
     context::{
-        crypto::{DigestHash, Hasher, Sign, Signed, Verify},
+        byzantine::{garble, Tamper},
+        clock::Timestamp,
+        crypto::{DigestHash, Hasher, Sign, Signed, Signer, StrictnessSwitch, Verify},
+        metrics::Gauges,
         ordered_multicast::{
-            OrderedMulticast,
-            Signature::{K256Unverified, K256},
+            Merge, OrderedMulticast, Reordered,
+            Signature::{Ecdsa, EcdsaUnverified, HalfSipHash},
         },
         ClientIndex, Host, OrderedMulticastReceivers, Receivers, ReplicaIndex, To,
     },
@@ -24,11 +32,19 @@ use crate::{
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Message {
-    Request(OrderedMulticast<Request>),
+    // a batch of requests, which clients send with a single one and the software sequencer may
+    // merge, see `ordered_multicast::Batcher`
+    Request(OrderedMulticast<Vec<Request>>),
     Reply(Signed<Reply>),
     Confirm(Signed<Confirm>),
     Query(Signed<Query>),
     QueryOk(QueryOk),
+    Checkpoint(Signed<Checkpoint>),
+    EpochChange(Signed<EpochChange>),
+    EpochStart(Signed<EpochStart>),
+    // sent to the replicas by a client that waits on its request too long, which is either lost on
+    // the way to the sequencer, or the sequencer goes silent, and no gap tells the replicas so
+    Resend(Signed<Request>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,25 +54,56 @@ pub struct Reply {
     epoch_num: u32,
     seq_num: u32,
     replica_index: ReplicaIndex,
+    // the replica has more than `Config::max_inflight` ops ordered after this one, not a part of
+    // the result that replies are matched on
+    backpressure: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Confirm {
     digest: [u8; 32],
-    op_nums: RangeInclusive<u32>,
+    op_nums: RangeInclusive<u64>,
     replica_index: ReplicaIndex,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Query {
-    op_num: u32,
+    op_num: u64,
     replica_index: ReplicaIndex,
+    // sent from the standby of the replica, which the replies go to instead
+    standby: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryOk {
-    op_num: u32,
-    request: OrderedMulticast<Request>,
+    op_num: u64,
+    request: OrderedMulticast<Vec<Request>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    op_num: u64,
+    // chained over the requests since the previous checkpoint, so it stands for the whole log
+    digest: [u8; 32],
+    replica_index: ReplicaIndex,
+}
+
+// the sender gives up on the sequencer of the epoch before, and stops ordering its messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EpochChange {
+    epoch_num: u32,
+    op_num: u64,
+    replica_index: ReplicaIndex,
+}
+
+// sent by the leader of the epoch, which starts after the (f + 1)th highest op number of the
+// changes, see `start_op_num`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EpochStart {
+    epoch_num: u32,
+    op_num: u64,
+    changes: Vec<Signed<EpochChange>>,
+    replica_index: ReplicaIndex,
 }
 
 #[derive(Debug)]
@@ -78,6 +125,8 @@ struct ClientInvoke {
     op: Vec<u8>,
     replies: HashMap<ReplicaIndex, Reply>,
     consume: BoxedConsume,
+    start: Timestamp,
+    first_reply: Option<Duration>,
 }
 
 impl Client {
@@ -105,16 +154,40 @@ impl crate::Client for Client {
             op: op.clone(),
             replies: Default::default(),
             consume: consume.into(),
+            start: Timestamp::now(),
+            first_reply: None,
         });
         let request = Request {
             client_index: self.index,
             request_num: shared.request_num,
             op,
         };
-        shared.context.send_ordered_multicast(request);
+        // replicas only order requests from sequencer, there is no unicast fallback yet
+        assert!(
+            shared
+                .context
+                .request_destination(ClientDestination::Multicast)
+                .is_none(),
+            "neo only accepts ordered multicast requests, see `ClientDestination::check`"
+        );
+        shared.context.send_ordered_multicast(vec![request]);
         shared.resend_timer.set(&mut shared.context)
     }
 
+    fn on_timer(&self) {
+        let shared = &mut *self.shared.lock().unwrap();
+        let Some(invoke) = &shared.invoke else {
+            unreachable!()
+        };
+        let request = Request {
+            client_index: self.index,
+            request_num: shared.request_num,
+            op: invoke.op.clone(),
+        };
+        shared.context.send_ordered_multicast(vec![request.clone()]);
+        shared.context.send(To::AllReplica, request)
+    }
+
     fn handle(&self, message: Self::Message) {
         let Message::Reply(message) = message else {
             unimplemented!()
@@ -127,50 +200,127 @@ impl crate::Client for Client {
         let Some(invoke) = &mut shared.invoke else {
             return;
         };
+        invoke
+            .first_reply
+            .get_or_insert_with(|| invoke.start.elapsed());
         invoke
             .replies
             .insert(message.replica_index, Reply::clone(&message));
-        if invoke
-            .replies
-            .values()
-            .filter(|reply| {
-                (
-                    reply.epoch_num, //
-                    reply.seq_num,
-                    &reply.result,
-                ) == (message.epoch_num, message.seq_num, &message.result)
-            })
-            .count()
-            >= shared.context.config().num_replica - shared.context.config().num_faulty
+        let matched = Vec::from_iter(
+            invoke
+                .replies
+                .values()
+                .filter(|reply| {
+                    (
+                        reply.epoch_num, //
+                        reply.seq_num,
+                        &reply.result,
+                    ) == (message.epoch_num, message.seq_num, &message.result)
+                })
+                .cloned(),
+        );
+        if matched.len() >= shared.context.config().num_replica - shared.context.config().num_faulty
         {
             shared.resend_timer.unset(&mut shared.context);
             let invoke = shared.invoke.take().unwrap();
             let _op = invoke.op;
-            invoke.consume.apply(message.inner.result)
+            // so that a faulty replica alone cannot slow the client down
+            let backpressure = matched.iter().filter(|reply| reply.backpressure).count()
+                > shared.context.config().num_faulty;
+            invoke.consume.apply(Outcome {
+                op_num: Some(message.seq_num),
+                view_num: Some(message.epoch_num),
+                first_reply: invoke.first_reply,
+                proof: Some(Proof::new(matched)),
+                backpressure,
+                ..Outcome::new(message.inner.result, invoke.start)
+            })
         }
     }
 }
 
+// evidence of a faulty sequencer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Misbehavior {
+    // two different requests are ordered with the same op number
+    Duplicated(u64),
+    // the request's link hash does not extend the previous request
+    BrokenLink(u64),
+    // the request after the op number is linked to the one before it
+    Skipped(u64),
+    // a remote replica confirms different requests for the op numbers, which may also be the
+    // remote replica being faulty, so only the confirm is discarded
+    Diverged(ReplicaIndex, RangeInclusive<u64>),
+}
+
 #[derive(Debug)]
 pub struct Replica {
     context: Context<Message>,
     index: ReplicaIndex,
 
-    seq_num_offset: Option<u32>,
-    reordering_requests: HashMap<u32, OrderedMulticast<Request>>,
-    requests: Vec<OrderedMulticast<Request>>,
-    ordered_num: u32,
-    verified_num: u32,
-    replies: HashMap<ClientIndex, Reply>,
+    pub reorder: Merge<Vec<Request>>,
+    // the requests after the truncated ones
+    requests: Vec<OrderedMulticast<Vec<Request>>>,
+    truncated_num: u64,
+    ordered_num: u64,
+    verified_num: u64,
+    // the ordered multicast held back while execution lags, see `handle_multicast`
+    deferred: VecDeque<(Host, OrderedMulticast<Vec<Request>>)>,
+    pub client_table: ClientTable<Reply>,
     app: App,
 
     confirm: bool,
-    confirmed_num: u32, // global minimum
-    local_confirmed_num: u32,
-    remote_confirmed_nums: HashMap<ReplicaIndex, u32>,
+    confirmed_num: u64, // global minimum
+    local_confirmed_num: u64,
+    remote_confirmed_nums: HashMap<ReplicaIndex, u64>,
     // TODO persistent confirm as certificates
-    reordering_confirms1: HashMap<u32, Vec<Signed<Confirm>>>,
-    reordering_confirms2: HashMap<(ReplicaIndex, u32), Signed<Confirm>>,
+    // prioritized by op number, so confirms far beyond the ordered ones are evicted first
+    reordering_confirms1: Waiting<u64, u64, Vec<Signed<Confirm>>>,
+    reordering_confirms2: Waiting<(ReplicaIndex, u64), u64, Signed<Confirm>>,
+
+    // the checkpoints taken since the stable one, which is merged into the first handle
+    pub checkpoints: Vec<SnapshotHandle>,
+    // of the checkpoints since the stable one, each chained over the one before
+    checkpoint_digests: BTreeMap<u64, [u8; 32]>,
+    checkpoint_certificates: BTreeMap<(u64, [u8; 32]), HashMap<ReplicaIndex, Signed<Checkpoint>>>,
+    // the latest checkpoint that 2f + 1 replicas including this one agree on, the requests up to it
+    // are truncated
+    pub stable_op_num: u64,
+    pub stable_certificate: HashMap<ReplicaIndex, Signed<Checkpoint>>,
+
+    pub misbehaviors: Vec<Misbehavior>,
+    // stop ordering after the sequencer is proven faulty, until the next epoch
+    halted: bool,
+    // each epoch has a sequencer of its own, which numbers from the start, and its requests are
+    // ordered after `epoch_op_num`
+    pub epoch_num: u32,
+    epoch_op_num: u64,
+    // armed while waiting on the sequencer, i.e. halted or with a gap that is not filled, and
+    // changing the epoch on expiry
+    sequencer_timer: Timer,
+    // armed on the epoch leader while idle groups hold back the others, see `Merge::fills`
+    fill_timer: Timer,
+    // the end of the op numbers of each group that are filled, so a fill is not sent again while
+    // it is on the way
+    fill_nums: Vec<u64>,
+    // the epoch that this replica has sent a change for
+    changing_epoch: Option<u32>,
+    epoch_changes: BTreeMap<u32, HashMap<ReplicaIndex, Signed<EpochChange>>>,
+    // the epoch to enter and its op number, once caught up to it
+    epoch_start: Option<(u32, u64)>,
+    // a client resends a request that is not ordered yet, so the sequencer is waited on even
+    // without a gap, until anything is ordered
+    resent: bool,
+    // log and execute without replying, shared with the worker which promotes the replica by
+    // clearing it
+    // there is no reconfiguration, so a standby takes over the identity of an existing replica
+    pub standby: Arc<AtomicBool>,
+    // run at the standby's address rather than the replica's, where the others send nothing but
+    // the replies to its queries, see `To::Standby`, even after promoted
+    pub standby_host: bool,
+    pub query_trigger: QueryTrigger,
+    // notified of every commit, for switching verification strictness at an op boundary
+    pub strictness: Option<StrictnessSwitch>,
 }
 
 impl Replica {
@@ -182,41 +332,168 @@ impl Replica {
         } else {
             Default::default()
         };
+        let num_group = context.config().num_multicast_group();
         Self {
+            client_table: ClientTable::new(context.config().client_table_capacity),
+            reorder: Merge::new(num_group),
+            reordering_confirms2: Waiting::new(context.config().waiting_capacity),
+            reordering_confirms1: Waiting::new(context.config().waiting_capacity),
             context,
             index,
-            seq_num_offset: None,
-            reordering_requests: Default::default(),
             requests: Default::default(),
+            truncated_num: 0,
             ordered_num: 0,
             verified_num: 0,
-            replies: Default::default(),
+            deferred: Default::default(),
             app,
             confirm,
             confirmed_num: 0,
             local_confirmed_num: 0,
             remote_confirmed_nums,
-            reordering_confirms2: Default::default(),
-            reordering_confirms1: Default::default(),
+            checkpoints: Default::default(),
+            checkpoint_digests: Default::default(),
+            checkpoint_certificates: Default::default(),
+            stable_op_num: 0,
+            stable_certificate: Default::default(),
+            misbehaviors: Default::default(),
+            halted: false,
+            epoch_num: 0,
+            epoch_op_num: 0,
+            sequencer_timer: Timer::new(Self::SEQUENCER_TIMEOUT),
+            fill_timer: Timer::new(Self::FILL_DELAY),
+            fill_nums: vec![0; num_group],
+            changing_epoch: None,
+            epoch_changes: Default::default(),
+            epoch_start: None,
+            resent: false,
+            standby: Default::default(),
+            standby_host: false,
+            query_trigger: QueryTrigger::new(),
+            strictness: None,
         }
     }
+
+    // preallocate the log for `entry_num` entries, so that growing it does not stall the first
+    // seconds of a run
+    pub fn warm_up(&mut self, entry_num: usize) {
+        warm_up_log(&mut self.requests, entry_num)
+    }
+
+    pub fn waiting_stats(&self) -> WaitingStats {
+        self.reordering_confirms1
+            .stats
+            .merge(self.reordering_confirms2.stats)
+    }
 }
 
-struct I<'a>(&'a [OrderedMulticast<Request>]);
+// decide when a hole in the ordered multicast is a loss worth querying for, instead of a message
+// that is still in flight
+// the expected gap is the number of messages ordered during a query round trip, scaled by a slack
+// that grows as queries turn out premature, i.e. the hole is filled before any reply
+#[derive(Debug)]
+pub struct QueryTrigger {
+    pub stats: QueryTriggerStats,
+    window_start: Timestamp,
+    window_ordered: u32,
+    // the queried hole and when the query is sent
+    outstanding: Option<(u64, Timestamp)>,
+}
 
-impl std::ops::Index<u32> for I<'_> {
-    type Output = OrderedMulticast<Request>;
+impl QueryTrigger {
+    const MIN_THRESHOLD: usize = 4;
+    const MAX_THRESHOLD: usize = 1000;
+    const MAX_SLACK: f64 = 4.;
+    const WINDOW: Duration = Duration::from_millis(10);
+    // weight of a new sample, same as TCP's smoothed RTT
+    const ALPHA: f64 = 0.125;
 
-    fn index(&self, index: u32) -> &Self::Output {
-        &self.0[(index - 1) as usize]
+    fn new() -> Self {
+        Self {
+            stats: QueryTriggerStats {
+                ordered_rate: 0.,
+                query_rtt: Duration::from_micros(100),
+                loss_rate: 0.5,
+                // until the first estimation
+                threshold: Replica::QUERY_THRESHOLD,
+                num_query: 0,
+                num_premature: 0,
+            },
+            window_start: Timestamp::now(),
+            window_ordered: 0,
+            outstanding: None,
+        }
+    }
+
+    fn on_ordered(&mut self, count: usize) {
+        self.window_ordered += count as u32;
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= Self::WINDOW {
+            let rate = self.window_ordered as f64 / elapsed.as_secs_f64();
+            self.stats.ordered_rate += Self::ALPHA * (rate - self.stats.ordered_rate);
+            self.window_start = Timestamp::now();
+            self.window_ordered = 0;
+            self.update_threshold()
+        }
+    }
+
+    // whether to query for `hole`, with `num_pending` messages waiting behind it
+    fn trigger(&mut self, num_pending: usize, hole: u64) -> bool {
+        if num_pending < self.stats.threshold {
+            return false;
+        }
+        // re-query only after the outstanding one is considered lost
+        if let Some((op_num, start)) = self.outstanding {
+            if op_num == hole && start.elapsed() < 4 * self.stats.query_rtt {
+                return false;
+            }
+        }
+        self.outstanding = Some((hole, Timestamp::now()));
+        self.stats.num_query += 1;
+        true
+    }
+
+    fn on_reply(&mut self, op_num: u64, filled: bool) {
+        let Some((hole, start)) = self.outstanding else {
+            return;
+        };
+        if hole != op_num {
+            return;
+        }
+        self.outstanding = None;
+        let rtt = start.elapsed().as_secs_f64();
+        let query_rtt = self.stats.query_rtt.as_secs_f64();
+        self.stats.query_rtt = Duration::from_secs_f64(query_rtt + Self::ALPHA * (rtt - query_rtt));
+        let loss = if filled { 0. } else { 1. };
+        self.stats.loss_rate += Self::ALPHA * (loss - self.stats.loss_rate);
+        if filled {
+            self.stats.num_premature += 1
+        }
+        self.update_threshold()
+    }
+
+    fn update_threshold(&mut self) {
+        let slack = 1. + (Self::MAX_SLACK - 1.) * (1. - self.stats.loss_rate);
+        let gap = self.stats.ordered_rate * self.stats.query_rtt.as_secs_f64() * slack;
+        self.stats.threshold = (gap.ceil() as usize).clamp(Self::MIN_THRESHOLD, Self::MAX_THRESHOLD)
+    }
+}
+
+// indexed by op number, with the first this many requests truncated
+struct I<'a>(&'a [OrderedMulticast<Vec<Request>>], u64);
+
+impl std::ops::Index<u64> for I<'_> {
+    type Output = OrderedMulticast<Vec<Request>>;
+
+    fn index(&self, index: u64) -> &Self::Output {
+        &self.0[(index - self.1 - 1) as usize]
     }
 }
 
-impl std::ops::Index<RangeInclusive<u32>> for I<'_> {
-    type Output = [OrderedMulticast<Request>];
+impl std::ops::Index<RangeInclusive<u64>> for I<'_> {
+    type Output = [OrderedMulticast<Vec<Request>>];
 
-    fn index(&self, index: RangeInclusive<u32>) -> &Self::Output {
-        &self.0[(*index.start() - 1) as usize..=(*index.end() - 1) as usize]
+    fn index(&self, index: RangeInclusive<u64>) -> &Self::Output {
+        &self.0[(*index.start() - self.1 - 1) as usize..=(*index.end() - self.1 - 1) as usize]
     }
 }
 
@@ -226,18 +503,38 @@ impl Receivers for Replica {
     fn handle(&mut self, receiver: Host, remote: Host, message: Self::Message) {
         // println!("{message:?}");
         match (receiver, message) {
-            (Host::Multicast, Message::Request(message)) => self.handle_request(remote, message),
+            (Host::Multicast, Message::Request(message)) => self.handle_multicast(remote, message),
             (Host::Replica(_), Message::Confirm(message)) => self.handle_confirm(remote, message),
             (Host::Replica(_), Message::Query(message)) => self.handle_query(remote, message),
             (Host::Replica(_), Message::QueryOk(message)) => self.handle_query_ok(remote, message),
+            (Host::Replica(_), Message::Checkpoint(message)) => self.insert_checkpoint(message),
+            (Host::Replica(_), Message::EpochChange(message)) => self.handle_epoch_change(message),
+            (Host::Replica(_), Message::EpochStart(message)) => self.handle_epoch_start(message),
+            (Host::Replica(_), Message::Resend(message)) => self.handle_resend(remote, message),
             _ => unimplemented!(),
         }
+        self.do_resume()
+    }
+
+    // confirms covered by a previous one of the same replica
+    fn is_stale(&self, _: Host, message: &Self::Message) -> bool {
+        let Message::Confirm(message) = message else {
+            return false;
+        };
+        matches!(
+            self.remote_confirmed_nums.get(&message.replica_index),
+            Some(&confirmed_num) if *message.op_nums.end() <= confirmed_num
+        )
     }
 
     fn handle_loopback(&mut self, receiver: Host, message: Self::Message) {
         assert_eq!(receiver, Host::Replica(self.index));
-        let Message::Confirm(confirm) = message else {
-            unreachable!()
+        let confirm = match message {
+            Message::Confirm(confirm) => confirm,
+            Message::Checkpoint(checkpoint) => return self.insert_checkpoint(checkpoint),
+            Message::EpochChange(change) => return self.handle_epoch_change(change),
+            Message::EpochStart(start) => return self.handle_epoch_start(start),
+            _ => unreachable!(),
         };
         // println!("> confirm #s {:?}", confirm.op_nums);
         let evicted = self
@@ -245,14 +542,20 @@ impl Receivers for Replica {
             .insert(self.index, *confirm.op_nums.end());
         assert_eq!(evicted.unwrap() + 1, *confirm.op_nums.start());
         self.do_update_confirm_num();
+        self.do_resume();
 
         // if self.ordered_num >= self.local_confirmed_num + Self::CONFIRM_THRESHOLD {
         //     self.do_send_confirm()
         // }
     }
 
-    fn on_timer(&mut self, _: Host, _: crate::context::TimerId) {
-        unreachable!()
+    fn on_timer(&mut self, _: Host, id: crate::context::TimerId) {
+        if Some(id) == self.fill_timer.id {
+            return self.do_fill();
+        }
+        // a stalled epoch change moves on to the next epoch as well
+        let epoch_num = self.changing_epoch.unwrap_or(self.epoch_num) + 1;
+        self.do_change_epoch(epoch_num)
     }
 
     fn on_pace(&mut self) {
@@ -260,52 +563,187 @@ impl Receivers for Replica {
             self.do_send_confirm()
         }
     }
+
+    fn gauges(&self) -> Gauges {
+        Gauges {
+            log_len: Some(self.requests.len() as _),
+            commit_num: Some(if self.confirm {
+                self.confirmed_num
+            } else {
+                self.verified_num
+            }),
+            reorder_len: Some(self.reorder.num_pending() as _),
+        }
+    }
 }
 
 impl OrderedMulticastReceivers for Replica {
-    type Message = Request;
+    type Message = Vec<Request>;
 }
 
 impl Replica {
     // pub const CONFIRM_THRESHOLD: u32 = 100;
+    // the query trigger before it has any estimation
     pub const QUERY_THRESHOLD: usize = 100;
+    const SEQUENCER_TIMEOUT: Duration = Duration::from_millis(50);
+    // an idle group holds back every other one, so it is filled well before the sequencer timeout
+    const FILL_DELAY: Duration = Duration::from_millis(1);
+    // the epoch changes ahead of the current epoch that are kept, as the faulty replicas may send
+    // ones for any epoch
+    const EPOCH_WINDOW: u32 = 16;
 
-    fn handle_request(&mut self, _remote: Host, message: OrderedMulticast<Request>) {
-        // Jialin's trick to avoid resetting switch for every run
-        let op_num = message.seq_num - *self.seq_num_offset.get_or_insert(message.seq_num) + 1;
-        // if message.verified() {
-        //     println!(">> verified {}", op_num)
-        // }
-        // eager querying may defeat the slow original message...
-        // assert!(op_num >= next_op_num);
-        if op_num < self.ordered_num + 1 {
+    // the messages of a new sequencer that arrive before entering its epoch are dropped along
+    // with the ones of the replaced sequencer, so it should start after the replicas move on
+    fn handle_request(&mut self, remote: Host, message: OrderedMulticast<Vec<Request>>) {
+        if self.halted || self.changing_epoch.is_some() {
             return;
         }
+        self.do_order(remote, message)
+    }
+
+    // execution lags behind by more than `Config::max_inflight` ops, so the ordered multicast is held
+    // back instead of growing the log, and dropped once as many are held, to be queried for after
+    // catching up
+    fn handle_multicast(&mut self, remote: Host, message: OrderedMulticast<Vec<Request>>) {
+        if self.deferred.is_empty() && !self.is_lagging() {
+            return self.handle_request(remote, message);
+        }
+        if self.deferred.len() < self.context.config().max_inflight as usize {
+            self.deferred.push_back((remote, message))
+        } else {
+            tracing::debug!(
+                op_num = self.ordered_num,
+                "drop ordered multicast while lagging"
+            )
+        }
+    }
+
+    // called after every message that may move execution forward, and not inside `do_order`, which
+    // the held back messages are ordered with
+    fn do_resume(&mut self) {
+        while !self.is_lagging() {
+            let Some((remote, message)) = self.deferred.pop_front() else {
+                break;
+            };
+            self.handle_request(remote, message)
+        }
+    }
+
+    // without confirm the verified ops are executed right away, and only the unverified ones wait,
+    // for the next verified one that is bounded by the sequencer's signing interval
+    fn is_lagging(&self) -> bool {
+        let max_inflight = self.context.config().max_inflight;
+        max_inflight != 0
+            && self.confirm
+            && self.verified_num.saturating_sub(self.confirmed_num) > max_inflight
+    }
 
-        if op_num != self.ordered_num + 1 {
-            // println!("! miss {}", self.ordered_num + 1);
-            self.reordering_requests.insert(op_num, message);
-            // reordering should be resolved within millisecond
-            assert!(self.reordering_requests.len() < Self::QUERY_THRESHOLD + 1000);
-            if self.reordering_requests.len() == Self::QUERY_THRESHOLD {
-                self.do_query()
+    fn handle_resend(&mut self, remote: Host, message: Signed<Request>) {
+        match self
+            .client_table
+            .lookup(message.client_index, message.request_num, remote)
+        {
+            ClientLookup::Resend(reply) => {
+                let reply = reply.clone();
+                return self.do_send_reply(message.client_index, reply);
             }
+            ClientLookup::Execute => {}
+            _ => return,
+        }
+        // ordered already and waiting to be committed
+        let executed_num = if self.confirm {
+            self.confirmed_num
+        } else {
+            self.verified_num
+        };
+        let first_num = executed_num.max(self.truncated_num) + 1;
+        if first_num <= self.ordered_num
+            && I(&self.requests, self.truncated_num)[first_num..=self.ordered_num]
+                .iter()
+                .flat_map(|batch| batch.iter())
+                .any(|request| {
+                    (request.client_index, request.request_num)
+                        == (message.client_index, message.request_num)
+                })
+        {
             return;
         }
+        self.resent = true;
+        self.do_watch_sequencer()
+    }
+
+    fn do_order(&mut self, remote: Host, message: OrderedMulticast<Vec<Request>>) {
+        // eager querying may defeat the slow original message...
+        let requests = match self.reorder.receive_from(remote, message) {
+            // sequenced before the first received message, which the log starts from
+            Reordered::Stale(0, _)
+            | Reordered::Duplicated
+            | Reordered::Conflicted
+            | Reordered::Beyond => return,
+            Reordered::Equivocated(op_num, _) => {
+                self.do_misbehave(Misbehavior::Duplicated(op_num));
+                return;
+            }
+            // beyond the reorder history, check against the log unless truncated, and only the
+            // authenticated ones are evidence, as `Reordered::Equivocated`
+            Reordered::Stale(op_num, message) => {
+                if op_num > self.truncated_num && message.verified() {
+                    let logged = &I(&self.requests, self.truncated_num)[op_num];
+                    if logged.verified() && digest(logged) != digest(&message) {
+                        self.do_misbehave(Misbehavior::Duplicated(op_num))
+                    }
+                }
+                return;
+            }
+            Reordered::Pending => {
+                // println!("! miss {}", self.ordered_num + 1);
+                // reordering should be resolved within millisecond
+                assert!(self.reorder.num_reordering() < QueryTrigger::MAX_THRESHOLD + 1000);
+                self.do_check_skipped();
+                self.do_query();
+                self.do_watch_sequencer();
+                self.do_watch_groups();
+                return;
+            }
+            Reordered::Ordered(requests) => requests,
+        };
+        self.query_trigger.on_ordered(requests.len());
 
         let mut verified_num = self.verified_num;
-        self.ordered_num += 1;
-        if message.verified() {
-            verified_num = self.ordered_num
-        }
-        self.requests.push(message);
-        while let Some(request) = self.reordering_requests.remove(&(self.ordered_num + 1)) {
-            self.ordered_num += 1;
+        for (op_num, request) in requests {
+            // linked to the previous request of its group, unless it is truncated or of the epoch
+            // before, whose sequencer is another one
+            let previous_num = op_num.saturating_sub(self.reorder.num_group() as _);
+            if previous_num > self.truncated_num.max(self.epoch_op_num) {
+                let previous = &I(&self.requests, self.truncated_num)[previous_num];
+                if !matches!(request.signature, HalfSipHash(_))
+                    && <[_; 32]>::from(previous.state().finalize()) != request.linked
+                {
+                    // either of them may be forged if it is not authenticated on its own, which is
+                    // no evidence against the sequencer, but the log cannot be extended either
+                    if previous.verified() && request.verified() {
+                        self.do_misbehave(Misbehavior::BrokenLink(op_num))
+                    } else {
+                        tracing::warn!(op_num, "unauthenticated broken link");
+                        self.do_halt()
+                    }
+                    break;
+                }
+            }
+            self.ordered_num = op_num;
             if request.verified() {
-                verified_num = self.ordered_num
+                verified_num = op_num
             }
             self.requests.push(request);
         }
+        assert!(self.halted || self.ordered_num == self.reorder.ordered_num());
+        // restart the countdown on progress
+        self.resent = false;
+        if self.sequencer_timer.id.is_some() {
+            self.sequencer_timer.unset(&mut self.context)
+        }
+        self.do_watch_sequencer();
+        self.do_watch_groups();
 
         for op_num in self.verified_num + 1..=verified_num {
             if !self.confirm {
@@ -328,71 +766,158 @@ impl Replica {
         // println!("> confirm #{} {:?}", message.replica_index, message.op_nums);
 
         if *message.op_nums.end() > self.ordered_num {
-            self.reordering_confirms1
-                .entry(*message.op_nums.end())
-                .or_default()
-                .push(message);
+            let op_num = *message.op_nums.end();
+            if let Some(confirms) = self.reordering_confirms1.entry(op_num, op_num) {
+                confirms.push(message)
+            }
             return;
         }
         self.do_confirm1(message)
     }
 
     fn handle_query(&mut self, _remote: Host, message: Signed<Query>) {
-        let mut request = if message.op_num <= self.ordered_num {
-            I(&self.requests)[message.op_num].clone()
-        } else if let Some(request) = self.reordering_requests.get(&message.op_num) {
+        let mut request = if message.op_num <= self.truncated_num {
+            tracing::warn!(op_num = message.op_num, "query truncated");
+            return;
+        } else if message.op_num <= self.ordered_num {
+            I(&self.requests, self.truncated_num)[message.op_num].clone()
+        } else if let Some(request) = self.reorder.get_pending(message.op_num) {
             request.clone()
         } else {
-            println!("! query missing {}", message.op_num);
+            tracing::warn!(op_num = message.op_num, "query missing");
             return;
         };
-        if let &K256Unverified(signature) = &request.signature {
-            request.signature = K256(signature)
+        if let &EcdsaUnverified(signature) = &request.signature {
+            request.signature = Ecdsa(signature)
         }
         // println!("< query replied {}", message.op_num);
         let query_ok = QueryOk {
             op_num: message.op_num,
             request,
         };
-        self.context
-            .send(To::replica(message.replica_index), query_ok)
+        let to = if message.standby {
+            To::Standby(message.replica_index)
+        } else {
+            To::replica(message.replica_index)
+        };
+        self.context.send(to, query_ok)
     }
 
     fn handle_query_ok(&mut self, remote: Host, message: QueryOk) {
+        self.query_trigger
+            .on_reply(message.op_num, message.op_num <= self.ordered_num);
         if message.op_num == self.ordered_num + 1 {
             // println!("> query done {}", message.op_num);
             // let ordered_num = self.ordered_num;
             // let verified_num = self.verified_num;
-            self.handle_request(remote, message.request);
+            let Some((epoch_num, op_num)) = self.epoch_start else {
+                self.handle_request(remote, message.request);
+                return self.do_query();
+            };
+            // catching up to the epoch start, with the sequencer of the epoch before
+            self.do_order(remote, message.request);
             // println!(
             //     "> ordered {ordered_num} -> {} verified {verified_num} -> {}",
             //     self.ordered_num, self.verified_num
             // );
-            if self.reordering_requests.len() >= Self::QUERY_THRESHOLD {
-                self.do_query()
+            if self.ordered_num >= op_num {
+                self.do_enter_epoch(epoch_num, op_num)
+            } else {
+                self.do_send_query(self.ordered_num + 1)
             }
         }
     }
 
-    fn do_commit(&mut self, op_num: u32) {
-        let request = &I(&self.requests)[op_num];
-        match self.replies.get(&request.client_index) {
-            Some(reply) if reply.request_num > request.request_num => return,
-            Some(reply) if reply.request_num == request.request_num => {
-                self.context
-                    .send(To::client(request.client_index), reply.clone());
+    fn do_commit(&mut self, op_num: u64) {
+        let _span = tracing::debug_span!("commit", op_num).entered();
+        if let Some(strictness) = &self.strictness {
+            strictness.on_commit(op_num)
+        }
+        self.app.set_op_num(op_num);
+        // without confirm the op is executed before it is agreed on, and may be rolled back on
+        // entering the next epoch
+        if !self.confirm {
+            self.app.speculate(op_num);
+            self.client_table.speculate()
+        }
+        self.do_execute(op_num);
+        let interval = self.context.config().checkpoint_interval;
+        if interval != 0 && op_num.is_multiple_of(interval) {
+            self.do_checkpoint(op_num)
+        }
+    }
+
+    fn do_execute(&mut self, op_num: u64) {
+        for index in 0..I(&self.requests, self.truncated_num)[op_num].len() {
+            self.do_execute_request(op_num, index)
+        }
+    }
+
+    // the `index`th request of the batch, which shares the op number with the others
+    fn do_execute_request(&mut self, op_num: u64, index: usize) {
+        let batch = &I(&self.requests, self.truncated_num)[op_num];
+        let seq_num = batch.seq_num;
+        let request = &batch[index];
+        tracing::trace!(
+            client_index = request.client_index,
+            request_num = request.request_num,
+            "execute"
+        );
+        // ordered multicast carries no sender address, so there is no collision to detect
+        let remote = Host::Client(request.client_index);
+        match self
+            .client_table
+            .lookup(request.client_index, request.request_num, remote)
+        {
+            ClientLookup::Execute => {}
+            ClientLookup::Resend(reply) => {
+                let reply = reply.clone();
+                self.do_send_reply(request.client_index, reply);
                 return;
             }
-            _ => {}
+            ClientLookup::Ignore => return,
         }
         let reply = Reply {
-            epoch_num: 0,
+            epoch_num: self.epoch_num,
             request_num: request.request_num,
             result: self.app.execute(&request.op),
-            seq_num: request.seq_num,
+            seq_num,
             replica_index: self.index,
+            backpressure: false,
+        };
+        // without confirm only the stable checkpoint is never rolled back
+        let committed_num = if self.confirm {
+            op_num
+        } else {
+            self.stable_op_num
+        };
+        self.client_table.insert(
+            request.client_index,
+            remote,
+            op_num,
+            request.request_num,
+            reply.clone(),
+            committed_num,
+        );
+        // only the reply on executing tells the lag, the resent and re-derived ones do not
+        let reply = Reply {
+            backpressure: self.backpressure(op_num),
+            ..reply
         };
-        self.context.send(To::client(request.client_index), reply)
+        self.do_send_reply(request.client_index, reply)
+    }
+
+    // whether the execution of `op_num` lags behind ordering by more than the watermark, or ordered
+    // multicast is held back for it, see `handle_multicast`
+    fn backpressure(&self, op_num: u64) -> bool {
+        let max_inflight = self.context.config().max_inflight;
+        max_inflight != 0 && (self.ordered_num - op_num > max_inflight || !self.deferred.is_empty())
+    }
+
+    fn do_send_reply(&mut self, client_index: ClientIndex, reply: Reply) {
+        if !self.standby.load(SeqCst) {
+            self.context.send(To::client(client_index), reply)
+        }
     }
 
     fn do_send_confirm(&mut self) {
@@ -403,16 +928,29 @@ impl Replica {
         // );
         if !op_nums.is_empty() && *op_nums.start() == self.remote_confirmed_nums[&self.index] + 1 {
             // println!("confirming {op_nums:?}");
-            let mut digest = Sha256::new();
-            for request in &I(&self.requests)[op_nums.clone()] {
-                Hasher::sha256_update(&request.inner, &mut digest);
+            // split on the checkpoint op numbers, the only ones that a replica truncates on, so
+            // that every confirm is either below the truncation of a receiver or fully above it,
+            // see `do_confirm2`
+            let interval = self.context.config().checkpoint_interval;
+            let mut start = *op_nums.start();
+            while start <= *op_nums.end() {
+                let end = if interval == 0 {
+                    *op_nums.end()
+                } else {
+                    (start.div_ceil(interval) * interval).min(*op_nums.end())
+                };
+                let mut digest = Sha256::new();
+                for request in &I(&self.requests, self.truncated_num)[start..=end] {
+                    Hasher::sha256_update(&request.inner, &mut digest);
+                }
+                let confirm = Confirm {
+                    digest: digest.finalize().into(),
+                    op_nums: start..=end,
+                    replica_index: self.index,
+                };
+                self.context.send(To::AllReplicaWithLoopback, confirm);
+                start = end + 1
             }
-            let confirm = Confirm {
-                digest: digest.finalize().into(),
-                op_nums,
-                replica_index: self.index,
-            };
-            self.context.send(To::AllReplicaWithLoopback, confirm);
             // TODO set up resending confirm
             self.local_confirmed_num = self.ordered_num
         }
@@ -422,8 +960,9 @@ impl Replica {
         let confirmed_num = self.remote_confirmed_nums[&message.replica_index];
         assert!(*message.op_nums.start() > confirmed_num);
         if *message.op_nums.start() != confirmed_num + 1 {
+            let op_num = *message.op_nums.start();
             self.reordering_confirms2
-                .insert((message.replica_index, *message.op_nums.start()), message);
+                .insert((message.replica_index, op_num), op_num, message);
             return;
         }
         let index = message.replica_index;
@@ -437,11 +976,36 @@ impl Replica {
     }
 
     fn do_confirm2(&mut self, message: Signed<Confirm>) {
+        // a lagging replica confirms requests truncated already, which are agreed on by the stable
+        // checkpoint anyway
+        // a range that reaches past the truncation cannot be verified, and is never sent by a
+        // correct replica, see `do_send_confirm`
+        if *message.op_nums.start() <= self.truncated_num {
+            if *message.op_nums.end() > self.truncated_num {
+                tracing::warn!(
+                    replica_index = message.replica_index,
+                    op_nums = ?message.op_nums,
+                    truncated_num = self.truncated_num,
+                    "confirm across truncation"
+                );
+                return;
+            }
+            self.remote_confirmed_nums
+                .insert(message.replica_index, *message.op_nums.end());
+            return self.do_update_confirm_num();
+        }
         let mut local_digest = Sha256::new();
-        for request in &I(&self.requests)[message.op_nums.clone()] {
+        for request in &I(&self.requests, self.truncated_num)[message.op_nums.clone()] {
             Hasher::sha256_update(&request.inner, &mut local_digest)
         }
-        assert_eq!(<[_; 32]>::from(local_digest.finalize()), message.digest);
+        if <[_; 32]>::from(local_digest.finalize()) != message.digest {
+            // the remote replica is never counted as confirming beyond this point
+            self.do_misbehave(Misbehavior::Diverged(
+                message.replica_index,
+                message.op_nums.clone(),
+            ));
+            return;
+        }
         self.remote_confirmed_nums
             .insert(message.replica_index, *message.op_nums.end());
         self.do_update_confirm_num()
@@ -459,18 +1023,310 @@ impl Replica {
         self.confirmed_num = new_confirmed_num;
     }
 
+    fn do_checkpoint(&mut self, op_num: u64) {
+        let previous_num = self
+            .checkpoints
+            .last()
+            .map_or(0, |snapshot| snapshot.op_num);
+        self.checkpoints.push(self.app.checkpoint(op_num));
+        let mut digest = Sha256::new();
+        digest.update(
+            self.checkpoint_digests
+                .last_key_value()
+                .map_or(Default::default(), |(_, digest)| *digest),
+        );
+        for request in &I(&self.requests, self.truncated_num)[previous_num + 1..=op_num] {
+            Hasher::sha256_update(&request.inner, &mut digest)
+        }
+        let digest = digest.finalize().into();
+        self.checkpoint_digests.insert(op_num, digest);
+        let checkpoint = Checkpoint {
+            op_num,
+            digest,
+            replica_index: self.index,
+        };
+        // a standby is not one of the replicas, but still truncates on their checkpoints
+        if self.standby.load(SeqCst) {
+            self.context.send(To::Loopback, checkpoint)
+        } else {
+            self.context.send(To::AllReplicaWithLoopback, checkpoint)
+        }
+    }
+
+    fn insert_checkpoint(&mut self, checkpoint: Signed<Checkpoint>) {
+        if checkpoint.op_num <= self.stable_op_num {
+            return;
+        }
+        let key = (checkpoint.op_num, checkpoint.digest);
+        let certificate = self.checkpoint_certificates.entry(key).or_default();
+        certificate.insert(checkpoint.replica_index, checkpoint);
+        if certificate.len() >= self.context.config().num_replica - self.context.config().num_faulty
+            && certificate.contains_key(&self.index)
+        {
+            let op_num = key.0;
+            let mut certificates = self
+                .checkpoint_certificates
+                .split_off(&(op_num + 1, Default::default()));
+            std::mem::swap(&mut certificates, &mut self.checkpoint_certificates);
+            self.stable_certificate = certificates.remove(&key).unwrap();
+            self.stable_op_num = op_num;
+            SnapshotHandle::compact(&mut self.checkpoints, op_num);
+            self.checkpoint_digests = self.checkpoint_digests.split_off(&op_num);
+            self.app.truncate_undo(op_num);
+            self.client_table.truncate_undo(op_num);
+            self.requests
+                .drain(..(op_num - self.truncated_num) as usize);
+            self.truncated_num = op_num
+        }
+    }
+
+    // on authenticated evidence only, i.e. the messages that are `verified`
+    fn do_misbehave(&mut self, misbehavior: Misbehavior) {
+        tracing::error!(?misbehavior, "sequencer misbehavior");
+        if !matches!(misbehavior, Misbehavior::Diverged(..)) {
+            self.do_halt()
+        }
+        self.misbehaviors.push(misbehavior)
+    }
+
+    fn do_halt(&mut self) {
+        self.halted = true;
+        self.do_watch_sequencer()
+    }
+
+    // a skipped op number is indistinguishable from a lost message, except that with ECDSA the
+    // sequencer links the message after it to the one before, and signs the link
+    fn do_check_skipped(&mut self) {
+        let num_group = self.reorder.num_group() as u64;
+        let hole = self.ordered_num + 1;
+        let Some(previous_num) = hole
+            .checked_sub(num_group)
+            .filter(|&op_num| op_num > self.truncated_num.max(self.epoch_op_num))
+        else {
+            return;
+        };
+        let Some(next) = self.reorder.get_pending(hole + num_group) else {
+            return;
+        };
+        if !self.halted
+            && matches!(next.signature, Ecdsa(_))
+            && <[_; 32]>::from(
+                I(&self.requests, self.truncated_num)[previous_num]
+                    .state()
+                    .finalize(),
+            ) == next.linked
+        {
+            self.do_misbehave(Misbehavior::Skipped(hole))
+        }
+    }
+
+    fn do_watch_sequencer(&mut self) {
+        // the requests waiting for the turns of their groups as well, as the epoch leader fills the
+        // idle groups, unless it is faulty
+        let waiting = self.halted
+            || self.resent
+            || self.reorder.num_reordering() != 0
+            || self.reorder.is_held_back();
+        // a standby has no say in the epoch change, and follows the others
+        if waiting && self.sequencer_timer.id.is_none() && !self.standby.load(SeqCst) {
+            self.sequencer_timer.set(&mut self.context)
+        } else if !waiting && self.changing_epoch.is_none() && self.sequencer_timer.id.is_some() {
+            self.sequencer_timer.unset(&mut self.context)
+        }
+    }
+
+    fn is_filling(&self) -> bool {
+        self.epoch_leader(self.epoch_num) == self.index
+            && !self.standby.load(SeqCst)
+            && !self.halted
+            && self.changing_epoch.is_none()
+    }
+
+    fn do_watch_groups(&mut self) {
+        let filling = self.is_filling() && !self.reorder.fills().is_empty();
+        if filling && self.fill_timer.id.is_none() {
+            self.fill_timer.set(&mut self.context)
+        } else if !filling && self.fill_timer.id.is_some() {
+            self.fill_timer.unset(&mut self.context)
+        }
+    }
+
+    // multicast empty batches to the idle groups, which are ordered as ops without requests
+    fn do_fill(&mut self) {
+        self.fill_timer.unset(&mut self.context);
+        if !self.is_filling() {
+            return;
+        }
+        for (group, op_nums) in self.reorder.fills() {
+            let fill_num = &mut self.fill_nums[group as usize];
+            for _ in op_nums.start.max(*fill_num)..op_nums.end {
+                self.context
+                    .send_ordered_multicast_to(group, Vec::<Request>::new())
+            }
+            *fill_num = (*fill_num).max(op_nums.end)
+        }
+    }
+
+    fn epoch_leader(&self, epoch_num: u32) -> ReplicaIndex {
+        (epoch_num as usize % self.context.config().num_replica) as _
+    }
+
+    fn do_change_epoch(&mut self, epoch_num: u32) {
+        tracing::info!(epoch_num, op_num = self.ordered_num, "change epoch");
+        self.changing_epoch = Some(epoch_num);
+        if self.sequencer_timer.id.is_none() {
+            self.sequencer_timer.set(&mut self.context)
+        }
+        let change = EpochChange {
+            epoch_num,
+            op_num: self.ordered_num,
+            replica_index: self.index,
+        };
+        self.context.send(To::AllReplicaWithLoopback, change)
+    }
+
+    fn handle_epoch_change(&mut self, message: Signed<EpochChange>) {
+        if message.epoch_num <= self.epoch_num
+            || message.epoch_num > self.epoch_num + Self::EPOCH_WINDOW
+        {
+            return;
+        }
+        let epoch_num = message.epoch_num;
+        let changes = self.epoch_changes.entry(epoch_num).or_default();
+        changes.insert(message.replica_index, message);
+        let num_change = changes.len();
+        // at least one of them is correct, so the sequencer does stall
+        if num_change > self.context.config().num_faulty
+            && self
+                .changing_epoch
+                .is_none_or(|changing| changing < epoch_num)
+            && !self.standby.load(SeqCst)
+        {
+            self.do_change_epoch(epoch_num)
+        }
+        if num_change == self.context.config().num_replica - self.context.config().num_faulty
+            && self.epoch_leader(epoch_num) == self.index
+        {
+            let changes = Vec::from_iter(self.epoch_changes[&epoch_num].values().cloned());
+            let start = EpochStart {
+                epoch_num,
+                op_num: start_op_num(&changes, self.context.config().num_faulty),
+                changes,
+                replica_index: self.index,
+            };
+            self.context.send(To::AllReplicaWithLoopback, start)
+        }
+    }
+
+    fn handle_epoch_start(&mut self, message: Signed<EpochStart>) {
+        if message.epoch_num <= self.epoch_num
+            || self
+                .epoch_start
+                .is_some_and(|(epoch_num, _)| epoch_num >= message.epoch_num)
+        {
+            return;
+        }
+        let mut indexes = HashSet::new();
+        if message.replica_index != self.epoch_leader(message.epoch_num)
+            || !message.changes.iter().all(|change| {
+                change.epoch_num == message.epoch_num && indexes.insert(change.replica_index)
+            })
+            || indexes.len() < self.context.config().num_replica - self.context.config().num_faulty
+            || start_op_num(&message.changes, self.context.config().num_faulty) != message.op_num
+        {
+            tracing::warn!(epoch_num = message.epoch_num, "invalid epoch start");
+            return;
+        }
+        if self.ordered_num < message.op_num {
+            self.changing_epoch = Some(message.epoch_num);
+            self.epoch_start = Some((message.epoch_num, message.op_num));
+            return self.do_send_query(self.ordered_num + 1);
+        }
+        self.do_enter_epoch(message.epoch_num, message.op_num)
+    }
+
+    fn do_enter_epoch(&mut self, epoch_num: u32, op_num: u64) {
+        tracing::info!(epoch_num, op_num, "enter epoch");
+        // the requests ordered beyond the start are not agreed on, and are dropped from the log,
+        // and the ones speculatively executed without confirm are rolled back along with their
+        // checkpoints and replies, to be executed again if the next sequencer orders them
+        if self.ordered_num > op_num {
+            assert!(op_num >= self.truncated_num);
+            self.requests
+                .truncate((op_num - self.truncated_num) as usize);
+            self.app.rollback(op_num);
+            self.client_table.rollback(op_num);
+            let num_checkpoint = self
+                .checkpoints
+                .partition_point(|snapshot| snapshot.op_num <= op_num);
+            self.checkpoints.truncate(num_checkpoint);
+            self.checkpoint_digests.split_off(&(op_num + 1));
+            self.ordered_num = op_num;
+            self.verified_num = self.verified_num.min(op_num);
+            self.local_confirmed_num = self.local_confirmed_num.min(op_num);
+            for confirmed_num in self.remote_confirmed_nums.values_mut() {
+                *confirmed_num = (*confirmed_num).min(op_num)
+            }
+        }
+        self.epoch_num = epoch_num;
+        self.epoch_op_num = op_num;
+        // of the replaced sequencer
+        self.deferred.clear();
+        self.reorder = Merge::resume(self.context.config().num_multicast_group(), op_num);
+        self.fill_nums.fill(0);
+        self.halted = false;
+        self.resent = false;
+        self.changing_epoch = None;
+        self.epoch_start = None;
+        self.epoch_changes = self.epoch_changes.split_off(&(epoch_num + 1));
+        if self.sequencer_timer.id.is_some() {
+            self.sequencer_timer.unset(&mut self.context)
+        }
+        if self.fill_timer.id.is_some() {
+            self.fill_timer.unset(&mut self.context)
+        }
+    }
+
     fn do_query(&mut self) {
+        // the next op is not sent yet rather than lost if nothing after it is received from its
+        // group
+        if !self.reorder.is_next_missing()
+            || !self
+                .query_trigger
+                .trigger(self.reorder.num_reordering(), self.ordered_num + 1)
+        {
+            return;
+        }
+        self.do_send_query(self.ordered_num + 1)
+    }
+
+    fn do_send_query(&mut self, op_num: u64) {
         let query = Query {
-            op_num: self.ordered_num + 1,
+            op_num,
             replica_index: self.index,
+            standby: self.standby_host,
         };
         // println!("< query sent {}", query.op_num);
         self.context.send(To::AllReplica, query)
     }
 }
 
-impl From<OrderedMulticast<Request>> for Message {
-    fn from(value: OrderedMulticast<Request>) -> Self {
+// the ops committed by 2f + 1 replicas are ordered by at least f + 1 of any 2f + 1 changes, so
+// by the (f + 1)th highest one, which at least one correct replica orders as well, i.e. neither
+// the committed ops are left out, nor the faulty replicas claim ops that no correct replica can
+// provide
+fn start_op_num(changes: &[Signed<EpochChange>], num_faulty: usize) -> u64 {
+    let mut op_nums = Vec::from_iter(changes.iter().map(|change| change.op_num));
+    op_nums.sort_unstable_by(|a, b| b.cmp(a));
+    op_nums[num_faulty]
+}
+
+fn digest(request: &OrderedMulticast<Vec<Request>>) -> [u8; 32] {
+    Hasher::sha256(&request.inner).finalize().into()
+}
+
+impl From<OrderedMulticast<Vec<Request>>> for Message {
+    fn from(value: OrderedMulticast<Vec<Request>>) -> Self {
         Self::Request(value)
     }
 }
@@ -481,26 +1337,70 @@ impl DigestHash for Reply {
         hasher.write(&self.result);
         hasher.write_u32(self.epoch_num);
         hasher.write_u32(self.seq_num);
-        hasher.write_u8(self.replica_index)
+        hasher.write_u8(self.replica_index);
+        hasher.write_u8(self.backpressure as _)
     }
 }
 
 impl DigestHash for Confirm {
     fn hash(&self, hasher: &mut impl std::hash::Hasher) {
         hasher.write(&self.digest);
-        hasher.write_u32(*self.op_nums.start());
-        hasher.write_u32(*self.op_nums.end());
+        hasher.write_u64(*self.op_nums.start());
+        hasher.write_u64(*self.op_nums.end());
         hasher.write_u8(self.replica_index)
     }
 }
 
 impl DigestHash for Query {
     fn hash(&self, hasher: &mut impl std::hash::Hasher) {
-        hasher.write_u32(self.op_num);
+        hasher.write_u64(self.op_num);
+        hasher.write_u8(self.replica_index);
+        hasher.write_u8(self.standby as _)
+    }
+}
+
+impl DigestHash for EpochChange {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u32(self.epoch_num);
+        hasher.write_u64(self.op_num);
+        hasher.write_u8(self.replica_index)
+    }
+}
+
+impl DigestHash for EpochStart {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u32(self.epoch_num);
+        hasher.write_u64(self.op_num);
+        for change in &self.changes {
+            change.inner.hash(hasher)
+        }
         hasher.write_u8(self.replica_index)
     }
 }
 
+// the sequencer orders the requests, so the replicas have nothing to equivocate on
+impl Tamper for Message {
+    fn is_vote(&self) -> bool {
+        matches!(self, Self::Confirm(_) | Self::Checkpoint(_))
+    }
+
+    fn corrupt(&self, signer: &Signer) -> Option<Self> {
+        match self {
+            Self::Confirm(message) => {
+                let mut confirm = message.inner.clone();
+                garble(&mut confirm.digest);
+                Some(Self::sign(confirm, signer))
+            }
+            Self::Checkpoint(message) => {
+                let mut checkpoint = message.inner.clone();
+                garble(&mut checkpoint.digest);
+                Some(Self::sign(checkpoint, signer))
+            }
+            _ => None,
+        }
+    }
+}
+
 impl Sign<Reply> for Message {
     fn sign(message: Reply, signer: &crate::context::crypto::Signer) -> Self {
         Message::Reply(signer.sign_private(message))
@@ -519,6 +1419,38 @@ impl Sign<Query> for Message {
     }
 }
 
+impl DigestHash for Checkpoint {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u64(self.op_num);
+        hasher.write(&self.digest);
+        hasher.write_u8(self.replica_index)
+    }
+}
+
+impl Sign<Checkpoint> for Message {
+    fn sign(message: Checkpoint, signer: &crate::context::crypto::Signer) -> Self {
+        Message::Checkpoint(signer.sign_public(message))
+    }
+}
+
+impl Sign<EpochChange> for Message {
+    fn sign(message: EpochChange, signer: &crate::context::crypto::Signer) -> Self {
+        Message::EpochChange(signer.sign_public(message))
+    }
+}
+
+impl Sign<EpochStart> for Message {
+    fn sign(message: EpochStart, signer: &crate::context::crypto::Signer) -> Self {
+        Message::EpochStart(signer.sign_public(message))
+    }
+}
+
+impl Sign<Request> for Message {
+    fn sign(message: Request, signer: &crate::context::crypto::Signer) -> Self {
+        Message::Resend(signer.sign_private(message))
+    }
+}
+
 impl From<QueryOk> for Message {
     fn from(value: QueryOk) -> Self {
         Self::QueryOk(value)
@@ -536,6 +1468,273 @@ impl Verify for Message {
             Self::Confirm(message) => verifier.verify(message, message.replica_index),
             Self::Query(message) => verifier.verify(message, message.replica_index),
             Self::QueryOk(message) => verifier.verify_ordered_multicast(&message.request),
+            Self::Checkpoint(message) => verifier.verify(message, message.replica_index),
+            Self::EpochChange(message) => verifier.verify(message, message.replica_index),
+            Self::EpochStart(message) => {
+                for change in &message.changes {
+                    verifier.verify(change, change.replica_index)?
+                }
+                verifier.verify(message, message.replica_index)
+            }
+            Self::Resend(message) => verifier.verify_client(message, message.client_index),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ycsb"))]
+mod tests {
+    use bincode::Options;
+
+    use crate::{
+        app::ycsb,
+        context::{
+            crypto::Signer,
+            ordered_multicast::{Signature::Linked, Variant},
+            simulated::{
+                fixture::{config, deliver},
+                Network,
+            },
+        },
+    };
+
+    use super::*;
+
+    struct Replies;
+
+    impl Receivers for Replies {
+        type Message = Message;
+
+        fn handle(&mut self, _: Host, _: Host, _: Self::Message) {}
+
+        fn on_timer(&mut self, _: Host, _: crate::context::TimerId) {
+            unreachable!()
+        }
+    }
+
+    // replica 3 executes an op that the others never order, which is then left out by the epoch
+    // change of the others, so replica 3 rolls it back on entering the next epoch
+    #[test]
+    fn rollback_on_epoch_start() {
+        let mut config = config(4, 1);
+        config.checkpoint_interval = 2;
+        let network = Network::new(config.clone(), true, |index| {
+            Variant::new_half_sip_hash(index, 1.)
+        });
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            let app = App::Ycsb(Default::default());
+            Replica::new(network.register(Host::Replica(index)), index, app, false)
+        }));
+        let mut client = network.register::<Message>(Host::Client(0));
+        let mut run = |replicas: &mut Vec<Replica>, request_num, op| {
+            client.send_ordered_multicast(vec![Request {
+                client_index: 0,
+                request_num,
+                op: bincode::options().serialize(&op).unwrap(),
+            }]);
+            let mut replies = Replies;
+            deliver(
+                &network,
+                replicas,
+                [(Host::Client(0), &mut replies as _)],
+                Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+            );
+        };
+        run(&mut replicas, 1, ycsb::Op::Insert("a".into(), "1".into()));
+        network.schedule_chaos(&"0s isolate 0\n0s isolate 1\n0s isolate 2".parse().unwrap());
+        run(&mut replicas, 2, ycsb::Op::Insert("b".into(), "2".into()));
+        assert_eq!(replicas[3].ordered_num, 2);
+        assert_eq!(replicas[3].checkpoints.len(), 1);
+
+        let changes = Vec::from_iter((0..3).map(|index| {
+            let change = EpochChange {
+                epoch_num: 1,
+                op_num: 1,
+                replica_index: index,
+            };
+            Signer::new(&config, Host::Replica(index)).sign_public(change)
+        }));
+        let start = EpochStart {
+            epoch_num: 1,
+            op_num: 1,
+            changes,
+            replica_index: 1,
+        };
+        replicas[3].handle_epoch_start(Signer::new(&config, Host::Replica(1)).sign_public(start));
+        assert_eq!(replicas[3].epoch_num, 1);
+        assert!(replicas[3].checkpoints.is_empty());
+        assert!(matches!(
+            replicas[3].client_table.lookup(0, 2, Host::Client(0)),
+            ClientLookup::Execute
+        ));
+        for key in ["a", "b"] {
+            let read = bincode::options()
+                .serialize(&ycsb::Op::Read(key.into()))
+                .unwrap();
+            for replica in &replicas {
+                assert_eq!(
+                    replica.app.execute_read(&read),
+                    replicas[0].app.execute_read(&read)
+                )
+            }
+        }
+        // the checkpoint taken after the rolled back op is discarded along with its delta, so the
+        // next one carries the write of the op before as well
+        let snapshot = replicas[3].app.checkpoint(2);
+        let crate::app::Delta::Ycsb(delta) = snapshot.delta else {
+            unreachable!()
+        };
+        assert_eq!(delta.get("a"), Some(&Some("1".into())));
+        assert_eq!(delta.get("b"), Some(&None))
+    }
+
+    // a client resends a request that no replica has ordered, as the sequencer is silent without
+    // leaving a gap, so the replicas move on to the next epoch, while the resent request that is
+    // executed already is replied again
+    #[test]
+    fn resend() {
+        let network = Network::new(config(4, 1), true, |index| {
+            Variant::new_half_sip_hash(index, 1.)
+        });
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+                false,
+            )
+        }));
+        let mut client = network.register::<Message>(Host::Client(0));
+        let request = |request_num| Request {
+            client_index: 0,
+            request_num,
+            op: Default::default(),
+        };
+        client.send_ordered_multicast(vec![request(1)]);
+        let mut replies = Collect(Default::default());
+        let run = |replicas: &mut Vec<Replica>, replies: &mut Collect| {
+            deliver(
+                &network,
+                replicas,
+                [(Host::Client(0), replies as _)],
+                Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+            )
+        };
+        run(&mut replicas, &mut replies);
+        assert_eq!(replies.0.len(), 4);
+
+        client.send(To::AllReplica, request(1));
+        run(&mut replicas, &mut replies);
+        assert_eq!(replies.0.len(), 8);
+        for replica in &replicas {
+            assert_eq!(replica.epoch_num, 0)
+        }
+
+        client.send(To::AllReplica, request(2));
+        run(&mut replicas, &mut replies);
+        assert_eq!(replies.0.len(), 8);
+        for replica in &replicas {
+            assert_eq!(replica.epoch_num, 1);
+            assert_eq!(replica.epoch_op_num, 1)
+        }
+    }
+
+    // the faulty replica claims ops that no correct replica has ordered
+    #[test]
+    fn start_op_num_bounded() {
+        let config = config(4, 0);
+        let changes = Vec::from_iter([3, 1, 2, 100].into_iter().enumerate().map(
+            |(index, op_num)| {
+                let change = EpochChange {
+                    epoch_num: 1,
+                    op_num,
+                    replica_index: index as _,
+                };
+                Signer::new(&config, Host::Replica(index as _)).sign_public(change)
+            },
+        ));
+        assert_eq!(start_op_num(&changes, 1), 3);
+        assert_eq!(start_op_num(&changes[..3], 1), 2)
+    }
+
+    // a message without a signature of its own conflicts with the ordered one, which may be forged
+    // by anyone, so it is not taken as evidence against the sequencer
+    #[test]
+    fn unauthenticated_conflict() {
+        let network = Network::new(config(4, 1), true, |_| {
+            Variant::new_ecdsa(Default::default())
+        });
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            Replica::new(
+                network.register(Host::Replica(index)),
+                index,
+                App::Null,
+                false,
+            )
+        }));
+        let mut client = network.register::<Message>(Host::Client(0));
+        let request = Request {
+            client_index: 0,
+            request_num: 1,
+            op: Default::default(),
+        };
+        client.send_ordered_multicast(vec![request.clone()]);
+        deliver(
+            &network,
+            &mut replicas,
+            [(Host::Client(0), &mut Replies as _)],
+            Network::run_ordered_multicast_until_quiescent::<_, Vec<Request>>,
+        );
+        let mut forged = I(&replicas[0].requests, 0)[1].clone();
+        assert!(forged.verified());
+        forged.signature = Linked;
+        forged.inner = vec![Request {
+            op: vec![1],
+            ..request
+        }];
+        replicas[0].handle_request(Host::UnkownMulticastSender, forged);
+        assert!(replicas[0].misbehaviors.is_empty());
+        assert!(!replicas[0].halted)
+    }
+
+    // the truncated requests of a lagging confirm are agreed on by the stable checkpoint, but the
+    // ones past the truncation are not, so a confirm that reaches across is not counted
+    #[test]
+    fn confirm_across_truncation() {
+        let mut config = config(4, 1);
+        config.checkpoint_interval = 2;
+        let network = Network::new(config.clone(), true, |index| {
+            Variant::new_half_sip_hash(index, 1.)
+        });
+        let mut replica = Replica::new(network.register(Host::Replica(0)), 0, App::Null, true);
+        replica.truncated_num = 2;
+        let confirm = |op_nums| {
+            let confirm = Confirm {
+                digest: Default::default(),
+                op_nums,
+                replica_index: 1,
+            };
+            Signer::new(&config, Host::Replica(1)).sign_public(confirm)
+        };
+        replica.do_confirm2(confirm(1..=3));
+        assert_eq!(replica.remote_confirmed_nums[&1], 0);
+        replica.do_confirm2(confirm(1..=2));
+        assert_eq!(replica.remote_confirmed_nums[&1], 2)
+    }
+
+    struct Collect(Vec<Reply>);
+
+    impl Receivers for Collect {
+        type Message = Message;
+
+        fn handle(&mut self, _: Host, _: Host, message: Self::Message) {
+            let Message::Reply(reply) = message else {
+                unreachable!()
+            };
+            self.0.push(reply.inner)
+        }
+
+        fn on_timer(&mut self, _: Host, _: crate::context::TimerId) {
+            unreachable!()
         }
     }
 }
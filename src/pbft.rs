@@ -1,17 +1,29 @@
 use std::{
-    collections::HashMap,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
     sync::{Arc, Mutex},
     time::Duration,
 };
 
+use control_messages::{ClientDestination, WaitingStats};
+use k256::sha2::{Digest, Sha256};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    client::BoxedConsume,
-    common::{Block, BlockDigest, Chain, Request, Timer},
+    app::SnapshotHandle,
+    client::{BoxedConsume, Outcome, Proof},
+    common::{
+        state_transfer::{self, Fetch, Fetched, StateTransfer},
+        Block, BlockDigest, Chain, Request, Timer, Waiting,
+    },
     context::{
-        crypto::{DigestHash, Sign, Signed, Verify},
-        ClientIndex, Host, Receivers, ReplicaIndex, To,
+        byzantine::{garble, Tamper},
+        clock::Timestamp,
+        crypto::{DigestHash, Sign, Signed, Signer, Verify},
+        metrics::Gauges,
+        ordered_multicast::{OrderedMulticast, Reorder, Reordered},
+        storage::Storage,
+        ClientIndex, Host, OrderedMulticastReceivers, Receivers, ReplicaIndex, To,
     },
     App, Context,
 };
@@ -19,18 +31,57 @@ use crate::{
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Message {
     Request(Signed<Request>),
+    OrderedRequest(OrderedMulticast<Request>),
     Reply(Signed<Reply>),
     PrePrepare(Signed<PrePrepare>),
     Prepare(Signed<Prepare>),
     Commit(Signed<Commit>),
+    Checkpoint(Signed<Checkpoint>),
+    Fetch(Signed<Fetch>),
+    Fetched(Fetched<Block>),
+    FetchedState(FetchedState),
+    ReadRequest(Signed<ReadRequest>),
+    ReadReply(Signed<ReadReply>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Reply {
-    request_num: u32,
-    result: Vec<u8>,
-    block_digest: BlockDigest,
-    replica_index: ReplicaIndex,
+    pub client_index: ClientIndex,
+    pub request_num: u32,
+    // of the executed op, so the signed replies bind the result to the op, see `kv-verify` example
+    pub op_digest: [u8; 32],
+    pub result: Vec<u8>,
+    pub block_digest: BlockDigest,
+    pub replica_index: ReplicaIndex,
+}
+
+// a request that is answered from the executed state of every replica without being ordered, for ops
+// that do not write
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadRequest(pub Request);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadReply {
+    pub request_num: u32,
+    // none if the op is not read-only
+    pub result: Option<Vec<u8>>,
+    // the number of executed ops, i.e. the state that the result is read from
+    pub op_num: u64,
+    pub replica_index: ReplicaIndex,
+}
+
+// the write-ahead log of a replica, see `Replica::recover`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Record {
+    // proposed or prepared by this replica in the view, which it must not contradict after a crash
+    Prepared(u32, Block),
+    // the `Commit` of this replica is sent
+    Committed(u32, BlockDigest),
+    // the block itself, as the fetched ones are never prepared locally
+    Executed(Block),
+    // the app snapshot after the op number, restored at the block instead of executing the blocks
+    // up to it, see `Replica::handle_fetched_state`
+    Restored(u64, Block, Vec<u8>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,9 +104,31 @@ pub struct Commit {
     replica_index: ReplicaIndex,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    op_num: u64,
+    // the executed block stands for the state, which the chain of blocks up to it leads to
+    block_digest: BlockDigest,
+    // of the app snapshot, which a replica behind the truncated log restores, see `App::snapshot`
+    state_digest: [u8; 32],
+    replica_index: ReplicaIndex,
+}
+
+// the state of the stable checkpoint, in answer to a fetch of the log truncated up to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchedState {
+    start: u64,
+    // the executed block of the checkpoint, which tells the height to continue from
+    block: Block,
+    certificate: Vec<Signed<Checkpoint>>,
+    snapshot: Vec<u8>,
+}
+
 #[derive(Debug)]
 pub struct Client {
     index: ClientIndex,
+    // send requests through sequencer, for sequenced replicas
+    ordered: bool,
     shared: Arc<Mutex<ClientShared>>,
 }
 
@@ -70,14 +143,29 @@ struct ClientShared {
 #[derive(Debug)]
 struct ClientInvoke {
     op: Vec<u8>,
-    replies: HashMap<ReplicaIndex, Reply>,
+    op_digest: [u8; 32],
+    // signed, so the matched ones are a proof checkable by others, see `kv-verify` example
+    replies: HashMap<ReplicaIndex, Signed<Reply>>,
+    // some while the op takes the read path, which falls back to ordering it once the replies
+    // cannot match on a quorum anymore, e.g. replicas have executed to different op numbers
+    read_replies: Option<HashMap<ReplicaIndex, Signed<ReadReply>>>,
     consume: BoxedConsume,
+    start: Timestamp,
 }
 
 impl Client {
     pub fn new(context: Context<Message>, index: ClientIndex) -> Self {
+        Self::new_internal(context, index, false)
+    }
+
+    pub fn new_ordered(context: Context<Message>, index: ClientIndex) -> Self {
+        Self::new_internal(context, index, true)
+    }
+
+    fn new_internal(context: Context<Message>, index: ClientIndex, ordered: bool) -> Self {
         Self {
             index,
+            ordered,
             shared: Arc::new(Mutex::new(ClientShared {
                 context,
                 request_num: 0,
@@ -96,24 +184,80 @@ impl crate::Client for Client {
         assert!(shared.invoke.is_none());
         shared.request_num += 1;
         shared.invoke = Some(ClientInvoke {
+            op_digest: op_digest(&op),
             op: op.clone(),
             replies: Default::default(),
+            read_replies: None,
             consume: consume.into(),
+            start: Timestamp::now(),
         });
-        let request = Request {
+        self.send_request(shared, op);
+        shared.resend_timer.set(&mut shared.context)
+    }
+
+    fn invoke_read(&self, op: Vec<u8>, consume: impl Into<BoxedConsume>) {
+        let shared = &mut *self.shared.lock().unwrap();
+        assert!(shared.invoke.is_none());
+        shared.request_num += 1;
+        shared.invoke = Some(ClientInvoke {
+            op_digest: op_digest(&op),
+            op: op.clone(),
+            replies: Default::default(),
+            read_replies: Some(Default::default()),
+            consume: consume.into(),
+            start: Timestamp::now(),
+        });
+        let request = ReadRequest(Request {
             client_index: self.index,
             request_num: shared.request_num,
             op,
-        };
-        // TODO
-        shared.context.send(To::replica(0), request);
+        });
+        shared.context.send(To::AllReplica, request);
         shared.resend_timer.set(&mut shared.context)
     }
 
     fn handle(&self, message: Self::Message) {
-        let Message::Reply(message) = message else {
-            unimplemented!()
+        match message {
+            Message::Reply(message) => self.handle_reply(message),
+            Message::ReadReply(message) => self.handle_read_reply(message),
+            _ => unimplemented!(),
+        }
+    }
+
+    // the read path may neither reach nor rule out a quorum, e.g. a replica is silent and the
+    // others answer at different op numbers, so it falls back on timeout as well
+    fn on_timer(&self) {
+        let shared = &mut *self.shared.lock().unwrap();
+        if shared
+            .invoke
+            .as_ref()
+            .is_some_and(|invoke| invoke.read_replies.is_some())
+        {
+            return self.fall_back(shared);
+        }
+        panic!("timeout")
+    }
+}
+
+impl Client {
+    fn send_request(&self, shared: &mut ClientShared, op: Vec<u8>) {
+        let request = Request {
+            client_index: self.index,
+            request_num: shared.request_num,
+            op,
+        };
+        let default = if self.ordered {
+            ClientDestination::Multicast
+        } else {
+            ClientDestination::Primary
         };
+        match shared.context.request_destination(default) {
+            Some(to) => shared.context.send(to, request),
+            None => shared.context.send_ordered_multicast(request),
+        }
+    }
+
+    fn handle_reply(&self, message: Signed<Reply>) {
         let shared = &mut *self.shared.lock().unwrap();
         if message.request_num != shared.request_num {
             return;
@@ -121,89 +265,398 @@ impl crate::Client for Client {
         let Some(invoke) = &mut shared.invoke else {
             return;
         };
+        if (message.client_index, message.op_digest) != (self.index, invoke.op_digest) {
+            return;
+        }
         invoke
             .replies
-            .insert(message.replica_index, Reply::clone(&message));
-        let num_match = invoke
-            .replies
-            .values()
-            .filter(|reply| {
-                (reply.block_digest, &reply.result) == (message.block_digest, &message.result)
-            })
-            .count();
-        assert!(num_match <= shared.context.config().num_faulty + 1);
-        if num_match == shared.context.config().num_faulty + 1 {
+            .insert(message.replica_index, message.clone());
+        let matched = Vec::from_iter(
+            invoke
+                .replies
+                .values()
+                .filter(|reply| {
+                    (reply.block_digest, &reply.result) == (message.block_digest, &message.result)
+                })
+                .cloned(),
+        );
+        assert!(matched.len() <= shared.context.config().num_faulty + 1);
+        if matched.len() == shared.context.config().num_faulty + 1 {
             shared.resend_timer.unset(&mut shared.context);
             let invoke = shared.invoke.take().unwrap();
             let _op = invoke.op;
-            invoke.consume.apply(message.inner.result)
+            invoke.consume.apply(Outcome {
+                proof: Some(Proof::new(matched)),
+                ..Outcome::new(message.inner.result, invoke.start)
+            })
+        }
+    }
+
+    fn handle_read_reply(&self, message: Signed<ReadReply>) {
+        let shared = &mut *self.shared.lock().unwrap();
+        if message.request_num != shared.request_num {
+            return;
+        }
+        let Some(invoke) = &mut shared.invoke else {
+            return;
+        };
+        let Some(read_replies) = &mut invoke.read_replies else {
+            return;
+        };
+        read_replies.insert(message.replica_index, message);
+        let mut counts = HashMap::<_, usize>::new();
+        for reply in read_replies.values() {
+            if let Some(result) = &reply.result {
+                *counts.entry((reply.op_num, result)).or_default() += 1
+            }
+        }
+        let config = shared.context.config();
+        let quorum = config.num_replica - config.num_faulty;
+        let num_pending = config.num_replica - read_replies.len();
+        let most_matched = counts.into_iter().max_by_key(|(_, count)| *count);
+        let num_matched = most_matched.as_ref().map_or(0, |(_, count)| *count);
+        if num_matched + num_pending < quorum {
+            return self.fall_back(shared);
+        }
+        if let Some(((op_num, result), num_matched)) = most_matched {
+            if num_matched < quorum {
+                return;
+            }
+            let result = result.clone();
+            let matched = Vec::from_iter(
+                read_replies
+                    .values()
+                    .filter(|reply| {
+                        (reply.op_num, reply.result.as_ref()) == (op_num, Some(&result))
+                    })
+                    .cloned(),
+            );
+            shared.resend_timer.unset(&mut shared.context);
+            let invoke = shared.invoke.take().unwrap();
+            invoke.consume.apply(Outcome {
+                op_num: Some(op_num as _),
+                proof: Some(Proof::new(matched)),
+                ..Outcome::new(result, invoke.start)
+            })
         }
     }
+
+    // order the op as if it was invoked by `invoke`, under the same request number
+    fn fall_back(&self, shared: &mut ClientShared) {
+        let invoke = shared.invoke.as_mut().unwrap();
+        invoke.read_replies = None;
+        let op = invoke.op.clone();
+        self.send_request(shared, op);
+        shared.resend_timer.reset(&mut shared.context)
+    }
 }
 
+// the checkpoints that agree on the op number, the executed block and the state
+type CheckpointKey = (u64, BlockDigest, [u8; 32]);
+
 #[derive(Debug)]
 pub struct Replica {
     context: Context<Message>,
     index: ReplicaIndex,
-    view_num: u32,
+    pub view_num: u32,
     requests: Vec<Request>,
-    pre_prepares: HashMap<BlockDigest, Signed<PrePrepare>>,
+    blocks: HashMap<BlockDigest, Block>,
     prepare_certificates: HashMap<BlockDigest, HashMap<ReplicaIndex, Signed<Prepare>>>,
     commit_certificates: HashMap<BlockDigest, HashMap<ReplicaIndex, Signed<Commit>>>,
-    chain: Chain,
+    // certificates of blocks not received yet, moved into the ones above once received
+    // digests do not tell how far a block is from the frontier, so the oldest entries, which are
+    // the least likely to be resolved, are evicted first
+    waiting_prepares: Waiting<BlockDigest, Reverse<u64>, HashMap<ReplicaIndex, Signed<Prepare>>>,
+    waiting_commits: Waiting<BlockDigest, Reverse<u64>, HashMap<ReplicaIndex, Signed<Commit>>>,
+    num_waiting: u64,
+    // certificates and `prepared` of executed blocks are removed, and messages for them are ignored
+    // afterwards, so the maps above only keep blocks in flight
+    executed_height: u32,
+    // digests of the executed blocks after the truncated ones, i.e. the log that lagging replicas
+    // fetch from
+    executed_blocks: Vec<BlockDigest>,
+    pub state_transfer: StateTransfer<Block>,
+    pub op_num: u64,
+    // the checkpoints taken since the stable one, which is merged into the first handle, so the
+    // state of any of them is the initial state of the app with the handles up to it applied
+    // or the restored state with the handles after it applied, see `handle_fetched_state`
+    pub checkpoints: Vec<SnapshotHandle>,
+    // the app snapshots of the checkpoints since the stable one, along with their executed blocks
+    snapshots: BTreeMap<u64, (Block, Vec<u8>)>,
+    checkpoint_certificates: BTreeMap<CheckpointKey, HashMap<ReplicaIndex, Signed<Checkpoint>>>,
+    // the latest checkpoint that 2f + 1 replicas including this one agree on
+    pub stable_op_num: u64,
+    pub stable_certificate: HashMap<ReplicaIndex, Signed<Checkpoint>>,
+    // the blocks up to the stable checkpoint are removed, and late messages for them are kept as
+    // for blocks not received yet, until evicted from the waiting ones
+    truncated_height: u32,
+    // exposed for inspecting progress, e.g. between steps of `simulated::Lockstep`
+    pub chain: Chain,
     app: App,
+    // votes and executed blocks are logged before the messages that depend on them, which are held
+    // back until the next pace and sent after one sync for all of them
+    storage: Option<Storage>,
+    unsynced: Vec<Unsynced>,
+    // op numbers are assigned by sequencer instead of primary, and every replica including primary
+    // proposes a block for each ordered request, so there is no `PrePrepare`
+    // primary only certifies blocks by its `Prepare`, which must be included in certificates
+    sequenced: bool,
+    pub reorder: Reorder<Request>,
+    prepared: HashSet<BlockDigest>,
 }
 
 impl Replica {
     pub fn new(context: Context<Message>, index: ReplicaIndex, app: App) -> Self {
+        Self::new_internal(context, index, app, false)
+    }
+
+    pub fn new_sequenced(context: Context<Message>, index: ReplicaIndex, app: App) -> Self {
+        Self::new_internal(context, index, app, true)
+    }
+
+    fn new_internal(
+        context: Context<Message>,
+        index: ReplicaIndex,
+        app: App,
+        sequenced: bool,
+    ) -> Self {
         Self {
+            waiting_prepares: Waiting::new(context.config().waiting_capacity),
+            waiting_commits: Waiting::new(context.config().waiting_capacity),
+            num_waiting: 0,
+            executed_height: 0,
+            executed_blocks: Default::default(),
+            state_transfer: StateTransfer::new(index, Duration::from_millis(10)),
+            op_num: 0,
+            checkpoints: Default::default(),
+            snapshots: Default::default(),
+            checkpoint_certificates: Default::default(),
+            stable_op_num: 0,
+            stable_certificate: Default::default(),
+            truncated_height: 0,
             context,
             index,
             view_num: 0,
             requests: Default::default(),
-            pre_prepares: Default::default(),
+            blocks: Default::default(),
             prepare_certificates: Default::default(),
             commit_certificates: Default::default(),
             chain: Default::default(),
             app,
+            storage: None,
+            unsynced: Default::default(),
+            sequenced,
+            reorder: Default::default(),
+            prepared: Default::default(),
         }
     }
+
+    // preallocate the log for `entry_num` entries, so that growing it does not stall the first
+    // seconds of a run
+    pub fn warm_up(&mut self, entry_num: usize) {
+        self.blocks.reserve(entry_num);
+        self.prepare_certificates.reserve(entry_num);
+        self.commit_certificates.reserve(entry_num)
+    }
+
+    // number of blocks with a certificate in progress
+    pub fn num_certificates(&self) -> usize {
+        self.prepare_certificates
+            .len()
+            .max(self.commit_certificates.len())
+    }
+
+    // replay the records in `storage` which are logged before a crash, and log to it from now on
+    // the prepared blocks are restored along with the view, so the replica neither proposes nor
+    // prepares another block at their heights, and its votes for the blocks not executed yet are
+    // sent again for the peers that may miss them
+    // no checkpoint is taken for the replayed blocks, and the ones missed while down are fetched by
+    // state transfer
+    // sequenced replicas cannot recover, as ordered multicast does not resume from a sequence number
+    pub fn recover(&mut self, (storage, records): (Storage, Vec<Record>)) {
+        let mut prepared = Vec::new();
+        let mut committed = Vec::new();
+        for record in records {
+            match record {
+                Record::Prepared(view_num, block) => {
+                    self.view_num = self.view_num.max(view_num);
+                    self.chain.replay_proposed(&block);
+                    let block_digest = block.digest();
+                    prepared.push(block_digest);
+                    self.blocks.insert(block_digest, block);
+                }
+                Record::Committed(view_num, block_digest) => {
+                    self.view_num = self.view_num.max(view_num);
+                    committed.push(block_digest)
+                }
+                Record::Executed(block) => {
+                    self.chain.replay(&block);
+                    for request in &block.requests {
+                        self.op_num += 1;
+                        self.app.set_op_num(self.op_num);
+                        self.app.execute(&request.op);
+                    }
+                    self.executed_height = block.height;
+                    let block_digest = block.digest();
+                    self.executed_blocks.push(block_digest);
+                    self.blocks.insert(block_digest, block);
+                }
+                Record::Restored(op_num, block, snapshot) => {
+                    self.restore(op_num, block, &snapshot);
+                    // the ones up to the restored block are removed along with the executed ones
+                    prepared.retain(|block_digest| self.blocks.contains_key(block_digest));
+                    committed.retain(|block_digest| self.blocks.contains_key(block_digest))
+                }
+            }
+        }
+        for block_digest in prepared {
+            if self.executed(&block_digest) {
+                continue;
+            }
+            if self.index == self.primary_index() {
+                let pre_prepare = PrePrepare {
+                    view_num: self.view_num,
+                    block: self.blocks[&block_digest].clone(),
+                };
+                self.context.send(To::AllReplica, pre_prepare)
+            } else {
+                let prepare = Prepare {
+                    view_num: self.view_num,
+                    block_digest,
+                    replica_index: self.index,
+                };
+                self.context.send(To::AllReplicaWithLoopback, prepare)
+            }
+        }
+        for block_digest in committed {
+            if self.executed(&block_digest) {
+                continue;
+            }
+            let commit = Commit {
+                view_num: self.view_num,
+                block_digest,
+                replica_index: self.index,
+            };
+            self.context.send(To::AllReplicaWithLoopback, commit)
+        }
+        self.storage = Some(storage)
+    }
+
+    // number of blocks kept, i.e. the ones after the stable checkpoint and the ones in flight
+    pub fn num_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn waiting_stats(&self) -> WaitingStats {
+        self.waiting_prepares
+            .stats
+            .merge(self.waiting_commits.stats)
+    }
 }
 
 impl Receivers for Replica {
     type Message = Message;
 
     fn handle(&mut self, receiver: Host, remote: Host, message: Self::Message) {
+        if receiver == Host::Multicast {
+            let Message::OrderedRequest(message) = message else {
+                unimplemented!()
+            };
+            return self.handle_ordered_request(remote, message);
+        }
         assert_eq!(receiver, Host::Replica(self.index));
         match message {
             Message::Request(message) => self.handle_request(remote, message),
             Message::PrePrepare(message) => self.handle_pre_prepare(remote, message),
             Message::Prepare(message) => self.handle_prepare(remote, message),
             Message::Commit(message) => self.handle_commit(remote, message),
+            Message::Checkpoint(message) => self.insert_checkpoint(message),
+            Message::Fetch(message) => self.handle_fetch(remote, message),
+            Message::Fetched(message) => self.handle_fetched(remote, message),
+            Message::FetchedState(message) => self.handle_fetched_state(remote, message),
+            Message::ReadRequest(message) => self.handle_read_request(remote, message),
             _ => unimplemented!(),
         }
     }
 
+    // the only timer of replica
     fn on_timer(&mut self, _: Host, _: crate::context::TimerId) {
-        todo!()
+        self.state_transfer.on_timer(
+            self.executed_height as u64 + 1,
+            self.chain.is_behind(),
+            &mut self.context,
+        )
     }
 
     fn handle_loopback(&mut self, receiver: Host, message: Self::Message) {
         assert_eq!(receiver, Host::Replica(self.index));
         match message {
             Message::PrePrepare(message) => {
-                self.pre_prepares.insert(message.block.digest(), message);
+                self.insert_block(message.block.digest(), message.inner.block)
             }
+            Message::Prepare(message) if self.sequenced => self.insert_sequenced_prepare(message),
             Message::Prepare(message) => self.insert_prepare(message),
             Message::Commit(message) => self.insert_commit(message),
+            Message::Checkpoint(message) => self.insert_checkpoint(message),
             _ => unimplemented!(),
         }
     }
 
+    fn is_stale(&self, _: Host, message: &Self::Message) -> bool {
+        match message {
+            Message::PrePrepare(message) => message.view_num < self.view_num,
+            Message::Prepare(message) => {
+                message.view_num < self.view_num || self.executed(&message.block_digest)
+            }
+            Message::Commit(message) => {
+                message.view_num < self.view_num || self.executed(&message.block_digest)
+            }
+            _ => false,
+        }
+    }
+
+    fn gauges(&self) -> Gauges {
+        Gauges {
+            log_len: Some(self.executed_blocks.len() as _),
+            commit_num: Some(self.op_num),
+            reorder_len: Some(self.reorder.num_pending() as _).filter(|_| self.sequenced),
+        }
+    }
+
     fn on_pace(&mut self) {
-        if self.index == self.primary_index() && !self.requests.is_empty() {
+        if !self.sequenced && self.index == self.primary_index() && !self.requests.is_empty() {
             self.do_propose()
         }
+        if let Some(storage) = &mut self.storage {
+            if !self.unsynced.is_empty() {
+                storage.sync();
+                for message in self.unsynced.drain(..) {
+                    message.send(&mut self.context)
+                }
+            }
+        }
+    }
+}
+
+// a message to send once the records it depends on are synced
+#[derive(Debug)]
+enum Unsynced {
+    PrePrepare(PrePrepare),
+    Prepare(Prepare),
+    Commit(Commit),
+    Reply(ClientIndex, Reply),
+    Checkpoint(Checkpoint),
+}
+
+impl Unsynced {
+    fn send(self, context: &mut Context<Message>) {
+        match self {
+            Self::PrePrepare(message) => context.send(To::AllReplicaWithLoopback, message),
+            Self::Prepare(message) => context.send(To::AllReplicaWithLoopback, message),
+            Self::Commit(message) => context.send(To::AllReplicaWithLoopback, message),
+            Self::Reply(client_index, message) => context.send(To::client(client_index), message),
+            Self::Checkpoint(message) => context.send(To::AllReplicaWithLoopback, message),
+        }
     }
 }
 
@@ -212,9 +665,29 @@ impl Replica {
         (self.view_num as usize % self.context.config().num_replica) as _
     }
 
+    fn log(&mut self, record: Record) {
+        if let Some(storage) = &mut self.storage {
+            storage.append(&record)
+        }
+    }
+
+    // after the records logged so far are synced, see `on_pace`
+    fn send_logged(&mut self, message: Unsynced) {
+        if self.storage.is_some() {
+            self.unsynced.push(message)
+        } else {
+            message.send(&mut self.context)
+        }
+    }
+
     fn handle_request(&mut self, _remote: Host, message: Signed<Request>) {
         if self.index != self.primary_index() {
-            // TODO
+            // relay requests that clients sent to a random backup, broadcast ones have reached
+            // primary already
+            if self.context.config().client_destination == Some(ClientDestination::Random) {
+                self.context
+                    .send(To::replica(self.primary_index()), message.inner)
+            }
             return;
         }
 
@@ -223,6 +696,19 @@ impl Replica {
         self.requests.push(message.inner);
     }
 
+    // answer from the executed state, which is committed in this protocol, so a quorum of matching
+    // answers reflects a state that every later one extends
+    fn handle_read_request(&mut self, _remote: Host, message: Signed<ReadRequest>) {
+        let request = &message.0;
+        let reply = ReadReply {
+            request_num: request.request_num,
+            result: self.app.execute_read(&request.op),
+            op_num: self.op_num,
+            replica_index: self.index,
+        };
+        self.context.send(To::client(request.client_index), reply)
+    }
+
     fn handle_pre_prepare(&mut self, _remote: Host, message: Signed<PrePrepare>) {
         if message.view_num < self.view_num {
             return;
@@ -234,14 +720,15 @@ impl Replica {
         }
 
         let block_digest = message.block.digest();
-        self.pre_prepares.insert(block_digest, message);
+        self.log(Record::Prepared(self.view_num, message.block.clone()));
+        self.insert_block(block_digest, message.inner.block);
         assert_ne!(self.index, self.primary_index());
         let prepare = Prepare {
             view_num: self.view_num,
             block_digest,
             replica_index: self.index,
         };
-        self.context.send(To::AllReplicaWithLoopback, prepare)
+        self.send_logged(Unsynced::Prepare(prepare))
     }
 
     fn handle_prepare(&mut self, _remote: Host, message: Signed<Prepare>) {
@@ -254,7 +741,31 @@ impl Replica {
             return;
         }
 
-        self.insert_prepare(message);
+        if self.sequenced {
+            self.insert_sequenced_prepare(message)
+        } else {
+            self.insert_prepare(message)
+        }
+    }
+
+    fn handle_ordered_request(&mut self, remote: Host, message: OrderedMulticast<Request>) {
+        assert!(self.sequenced);
+        // TODO recover dropped multicast
+        let Reordered::Ordered(requests) = self.reorder.receive_from(remote, message) else {
+            return;
+        };
+        for (_, request) in requests {
+            let block = self.chain.propose(&mut vec![request.inner]);
+            let block_digest = block.digest();
+            self.log(Record::Prepared(self.view_num, block.clone()));
+            self.insert_block(block_digest, block);
+            let prepare = Prepare {
+                view_num: self.view_num,
+                block_digest,
+                replica_index: self.index,
+            };
+            self.send_logged(Unsynced::Prepare(prepare))
+        }
     }
 
     fn handle_commit(&mut self, _remote: Host, message: Signed<Commit>) {
@@ -270,17 +781,205 @@ impl Replica {
         self.insert_commit(message);
     }
 
+    fn handle_fetch(&mut self, _remote: Host, message: Signed<Fetch>) {
+        // the log up to the stable checkpoint is truncated, so its state is sent instead
+        if message.start <= self.truncated_height as u64 {
+            if let Some((block, snapshot)) = self.snapshots.get(&self.stable_op_num) {
+                let fetched = FetchedState {
+                    start: message.start,
+                    block: block.clone(),
+                    certificate: self.stable_certificate.values().cloned().collect(),
+                    snapshot: snapshot.clone(),
+                };
+                self.context
+                    .send(To::replica(message.replica_index), fetched);
+                return;
+            }
+        }
+        let fetched = state_transfer::serve(&message, |height| {
+            let index = height.checked_sub(self.truncated_height as u64 + 1)?;
+            let block_digest = self.executed_blocks.get(index as usize)?;
+            Some(self.blocks[block_digest].clone())
+        });
+        self.context
+            .send(To::replica(message.replica_index), fetched)
+    }
+
+    fn handle_fetched(&mut self, _remote: Host, message: Fetched<Block>) {
+        let Some(num_entry) = self.state_transfer.on_fetched(message) else {
+            return;
+        };
+        // the fetched blocks must extend the executed ones, and are only applied up to the last one
+        // that leads to a block committed locally, the ones after it are kept for the next chunks
+        let mut blocks = std::mem::take(&mut self.state_transfer.fetched);
+        let mut block_digest = self.chain.digest_execute;
+        let (mut num_linked, mut num_verified) = (0, 0);
+        for block in &blocks {
+            if block.parent_digest != block_digest {
+                break;
+            }
+            block_digest = block.digest();
+            num_linked += 1;
+            if self.chain.waits_for(&block_digest) {
+                num_verified = num_linked
+            }
+        }
+        let accepted = num_linked == blocks.len();
+        blocks.truncate(num_linked);
+        self.state_transfer.fetched = blocks.split_off(num_verified);
+        for block in blocks {
+            let block_digest = block.digest();
+            self.blocks.entry(block_digest).or_insert(block);
+            if !self.executed(&block_digest) {
+                self.do_execute(block_digest)
+            }
+        }
+        self.state_transfer.on_applied(
+            self.executed_height as u64 + 1,
+            num_verified,
+            num_entry == state_transfer::CHUNK && accepted,
+            self.chain.is_behind(),
+            &mut self.context,
+        )
+    }
+
+    // restored if a quorum certifies it as the state of a checkpoint ahead of this replica, as the
+    // peer may be faulty, then the blocks after it are fetched
+    fn handle_fetched_state(&mut self, _remote: Host, message: FetchedState) {
+        if !self.state_transfer.on_fetched_state(message.start) {
+            return;
+        }
+        let FetchedState {
+            block,
+            certificate,
+            snapshot,
+            ..
+        } = message;
+        let quorum = self.context.config().num_replica - self.context.config().num_faulty;
+        let state_digest = Sha256::digest(&snapshot).into();
+        let certified = match certificate.first() {
+            Some(checkpoint) => {
+                let key = (checkpoint.op_num, block.digest(), state_digest);
+                checkpoint.op_num > self.op_num
+                    && certificate.iter().all(|checkpoint| {
+                        (
+                            checkpoint.op_num,
+                            checkpoint.block_digest,
+                            checkpoint.state_digest,
+                        ) == key
+                    })
+                    && HashSet::<_>::from_iter(
+                        certificate
+                            .iter()
+                            .map(|checkpoint| checkpoint.replica_index),
+                    )
+                    .len()
+                        >= quorum
+            }
+            None => false,
+        };
+        if certified {
+            let op_num = certificate[0].op_num;
+            tracing::debug!(op_num, height = block.height, "restore");
+            self.log(Record::Restored(op_num, block.clone(), snapshot.clone()));
+            let next_execute = self.restore(op_num, block.clone(), &snapshot);
+            self.stable_op_num = op_num;
+            self.stable_certificate = HashMap::from_iter(
+                certificate
+                    .into_iter()
+                    .map(|checkpoint| (checkpoint.replica_index, checkpoint)),
+            );
+            self.checkpoint_certificates = self.checkpoint_certificates.split_off(&(
+                op_num + 1,
+                Default::default(),
+                Default::default(),
+            ));
+            self.snapshots = BTreeMap::from([(op_num, (block, snapshot))]);
+            self.state_transfer.num_restored += 1;
+            if let Some(block_digest) = next_execute {
+                self.do_execute(block_digest)
+            }
+        }
+        self.state_transfer.on_applied(
+            self.executed_height as u64 + 1,
+            0,
+            certified,
+            self.chain.is_behind(),
+            &mut self.context,
+        )
+    }
+
+    // bring the executed state to the app `snapshot` after `op_num` ops at the executed `block`, in
+    // place of the blocks up to it, and return the committed block that is waiting for it
+    fn restore(&mut self, op_num: u64, block: Block, snapshot: &[u8]) -> Option<BlockDigest> {
+        assert!(self.app.restore(snapshot));
+        self.op_num = op_num;
+        self.executed_height = block.height;
+        self.truncated_height = block.height;
+        self.executed_blocks.clear();
+        self.checkpoints.clear();
+        // including the ones in flight, which are either executed as part of the state or conflict
+        // with it
+        self.blocks.retain(|_, other| other.height > block.height);
+        self.prepare_certificates
+            .retain(|block_digest, _| self.blocks.contains_key(block_digest));
+        self.commit_certificates
+            .retain(|block_digest, _| self.blocks.contains_key(block_digest));
+        self.prepared
+            .retain(|block_digest| self.blocks.contains_key(block_digest));
+        self.chain.restore(&block)
+    }
+
     fn do_propose(&mut self) {
         assert_eq!(self.index, self.primary_index());
         let pre_prepare = PrePrepare {
             view_num: self.view_num,
             block: self.chain.propose(&mut self.requests),
         };
-        self.context.send(To::AllReplicaWithLoopback, pre_prepare)
+        self.log(Record::Prepared(self.view_num, pre_prepare.block.clone()));
+        self.send_logged(Unsynced::PrePrepare(pre_prepare))
+    }
+
+    fn insert_block(&mut self, block_digest: BlockDigest, block: Block) {
+        self.blocks.insert(block_digest, block);
+        if let Some(prepare_certificate) = self.waiting_prepares.remove(&block_digest) {
+            self.prepare_certificates
+                .insert(block_digest, prepare_certificate);
+        }
+        if let Some(commit_certificate) = self.waiting_commits.remove(&block_digest) {
+            let committed = commit_certificate.len()
+                >= self.context.config().num_replica - self.context.config().num_faulty;
+            self.commit_certificates
+                .insert(block_digest, commit_certificate);
+            if committed {
+                self.do_execute(block_digest)
+            }
+        }
+    }
+
+    fn executed(&self, block_digest: &BlockDigest) -> bool {
+        matches!(self.blocks.get(block_digest), Some(block) if block.height <= self.executed_height)
     }
 
     fn insert_prepare(&mut self, prepare: Signed<Prepare>) {
         let block_digest = prepare.block_digest;
+        if self.executed(&block_digest) {
+            return;
+        }
+        if !self.blocks.contains_key(&block_digest) {
+            self.num_waiting += 1;
+            let quorum = self.context.config().num_replica - self.context.config().num_faulty;
+            if let Some(prepare_certificate) = self
+                .waiting_prepares
+                .entry(block_digest, Reverse(self.num_waiting))
+            {
+                // leave room for the `Prepare` of this replica, same as below
+                if prepare_certificate.len() + 1 < quorum {
+                    prepare_certificate.insert(prepare.replica_index, prepare);
+                }
+            }
+            return;
+        }
         let prepare_certificate = self.prepare_certificates.entry(block_digest).or_default();
         #[allow(clippy::int_plus_one)]
         {
@@ -299,7 +998,7 @@ impl Replica {
         } else {
             prepare_certificate.insert(prepare.replica_index, prepare);
         }
-        if self.pre_prepares.contains_key(&block_digest)
+        if self.blocks.contains_key(&block_digest)
             && prepare_certificate.len() + 1
                 == self.context.config().num_replica - self.context.config().num_faulty
         {
@@ -308,13 +1007,65 @@ impl Replica {
                 block_digest,
                 replica_index: self.index,
             };
-            self.context
-                .send(To::AllReplicaWithLoopback, commit.clone())
+            self.log(Record::Committed(self.view_num, block_digest));
+            self.send_logged(Unsynced::Commit(commit))
+        }
+    }
+
+    // prepared once there are `Prepare`s from n - f replicas including primary and this replica,
+    // where the latter implies the block is ordered locally
+    fn insert_sequenced_prepare(&mut self, prepare: Signed<Prepare>) {
+        let block_digest = prepare.block_digest;
+        if self.prepared.contains(&block_digest) || self.executed(&block_digest) {
+            return;
+        }
+        if !self.blocks.contains_key(&block_digest) {
+            self.num_waiting += 1;
+            if let Some(prepare_certificate) = self
+                .waiting_prepares
+                .entry(block_digest, Reverse(self.num_waiting))
+            {
+                prepare_certificate.insert(prepare.replica_index, prepare);
+            }
+            return;
+        }
+        let primary_index = self.primary_index();
+        let prepare_certificate = self.prepare_certificates.entry(block_digest).or_default();
+        prepare_certificate.insert(prepare.replica_index, prepare);
+        if prepare_certificate.len()
+            >= self.context.config().num_replica - self.context.config().num_faulty
+            && prepare_certificate.contains_key(&primary_index)
+            && prepare_certificate.contains_key(&self.index)
+        {
+            self.prepared.insert(block_digest);
+            let commit = Commit {
+                view_num: self.view_num,
+                block_digest,
+                replica_index: self.index,
+            };
+            self.log(Record::Committed(self.view_num, block_digest));
+            self.send_logged(Unsynced::Commit(commit))
         }
     }
 
     fn insert_commit(&mut self, commit: Signed<Commit>) {
         let block_digest = commit.block_digest;
+        if self.executed(&block_digest) {
+            return;
+        }
+        if !self.blocks.contains_key(&block_digest) {
+            self.num_waiting += 1;
+            let quorum = self.context.config().num_replica - self.context.config().num_faulty;
+            if let Some(commit_certificate) = self
+                .waiting_commits
+                .entry(block_digest, Reverse(self.num_waiting))
+            {
+                if commit_certificate.len() < quorum {
+                    commit_certificate.insert(commit.replica_index, commit);
+                }
+            }
+            return;
+        }
         let commit_certificate = self.commit_certificates.entry(block_digest).or_default();
         assert!(
             commit_certificate.len()
@@ -334,38 +1085,156 @@ impl Replica {
     }
 
     fn do_execute(&mut self, block_digest: BlockDigest) {
-        let mut block = &self.pre_prepares[&block_digest].block;
+        let mut block = &self.blocks[&block_digest];
         if !self.chain.commit(block) {
+            // usually the parent commits shortly, otherwise it is missed and fetched from peers
+            tracing::debug!(height = block.height, "commit ahead of execution");
+            self.state_transfer.on_behind(&mut self.context);
             return;
         }
+        let mut executed = block_digest;
+        let (mut replies, mut checkpoints) = (Vec::new(), Vec::new());
         loop {
-            for request in &block.requests {
+            let _span =
+                tracing::debug_span!("execute", height = block.height, op_num = self.op_num + 1)
+                    .entered();
+            if let Some(storage) = &mut self.storage {
+                storage.append(&Record::Executed(block.clone()))
+            }
+            self.executed_height = block.height;
+            self.executed_blocks.push(executed);
+            self.prepare_certificates.remove(&executed);
+            self.commit_certificates.remove(&executed);
+            self.prepared.remove(&executed);
+            for (index, request) in block.requests.iter().enumerate() {
+                tracing::trace!(
+                    client_index = request.client_index,
+                    request_num = request.request_num,
+                    "execute"
+                );
+                self.app.set_op_num(self.op_num + 1 + index as u64);
                 let reply = Reply {
+                    client_index: request.client_index,
                     request_num: request.request_num,
+                    op_digest: op_digest(&request.op),
                     result: self.app.execute(&request.op),
                     block_digest,
                     replica_index: self.index,
                 };
-                self.context.send(To::client(request.client_index), reply)
+                replies.push((request.client_index, reply))
+            }
+            let interval = self.context.config().checkpoint_interval;
+            let op_num = self.op_num + block.requests.len() as u64;
+            if interval != 0 && op_num / interval > self.op_num / interval {
+                self.checkpoints.push(self.app.checkpoint(op_num));
+                let snapshot = self.app.snapshot();
+                let checkpoint = Checkpoint {
+                    op_num,
+                    block_digest: executed,
+                    state_digest: Sha256::digest(&snapshot).into(),
+                    replica_index: self.index,
+                };
+                self.snapshots.insert(op_num, (block.clone(), snapshot));
+                checkpoints.push(checkpoint)
             }
+            self.op_num = op_num;
             if let Some(block_digest) = self.chain.next_execute() {
-                block = &self.pre_prepares[&block_digest].block;
+                executed = block_digest;
+                block = &self.blocks[&block_digest];
             } else {
                 break;
             }
         }
+        for (client_index, reply) in replies {
+            self.send_logged(Unsynced::Reply(client_index, reply))
+        }
+        for checkpoint in checkpoints {
+            self.send_logged(Unsynced::Checkpoint(checkpoint))
+        }
+    }
+}
+
+impl Replica {
+    fn insert_checkpoint(&mut self, checkpoint: Signed<Checkpoint>) {
+        if checkpoint.op_num <= self.stable_op_num {
+            return;
+        }
+        let key = (
+            checkpoint.op_num,
+            checkpoint.block_digest,
+            checkpoint.state_digest,
+        );
+        let certificate = self.checkpoint_certificates.entry(key).or_default();
+        certificate.insert(checkpoint.replica_index, checkpoint);
+        if certificate.len() >= self.context.config().num_replica - self.context.config().num_faulty
+            && certificate.contains_key(&self.index)
+        {
+            self.do_stabilize(key)
+        }
+    }
+
+    fn do_stabilize(&mut self, key: CheckpointKey) {
+        let (op_num, block_digest, _) = key;
+        let mut certificates = self.checkpoint_certificates.split_off(&(
+            op_num + 1,
+            Default::default(),
+            Default::default(),
+        ));
+        std::mem::swap(&mut certificates, &mut self.checkpoint_certificates);
+        self.stable_certificate = certificates.remove(&key).unwrap();
+        self.stable_op_num = op_num;
+        self.snapshots = self.snapshots.split_off(&op_num);
+
+        SnapshotHandle::compact(&mut self.checkpoints, op_num);
+
+        let height = self.blocks[&block_digest].height;
+        for block_digest in self
+            .executed_blocks
+            .drain(..(height - self.truncated_height) as usize)
+        {
+            self.blocks.remove(&block_digest);
+        }
+        self.truncated_height = height
     }
 }
 
+pub fn op_digest(op: &[u8]) -> [u8; 32] {
+    Sha256::digest(op).into()
+}
+
 impl DigestHash for Reply {
     fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u16(self.client_index);
         hasher.write_u32(self.request_num);
+        hasher.write(&self.op_digest);
         hasher.write(&self.result);
         hasher.write(&self.block_digest);
         hasher.write_u8(self.replica_index)
     }
 }
 
+impl DigestHash for ReadRequest {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        // distinct from the digest of the same request to be ordered
+        hasher.write(b"read");
+        self.0.hash(hasher)
+    }
+}
+
+impl DigestHash for ReadReply {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u32(self.request_num);
+        if let Some(result) = &self.result {
+            hasher.write_u8(1);
+            hasher.write(result)
+        } else {
+            hasher.write_u8(0)
+        }
+        hasher.write_u64(self.op_num);
+        hasher.write_u8(self.replica_index)
+    }
+}
+
 impl DigestHash for PrePrepare {
     fn hash(&self, hasher: &mut impl std::hash::Hasher) {
         hasher.write_u32(self.view_num);
@@ -389,6 +1258,15 @@ impl DigestHash for Commit {
     }
 }
 
+impl DigestHash for Checkpoint {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u64(self.op_num);
+        hasher.write(&self.block_digest);
+        hasher.write(&self.state_digest);
+        hasher.write_u8(self.replica_index)
+    }
+}
+
 impl Sign<Request> for Message {
     fn sign(message: Request, signer: &crate::context::crypto::Signer) -> Self {
         Self::Request(signer.sign_private(message))
@@ -401,6 +1279,18 @@ impl Sign<Reply> for Message {
     }
 }
 
+impl Sign<ReadRequest> for Message {
+    fn sign(message: ReadRequest, signer: &crate::context::crypto::Signer) -> Self {
+        Self::ReadRequest(signer.sign_private(message))
+    }
+}
+
+impl Sign<ReadReply> for Message {
+    fn sign(message: ReadReply, signer: &crate::context::crypto::Signer) -> Self {
+        Self::ReadReply(signer.sign_private(message))
+    }
+}
+
 impl Sign<PrePrepare> for Message {
     fn sign(message: PrePrepare, signer: &crate::context::crypto::Signer) -> Self {
         Self::PrePrepare(signer.sign_public(message))
@@ -419,17 +1309,113 @@ impl Sign<Commit> for Message {
     }
 }
 
+impl Sign<Checkpoint> for Message {
+    fn sign(message: Checkpoint, signer: &crate::context::crypto::Signer) -> Self {
+        Self::Checkpoint(signer.sign_public(message))
+    }
+}
+
+impl Sign<Fetch> for Message {
+    fn sign(message: Fetch, signer: &crate::context::crypto::Signer) -> Self {
+        Self::Fetch(signer.sign_public(message))
+    }
+}
+
+impl From<Fetched<Block>> for Message {
+    fn from(value: Fetched<Block>) -> Self {
+        Self::Fetched(value)
+    }
+}
+
+impl From<FetchedState> for Message {
+    fn from(value: FetchedState) -> Self {
+        Self::FetchedState(value)
+    }
+}
+
 impl Verify for Message {
     fn verify(
         &self,
         verifier: &crate::context::crypto::Verifier,
     ) -> Result<(), crate::context::crypto::Invalid> {
         match self {
-            Self::Request(message) => verifier.verify(message, None),
+            Self::Request(message) => verifier.verify_client(message, message.client_index),
+            Self::OrderedRequest(message) => verifier.verify_ordered_multicast(message),
             Self::Reply(message) => verifier.verify(message, message.replica_index),
+            Self::ReadRequest(message) => verifier.verify_client(message, message.0.client_index),
+            Self::ReadReply(message) => verifier.verify(message, message.replica_index),
             Self::PrePrepare(message) => verifier.verify(message, 0), // TODO
             Self::Prepare(message) => verifier.verify(message, message.replica_index),
             Self::Commit(message) => verifier.verify(message, message.replica_index),
+            Self::Checkpoint(message) => verifier.verify(message, message.replica_index),
+            Self::Fetch(message) => verifier.verify(message, message.replica_index),
+            // checked against the locally committed blocks instead
+            Self::Fetched(_) => Ok(()),
+            // the quorum and the state are checked by the receiving replica that knows them
+            Self::FetchedState(message) => verifier.verify_batch(
+                message
+                    .certificate
+                    .iter()
+                    .map(|checkpoint| (checkpoint, checkpoint.replica_index)),
+            ),
+        }
+    }
+}
+
+impl OrderedMulticastReceivers for Replica {
+    type Message = Request;
+}
+
+impl Tamper for Message {
+    // the same slot proposed without the requests
+    fn conflict(&self, signer: &Signer) -> Option<Self> {
+        let Self::PrePrepare(message) = self else {
+            return None;
+        };
+        let mut pre_prepare = message.inner.clone();
+        if pre_prepare.block.requests.is_empty() {
+            return None;
         }
+        pre_prepare.block.requests.clear();
+        Some(Self::sign(pre_prepare, signer))
+    }
+
+    fn is_vote(&self) -> bool {
+        matches!(
+            self,
+            Self::Prepare(_) | Self::Commit(_) | Self::Checkpoint(_)
+        )
+    }
+
+    fn corrupt(&self, signer: &Signer) -> Option<Self> {
+        match self {
+            Self::PrePrepare(message) => {
+                let mut pre_prepare = message.inner.clone();
+                garble(&mut pre_prepare.block.parent_digest);
+                Some(Self::sign(pre_prepare, signer))
+            }
+            Self::Prepare(message) => {
+                let mut prepare = message.inner.clone();
+                garble(&mut prepare.block_digest);
+                Some(Self::sign(prepare, signer))
+            }
+            Self::Commit(message) => {
+                let mut commit = message.inner.clone();
+                garble(&mut commit.block_digest);
+                Some(Self::sign(commit, signer))
+            }
+            Self::Checkpoint(message) => {
+                let mut checkpoint = message.inner.clone();
+                garble(&mut checkpoint.block_digest);
+                Some(Self::sign(checkpoint, signer))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<OrderedMulticast<Request>> for Message {
+    fn from(value: OrderedMulticast<Request>) -> Self {
+        Self::OrderedRequest(value)
     }
 }
@@ -0,0 +1,697 @@
+// the linear variant of pbft in SBFT, where replicas send their signature shares of a block to the
+// collectors instead of to all, and a collector aggregates the shares into one proof for everyone
+// - fast path: 3f + c + 1 shares are a full commit proof, which commits the block in one round
+// - fallback: if the fast path stalls, e.g. a replica is down, a collector with 2f + c + 1 shares
+//   sends them as the prepare certificate, and 2f + c + 1 commit shares are the full commit proof
+// with n = 3f + 2c + 1 replicas, c + 1 collectors make sure that the shares reach a correct one
+// BLS shares stand in for threshold signatures, i.e. a proof carries the indices of its signers
+// replies are not aggregated by execution collectors, clients take f + 1 matching ones as in pbft,
+// and there is no view change yet, i.e. the primary and the collectors are fixed
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use control_messages::ClientDestination;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::{BoxedConsume, Outcome, Proof},
+    common::{Block, BlockDigest, Chain, ClientLookup, ClientTable, Request, Timer},
+    context::{
+        byzantine::{garble, Tamper},
+        clock::Timestamp,
+        crypto::{DigestHash, Invalid, Sign, Signature, Signed, Signer, Verifier, Verify},
+        metrics::Gauges,
+        ClientIndex, Host, Receivers, ReplicaIndex, To,
+    },
+    App, Context,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    Request(Signed<Request>),
+    Reply(Signed<Reply>),
+    PrePrepare(Signed<PrePrepare>),
+    Share(Signed<Share>),
+    Prepare(Prepare),
+    FullCommitProof(FullCommitProof),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reply {
+    request_num: u32,
+    result: Vec<u8>,
+    block_digest: BlockDigest,
+    replica_index: ReplicaIndex,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrePrepare {
+    view_num: u32,
+    block: Block,
+    // the primary of the view, which the receiving replicas check
+    replica_index: ReplicaIndex,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Share {
+    view_num: u32,
+    block_digest: BlockDigest,
+    // of the commit phase of the fallback, otherwise of the fast path, which is also the prepare
+    // phase of the fallback
+    commit: bool,
+    replica_index: ReplicaIndex,
+}
+
+// not signed, the certificates stand for themselves
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prepare {
+    view_num: u32,
+    block_digest: BlockDigest,
+    certificate: Certificate,
+    // the collector, which the commit shares are sent back to
+    replica_index: ReplicaIndex,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FullCommitProof {
+    view_num: u32,
+    block_digest: BlockDigest,
+    // of 2f + c + 1 commit shares, otherwise of 3f + c + 1 shares of the fast path
+    slow: bool,
+    certificate: Certificate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Certificate {
+    Shares(Vec<Signed<Share>>),
+    Aggregated(Vec<ReplicaIndex>, Signature),
+}
+
+#[derive(Debug)]
+pub struct Client {
+    index: ClientIndex,
+    shared: Arc<Mutex<ClientShared>>,
+}
+
+#[derive(Debug)]
+struct ClientShared {
+    context: Context<Message>,
+    request_num: u32,
+    invoke: Option<ClientInvoke>,
+    resend_timer: Timer,
+}
+
+#[derive(Debug)]
+struct ClientInvoke {
+    op: Vec<u8>,
+    replies: HashMap<ReplicaIndex, Reply>,
+    consume: BoxedConsume,
+    start: Timestamp,
+}
+
+impl Client {
+    pub fn new(context: Context<Message>, index: ClientIndex) -> Self {
+        Self {
+            index,
+            shared: Arc::new(Mutex::new(ClientShared {
+                context,
+                request_num: 0,
+                invoke: None,
+                resend_timer: Timer::new(Duration::from_millis(100)),
+            })),
+        }
+    }
+}
+
+impl crate::Client for Client {
+    type Message = Message;
+
+    fn invoke(&self, op: Vec<u8>, consume: impl Into<BoxedConsume>) {
+        let shared = &mut *self.shared.lock().unwrap();
+        assert!(shared.invoke.is_none());
+        shared.request_num += 1;
+        shared.invoke = Some(ClientInvoke {
+            op: op.clone(),
+            replies: Default::default(),
+            consume: consume.into(),
+            start: Timestamp::now(),
+        });
+        let request = Request {
+            client_index: self.index,
+            request_num: shared.request_num,
+            op,
+        };
+        let Some(to) = shared.context.request_destination(ClientDestination::All) else {
+            unreachable!(
+                "sbft does not accept ordered multicast requests, see `ClientDestination::check`"
+            )
+        };
+        shared.context.send(to, request);
+        shared.resend_timer.set(&mut shared.context)
+    }
+
+    fn handle(&self, message: Self::Message) {
+        let Message::Reply(message) = message else {
+            unimplemented!()
+        };
+        let shared = &mut *self.shared.lock().unwrap();
+        if message.request_num != shared.request_num {
+            return;
+        }
+        let Some(invoke) = &mut shared.invoke else {
+            return;
+        };
+        invoke
+            .replies
+            .insert(message.replica_index, Reply::clone(&message));
+        let matched = Vec::from_iter(
+            invoke
+                .replies
+                .values()
+                .filter(|reply| {
+                    (reply.block_digest, &reply.result) == (message.block_digest, &message.result)
+                })
+                .cloned(),
+        );
+        assert!(matched.len() <= shared.context.config().num_faulty + 1);
+        if matched.len() == shared.context.config().num_faulty + 1 {
+            shared.resend_timer.unset(&mut shared.context);
+            let invoke = shared.invoke.take().unwrap();
+            let _op = invoke.op;
+            invoke.consume.apply(Outcome {
+                proof: Some(Proof::new(matched)),
+                ..Outcome::new(message.inner.result, invoke.start)
+            })
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Replica {
+    context: Context<Message>,
+    index: ReplicaIndex,
+    view_num: u32,
+    requests: Vec<Request>,
+    blocks: HashMap<BlockDigest, Block>,
+    // by either path, may be proved before the block is pre-prepared
+    committed: HashSet<BlockDigest>,
+    // as a collector
+    collects: HashMap<BlockDigest, Collect>,
+    // the blocks that reach 2f + c + 1 shares but not 3f + c + 1 by the time it fires fall back
+    fallback_timer: Timer,
+    chain: Chain,
+    pub client_table: ClientTable<Reply>,
+    app: App,
+}
+
+#[derive(Debug, Default)]
+struct Collect {
+    sign_shares: HashMap<ReplicaIndex, Signed<Share>>,
+    commit_shares: HashMap<ReplicaIndex, Signed<Share>>,
+    prepared: bool,
+    proved: bool,
+}
+
+impl Replica {
+    pub fn new(context: Context<Message>, index: ReplicaIndex, app: App) -> Self {
+        Self {
+            client_table: ClientTable::new(context.config().client_table_capacity),
+            context,
+            index,
+            view_num: 0,
+            requests: Default::default(),
+            blocks: Default::default(),
+            committed: Default::default(),
+            collects: Default::default(),
+            fallback_timer: Timer::new(Duration::from_millis(20)),
+            chain: Default::default(),
+            app,
+        }
+    }
+
+    // preallocate the log for `entry_num` entries, so that growing it does not stall the first
+    // seconds of a run
+    pub fn warm_up(&mut self, entry_num: usize) {
+        self.blocks.reserve(entry_num);
+        self.committed.reserve(entry_num)
+    }
+}
+
+impl Receivers for Replica {
+    type Message = Message;
+
+    fn handle(&mut self, receiver: Host, remote: Host, message: Self::Message) {
+        assert_eq!(receiver, Host::Replica(self.index));
+        match message {
+            Message::Request(message) => self.handle_request(remote, message),
+            Message::PrePrepare(message) => self.handle_pre_prepare(remote, message),
+            Message::Share(message) => self.handle_share(remote, message),
+            Message::Prepare(message) => self.handle_prepare(remote, message),
+            Message::FullCommitProof(message) => self.handle_full_commit_proof(remote, message),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn handle_loopback(&mut self, receiver: Host, message: Self::Message) {
+        assert_eq!(receiver, Host::Replica(self.index));
+        match message {
+            Message::PrePrepare(message) => self.handle_pre_prepare(receiver, message),
+            Message::Share(message) => self.handle_share(receiver, message),
+            Message::Prepare(message) => self.handle_prepare(receiver, message),
+            Message::FullCommitProof(message) => self.handle_full_commit_proof(receiver, message),
+            _ => unimplemented!(),
+        }
+    }
+
+    // the fallback timer
+    fn on_timer(&mut self, _: Host, _: crate::context::TimerId) {
+        let slow_quorum = self.slow_quorum();
+        let mut prepares = Vec::new();
+        for (&block_digest, collect) in &mut self.collects {
+            if !collect.proved && !collect.prepared && collect.sign_shares.len() >= slow_quorum {
+                collect.prepared = true;
+                prepares.push(Prepare {
+                    view_num: self.view_num,
+                    block_digest,
+                    certificate: Certificate::new(collect.sign_shares.values()),
+                    replica_index: self.index,
+                })
+            }
+        }
+        for prepare in prepares {
+            self.context.send(To::AllReplicaWithLoopback, prepare)
+        }
+        // until another block reaches 2f + c + 1 shares
+        self.fallback_timer.unset(&mut self.context)
+    }
+
+    fn gauges(&self) -> Gauges {
+        Gauges {
+            log_len: Some(self.blocks.len() as _),
+            commit_num: Some(self.chain.executed_height() as _),
+            reorder_len: None,
+        }
+    }
+
+    fn on_pace(&mut self) {
+        if self.index == self.primary_index() && !self.requests.is_empty() {
+            self.do_propose()
+        }
+    }
+}
+
+impl Replica {
+    fn primary_index(&self) -> ReplicaIndex {
+        (self.view_num as usize % self.context.config().num_replica) as _
+    }
+
+    fn num_collector(&self) -> usize {
+        let config = self.context.config();
+        (config.num_replica - 3 * config.num_faulty - 1) / 2 + 1
+    }
+
+    // the primary and the c replicas following it
+    fn collectors(&self) -> Vec<ReplicaIndex> {
+        let num_replica = self.context.config().num_replica;
+        Vec::from_iter(
+            (0..self.num_collector())
+                .map(|i| ((self.primary_index() as usize + i) % num_replica) as ReplicaIndex),
+        )
+    }
+
+    fn fast_quorum(&self) -> usize {
+        3 * self.context.config().num_faulty + self.num_collector()
+    }
+
+    fn slow_quorum(&self) -> usize {
+        2 * self.context.config().num_faulty + self.num_collector()
+    }
+
+    fn handle_request(&mut self, remote: Host, message: Signed<Request>) {
+        // clients send to all replicas, see `ClientDestination::All`
+        if self.index != self.primary_index() {
+            return;
+        }
+
+        match self
+            .client_table
+            .lookup(message.client_index, message.request_num, remote)
+        {
+            ClientLookup::Execute => {}
+            ClientLookup::Resend(reply) => {
+                self.context.send(To::Host(remote), reply.clone());
+                return;
+            }
+            ClientLookup::Ignore => return,
+        }
+
+        self.requests.push(message.inner);
+    }
+
+    fn handle_pre_prepare(&mut self, _remote: Host, message: Signed<PrePrepare>) {
+        // there is no view change, so the view never moves
+        if message.view_num != self.view_num || message.replica_index != self.primary_index() {
+            return;
+        }
+
+        let block_digest = message.block.digest();
+        if self
+            .blocks
+            .insert(block_digest, message.inner.block)
+            .is_some()
+        {
+            return;
+        }
+        let share = Share {
+            view_num: self.view_num,
+            block_digest,
+            commit: false,
+            replica_index: self.index,
+        };
+        self.send_to_collectors(share);
+        if self.committed.contains(&block_digest) {
+            self.do_execute(block_digest)
+        }
+    }
+
+    fn send_to_collectors(&mut self, share: Share) {
+        let collectors = self.collectors();
+        if collectors.contains(&self.index) {
+            self.context.send(To::Loopback, share.clone())
+        }
+        let hosts = Vec::from_iter(
+            collectors
+                .into_iter()
+                .filter(|&index| index != self.index)
+                .map(Host::Replica),
+        );
+        if !hosts.is_empty() {
+            self.context.send(To::Hosts(hosts), share)
+        }
+    }
+
+    fn handle_share(&mut self, _remote: Host, message: Signed<Share>) {
+        if message.view_num != self.view_num || !self.collectors().contains(&self.index) {
+            return;
+        }
+
+        let (fast_quorum, slow_quorum) = (self.fast_quorum(), self.slow_quorum());
+        let block_digest = message.block_digest;
+        let collect = self.collects.entry(block_digest).or_default();
+        if collect.proved {
+            return;
+        }
+        let (shares, quorum) = if message.commit {
+            (&mut collect.commit_shares, slow_quorum)
+        } else {
+            (&mut collect.sign_shares, fast_quorum)
+        };
+        shares.insert(message.replica_index, message.clone());
+        if shares.len() == quorum {
+            collect.proved = true;
+            let proof = FullCommitProof {
+                view_num: self.view_num,
+                block_digest,
+                slow: message.commit,
+                certificate: Certificate::new(shares.values()),
+            };
+            self.context.send(To::AllReplicaWithLoopback, proof)
+        } else if !message.commit && shares.len() == slow_quorum && self.fallback_timer.id.is_none()
+        {
+            self.fallback_timer.set(&mut self.context)
+        }
+    }
+
+    fn handle_prepare(&mut self, _remote: Host, message: Prepare) {
+        if message.view_num != self.view_num
+            || message.certificate.num_signer() < self.slow_quorum()
+        {
+            return;
+        }
+
+        let share = Share {
+            view_num: self.view_num,
+            block_digest: message.block_digest,
+            commit: true,
+            replica_index: self.index,
+        };
+        let to = if message.replica_index == self.index {
+            To::Loopback
+        } else {
+            To::replica(message.replica_index)
+        };
+        self.context.send(to, share)
+    }
+
+    fn handle_full_commit_proof(&mut self, _remote: Host, message: FullCommitProof) {
+        let quorum = if message.slow {
+            self.slow_quorum()
+        } else {
+            self.fast_quorum()
+        };
+        if message.view_num != self.view_num || message.certificate.num_signer() < quorum {
+            return;
+        }
+
+        if !self.committed.insert(message.block_digest) {
+            return;
+        }
+        if self.blocks.contains_key(&message.block_digest) {
+            self.do_execute(message.block_digest)
+        }
+    }
+
+    fn do_propose(&mut self) {
+        assert_eq!(self.index, self.primary_index());
+        let pre_prepare = PrePrepare {
+            view_num: self.view_num,
+            block: self.chain.propose(&mut self.requests),
+            replica_index: self.index,
+        };
+        self.context.send(To::AllReplicaWithLoopback, pre_prepare)
+    }
+
+    fn do_execute(&mut self, mut block_digest: BlockDigest) {
+        let mut block = &self.blocks[&block_digest];
+        if !self.chain.commit(block) {
+            return;
+        }
+        loop {
+            self.app.set_op_num(block.height as _);
+            for request in &block.requests {
+                // a request resent before its first copy is executed may be proposed twice
+                let remote = Host::Client(request.client_index);
+                match self
+                    .client_table
+                    .lookup(request.client_index, request.request_num, remote)
+                {
+                    ClientLookup::Execute => {}
+                    ClientLookup::Resend(reply) => {
+                        self.context.send(To::Host(remote), reply.clone());
+                        continue;
+                    }
+                    ClientLookup::Ignore => continue,
+                }
+                let reply = Reply {
+                    request_num: request.request_num,
+                    result: self.app.execute(&request.op),
+                    block_digest,
+                    replica_index: self.index,
+                };
+                // every executed block is committed
+                let op_num = block.height as u64;
+                self.client_table.insert(
+                    request.client_index,
+                    remote,
+                    op_num,
+                    request.request_num,
+                    reply.clone(),
+                    op_num,
+                );
+                self.context.send(To::client(request.client_index), reply)
+            }
+            if let Some(next_digest) = self.chain.next_execute() {
+                block_digest = next_digest;
+                block = &self.blocks[&block_digest];
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+impl Certificate {
+    fn new<'a>(shares: impl Iterator<Item = &'a Signed<Share>> + Clone) -> Self {
+        if let Some(signature) = Signature::aggregate(shares.clone()) {
+            Self::Aggregated(
+                Vec::from_iter(shares.map(|share| share.replica_index)),
+                signature,
+            )
+        } else {
+            Self::Shares(shares.cloned().collect())
+        }
+    }
+
+    fn num_signer(&self) -> usize {
+        match self {
+            Self::Shares(shares) => shares.len(),
+            Self::Aggregated(replica_indices, _) => replica_indices.len(),
+        }
+    }
+
+    fn verify(
+        &self,
+        verifier: &Verifier,
+        view_num: u32,
+        block_digest: &BlockDigest,
+        commit: bool,
+    ) -> Result<(), Invalid> {
+        match self {
+            Self::Shares(shares) => {
+                let mut replica_indices = HashSet::new();
+                for share in shares {
+                    if (share.view_num, &share.block_digest, share.commit)
+                        != (view_num, block_digest, commit)
+                        || !replica_indices.insert(share.replica_index)
+                    {
+                        return Err(Invalid::Public);
+                    }
+                }
+                verifier.verify_batch(shares.iter().map(|share| (share, share.replica_index)))
+            }
+            Self::Aggregated(replica_indices, signature) => {
+                // any signer index stands for all, as it is not hashed
+                let share = Share {
+                    view_num,
+                    block_digest: *block_digest,
+                    commit,
+                    replica_index: 0,
+                };
+                verifier.verify_aggregated(&share, replica_indices, signature)
+            }
+        }
+    }
+}
+
+impl DigestHash for Reply {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u32(self.request_num);
+        hasher.write(&self.result);
+        hasher.write(&self.block_digest);
+        hasher.write_u8(self.replica_index)
+    }
+}
+
+impl DigestHash for PrePrepare {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u32(self.view_num);
+        self.block.hash(hasher);
+        hasher.write_u8(self.replica_index)
+    }
+}
+
+impl DigestHash for Share {
+    // not the signer, so the shares of a block sign the same message and can be aggregated
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u32(self.view_num);
+        hasher.write(&self.block_digest);
+        hasher.write_u8(self.commit as _)
+    }
+}
+
+impl Tamper for Message {
+    // the same slot proposed without the requests
+    fn conflict(&self, signer: &Signer) -> Option<Self> {
+        let Self::PrePrepare(message) = self else {
+            return None;
+        };
+        let mut pre_prepare = message.inner.clone();
+        if pre_prepare.block.requests.is_empty() {
+            return None;
+        }
+        pre_prepare.block.requests.clear();
+        Some(Self::sign(pre_prepare, signer))
+    }
+
+    fn is_vote(&self) -> bool {
+        matches!(self, Self::Share(_))
+    }
+
+    fn corrupt(&self, signer: &Signer) -> Option<Self> {
+        match self {
+            Self::PrePrepare(message) => {
+                let mut pre_prepare = message.inner.clone();
+                garble(&mut pre_prepare.block.parent_digest);
+                Some(Self::sign(pre_prepare, signer))
+            }
+            Self::Share(message) => {
+                let mut share = message.inner.clone();
+                garble(&mut share.block_digest);
+                Some(Self::sign(share, signer))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Sign<Request> for Message {
+    fn sign(message: Request, signer: &crate::context::crypto::Signer) -> Self {
+        Self::Request(signer.sign_private(message))
+    }
+}
+
+impl Sign<Reply> for Message {
+    fn sign(message: Reply, signer: &crate::context::crypto::Signer) -> Self {
+        Self::Reply(signer.sign_private(message))
+    }
+}
+
+impl Sign<PrePrepare> for Message {
+    fn sign(message: PrePrepare, signer: &crate::context::crypto::Signer) -> Self {
+        Self::PrePrepare(signer.sign_public(message))
+    }
+}
+
+impl Sign<Share> for Message {
+    fn sign(message: Share, signer: &crate::context::crypto::Signer) -> Self {
+        Self::Share(signer.sign_aggregatable(message))
+    }
+}
+
+impl From<Prepare> for Message {
+    fn from(value: Prepare) -> Self {
+        Self::Prepare(value)
+    }
+}
+
+impl From<FullCommitProof> for Message {
+    fn from(value: FullCommitProof) -> Self {
+        Self::FullCommitProof(value)
+    }
+}
+
+impl Verify for Message {
+    fn verify(&self, verifier: &Verifier) -> Result<(), Invalid> {
+        match self {
+            Self::Request(message) => verifier.verify_client(message, message.client_index),
+            Self::Reply(message) => verifier.verify(message, message.replica_index),
+            Self::PrePrepare(message) => verifier.verify(message, message.replica_index),
+            Self::Share(message) => verifier.verify(message, message.replica_index),
+            Self::Prepare(message) => {
+                message
+                    .certificate
+                    .verify(verifier, message.view_num, &message.block_digest, false)
+            }
+            Self::FullCommitProof(message) => message.certificate.verify(
+                verifier,
+                message.view_num,
+                &message.block_digest,
+                message.slow,
+            ),
+        }
+    }
+}
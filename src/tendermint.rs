@@ -0,0 +1,764 @@
+// tendermint, following "The latest gossip on BFT consensus", i.e. a block is decided per height
+// in rounds of propose, prevote and precommit, with the proposer rotating by height and round
+// - a replica prevotes a proposal unless it is locked on another block, and locks on the block
+//   that it sees 2f + 1 prevotes of before precommitting it
+// - a block is decided on 2f + 1 precommits of any round
+// - a stalled round, e.g. the proposer is down, is given up by voting nil on the timer
+// there is no gossip layer, every message is broadcast once and the ones of a window of later heights
+// are kept until the replica reaches them, so a replica that misses a decision does not catch up
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use control_messages::ClientDestination;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::{BoxedConsume, Outcome, Proof},
+    common::{Block, BlockDigest, Chain, Request, Timer},
+    context::{
+        byzantine::{garble, Tamper},
+        clock::Timestamp,
+        crypto::{DigestHash, Sign, Signed, Signer, Verify},
+        metrics::Gauges,
+        ClientIndex, Host, Receivers, ReplicaIndex, To,
+    },
+    App, Context,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    Request(Signed<Request>),
+    Reply(Signed<Reply>),
+    Proposal(Signed<Proposal>),
+    Prevote(Signed<Prevote>),
+    Precommit(Signed<Precommit>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reply {
+    request_num: u32,
+    result: Vec<u8>,
+    block_digest: BlockDigest,
+    replica_index: ReplicaIndex,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Proposal {
+    round: u32,
+    // the height is the block's
+    block: Block,
+    // the round that the block is seen 2f + 1 prevotes of by the proposer, if it is proposed again
+    valid_round: Option<u32>,
+    replica_index: ReplicaIndex,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prevote {
+    height: u32,
+    round: u32,
+    // none for nil
+    block_digest: Option<BlockDigest>,
+    replica_index: ReplicaIndex,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Precommit {
+    height: u32,
+    round: u32,
+    block_digest: Option<BlockDigest>,
+    replica_index: ReplicaIndex,
+}
+
+#[derive(Debug)]
+pub struct Client {
+    index: ClientIndex,
+    shared: Arc<Mutex<ClientShared>>,
+}
+
+#[derive(Debug)]
+struct ClientShared {
+    context: Context<Message>,
+    request_num: u32,
+    invoke: Option<ClientInvoke>,
+    resend_timer: Timer,
+}
+
+#[derive(Debug)]
+struct ClientInvoke {
+    op: Vec<u8>,
+    replies: HashMap<ReplicaIndex, Reply>,
+    consume: BoxedConsume,
+    start: Timestamp,
+}
+
+impl Client {
+    pub fn new(context: Context<Message>, index: ClientIndex) -> Self {
+        Self {
+            index,
+            shared: Arc::new(Mutex::new(ClientShared {
+                context,
+                request_num: 0,
+                invoke: None,
+                resend_timer: Timer::new(Duration::from_millis(100)),
+            })),
+        }
+    }
+}
+
+impl crate::Client for Client {
+    type Message = Message;
+
+    fn invoke(&self, op: Vec<u8>, consume: impl Into<BoxedConsume>) {
+        let shared = &mut *self.shared.lock().unwrap();
+        assert!(shared.invoke.is_none());
+        shared.request_num += 1;
+        shared.invoke = Some(ClientInvoke {
+            op: op.clone(),
+            replies: Default::default(),
+            consume: consume.into(),
+            start: Timestamp::now(),
+        });
+        let request = Request {
+            client_index: self.index,
+            request_num: shared.request_num,
+            op,
+        };
+        let Some(to) = shared.context.request_destination(ClientDestination::All) else {
+            unreachable!("tendermint does not accept ordered multicast requests, see `ClientDestination::check`")
+        };
+        shared.context.send(to, request);
+        shared.resend_timer.set(&mut shared.context)
+    }
+
+    fn handle(&self, message: Self::Message) {
+        let Message::Reply(message) = message else {
+            unimplemented!()
+        };
+        let shared = &mut *self.shared.lock().unwrap();
+        if message.request_num != shared.request_num {
+            return;
+        }
+        let Some(invoke) = &mut shared.invoke else {
+            return;
+        };
+        invoke
+            .replies
+            .insert(message.replica_index, Reply::clone(&message));
+        let matched = Vec::from_iter(
+            invoke
+                .replies
+                .values()
+                .filter(|reply| {
+                    (reply.block_digest, &reply.result) == (message.block_digest, &message.result)
+                })
+                .cloned(),
+        );
+        assert!(matched.len() <= shared.context.config().num_faulty + 1);
+        if matched.len() == shared.context.config().num_faulty + 1 {
+            shared.resend_timer.unset(&mut shared.context);
+            let invoke = shared.invoke.take().unwrap();
+            let _op = invoke.op;
+            invoke.consume.apply(Outcome {
+                proof: Some(Proof::new(matched)),
+                ..Outcome::new(message.inner.result, invoke.start)
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Step {
+    Propose,
+    Prevote,
+    Precommit,
+}
+
+#[derive(Debug)]
+pub struct Replica {
+    context: Context<Message>,
+    index: ReplicaIndex,
+
+    // the height to decide, i.e. the blocks below are decided
+    pub height: u32,
+    round: u32,
+    step: Step,
+    proposed: bool,
+    // the round of the first 2f + 1 prevotes of the proposal, which are only acted on once
+    prevote_quorum_round: Option<u32>,
+    locked: Option<(u32, Block)>,
+    valid: Option<(u32, Block)>,
+    // of the height, by round
+    proposals: HashMap<u32, Signed<Proposal>>,
+    prevotes: HashMap<u32, HashMap<ReplicaIndex, Signed<Prevote>>>,
+    precommits: HashMap<u32, HashMap<ReplicaIndex, Signed<Precommit>>>,
+    // the replicas that are seen in each later round, f + 1 of them make the replica skip to it
+    round_replicas: HashMap<u32, HashSet<ReplicaIndex>>,
+    // of the later heights, by round, kind and replica, so each replica has at most one message
+    // of a kind in a round kept
+    future_messages: BTreeMap<u32, BTreeMap<(u32, u8, ReplicaIndex), Message>>,
+    // the timeout of the step, runs while some request is not executed
+    round_timer: Timer,
+
+    requests: Vec<Request>,
+    replies: HashMap<ClientIndex, (u32, Option<Reply>)>,
+    pub chain: Chain,
+    app: App,
+}
+
+impl Replica {
+    pub fn new(context: Context<Message>, index: ReplicaIndex, app: App) -> Self {
+        Self {
+            context,
+            index,
+            height: 1,
+            round: 0,
+            step: Step::Propose,
+            proposed: false,
+            prevote_quorum_round: None,
+            locked: None,
+            valid: None,
+            proposals: Default::default(),
+            prevotes: Default::default(),
+            precommits: Default::default(),
+            round_replicas: Default::default(),
+            future_messages: Default::default(),
+            round_timer: Timer::new(Duration::from_millis(20)),
+            requests: Default::default(),
+            replies: Default::default(),
+            chain: Default::default(),
+            app,
+        }
+    }
+
+    // preallocate the client table for `entry_num` clients, the votes do not outlive their height
+    pub fn warm_up(&mut self, entry_num: usize) {
+        self.replies.reserve(entry_num)
+    }
+}
+
+impl Receivers for Replica {
+    type Message = Message;
+
+    fn handle(&mut self, receiver: Host, remote: Host, message: Self::Message) {
+        assert_eq!(receiver, Host::Replica(self.index));
+        match message {
+            Message::Request(message) => self.handle_request(remote, message),
+            Message::Proposal(_) | Message::Prevote(_) | Message::Precommit(_) => {
+                self.handle_consensus(message)
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn handle_loopback(&mut self, receiver: Host, message: Self::Message) {
+        assert_eq!(receiver, Host::Replica(self.index));
+        match message {
+            Message::Proposal(_) | Message::Prevote(_) | Message::Precommit(_) => {
+                self.handle_consensus(message)
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    // the timeout of the current step
+    fn on_timer(&mut self, receiver: Host, _: crate::context::TimerId) {
+        assert_eq!(receiver, Host::Replica(self.index));
+        match self.step {
+            Step::Propose => self.do_prevote(None),
+            Step::Prevote => self.do_precommit(None),
+            Step::Precommit => self.start_round(self.round + 1),
+        }
+        self.do_check()
+    }
+
+    // the messages of a height are discarded once it is decided, so there is no log to report
+    fn gauges(&self) -> Gauges {
+        Gauges {
+            log_len: None,
+            commit_num: Some(self.chain.executed_height() as _),
+            reorder_len: None,
+        }
+    }
+
+    fn on_pace(&mut self) {
+        if self.index == self.proposer(self.height, self.round)
+            && self.step == Step::Propose
+            && !self.proposed
+            && (self.valid.is_some() || !self.requests.is_empty())
+        {
+            self.do_propose()
+        }
+    }
+}
+
+impl Replica {
+    // the heights ahead of the current one that messages are kept for
+    const HEIGHT_WINDOW: u32 = 64;
+    // the rounds ahead of the current one, or of the first one for a later height
+    const ROUND_WINDOW: u32 = 64;
+
+    fn proposer(&self, height: u32, round: u32) -> ReplicaIndex {
+        ((height + round) as usize % self.context.config().num_replica) as _
+    }
+
+    fn quorum(&self) -> usize {
+        self.context.config().num_replica - self.context.config().num_faulty
+    }
+
+    fn handle_request(&mut self, remote: Host, message: Signed<Request>) {
+        match self.replies.get(&message.client_index) {
+            Some((request_num, _)) if request_num > &message.request_num => return,
+            Some((request_num, reply)) if request_num == &message.request_num => {
+                if let Some(reply) = reply {
+                    self.context.send(To::Host(remote), reply.clone())
+                }
+                return;
+            }
+            _ => {}
+        }
+        self.replies
+            .insert(message.client_index, (message.request_num, None));
+        self.requests.push(message.inner);
+        if self.round_timer.id.is_none() {
+            self.round_timer.set(&mut self.context)
+        }
+    }
+
+    fn handle_consensus(&mut self, message: Message) {
+        let (height, round, kind, replica_index) = match &message {
+            Message::Proposal(message) => (
+                message.block.height,
+                message.round,
+                0,
+                message.replica_index,
+            ),
+            Message::Prevote(message) => (message.height, message.round, 1, message.replica_index),
+            Message::Precommit(message) => {
+                (message.height, message.round, 2, message.replica_index)
+            }
+            _ => unreachable!(),
+        };
+        // the heights and rounds far ahead are dropped, so a faulty replica cannot fill the maps
+        // with messages of arbitrary ones
+        let base_round = if height == self.height { self.round } else { 0 };
+        if height < self.height
+            || height >= self.height + Self::HEIGHT_WINDOW
+            || round >= base_round + Self::ROUND_WINDOW
+        {
+            return;
+        }
+        if height > self.height {
+            self.future_messages
+                .entry(height)
+                .or_default()
+                .entry((round, kind, replica_index))
+                .or_insert(message);
+            return;
+        }
+        match message {
+            Message::Proposal(message) => {
+                if message.replica_index != self.proposer(height, round) {
+                    return;
+                }
+                self.proposals.entry(round).or_insert(message);
+            }
+            Message::Prevote(message) => {
+                self.prevotes
+                    .entry(round)
+                    .or_default()
+                    .insert(replica_index, message);
+            }
+            Message::Precommit(message) => {
+                self.precommits
+                    .entry(round)
+                    .or_default()
+                    .insert(replica_index, message);
+            }
+            _ => unreachable!(),
+        }
+        if round > self.round {
+            let replicas = self.round_replicas.entry(round).or_default();
+            replicas.insert(replica_index);
+            if replicas.len() > self.context.config().num_faulty {
+                self.start_round(round)
+            }
+        }
+        self.do_check()
+    }
+
+    fn start_round(&mut self, round: u32) {
+        self.round = round;
+        self.step = Step::Propose;
+        self.proposed = false;
+        self.round_replicas
+            .retain(|&later_round, _| later_round > round);
+        self.reset_timer()
+    }
+
+    fn reset_timer(&mut self) {
+        if self.round_timer.id.is_some() {
+            self.round_timer.reset(&mut self.context)
+        }
+    }
+
+    fn do_propose(&mut self) {
+        self.proposed = true;
+        let (valid_round, block) = if let Some((valid_round, block)) = &self.valid {
+            (Some(*valid_round), block.clone())
+        } else {
+            let requests =
+                Vec::from_iter(self.requests.iter().take(Chain::MAX_BATCH_SIZE).cloned());
+            let block = Block {
+                requests,
+                parent_digest: self.chain.digest_execute,
+                height: self.height,
+                batches: Default::default(),
+            };
+            (None, block)
+        };
+        let proposal = Proposal {
+            round: self.round,
+            block,
+            valid_round,
+            replica_index: self.index,
+        };
+        self.context.send(To::AllReplicaWithLoopback, proposal)
+    }
+
+    fn do_prevote(&mut self, block_digest: Option<BlockDigest>) {
+        self.step = Step::Prevote;
+        self.reset_timer();
+        let prevote = Prevote {
+            height: self.height,
+            round: self.round,
+            block_digest,
+            replica_index: self.index,
+        };
+        self.context.send(To::AllReplicaWithLoopback, prevote)
+    }
+
+    fn do_precommit(&mut self, block_digest: Option<BlockDigest>) {
+        self.step = Step::Precommit;
+        self.reset_timer();
+        let precommit = Precommit {
+            height: self.height,
+            round: self.round,
+            block_digest,
+            replica_index: self.index,
+        };
+        self.context.send(To::AllReplicaWithLoopback, precommit)
+    }
+
+    fn num_prevote(&self, round: u32, block_digest: Option<BlockDigest>) -> usize {
+        self.prevotes.get(&round).map_or(0, |prevotes| {
+            prevotes
+                .values()
+                .filter(|prevote| prevote.block_digest == block_digest)
+                .count()
+        })
+    }
+
+    fn num_precommit(&self, round: u32, block_digest: Option<BlockDigest>) -> usize {
+        self.precommits.get(&round).map_or(0, |precommits| {
+            precommits
+                .values()
+                .filter(|precommit| precommit.block_digest == block_digest)
+                .count()
+        })
+    }
+
+    fn is_valid(&self, block: &Block) -> bool {
+        block.height == self.height && block.parent_digest == self.chain.digest_execute
+    }
+
+    // the upon rules of the paper that the inserted message may enable
+    fn do_check(&mut self) {
+        let quorum = self.quorum();
+        let decided = self.proposals.iter().find(|(&round, proposal)| {
+            self.is_valid(&proposal.block)
+                && self.num_precommit(round, Some(proposal.block.digest())) >= quorum
+        });
+        if let Some((_, proposal)) = decided {
+            let block = proposal.block.clone();
+            self.do_decide(block);
+            return;
+        }
+
+        let round = self.round;
+        if let Some(proposal) = self.proposals.get(&round) {
+            let block_digest = proposal.block.digest();
+            let valid = self.is_valid(&proposal.block);
+            let proposal_valid_round = proposal.valid_round;
+            if self.step == Step::Propose {
+                let prevote = match proposal_valid_round {
+                    None => Some(
+                        valid
+                            && self
+                                .locked
+                                .as_ref()
+                                .is_none_or(|(_, block)| block.digest() == block_digest),
+                    ),
+                    Some(valid_round)
+                        if valid_round < round
+                            && self.num_prevote(valid_round, Some(block_digest)) >= quorum =>
+                    {
+                        Some(
+                            valid
+                                && self.locked.as_ref().is_none_or(|(locked_round, block)| {
+                                    *locked_round <= valid_round || block.digest() == block_digest
+                                }),
+                        )
+                    }
+                    _ => None,
+                };
+                if let Some(prevote) = prevote {
+                    self.do_prevote(Some(block_digest).filter(|_| prevote))
+                }
+            }
+            if self.step >= Step::Prevote
+                && self.prevote_quorum_round != Some(round)
+                && valid
+                && self.num_prevote(round, Some(block_digest)) >= quorum
+            {
+                self.prevote_quorum_round = Some(round);
+                let block = self.proposals[&round].block.clone();
+                if self.step == Step::Prevote {
+                    self.locked = Some((round, block.clone()));
+                    self.do_precommit(Some(block_digest))
+                }
+                self.valid = Some((round, block))
+            }
+        }
+        if self.step == Step::Prevote && self.num_prevote(round, None) >= quorum {
+            self.do_precommit(None)
+        }
+    }
+
+    fn do_decide(&mut self, block: Block) {
+        let block_digest = block.digest();
+        let execute = self.chain.commit(&block);
+        assert!(execute);
+        self.app.set_op_num(block.height as _);
+        for request in &block.requests {
+            if matches!(
+                self.replies.get(&request.client_index),
+                Some((request_num, Some(_))) if *request_num >= request.request_num
+            ) {
+                continue;
+            }
+            let reply = Reply {
+                request_num: request.request_num,
+                result: self.app.execute(&request.op),
+                block_digest,
+                replica_index: self.index,
+            };
+            if self
+                .replies
+                .get(&request.client_index)
+                .is_none_or(|(request_num, _)| *request_num <= request.request_num)
+            {
+                self.replies.insert(
+                    request.client_index,
+                    (request.request_num, Some(reply.clone())),
+                );
+            }
+            self.context.send(To::client(request.client_index), reply)
+        }
+        let replies = &self.replies;
+        self.requests.retain(|request| {
+            matches!(
+                replies.get(&request.client_index),
+                Some((request_num, None)) if *request_num == request.request_num
+            )
+        });
+
+        self.height += 1;
+        self.prevote_quorum_round = None;
+        self.locked = None;
+        self.valid = None;
+        self.proposals.clear();
+        self.prevotes.clear();
+        self.precommits.clear();
+        self.round_replicas.clear();
+        self.start_round(0);
+        if self.round_timer.id.is_some() && self.requests.is_empty() {
+            self.round_timer.unset(&mut self.context)
+        }
+
+        self.future_messages
+            .retain(|&height, _| height >= self.height);
+        if let Some(messages) = self.future_messages.remove(&self.height) {
+            for message in messages.into_values() {
+                self.handle_consensus(message)
+            }
+        }
+    }
+}
+
+impl DigestHash for Reply {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u32(self.request_num);
+        hasher.write(&self.result);
+        hasher.write(&self.block_digest);
+        hasher.write_u8(self.replica_index)
+    }
+}
+
+impl DigestHash for Proposal {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u32(self.round);
+        self.block.hash(hasher);
+        hasher.write_u32(self.valid_round.map_or(u32::MAX, |round| round));
+        hasher.write_u8(self.replica_index)
+    }
+}
+
+impl DigestHash for Prevote {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u32(self.height);
+        hasher.write_u32(self.round);
+        hasher.write(&self.block_digest.unwrap_or_default());
+        hasher.write_u8(self.replica_index)
+    }
+}
+
+impl DigestHash for Precommit {
+    fn hash(&self, hasher: &mut impl std::hash::Hasher) {
+        hasher.write_u32(self.height);
+        hasher.write_u32(self.round);
+        hasher.write(&self.block_digest.unwrap_or_default());
+        hasher.write_u8(self.replica_index)
+    }
+}
+
+impl Tamper for Message {
+    // the same round proposed without the requests
+    // the proposals are not gossiped, so the replicas that received the conflicting one never learn
+    // the decided block and stall on its height
+    fn conflict(&self, signer: &Signer) -> Option<Self> {
+        let Self::Proposal(message) = self else {
+            return None;
+        };
+        let mut proposal = message.inner.clone();
+        if proposal.block.requests.is_empty() {
+            return None;
+        }
+        proposal.block.requests.clear();
+        Some(Self::sign(proposal, signer))
+    }
+
+    fn is_vote(&self) -> bool {
+        matches!(self, Self::Prevote(_) | Self::Precommit(_))
+    }
+
+    fn corrupt(&self, signer: &Signer) -> Option<Self> {
+        match self {
+            Self::Proposal(message) => {
+                let mut proposal = message.inner.clone();
+                garble(&mut proposal.block.parent_digest);
+                Some(Self::sign(proposal, signer))
+            }
+            Self::Prevote(message) => {
+                let mut prevote = message.inner.clone();
+                if let Some(digest) = &mut prevote.block_digest {
+                    garble(digest)
+                }
+                Some(Self::sign(prevote, signer))
+            }
+            Self::Precommit(message) => {
+                let mut precommit = message.inner.clone();
+                if let Some(digest) = &mut precommit.block_digest {
+                    garble(digest)
+                }
+                Some(Self::sign(precommit, signer))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Sign<Request> for Message {
+    fn sign(message: Request, signer: &crate::context::crypto::Signer) -> Self {
+        Self::Request(signer.sign_private(message))
+    }
+}
+
+impl Sign<Reply> for Message {
+    fn sign(message: Reply, signer: &crate::context::crypto::Signer) -> Self {
+        Self::Reply(signer.sign_private(message))
+    }
+}
+
+impl Sign<Proposal> for Message {
+    fn sign(message: Proposal, signer: &crate::context::crypto::Signer) -> Self {
+        Self::Proposal(signer.sign_public(message))
+    }
+}
+
+impl Sign<Prevote> for Message {
+    fn sign(message: Prevote, signer: &crate::context::crypto::Signer) -> Self {
+        Self::Prevote(signer.sign_public(message))
+    }
+}
+
+impl Sign<Precommit> for Message {
+    fn sign(message: Precommit, signer: &crate::context::crypto::Signer) -> Self {
+        Self::Precommit(signer.sign_public(message))
+    }
+}
+
+impl Verify for Message {
+    fn verify(
+        &self,
+        verifier: &crate::context::crypto::Verifier,
+    ) -> Result<(), crate::context::crypto::Invalid> {
+        match self {
+            Self::Request(message) => verifier.verify_client(message, message.client_index),
+            Self::Reply(message) => verifier.verify(message, message.replica_index),
+            Self::Proposal(message) => verifier.verify(message, message.replica_index),
+            Self::Prevote(message) => verifier.verify(message, message.replica_index),
+            Self::Precommit(message) => verifier.verify(message, message.replica_index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::{
+        ordered_multicast::Variant,
+        simulated::{fixture::config, Network},
+    };
+
+    use super::*;
+
+    // a faulty replica votes for arbitrary heights and rounds, and repeatedly for the same ones
+    #[test]
+    fn future_messages_bounded() {
+        let config = config(4, 1);
+        let network = Network::new(config.clone(), true, |_| Variant::Unreachable);
+        let mut replica = Replica::new(network.register(Host::Replica(0)), 0, App::Null);
+        let signer = Signer::new(&config, Host::Replica(1));
+        let mut prevote = |height, round, block_digest| {
+            let prevote = Prevote {
+                height,
+                round,
+                block_digest,
+                replica_index: 1,
+            };
+            replica.handle_consensus(Message::Prevote(signer.sign_public(prevote)))
+        };
+        prevote(2, 0, None);
+        prevote(2, 0, Some([1; 32]));
+        prevote(2, Replica::ROUND_WINDOW, None);
+        prevote(1 + Replica::HEIGHT_WINDOW, 0, None);
+        prevote(u32::MAX, 0, None);
+        prevote(1, Replica::ROUND_WINDOW, None);
+        assert_eq!(replica.future_messages.len(), 1);
+        assert_eq!(replica.future_messages[&2].len(), 1);
+        assert!(replica.prevotes.is_empty())
+    }
+}
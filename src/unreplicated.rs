@@ -1,13 +1,18 @@
 use std::{collections::HashMap, sync::Mutex, time::Duration};
 
+use control_messages::ClientDestination;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    client::BoxedConsume,
-    common::{Block, BlockDigest, Chain, Request, Timer},
+    client::{BoxedConsume, Outcome, Proof},
+    common::{warm_up_log, Block, BlockDigest, Chain, ClientLookup, ClientTable, Request, Timer},
     context::{
+        byzantine::Tamper,
+        clock::Timestamp,
         crypto::{DigestHash, Sign, Signed, Verify},
-        ClientIndex, Context, Host, Receivers, To,
+        metrics::Gauges,
+        ordered_multicast::{OrderedMulticast, Reorder, Reordered},
+        ClientIndex, Context, Host, OrderedMulticastReceivers, Receivers, To,
     },
     App,
 };
@@ -15,6 +20,7 @@ use crate::{
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Message {
     Request(Signed<Request>),
+    OrderedRequest(OrderedMulticast<Request>),
     Reply(Signed<Reply>),
 }
 
@@ -27,6 +33,8 @@ pub struct Reply {
 #[derive(Debug)]
 pub struct Client {
     index: ClientIndex,
+    // send requests through sequencer instead of to the replica directly
+    ordered: bool,
     shared: Mutex<ClientShared>,
 }
 
@@ -36,18 +44,29 @@ struct ClientShared {
     request_num: u32,
     op: Option<Vec<u8>>,
     consume: Option<BoxedConsume>,
+    start: Timestamp,
     resend_timer: Timer,
 }
 
 impl Client {
     pub fn new(context: Context<Message>, index: ClientIndex) -> Self {
+        Self::new_internal(context, index, false)
+    }
+
+    pub fn new_ordered(context: Context<Message>, index: ClientIndex) -> Self {
+        Self::new_internal(context, index, true)
+    }
+
+    fn new_internal(context: Context<Message>, index: ClientIndex, ordered: bool) -> Self {
         Self {
             index,
+            ordered,
             shared: Mutex::new(ClientShared {
                 context,
                 request_num: 0,
                 op: None,
                 consume: None,
+                start: Timestamp::now(),
                 resend_timer: Timer::new(Duration::from_millis(100)),
             }),
         }
@@ -63,6 +82,7 @@ impl crate::Client for Client {
         assert!(shared.op.is_none());
         shared.op = Some(op.clone());
         shared.consume = Some(consume.into());
+        shared.start = Timestamp::now();
         shared.resend_timer.set(&mut shared.context);
 
         let request = Request {
@@ -70,7 +90,15 @@ impl crate::Client for Client {
             request_num: shared.request_num,
             op,
         };
-        shared.context.send(To::replica(0), request)
+        let default = if self.ordered {
+            ClientDestination::Multicast
+        } else {
+            ClientDestination::Primary
+        };
+        match shared.context.request_destination(default) {
+            Some(to) => shared.context.send(to, request),
+            None => shared.context.send_ordered_multicast(request),
+        }
     }
 
     fn handle(&self, message: Self::Message) {
@@ -83,7 +111,11 @@ impl crate::Client for Client {
         }
         shared.op.take().unwrap();
         shared.resend_timer.unset(&mut shared.context);
-        shared.consume.take().unwrap().apply(reply.inner.result);
+        let outcome = Outcome {
+            proof: Some(Proof::new(reply.clone())),
+            ..Outcome::new(reply.inner.result, shared.start)
+        };
+        shared.consume.take().unwrap().apply(outcome)
     }
 }
 
@@ -93,7 +125,10 @@ pub struct Replica {
     blocks: HashMap<BlockDigest, Block>,
     chain: Chain,
     requests: Vec<Request>,
-    replies: HashMap<ClientIndex, Reply>,
+    // ordered mode does not recover from dropped multicast, as the unordered mode does not resend
+    pub reorder: Reorder<Request>,
+    op_num: u64,
+    pub client_table: ClientTable<Reply>,
     app: App,
     pub make_blocks: bool,
 }
@@ -101,48 +136,46 @@ pub struct Replica {
 impl Replica {
     pub fn new(context: Context<Message>, app: App) -> Self {
         Self {
+            client_table: ClientTable::new(context.config().client_table_capacity),
             context,
             // probably need to reserve if `make_blocks` is set
             // or the rehashing will cause huge latency spikes
             blocks: HashMap::default(),
             chain: Default::default(),
             requests: Default::default(),
-            replies: Default::default(),
+            reorder: Default::default(),
+            op_num: 0,
             app,
             make_blocks: false,
         }
     }
+
+    // preallocate the log for `entry_num` entries, so that growing it does not stall the first
+    // seconds of a run
+    pub fn warm_up(&mut self, entry_num: usize) {
+        warm_up_log(&mut self.requests, entry_num);
+        self.blocks.reserve(entry_num)
+    }
 }
 
 impl Receivers for Replica {
     type Message = Message;
 
     fn handle(&mut self, receiver: Host, remote: Host, message: Self::Message) {
-        assert_eq!(receiver, Host::Replica(0));
-        let (Host::Client(index), Message::Request(request)) = (remote, message) else {
-            unimplemented!()
-        };
-        match self.replies.get(&index) {
-            Some(reply) if reply.request_num > request.request_num => return,
-            Some(reply) if reply.request_num == request.request_num => {
-                self.context.send(To::Host(remote), reply.clone());
-                return;
+        match (receiver, remote, message) {
+            (Host::Replica(0), Host::Client(_), Message::Request(request)) => {
+                self.handle_request(remote, request.inner)
             }
-            _ => {}
-        }
-
-        self.requests.push(request.inner);
-        if !self.make_blocks {
-            let request = self.requests.last().unwrap();
-            let reply = Reply {
-                request_num: request.request_num,
-                result: self.app.execute(&request.op),
-            };
-            let evicted = self.replies.insert(request.client_index, reply.clone());
-            if let Some(evicted) = evicted {
-                assert_eq!(evicted.request_num, request.request_num - 1)
+            (Host::Multicast, _, Message::OrderedRequest(request)) => {
+                let Reordered::Ordered(requests) = self.reorder.receive_from(remote, request)
+                else {
+                    return;
+                };
+                for (_, request) in requests {
+                    self.handle_request(Host::Client(request.client_index), request.inner)
+                }
             }
-            self.context.send(To::client(request.client_index), reply)
+            _ => unimplemented!(),
         }
     }
 
@@ -150,6 +183,14 @@ impl Receivers for Replica {
         unreachable!()
     }
 
+    fn gauges(&self) -> Gauges {
+        Gauges {
+            log_len: None,
+            commit_num: Some(self.op_num),
+            reorder_len: Some(self.reorder.num_pending() as _),
+        }
+    }
+
     fn on_pace(&mut self) {
         if self.make_blocks && !self.requests.is_empty() {
             let block = self.chain.propose(&mut self.requests);
@@ -160,14 +201,20 @@ impl Receivers for Replica {
             let execute = self.chain.commit(&block);
             assert!(execute);
             for request in &block.requests {
+                self.op_num += 1;
+                self.app.set_op_num(self.op_num);
                 let reply = Reply {
                     request_num: request.request_num,
                     result: self.app.execute(&request.op),
                 };
-                let evicted = self.replies.insert(request.client_index, reply.clone());
-                if let Some(evicted) = evicted {
-                    assert_eq!(evicted.request_num, request.request_num - 1)
-                }
+                self.client_table.insert(
+                    request.client_index,
+                    Host::Client(request.client_index),
+                    self.op_num,
+                    request.request_num,
+                    reply.clone(),
+                    self.op_num,
+                );
                 self.context.send(To::client(request.client_index), reply)
             }
             assert!(self.chain.next_execute().is_none())
@@ -175,6 +222,43 @@ impl Receivers for Replica {
     }
 }
 
+impl Replica {
+    fn handle_request(&mut self, remote: Host, request: Request) {
+        match self
+            .client_table
+            .lookup(request.client_index, request.request_num, remote)
+        {
+            ClientLookup::Execute => {}
+            ClientLookup::Resend(reply) => {
+                self.context.send(To::Host(remote), reply.clone());
+                return;
+            }
+            ClientLookup::Ignore => return,
+        }
+
+        self.requests.push(request);
+        if !self.make_blocks {
+            self.op_num += 1;
+            self.app.set_op_num(self.op_num);
+            let request = self.requests.last().unwrap();
+            let reply = Reply {
+                request_num: request.request_num,
+                result: self.app.execute(&request.op),
+            };
+            // every executed request is committed
+            self.client_table.insert(
+                request.client_index,
+                remote,
+                self.op_num,
+                request.request_num,
+                reply.clone(),
+                self.op_num,
+            );
+            self.context.send(To::client(request.client_index), reply)
+        }
+    }
+}
+
 impl DigestHash for Reply {
     fn hash(&self, hasher: &mut impl std::hash::Hasher) {
         hasher.write_u32(self.request_num);
@@ -182,6 +266,9 @@ impl DigestHash for Reply {
     }
 }
 
+// the only replica has no one to deceive, and can only stay silent
+impl Tamper for Message {}
+
 impl Sign<Request> for Message {
     fn sign(message: Request, signer: &crate::context::crypto::Signer) -> Self {
         Self::Request(signer.sign_private(message))
@@ -200,8 +287,19 @@ impl Verify for Message {
         verifier: &crate::context::crypto::Verifier,
     ) -> Result<(), crate::context::crypto::Invalid> {
         match self {
-            Self::Request(message) => verifier.verify(message, None),
+            Self::Request(message) => verifier.verify_client(message, message.client_index),
+            Self::OrderedRequest(message) => verifier.verify_ordered_multicast(message),
             Self::Reply(message) => verifier.verify(message, 0),
         }
     }
 }
+
+impl OrderedMulticastReceivers for Replica {
+    type Message = Request;
+}
+
+impl From<OrderedMulticast<Request>> for Message {
+    fn from(value: OrderedMulticast<Request>) -> Self {
+        Self::OrderedRequest(value)
+    }
+}
@@ -1,16 +1,20 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     sync::{Arc, Mutex},
     time::Duration,
 };
 
+use control_messages::ClientDestination;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    client::BoxedConsume,
+    client::{BoxedConsume, Outcome, Proof},
     common::{Block, BlockDigest, Chain, Request, Timer},
     context::{
-        crypto::{DigestHash, Sign, Signed, Verify},
+        byzantine::{garble, Tamper},
+        clock::Timestamp,
+        crypto::{DigestHash, Sign, Signed, Signer, Verify},
+        metrics::Gauges,
         ClientIndex, Host, Receivers, ReplicaIndex, To,
     },
     App, Context,
@@ -74,6 +78,8 @@ struct ClientInvoke {
     commit_result: Option<Vec<u8>>,
     local_commits: HashSet<ReplicaIndex>,
     consume: BoxedConsume,
+    start: Timestamp,
+    first_reply: Option<Duration>,
 }
 
 impl Client {
@@ -105,13 +111,18 @@ impl crate::Client for Client {
             commit_result: None,
             local_commits: Default::default(),
             consume: consume.into(),
+            start: Timestamp::now(),
+            first_reply: None,
         });
         let request = Request {
             client_index: self.index,
             request_num: shared.request_num,
             op,
         };
-        shared.context.send(To::AllReplica, request);
+        let Some(to) = shared.context.request_destination(ClientDestination::All) else {
+            unreachable!("zyzzyva does not accept ordered multicast requests, see `ClientDestination::check`")
+        };
+        shared.context.send(to, request);
         shared.resend_timer.set(&mut shared.context)
     }
 
@@ -135,6 +146,9 @@ impl crate::Client for Client {
                 else {
                     return;
                 };
+                invoke
+                    .first_reply
+                    .get_or_insert_with(|| invoke.start.elapsed());
                 invoke
                     .responses
                     .insert(message.replica_index, message.clone());
@@ -147,7 +161,12 @@ impl crate::Client for Client {
                     shared.resend_timer.unset(&mut shared.context);
                     let invoke = shared.invoke.take().unwrap();
                     let _op = invoke.op;
-                    invoke.consume.apply(result.clone())
+                    let proof = Vec::from_iter(invoke.responses.into_values());
+                    invoke.consume.apply(Outcome {
+                        first_reply: invoke.first_reply,
+                        proof: Some(Proof::new(proof)),
+                        ..Outcome::new(result.clone(), invoke.start)
+                    })
                 } else if self.byzantine
                     && num_match
                         == shared.context.config().num_replica - shared.context.config().num_faulty
@@ -175,7 +194,11 @@ impl crate::Client for Client {
                 {
                     shared.resend_timer.unset(&mut shared.context);
                     let invoke = shared.invoke.take().unwrap();
-                    invoke.consume.apply(invoke.commit_result.unwrap())
+                    // the commit certificate is with replicas, there is no proof to keep here
+                    invoke.consume.apply(Outcome {
+                        first_reply: invoke.first_reply,
+                        ..Outcome::new(invoke.commit_result.unwrap(), invoke.start)
+                    })
                 }
             }
             _ => unimplemented!(),
@@ -196,6 +219,9 @@ pub struct Replica {
     order_requests: HashMap<BlockDigest, Signed<OrderRequest>>,
     commits: HashMap<BlockDigest, Signed<Commit>>,
     chain: Chain,
+    // the digests of the speculatively executed blocks by their heights, which are the op numbers of
+    // the app, so the ones conflicting with a commit certificate can be rolled back
+    speculated: BTreeMap<u32, BlockDigest>,
     app: App,
 }
 
@@ -209,9 +235,17 @@ impl Replica {
             order_requests: Default::default(),
             commits: Default::default(),
             chain: Default::default(),
+            speculated: Default::default(),
             app,
         }
     }
+
+    // preallocate the log for `entry_num` entries, so that growing it does not stall the first
+    // seconds of a run
+    pub fn warm_up(&mut self, entry_num: usize) {
+        self.order_requests.reserve(entry_num);
+        self.commits.reserve(entry_num)
+    }
 }
 
 impl Receivers for Replica {
@@ -242,6 +276,14 @@ impl Receivers for Replica {
         self.handle_order_request(Host::Replica(self.index), message)
     }
 
+    fn gauges(&self) -> Gauges {
+        Gauges {
+            log_len: Some(self.order_requests.len() as _),
+            commit_num: Some(self.chain.executed_height() as _),
+            reorder_len: None,
+        }
+    }
+
     fn on_pace(&mut self) {
         if self.index == self.primary_index() && !self.requests.is_empty() {
             self.do_propose()
@@ -250,6 +292,10 @@ impl Receivers for Replica {
 }
 
 impl Replica {
+    // the blocks that are kept undoable, beyond which the oldest one is taken as committed, since
+    // replicas never learn about the commits of the fast path
+    const SPECULATION_WINDOW: usize = 1000;
+
     fn primary_index(&self) -> ReplicaIndex {
         (self.view_num as usize % self.context.config().num_replica) as _
     }
@@ -290,10 +336,49 @@ impl Replica {
             return;
         }
 
-        let Some(_order_request) = self.order_requests.get(&commit.block_digest) else {
-            // TODO
+        // the matching responses of 2f + 1 replicas certify the block
+        let certified = Vec::from_iter(
+            commit
+                .responses
+                .iter()
+                .filter(|response| response.block.digest() == commit.block_digest),
+        );
+        let replica_indexes =
+            HashSet::<_>::from_iter(certified.iter().map(|response| response.replica_index));
+        if replica_indexes.len()
+            < self.context.config().num_replica - self.context.config().num_faulty
+        {
             return;
-        };
+        }
+        let block = &certified[0].block;
+        match self.speculated.get(&block.height) {
+            Some(digest) if *digest == commit.block_digest => {}
+            Some(digest) => {
+                // the primary has equivocated and this replica has executed another block at the
+                // height, which is undone and replaced by the certified one along with the blocks
+                // after it
+                if self.order_requests[digest].block.parent_digest != block.parent_digest {
+                    // TODO fetch and roll back further
+                    return;
+                }
+                self.app.rollback(block.height as u64 - 1);
+                self.speculated.split_off(&block.height);
+                self.chain.digest_execute = block.parent_digest;
+                let block = block.clone();
+                assert!(self.chain.commit(&block));
+                self.execute_chain(block)
+            }
+            None => {
+                if !self.order_requests.contains_key(&commit.block_digest) {
+                    // TODO
+                    return;
+                }
+            }
+        }
+        if self.speculated.get(&block.height) == Some(&commit.block_digest) {
+            self.app.truncate_undo(block.height as _);
+            self.speculated = self.speculated.split_off(&(block.height + 1))
+        }
 
         let local_commit = LocalCommit {
             block_digest: commit.block_digest,
@@ -313,31 +398,46 @@ impl Replica {
     }
 
     fn do_execute(&mut self, block_digest: BlockDigest) {
-        let mut block = &self.order_requests[&block_digest].block;
+        let block = &self.order_requests[&block_digest].block;
         if !self.chain.commit(block) {
             return;
         }
-        while let Some(block_digest) = {
+        self.execute_chain(block.clone())
+    }
+
+    // execute `block` that the chain has just reached, and then the blocks waiting for it
+    fn execute_chain(&mut self, mut block: Block) {
+        loop {
+            let _span = tracing::debug_span!("execute", height = block.height).entered();
+            self.app.set_op_num(block.height as _);
+            self.app.speculate(block.height as _);
             let results = Vec::from_iter(
                 block
                     .requests
                     .iter()
                     .map(|request| self.app.execute(&request.op)),
             );
-            let spec_response = SpecResponse {
-                block: block.clone(),
-                results,
-                replica_index: self.index,
-            };
+            self.speculated
+                .insert(block.height, self.chain.digest_execute);
+            if self.speculated.len() > Self::SPECULATION_WINDOW {
+                let (height, _) = self.speculated.pop_first().unwrap();
+                self.app.truncate_undo(height as _)
+            }
             let hosts = block
                 .requests
                 .iter()
                 .map(|request| Host::Client(request.client_index))
                 .collect();
+            let spec_response = SpecResponse {
+                block,
+                results,
+                replica_index: self.index,
+            };
             self.context.send(To::Hosts(hosts), spec_response);
-            self.chain.next_execute()
-        } {
-            block = &self.order_requests[&block_digest].block
+            let Some(block_digest) = self.chain.next_execute() else {
+                break;
+            };
+            block = self.order_requests[&block_digest].block.clone()
         }
     }
 }
@@ -374,6 +474,41 @@ impl DigestHash for LocalCommit {
     }
 }
 
+impl Tamper for Message {
+    // the same slot proposed without the requests
+    fn conflict(&self, signer: &Signer) -> Option<Self> {
+        let Self::OrderRequest(message) = self else {
+            return None;
+        };
+        let mut order_request = message.inner.clone();
+        if order_request.block.requests.is_empty() {
+            return None;
+        }
+        order_request.block.requests.clear();
+        Some(Self::sign(order_request, signer))
+    }
+
+    fn is_vote(&self) -> bool {
+        matches!(self, Self::LocalCommit(_))
+    }
+
+    fn corrupt(&self, signer: &Signer) -> Option<Self> {
+        match self {
+            Self::OrderRequest(message) => {
+                let mut order_request = message.inner.clone();
+                garble(&mut order_request.block.parent_digest);
+                Some(Self::sign(order_request, signer))
+            }
+            Self::LocalCommit(message) => {
+                let mut local_commit = message.inner.clone();
+                garble(&mut local_commit.block_digest);
+                Some(Self::sign(local_commit, signer))
+            }
+            _ => None,
+        }
+    }
+}
+
 impl Sign<Request> for Message {
     fn sign(message: Request, signer: &crate::context::crypto::Signer) -> Self {
         Self::Request(signer.sign_private(message))
@@ -410,21 +545,122 @@ impl Verify for Message {
         verifier: &crate::context::crypto::Verifier,
     ) -> Result<(), crate::context::crypto::Invalid> {
         match self {
-            Self::Request(message) => verifier.verify(message, None),
+            Self::Request(message) => verifier.verify_client(message, message.client_index),
             Self::OrderRequest(message) => verifier.verify(message, 0), // TODO
             Self::SpecResponse(message) => verifier.verify(message, message.replica_index),
             Self::Commit(message) => {
-                if message.responses[0].block.requests[0].client_index != message.client_index {
-                    return Ok(());
-                }
-                verifier.verify(message, None)?;
-                // TODO check responses length
-                for response in &message.responses {
-                    verifier.verify(response, response.replica_index)?
-                }
-                Ok(())
+                // the certificate rolls back speculated blocks, so it is verified for every client
+                verifier.verify_client(message, message.client_index)?;
+                verifier.verify_batch(
+                    message
+                        .responses
+                        .iter()
+                        .map(|response| (response, response.replica_index)),
+                )
             }
             Self::LocalCommit(message) => verifier.verify(message, message.replica_index),
         }
     }
 }
+
+#[cfg(all(test, feature = "ycsb"))]
+mod tests {
+    use bincode::Options;
+
+    use crate::{
+        app::ycsb,
+        context::{
+            crypto::Signer,
+            ordered_multicast::Variant,
+            simulated::{fixture::config, Network},
+        },
+    };
+
+    use super::*;
+
+    // the primary equivocates on the first block to replica 3, which rolls it back on the commit
+    // certificate of the others and executes the certified block along with the one waiting for it
+    #[test]
+    fn rollback_on_commit() {
+        let config = config(4, 1);
+        let network = Network::new(config.clone(), true, |_| Variant::Unreachable);
+        let mut replicas = Vec::from_iter((0..4).map(|index| {
+            let app = App::Ycsb(Default::default());
+            Replica::new(network.register(Host::Replica(index)), index, app)
+        }));
+        let request = |request_num, op: ycsb::Op| Request {
+            client_index: 0,
+            request_num,
+            op: bincode::options().serialize(&op).unwrap(),
+        };
+        let mut chain = Chain::new();
+        let block = chain.propose(&mut vec![request(
+            1,
+            ycsb::Op::Insert("a".into(), "1".into()),
+        )]);
+        let next_block = chain.propose(&mut vec![request(
+            2,
+            ycsb::Op::Insert("b".into(), "2".into()),
+        )]);
+        let conflicting = Chain::new().propose(&mut vec![request(
+            1,
+            ycsb::Op::Insert("a".into(), "2".into()),
+        )]);
+        let primary = Signer::new(&config, Host::Replica(0));
+        let order_request = |block: &Block| {
+            primary.sign_public(OrderRequest {
+                view_num: 0,
+                block: block.clone(),
+            })
+        };
+        for replica in &mut replicas[..3] {
+            replica.handle_order_request(Host::Replica(0), order_request(&block))
+        }
+        replicas[3].handle_order_request(Host::Replica(0), order_request(&conflicting));
+        for replica in &mut replicas {
+            replica.handle_order_request(Host::Replica(0), order_request(&next_block))
+        }
+        let read = |key: &str| {
+            bincode::options()
+                .serialize(&ycsb::Op::Read(key.into()))
+                .unwrap()
+        };
+        assert_ne!(
+            replicas[3].app.execute_read(&read("a")),
+            replicas[0].app.execute_read(&read("a"))
+        );
+
+        let responses = Vec::from_iter((0..3).map(|index| {
+            let response = SpecResponse {
+                block: block.clone(),
+                results: Default::default(),
+                replica_index: index,
+            };
+            Signer::new(&config, Host::Replica(index)).sign_public(response)
+        }));
+        let commit = |responses: &[Signed<SpecResponse>]| {
+            Signer::new(&config, Host::Client(0)).sign_private(Commit {
+                client_index: 0,
+                block_digest: block.digest(),
+                responses: responses.to_vec(),
+            })
+        };
+        // not certified without 2f + 1 responses
+        replicas[3].handle_commit(Host::Client(0), commit(&responses[..2]));
+        assert_eq!(replicas[3].speculated[&1], conflicting.digest());
+        replicas[3].handle_commit(Host::Client(0), commit(&responses));
+        // the certified block is committed and no longer undoable
+        assert_eq!(
+            replicas[3].speculated,
+            BTreeMap::from_iter([(2, next_block.digest())])
+        );
+        for key in ["a", "b"] {
+            for replica in &replicas {
+                assert_eq!(
+                    replica.app.execute_read(&read(key)),
+                    replicas[0].app.execute_read(&read(key))
+                )
+            }
+        }
+    }
+}